@@ -0,0 +1,34 @@
+//! 把 scripts/ksyms.py 生成的符号表嵌入内核
+//! 环境变量 KERNEL_SYMBOLS 指向符号表文件，没有设置时写入一个只有头部的空表
+//!
+//! 打开 multiboot2 feature 时改用 multiboot2.ld 链接内核
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    if env::var_os("CARGO_FEATURE_MULTIBOOT2").is_some() {
+        let script =
+            PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("multiboot2.ld");
+        println!("cargo:rerun-if-changed={}", script.display());
+        println!("cargo:rustc-link-arg-bins=-T{}", script.display());
+    }
+
+    println!("cargo:rerun-if-env-changed=KERNEL_SYMBOLS");
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("symbols.bin");
+
+    match env::var_os("KERNEL_SYMBOLS") {
+        Some(path) => {
+            println!("cargo:rerun-if-changed={}", PathBuf::from(&path).display());
+            fs::copy(&path, &out).expect("failed to copy KERNEL_SYMBOLS");
+        }
+        None => {
+            // 魔数、版本 1、anchor 0、符号数 0、保留
+            let mut header = b"KSYM".to_vec();
+            header.extend_from_slice(&1u32.to_le_bytes());
+            header.extend_from_slice(&0u64.to_le_bytes());
+            header.extend_from_slice(&[0; 8]);
+            fs::write(&out, header).expect("failed to write empty symbol map");
+        }
+    }
+}
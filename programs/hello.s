@@ -0,0 +1,20 @@
+# exec 的示例程序，与位置无关的平坦二进制，从第一个字节开始执行
+# RDI 是内核提供的函数表：偏移 0 是 puts(ptr, len)，偏移 8 是 exit(code)
+#
+#   as --64 -o hello.o hello.s && objcopy -O binary -j .text hello.o hello.bin
+
+.section .text
+.code64
+_start:
+    pushq %rbx
+    movq %rdi, %rbx
+    leaq message(%rip), %rdi
+    movq $(message_end - message), %rsi
+    call *0(%rbx)
+    movl $42, %edi
+    call *8(%rbx)
+    # exit 不会返回
+
+message:
+    .ascii "hello from a flat binary"
+message_end:
@@ -0,0 +1,12 @@
+# usermode 的示例程序，读取 RDI 指向的地址；传入内核地址时触发缺页，程序被结束
+#
+#   as --64 -o user_fault.o user_fault.s && objcopy -O binary -j .text user_fault.o user_fault.bin
+
+.section .text
+.code64
+_start:
+    movq (%rdi), %rax
+    # SYS_EXIT(0)，读取成功时才会执行到这里
+    movq $2, %rax
+    xorl %edi, %edi
+    int $0x80
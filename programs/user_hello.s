@@ -0,0 +1,38 @@
+# usermode 的示例程序，在 ring 3 运行，RDI 是内核传入的一个内核地址
+# 打印一行后用这个地址调用 SYS_WRITE，应当被拒绝；两次调用的结果都符合预期时以 0 退出，否则以 1 退出
+#
+#   as --64 -o user_hello.o user_hello.s && objcopy -O binary -j .text user_hello.o user_hello.bin
+
+.section .text
+.code64
+_start:
+    movq %rdi, %rbx
+    # SYS_WRITE(message, len)
+    movq $1, %rax
+    leaq message(%rip), %rdi
+    movq $(message_end - message), %rsi
+    int $0x80
+    cmpq $(message_end - message), %rax
+    jne failed
+
+    # SYS_WRITE(内核地址, 8) 返回 u64::MAX
+    movq $1, %rax
+    movq %rbx, %rdi
+    movq $8, %rsi
+    int $0x80
+    cmpq $-1, %rax
+    jne failed
+
+    # SYS_EXIT(0)
+    movq $2, %rax
+    xorl %edi, %edi
+    int $0x80
+
+failed:
+    movq $2, %rax
+    movl $1, %edi
+    int $0x80
+
+message:
+    .ascii "hello from ring 3"
+message_end:
@@ -0,0 +1,64 @@
+#!/usr/bin/env python3
+"""从内核 ELF 中提取函数符号，生成 src/symbols.rs 读取的符号表
+
+用法: scripts/ksyms.py <内核 ELF> <输出文件>
+需要 nm（GNU binutils 或 llvm-nm，可以用环境变量 NM 指定）
+"""
+import os
+import struct
+import subprocess
+import sys
+
+MAGIC = b"KSYM"
+VERSION = 1
+ANCHOR = "ksym_anchor"
+
+
+def read_symbols(elf):
+    nm = os.environ.get("NM", "nm")
+    output = subprocess.run(
+        [nm, "--defined-only", "--print-size", "--numeric-sort", elf],
+        check=True,
+        capture_output=True,
+        text=True,
+    ).stdout
+    anchor = 0
+    symbols = {}
+    for line in output.splitlines():
+        fields = line.split()
+        if len(fields) == 4:
+            address, size, kind, name = fields
+        elif len(fields) == 3:
+            (address, kind, name), size = fields, "0"
+        else:
+            continue
+        address, size = int(address, 16), int(size, 16)
+        if name == ANCHOR:
+            anchor = address
+        # 只保留代码段中的符号，同一地址的别名只保留第一个
+        if kind in "tTwW":
+            symbols.setdefault(address, (size, name))
+    return anchor, sorted(symbols.items())
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit(__doc__)
+    elf, out = sys.argv[1:]
+    anchor, symbols = read_symbols(elf)
+    if anchor == 0:
+        sys.exit(f"{elf}: symbol {ANCHOR} not found")
+
+    entries = bytearray()
+    names = bytearray()
+    for address, (size, name) in symbols:
+        encoded = name.encode()
+        entries += struct.pack("<QQII", address, size, len(names), len(encoded))
+        names += encoded
+    header = MAGIC + struct.pack("<IQII", VERSION, anchor, len(symbols), 0)
+    with open(out, "wb") as f:
+        f.write(header + entries + names)
+
+
+if __name__ == "__main__":
+    main()
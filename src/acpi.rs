@@ -0,0 +1,654 @@
+//! ACPI 表
+//! 只做启动 AP 和关机所需的最少工作：
+//! - 引导程序提供了 RSDP（Multiboot2 的 ACPI 标签）时直接使用，
+//!   否则在 EBDA 的第一个 KiB 和 0xE0000..0x100000 中按 16 字节对齐查找 "RSD PTR " 签名的 RSDP
+//! - 版本 2 以上的 RSDP 带有 XSDT（64 位表指针），否则使用 RSDT（32 位表指针）
+//! - 在根表中按签名找到 MADT（"APIC"），列出其中的本地 APIC
+//! - 在根表中找到 FADT（"FACP"），取出 PM1a/PM1b 控制寄存器的端口和 DSDT 的地址，
+//!   再在 DSDT 中查找 \_S5 对象得到关机用的 SLP_TYP，见 sleep_control
+//!
+//! 每张表都检查校验和（所有字节相加为 0）。解析函数只处理字节切片，与物理内存的访问分开，便于测试。
+//! x2APIC 条目（类型 9，APIC id 大于 255）不处理。
+//! 不解释 AML：\_S5 按固定的字节模式查找（NameOp、名字、PackageOp），
+//! 用方法或者条件计算出来的 \_S5 找不到
+use crate::{memory, multiboot2, time};
+use core::fmt;
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// 版本 1 的 RSDP 长度，之后的扩展部分只在版本 2 以上存在
+const RSDP_V1_LEN: usize = 20;
+const RSDP_V2_LEN: usize = 36;
+/// 所有系统描述表共同的表头长度
+const SDT_HEADER_LEN: usize = 36;
+/// MADT 表头之后是本地 APIC 地址（4 字节）和标志（4 字节），然后是变长条目
+const MADT_ENTRIES_OFFSET: usize = SDT_HEADER_LEN + 8;
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LOCAL_APIC_OVERRIDE: u8 = 5;
+/// FADT 中各字段的偏移
+const FADT_DSDT: usize = 40;
+const FADT_SMI_COMMAND: usize = 48;
+const FADT_ACPI_ENABLE: usize = 52;
+const FADT_PM1A_CONTROL: usize = 64;
+const FADT_PM1B_CONTROL: usize = 68;
+/// ACPI 2.0 起的 64 位 DSDT 地址，表长至少 148 字节时才有
+const FADT_X_DSDT: usize = 140;
+
+/// AML 操作码
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ROOT_PREFIX: u8 = b'\\';
+
+/// PM1 控制寄存器：bit 0 SCI_EN（ACPI 模式），bit 10-12 SLP_TYP，bit 13 SLP_EN
+const SCI_EN: u16 = 1;
+const SLP_EN: u16 = 1 << 13;
+/// 切换到 ACPI 模式后等待 SCI_EN 置位的时间
+const ACPI_ENABLE_TIMEOUT_MS: u32 = 300;
+
+/// BDA 中保存 EBDA 段地址的位置
+const EBDA_POINTER: u64 = 0x40E;
+const BIOS_AREA: (u64, u64) = (0xE0000, 0x10_0000);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    NoRsdp,
+    /// 签名为这个的表校验和错误
+    BadChecksum([u8; 4]),
+    Truncated([u8; 4]),
+    /// 根表中没有这个签名的表
+    NotFound([u8; 4]),
+    NoPhysicalMapping,
+    /// FADT 中没有 PM1a 控制寄存器
+    NoPm1Control,
+    /// DSDT 中找不到 \_S5 对象
+    NoS5,
+}
+
+fn name(signature: &[u8; 4]) -> &str {
+    core::str::from_utf8(signature).unwrap_or("????")
+}
+
+impl fmt::Display for AcpiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AcpiError::NoRsdp => write!(f, "no ACPI RSDP found"),
+            AcpiError::BadChecksum(signature) => write!(f, "{}: bad checksum", name(signature)),
+            AcpiError::Truncated(signature) => write!(f, "{}: table is truncated", name(signature)),
+            AcpiError::NotFound(signature) => write!(f, "no {} table", name(signature)),
+            AcpiError::NoPhysicalMapping => write!(f, "physical memory is not mapped"),
+            AcpiError::NoPm1Control => write!(f, "FACP: no PM1a control block"),
+            AcpiError::NoS5 => write!(f, "DSDT: no \\_S5 package"),
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// 根表的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Root {
+    Rsdt(u64),
+    Xsdt(u64),
+}
+
+/// 解析 RSDP，bytes 至少要有 RSDP_V2_LEN 字节才能识别版本 2
+fn parse_rsdp(bytes: &[u8]) -> Option<Root> {
+    if bytes.get(..8)? != RSDP_SIGNATURE || !checksum_ok(bytes.get(..RSDP_V1_LEN)?) {
+        return None;
+    }
+    let revision = bytes[15];
+    let extended = bytes
+        .get(..RSDP_V2_LEN)
+        .filter(|extended| checksum_ok(extended));
+    if let (2.., Some(extended)) = (revision, extended) {
+        return Some(Root::Xsdt(read_u64(extended, 24)?));
+    }
+    Some(Root::Rsdt(read_u32(bytes, 16)? as u64))
+}
+
+fn signature(table: &[u8]) -> [u8; 4] {
+    table
+        .get(..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .unwrap_or([0; 4])
+}
+
+/// 检查表头中的长度和校验和，返回长度恰好为表长的切片
+fn validate(table: &[u8]) -> Result<&[u8], AcpiError> {
+    let signature = signature(table);
+    let length = read_u32(table, 4).ok_or(AcpiError::Truncated(signature))? as usize;
+    let table = table
+        .get(..length)
+        .filter(|_| length >= SDT_HEADER_LEN)
+        .ok_or(AcpiError::Truncated(signature))?;
+    if !checksum_ok(table) {
+        return Err(AcpiError::BadChecksum(signature));
+    }
+    Ok(table)
+}
+
+/// 根表中的表指针
+fn root_entries(root: &[u8], wide: bool) -> impl Iterator<Item = u64> + '_ {
+    let size = if wide { 8 } else { 4 };
+    root[SDT_HEADER_LEN.min(root.len())..]
+        .chunks_exact(size)
+        .map(move |entry| {
+            if wide {
+                read_u64(entry, 0).unwrap()
+            } else {
+                read_u32(entry, 0).unwrap() as u64
+            }
+        })
+}
+
+/// MADT 中的一个处理器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApic {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    /// 可以启动；没有启用但标记为 "online capable" 的处理器只能热插拔，不算在内
+    pub enabled: bool,
+}
+
+/// MADT 中与启动 AP 有关的内容
+#[derive(Debug, Clone, Copy)]
+pub struct Madt<'a> {
+    /// 本地 APIC 寄存器的物理地址，已经考虑了类型 5 的 64 位覆盖条目
+    pub local_apic_address: u64,
+    entries: &'a [u8],
+}
+
+impl<'a> Madt<'a> {
+    pub fn parse(table: &'a [u8]) -> Result<Madt<'a>, AcpiError> {
+        let table = validate(table)?;
+        let truncated = AcpiError::Truncated(signature(table));
+        let mut madt = Madt {
+            local_apic_address: read_u32(table, SDT_HEADER_LEN).ok_or(truncated)? as u64,
+            entries: table.get(MADT_ENTRIES_OFFSET..).ok_or(truncated)?,
+        };
+        if let Some(entry) = madt
+            .entries()
+            .find(|entry| entry[0] == MADT_LOCAL_APIC_OVERRIDE)
+        {
+            madt.local_apic_address = read_u64(entry, 4).ok_or(truncated)?;
+        }
+        Ok(madt)
+    }
+
+    /// 每个条目的字节（包括类型和长度），长度不合法时停止
+    fn entries(&self) -> impl Iterator<Item = &'a [u8]> {
+        let mut rest = self.entries;
+        core::iter::from_fn(move || {
+            let length = *rest.get(1)? as usize;
+            if length < 2 || length > rest.len() {
+                return None;
+            }
+            let (entry, tail) = rest.split_at(length);
+            rest = tail;
+            Some(entry)
+        })
+    }
+
+    pub fn local_apics(&self) -> impl Iterator<Item = LocalApic> + 'a {
+        self.entries()
+            .filter(|entry| entry[0] == MADT_LOCAL_APIC && entry.len() >= 8)
+            .map(|entry| LocalApic {
+                processor_id: entry[2],
+                apic_id: entry[3],
+                enabled: read_u32(entry, 4).unwrap() & 1 != 0,
+            })
+    }
+}
+
+/// FADT 中与关机有关的字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    /// DSDT 的物理地址，有 X_DSDT 时优先使用
+    pub dsdt: u64,
+    pub pm1a_control: u16,
+    pub pm1b_control: Option<u16>,
+    /// 向这个端口写入 acpi_enable 让固件切换到 ACPI 模式，都为 0 时不需要切换
+    pub smi_command: u16,
+    pub acpi_enable: u8,
+}
+
+impl Fadt {
+    pub fn parse(table: &[u8]) -> Result<Fadt, AcpiError> {
+        let table = validate(table)?;
+        let truncated = AcpiError::Truncated(signature(table));
+        let port = |offset| {
+            read_u32(table, offset)
+                .ok_or(truncated)
+                .map(|port| u16::try_from(port).unwrap_or(0))
+        };
+        let dsdt = match read_u64(table, FADT_X_DSDT) {
+            Some(x_dsdt) if x_dsdt != 0 => x_dsdt,
+            _ => read_u32(table, FADT_DSDT).ok_or(truncated)? as u64,
+        };
+        let pm1a_control = port(FADT_PM1A_CONTROL)?;
+        if pm1a_control == 0 {
+            return Err(AcpiError::NoPm1Control);
+        }
+        Ok(Fadt {
+            dsdt,
+            pm1a_control,
+            pm1b_control: Some(port(FADT_PM1B_CONTROL)?).filter(|&port| port != 0),
+            smi_command: port(FADT_SMI_COMMAND)?,
+            acpi_enable: *table.get(FADT_ACPI_ENABLE).ok_or(truncated)?,
+        })
+    }
+}
+
+/// AML 中的一个整数常量，只认识 ZeroOp、OneOp 和 BytePrefix
+fn aml_byte(aml: &[u8]) -> Option<(u8, &[u8])> {
+    match *aml.first()? {
+        AML_ZERO_OP => Some((0, &aml[1..])),
+        AML_ONE_OP => Some((1, &aml[1..])),
+        AML_BYTE_PREFIX => Some((*aml.get(1)?, aml.get(2..)?)),
+        _ => None,
+    }
+}
+
+/// name 处是 "_S5_" 时，检查它是不是 Name(\_S5, Package() { SLP_TYPa, SLP_TYPb, ... })，
+/// 返回前两个元素
+fn s5_package(aml: &[u8], name: usize) -> Option<(u8, u8)> {
+    let name_op = match *aml.get(name.checked_sub(1)?)? {
+        AML_ROOT_PREFIX => name.checked_sub(2)?,
+        _ => name - 1,
+    };
+    if aml[name_op] != AML_NAME_OP {
+        return None;
+    }
+    let package = aml.get(name + 4..)?;
+    if *package.first()? != AML_PACKAGE_OP {
+        return None;
+    }
+    // PkgLength 第一个字节的 bit 6-7 是后面还有几个字节，之后是 NumElements
+    let length_bytes = 1 + (*package.get(1)? >> 6) as usize;
+    let elements = package.get(1 + length_bytes + 1..)?;
+    let (a, rest) = aml_byte(elements)?;
+    let (b, _) = aml_byte(rest)?;
+    Some((a, b))
+}
+
+/// 在 DSDT 中查找 \_S5，返回 (SLP_TYPa, SLP_TYPb)
+fn parse_s5(dsdt: &[u8]) -> Result<(u8, u8), AcpiError> {
+    let aml = validate(dsdt)?.get(SDT_HEADER_LEN..).unwrap_or(&[]);
+    aml.windows(4)
+        .enumerate()
+        .filter(|&(_, window)| window == b"_S5_")
+        .find_map(|(name, _)| s5_package(aml, name))
+        .ok_or(AcpiError::NoS5)
+}
+
+/// 进入 S5（软关机）需要写的寄存器和值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SleepControl {
+    pub fadt: Fadt,
+    pub slp_typ_a: u8,
+    pub slp_typ_b: u8,
+}
+
+impl SleepControl {
+    /// 写入 PM1a 控制寄存器的值
+    pub fn pm1a_value(&self) -> u16 {
+        sleep_value(self.slp_typ_a)
+    }
+
+    pub fn pm1b_value(&self) -> u16 {
+        sleep_value(self.slp_typ_b)
+    }
+}
+
+fn sleep_value(slp_typ: u8) -> u16 {
+    (slp_typ as u16 & 0x7) << 10 | SLP_EN
+}
+
+/// 物理内存中从 addr 开始的 len 个字节
+///
+/// # Safety
+/// 这段物理内存必须在物理内存映射中，并且在返回的切片使用期间不被修改
+unsafe fn physical(addr: u64, len: usize) -> Result<&'static [u8], AcpiError> {
+    let virt = memory::phys_to_virt(PhysAddr::new(addr)).ok_or(AcpiError::NoPhysicalMapping)?;
+    Ok(core::slice::from_raw_parts(virt.as_ptr(), len))
+}
+
+/// 物理地址处的一张完整的表
+unsafe fn table_at(addr: u64) -> Result<&'static [u8], AcpiError> {
+    let header = physical(addr, SDT_HEADER_LEN)?;
+    let length = read_u32(header, 4).unwrap() as usize;
+    validate(physical(addr, length.max(SDT_HEADER_LEN))?)
+}
+
+fn find_rsdp() -> Result<Root, AcpiError> {
+    if let Some(addr) = multiboot2::rsdp_address() {
+        let rsdp = unsafe { physical(addr, RSDP_V2_LEN)? };
+        // 版本 1 的 RSDP 后面的内存不属于它，parse_rsdp 只在扩展校验和正确时使用扩展部分
+        if let Some(root) = parse_rsdp(rsdp) {
+            return Ok(root);
+        }
+    }
+    let ebda = unsafe { physical(EBDA_POINTER, 2)? };
+    let ebda = (u16::from_le_bytes([ebda[0], ebda[1]]) as u64) << 4;
+    let areas = [(ebda, ebda + 1024), BIOS_AREA];
+    for (start, end) in areas.into_iter().filter(|&(start, _)| start != 0) {
+        let area = unsafe { physical(start, (end - start) as usize)? };
+        for offset in (0..area.len()).step_by(16) {
+            let candidate = &area[offset..(offset + RSDP_V2_LEN).min(area.len())];
+            if let Some(root) = parse_rsdp(candidate) {
+                return Ok(root);
+            }
+        }
+    }
+    Err(AcpiError::NoRsdp)
+}
+
+/// 在根表中查找签名为 signature 的表，table_at 读取并检查一个地址处的表
+fn find_in_root<'a>(
+    root: &[u8],
+    wide: bool,
+    signature: &[u8; 4],
+    table_at: impl Fn(u64) -> Result<&'a [u8], AcpiError>,
+) -> Result<&'a [u8], AcpiError> {
+    for addr in root_entries(root, wide) {
+        let table = table_at(addr)?;
+        if table.starts_with(signature) {
+            return Ok(table);
+        }
+    }
+    Err(AcpiError::NotFound(*signature))
+}
+
+/// 在根表中查找签名为 signature 的表
+pub fn find_table(signature: &[u8; 4]) -> Result<&'static [u8], AcpiError> {
+    let (root, wide) = match find_rsdp()? {
+        Root::Rsdt(addr) => (addr, false),
+        Root::Xsdt(addr) => (addr, true),
+    };
+    let root = unsafe { table_at(root)? };
+    // 先只读签名，不关心的表即使损坏也不影响查找
+    find_in_root(root, wide, signature, |addr| unsafe {
+        let header = physical(addr, 4)?;
+        if header == signature {
+            table_at(addr)
+        } else {
+            Ok(header)
+        }
+    })
+}
+
+pub fn madt() -> Result<Madt<'static>, AcpiError> {
+    Madt::parse(find_table(b"APIC")?)
+}
+
+/// 从 FADT 和 DSDT 中取出关机需要的寄存器和值
+pub fn sleep_control() -> Result<SleepControl, AcpiError> {
+    let fadt = Fadt::parse(find_table(b"FACP")?)?;
+    let (slp_typ_a, slp_typ_b) = parse_s5(unsafe { table_at(fadt.dsdt)? })?;
+    Ok(SleepControl {
+        fadt,
+        slp_typ_a,
+        slp_typ_b,
+    })
+}
+
+/// 固件还没有切换到 ACPI 模式（SCI_EN 为 0）时通过 SMI 命令端口切换，
+/// 在 SLP_EN 起作用之前需要完成。等待超时后直接返回，之后的关机尝试可能不起作用
+pub fn enable(fadt: &Fadt) {
+    let mut pm1a = Port::<u16>::new(fadt.pm1a_control);
+    if unsafe { pm1a.read() } & SCI_EN != 0 || fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    unsafe { Port::<u8>::new(fadt.smi_command).write(fadt.acpi_enable) };
+    for _ in 0..ACPI_ENABLE_TIMEOUT_MS {
+        if unsafe { pm1a.read() } & SCI_EN != 0 {
+            return;
+        }
+        time::spin_delay_ms(1);
+    }
+}
+
+/// 把 bytes 的校验和修正为 0，校验和字节在 offset 处
+#[cfg(test)]
+fn seal(bytes: &mut [u8], offset: usize) {
+    bytes[offset] = 0;
+    let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    bytes[offset] = sum.wrapping_neg();
+}
+
+/// 带有表头的表，body 跟在表头之后
+#[cfg(test)]
+fn make_table(signature: &[u8; 4], body: &[u8]) -> alloc::vec::Vec<u8> {
+    let mut table = alloc::vec![0u8; SDT_HEADER_LEN];
+    table[..4].copy_from_slice(signature);
+    table.extend_from_slice(body);
+    let length = table.len() as u32;
+    table[4..8].copy_from_slice(&length.to_le_bytes());
+    seal(&mut table, 9);
+    table
+}
+
+#[test_case]
+fn test_parse_rsdp() {
+    let mut rsdp = [0u8; RSDP_V2_LEN];
+    rsdp[..8].copy_from_slice(RSDP_SIGNATURE);
+    rsdp[16..20].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+    seal(&mut rsdp[..RSDP_V1_LEN], 8);
+    assert_eq!(parse_rsdp(&rsdp), Some(Root::Rsdt(0x1234_5678)));
+
+    // 版本 2：扩展校验和正确时使用 XSDT，否则退回 RSDT
+    rsdp[15] = 2;
+    rsdp[20..24].copy_from_slice(&(RSDP_V2_LEN as u32).to_le_bytes());
+    rsdp[24..32].copy_from_slice(&0x1_0000_0000u64.to_le_bytes());
+    seal(&mut rsdp[..RSDP_V1_LEN], 8);
+    assert_eq!(parse_rsdp(&rsdp), Some(Root::Rsdt(0x1234_5678)));
+    seal(&mut rsdp, 32);
+    assert_eq!(parse_rsdp(&rsdp), Some(Root::Xsdt(0x1_0000_0000)));
+
+    rsdp[16] ^= 1;
+    assert_eq!(parse_rsdp(&rsdp), None);
+    assert_eq!(parse_rsdp(b"RSD PTR"), None);
+}
+
+#[test_case]
+fn test_root_entries() {
+    let mut body = alloc::vec::Vec::new();
+    body.extend_from_slice(&0x1000u32.to_le_bytes());
+    body.extend_from_slice(&0x2000u32.to_le_bytes());
+    let rsdt = make_table(b"RSDT", &body);
+    let rsdt = validate(&rsdt).unwrap();
+    assert!(root_entries(rsdt, false).eq([0x1000, 0x2000]));
+    assert!(root_entries(rsdt, true).eq([0x2000_0000_1000]));
+
+    let mut bad = make_table(b"RSDT", &body);
+    bad[SDT_HEADER_LEN] ^= 1;
+    assert_eq!(validate(&bad), Err(AcpiError::BadChecksum(*b"RSDT")));
+    assert_eq!(
+        validate(&bad[..SDT_HEADER_LEN]),
+        Err(AcpiError::Truncated(*b"RSDT"))
+    );
+}
+
+#[test_case]
+fn test_parse_madt() {
+    let mut body = alloc::vec::Vec::new();
+    body.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+    body.extend_from_slice(&1u32.to_le_bytes());
+    // 三个处理器，第三个没有启用；中间夹着一个 I/O APIC 条目（类型 1）
+    body.extend_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]);
+    body.extend_from_slice(&[1, 12, 0, 0, 0, 0, 0xc0, 0xfe, 0, 0, 0, 0]);
+    body.extend_from_slice(&[0, 8, 1, 2, 1, 0, 0, 0]);
+    body.extend_from_slice(&[0, 8, 2, 4, 2, 0, 0, 0]);
+    let table = make_table(b"APIC", &body);
+    let madt = Madt::parse(&table).unwrap();
+    assert_eq!(madt.local_apic_address, 0xfee0_0000);
+    let apic = |processor_id, apic_id, enabled| LocalApic {
+        processor_id,
+        apic_id,
+        enabled,
+    };
+    assert!(madt
+        .local_apics()
+        .eq([apic(0, 0, true), apic(1, 2, true), apic(2, 4, false)]));
+
+    // 类型 5 覆盖本地 APIC 地址；长度为 0 的条目让遍历停止
+    body.extend_from_slice(&[5, 12, 0, 0]);
+    body.extend_from_slice(&0x1_fee0_0000u64.to_le_bytes());
+    body.extend_from_slice(&[0, 0]);
+    let table = make_table(b"APIC", &body);
+    let madt = Madt::parse(&table).unwrap();
+    assert_eq!(madt.local_apic_address, 0x1_fee0_0000);
+    assert_eq!(madt.local_apics().count(), 3);
+}
+
+/// 带有 \_S5 的 DSDT：Name(\_S5, Package(4) { 5, 0, 0, 0 })，前面放一段包含 "_S5_" 的字符串
+#[cfg(test)]
+fn make_dsdt(root_prefix: bool) -> alloc::vec::Vec<u8> {
+    let mut aml = alloc::vec::Vec::new();
+    // String("_S5_")，不是 Name 对象
+    aml.extend_from_slice(b"\x0d_S5_\0");
+    aml.push(AML_NAME_OP);
+    if root_prefix {
+        aml.push(AML_ROOT_PREFIX);
+    }
+    aml.extend_from_slice(b"_S5_");
+    aml.extend_from_slice(&[
+        AML_PACKAGE_OP,
+        0x0a,
+        0x04,
+        AML_BYTE_PREFIX,
+        0x05,
+        AML_ZERO_OP,
+    ]);
+    aml.extend_from_slice(&[AML_ZERO_OP, AML_ZERO_OP]);
+    make_table(b"DSDT", &aml)
+}
+
+#[cfg(test)]
+fn make_fadt(dsdt: u32, x_dsdt: Option<u64>, pm1a: u32, pm1b: u32) -> alloc::vec::Vec<u8> {
+    let mut body = alloc::vec![0u8; FADT_X_DSDT + 8 - SDT_HEADER_LEN];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        body[offset - SDT_HEADER_LEN..][..bytes.len()].copy_from_slice(bytes)
+    };
+    put(FADT_DSDT, &dsdt.to_le_bytes());
+    put(FADT_SMI_COMMAND, &0xb2u32.to_le_bytes());
+    put(FADT_ACPI_ENABLE, &[0xf0]);
+    put(FADT_PM1A_CONTROL, &pm1a.to_le_bytes());
+    put(FADT_PM1B_CONTROL, &pm1b.to_le_bytes());
+    if let Some(x_dsdt) = x_dsdt {
+        put(FADT_X_DSDT, &x_dsdt.to_le_bytes());
+    } else {
+        // ACPI 1.0 的 FADT 没有 X_DSDT
+        body.truncate(FADT_X_DSDT - SDT_HEADER_LEN);
+    }
+    make_table(b"FACP", &body)
+}
+
+#[test_case]
+fn test_parse_fadt() {
+    let fadt = Fadt::parse(&make_fadt(0x7fe0_0000, None, 0x604, 0)).unwrap();
+    assert_eq!(
+        fadt,
+        Fadt {
+            dsdt: 0x7fe0_0000,
+            pm1a_control: 0x604,
+            pm1b_control: None,
+            smi_command: 0xb2,
+            acpi_enable: 0xf0,
+        }
+    );
+    // 有 X_DSDT 时优先使用
+    let fadt = Fadt::parse(&make_fadt(0x1000, Some(0x1_0000_0000), 0x1804, 0x1808)).unwrap();
+    assert_eq!(fadt.dsdt, 0x1_0000_0000);
+    assert_eq!(fadt.pm1b_control, Some(0x1808));
+
+    assert_eq!(
+        Fadt::parse(&make_fadt(0x1000, None, 0, 0)),
+        Err(AcpiError::NoPm1Control)
+    );
+    let mut bad = make_fadt(0x1000, None, 0x604, 0);
+    bad[FADT_PM1A_CONTROL] ^= 0x10;
+    assert_eq!(Fadt::parse(&bad), Err(AcpiError::BadChecksum(*b"FACP")));
+}
+
+#[test_case]
+fn test_parse_s5() {
+    assert_eq!(parse_s5(&make_dsdt(true)), Ok((5, 0)));
+    assert_eq!(parse_s5(&make_dsdt(false)), Ok((5, 0)));
+    // 只有字符串中的 "_S5_"
+    let dsdt = make_table(b"DSDT", b"\x0d_S5_\0\x08_S4_\x12\x06\x02\x01\x01");
+    assert_eq!(parse_s5(&dsdt), Err(AcpiError::NoS5));
+    // 元素不是常量
+    let dsdt = make_table(b"DSDT", b"\x08_S5_\x12\x06\x02\x70\x01");
+    assert_eq!(parse_s5(&dsdt), Err(AcpiError::NoS5));
+    // 两字节的 PkgLength，OneOp
+    let dsdt = make_table(b"DSDT", b"\x08_S5_\x12\x40\x01\x02\x01\x0a\x07");
+    assert_eq!(parse_s5(&dsdt), Ok((1, 7)));
+    // 被截断的 DSDT
+    let mut dsdt = make_dsdt(true);
+    dsdt.truncate(dsdt.len() - 4);
+    assert_eq!(parse_s5(&dsdt), Err(AcpiError::Truncated(*b"DSDT")));
+
+    let control = SleepControl {
+        fadt: Fadt::parse(&make_fadt(0x1000, None, 0x604, 0)).unwrap(),
+        slp_typ_a: 5,
+        slp_typ_b: 0,
+    };
+    assert_eq!(control.pm1a_value(), 0x3400);
+    assert_eq!(control.pm1b_value(), 0x2000);
+}
+
+#[test_case]
+fn test_find_fadt_in_root() {
+    use alloc::vec::Vec;
+
+    let madt = make_table(b"APIC", &[0; 8]);
+    let fadt = make_fadt(0x3000, None, 0x604, 0);
+    let mut body = Vec::new();
+    for addr in [0x1000u64, 0x2000] {
+        body.extend_from_slice(&addr.to_le_bytes());
+    }
+    let xsdt = make_table(b"XSDT", &body);
+    let tables = |madt: &[u8], fadt: &[u8]| -> [(u64, Vec<u8>); 2] {
+        [(0x1000, madt.to_vec()), (0x2000, fadt.to_vec())]
+    };
+    fn lookup(tables: &[(u64, Vec<u8>)], addr: u64) -> Result<&[u8], AcpiError> {
+        let (_, table) = tables.iter().find(|(at, _)| *at == addr).unwrap();
+        validate(table)
+    }
+
+    let good = tables(&madt, &fadt);
+    let found = find_in_root(&xsdt, true, b"FACP", |addr| lookup(&good, addr)).unwrap();
+    assert_eq!(Fadt::parse(found).unwrap().dsdt, 0x3000);
+    assert_eq!(
+        find_in_root(&xsdt, true, b"HPET", |addr| lookup(&good, addr)),
+        Err(AcpiError::NotFound(*b"HPET"))
+    );
+
+    // 校验和错误的表让查找失败，而不是返回损坏的数据
+    let mut corrupt = fadt.clone();
+    corrupt[FADT_PM1A_CONTROL + 1] ^= 1;
+    let bad = tables(&madt, &corrupt);
+    assert_eq!(
+        find_in_root(&xsdt, true, b"FACP", |addr| lookup(&bad, addr)),
+        Err(AcpiError::BadChecksum(*b"FACP"))
+    );
+}
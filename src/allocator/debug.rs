@@ -0,0 +1,375 @@
+//! 调试用的堆包装，打开 heap_debug feature 时包在全局分配器外面
+//!
+//! 每个块的布局（前缀按对齐要求补齐）：
+//!
+//! ```text
+//! [填充][大小][CANARY] 用户数据 [CANARY]
+//!                     ^ 返回给调用者的指针
+//! ```
+//!
+//! - 新分配的用户数据填充 ALLOC_POISON，释放的整个块填充 FREE_POISON，
+//!   使用未初始化或已释放的内存时更容易看出来
+//! - 释放时检查两端的 canary 和记录的大小，末尾的 canary 被改写说明写越界
+//! - 最近释放的 RECENT_FREES 个指针记录下来，再次释放其中之一是重复释放
+//! - 不在堆中、或者前面没有 canary 的指针不是分配出去的块
+//!
+//! 重复释放和无效的指针不交给后端，宁可泄漏也不破坏后端的空闲链表。
+//! 问题通过 kerror! 报告；释放可能发生在持有 WRITER 锁的代码中（例如 shell 命令），此时只写串口
+use crate::vga_buffer::WRITER;
+use crate::{kerror, serial_println};
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+pub const ALLOC_POISON: u8 = 0xcd;
+pub const FREE_POISON: u8 = 0xdd;
+pub const CANARY: u64 = 0xc0ff_ee00_dead_beef;
+/// 记录的最近释放的指针个数
+pub const RECENT_FREES: usize = 16;
+
+/// 用户数据之前的大小和 canary
+const HEADER_SIZE: usize = 16;
+const TRAILER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// 末尾的 canary 被改写
+    Overflow {
+        address: usize,
+        size: usize,
+    },
+    /// 释放时给出的大小与分配时不同
+    SizeMismatch {
+        address: usize,
+        allocated: usize,
+        freed: usize,
+    },
+    DoubleFree {
+        address: usize,
+    },
+    /// 指针不在堆中，或者前面的 canary 不对（不是分配出去的块，或者块之前的内存被改写）
+    InvalidFree {
+        address: usize,
+    },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeapError::Overflow { address, size } => write!(
+                f,
+                "buffer overflow past the {}-byte block at {:#x}",
+                size, address
+            ),
+            HeapError::SizeMismatch {
+                address,
+                allocated,
+                freed,
+            } => write!(
+                f,
+                "block at {:#x} allocated with {} bytes but freed with {}",
+                address, allocated, freed
+            ),
+            HeapError::DoubleFree { address } => write!(f, "double free of {:#x}", address),
+            HeapError::InvalidFree { address } => {
+                write!(f, "free of {:#x}, which was never allocated", address)
+            }
+        }
+    }
+}
+
+struct State {
+    recent: [usize; RECENT_FREES],
+    next: usize,
+    last_error: Option<HeapError>,
+}
+
+pub struct DebugHeap<A> {
+    inner: A,
+    state: Mutex<State>,
+    errors: AtomicUsize,
+    /// 堆的范围，都为 0 时不检查
+    start: AtomicUsize,
+    end: AtomicUsize,
+}
+
+/// 用户数据之前的字节数：至少放得下头部，并且保持用户数据的对齐
+fn prefix(layout: Layout) -> usize {
+    HEADER_SIZE.next_multiple_of(layout.align())
+}
+
+fn block_layout(layout: Layout) -> Option<Layout> {
+    let size = prefix(layout)
+        .checked_add(layout.size())?
+        .checked_add(TRAILER_SIZE)?;
+    Layout::from_size_align(size, layout.align().max(8)).ok()
+}
+
+impl<A: GlobalAlloc> DebugHeap<A> {
+    pub const fn new(inner: A) -> Self {
+        DebugHeap {
+            inner,
+            state: Mutex::new(State {
+                recent: [0; RECENT_FREES],
+                next: 0,
+                last_error: None,
+            }),
+            errors: AtomicUsize::new(0),
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// 设置堆的范围，之后释放范围外的指针直接报告为无效，不读取它前面的内存
+    pub fn set_range(&self, start: usize, size: usize) {
+        self.start.store(start, Ordering::Relaxed);
+        self.end.store(start + size, Ordering::Relaxed);
+    }
+
+    /// 报告过的问题数
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn last_error(&self) -> Option<HeapError> {
+        self.state.lock().last_error
+    }
+
+    fn report(&self, error: HeapError) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.state.lock().last_error = Some(error);
+        if WRITER.is_locked() {
+            serial_println!("heap: {}", error);
+        } else {
+            kerror!("heap: {}", error);
+        }
+    }
+
+    fn in_range(&self, address: usize, len: usize) -> bool {
+        let (start, end) = (
+            self.start.load(Ordering::Relaxed),
+            self.end.load(Ordering::Relaxed),
+        );
+        (start == 0 && end == 0)
+            || (address >= start && address.checked_add(len).is_some_and(|last| last <= end))
+    }
+
+    /// 检查要释放的块，Ok 时块可以交给后端
+    unsafe fn check_free(&self, ptr: *mut u8, layout: Layout) -> Result<(), HeapError> {
+        let address = ptr as usize;
+        if self.state.lock().recent.contains(&address) {
+            return Err(HeapError::DoubleFree { address });
+        }
+        let invalid = HeapError::InvalidFree { address };
+        if !address.is_multiple_of(8)
+            || address < HEADER_SIZE
+            || !self.in_range(address - HEADER_SIZE, HEADER_SIZE)
+        {
+            return Err(invalid);
+        }
+        let header = ptr.sub(HEADER_SIZE) as *const u64;
+        if header.add(1).read() != CANARY {
+            return Err(invalid);
+        }
+        let allocated = header.read() as usize;
+        if allocated != layout.size() {
+            return Err(HeapError::SizeMismatch {
+                address,
+                allocated,
+                freed: layout.size(),
+            });
+        }
+        if (ptr.add(allocated) as *const u64).read_unaligned() != CANARY {
+            return Err(HeapError::Overflow {
+                address,
+                size: allocated,
+            });
+        }
+        Ok(())
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(block) = block_layout(layout) else {
+            return core::ptr::null_mut();
+        };
+        let base = self.inner.alloc(block);
+        if base.is_null() {
+            return base;
+        }
+        let ptr = base.add(prefix(layout));
+        let header = ptr.sub(HEADER_SIZE) as *mut u64;
+        header.write(layout.size() as u64);
+        header.add(1).write(CANARY);
+        ptr.write_bytes(ALLOC_POISON, layout.size());
+        (ptr.add(layout.size()) as *mut u64).write_unaligned(CANARY);
+        // 地址被重新分配出去后，再释放它就不是重复释放了
+        let mut state = self.state.lock();
+        for slot in state
+            .recent
+            .iter_mut()
+            .filter(|slot| **slot == ptr as usize)
+        {
+            *slot = 0;
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.check_free(ptr, layout) {
+            Ok(()) => {}
+            // 块本身是有效的，报告之后照常释放
+            Err(error @ HeapError::Overflow { .. }) => self.report(error),
+            Err(error) => return self.report(error),
+        }
+        let Some(block) = block_layout(layout) else {
+            return;
+        };
+        let base = ptr.sub(prefix(layout));
+        base.write_bytes(FREE_POISON, block.size());
+        {
+            let mut state = self.state.lock();
+            let next = state.next;
+            state.recent[next] = ptr as usize;
+            state.next = (next + 1) % RECENT_FREES;
+        }
+        self.inner.dealloc(base, block);
+    }
+}
+
+/// 测试用的堆：后端管理一段从全局堆借来的内存
+#[cfg(test)]
+struct TestHeap {
+    heap: DebugHeap<linked_list_allocator::LockedHeap>,
+    arena: alloc::vec::Vec<u64>,
+}
+
+#[cfg(test)]
+impl TestHeap {
+    fn new() -> Self {
+        let mut arena = alloc::vec![0u64; 512];
+        let heap = DebugHeap::new(linked_list_allocator::LockedHeap::empty());
+        let (start, size) = (arena.as_mut_ptr() as *mut u8, arena.len() * 8);
+        unsafe { heap.inner().lock().init(start, size) };
+        heap.set_range(start as usize, size);
+        TestHeap { heap, arena }
+    }
+
+    fn contains(&self, ptr: *mut u8) -> bool {
+        self.arena.as_ptr_range().contains(&(ptr as *const u64))
+    }
+}
+
+#[test_case]
+fn test_poison_and_clean_free() {
+    let test = TestHeap::new();
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    unsafe {
+        let ptr = test.heap.alloc(layout);
+        assert!(test.contains(ptr));
+        assert!((0..24).all(|i| ptr.add(i).read() == ALLOC_POISON));
+        ptr.write_bytes(0x11, 24);
+        test.heap.dealloc(ptr, layout);
+        // 后端的空闲链表节点只占用块开头的头部，用户数据保持 FREE_POISON
+        assert!((0..24).all(|i| ptr.add(i).read() == FREE_POISON));
+    }
+    assert_eq!(test.heap.errors(), 0);
+
+    // 对齐要求大于头部时用户数据仍然对齐
+    let aligned = Layout::from_size_align(8, 64).unwrap();
+    unsafe {
+        let ptr = test.heap.alloc(aligned);
+        assert!((ptr as usize).is_multiple_of(64));
+        test.heap.dealloc(ptr, aligned);
+    }
+    assert_eq!(test.heap.errors(), 0);
+}
+
+#[test_case]
+fn test_overflow_into_canary() {
+    let test = TestHeap::new();
+    let layout = Layout::from_size_align(10, 1).unwrap();
+    unsafe {
+        let ptr = test.heap.alloc(layout);
+        // 多写了一个字节
+        ptr.write_bytes(b'x', 11);
+        test.heap.dealloc(ptr, layout);
+        assert_eq!(
+            test.heap.last_error(),
+            Some(HeapError::Overflow {
+                address: ptr as usize,
+                size: 10
+            })
+        );
+    }
+    assert_eq!(test.heap.errors(), 1);
+}
+
+#[test_case]
+fn test_double_free() {
+    let test = TestHeap::new();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    unsafe {
+        let ptr = test.heap.alloc(layout);
+        test.heap.dealloc(ptr, layout);
+        assert_eq!(test.heap.errors(), 0);
+        test.heap.dealloc(ptr, layout);
+        assert_eq!(
+            test.heap.last_error(),
+            Some(HeapError::DoubleFree {
+                address: ptr as usize
+            })
+        );
+        assert_eq!(test.heap.errors(), 1);
+
+        // 同一个地址再次分配出去后可以正常释放
+        let again = test.heap.alloc(layout);
+        assert_eq!(again, ptr);
+        test.heap.dealloc(again, layout);
+    }
+    assert_eq!(test.heap.errors(), 1);
+}
+
+#[test_case]
+fn test_free_of_bogus_pointers() {
+    let test = TestHeap::new();
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    unsafe {
+        // 堆外的地址不会被读取
+        let outside = 0x1000 as *mut u8;
+        test.heap.dealloc(outside, layout);
+        assert_eq!(
+            test.heap.last_error(),
+            Some(HeapError::InvalidFree { address: 0x1000 })
+        );
+
+        // 堆中但不是块的开头
+        let ptr = test.heap.alloc(Layout::from_size_align(64, 8).unwrap());
+        let inner = ptr.add(32);
+        test.heap.dealloc(inner, layout);
+        assert_eq!(
+            test.heap.last_error(),
+            Some(HeapError::InvalidFree {
+                address: inner as usize
+            })
+        );
+
+        // 大小与分配时不同
+        test.heap.dealloc(ptr, layout);
+        assert_eq!(
+            test.heap.last_error(),
+            Some(HeapError::SizeMismatch {
+                address: ptr as usize,
+                allocated: 64,
+                freed: 16
+            })
+        );
+    }
+    assert_eq!(test.heap.errors(), 3);
+}
@@ -0,0 +1,173 @@
+//! 内核堆
+//! 在虚拟地址 HEAP_START 处映射 HEAP_SIZE 大小的页，交给 linked_list_allocator 管理。
+//! 打开 heap_debug feature 时全局分配器外面包一层 debug::DebugHeap，关闭时没有任何额外开销。
+//! 最外面是故障注入点 faultinject::ALLOC，只在测试和打开 faultinject feature 时起作用
+//!
+//! 分配失败时 alloc 调用 #[alloc_error_handler]。它需要 nightly 的 alloc_error_handler feature，
+//! 只能定义在最终的二进制中，所以与 #[panic_handler] 一样由 main.rs 定义并转发到 handle_alloc_error
+use crate::{eprintln, faultinject, hlt_loop};
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use linked_list_allocator::LockedHeap;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+pub mod debug;
+
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+pub const HEAP_SIZE: usize = 100 * 1024;
+
+#[cfg(not(feature = "heap_debug"))]
+#[global_allocator]
+static ALLOCATOR: Injectable<LockedHeap> = Injectable(LockedHeap::empty());
+
+#[cfg(feature = "heap_debug")]
+#[global_allocator]
+static ALLOCATOR: Injectable<debug::DebugHeap<LockedHeap>> =
+    Injectable(debug::DebugHeap::new(LockedHeap::empty()));
+
+/// 实际管理堆内存的后端
+fn backend() -> &'static LockedHeap {
+    #[cfg(not(feature = "heap_debug"))]
+    return &ALLOCATOR.0;
+    #[cfg(feature = "heap_debug")]
+    return ALLOCATOR.0.inner();
+}
+
+/// 注入点 faultinject::ALLOC 要求失败时返回空指针，否则交给 A
+struct Injectable<A>(A);
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Injectable<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if faultinject::should_fail(faultinject::ALLOC) {
+            return core::ptr::null_mut();
+        }
+        unsafe { self.0.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.0.dealloc(ptr, layout) }
+    }
+}
+
+/// 堆的使用情况，单位是字节
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "heap: {} of {} bytes used, {} free",
+            self.used, self.total, self.free
+        )
+    }
+}
+
+/// 后端统计的堆使用情况：每次分配和释放时更新，按后端实际占用的大小计算
+/// （向上取整到最小块大小；打开 heap_debug 时包括头部和 canary）。init_heap 之前全为 0
+pub fn heap_stats() -> HeapStats {
+    stats_of(&backend().lock())
+}
+
+/// 与 heap_stats 相同，但堆的锁正被占用时返回 None，可以在被打断的代码可能正在分配时使用
+pub fn try_heap_stats() -> Option<HeapStats> {
+    backend().try_lock().map(|heap| stats_of(&heap))
+}
+
+fn stats_of(heap: &linked_list_allocator::Heap) -> HeapStats {
+    HeapStats {
+        total: heap.size(),
+        used: heap.used(),
+        free: heap.free(),
+    }
+}
+
+/// 为堆区域分配物理帧并建立映射，然后初始化分配器
+pub fn init_heap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let page_range = {
+        let heap_start = VirtAddr::new(HEAP_START as u64);
+        let heap_end = heap_start + HEAP_SIZE as u64 - 1u64;
+        let heap_start_page = Page::containing_address(heap_start);
+        let heap_end_page = Page::containing_address(heap_end);
+        Page::range_inclusive(heap_start_page, heap_end_page)
+    };
+
+    for page in page_range {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    unsafe {
+        backend().lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+    }
+    #[cfg(feature = "heap_debug")]
+    ALLOCATOR.0.set_range(HEAP_START, HEAP_SIZE);
+
+    Ok(())
+}
+
+/// 用红色打印分配失败的大小和对齐，以及当时堆的使用情况。
+/// 堆已经用尽，所以输出不分配内存
+pub fn report_alloc_error(layout: Layout) {
+    eprintln!(
+        "out of memory: allocating {} bytes (align {}) failed; {}",
+        layout.size(),
+        layout.align(),
+        heap_stats()
+    );
+}
+
+/// #[alloc_error_handler] 的实现：报告后停机
+pub fn handle_alloc_error(layout: Layout) -> ! {
+    report_alloc_error(layout);
+    hlt_loop();
+}
+
+#[test_case]
+fn test_heap_stats_track_alloc_and_free() {
+    use alloc::vec::Vec;
+
+    let before = heap_stats();
+    assert_eq!(before.total, HEAP_SIZE);
+    assert_eq!(before.used + before.free, before.total);
+    let buffer: Vec<u8> = Vec::with_capacity(1000);
+    let during = heap_stats();
+    assert!(during.used >= before.used + 1000);
+    assert_eq!(during.used + during.free, during.total);
+    drop(buffer);
+    assert_eq!(heap_stats(), before);
+}
+
+#[test_case]
+fn test_injected_alloc_failure() {
+    use alloc::vec::Vec;
+    use x86_64::instructions::interrupts;
+
+    let mut buffer: Vec<u8> = Vec::with_capacity(16);
+    buffer.extend_from_slice(b"kept");
+    let before = heap_stats();
+    // 关闭中断，下一次分配一定是 try_reserve 的
+    let result = interrupts::without_interrupts(|| {
+        faultinject::set(faultinject::ALLOC, faultinject::Mode::Nth(1));
+        let result = buffer.try_reserve(1000);
+        faultinject::clear(faultinject::ALLOC);
+        result
+    });
+    assert!(result.is_err());
+    // 增长失败时原来的内容和堆都不变
+    assert_eq!(buffer, b"kept");
+    assert_eq!(heap_stats(), before);
+    assert!(buffer.try_reserve(1000).is_ok());
+}
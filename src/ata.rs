@@ -0,0 +1,661 @@
+//! ATA PIO 磁盘驱动
+//! 只支持主通道（命令寄存器 0x1F0-0x1F7，控制寄存器 0x3F6）上的两个驱动器，
+//! 用 28 位 LBA 的 READ SECTORS / WRITE SECTORS 命令，每次传输 512 字节的扇区，
+//! 数据通过数据端口逐字读写，全程轮询状态寄存器，不使用 IRQ 14。
+//!
+//! 每个扇区传输前等待 BSY 清零、DRQ 置位；ERR 或 DF 置位时读取错误寄存器并返回对应的错误。
+//! 轮询有次数上限，驱动器不响应时返回 Timeout 而不是卡住。
+//! 通道上没有任何驱动器时总线是浮空的，状态寄存器读出 0xFF
+//!
+//! ```shell
+//! qemu-system-x86_64 ... -drive file=disk.img,format=raw,if=ide
+//! ```
+use crate::faultinject;
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+pub const SECTOR_SIZE: usize = 512;
+
+const IO_BASE: u16 = 0x1f0;
+const CONTROL_BASE: u16 = 0x3f6;
+
+/// 等待状态变化时最多读取状态寄存器的次数，每次读端口大约 1 微秒
+const TIMEOUT_SPINS: usize = 1_000_000;
+/// 一条命令最多传输的扇区数，扇区计数寄存器写 0 表示 256
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+
+const COMMAND_READ_SECTORS: u8 = 0x20;
+const COMMAND_WRITE_SECTORS: u8 = 0x30;
+const COMMAND_CACHE_FLUSH: u8 = 0xe7;
+const COMMAND_IDENTIFY: u8 = 0xec;
+
+/// 设备控制寄存器 bit 1：关闭驱动器的中断
+const CONTROL_NIEN: u8 = 0x02;
+
+/// 状态寄存器的各位
+pub mod status {
+    pub const ERR: u8 = 0x01;
+    pub const DRQ: u8 = 0x08;
+    pub const DF: u8 = 0x20;
+    pub const RDY: u8 = 0x40;
+    pub const BSY: u8 = 0x80;
+    /// 没有驱动器时浮空总线读出的值
+    pub const FLOATING: u8 = 0xff;
+}
+
+/// 命令块中的寄存器，值是相对 0x1F0 的偏移
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Register {
+    /// 读出时是错误寄存器，写入时是特性寄存器
+    Error = 1,
+    SectorCount = 2,
+    LbaLow = 3,
+    LbaMid = 4,
+    LbaHigh = 5,
+    DriveHead = 6,
+    /// 读出时是状态寄存器，写入时是命令寄存器
+    Command = 7,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drive {
+    Master,
+    Slave,
+}
+
+impl Drive {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// 驱动器/磁头寄存器中的驱动器选择位
+    fn select_bit(self) -> u8 {
+        match self {
+            Drive::Master => 0,
+            Drive::Slave => 1 << 4,
+        }
+    }
+}
+
+/// 错误寄存器报告的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceError {
+    AddressMarkNotFound,
+    Track0NotFound,
+    /// 驱动器不支持这条命令或参数无效
+    Aborted,
+    MediaChangeRequest,
+    /// 扇区不存在，通常是 LBA 超出了容量
+    IdNotFound,
+    MediaChanged,
+    /// 数据无法纠正
+    Uncorrectable,
+    BadBlock,
+    /// ERR 置位但错误寄存器为 0
+    Unknown,
+}
+
+impl DeviceError {
+    /// 取错误寄存器中最高的置位
+    pub fn decode(error: u8) -> DeviceError {
+        const BITS: [DeviceError; 8] = [
+            DeviceError::AddressMarkNotFound,
+            DeviceError::Track0NotFound,
+            DeviceError::Aborted,
+            DeviceError::MediaChangeRequest,
+            DeviceError::IdNotFound,
+            DeviceError::MediaChanged,
+            DeviceError::Uncorrectable,
+            DeviceError::BadBlock,
+        ];
+        match error {
+            0 => DeviceError::Unknown,
+            error => BITS[7 - error.leading_zeros() as usize],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtaError {
+    /// 没有驱动器（状态为 0 或浮空总线）
+    NoDevice,
+    /// 驱动器存在但不是 ATA 硬盘（例如 ATAPI 光驱），值为 LBA mid/high 中的签名
+    NotAta(u8, u8),
+    /// 没有通过 IDENTIFY 识别过这个驱动器
+    NotIdentified,
+    Timeout,
+    /// 驱动器报告了故障（DF）
+    DeviceFault,
+    Device(DeviceError),
+    /// 访问的扇区超出了容量
+    OutOfRange {
+        lba: u32,
+        count: usize,
+        capacity: u32,
+    },
+    /// 缓冲区长度不是扇区大小的整数倍
+    BadBufferLength(usize),
+    /// 通道正被使用，见 try_read_sectors
+    Busy,
+}
+
+impl fmt::Display for AtaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtaError::NoDevice => write!(f, "no drive"),
+            AtaError::NotAta(mid, high) => {
+                write!(f, "not an ATA disk (signature {:02x}{:02x})", high, mid)
+            }
+            AtaError::NotIdentified => write!(f, "drive has not been identified"),
+            AtaError::Timeout => write!(f, "timed out"),
+            AtaError::DeviceFault => write!(f, "drive fault"),
+            AtaError::Device(error) => write!(f, "drive error: {:?}", error),
+            AtaError::OutOfRange {
+                lba,
+                count,
+                capacity,
+            } => write!(
+                f,
+                "sectors {}..{} beyond capacity {}",
+                lba,
+                *lba as u64 + *count as u64,
+                capacity
+            ),
+            AtaError::BadBufferLength(len) => {
+                write!(
+                    f,
+                    "buffer length {} is not a multiple of {}",
+                    len, SECTOR_SIZE
+                )
+            }
+            AtaError::Busy => write!(f, "channel busy"),
+        }
+    }
+}
+
+/// IDENTIFY 返回的信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    model: [u8; 40],
+    model_len: usize,
+    /// 28 位 LBA 可以访问的扇区数
+    pub sectors: u32,
+}
+
+impl Identity {
+    pub fn model(&self) -> &str {
+        core::str::from_utf8(&self.model[..self.model_len]).unwrap_or("?")
+    }
+
+    /// 从 IDENTIFY 的 256 个字解析
+    pub fn parse(words: &[u16; 256]) -> Identity {
+        let (model, model_len) = ata_string(&words[27..47]);
+        Identity {
+            model,
+            model_len,
+            sectors: words[60] as u32 | (words[61] as u32) << 16,
+        }
+    }
+}
+
+/// IDENTIFY 中的字符串每个字的高字节在前，交换后去掉末尾的空格填充
+/// 最多 20 个字，返回字节和有效长度
+pub fn ata_string(words: &[u16]) -> ([u8; 40], usize) {
+    let mut bytes = [0; 40];
+    for (pair, word) in bytes.chunks_exact_mut(2).zip(words) {
+        pair.copy_from_slice(&word.to_be_bytes());
+    }
+    let len = 2 * words.len().min(20);
+    let trimmed = bytes[..len]
+        .iter()
+        .rposition(|&byte| byte != b' ' && byte != 0)
+        .map_or(0, |last| last + 1);
+    // 非 ASCII 的字节不能保证是合法的 UTF-8
+    for byte in &mut bytes[..trimmed] {
+        if !byte.is_ascii() {
+            *byte = b'?';
+        }
+    }
+    (bytes, trimmed)
+}
+
+/// 访问通道的寄存器，抽象出来以便用脚本化的寄存器值测试
+pub trait AtaPorts {
+    fn read(&mut self, register: Register) -> u8;
+    fn write(&mut self, register: Register, value: u8);
+    fn read_data(&mut self) -> u16;
+    fn write_data(&mut self, value: u16);
+    /// 备用状态寄存器，读取它不会清除驱动器的中断
+    fn alt_status(&mut self) -> u8;
+    fn write_control(&mut self, value: u8);
+}
+
+struct PrimaryChannel;
+
+impl AtaPorts for PrimaryChannel {
+    fn read(&mut self, register: Register) -> u8 {
+        unsafe { Port::new(IO_BASE + register as u16).read() }
+    }
+
+    fn write(&mut self, register: Register, value: u8) {
+        unsafe { Port::new(IO_BASE + register as u16).write(value) }
+    }
+
+    fn read_data(&mut self) -> u16 {
+        unsafe { Port::new(IO_BASE).read() }
+    }
+
+    fn write_data(&mut self, value: u16) {
+        unsafe { Port::new(IO_BASE).write(value) }
+    }
+
+    fn alt_status(&mut self) -> u8 {
+        unsafe { Port::new(CONTROL_BASE).read() }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        unsafe { Port::new(CONTROL_BASE).write(value) }
+    }
+}
+
+/// 选择驱动器后驱动器需要 400ns 才能给出有效的状态，读 4 次备用状态寄存器大约就是这么久
+fn delay_400ns(ports: &mut impl AtaPorts) {
+    for _ in 0..4 {
+        ports.alt_status();
+    }
+}
+
+/// 等待 BSY 清零
+fn wait_not_busy(ports: &mut impl AtaPorts, spins: usize) -> Result<u8, AtaError> {
+    if faultinject::should_fail(faultinject::ATA_WAIT) {
+        return Err(AtaError::Timeout);
+    }
+    for _ in 0..spins {
+        let status = ports.read(Register::Command);
+        if status == status::FLOATING {
+            return Err(AtaError::NoDevice);
+        }
+        if status & status::BSY == 0 {
+            return Ok(status);
+        }
+        core::hint::spin_loop();
+    }
+    Err(AtaError::Timeout)
+}
+
+fn check_error(ports: &mut impl AtaPorts, status: u8) -> Result<(), AtaError> {
+    if status & status::ERR != 0 {
+        return Err(AtaError::Device(DeviceError::decode(
+            ports.read(Register::Error),
+        )));
+    }
+    if status & status::DF != 0 {
+        return Err(AtaError::DeviceFault);
+    }
+    Ok(())
+}
+
+/// 等待驱动器准备好传输一个扇区：BSY 清零后 DRQ 置位，期间出错立即返回
+fn poll(ports: &mut impl AtaPorts, spins: usize) -> Result<(), AtaError> {
+    for _ in 0..spins {
+        let status = wait_not_busy(ports, spins)?;
+        check_error(ports, status)?;
+        if status & status::DRQ != 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(AtaError::Timeout)
+}
+
+fn identify_with(ports: &mut impl AtaPorts, drive: Drive) -> Result<Identity, AtaError> {
+    ports.write(Register::DriveHead, 0xa0 | drive.select_bit());
+    delay_400ns(ports);
+    for register in [
+        Register::SectorCount,
+        Register::LbaLow,
+        Register::LbaMid,
+        Register::LbaHigh,
+    ] {
+        ports.write(register, 0);
+    }
+    ports.write(Register::Command, COMMAND_IDENTIFY);
+    if ports.read(Register::Command) == 0 {
+        return Err(AtaError::NoDevice);
+    }
+    wait_not_busy(ports, TIMEOUT_SPINS)?;
+    // ATAPI 和 SATA 设备会在 LBA mid/high 中留下非零的签名
+    let (mid, high) = (ports.read(Register::LbaMid), ports.read(Register::LbaHigh));
+    if mid != 0 || high != 0 {
+        return Err(AtaError::NotAta(mid, high));
+    }
+    poll(ports, TIMEOUT_SPINS)?;
+    let mut words = [0; 256];
+    for word in &mut words {
+        *word = ports.read_data();
+    }
+    Ok(Identity::parse(&words))
+}
+
+fn check_range(identity: &Identity, lba: u32, len: usize) -> Result<usize, AtaError> {
+    if !len.is_multiple_of(SECTOR_SIZE) {
+        return Err(AtaError::BadBufferLength(len));
+    }
+    let count = len / SECTOR_SIZE;
+    if lba as u64 + count as u64 > identity.sectors as u64 {
+        return Err(AtaError::OutOfRange {
+            lba,
+            count,
+            capacity: identity.sectors,
+        });
+    }
+    Ok(count)
+}
+
+/// 选择驱动器并写入 LBA、扇区数和命令，count 为 1-256
+fn start_command(
+    ports: &mut impl AtaPorts,
+    drive: Drive,
+    lba: u32,
+    count: usize,
+    command: u8,
+) -> Result<(), AtaError> {
+    wait_not_busy(ports, TIMEOUT_SPINS)?;
+    // bit 6 选择 LBA 模式，低 4 位是 LBA 的 24-27 位
+    ports.write(
+        Register::DriveHead,
+        0xe0 | drive.select_bit() | (lba >> 24) as u8 & 0x0f,
+    );
+    delay_400ns(ports);
+    ports.write(Register::SectorCount, count as u8);
+    ports.write(Register::LbaLow, lba as u8);
+    ports.write(Register::LbaMid, (lba >> 8) as u8);
+    ports.write(Register::LbaHigh, (lba >> 16) as u8);
+    ports.write(Register::Command, command);
+    Ok(())
+}
+
+fn read_with(
+    ports: &mut impl AtaPorts,
+    drive: Drive,
+    identity: &Identity,
+    lba: u32,
+    buf: &mut [u8],
+) -> Result<(), AtaError> {
+    check_range(identity, lba, buf.len())?;
+    for (index, chunk) in buf
+        .chunks_mut(SECTOR_SIZE * MAX_SECTORS_PER_COMMAND)
+        .enumerate()
+    {
+        let lba = lba + (index * MAX_SECTORS_PER_COMMAND) as u32;
+        start_command(
+            ports,
+            drive,
+            lba,
+            chunk.len() / SECTOR_SIZE,
+            COMMAND_READ_SECTORS,
+        )?;
+        for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+            poll(ports, TIMEOUT_SPINS)?;
+            for pair in sector.chunks_exact_mut(2) {
+                pair.copy_from_slice(&ports.read_data().to_le_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_with(
+    ports: &mut impl AtaPorts,
+    drive: Drive,
+    identity: &Identity,
+    lba: u32,
+    buf: &[u8],
+) -> Result<(), AtaError> {
+    check_range(identity, lba, buf.len())?;
+    for (index, chunk) in buf
+        .chunks(SECTOR_SIZE * MAX_SECTORS_PER_COMMAND)
+        .enumerate()
+    {
+        let lba = lba + (index * MAX_SECTORS_PER_COMMAND) as u32;
+        start_command(
+            ports,
+            drive,
+            lba,
+            chunk.len() / SECTOR_SIZE,
+            COMMAND_WRITE_SECTORS,
+        )?;
+        for sector in chunk.chunks_exact(SECTOR_SIZE) {
+            poll(ports, TIMEOUT_SPINS)?;
+            for pair in sector.chunks_exact(2) {
+                ports.write_data(u16::from_le_bytes([pair[0], pair[1]]));
+            }
+        }
+        // 写入的数据可能还在驱动器的缓存中
+        ports.write(Register::Command, COMMAND_CACHE_FLUSH);
+        let status = wait_not_busy(ports, TIMEOUT_SPINS)?;
+        check_error(ports, status)?;
+    }
+    Ok(())
+}
+
+/// 两个驱动器的 IDENTIFY 结果，同时作为访问通道的锁
+static DRIVES: Mutex<[Option<Identity>; 2]> = Mutex::new([None, None]);
+
+/// 识别主通道上的两个驱动器，返回各自的结果
+pub fn init() -> [Result<Identity, AtaError>; 2] {
+    let mut drives = DRIVES.lock();
+    let mut ports = PrimaryChannel;
+    ports.write_control(CONTROL_NIEN);
+    [Drive::Master, Drive::Slave].map(|drive| {
+        let result = identify_with(&mut ports, drive);
+        drives[drive.index()] = result.ok();
+        result
+    })
+}
+
+/// init 识别出的驱动器信息
+pub fn identity(drive: Drive) -> Option<Identity> {
+    DRIVES.lock()[drive.index()]
+}
+
+/// 从 lba 开始读取 buf.len() / 512 个扇区
+pub fn read_sectors(drive: Drive, lba: u32, buf: &mut [u8]) -> Result<(), AtaError> {
+    let drives = DRIVES.lock();
+    let identity = drives[drive.index()].ok_or(AtaError::NotIdentified)?;
+    read_with(&mut PrimaryChannel, drive, &identity, lba, buf)
+}
+
+/// 从 lba 开始写入 buf.len() / 512 个扇区，返回前刷新驱动器的缓存
+pub fn write_sectors(drive: Drive, lba: u32, buf: &[u8]) -> Result<(), AtaError> {
+    let drives = DRIVES.lock();
+    let identity = drives[drive.index()].ok_or(AtaError::NotIdentified)?;
+    write_with(&mut PrimaryChannel, drive, &identity, lba, buf)
+}
+
+/// 与 read_sectors 相同，但通道正被使用时立即返回 Busy 而不是等待，
+/// 用于 panic 处理：被打断的代码可能正在访问磁盘
+pub fn try_read_sectors(drive: Drive, lba: u32, buf: &mut [u8]) -> Result<(), AtaError> {
+    let drives = DRIVES.try_lock().ok_or(AtaError::Busy)?;
+    let identity = drives[drive.index()].ok_or(AtaError::NotIdentified)?;
+    read_with(&mut PrimaryChannel, drive, &identity, lba, buf)
+}
+
+/// 与 write_sectors 相同，通道正被使用时返回 Busy
+pub fn try_write_sectors(drive: Drive, lba: u32, buf: &[u8]) -> Result<(), AtaError> {
+    let drives = DRIVES.try_lock().ok_or(AtaError::Busy)?;
+    let identity = drives[drive.index()].ok_or(AtaError::NotIdentified)?;
+    write_with(&mut PrimaryChannel, drive, &identity, lba, buf)
+}
+
+#[cfg(test)]
+use alloc::collections::VecDeque;
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 脚本化的通道：状态寄存器依次返回 statuses 中的值，用完后一直返回最后一个
+#[cfg(test)]
+#[derive(Default)]
+struct ScriptedPorts {
+    statuses: VecDeque<u8>,
+    error: u8,
+    data: VecDeque<u16>,
+    writes: Vec<(Register, u8)>,
+    written_data: Vec<u16>,
+}
+
+#[cfg(test)]
+impl ScriptedPorts {
+    fn with_statuses(statuses: &[u8]) -> Self {
+        ScriptedPorts {
+            statuses: statuses.iter().copied().collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+impl AtaPorts for ScriptedPorts {
+    fn read(&mut self, register: Register) -> u8 {
+        match register {
+            Register::Command if self.statuses.len() > 1 => self.statuses.pop_front().unwrap(),
+            Register::Command => self.statuses[0],
+            Register::Error => self.error,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, register: Register, value: u8) {
+        self.writes.push((register, value));
+    }
+
+    fn read_data(&mut self) -> u16 {
+        self.data.pop_front().unwrap()
+    }
+
+    fn write_data(&mut self, value: u16) {
+        self.written_data.push(value);
+    }
+
+    fn alt_status(&mut self) -> u8 {
+        0
+    }
+
+    fn write_control(&mut self, _value: u8) {}
+}
+
+#[test_case]
+fn test_ata_string_swaps_bytes() {
+    // "QEMU HARDDISK" 后面用空格填充
+    let mut words = [0x2020; 20];
+    for (word, pair) in words.iter_mut().zip(b"QEMU HARDDISK ".chunks(2)) {
+        *word = u16::from_be_bytes([pair[0], pair[1]]);
+    }
+    let (bytes, len) = ata_string(&words);
+    assert_eq!(&bytes[..len], b"QEMU HARDDISK");
+
+    let mut identify = [0; 256];
+    identify[27..47].copy_from_slice(&words);
+    identify[60] = 0x8000;
+    identify[61] = 0x0001;
+    let identity = Identity::parse(&identify);
+    assert_eq!(identity.model(), "QEMU HARDDISK");
+    assert_eq!(identity.sectors, 0x1_8000);
+
+    assert_eq!(ata_string(&[0x2020; 20]).1, 0);
+}
+
+#[test_case]
+fn test_poll_status_machine() {
+    use status::*;
+
+    let mut ports = ScriptedPorts::with_statuses(&[BSY, BSY, RDY, RDY | DRQ]);
+    assert_eq!(poll(&mut ports, 10), Ok(()));
+
+    let mut ports = ScriptedPorts::with_statuses(&[BSY, RDY | ERR]);
+    ports.error = 0x10;
+    assert_eq!(
+        poll(&mut ports, 10),
+        Err(AtaError::Device(DeviceError::IdNotFound))
+    );
+
+    let mut ports = ScriptedPorts::with_statuses(&[RDY | DF]);
+    assert_eq!(poll(&mut ports, 10), Err(AtaError::DeviceFault));
+    let mut ports = ScriptedPorts::with_statuses(&[FLOATING]);
+    assert_eq!(poll(&mut ports, 10), Err(AtaError::NoDevice));
+    let mut ports = ScriptedPorts::with_statuses(&[BSY]);
+    assert_eq!(poll(&mut ports, 10), Err(AtaError::Timeout));
+
+    assert_eq!(DeviceError::decode(0x04), DeviceError::Aborted);
+    assert_eq!(DeviceError::decode(0x41), DeviceError::Uncorrectable);
+    assert_eq!(DeviceError::decode(0), DeviceError::Unknown);
+}
+
+#[test_case]
+fn test_read_sectors_sequence() {
+    use status::*;
+
+    let identity = Identity {
+        model: [0; 40],
+        model_len: 0,
+        sectors: 0x0100_0010,
+    };
+    let mut ports = ScriptedPorts::with_statuses(&[RDY, BSY, RDY | DRQ]);
+    ports.data = (0..256).collect();
+    let mut buf = [0; SECTOR_SIZE];
+    read_with(&mut ports, Drive::Slave, &identity, 0x0100_000f, &mut buf).unwrap();
+    assert_eq!(
+        ports.writes,
+        [
+            (Register::DriveHead, 0xe0 | 0x10 | 0x01),
+            (Register::SectorCount, 1),
+            (Register::LbaLow, 0x0f),
+            (Register::LbaMid, 0x00),
+            (Register::LbaHigh, 0x00),
+            (Register::Command, COMMAND_READ_SECTORS),
+        ]
+    );
+    // 数据端口的每个字低字节在前
+    assert_eq!(&buf[..4], [0, 0, 1, 0]);
+    assert_eq!(&buf[510..], [0xff, 0]);
+
+    // 超出容量时不访问驱动器
+    let mut ports = ScriptedPorts::with_statuses(&[RDY]);
+    let mut buf = [0; 2 * SECTOR_SIZE];
+    assert_eq!(
+        read_with(&mut ports, Drive::Master, &identity, 0x0100_000f, &mut buf),
+        Err(AtaError::OutOfRange {
+            lba: 0x0100_000f,
+            count: 2,
+            capacity: 0x0100_0010
+        })
+    );
+    assert!(ports.writes.is_empty());
+    assert_eq!(
+        read_with(&mut ports, Drive::Master, &identity, 0, &mut buf[..100]),
+        Err(AtaError::BadBufferLength(100))
+    );
+}
+
+#[test_case]
+fn test_injected_wait_timeout() {
+    use status::*;
+
+    let identity = Identity {
+        model: [0; 40],
+        model_len: 0,
+        sectors: 16,
+    };
+    // 驱动器本来已经准备好，注入点让等待超时
+    let mut ports = ScriptedPorts::with_statuses(&[RDY, RDY | DRQ]);
+    let mut buf = [0; SECTOR_SIZE];
+    faultinject::set(faultinject::ATA_WAIT, faultinject::Mode::Always);
+    let result = read_with(&mut ports, Drive::Master, &identity, 0, &mut buf);
+    let failures = faultinject::clear(faultinject::ATA_WAIT);
+    assert_eq!(result, Err(AtaError::Timeout));
+    assert_eq!(failures, 1);
+    assert_eq!(alloc::format!("{}", result.unwrap_err()), "timed out");
+    assert_eq!(buf, [0; SECTOR_SIZE]);
+}
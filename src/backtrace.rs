@@ -0,0 +1,286 @@
+//! 基于帧指针的调用栈回溯
+//! 目标配置中打开了 frame-pointer，每个函数的栈帧开头都是 [调用者的 RBP, 返回地址]，
+//! 从当前的 RBP 出发沿着保存的 RBP 链逐帧向上，打印每一帧的返回地址。
+//! 构建时嵌入了符号表（见 symbols 模块）就同时打印函数名和偏移，
+//! 否则只打印原始地址，可以离线用 addr2line 对照内核的 ELF 文件解析出函数名
+//!
+//! panic 时的栈可能已经损坏，所以每一步都要检查帧指针：必须 8 字节对齐、
+//! 比上一帧的高（栈向低地址增长，否则说明链成了环）、不超出内核栈的范围，
+//! 并且（知道物理内存偏移时）所在的页确实有映射，任何一项不满足就停止
+use crate::memory;
+use crate::symbols::{self, Demangle, MapError};
+use crate::vga_buffer::Stdout;
+use core::fmt::{self, Write};
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+
+/// 最多打印的帧数
+pub const MAX_DEPTH: usize = 32;
+
+/// bootloader 默认的内核栈大小（512 页），帧指针不会超出当前位置之上这么远
+const KERNEL_STACK_SIZE: u64 = 512 * 4096;
+
+/// 物理内存映射的偏移，0 表示还不知道，此时不检查地址是否有映射
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// 记录物理内存的偏移，之后的回溯会用页表检查每个帧指针
+pub fn init(physical_memory_offset: VirtAddr) {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+}
+
+/// 回溯停止的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkEnd {
+    /// 帧指针为 0，到达了链的末尾
+    Finished,
+    /// 达到 MAX_DEPTH
+    DepthLimit,
+    /// 帧指针没有 8 字节对齐
+    Misaligned(u64),
+    /// 帧指针不高于上一帧，链成了环或者已经损坏
+    NotAscending(u64),
+    /// 帧指针不在栈的范围内
+    OutOfStack(u64),
+    /// 帧指针所在的内存读不出来
+    Unreadable(u64),
+}
+
+/// 读取栈上的一个 u64，抽象出来以便用构造出来的帧链测试
+pub trait StackMemory {
+    /// 地址不可读时返回 None
+    fn read(&self, addr: u64) -> Option<u64>;
+}
+
+/// 当前地址空间，PHYSICAL_MEMORY_OFFSET 已知时先用页表检查地址
+struct KernelMemory;
+
+impl StackMemory for KernelMemory {
+    fn read(&self, addr: u64) -> Option<u64> {
+        let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed);
+        if offset != 0 {
+            let addr = VirtAddr::try_new(addr).ok()?;
+            unsafe { memory::translate_addr(addr, VirtAddr::new(offset)) }?;
+        }
+        Some(unsafe { core::ptr::read_volatile(addr as *const u64) })
+    }
+}
+
+/// 从帧指针 fp 开始回溯，对每一帧的返回地址调用 f(深度, 返回地址)
+/// stack 是允许的帧指针范围，整个 [fp, fp + 16) 都必须在其中
+pub fn walk(
+    mut fp: u64,
+    stack: Range<u64>,
+    memory: &impl StackMemory,
+    mut f: impl FnMut(usize, u64),
+) -> WalkEnd {
+    let mut previous = None;
+    for depth in 0..MAX_DEPTH {
+        if fp == 0 {
+            return WalkEnd::Finished;
+        }
+        if !fp.is_multiple_of(8) {
+            return WalkEnd::Misaligned(fp);
+        }
+        if previous.is_some_and(|previous| fp <= previous) {
+            return WalkEnd::NotAscending(fp);
+        }
+        if fp < stack.start || fp.checked_add(16).is_none_or(|end| end > stack.end) {
+            return WalkEnd::OutOfStack(fp);
+        }
+        // 两个字可能跨页，分别检查
+        let (Some(saved_fp), Some(return_address)) = (memory.read(fp), memory.read(fp + 8)) else {
+            return WalkEnd::Unreadable(fp);
+        };
+        f(depth, return_address);
+        previous = Some(fp);
+        fp = saved_fp;
+    }
+    WalkEnd::DepthLimit
+}
+
+/// 当前函数的帧指针，必须内联到调用者中
+#[inline(always)]
+fn frame_pointer() -> u64 {
+    let fp: u64;
+    unsafe { core::arch::asm!("mov {}, rbp", out(reg) fp, options(nomem, nostack)) };
+    fp
+}
+
+/// 打印调用者的调用栈，#0 是调用 print 的位置
+#[inline(never)]
+pub fn print() {
+    let _ = write_from(&mut Stdout, frame_pointer());
+}
+
+/// 与 print 相同，但写入 out，例如写到崩溃记录的缓冲区中；#0 是调用 write 的位置
+#[inline(never)]
+pub fn write(out: &mut impl Write) -> fmt::Result {
+    write_from(out, frame_pointer())
+}
+
+/// 把调用者的调用栈中最近的返回地址依次放进 addresses，返回回溯到的总帧数；
+/// 总帧数大于 addresses.len() 时多出来的帧被丢弃。#0 是调用 return_addresses 的位置
+#[inline(never)]
+pub fn return_addresses(addresses: &mut [u64]) -> usize {
+    let fp = frame_pointer();
+    let mut frames = 0;
+    walk(
+        fp,
+        fp..fp.saturating_add(KERNEL_STACK_SIZE),
+        &KernelMemory,
+        |depth, address| {
+            if let Some(slot) = addresses.get_mut(depth) {
+                *slot = address;
+            }
+            frames = depth + 1;
+        },
+    );
+    frames
+}
+
+fn write_from(out: &mut impl Write, fp: u64) -> fmt::Result {
+    writeln!(out, "stack backtrace:")?;
+    let map = symbols::kernel_map();
+    match &map {
+        Ok(_) | Err(MapError::Missing) => {}
+        Err(error) => writeln!(out, "  symbol map unusable: {:?}", error)?,
+    }
+    let mut result = Ok(());
+    let end = walk(
+        fp,
+        fp..fp.saturating_add(KERNEL_STACK_SIZE),
+        &KernelMemory,
+        |depth, address| {
+            // 返回地址指向 call 的下一条指令，调用发生在函数末尾时它已经属于下一个函数，
+            // 所以用前一个字节查找
+            let symbol = map
+                .as_ref()
+                .ok()
+                .zip(address.checked_sub(1))
+                .and_then(|(map, address)| map.resolve(address));
+            result = result.and_then(|()| match symbol {
+                Some((name, offset)) => writeln!(
+                    out,
+                    "  #{} {:#018x} {} +{:#x}",
+                    depth,
+                    address,
+                    Demangle(name),
+                    offset + 1
+                ),
+                None => writeln!(out, "  #{} {:#018x}", depth, address),
+            });
+        },
+    );
+    result?;
+    match end {
+        WalkEnd::Finished => Ok(()),
+        WalkEnd::DepthLimit => writeln!(out, "  ... (more than {} frames)", MAX_DEPTH),
+        other => writeln!(out, "  stopped: {:?}", other),
+    }
+}
+
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 从 BASE 开始的一段假的栈，按字存放
+#[cfg(test)]
+struct SyntheticStack(Vec<u64>);
+
+#[cfg(test)]
+const BASE: u64 = 0x1000;
+
+#[cfg(test)]
+impl SyntheticStack {
+    fn range(&self) -> Range<u64> {
+        BASE..BASE + self.0.len() as u64 * 8
+    }
+
+    /// 在第 slot 个字处放一帧
+    fn frame(&mut self, slot: usize, saved_fp: u64, return_address: u64) {
+        self.0[slot] = saved_fp;
+        self.0[slot + 1] = return_address;
+    }
+}
+
+#[cfg(test)]
+impl StackMemory for SyntheticStack {
+    fn read(&self, addr: u64) -> Option<u64> {
+        let index = addr.checked_sub(BASE)? / 8;
+        self.0.get(index as usize).copied()
+    }
+}
+
+#[cfg(test)]
+fn slot(index: usize) -> u64 {
+    BASE + index as u64 * 8
+}
+
+#[cfg(test)]
+fn collect(stack: &SyntheticStack, fp: u64) -> (Vec<u64>, WalkEnd) {
+    let mut addresses = Vec::new();
+    let end = walk(fp, stack.range(), stack, |depth, address| {
+        assert_eq!(depth, addresses.len());
+        addresses.push(address);
+    });
+    (addresses, end)
+}
+
+#[test_case]
+fn test_walk_follows_chain() {
+    let mut stack = SyntheticStack(alloc::vec![0; 16]);
+    stack.frame(2, slot(6), 0xa1);
+    stack.frame(6, slot(12), 0xa2);
+    stack.frame(12, 0, 0xa3);
+    assert_eq!(
+        collect(&stack, slot(2)),
+        (alloc::vec![0xa1, 0xa2, 0xa3], WalkEnd::Finished)
+    );
+}
+
+#[test_case]
+fn test_walk_stops_on_corruption() {
+    let mut stack = SyntheticStack(alloc::vec![0; 16]);
+
+    // 指向自己和指向更低的帧都是环
+    stack.frame(2, slot(2), 0xa1);
+    assert_eq!(
+        collect(&stack, slot(2)),
+        (alloc::vec![0xa1], WalkEnd::NotAscending(slot(2)))
+    );
+    stack.frame(2, slot(6), 0xa1);
+    stack.frame(6, slot(2), 0xa2);
+    assert_eq!(collect(&stack, slot(2)).1, WalkEnd::NotAscending(slot(2)));
+
+    stack.frame(6, slot(8) + 3, 0xa2);
+    assert_eq!(collect(&stack, slot(2)).1, WalkEnd::Misaligned(slot(8) + 3));
+
+    // 超出栈的范围，包括只有返回地址落在范围外
+    stack.frame(6, slot(15), 0xa2);
+    assert_eq!(collect(&stack, slot(2)).1, WalkEnd::OutOfStack(slot(15)));
+    stack.frame(6, 0xffff_ffff_ffff_fff8, 0xa2);
+    assert_eq!(
+        collect(&stack, slot(2)).1,
+        WalkEnd::OutOfStack(0xffff_ffff_ffff_fff8)
+    );
+    assert_eq!(collect(&stack, 8).1, WalkEnd::OutOfStack(8));
+}
+
+#[test_case]
+fn test_walk_depth_limit_and_unreadable() {
+    // 每帧只占两个字，一直向上排列，比 MAX_DEPTH 更长
+    let len = (MAX_DEPTH + 2) * 2;
+    let mut stack = SyntheticStack(alloc::vec![0; len]);
+    for frame in 0..MAX_DEPTH + 1 {
+        stack.frame(frame * 2, slot(frame * 2 + 2), frame as u64);
+    }
+    let (addresses, end) = collect(&stack, slot(0));
+    assert_eq!(addresses.len(), MAX_DEPTH);
+    assert_eq!(end, WalkEnd::DepthLimit);
+
+    // 范围比实际可读的内存大
+    let stack = SyntheticStack(alloc::vec![slot(4), 0xa1]);
+    let mut visited = 0;
+    let end = walk(slot(0), BASE..BASE + 64, &stack, |_, _| visited += 1);
+    assert_eq!((visited, end), (1, WalkEnd::Unreadable(slot(4))));
+}
@@ -0,0 +1,430 @@
+//! 启动阶段的进度显示
+//! boot::stage("net", || ...) 开始时打印一行 "[ .. ] net"，闭包返回后把这一行改成绿色的 "[ OK ]"
+//! 或者红色的 "[FAIL]"，并记录这个阶段用了多久；初始化结束时 summary 打印各阶段的用时表，
+//! 最慢的一项反色显示。
+//!
+//! - 闭包可以返回 () 或者 Result。返回 Err 时在状态行下面打印错误：stage 继续启动，
+//!   critical_stage 中止启动（panic）
+//! - 在一个阶段中开始的阶段缩进两格
+//! - 状态行打印后就换行，阶段自己的输出出现在它下面。闭包返回时如果屏幕没有滚动、光标还在行首，
+//!   状态行还在原处，原地改写；否则在下面重新打印一行完整的状态行，不去改写已经被挤上去的那一行
+//! - 状态行、错误和用时表都和 print! 一样交给登记的输出目标（console::sink），
+//!   串口等目标不能改写已经输出的行，屏幕上原地改写时它们收到一行完整的结果
+//!
+//! 用时用 TSC 计量，前几个阶段运行时时钟中断还没有开始；换算成毫秒时使用 time 模块对 TSC 频率的估计。
+//! 记录保存在固定大小的表中，不分配内存，堆初始化之前的阶段也可以使用
+use crate::console::sink::{self, Style, VGA_SINK};
+use crate::console::{self, ConsoleState};
+use crate::time;
+use crate::vga_buffer::{self, Color, ColorCode, Writer, BUFFER_HEIGHT, WRITER};
+use core::convert::Infallible;
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// 表中最多记录的阶段数，之后的阶段照常运行和显示，但不出现在用时表中
+pub const MAX_STAGES: usize = 32;
+/// 每一层嵌套缩进的列数
+pub const STAGE_INDENT: usize = 2;
+
+const OK_COLOR: ColorCode = ColorCode::new(Color::LightGreen, Color::Black);
+const FAIL_COLOR: ColorCode = ColorCode::new(Color::LightRed, Color::Black);
+
+/// 阶段的闭包可以返回的类型
+pub trait StageResult {
+    type Output;
+    type Error: fmt::Display;
+
+    fn into_result(self) -> Result<Self::Output, Self::Error>;
+}
+
+impl StageResult for () {
+    type Output = ();
+    type Error = Infallible;
+
+    fn into_result(self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl<T, E: fmt::Display> StageResult for Result<T, E> {
+    type Output = T;
+    type Error = E;
+
+    fn into_result(self) -> Result<T, E> {
+        self
+    }
+}
+
+/// 用时表中的一项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageRecord {
+    pub name: &'static str,
+    /// 嵌套的层数，最外层是 0
+    pub depth: usize,
+    /// 用了多少个 TSC 周期，阶段还没有结束时是 0
+    pub cycles: u64,
+    pub ok: bool,
+}
+
+impl StageRecord {
+    const EMPTY: StageRecord = StageRecord {
+        name: "",
+        depth: 0,
+        cycles: 0,
+        ok: false,
+    };
+}
+
+/// 各阶段的用时记录，按开始的顺序排列
+pub struct Stages {
+    records: [StageRecord; MAX_STAGES],
+    len: usize,
+    /// 正在运行的阶段的层数
+    depth: usize,
+    clock: fn() -> u64,
+}
+
+/// 正在运行的阶段，由 begin 返回、交给 end
+struct Running {
+    index: Option<usize>,
+    depth: usize,
+    start: u64,
+}
+
+impl Stages {
+    /// clock 返回当前的 TSC 值，测试中可以换成假的时钟
+    pub const fn new(clock: fn() -> u64) -> Self {
+        Stages {
+            records: [StageRecord::EMPTY; MAX_STAGES],
+            len: 0,
+            depth: 0,
+            clock,
+        }
+    }
+
+    pub fn records(&self) -> &[StageRecord] {
+        &self.records[..self.len]
+    }
+
+    fn begin(&mut self, name: &'static str) -> Running {
+        let depth = self.depth;
+        self.depth += 1;
+        let index = (self.len < MAX_STAGES).then(|| {
+            self.records[self.len] = StageRecord {
+                name,
+                depth,
+                ..StageRecord::EMPTY
+            };
+            self.len += 1;
+            self.len - 1
+        });
+        Running {
+            index,
+            depth,
+            start: (self.clock)(),
+        }
+    }
+
+    fn end(&mut self, running: &Running, ok: bool) {
+        let cycles = (self.clock)().wrapping_sub(running.start);
+        self.depth -= 1;
+        if let Some(index) = running.index {
+            self.records[index].cycles = cycles;
+            self.records[index].ok = ok;
+        }
+    }
+
+    /// 最慢的阶段：只在没有嵌套阶段的项中找，外层阶段的用时包含了里面的阶段
+    fn slowest(&self) -> Option<usize> {
+        let records = self.records();
+        (0..records.len())
+            .filter(|&index| {
+                records
+                    .get(index + 1)
+                    .is_none_or(|next| next.depth <= records[index].depth)
+            })
+            .max_by_key(|&index| records[index].cycles)
+    }
+
+    /// 输出用时表，tsc_per_ms 是每毫秒的 TSC 周期数，最慢的一项使用 highlight 的颜色；
+    /// out 按指定的颜色输出一段文字
+    pub fn write_summary(
+        &self,
+        tsc_per_ms: u64,
+        highlight: ColorCode,
+        mut out: impl FnMut(fmt::Arguments, Style),
+    ) {
+        let slowest = self.slowest();
+        out(format_args!("boot stages:\n"), Style::Normal);
+        for (index, record) in self.records().iter().enumerate() {
+            let micros = record.cycles * 1000 / tsc_per_ms.max(1);
+            let style = if Some(index) == slowest {
+                Style::Colored(highlight)
+            } else {
+                Style::Normal
+            };
+            out(
+                format_args!(
+                    "  {:indent$}{:<width$} {:>5}.{:03} ms{}",
+                    "",
+                    record.name,
+                    micros / 1000,
+                    micros % 1000,
+                    if record.ok { "" } else { " FAIL" },
+                    indent = record.depth * STAGE_INDENT,
+                    width = 20usize.saturating_sub(record.depth * STAGE_INDENT),
+                ),
+                style,
+            );
+            out(format_args!("\n"), Style::Normal);
+        }
+    }
+}
+
+static STAGES: Mutex<Stages> = Mutex::new(Stages::new(crate::time::rdtsc));
+
+fn with_writer<R>(f: impl FnOnce(&mut Writer) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut WRITER.lock()))
+}
+
+/// 按 style 输出一段文字；screen 为 false 时跳过屏幕，只交给其他输出目标
+fn emit(args: fmt::Arguments, style: Style, screen: bool) {
+    if screen {
+        vga_buffer::print_in_state(args, None, style);
+    } else if console::state() == ConsoleState::Full {
+        sink::dispatch_except(Some(VGA_SINK), args, None, style);
+    }
+}
+
+/// 屏幕上的光标不在行首时先换行
+fn start_line() {
+    if vga_buffer::routes().0 && with_writer(|writer| writer.column()) != 0 {
+        emit(format_args!("\n"), Style::Normal, true);
+    }
+}
+
+fn write_status(depth: usize, label: &str, style: Style, name: &str, screen: bool) {
+    emit(
+        format_args!("{:1$}", "", depth * STAGE_INDENT),
+        Style::Normal,
+        screen,
+    );
+    emit(format_args!("{}", label), style, screen);
+    emit(format_args!(" {}\n", name), Style::Normal, screen);
+}
+
+/// 打印 "[ .. ] name" 并换行
+fn show_running(depth: usize, name: &str) {
+    start_line();
+    write_status(depth, "[ .. ]", Style::Normal, name, true);
+    with_writer(|writer| writer.take_scrolled());
+}
+
+/// 把状态行改成结果：屏幕上之后没有任何输出时原地改写，否则在下面重新打印一行
+fn show_result(depth: usize, name: &str, ok: bool) {
+    let (label, color) = if ok {
+        ("[ OK ]", OK_COLOR)
+    } else {
+        ("[FAIL]", FAIL_COLOR)
+    };
+    let rewritten = vga_buffer::routes().0
+        && with_writer(|writer| {
+            if writer.take_scrolled() || writer.column() != 0 {
+                return false;
+            }
+            writer.with_saved_cursor(|writer| {
+                writer.set_color_code(color);
+                writer.write_fmt_at(
+                    BUFFER_HEIGHT - 2,
+                    depth * STAGE_INDENT,
+                    format_args!("{}", label),
+                );
+            });
+            true
+        });
+    if !rewritten {
+        start_line();
+    }
+    write_status(depth, label, Style::Colored(color), name, !rewritten);
+}
+
+/// 运行一个阶段，失败时返回错误；critical 只影响错误信息的说明
+fn run<R: StageResult>(
+    stages: &Mutex<Stages>,
+    name: &'static str,
+    critical: bool,
+    f: impl FnOnce() -> R,
+) -> Result<R::Output, R::Error> {
+    let running = interrupts::without_interrupts(|| stages.lock().begin(name));
+    show_running(running.depth, name);
+    let result = f().into_result();
+    interrupts::without_interrupts(|| stages.lock().end(&running, result.is_ok()));
+    show_result(running.depth, name, result.is_ok());
+    if let Err(error) = &result {
+        let consequence = if critical {
+            "aborting boot"
+        } else {
+            "continuing"
+        };
+        emit(
+            format_args!(
+                "{:indent$}{}: {} ({})",
+                "",
+                name,
+                error,
+                consequence,
+                indent = (running.depth + 1) * STAGE_INDENT,
+            ),
+            Style::Colored(FAIL_COLOR),
+            true,
+        );
+        emit(format_args!("\n"), Style::Normal, true);
+    }
+    result
+}
+
+/// 运行一个启动阶段并显示进度；失败时打印错误后继续启动，返回 None
+pub fn stage<R: StageResult>(name: &'static str, f: impl FnOnce() -> R) -> Option<R::Output> {
+    run(&STAGES, name, false, f).ok()
+}
+
+/// 与 stage 相同，但失败时中止启动
+pub fn critical_stage<R: StageResult>(name: &'static str, f: impl FnOnce() -> R) -> R::Output {
+    match run(&STAGES, name, true, f) {
+        Ok(output) => output,
+        Err(error) => panic!("boot stage {} failed: {}", name, error),
+    }
+}
+
+/// 打印到目前为止各阶段的用时表
+pub fn summary() {
+    let tsc_per_ms = time::tsc_per_ms();
+    let highlight = with_writer(|writer| writer.color_code()).inverted();
+    interrupts::without_interrupts(|| {
+        STAGES
+            .lock()
+            .write_summary(tsc_per_ms, highlight, |args, style| emit(args, style, true))
+    });
+}
+
+#[cfg(test)]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(test)]
+static FAKE_TSC: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(test)]
+fn fake_tsc() -> u64 {
+    FAKE_TSC.load(Ordering::SeqCst)
+}
+
+/// 假的时钟前进 cycles 个周期
+#[cfg(test)]
+fn spend(cycles: u64) {
+    FAKE_TSC.fetch_add(cycles, Ordering::SeqCst);
+}
+
+/// 屏幕上第 row 行去掉行尾空格的文字
+#[cfg(test)]
+fn screen_row(row: usize) -> alloc::string::String {
+    use crate::vga_buffer::BUFFER_WIDTH;
+
+    let writer = WRITER.lock();
+    let text: alloc::string::String = (0..BUFFER_WIDTH)
+        .map(|col| writer.read_char(row, col).0 as char)
+        .collect();
+    alloc::string::String::from(text.trim_end())
+}
+
+#[test_case]
+fn test_scripted_stages() {
+    let stages = Mutex::new(Stages::new(fake_tsc));
+    with_writer(|writer| writer.clear_screen());
+
+    assert_eq!(run(&stages, "quiet", false, || spend(2_000)), Ok(()));
+    let net = run(&stages, "net", false, || {
+        spend(500);
+        crate::println!("net: no card");
+        Err::<(), _>("link down")
+    });
+    assert_eq!(net, Err("link down"));
+    let value = run(&stages, "outer", true, || {
+        spend(1_000);
+        run(&stages, "inner", true, || {
+            spend(3_000);
+            Ok::<_, &str>(7)
+        })
+    });
+    assert_eq!(value, Ok(7));
+
+    // 没有输出的阶段原地改写，有输出的阶段在下面重新打印
+    let rows: alloc::vec::Vec<_> = (BUFFER_HEIGHT - 9..BUFFER_HEIGHT).map(screen_row).collect();
+    assert_eq!(
+        rows,
+        [
+            "[ OK ] quiet",
+            "[ .. ] net",
+            "net: no card",
+            "[FAIL] net",
+            "  net: link down (continuing)",
+            "[ .. ] outer",
+            "  [ OK ] inner",
+            "[ OK ] outer",
+            ""
+        ]
+    );
+    let fail = WRITER.lock().read_char(BUFFER_HEIGHT - 6, 1);
+    assert_eq!(fail, (b'F', FAIL_COLOR));
+
+    let stages = stages.lock();
+    let record = |name, depth, cycles, ok| StageRecord {
+        name,
+        depth,
+        cycles,
+        ok,
+    };
+    assert_eq!(
+        stages.records(),
+        [
+            record("quiet", 0, 2_000, true),
+            record("net", 0, 500, false),
+            record("outer", 0, 4_000, true),
+            record("inner", 1, 3_000, true),
+        ]
+    );
+    // 最慢的是 inner 而不是包含它的 outer
+    assert_eq!(stages.slowest(), Some(3));
+
+    with_writer(|writer| writer.clear_screen());
+    let highlight = with_writer(|writer| writer.color_code()).inverted();
+    stages.write_summary(1_000, highlight, |args, style| emit(args, style, true));
+    assert_eq!(screen_row(BUFFER_HEIGHT - 6), "boot stages:");
+    assert_eq!(
+        screen_row(BUFFER_HEIGHT - 4),
+        "  net                      0.500 ms FAIL"
+    );
+    assert_eq!(
+        screen_row(BUFFER_HEIGHT - 2),
+        "    inner                  3.000 ms"
+    );
+    let highlighted = WRITER.lock().read_char(BUFFER_HEIGHT - 2, 4).1;
+    assert_eq!(highlighted, highlight);
+}
+
+#[test_case]
+fn test_rewritten_status_reaches_other_sinks() {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use sink::Recording;
+
+    let stages = Mutex::new(Stages::new(fake_tsc));
+    with_writer(|writer| writer.clear_screen());
+    let id = sink::register(Box::leak(Box::new(Recording(Vec::new())))).unwrap();
+    assert_eq!(run(&stages, "quiet", false, || ()), Ok(()));
+    let recorded = sink::take_recording(id);
+    // 屏幕上原地改写，其他目标在下面收到一行结果
+    assert_eq!(screen_row(BUFFER_HEIGHT - 2), "[ OK ] quiet");
+    assert_eq!(screen_row(BUFFER_HEIGHT - 3), "");
+    assert_eq!(recorded, "[ .. ] quiet\n[ OK ] quiet\n");
+}
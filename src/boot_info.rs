@@ -0,0 +1,211 @@
+//! 启动时的诊断信息
+//! print_boot_info 用方框画出显示模式、CPU、内存、堆和时钟频率，每一项单独一行。
+//! 各项互不依赖：某个子系统还没有初始化或者不存在时，那一行显示 "n/a"，其他行照常显示
+use crate::vga_buffer::{char_display_width, PadRight, Writer};
+use crate::vga_mode::{self, VgaMode};
+use crate::{allocator, cpu, memory, time};
+use core::fmt::{self, Write};
+
+/// 方框的总宽度，包括两侧边框
+pub const BOX_WIDTH: usize = 60;
+/// 名称一列的宽度
+const LABEL_WIDTH: usize = 10;
+/// 值一列的宽度：减去两侧的边框和空格
+const VALUE_WIDTH: usize = BOX_WIDTH - 4 - LABEL_WIDTH;
+const TITLE: &str = "boot info";
+
+/// 要显示的各项，None 显示为 "n/a"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootInfo {
+    pub vga_mode: Option<VgaMode>,
+    pub cpu_vendor: Option<[u8; 12]>,
+    pub cpu_features: Option<cpu::Features>,
+    /// 可用物理内存的字节数
+    pub memory: Option<u64>,
+    /// 堆的字节数
+    pub heap: Option<usize>,
+    pub timer_hz: Option<u32>,
+}
+
+impl BootInfo {
+    /// 读取各子系统的当前状态
+    pub fn collect() -> Self {
+        BootInfo {
+            vga_mode: Some(vga_mode::current_mode()),
+            cpu_vendor: Some(cpu::cpu_vendor()),
+            cpu_features: Some(cpu::features()),
+            memory: memory::usable_memory(),
+            // 持有堆的锁时（例如在分配器中）不等待
+            heap: allocator::try_heap_stats()
+                .map(|stats| stats.total)
+                .filter(|&total| total > 0),
+            // 还没有时钟中断时 PIT 没有在运行
+            timer_hz: (time::ticks() > 0).then_some(time::TIMER_FREQUENCY_HZ),
+        }
+    }
+
+    /// 画出整个方框，光标停在方框下一行的行首
+    pub fn write_to(&self, writer: &mut Writer) {
+        let vendor = self
+            .cpu_vendor
+            .as_ref()
+            .and_then(|vendor| core::str::from_utf8(vendor).ok())
+            .map(|vendor| vendor.trim_end_matches('\0'))
+            .filter(|vendor| !vendor.is_empty());
+
+        let _ = write!(writer, "┌─ {} ", TITLE);
+        write_horizontal(writer, BOX_WIDTH - 5 - TITLE.len());
+        writer.write_string("┐\n");
+        write_row(writer, "vga mode", self.vga_mode.map(Mode));
+        write_row(writer, "cpu", vendor);
+        write_row(writer, "features", self.cpu_features);
+        write_row(writer, "memory", self.memory.map(Bytes));
+        write_row(writer, "heap", self.heap.map(|heap| Bytes(heap as u64)));
+        write_row(writer, "timer", self.timer_hz.map(Hz));
+        writer.write_string("└");
+        write_horizontal(writer, BOX_WIDTH - 2);
+        writer.write_string("┘\n");
+    }
+}
+
+/// 打印启动诊断信息
+pub fn print_boot_info(writer: &mut Writer) {
+    BootInfo::collect().write_to(writer);
+}
+
+fn write_horizontal(writer: &mut Writer, count: usize) {
+    for _ in 0..count {
+        writer.write_char('─');
+    }
+}
+
+/// 方框中的一行，值超出宽度的部分被截掉
+fn write_row(writer: &mut Writer, label: &str, value: Option<impl fmt::Display>) {
+    let _ = write!(writer, "│ {} ", PadRight(label, LABEL_WIDTH));
+    let mut clipped = Clipped {
+        writer,
+        left: VALUE_WIDTH,
+    };
+    let _ = match value {
+        Some(value) => write!(clipped, "{}", value),
+        None => clipped.write_str("n/a"),
+    };
+    let left = clipped.left;
+    let _ = writeln!(writer, "{:1$} │", "", left);
+}
+
+/// 最多写入 left 列，之后的字符丢弃
+struct Clipped<'a> {
+    writer: &'a mut Writer,
+    left: usize,
+}
+
+impl Write for Clipped<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let width = char_display_width(c);
+            if width > self.left {
+                self.left = 0;
+                break;
+            }
+            self.writer.write_char(c);
+            self.left -= width;
+        }
+        Ok(())
+    }
+}
+
+struct Mode(VgaMode);
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.text_size() {
+            Some((rows, cols)) => write!(f, "text {}x{}", cols, rows),
+            None if self.0 == VgaMode::Graphics => f.write_str("graphics"),
+            None => f.write_str("text, nonstandard font"),
+        }
+    }
+}
+
+/// 字节数，按 MiB 或 KiB 显示
+struct Bytes(u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const KIB: u64 = 1024;
+        const MIB: u64 = 1024 * KIB;
+        if self.0 >= MIB {
+            write!(f, "{} MiB", self.0 / MIB)
+        } else {
+            write!(f, "{} KiB", self.0 / KIB)
+        }
+    }
+}
+
+struct Hz(u32);
+
+impl fmt::Display for Hz {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} Hz", self.0)
+    }
+}
+
+#[cfg(test)]
+use crate::vga_buffer::{TestWriter, BUFFER_HEIGHT};
+
+/// 屏幕上第 row 行方框宽度内的文字，方框字符换回 Unicode
+#[cfg(test)]
+fn box_row(writer: &Writer, row: usize) -> alloc::string::String {
+    use crate::vga_buffer::cp437;
+
+    (0..BOX_WIDTH)
+        .map(|col| cp437::to_char(writer.read_char(row, col).0).unwrap_or('?'))
+        .collect()
+}
+
+#[test_case]
+fn test_missing_items_show_na() {
+    let mut writer = TestWriter::new();
+    let info = BootInfo {
+        vga_mode: Some(VgaMode::Text80x25),
+        cpu_vendor: Some(*b"GenuineIntel"),
+        cpu_features: None,
+        memory: Some(127 * 1024 * 1024 + 512 * 1024),
+        heap: None,
+        timer_hz: Some(1000),
+    };
+    info.write_to(&mut writer);
+
+    // 方框共 8 行，最后的换行之后光标在空的最后一行
+    let top = BUFFER_HEIGHT - 9;
+    let rows: alloc::vec::Vec<_> = (top..BUFFER_HEIGHT - 1)
+        .map(|row| box_row(&writer, row))
+        .collect();
+    assert!(rows[0].starts_with("┌─ boot info ─"));
+    assert!(rows[0].ends_with("─┐"));
+    let expected = [
+        ("vga mode", "text 80x25"),
+        ("cpu", "GenuineIntel"),
+        ("features", "n/a"),
+        ("memory", "127 MiB"),
+        ("heap", "n/a"),
+        ("timer", "1000 Hz"),
+    ];
+    for (row, (label, value)) in rows[1..7].iter().zip(expected) {
+        assert!(row.trim_start_matches("│ ").starts_with(label));
+        assert!(row.contains(value), "{:?} lacks {:?}", row, value);
+        assert!(row.starts_with("│ ") && row.ends_with(" │"), "{:?}", row);
+    }
+    assert!(rows[7].starts_with("└─") && rows[7].ends_with("─┘"));
+    assert_eq!(writer.column(), 0);
+}
+
+#[test_case]
+fn test_long_value_is_clipped() {
+    let mut writer = TestWriter::new();
+    let long = "x".repeat(BOX_WIDTH);
+    write_row(&mut writer, "cpu", Some(long.as_str()));
+    let row = box_row(&writer, BUFFER_HEIGHT - 2);
+    assert!(row.ends_with("x │"), "{:?}", row);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, BOX_WIDTH).0, b' ');
+}
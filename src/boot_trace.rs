@@ -0,0 +1,231 @@
+//! 启动阶段的面包屑
+//! IDT 加载之前出错时机器只会三重错误后重启，屏幕上什么也看不到。boot_trace! 在每个初始化阶段完成时：
+//! - 向 0xE9 端口（QEMU 的 debugcon，用 -debugcon stdio 查看）写一行 "boot: <阶段名>"
+//! - 把阶段编号写到低端内存中固定的 SCRATCH_ADDR，这个帧不交给帧分配器
+//!
+//! 重启时内存中的内容通常还在。init 在覆盖之前读出上一次启动留下的记录，如果它没有走到 Done，
+//! report 打印 "previous boot died after stage X"。冷启动时这里是随机内容，靠魔数和校验字节排除。
+//!
+//! boot_trace! 只写端口和内存，不加锁、不分配，可以在关中断时、Writer 和堆初始化之前使用；
+//! init 之前（例如 multiboot2 入口中）只写端口
+use crate::println;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// QEMU 的 debugcon 端口，没有这个设备时写入被忽略
+const DEBUGCON_PORT: u16 = 0xe9;
+
+/// 记录所在的物理地址：EBDA 之下、bootloader 和内核都不使用的一个帧的开头
+pub const SCRATCH_ADDR: u64 = 0x9_e000;
+
+/// 记录的高 32 位
+const MAGIC: u32 = 0xb007_7ace;
+
+/// 初始化的各个阶段，按发生的顺序排列
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Stage {
+    /// 进入 kernel_main
+    Entry = 1,
+    Console,
+    Config,
+    Gdt,
+    Idt,
+    Pic,
+    Timer,
+    Interrupts,
+    Paging,
+    Heap,
+    /// 初始化完成，即将进入执行器
+    Done,
+}
+
+impl Stage {
+    const ALL: [Stage; 11] = [
+        Stage::Entry,
+        Stage::Console,
+        Stage::Config,
+        Stage::Gdt,
+        Stage::Idt,
+        Stage::Pic,
+        Stage::Timer,
+        Stage::Interrupts,
+        Stage::Paging,
+        Stage::Heap,
+        Stage::Done,
+    ];
+
+    pub fn from_id(id: u8) -> Option<Stage> {
+        Stage::ALL.iter().copied().find(|&stage| stage as u8 == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Entry => "entry",
+            Stage::Console => "console",
+            Stage::Config => "config",
+            Stage::Gdt => "gdt",
+            Stage::Idt => "idt",
+            Stage::Pic => "pic",
+            Stage::Timer => "timer",
+            Stage::Interrupts => "interrupts",
+            Stage::Paging => "paging",
+            Stage::Heap => "heap",
+            Stage::Done => "done",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// 写在 SCRATCH_ADDR 的记录，编码为一个 u64：
+/// 魔数（32 位）、启动序号（16 位）、校验字节（阶段编号取反）、阶段编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    /// 每次启动加一，用来区分本次写下的记录和上一次留下的记录
+    pub generation: u16,
+    pub stage: Stage,
+}
+
+impl Record {
+    pub fn encode(self) -> u64 {
+        let id = self.stage as u8;
+        (MAGIC as u64) << 32 | (self.generation as u64) << 16 | (!id as u64) << 8 | id as u64
+    }
+
+    /// 魔数、校验字节或阶段编号不对时返回 None
+    pub fn decode(raw: u64) -> Option<Record> {
+        let id = raw as u8;
+        if (raw >> 32) as u32 != MAGIC || (raw >> 8) as u8 != !id {
+            return None;
+        }
+        Some(Record {
+            generation: (raw >> 16) as u16,
+            stage: Stage::from_id(id)?,
+        })
+    }
+}
+
+/// 本次启动序号为 generation 时，raw 说明的上一次启动停在了哪个阶段之后：
+/// 不是有效的记录、是本次启动写下的、或者上一次已经走到 Done 时返回 None
+pub fn died_after(raw: u64, generation: u16) -> Option<Stage> {
+    let record = Record::decode(raw)?;
+    if record.generation == generation || record.stage == Stage::Done {
+        return None;
+    }
+    Some(record.stage)
+}
+
+/// SCRATCH_ADDR 映射到的虚拟地址，0 表示还没有调用 init
+static SCRATCH: AtomicU64 = AtomicU64::new(0);
+static GENERATION: AtomicU16 = AtomicU16::new(0);
+/// init 覆盖之前读到的内容
+static PREVIOUS: AtomicU64 = AtomicU64::new(0);
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// 包含 SCRATCH_ADDR 的帧，帧分配器跳过它
+pub fn reserved_frame() -> PhysAddr {
+    PhysAddr::new(SCRATCH_ADDR).align_down(4096u64)
+}
+
+/// 读出上一次启动的记录并开始记录本次启动，应该是 kernel_main 做的第一件事。
+/// physical_memory_offset 是物理内存映射的起始地址，重复调用时什么也不做
+pub fn init(physical_memory_offset: VirtAddr) {
+    if INITIALIZED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let scratch = physical_memory_offset + SCRATCH_ADDR;
+    let previous = unsafe { scratch.as_ptr::<u64>().read_volatile() };
+    let generation = match Record::decode(previous) {
+        Some(record) => record.generation.wrapping_add(1),
+        None => 0,
+    };
+    PREVIOUS.store(previous, Ordering::SeqCst);
+    GENERATION.store(generation, Ordering::SeqCst);
+    SCRATCH.store(scratch.as_u64(), Ordering::SeqCst);
+    trace(Stage::Entry);
+}
+
+/// boot_trace! 的实现
+pub fn trace(stage: Stage) {
+    let mut port = Port::<u8>::new(DEBUGCON_PORT);
+    for &byte in b"boot: ".iter().chain(stage.name().as_bytes()).chain(b"\n") {
+        unsafe { port.write(byte) };
+    }
+    let scratch = SCRATCH.load(Ordering::SeqCst);
+    if scratch != 0 {
+        let record = Record {
+            generation: GENERATION.load(Ordering::SeqCst),
+            stage,
+        };
+        unsafe { (scratch as *mut u64).write_volatile(record.encode()) };
+    }
+}
+
+/// 上一次启动停在了哪个阶段之后，见 died_after
+pub fn previous_boot_died_after() -> Option<Stage> {
+    died_after(
+        PREVIOUS.load(Ordering::SeqCst),
+        GENERATION.load(Ordering::SeqCst),
+    )
+}
+
+/// 上一次启动没有完成初始化时打印一行提示，需要 println! 可用
+pub fn report() {
+    if let Some(stage) = previous_boot_died_after() {
+        println!("previous boot died after stage {}", stage);
+    }
+}
+
+/// 记录启动走到了哪个阶段，参数是 Stage 的变体名，例如 boot_trace!(Gdt)
+#[macro_export]
+macro_rules! boot_trace {
+    ($stage:ident) => {
+        $crate::boot_trace::trace($crate::boot_trace::Stage::$stage)
+    };
+}
+
+#[test_case]
+fn test_record_round_trip() {
+    for stage in Stage::ALL {
+        let record = Record {
+            generation: 0xbeef,
+            stage,
+        };
+        assert_eq!(Record::decode(record.encode()), Some(record));
+    }
+    let raw = Record {
+        generation: 1,
+        stage: Stage::Gdt,
+    }
+    .encode();
+    assert_eq!(raw, 0xb007_7ace_0001_fb04);
+    // 魔数、校验字节或编号不对
+    assert_eq!(Record::decode(raw ^ 1 << 40), None);
+    assert_eq!(Record::decode(raw ^ 1 << 8), None);
+    assert_eq!(Record::decode(0xb007_7ace_0001_f30c), None);
+    assert_eq!(Record::decode(0), None);
+}
+
+#[test_case]
+fn test_stale_and_fresh_records() {
+    let record = |generation, stage| Record { generation, stage }.encode();
+    // 上一次启动留下的、没有走完的记录
+    assert_eq!(died_after(record(6, Stage::Idt), 7), Some(Stage::Idt));
+    assert_eq!(
+        died_after(record(u16::MAX, Stage::Heap), 0),
+        Some(Stage::Heap)
+    );
+    // 本次启动自己写下的记录
+    assert_eq!(died_after(record(7, Stage::Idt), 7), None);
+    // 上一次正常完成
+    assert_eq!(died_after(record(6, Stage::Done), 7), None);
+    // 冷启动时的随机内容
+    assert_eq!(died_after(0x1234_5678_9abc_def0, 7), None);
+}
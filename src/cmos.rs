@@ -0,0 +1,229 @@
+//! CMOS NVRAM
+//! 主板上由电池供电的 128 字节存储，0x70 是索引端口，0x71 是数据端口：先写索引选中寄存器，再读写数据。
+//! 索引端口的 bit 7 同时控制 NMI，置位时屏蔽 NMI。索引端口是只写的，
+//! 所以当前的 NMI 状态记录在 NMI_DISABLED 中，每次写索引都带上它。
+//! 访问期间屏蔽 NMI，结束后重新选中状态寄存器 D 并恢复原来的 NMI 状态，
+//! 同时关闭中断，避免中断处理函数在索引和数据之间插入另一次访问
+//!
+//! 0x00-0x0d 是 RTC 的时间、闹钟和状态寄存器，不能通过 write 修改。
+//! 末尾的几个字节没有被 BIOS 使用，作为带校验和的用户区保存跨重启的少量状态
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const INDEX: u16 = 0x70;
+const DATA: u16 = 0x71;
+
+/// 寄存器总数，索引只有 7 位
+pub const REGISTER_COUNT: u8 = 0x80;
+/// RTC 的时间、闹钟和状态寄存器 A-D
+pub const RTC_REGISTERS: core::ops::RangeInclusive<u8> = 0x00..=0x0d;
+/// 软盘驱动器类型：高 4 位是第一个驱动器，低 4 位是第二个
+pub const FLOPPY_TYPES: u8 = 0x10;
+/// 设备信息字节
+pub const EQUIPMENT: u8 = 0x14;
+/// 大多数 BIOS 存放世纪的位置，ACPI FADT 中可以给出实际位置
+pub const CENTURY: u8 = 0x32;
+
+/// 访问结束后选中的寄存器
+const STATUS_D: u8 = 0x0d;
+const NMI_DISABLE: u8 = 0x80;
+
+/// 用户区的数据字节数
+pub const USER_FLAGS_LEN: usize = 3;
+/// 用户区的起始寄存器，数据之后紧跟一个校验和字节，一直用到最后一个寄存器
+const USER_AREA: u8 = REGISTER_COUNT - USER_FLAGS_LEN as u8 - 1;
+/// 校验和的初值，全 0 的用户区不是有效的
+const CHECKSUM_SEED: u8 = 0xa5;
+
+static NMI_DISABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmosError {
+    /// 寄存器号超出 0-127
+    OutOfRange(u8),
+    /// RTC 的时间和状态寄存器不能通过 write 修改
+    RtcRegister(u8),
+}
+
+/// 访问索引和数据端口，抽象出来以便测试
+pub trait CmosPorts {
+    fn read(&mut self, port: u16) -> u8;
+    fn write(&mut self, port: u16, value: u8);
+}
+
+struct HardwarePorts;
+
+impl CmosPorts for HardwarePorts {
+    fn read(&mut self, port: u16) -> u8 {
+        unsafe { Port::new(port).read() }
+    }
+
+    fn write(&mut self, port: u16, value: u8) {
+        unsafe { Port::new(port).write(value) }
+    }
+}
+
+fn check_range(reg: u8) -> Result<(), CmosError> {
+    if reg >= REGISTER_COUNT {
+        return Err(CmosError::OutOfRange(reg));
+    }
+    Ok(())
+}
+
+fn nmi_bit() -> u8 {
+    if NMI_DISABLED.load(Ordering::Relaxed) {
+        NMI_DISABLE
+    } else {
+        0
+    }
+}
+
+/// 屏蔽 NMI 选中 reg，执行 f，再恢复 NMI 状态
+fn access<T>(ports: &mut impl CmosPorts, reg: u8, f: impl FnOnce(&mut dyn CmosPorts) -> T) -> T {
+    interrupts::without_interrupts(|| {
+        ports.write(INDEX, reg | NMI_DISABLE);
+        let result = f(ports);
+        ports.write(INDEX, STATUS_D | nmi_bit());
+        result
+    })
+}
+
+fn read_with(ports: &mut impl CmosPorts, reg: u8) -> Result<u8, CmosError> {
+    check_range(reg)?;
+    Ok(access(ports, reg, |ports| ports.read(DATA)))
+}
+
+fn write_with(ports: &mut impl CmosPorts, reg: u8, value: u8) -> Result<(), CmosError> {
+    check_range(reg)?;
+    if RTC_REGISTERS.contains(&reg) {
+        return Err(CmosError::RtcRegister(reg));
+    }
+    access(ports, reg, |ports| ports.write(DATA, value));
+    Ok(())
+}
+
+/// 读取寄存器
+pub fn read(reg: u8) -> Result<u8, CmosError> {
+    read_with(&mut HardwarePorts, reg)
+}
+
+/// 写入寄存器，拒绝 RTC 的时间和状态寄存器
+pub fn write(reg: u8, value: u8) -> Result<(), CmosError> {
+    write_with(&mut HardwarePorts, reg, value)
+}
+
+/// 设置 NMI 是否被屏蔽，之后的每次访问结束时都会恢复到这个状态
+pub fn set_nmi_enabled(enabled: bool) {
+    NMI_DISABLED.store(!enabled, Ordering::Relaxed);
+    interrupts::without_interrupts(|| HardwarePorts.write(INDEX, STATUS_D | nmi_bit()));
+}
+
+fn checksum(flags: &[u8; USER_FLAGS_LEN]) -> u8 {
+    flags
+        .iter()
+        .fold(CHECKSUM_SEED, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn user_flags_with(ports: &mut impl CmosPorts) -> Option<[u8; USER_FLAGS_LEN]> {
+    let mut flags = [0; USER_FLAGS_LEN];
+    for (reg, flag) in (USER_AREA..).zip(&mut flags) {
+        *flag = read_with(ports, reg).ok()?;
+    }
+    let stored = read_with(ports, USER_AREA + USER_FLAGS_LEN as u8).ok()?;
+    (stored == checksum(&flags)).then_some(flags)
+}
+
+fn set_user_flags_with(ports: &mut impl CmosPorts, flags: [u8; USER_FLAGS_LEN]) {
+    for (reg, &flag) in (USER_AREA..).zip(&flags) {
+        write_with(ports, reg, flag).unwrap();
+    }
+    write_with(ports, USER_AREA + USER_FLAGS_LEN as u8, checksum(&flags)).unwrap();
+}
+
+/// 读取用户区，从未写入过或校验和不对（例如电池没电）时返回 None
+pub fn user_flags() -> Option<[u8; USER_FLAGS_LEN]> {
+    user_flags_with(&mut HardwarePorts)
+}
+
+/// 写入用户区并更新校验和
+pub fn set_user_flags(flags: [u8; USER_FLAGS_LEN]) {
+    set_user_flags_with(&mut HardwarePorts, flags);
+}
+
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 模拟的 CMOS：记录写入索引端口的值，按最后选中的寄存器读写
+#[cfg(test)]
+struct MockCmos {
+    registers: [u8; REGISTER_COUNT as usize],
+    indexes: Vec<u8>,
+}
+
+#[cfg(test)]
+impl MockCmos {
+    fn new() -> Self {
+        MockCmos {
+            registers: [0; REGISTER_COUNT as usize],
+            indexes: Vec::new(),
+        }
+    }
+
+    fn selected(&self) -> usize {
+        (self.indexes.last().unwrap() & !NMI_DISABLE) as usize
+    }
+}
+
+#[cfg(test)]
+impl CmosPorts for MockCmos {
+    fn read(&mut self, port: u16) -> u8 {
+        assert_eq!(port, DATA);
+        self.registers[self.selected()]
+    }
+
+    fn write(&mut self, port: u16, value: u8) {
+        match port {
+            INDEX => self.indexes.push(value),
+            DATA => self.registers[self.selected()] = value,
+            _ => panic!("unexpected port {:#x}", port),
+        }
+    }
+}
+
+#[test_case]
+fn test_register_guards() {
+    let mut cmos = MockCmos::new();
+    for reg in [0x00, 0x04, 0x0a, 0x0d] {
+        assert_eq!(
+            write_with(&mut cmos, reg, 1),
+            Err(CmosError::RtcRegister(reg))
+        );
+    }
+    assert_eq!(
+        write_with(&mut cmos, 0x80, 1),
+        Err(CmosError::OutOfRange(0x80))
+    );
+    assert_eq!(read_with(&mut cmos, 0xff), Err(CmosError::OutOfRange(0xff)));
+    // 被拒绝的访问不碰端口
+    assert!(cmos.indexes.is_empty());
+
+    assert_eq!(write_with(&mut cmos, 0x0e, 0x42), Ok(()));
+    assert_eq!(read_with(&mut cmos, 0x0e), Ok(0x42));
+    // 访问时屏蔽 NMI，结束后选中状态寄存器 D 并恢复 NMI
+    assert_eq!(cmos.indexes, [0x8e, STATUS_D, 0x8e, STATUS_D]);
+}
+
+#[test_case]
+fn test_user_flags_checksum() {
+    let mut cmos = MockCmos::new();
+    // 全 0 的 CMOS 没有有效的用户区
+    assert_eq!(user_flags_with(&mut cmos), None);
+
+    set_user_flags_with(&mut cmos, [1, 0x80, 0xff]);
+    assert_eq!(user_flags_with(&mut cmos), Some([1, 0x80, 0xff]));
+    assert_eq!(cmos.registers[REGISTER_COUNT as usize - 1], 0x25);
+
+    cmos.registers[USER_AREA as usize + 1] ^= 0x10;
+    assert_eq!(user_flags_with(&mut cmos), None);
+}
@@ -0,0 +1,387 @@
+//! 启动配置
+//! 从引导程序传入的命令行解析出启动选项，不需要重新编译就能调整行为。
+//! 命令行由空白分隔的 key=value 和单独的开关组成，例如：
+//!
+//! ```text
+//! loglevel=3 console=both theme=light panic=reboot:5 watchdog=reset quiet selftest nobeep gfxdemo
+//! statusbar=250 screen=clear novgafix=blink,start
+//! ```
+//!
+//! - 同一个键出现多次时以最后一次为准
+//! - 不认识的键和格式错误的值只产生警告，值无效时保留之前的值（默认值或前面出现过的值）
+//!
+//! 控制台输出需要在很早的时候就知道配置，所以解析不使用堆，结果存放在静态变量中
+use crate::console::{self, ConsoleState};
+use crate::panic::{self, PanicAction};
+use crate::println;
+use crate::statusbar;
+use crate::vga_buffer::{self, Color, Fixes, InheritedScreen, WRITER};
+use crate::watchdog::{self, WatchdogAction};
+use conquer_once::spin::OnceCell;
+
+/// loglevel 的最大值，与 Linux 相同，0 最紧急、7 是调试信息
+pub const MAX_LOGLEVEL: u8 = 7;
+
+/// init 最多暂存并打印的警告数量，其余的只计数
+const MAX_WARNINGS: usize = 8;
+
+/// 输出发往哪里
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Vga,
+    Serial,
+    Both,
+}
+
+impl Console {
+    pub fn vga(self) -> bool {
+        matches!(self, Console::Vga | Console::Both)
+    }
+
+    pub fn serial(self) -> bool {
+        matches!(self, Console::Serial | Console::Both)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Console::Vga => "vga",
+            Console::Serial => "serial",
+            Console::Both => "both",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Console> {
+        match name {
+            "vga" => Some(Console::Vga),
+            "serial" => Some(Console::Serial),
+            "both" => Some(Console::Both),
+            _ => None,
+        }
+    }
+}
+
+/// 控制台的配色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    /// 黑底黄字，与 Writer 的默认颜色相同
+    Default,
+    /// 浅灰底黑字
+    Light,
+    /// 黑底绿字
+    Green,
+}
+
+impl Theme {
+    /// (前景色, 背景色)
+    pub fn colors(self) -> (Color, Color) {
+        match self {
+            Theme::Default => (Color::Yellow, Color::Black),
+            Theme::Light => (Color::Black, Color::LightGray),
+            Theme::Green => (Color::LightGreen, Color::Black),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Default => "default",
+            Theme::Light => "light",
+            Theme::Green => "green",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::Default),
+            "light" => Some(Theme::Light),
+            "green" => Some(Theme::Green),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootConfig {
+    pub loglevel: u8,
+    pub console: Console,
+    pub theme: Theme,
+    /// panic 之后的动作，见 panic::PanicAction::parse
+    pub panic: PanicAction,
+    /// 主循环卡住时的动作，见 watchdog::WatchdogAction::parse
+    pub watchdog: WatchdogAction,
+    /// 不打印启动横幅
+    pub quiet: bool,
+    /// 启动时运行 selftest，与打开 selftest feature 的效果相同
+    pub selftest: bool,
+    /// 关闭 PC 扬声器，见 speaker::set_silenced
+    pub nobeep: bool,
+    /// 启动时运行 graphics::demo
+    pub gfxdemo: bool,
+    /// 状态栏的刷新间隔（毫秒），None 表示不显示状态栏；单独的开关使用默认间隔
+    pub statusbar: Option<u32>,
+    /// 引导程序留在屏幕上的内容，见 vga_buffer::sanitize_hardware_state
+    pub screen: InheritedScreen,
+    /// 接管显卡时执行的修正，novgafix 列出的项被跳过
+    pub vgafix: Fixes,
+}
+
+impl BootConfig {
+    pub const DEFAULT: BootConfig = BootConfig {
+        loglevel: 4,
+        console: Console::Vga,
+        theme: Theme::Default,
+        panic: PanicAction::DEFAULT,
+        watchdog: WatchdogAction::DEFAULT,
+        quiet: false,
+        selftest: false,
+        nobeep: false,
+        gfxdemo: false,
+        statusbar: None,
+        screen: InheritedScreen::Preserve,
+        vgafix: Fixes::ALL,
+    };
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// 命令行中以有效的值给出的、也能由 settings 保存的选项，这些选项以命令行为准
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Given {
+    pub loglevel: bool,
+    pub console: bool,
+    pub theme: bool,
+}
+
+/// 解析时发现的问题，都不是致命的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning<'a> {
+    UnknownKey(&'a str),
+    /// 值无法解析，或者需要值的键没有给出值、开关带了值，值为空表示没有给出
+    BadValue {
+        key: &'a str,
+        value: &'a str,
+    },
+}
+
+/// 解析命令行，每发现一个问题调用一次 warn
+pub fn parse<'a>(command_line: &'a str, warn: impl FnMut(Warning<'a>)) -> BootConfig {
+    parse_given(command_line, warn).0
+}
+
+/// 与 parse 相同，同时返回哪些选项由命令行给出
+pub fn parse_given<'a>(
+    command_line: &'a str,
+    mut warn: impl FnMut(Warning<'a>),
+) -> (BootConfig, Given) {
+    let mut config = BootConfig::DEFAULT;
+    let mut given = Given::default();
+    for word in command_line.split_ascii_whitespace() {
+        let (key, value) = match word.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (word, None),
+        };
+        let bad_value = Warning::BadValue {
+            key,
+            value: value.unwrap_or(""),
+        };
+        match (key, value) {
+            ("loglevel", Some(value)) => match value.parse() {
+                Ok(level) if level <= MAX_LOGLEVEL => {
+                    config.loglevel = level;
+                    given.loglevel = true;
+                }
+                _ => warn(bad_value),
+            },
+            ("console", Some(value)) => match Console::from_name(value) {
+                Some(console) => (config.console, given.console) = (console, true),
+                None => warn(bad_value),
+            },
+            ("theme", Some(value)) => match Theme::from_name(value) {
+                Some(theme) => (config.theme, given.theme) = (theme, true),
+                None => warn(bad_value),
+            },
+            ("panic", Some(value)) => match PanicAction::parse(value) {
+                Some(action) => config.panic = action,
+                None => warn(bad_value),
+            },
+            ("watchdog", Some(value)) => match WatchdogAction::parse(value) {
+                Some(action) => config.watchdog = action,
+                None => warn(bad_value),
+            },
+            ("quiet", None) => config.quiet = true,
+            ("selftest", None) => config.selftest = true,
+            ("nobeep", None) => config.nobeep = true,
+            ("gfxdemo", None) => config.gfxdemo = true,
+            ("statusbar", None) => config.statusbar = Some(statusbar::DEFAULT_INTERVAL_MS),
+            ("statusbar", Some(value)) => match value.parse() {
+                Ok(ms) if ms > 0 => config.statusbar = Some(ms),
+                _ => warn(bad_value),
+            },
+            ("screen", Some(value)) => match InheritedScreen::parse(value) {
+                Some(screen) => config.screen = screen,
+                None => warn(bad_value),
+            },
+            ("novgafix", Some(value)) => match Fixes::parse_list(value) {
+                Some(skip) => config.vgafix = Fixes::ALL.without(skip),
+                None => warn(bad_value),
+            },
+            (
+                "loglevel" | "console" | "theme" | "panic" | "watchdog" | "quiet" | "selftest"
+                | "nobeep" | "gfxdemo" | "screen" | "novgafix",
+                _,
+            ) => warn(bad_value),
+            _ => warn(Warning::UnknownKey(key)),
+        }
+    }
+    (config, given)
+}
+
+static CONFIG: OnceCell<BootConfig> = OnceCell::uninit();
+static GIVEN: OnceCell<Given> = OnceCell::uninit();
+
+/// 解析命令行并保存结果，应用配色，再把警告打印出来（此时输出已经按配置的控制台发送）
+/// 只有第一次调用有效，应当在任何输出之前调用
+pub fn init(command_line: &str) {
+    use x86_64::instructions::interrupts;
+
+    let mut warnings = [None; MAX_WARNINGS];
+    let mut count = 0;
+    let (config, given) = parse_given(command_line, |warning| {
+        if let Some(slot) = warnings.get_mut(count) {
+            *slot = Some(warning);
+        }
+        count += 1;
+    });
+    if CONFIG.try_init_once(|| config).is_err() {
+        return;
+    }
+    let _ = GIVEN.try_init_once(|| given);
+    // 在第一次使用 WRITER 之前，接管引导程序留下的画面时要用到这里的设置
+    vga_buffer::sanitize_hardware_state(config.screen, config.vgafix);
+    // 输出发往哪里已经确定，之后的输出经过 console::sink 分发
+    console::advance(ConsoleState::Full);
+
+    panic::set_action(config.panic);
+    watchdog::set_action(config.watchdog);
+    let (foreground, background) = config.theme.colors();
+    interrupts::without_interrupts(|| WRITER.lock().set_color(foreground, background));
+    for warning in warnings.iter().flatten() {
+        match warning {
+            Warning::UnknownKey(key) => println!("config: unknown option {:?}", key),
+            Warning::BadValue { key, value } => {
+                println!("config: bad value {:?} for {}, ignored", value, key)
+            }
+        }
+    }
+    if count > MAX_WARNINGS {
+        println!("config: {} more warnings", count - MAX_WARNINGS);
+    }
+}
+
+/// 当前的启动配置，init 之前返回默认配置
+pub fn get() -> &'static BootConfig {
+    CONFIG.get().unwrap_or(&BootConfig::DEFAULT)
+}
+
+/// 命令行给出了哪些选项，init 之前都没有给出
+pub fn given() -> Given {
+    GIVEN.get().copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+use alloc::vec::Vec;
+
+#[cfg(test)]
+fn parse_collect(command_line: &str) -> (BootConfig, Vec<Warning<'_>>) {
+    let mut warnings = Vec::new();
+    let config = parse(command_line, |warning| warnings.push(warning));
+    (config, warnings)
+}
+
+#[test_case]
+fn test_parse_all_options() {
+    assert_eq!(parse_collect(""), (BootConfig::DEFAULT, Vec::new()));
+    assert_eq!(parse_collect(" \t\n "), (BootConfig::DEFAULT, Vec::new()));
+
+    let (config, warnings) = parse_collect(
+        "loglevel=7 console=both  theme=light\tpanic=exit watchdog=off quiet selftest nobeep gfxdemo statusbar=250 screen=clear novgafix=attr,cursor",
+    );
+    assert!(warnings.is_empty());
+    assert_eq!(
+        config,
+        BootConfig {
+            loglevel: 7,
+            console: Console::Both,
+            theme: Theme::Light,
+            panic: PanicAction::ExitQemuFailure,
+            watchdog: WatchdogAction::Off,
+            quiet: true,
+            selftest: true,
+            nobeep: true,
+            gfxdemo: true,
+            statusbar: Some(250),
+            screen: InheritedScreen::Clear,
+            vgafix: Fixes::BLINK.union(Fixes::START_ADDRESS),
+        }
+    );
+}
+
+#[test_case]
+fn test_parse_last_duplicate_wins() {
+    let (config, warnings) = parse_collect("console=serial loglevel=1 console=vga loglevel=2");
+    assert!(warnings.is_empty());
+    assert_eq!((config.console, config.loglevel), (Console::Vga, 2));
+
+    // 无效的值不覆盖前面有效的值
+    let (config, warnings) = parse_collect("theme=green theme=purple");
+    assert_eq!(config.theme, Theme::Green);
+    assert_eq!(
+        warnings,
+        [Warning::BadValue {
+            key: "theme",
+            value: "purple"
+        }]
+    );
+}
+
+#[test_case]
+fn test_parse_malformed_and_junk() {
+    let (config, warnings) = parse_collect(
+        "loglevel=8 loglevel=-1 loglevel console= panic=reboot:x quiet=1 root=/dev/sda \x01\x7f é=ü =",
+    );
+    assert_eq!(config, BootConfig::DEFAULT);
+    let bad = |key, value| Warning::BadValue { key, value };
+    assert_eq!(
+        warnings,
+        [
+            bad("loglevel", "8"),
+            bad("loglevel", "-1"),
+            bad("loglevel", ""),
+            bad("console", ""),
+            bad("panic", "reboot:x"),
+            bad("quiet", "1"),
+            Warning::UnknownKey("root"),
+            Warning::UnknownKey("\x01\x7f"),
+            Warning::UnknownKey("é"),
+            Warning::UnknownKey(""),
+        ]
+    );
+}
+
+#[test_case]
+fn test_parse_given_only_valid_values() {
+    let (_, given) = parse_given("theme=purple loglevel=2 console= quiet", |_| {});
+    assert_eq!(
+        given,
+        Given {
+            loglevel: true,
+            console: false,
+            theme: false,
+        }
+    );
+    let (_, given) = parse_given("console=serial theme=green", |_| {});
+    assert!(given.console && given.theme && !given.loglevel);
+}
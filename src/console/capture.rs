@@ -0,0 +1,297 @@
+//! 捕获输出的目标，测试中用来检查 print! 的内容而不读取屏幕
+//! - CaptureSink：写入的字节存放在固定大小的环形缓冲区中，写满后覆盖最早的内容，只保留最近的 N 个字节
+//! - LineCapture：按换行符切分成行，只保留最近的 MAX_LINES 行，用 lines、contains 检查。
+//!   一条 print! 在分发期间一直持有登记表的锁（见 sink::dispatch），
+//!   其他地方（例如中断处理函数）的输出只会出现在两条消息之间，每条以换行结尾的消息总是完整的一行
+use super::sink::{self, OutputSink, SinkError, SinkId};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub const DEFAULT_CAPACITY: usize = 1024;
+/// LineCapture 保留的行数，更早的行被丢弃
+pub const MAX_LINES: usize = 64;
+/// 每行保留的字节数，超出的部分被丢弃，行尾加上 TRUNCATED
+pub const LINE_CAPACITY: usize = 160;
+pub const TRUNCATED: &str = "[...]";
+
+pub struct CaptureSink<const N: usize = DEFAULT_CAPACITY> {
+    buf: [u8; N],
+    /// 最早的字节所在的位置
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> CaptureSink<N> {
+    pub const fn new() -> Self {
+        CaptureSink {
+            buf: [0; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// 按写入的顺序返回保留的字节
+    pub fn bytes(&self) -> Vec<u8> {
+        let (tail, head) = self.buf.split_at(self.start);
+        head.iter().chain(tail).take(self.len).copied().collect()
+    }
+
+    /// 按写入的顺序返回保留的字节，分成两段，不分配内存
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let (tail, head) = self.buf.split_at(self.start);
+        let head_len = head.len().min(self.len);
+        (&head[..head_len], &tail[..self.len - head_len])
+    }
+
+    /// 保留的内容，不是合法 UTF-8 的部分（例如被覆盖截断的字符）替换为 U+FFFD
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.bytes()).into_owned()
+    }
+
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for CaptureSink<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> OutputSink for CaptureSink<N> {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if N == 0 {
+                return;
+            }
+            if self.len < N {
+                self.buf[(self.start + self.len) % N] = byte;
+                self.len += 1;
+            } else {
+                self.buf[self.start] = byte;
+                self.start = (self.start + 1) % N;
+            }
+        }
+    }
+}
+
+/// 登记一个 CaptureSink，执行 f，然后取消登记并返回捕获到的内容
+/// 期间所有 print! 的输出照常写到其他目标
+pub fn capture<const N: usize>(f: impl FnOnce()) -> Result<Box<CaptureSink<N>>, SinkError> {
+    let sink = Box::into_raw(Box::new(CaptureSink::<N>::new()));
+    let id = match sink::register(unsafe { &mut *sink }) {
+        Ok(id) => id,
+        Err(error) => {
+            drop(unsafe { Box::from_raw(sink) });
+            return Err(error);
+        }
+    };
+    f();
+    sink::unregister(id);
+    // 取消登记后不再有其他引用
+    Ok(unsafe { Box::from_raw(sink) })
+}
+
+struct Lines {
+    lines: VecDeque<String>,
+    /// 还没有遇到换行符的部分
+    partial: Vec<u8>,
+    truncated: bool,
+}
+
+impl Lines {
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'\n' {
+                self.end_line();
+            } else if self.partial.len() < LINE_CAPACITY {
+                self.partial.push(byte);
+            } else {
+                self.truncated = true;
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        let mut line = String::from_utf8_lossy(&self.partial).into_owned();
+        if core::mem::take(&mut self.truncated) {
+            line.push_str(TRUNCATED);
+        }
+        self.partial.clear();
+        if self.lines.len() == MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+}
+
+static LINES: Mutex<Lines> = Mutex::new(Lines {
+    lines: VecDeque::new(),
+    partial: Vec::new(),
+    truncated: false,
+});
+/// 同一时间只能有一个 LineCapture，否则每行会被记录两次
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+fn with_lines<R>(f: impl FnOnce(&mut Lines) -> R) -> R {
+    // 写入发生在关闭中断的分发过程中，这里也要关闭中断，否则会在同一把锁上死锁
+    interrupts::without_interrupts(|| f(&mut LINES.lock()))
+}
+
+/// 把输出写进 LINES，零大小，登记时泄漏的 Box 不占用内存
+struct LineSink;
+
+impl OutputSink for LineSink {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        LINES.lock().push(bytes);
+    }
+}
+
+/// 按行捕获输出的守卫：创建时清空之前捕获的内容并登记，drop 时取消登记，
+/// 捕获到的行在 drop 之后仍然可以读取，直到下一次 start 或 clear
+pub struct LineCapture {
+    id: SinkId,
+}
+
+impl LineCapture {
+    /// 已经有一个 LineCapture 时 panic
+    pub fn start() -> Result<LineCapture, SinkError> {
+        assert!(
+            !CAPTURING.swap(true, Ordering::Relaxed),
+            "nested LineCapture"
+        );
+        clear();
+        match sink::register(Box::leak(Box::new(LineSink))) {
+            Ok(id) => Ok(LineCapture { id }),
+            Err(error) => {
+                CAPTURING.store(false, Ordering::Relaxed);
+                Err(error)
+            }
+        }
+    }
+}
+
+impl Drop for LineCapture {
+    fn drop(&mut self) {
+        sink::unregister(self.id);
+        CAPTURING.store(false, Ordering::Relaxed);
+    }
+}
+
+/// 捕获到的完整的行，不含换行符，最早的在前
+pub fn lines() -> Vec<String> {
+    with_lines(|lines| lines.lines.iter().cloned().collect())
+}
+
+/// 把还没有换行的部分也作为一行，例如最后一条输出是 print! 时
+pub fn flush() {
+    with_lines(|lines| {
+        if !lines.partial.is_empty() || lines.truncated {
+            lines.end_line();
+        }
+    });
+}
+
+/// 完整的行中是否有一行包含 needle，不跨行查找
+pub fn contains(needle: &str) -> bool {
+    with_lines(|lines| lines.lines.iter().any(|line| line.contains(needle)))
+}
+
+pub fn clear() {
+    with_lines(|lines| {
+        lines.lines.clear();
+        lines.partial.clear();
+        lines.truncated = false;
+    });
+}
+
+#[test_case]
+fn test_capture_println() {
+    let captured = capture::<DEFAULT_CAPACITY>(|| crate::println!("value = {}", 42)).unwrap();
+    assert!(captured.contents().contains("value = 42"));
+
+    let guard = LineCapture::start().unwrap();
+    crate::println!("value = {}", 42);
+    drop(guard);
+    assert_eq!(lines(), ["value = 42"]);
+    // 取消登记后不再捕获
+    crate::println!("after");
+    assert!(!contains("after"));
+}
+
+#[test_case]
+fn test_line_capture_splits_and_truncates() {
+    let _guard = LineCapture::start().unwrap();
+    crate::print!("one\ntw");
+    crate::print!("o\nthree");
+    assert_eq!(lines(), ["one", "two"]);
+    flush();
+    assert_eq!(lines(), ["one", "two", "three"]);
+
+    clear();
+    let long: String = (0..LINE_CAPACITY + 10).map(|_| 'x').collect();
+    crate::println!("{}", long);
+    crate::println!("short");
+    let expected = alloc::format!("{}{}", &long[..LINE_CAPACITY], TRUNCATED);
+    assert_eq!(lines(), [expected.as_str(), "short"]);
+
+    // 只保留最近的 MAX_LINES 行
+    clear();
+    for i in 0..MAX_LINES + 3 {
+        crate::println!("line {}", i);
+    }
+    let kept = lines();
+    assert_eq!(kept.len(), MAX_LINES);
+    assert_eq!(kept[0], "line 3");
+    assert!(contains(&alloc::format!("line {}", MAX_LINES + 2)));
+    assert!(!kept.iter().any(|line| line == "line 2"));
+}
+
+#[test_case]
+fn test_queued_messages_are_not_torn() {
+    // 模拟延迟打印的队列：两个来源的消息交错排队，之后依次打印，
+    // 每条消息由几段格式化的输出组成，仍然各自是完整的一行
+    let mut queue = VecDeque::new();
+    for i in 0..4 {
+        queue.push_back(("timer", i));
+        queue.push_back(("keyboard", i * 10));
+    }
+    let _guard = LineCapture::start().unwrap();
+    while let Some((source, value)) = queue.pop_front() {
+        crate::println!("[{}] value {} done", source, value);
+    }
+    let captured = lines();
+    assert_eq!(captured.len(), 8);
+    assert_eq!(captured[0], "[timer] value 0 done");
+    assert_eq!(captured[1], "[keyboard] value 0 done");
+    assert_eq!(captured[7], "[keyboard] value 30 done");
+    assert!(captured
+        .iter()
+        .all(|line| line.starts_with('[') && line.ends_with(" done")));
+}
+
+#[test_case]
+fn test_ring_keeps_latest_bytes() {
+    let mut sink = CaptureSink::<4>::new();
+    sink.write_bytes(b"ab");
+    assert_eq!(sink.contents(), "ab");
+    sink.write_bytes(b"cdef");
+    assert_eq!(sink.contents(), "cdef");
+    sink.write_bytes(b"g");
+    assert_eq!(sink.bytes(), b"defg");
+    assert_eq!(sink.as_slices(), (&b"d"[..], &b"efg"[..]));
+    sink.clear();
+    assert_eq!(sink.as_slices(), (&b""[..], &b""[..]));
+    assert_eq!(sink.contents(), "");
+
+    let mut empty = CaptureSink::<0>::new();
+    empty.write_bytes(b"x");
+    assert_eq!(empty.contents(), "");
+}
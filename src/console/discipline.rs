@@ -0,0 +1,633 @@
+//! 字节级的行规程，键盘和串口的输入经过同一套规则
+//! 键盘按键先换成终端会发送的字节（Ctrl+字母是对应的控制字符，见 key_byte），
+//! 之后两条路径完全相同：
+//! - 规范模式：可打印字节追加到行缓冲区，0x08/0x7f 删除最后一个字节，Ctrl+U 删除整行，
+//!   Ctrl+C 丢弃整行，回车（'\r' 或 '\n'）把这一行交给 LineConsumer::line
+//! - 原始模式：每个字节原样交给 LineConsumer::raw，不做任何编辑
+//!
+//! 回显可以单独关闭（输入密码）。关闭时除了结束一行的换行和取消时的 "^C" 什么也不回显。
+//!
+//! 中途切换模式时：从规范模式切换到原始模式，没有提交的半行按顺序作为原始字节交给消费者，
+//! 已经输入的内容不会丢失；从原始模式切换到规范模式时没有待处理的内容。
+//! 只切换回显时半行保留，之前回显的字符留在屏幕上，之后的编辑按新的设置回显。
+//!
+//! 行缓冲区通过 LineBuffer 抽象：SliceBuffer 借用一个固定数组，在堆初始化之前也能用；
+//! Vec<u8> 没有长度限制。
+//!
+//! 每个字节分两步处理：edit 修改缓冲区并回显，返回的 Event 再交给 deliver 调用消费者。
+//! 回显时要持有 WRITER 和 SERIAL1 的锁，消费者却可能打印，所以 pump 在释放这些锁之后才调用 deliver。
+//! shell 和 read_line 使用的 LineEditor 也建立在 LineDiscipline 上，它自己取走提交的行，
+//! 不需要消费者；键盘和 COM1 的输入都交给它，两者的编辑和回显完全相同
+use crate::keyboard::{DecodedKey, KeyInput};
+use crate::serial::SERIAL1;
+use crate::vga_buffer::{Writer, WRITER};
+use alloc::vec::Vec;
+use uart_16550::SerialPort;
+use x86_64::instructions::interrupts;
+
+use super::PolledKeyboard;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+pub(crate) const CTRL_C: u8 = 0x03;
+pub(crate) const CTRL_U: u8 = 0x15;
+
+/// 回显目标
+pub trait Echo {
+    fn echo(&mut self, byte: u8);
+    /// 擦掉最后回显的一个字符
+    fn erase(&mut self);
+}
+
+impl Echo for Writer {
+    fn echo(&mut self, byte: u8) {
+        self.write_byte(byte);
+    }
+
+    fn erase(&mut self) {
+        self.backspace();
+    }
+}
+
+impl Echo for SerialPort {
+    fn echo(&mut self, byte: u8) {
+        self.send(byte);
+    }
+
+    /// send 把退格展开为 "\b \b"
+    fn erase(&mut self) {
+        self.send(BACKSPACE);
+    }
+}
+
+impl<T: Echo + ?Sized> Echo for &mut T {
+    fn echo(&mut self, byte: u8) {
+        (**self).echo(byte);
+    }
+
+    fn erase(&mut self) {
+        (**self).erase();
+    }
+}
+
+/// 同时回显到两个目标，例如屏幕和串口
+impl<A: Echo, B: Echo> Echo for (A, B) {
+    fn echo(&mut self, byte: u8) {
+        self.0.echo(byte);
+        self.1.echo(byte);
+    }
+
+    fn erase(&mut self) {
+        self.0.erase();
+        self.1.erase();
+    }
+}
+
+/// 行缓冲区，只会存放可打印的 ASCII 字节
+pub trait LineBuffer {
+    /// 放不下时返回 false
+    fn push(&mut self, byte: u8) -> bool;
+    fn pop(&mut self) -> Option<u8>;
+    fn clear(&mut self);
+    fn as_bytes(&self) -> &[u8];
+}
+
+/// 借用固定大小的数组，写满后不再接受新的字节
+pub struct SliceBuffer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SliceBuffer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        SliceBuffer { buf, len: 0 }
+    }
+
+    pub fn into_bytes(self) -> &'a [u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl LineBuffer for SliceBuffer<'_> {
+    fn push(&mut self, byte: u8) -> bool {
+        let Some(slot) = self.buf.get_mut(self.len) else {
+            return false;
+        };
+        *slot = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        self.len = self.len.checked_sub(1)?;
+        Some(self.buf[self.len])
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl LineBuffer for Vec<u8> {
+    fn push(&mut self, byte: u8) -> bool {
+        Vec::push(self, byte);
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        Vec::pop(self)
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+/// 规范模式下的一次编辑
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Edit {
+    Insert(u8),
+    Erase,
+    Kill,
+    Submit,
+    Cancel,
+}
+
+impl Edit {
+    /// 其他控制字节和非 ASCII 字节没有对应的编辑
+    pub(crate) fn from_byte(byte: u8) -> Option<Edit> {
+        match byte {
+            b'\r' | b'\n' => Some(Edit::Submit),
+            BACKSPACE | DELETE => Some(Edit::Erase),
+            CTRL_U => Some(Edit::Kill),
+            CTRL_C => Some(Edit::Cancel),
+            b' '..=b'~' => Some(Edit::Insert(byte)),
+            _ => None,
+        }
+    }
+
+    /// 修改 buffer，回显打开时同时回显；Submit 和 Cancel 只回显，这一行由调用者处理
+    pub(crate) fn apply(self, buffer: &mut impl LineBuffer, echo: &mut impl Echo, echo_on: bool) {
+        match self {
+            Edit::Insert(byte) => {
+                if buffer.push(byte) && echo_on {
+                    echo.echo(byte);
+                }
+            }
+            Edit::Erase => {
+                if buffer.pop().is_some() && echo_on {
+                    echo.erase();
+                }
+            }
+            Edit::Kill => {
+                while buffer.pop().is_some() {
+                    if echo_on {
+                        echo.erase();
+                    }
+                }
+            }
+            Edit::Submit => echo.echo(b'\n'),
+            Edit::Cancel => {
+                for &byte in b"^C\n" {
+                    echo.echo(byte);
+                }
+            }
+        }
+    }
+}
+
+/// 按键对应的终端字节：Ctrl+字母是控制字符，回车是 '\n'，退格是 0x08。
+/// 松开、功能键、非 ASCII 字符和 Delete 键（终端发送的是转义序列）没有对应的字节
+pub fn key_byte(input: &KeyInput) -> Option<u8> {
+    let Some(DecodedKey::Unicode(character)) = input.key else {
+        return None;
+    };
+    if !character.is_ascii() || character as u8 == DELETE {
+        return None;
+    }
+    let byte = character as u8;
+    if input.event.modifiers.ctrl && byte.is_ascii_alphabetic() {
+        return Some(byte.to_ascii_uppercase() & 0x1f);
+    }
+    Some(byte)
+}
+
+/// 一个输入事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Key(KeyInput),
+    Serial(u8),
+}
+
+impl Input {
+    pub fn byte(&self) -> Option<u8> {
+        match self {
+            Input::Key(input) => key_byte(input),
+            Input::Serial(byte) => Some(*byte),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+    pub echo: bool,
+    /// false 时为原始模式
+    pub canonical: bool,
+}
+
+impl Mode {
+    /// 默认的模式：按行编辑并回显
+    pub const LINE: Mode = Mode {
+        echo: true,
+        canonical: true,
+    };
+    /// 输入密码：按行编辑但不回显
+    pub const PASSWORD: Mode = Mode {
+        echo: false,
+        canonical: true,
+    };
+    pub const RAW: Mode = Mode {
+        echo: false,
+        canonical: false,
+    };
+}
+
+/// edit 处理一个字节的结果，交给 deliver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// 规范模式下提交了一行，deliver 之前这一行仍然在缓冲区中
+    Line,
+    /// 原始模式下的一个字节
+    Raw(u8),
+    Cancelled,
+}
+
+/// 接收行规程的输出
+pub trait LineConsumer {
+    /// 规范模式下输入回车时调用，不包括换行符
+    fn line(&mut self, line: &str);
+    /// 原始模式下的每一个字节，以及切换到原始模式时没有提交的半行
+    fn raw(&mut self, byte: u8);
+    /// Ctrl+C 丢弃了当前的半行
+    fn cancelled(&mut self) {}
+}
+
+/// 自己取走提交的行的读者（例如 LineEditor）不需要消费者
+impl LineConsumer for () {
+    fn line(&mut self, _line: &str) {}
+    fn raw(&mut self, _byte: u8) {}
+}
+
+pub struct LineDiscipline<B, C> {
+    buffer: B,
+    consumer: C,
+    mode: Mode,
+}
+
+impl<B: LineBuffer, C: LineConsumer> LineDiscipline<B, C> {
+    /// 初始为 Mode::LINE
+    pub fn new(buffer: B, consumer: C) -> Self {
+        LineDiscipline {
+            buffer,
+            consumer,
+            mode: Mode::LINE,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// 中途切换的规则见模块文档
+    pub fn set_mode(&mut self, mode: Mode) {
+        if self.mode.canonical && !mode.canonical {
+            for &byte in self.buffer.as_bytes() {
+                self.consumer.raw(byte);
+            }
+            self.buffer.clear();
+        }
+        self.mode = mode;
+    }
+
+    /// 还没有提交的半行
+    pub fn pending(&self) -> &[u8] {
+        self.buffer.as_bytes()
+    }
+
+    pub fn consumer(&self) -> &C {
+        &self.consumer
+    }
+
+    pub fn consumer_mut(&mut self) -> &mut C {
+        &mut self.consumer
+    }
+
+    pub fn feed(&mut self, input: Input, echo: &mut impl Echo) {
+        if let Some(byte) = input.byte() {
+            self.feed_byte(byte, echo);
+        }
+    }
+
+    /// edit 之后立即 deliver
+    pub fn feed_byte(&mut self, byte: u8, echo: &mut impl Echo) {
+        if let Some(event) = self.edit(byte, echo) {
+            self.deliver(event);
+        }
+    }
+
+    /// 修改缓冲区并回显，不调用消费者；返回的事件必须在下一次 edit 之前交给 deliver
+    pub fn edit(&mut self, byte: u8, echo: &mut impl Echo) -> Option<Event> {
+        if !self.mode.canonical {
+            if self.mode.echo && (byte == b'\n' || byte.is_ascii_graphic() || byte == b' ') {
+                echo.echo(byte);
+            }
+            return Some(Event::Raw(byte));
+        }
+        let edit = Edit::from_byte(byte)?;
+        edit.apply(&mut self.buffer, echo, self.mode.echo);
+        match edit {
+            Edit::Submit => Some(Event::Line),
+            Edit::Cancel => {
+                self.buffer.clear();
+                Some(Event::Cancelled)
+            }
+            _ => None,
+        }
+    }
+
+    /// 把 edit 返回的事件交给消费者，提交的行在这之后从缓冲区清除
+    pub fn deliver(&mut self, event: Event) {
+        match event {
+            Event::Line => {
+                // 缓冲区中只有可打印的 ASCII 字节
+                let line = core::str::from_utf8(self.buffer.as_bytes()).unwrap();
+                self.consumer.line(line);
+                self.buffer.clear();
+            }
+            Event::Raw(byte) => self.consumer.raw(byte),
+            Event::Cancelled => self.consumer.cancelled(),
+        }
+    }
+
+    pub fn into_buffer(self) -> B {
+        self.buffer
+    }
+}
+
+/// 轮询键盘控制器和 COM1，用于还没有运行执行器的场景。
+/// 与 PolledKeyboard 一样，IRQ1 和 IRQ4 必须处于屏蔽状态，否则输入会先被中断处理函数取走
+#[derive(Default)]
+pub struct PolledInput {
+    keyboard: PolledKeyboard,
+}
+
+impl PolledInput {
+    pub const fn new() -> Self {
+        PolledInput {
+            keyboard: PolledKeyboard::new(),
+        }
+    }
+
+    /// 不阻塞地取出已经到达的输入，键盘优先
+    pub fn try_next(&mut self) -> Option<Input> {
+        if let Some(key) = self.keyboard.try_next() {
+            return Some(Input::Key(key));
+        }
+        interrupts::without_interrupts(|| SERIAL1.lock().try_receive().ok()).map(Input::Serial)
+    }
+}
+
+impl Iterator for PolledInput {
+    type Item = Input;
+
+    /// 忙等直到有输入，永远不会返回 None
+    fn next(&mut self) -> Option<Input> {
+        loop {
+            if let Some(input) = self.try_next() {
+                return Some(input);
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// 把 next 返回的输入逐个交给 discipline，直到它返回 None；回显同时写到屏幕和串口。
+/// 回显时持有 WRITER 和 SERIAL1 的锁并关闭中断，消费者在释放它们之后才被调用，可以打印。
+/// 轮询时 next 传入 PolledInput::try_next，不阻塞
+pub fn pump<B: LineBuffer, C: LineConsumer>(
+    discipline: &mut LineDiscipline<B, C>,
+    mut next: impl FnMut() -> Option<Input>,
+) {
+    while let Some(input) = next() {
+        let Some(byte) = input.byte() else {
+            continue;
+        };
+        let event = interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let mut serial = SERIAL1.lock();
+            discipline.edit(byte, &mut (&mut *writer, &mut *serial))
+        });
+        if let Some(event) = event {
+            discipline.deliver(event);
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::keyboard::{KeyCode, KeyEvent, Modifiers};
+#[cfg(test)]
+use alloc::string::String;
+
+/// 回显的记录，擦除记为 0x08
+#[cfg(test)]
+#[derive(Default)]
+struct Transcript(Vec<u8>);
+
+#[cfg(test)]
+impl Echo for Transcript {
+    fn echo(&mut self, byte: u8) {
+        self.0.push(byte);
+    }
+
+    fn erase(&mut self) {
+        self.0.push(BACKSPACE);
+    }
+}
+
+/// 按顺序记录消费者收到的内容，原始字节记为 "raw:字节"，取消记为 "^C"
+#[cfg(test)]
+#[derive(Default)]
+struct Recorder(Vec<String>);
+
+#[cfg(test)]
+impl LineConsumer for Recorder {
+    fn line(&mut self, line: &str) {
+        self.0.push(String::from(line));
+    }
+
+    fn raw(&mut self, byte: u8) {
+        self.0.push(alloc::format!("raw:{:02x}", byte));
+    }
+
+    fn cancelled(&mut self) {
+        self.0.push(String::from("^C"));
+    }
+}
+
+#[cfg(test)]
+fn key(character: char, ctrl: bool) -> Input {
+    Input::Key(KeyInput {
+        event: KeyEvent {
+            code: KeyCode::A,
+            pressed: true,
+            modifiers: Modifiers {
+                ctrl,
+                ..Modifiers::NONE
+            },
+        },
+        key: Some(DecodedKey::Unicode(character)),
+    })
+}
+
+#[cfg(test)]
+fn serial(text: &[u8]) -> impl Iterator<Item = Input> + '_ {
+    text.iter().map(|&byte| Input::Serial(byte))
+}
+
+#[cfg(test)]
+fn drive<B: LineBuffer>(
+    discipline: &mut LineDiscipline<B, Recorder>,
+    inputs: impl IntoIterator<Item = Input>,
+) -> Vec<u8> {
+    let mut transcript = Transcript::default();
+    for input in inputs {
+        discipline.feed(input, &mut transcript);
+    }
+    transcript.0
+}
+
+#[test_case]
+fn test_keyboard_and_serial_edit_the_same_line() {
+    let mut buf = [0u8; 16];
+    let mut discipline = LineDiscipline::new(SliceBuffer::new(&mut buf), Recorder::default());
+
+    // 键盘输入 "lx"，串口退格（DEL）删掉 x，再从串口补上 "s"，键盘回车
+    let inputs = [key('l', false), key('x', false)]
+        .into_iter()
+        .chain(serial(b"\x7fs"))
+        .chain([key('\n', false)]);
+    assert_eq!(drive(&mut discipline, inputs), b"lx\x08s\n");
+    assert_eq!(discipline.consumer().0, ["ls"]);
+
+    // 键盘 Ctrl+U 和串口的 0x15 效果相同；串口的 '\r' 也结束一行
+    let inputs = serial(b"ab")
+        .chain([key('u', true), key('c', false)])
+        .chain(serial(b"d\x15ef\r"));
+    assert_eq!(drive(&mut discipline, inputs), b"ab\x08\x08cd\x08\x08ef\n");
+    assert_eq!(discipline.consumer().0, ["ls", "ef"]);
+
+    // 键盘 Ctrl+C 和串口的 0x03 都丢弃半行
+    let inputs = [key('x', false), key('c', true)]
+        .into_iter()
+        .chain(serial(b"y\x03"));
+    assert_eq!(drive(&mut discipline, inputs), b"x^C\ny^C\n");
+    assert_eq!(discipline.consumer().0, ["ls", "ef", "^C", "^C"]);
+    assert_eq!(discipline.pending(), b"");
+}
+
+#[test_case]
+fn test_password_and_raw_modes() {
+    let mut discipline = LineDiscipline::new(Vec::new(), Recorder::default());
+
+    // 不回显时仍然可以编辑，只回显结束一行的换行
+    discipline.set_mode(Mode::PASSWORD);
+    let inputs = [key('p', false), key('w', false), key('\u{8}', false)]
+        .into_iter()
+        .chain(serial(b"x\r"));
+    assert_eq!(drive(&mut discipline, inputs), b"\n");
+    assert_eq!(discipline.consumer().0, ["px"]);
+
+    // 原始模式下控制字节也原样交出，键盘 Ctrl+U 同样是 0x15
+    discipline.set_mode(Mode::RAW);
+    let inputs = serial(b"a\x7f\r").chain([key('u', true)]);
+    assert_eq!(drive(&mut discipline, inputs), b"");
+    assert_eq!(
+        discipline.consumer().0[1..],
+        ["raw:61", "raw:7f", "raw:0d", "raw:15"]
+    );
+
+    // 打开回显的原始模式只回显可打印字符和换行
+    discipline.set_mode(Mode {
+        echo: true,
+        canonical: false,
+    });
+    assert_eq!(drive(&mut discipline, serial(b"k\x08\n")), b"k\n");
+}
+
+#[test_case]
+fn test_mode_switch_mid_line() {
+    let mut buf = [0u8; 4];
+    let mut discipline = LineDiscipline::new(SliceBuffer::new(&mut buf), Recorder::default());
+
+    // 缓冲区满了以后多出来的字节既不保存也不回显
+    assert_eq!(drive(&mut discipline, serial(b"abcde")), b"abcd");
+    // 切换到原始模式时半行按顺序交出
+    discipline.set_mode(Mode::RAW);
+    assert_eq!(discipline.pending(), b"");
+    assert_eq!(
+        discipline.consumer().0,
+        ["raw:61", "raw:62", "raw:63", "raw:64"]
+    );
+    drive(&mut discipline, serial(b"z"));
+    // 切回规范模式从空行开始
+    discipline.set_mode(Mode::LINE);
+    assert_eq!(drive(&mut discipline, serial(b"q\n")), b"q\n");
+    assert_eq!(discipline.consumer().0[4..], ["raw:7a", "q"]);
+
+    // 只关闭回显时半行保留，之后的删除不再回显
+    drive(&mut discipline, serial(b"ab"));
+    discipline.set_mode(Mode::PASSWORD);
+    assert_eq!(drive(&mut discipline, serial(b"\x7fc\n")), b"\n");
+    assert_eq!(discipline.consumer().0[6..], ["ac"]);
+}
+
+/// 提交的行原样打印出来
+#[cfg(test)]
+struct Printer(Vec<String>);
+
+#[cfg(test)]
+impl LineConsumer for Printer {
+    fn line(&mut self, line: &str) {
+        crate::println!("got {}", line);
+        self.0.push(String::from(line));
+    }
+
+    fn raw(&mut self, _byte: u8) {}
+}
+
+#[test_case]
+fn test_pump_delivers_after_releasing_locks() {
+    use crate::vga_buffer::BUFFER_HEIGHT;
+
+    // 从行首开始回显
+    crate::println!();
+    let mut discipline = LineDiscipline::new(Vec::new(), Printer(Vec::new()));
+    let mut inputs = [key('h', false)].into_iter().chain(serial(b"i\r"));
+    // 消费者打印时如果 WRITER 还被锁着，这里会卡住
+    pump(&mut discipline, || inputs.next());
+    assert_eq!(discipline.consumer().0, ["hi"]);
+    let row = |row: usize, len: usize| -> Vec<u8> {
+        let writer = WRITER.lock();
+        (0..len).map(|col| writer.read_char(row, col).0).collect()
+    };
+    assert_eq!(row(BUFFER_HEIGHT - 3, 2), b"hi");
+    assert_eq!(row(BUFFER_HEIGHT - 2, 6), b"got hi");
+}
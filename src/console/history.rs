@@ -0,0 +1,72 @@
+//! 输入历史
+//! 保存最近提交的若干行，最旧的在前。空行不保存，与上一条相同的行只保存一次
+use alloc::collections::VecDeque;
+use alloc::string::String;
+
+pub struct History {
+    entries: VecDeque<String>,
+    capacity: usize,
+}
+
+impl History {
+    /// 最多保存 capacity 条，超出时丢弃最旧的一条
+    pub fn new(capacity: usize) -> Self {
+        History {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// 记录提交的一行，只含空白的行和与最新一条相同的行被忽略
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.get(0) == Some(line) || self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(String::from(line));
+    }
+
+    /// 按从新到旧的顺序取一条，0 是最新的一条
+    pub fn get(&self, age: usize) -> Option<&str> {
+        let index = self.entries.len().checked_sub(age + 1)?;
+        self.entries.get(index).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[test_case]
+fn test_history_skips_empty_and_repeated_lines() {
+    let mut history = History::new(8);
+    history.push("ls");
+    history.push("");
+    history.push("   ");
+    history.push("ls");
+    history.push("echo hi");
+    history.push("ls");
+    // 只合并连续重复的行
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.get(0), Some("ls"));
+    assert_eq!(history.get(1), Some("echo hi"));
+    assert_eq!(history.get(2), Some("ls"));
+    assert_eq!(history.get(3), None);
+}
+
+#[test_case]
+fn test_history_drops_oldest_when_full() {
+    let mut history = History::new(2);
+    history.push("a");
+    history.push("b");
+    history.push("c");
+    assert_eq!(history.len(), 2);
+    assert_eq!(history.get(0), Some("c"));
+    assert_eq!(history.get(1), Some("b"));
+}
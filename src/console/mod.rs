@@ -0,0 +1,983 @@
+//! 控制台行输入
+//! 逐个处理按键并回显到屏幕，回车结束一行：
+//! - 只接受可打印的 ASCII 字符（0x20..=0x7e），返回的字符串因此总是合法的 UTF-8；
+//!   码点大于 0x7F 的字符无法用单个字节在 VGA 文本模式下显示，直接忽略
+//! - Backspace 删除最后一个字节并擦除屏幕上的字符，行首再退格不会删到提示符
+//! - 其他控制字符和功能键被忽略，缓冲区满后不再接受新的字符
+//! - 带有 History 时，上/下方向键在历史中前后翻找，用找到的行替换当前输入；
+//!   翻过最新一条时恢复翻找前输入了一半的内容
+//!
+//! 输入可以来自中断驱动的异步流，也可以轮询键盘控制器和 COM1 获得。
+//! 按键交给行编辑之前先查 keybindings 中的快捷键，绑定的组合键在这里执行，不会回显；
+//! 可打印的输入让回滚的视图回到底部（Writer 关闭了 snap_on_output 时除外）
+//!
+//! 编辑本身由 discipline 模块的行规程完成：键盘按键换成字节后与串口收到的字节走同一条路径，
+//! 退格、Ctrl+U、Ctrl+C 和回车的效果相同。控制台输出到串口时回显也写到串口。
+//! 行规程的回显开关、原始模式和推送给消费者的用法见 discipline 模块，
+//! print! 等宏的输出目标见 sink 模块，控制台的初始化阶段见 state 模块，
+//! 通过串口导出屏幕内容见 screen_dump 模块
+use crate::keybindings::{self, Action};
+use crate::keyboard::{self, DecodedKey, KeyCode, KeyInput};
+use crate::serial::SERIAL1;
+use crate::vga_buffer::{self, Writer, WRITER};
+use crate::{task, time};
+use alloc::string::String;
+use discipline::{Echo, Event, SliceBuffer, CTRL_C, CTRL_U};
+use futures_util::stream::{Stream, StreamExt};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+pub mod capture;
+pub mod discipline;
+mod history;
+pub mod screen_dump;
+pub mod sink;
+mod state;
+
+pub use capture::{capture, CaptureSink};
+pub use discipline::{Input, LineDiscipline, Mode, PolledInput};
+pub use history::History;
+pub use screen_dump::dump_screen_to_serial;
+pub use sink::{
+    output, set_output, set_sink_level, set_unleveled, OutputMode, OutputSink, SinkId, Style,
+};
+pub use state::{advance, state, ConsoleState};
+
+/// LineEditor 的回显：写到屏幕，serial 为 true 时同时写到串口
+struct EditorEcho<'w> {
+    writer: &'w mut Writer,
+    serial: bool,
+}
+
+impl Echo for EditorEcho<'_> {
+    fn echo(&mut self, byte: u8) {
+        self.writer.echo(byte);
+        if self.serial {
+            interrupts::without_interrupts(|| SERIAL1.lock().echo(byte));
+        }
+    }
+
+    fn erase(&mut self) {
+        self.writer.erase();
+        if self.serial {
+            interrupts::without_interrupts(|| SERIAL1.lock().erase());
+        }
+    }
+}
+
+/// 行编辑状态，按键逐个交给 handle_key 处理，串口收到的字节交给 handle_byte
+pub struct LineEditor<'a> {
+    /// 总是处于规范模式，提交的行由 into_line 取走
+    discipline: LineDiscipline<SliceBuffer<'a>, ()>,
+    history: Option<&'a mut History>,
+    /// 正在显示的历史条目，0 是最新的一条，None 表示在编辑新的一行
+    recalled: Option<usize>,
+    /// 开始翻找历史前输入的内容
+    draft: String,
+    /// 控制台输出到串口时是否把回显也写到串口，控制台的读取函数打开，测试中不打开
+    serial_echo: bool,
+}
+
+impl<'a> LineEditor<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        LineEditor {
+            discipline: LineDiscipline::new(SliceBuffer::new(buf), ()),
+            history: None,
+            recalled: None,
+            draft: String::new(),
+            serial_echo: false,
+        }
+    }
+
+    /// 可以用上/下方向键翻找 history，回车提交的行会被记录到 history 中
+    pub fn with_history(buf: &'a mut [u8], history: &'a mut History) -> Self {
+        LineEditor {
+            history: Some(history),
+            ..LineEditor::new(buf)
+        }
+    }
+
+    /// 处理一个按键并把回显写入 writer，输入回车时返回 true
+    pub fn handle_key(&mut self, key: DecodedKey, writer: &mut Writer) -> bool {
+        self.key(key, writer) == Some(LineEnd::Submitted)
+    }
+
+    /// ASCII 字符的按键与串口输入一样交给行规程。Ctrl 组合键先由快捷键处理，
+    /// 没有绑定的照常作为字符输入，不换成 key_byte 的控制字符
+    fn key(&mut self, key: DecodedKey, writer: &mut Writer) -> Option<LineEnd> {
+        match key {
+            // Delete 键不是退格
+            DecodedKey::Unicode(character) if character.is_ascii() && character != '\u{7f}' => {
+                self.edit_byte(character as u8, writer)
+            }
+            DecodedKey::RawKey(KeyCode::ArrowUp) => {
+                self.recall(self.recalled.map_or(0, |age| age + 1), writer);
+                None
+            }
+            DecodedKey::RawKey(KeyCode::ArrowDown) => {
+                if let Some(age) = self.recalled {
+                    self.recall_newer(age, writer);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// 处理串口收到的一个字节并回显，输入回车时返回 true
+    pub fn handle_byte(&mut self, byte: u8, writer: &mut Writer) -> bool {
+        self.edit_byte(byte, writer) == Some(LineEnd::Submitted)
+    }
+
+    /// 交给行规程编辑；提交的行留在缓冲区中，由 into_line 取走
+    fn edit_byte(&mut self, byte: u8, writer: &mut Writer) -> Option<LineEnd> {
+        let mut echo = EditorEcho {
+            writer,
+            serial: self.serial_echo && vga_buffer::routes().1,
+        };
+        match self.discipline.edit(byte, &mut echo)? {
+            Event::Line => {
+                if let Some(history) = self.history.as_deref_mut() {
+                    history.push(core::str::from_utf8(self.discipline.pending()).unwrap());
+                }
+                Some(LineEnd::Submitted)
+            }
+            Event::Cancelled => Some(LineEnd::Cancelled),
+            Event::Raw(_) => None,
+        }
+    }
+
+    /// 目前输入的内容
+    pub fn line(&self) -> &str {
+        // 缓冲区中只会写入 ASCII 字节
+        core::str::from_utf8(self.discipline.pending()).unwrap()
+    }
+
+    pub fn into_line(self) -> &'a str {
+        core::str::from_utf8(self.discipline.into_buffer().into_bytes()).unwrap()
+    }
+
+    /// 删除已经输入的全部内容，并从屏幕上擦除
+    pub fn kill_line(&mut self, writer: &mut Writer) {
+        self.edit_byte(CTRL_U, writer);
+    }
+
+    /// 显示第 age 条历史，没有这一条时什么也不做
+    fn recall(&mut self, age: usize, writer: &mut Writer) {
+        let Some(history) = self.history.as_deref() else {
+            return;
+        };
+        let Some(entry) = history.get(age) else {
+            return;
+        };
+        let entry = String::from(entry);
+        if self.recalled.is_none() {
+            self.draft = String::from(self.line());
+        }
+        self.recalled = Some(age);
+        self.replace_line(&entry, writer);
+    }
+
+    /// 显示较新的一条历史，已经是最新的一条时恢复草稿
+    fn recall_newer(&mut self, age: usize, writer: &mut Writer) {
+        if age > 0 {
+            self.recall(age - 1, writer);
+            return;
+        }
+        self.recalled = None;
+        let draft = core::mem::take(&mut self.draft);
+        self.replace_line(&draft, writer);
+    }
+
+    /// 擦掉当前输入并换成 text，超出缓冲区的部分被截断
+    fn replace_line(&mut self, text: &str, writer: &mut Writer) {
+        self.kill_line(writer);
+        for &byte in text.as_bytes() {
+            self.edit_byte(byte, writer);
+        }
+    }
+}
+
+/// 一行输入的结束方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineEnd {
+    Submitted,
+    Cancelled,
+}
+
+/// 执行快捷键绑定的动作
+fn apply_action(editor: &mut LineEditor, action: Action, writer: &mut Writer) -> Option<LineEnd> {
+    match action {
+        Action::ClearScreen => {
+            // 提示符随屏幕一起被清掉，只重新显示已经输入的内容
+            writer.clear_screen();
+            writer.write_string(editor.line());
+        }
+        // 与串口收到 0x03 相同
+        Action::CancelLine => return editor.edit_byte(CTRL_C, writer),
+        Action::KillLine => editor.kill_line(writer),
+        Action::ScrollUp(lines) => writer.scroll_view_up(lines),
+        Action::ScrollDown(lines) => writer.scroll_view_down(lines),
+        Action::SwitchConsole(index) => {
+            let _ = writer.switch_console(index);
+        }
+        // 对 WRITER 的这个动作已经由 echo_input 在加锁之前处理，这里只会遇到其他 Writer
+        Action::DumpScreen => screen_dump::dump_snapshot_to_serial(&writer.snapshot(), true),
+        Action::Custom(handler) => handler(writer),
+    }
+    None
+}
+
+/// 先查快捷键，没有绑定的按键交给行编辑
+fn handle_input(editor: &mut LineEditor, input: &KeyInput, writer: &mut Writer) -> Option<LineEnd> {
+    if let Some(action) = keybindings::lookup(&input.event) {
+        return apply_action(editor, action, writer);
+    }
+    let key = input.key?;
+    let printable = matches!(key, DecodedKey::Unicode(c) if !c.is_control());
+    if printable && writer.snap_on_output() {
+        writer.snap_to_bottom();
+    }
+    editor.key(key, writer)
+}
+
+/// 按键先查快捷键；串口收到的字节直接交给行规程
+fn handle_console_input(
+    editor: &mut LineEditor,
+    input: &Input,
+    writer: &mut Writer,
+) -> Option<LineEnd> {
+    match *input {
+        Input::Key(ref key) => handle_input(editor, key, writer),
+        Input::Serial(byte) => {
+            if (b' '..=b'~').contains(&byte) && writer.snap_on_output() {
+                writer.snap_to_bottom();
+            }
+            editor.edit_byte(byte, writer)
+        }
+    }
+}
+
+/// 每个输入单独加锁回显，等待输入期间不持有 WRITER 的锁。
+/// 导出画面要向串口写很久，只在复制画面时持有锁，写串口在释放锁、恢复中断之后
+fn echo_input(editor: &mut LineEditor, input: &Input) -> Option<LineEnd> {
+    if let Input::Key(key) = input {
+        if matches!(keybindings::lookup(&key.event), Some(Action::DumpScreen)) {
+            screen_dump::dump_screen_to_serial(true);
+            return None;
+        }
+    }
+    interrupts::without_interrupts(|| handle_console_input(editor, input, &mut WRITER.lock()))
+}
+
+/// 控制台的读取函数使用的 LineEditor，回显跟随控制台的输出写到串口
+fn console_editor(mut editor: LineEditor) -> LineEditor {
+    editor.serial_echo = true;
+    editor
+}
+
+/// 轮询键盘控制器读取按键，用于还没有运行执行器的场景
+/// IRQ1 必须处于屏蔽状态，否则扫描码会先被键盘中断处理函数取走
+pub struct PolledKeyboard {
+    status: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Default for PolledKeyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PolledKeyboard {
+    pub const fn new() -> Self {
+        PolledKeyboard {
+            status: Port::new(0x64),
+            data: Port::new(0x60),
+        }
+    }
+}
+
+impl PolledKeyboard {
+    /// 取出控制器中已经到达的扫描码并解码，没有完整的按键时立即返回 None
+    pub fn try_next(&mut self) -> Option<KeyInput> {
+        // 状态寄存器 bit 0：输出缓冲区中有数据
+        while unsafe { self.status.read() } & 0x01 != 0 {
+            let scancode = unsafe { self.data.read() };
+            if let Some(input) = keyboard::decode_input(scancode) {
+                return Some(input);
+            }
+        }
+        None
+    }
+}
+
+impl Iterator for PolledKeyboard {
+    type Item = KeyInput;
+
+    /// 忙等直到解码出一个按键，永远不会返回 None
+    fn next(&mut self) -> Option<KeyInput> {
+        loop {
+            if let Some(input) = self.try_next() {
+                return Some(input);
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// 轮询键盘和 COM1 读取一行，阻塞直到输入回车；按 Ctrl+C 取消时返回 None
+pub fn read_line(buf: &mut [u8]) -> Option<&str> {
+    read_line_from(&mut PolledInput::new(), buf)
+}
+
+/// 从任意输入来源读取一行，来源耗尽时返回已经输入的内容，取消时返回 None
+pub fn read_line_from<'a>(
+    inputs: &mut impl Iterator<Item = Input>,
+    buf: &'a mut [u8],
+) -> Option<&'a str> {
+    let mut editor = console_editor(LineEditor::new(buf));
+    for input in inputs {
+        match echo_input(&mut editor, &input) {
+            Some(LineEnd::Submitted) => break,
+            Some(LineEnd::Cancelled) => return None,
+            None => {}
+        }
+    }
+    Some(editor.into_line())
+}
+
+/// 限时读取时的输入来源和时钟
+trait TimedKeys {
+    fn ticks(&self) -> u64;
+    /// 不阻塞地取出一个输入
+    fn poll(&mut self) -> Option<Input>;
+    /// 没有输入可取时休眠，直到下一个中断
+    fn wait(&mut self);
+}
+
+/// 从键盘和串口中断填充的队列取输入，用时钟节拍计时
+struct QueuedInput;
+
+impl TimedKeys for QueuedInput {
+    fn ticks(&self) -> u64 {
+        time::ticks()
+    }
+
+    fn poll(&mut self) -> Option<Input> {
+        while let Some(scancode) = task::keyboard::try_next_scancode() {
+            if let Some(input) = keyboard::decode_input(scancode) {
+                return Some(Input::Key(input));
+            }
+        }
+        task::serial::try_next_byte().map(Input::Serial)
+    }
+
+    /// 与执行器相同：关中断后检查队列，再用 sti; hlt 原子地开中断并休眠，
+    /// 检查之后到来的输入不会让这次等待错过
+    fn wait(&mut self) {
+        interrupts::disable();
+        if task::keyboard::scancode_pending() || task::serial::byte_pending() {
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+/// 在节拍数前进 timeout_ticks 之前读完一行时返回 true
+fn read_line_before(
+    keys: &mut impl TimedKeys,
+    editor: &mut LineEditor,
+    timeout_ticks: u64,
+    mut echo: impl FnMut(&mut LineEditor, &Input) -> Option<LineEnd>,
+) -> bool {
+    let start = keys.ticks();
+    loop {
+        // 先取完已经到达的按键再检查期限，期限当拍到达的按键仍然有效
+        while let Some(input) = keys.poll() {
+            match echo(editor, &input) {
+                Some(LineEnd::Submitted) => return true,
+                Some(LineEnd::Cancelled) => return false,
+                None => {}
+            }
+        }
+        if keys.ticks().wrapping_sub(start) >= timeout_ticks {
+            return false;
+        }
+        keys.wait();
+    }
+}
+
+/// 读取一行，返回行的字节数；节拍数前进 timeout_ticks 时还没有输入回车，
+/// 或者按 Ctrl+C 取消时返回 None，已经回显的半行留在屏幕上
+///
+/// 精度为 1 个节拍：调用时可能正处在两个节拍之间，实际期限在
+/// (timeout_ticks - 1, timeout_ticks] 个节拍之间。
+/// 输入来自键盘和串口中断填充的队列，等待期间用 hlt 休眠，所以必须在中断开启后调用；
+/// 不要在 shell 等 ScancodeStream、SerialStream 的消费者运行时调用，两者会互相抢走输入
+pub fn read_line_timeout(buf: &mut [u8], timeout_ticks: u64) -> Option<usize> {
+    debug_assert!(
+        interrupts::are_enabled(),
+        "read_line_timeout requires interrupts to be enabled"
+    );
+    task::keyboard::init_queue();
+    task::serial::init_queue();
+    let mut editor = console_editor(LineEditor::new(buf));
+    read_line_before(&mut QueuedInput, &mut editor, timeout_ticks, echo_input)
+        .then(|| editor.into_line().len())
+}
+
+/// 从异步输入流读取一行，例如 console_inputs，取消时返回 None
+pub async fn read_line_async<'a, S>(inputs: &mut S, buf: &'a mut [u8]) -> Option<&'a str>
+where
+    S: Stream<Item = Input> + Unpin,
+{
+    edit_line_async(inputs, LineEditor::new(buf)).await
+}
+
+/// 与 read_line_async 相同，但可以翻找 history，提交的行会被记录下来
+pub async fn read_line_with_history<'a, S>(
+    inputs: &mut S,
+    buf: &'a mut [u8],
+    history: &'a mut History,
+) -> Option<&'a str>
+where
+    S: Stream<Item = Input> + Unpin,
+{
+    edit_line_async(inputs, LineEditor::with_history(buf, history)).await
+}
+
+async fn edit_line_async<'a, S>(inputs: &mut S, editor: LineEditor<'a>) -> Option<&'a str>
+where
+    S: Stream<Item = Input> + Unpin,
+{
+    let mut editor = console_editor(editor);
+    while let Some(input) = inputs.next().await {
+        match echo_input(&mut editor, &input) {
+            Some(LineEnd::Submitted) => break,
+            Some(LineEnd::Cancelled) => return None,
+            None => {}
+        }
+    }
+    Some(editor.into_line())
+}
+
+/// 键盘和 COM1 合并成的异步输入流，只能创建一次（见 ScancodeStream 和 SerialStream）
+pub fn console_inputs() -> impl Stream<Item = Input> + Unpin {
+    use task::keyboard::{key_inputs, ScancodeStream};
+    use task::serial::SerialStream;
+
+    futures_util::stream::select(
+        key_inputs(ScancodeStream::new()).map(Input::Key),
+        SerialStream::new().map(Input::Serial),
+    )
+}
+
+#[cfg(test)]
+use crate::keyboard::{KeyEvent, Modifiers};
+#[cfg(test)]
+use crate::vga_buffer::{TestWriter, BUFFER_HEIGHT};
+#[cfg(test)]
+use DecodedKey::{RawKey, Unicode};
+
+#[cfg(test)]
+const BACKSPACE: char = '\u{8}';
+
+#[cfg(test)]
+fn type_keys<'a>(writer: &mut Writer, keys: &[DecodedKey], buf: &'a mut [u8]) -> &'a str {
+    let mut editor = LineEditor::new(buf);
+    for &key in keys {
+        if editor.handle_key(key, writer) {
+            break;
+        }
+    }
+    editor.into_line()
+}
+
+#[cfg(test)]
+fn bottom_row(writer: &Writer, len: usize) -> alloc::vec::Vec<u8> {
+    (0..len)
+        .map(|col| writer.read_char(BUFFER_HEIGHT - 1, col).0)
+        .collect()
+}
+
+#[test_case]
+fn test_read_line_echoes_and_stops_at_enter() {
+    let mut writer = TestWriter::new();
+    let mut buf = [0u8; 16];
+    let keys = [Unicode('h'), Unicode('i'), Unicode('\n'), Unicode('x')];
+    assert_eq!(type_keys(&mut writer, &keys, &mut buf), "hi");
+    // 回车也被回显，屏幕上滚了一行
+    let row = BUFFER_HEIGHT - 2;
+    assert_eq!(writer.read_char(row, 0).0, b'h');
+    assert_eq!(writer.read_char(row, 1).0, b'i');
+    assert_eq!(bottom_row(&writer, 1), b" ");
+}
+
+#[test_case]
+fn test_backspace_removes_last_byte() {
+    let mut writer = TestWriter::new();
+    let mut buf = [0u8; 16];
+    let keys = [Unicode('a'), Unicode('b'), Unicode(BACKSPACE), Unicode('c')];
+    assert_eq!(type_keys(&mut writer, &keys, &mut buf), "ac");
+    assert_eq!(bottom_row(&writer, 3), b"ac ");
+}
+
+#[test_case]
+fn test_backspace_does_not_eat_prompt() {
+    let mut writer = TestWriter::new();
+    writer.write_string("> ");
+    let mut buf = [0u8; 16];
+    let keys = [
+        Unicode('a'),
+        Unicode(BACKSPACE),
+        Unicode(BACKSPACE),
+        Unicode(BACKSPACE),
+        Unicode('b'),
+    ];
+    assert_eq!(type_keys(&mut writer, &keys, &mut buf), "b");
+    assert_eq!(bottom_row(&writer, 4), b"> b ");
+}
+
+#[test_case]
+fn test_control_and_non_ascii_keys_are_ignored() {
+    let mut writer = TestWriter::new();
+    let mut buf = [0u8; 16];
+    let keys = [
+        Unicode('a'),
+        RawKey(KeyCode::ArrowLeft),
+        Unicode('\t'),
+        Unicode('\u{1b}'),
+        Unicode('é'),
+        Unicode('b'),
+    ];
+    assert_eq!(type_keys(&mut writer, &keys, &mut buf), "ab");
+    assert_eq!(bottom_row(&writer, 3), b"ab ");
+}
+
+#[test_case]
+fn test_full_buffer_stops_accepting_input() {
+    let mut writer = TestWriter::new();
+    let mut buf = [0u8; 3];
+    let keys = [
+        Unicode('a'),
+        Unicode('b'),
+        Unicode('c'),
+        Unicode('d'),
+        Unicode(BACKSPACE),
+        Unicode('e'),
+    ];
+    assert_eq!(type_keys(&mut writer, &keys, &mut buf), "abe");
+    assert_eq!(bottom_row(&writer, 4), b"abe ");
+}
+
+/// 构造一个按下事件，key 为按键对应的字符
+#[cfg(test)]
+fn press(code: KeyCode, shift: bool, key: Option<DecodedKey>) -> KeyInput {
+    KeyInput {
+        event: KeyEvent {
+            code,
+            pressed: true,
+            modifiers: Modifiers {
+                shift,
+                ..Modifiers::NONE
+            },
+        },
+        key,
+    }
+}
+
+/// 依次处理按键，返回最后的视图范围
+#[cfg(test)]
+fn drive(writer: &mut Writer, inputs: &[KeyInput]) -> core::ops::Range<usize> {
+    let mut buf = [0u8; 16];
+    let mut editor = LineEditor::new(&mut buf);
+    for input in inputs {
+        handle_input(&mut editor, input, writer);
+    }
+    writer.visible_lines()
+}
+
+/// 写入 50 行，历史中有 50 行（前 24 行是原来屏幕上的空行）
+#[cfg(test)]
+fn populated_writer() -> TestWriter {
+    use core::fmt::Write;
+
+    let mut writer = TestWriter::with_scrollback(100);
+    for line in 0..50 {
+        writeln!(writer, "line {}", line).unwrap();
+    }
+    writer
+}
+
+#[test_case]
+fn test_page_keys_scroll_by_screenful() {
+    let mut writer = populated_writer();
+    let page_up = press(KeyCode::PageUp, false, Some(RawKey(KeyCode::PageUp)));
+    let page_down = press(KeyCode::PageDown, false, Some(RawKey(KeyCode::PageDown)));
+    assert_eq!(drive(&mut writer, &[page_up]), 25..50);
+    // 按住 PageUp：重复的按下事件继续翻页，最多到最旧的历史
+    assert_eq!(drive(&mut writer, &[page_up, page_up]), 0..25);
+    assert_eq!(drive(&mut writer, &[page_down]), 25..50);
+    assert_eq!(drive(&mut writer, &[page_down, page_down]), 50..75);
+}
+
+#[test_case]
+fn test_shift_arrows_scroll_by_line() {
+    let mut writer = populated_writer();
+    let shift_up = press(KeyCode::ArrowUp, true, Some(RawKey(KeyCode::ArrowUp)));
+    let shift_down = press(KeyCode::ArrowDown, true, Some(RawKey(KeyCode::ArrowDown)));
+    let up = press(KeyCode::ArrowUp, false, Some(RawKey(KeyCode::ArrowUp)));
+    assert_eq!(drive(&mut writer, &[shift_up, shift_up, shift_up]), 47..72);
+    assert_eq!(drive(&mut writer, &[shift_down]), 48..73);
+    // 没有 Shift 的方向键不滚动
+    assert_eq!(drive(&mut writer, &[up]), 48..73);
+}
+
+#[test_case]
+fn test_page_up_without_history() {
+    let mut writer = TestWriter::with_scrollback(100);
+    let page_up = press(KeyCode::PageUp, false, Some(RawKey(KeyCode::PageUp)));
+    assert_eq!(drive(&mut writer, &[page_up]), 0..25);
+    assert_eq!(writer.scroll_offset(), 0);
+}
+
+#[test_case]
+fn test_printable_key_snaps_back() {
+    let mut writer = populated_writer();
+    let page_up = press(KeyCode::PageUp, false, Some(RawKey(KeyCode::PageUp)));
+    let a = press(KeyCode::A, false, Some(Unicode('a')));
+    assert_eq!(drive(&mut writer, &[page_up, a]), 50..75);
+    assert_eq!(writer.read_visible_char(BUFFER_HEIGHT - 1, 0).0, b'a');
+
+    // 关闭自动回到底部后按键只写进实时画面，视图不动
+    writer.set_snap_on_output(false);
+    assert_eq!(drive(&mut writer, &[page_up, a]), 25..50);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, b'a');
+}
+
+#[cfg(test)]
+fn ctrl(code: KeyCode, character: char) -> KeyInput {
+    let mut input = press(code, false, Some(Unicode(character)));
+    input.event.modifiers.ctrl = true;
+    input
+}
+
+/// 与 read_line_from 相同的流程，但回显写到测试用的 Writer 上
+#[cfg(test)]
+fn read_line_on<'a>(
+    writer: &mut Writer,
+    inputs: &[KeyInput],
+    buf: &'a mut [u8],
+) -> Option<&'a str> {
+    let mut editor = LineEditor::new(buf);
+    for input in inputs {
+        match handle_input(&mut editor, input, writer) {
+            Some(LineEnd::Submitted) => break,
+            Some(LineEnd::Cancelled) => return None,
+            None => {}
+        }
+    }
+    Some(editor.into_line())
+}
+
+#[test_case]
+fn test_ctrl_c_cancels_read_line() {
+    let mut writer = TestWriter::new();
+    let mut buf = [0u8; 16];
+    let inputs = [
+        press(KeyCode::A, false, Some(Unicode('a'))),
+        ctrl(KeyCode::C, 'c'),
+        press(KeyCode::B, false, Some(Unicode('b'))),
+        press(KeyCode::Return, false, Some(Unicode('\n'))),
+    ];
+    assert_eq!(read_line_on(&mut writer, &inputs, &mut buf), None);
+    // Ctrl+C 本身没有作为字符回显
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0).0, b'a');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 1).0, b'^');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 2).0, b'C');
+    assert_eq!(bottom_row(&writer, 1), b" ");
+}
+
+#[test_case]
+fn test_ctrl_u_kills_line() {
+    let mut writer = TestWriter::new();
+    writer.write_string("> ");
+    let mut buf = [0u8; 16];
+    let inputs = [
+        press(KeyCode::A, false, Some(Unicode('a'))),
+        press(KeyCode::B, false, Some(Unicode('b'))),
+        ctrl(KeyCode::U, 'u'),
+        press(KeyCode::C, false, Some(Unicode('c'))),
+        press(KeyCode::Return, false, Some(Unicode('\n'))),
+    ];
+    assert_eq!(read_line_on(&mut writer, &inputs, &mut buf), Some("c"));
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 2).0, b'c');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 3).0, b' ');
+}
+
+#[test_case]
+fn test_ctrl_l_clears_and_keeps_input() {
+    let mut writer = TestWriter::new();
+    writer.write_string("old output\n> ");
+    let mut buf = [0u8; 16];
+    let inputs = [
+        press(KeyCode::X, false, Some(Unicode('x'))),
+        ctrl(KeyCode::L, 'l'),
+        press(KeyCode::Y, false, Some(Unicode('y'))),
+    ];
+    assert_eq!(read_line_on(&mut writer, &inputs, &mut buf), Some("xy"));
+    assert_eq!(bottom_row(&writer, 3), b"xy ");
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0).0, b' ');
+}
+
+/// 模拟的输入序列：Key 在当前节拍内到达，Tick 表示一次等待后节拍数加一
+#[cfg(test)]
+enum Step {
+    Key(Input),
+    Tick,
+}
+
+#[cfg(test)]
+struct ScriptedKeys<'a> {
+    steps: core::slice::Iter<'a, Step>,
+    ticks: u64,
+}
+
+#[cfg(test)]
+impl TimedKeys for ScriptedKeys<'_> {
+    fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    fn poll(&mut self) -> Option<Input> {
+        match self.steps.as_slice().first()? {
+            Step::Key(input) => {
+                self.steps.next();
+                Some(*input)
+            }
+            Step::Tick => None,
+        }
+    }
+
+    /// 脚本用完以后每次等待同样经过一个节拍
+    fn wait(&mut self) {
+        self.steps.next();
+        self.ticks += 1;
+    }
+}
+
+#[test_case]
+fn test_read_line_timeout() {
+    let a = Input::Key(press(KeyCode::A, false, Some(Unicode('a'))));
+    let b = Input::Serial(b'b');
+    let enter = Input::Key(press(KeyCode::Return, false, Some(Unicode('\n'))));
+    let run = |steps: &[Step], timeout_ticks: u64, buf: &mut [u8]| {
+        let mut keys = ScriptedKeys {
+            steps: steps.iter(),
+            ticks: 1000,
+        };
+        let mut writer = TestWriter::new();
+        let mut editor = LineEditor::new(buf);
+        let done = read_line_before(&mut keys, &mut editor, timeout_ticks, |editor, input| {
+            handle_console_input(editor, input, &mut writer)
+        });
+        (done.then(|| editor.into_line().len()), keys.ticks - 1000)
+    };
+    let mut buf = [0u8; 16];
+
+    // 在期限之前完成一行
+    let steps = [
+        Step::Tick,
+        Step::Key(a),
+        Step::Tick,
+        Step::Key(b),
+        Step::Key(enter),
+    ];
+    assert_eq!(run(&steps, 3, &mut buf), (Some(2), 2));
+    assert_eq!(&buf[..2], b"ab");
+    // 回车恰好在期限当拍到达
+    assert_eq!(run(&steps, 2, &mut buf), (Some(2), 2));
+    // 回车晚了一拍：半行被丢弃
+    let late = [Step::Key(a), Step::Tick, Step::Tick, Step::Key(enter)];
+    assert_eq!(run(&late, 1, &mut buf), (None, 1));
+    // 没有任何输入时恰好等待 timeout_ticks 个节拍
+    assert_eq!(run(&[], 5, &mut buf), (None, 5));
+    // 期限为 0 时仍然先取完已经到达的按键
+    assert_eq!(
+        run(&[Step::Key(a), Step::Key(enter)], 0, &mut buf),
+        (Some(1), 0)
+    );
+    assert_eq!(run(&[Step::Key(a), Step::Tick], 0, &mut buf), (None, 0));
+    // Ctrl+C 取消
+    let cancel = [
+        Step::Key(a),
+        Step::Key(Input::Key(ctrl(KeyCode::C, 'c'))),
+        Step::Key(enter),
+    ];
+    assert_eq!(run(&cancel, 10, &mut buf), (None, 0));
+    // 串口的 0x03 同样取消
+    let cancel = [
+        Step::Key(a),
+        Step::Key(Input::Serial(0x03)),
+        Step::Key(enter),
+    ];
+    assert_eq!(run(&cancel, 10, &mut buf), (None, 0));
+}
+
+#[test_case]
+fn test_unbound_chord_passes_through() {
+    let mut writer = TestWriter::new();
+    let mut buf = [0u8; 16];
+    // Ctrl+A 没有绑定，照常作为字符交给行编辑
+    let inputs = [ctrl(KeyCode::A, 'a')];
+    assert_eq!(read_line_on(&mut writer, &inputs, &mut buf), Some("a"));
+}
+
+#[cfg(test)]
+fn arrow(code: KeyCode) -> KeyInput {
+    press(code, false, Some(RawKey(code)))
+}
+
+#[cfg(test)]
+fn type_str(editor: &mut LineEditor, text: &str, writer: &mut Writer) {
+    for character in text.chars() {
+        handle_input(
+            editor,
+            &press(KeyCode::A, false, Some(Unicode(character))),
+            writer,
+        );
+    }
+}
+
+#[cfg(test)]
+fn history_of(lines: &[&str]) -> History {
+    let mut history = History::new(8);
+    for line in lines {
+        history.push(line);
+    }
+    history
+}
+
+#[test_case]
+fn test_history_up_down_restores_draft() {
+    let mut writer = TestWriter::new();
+    writer.write_string("> ");
+    let mut history = history_of(&["ls", "echo hi"]);
+    let mut buf = [0u8; 32];
+    let mut editor = LineEditor::with_history(&mut buf, &mut history);
+    type_str(&mut editor, "ec", &mut writer);
+
+    let up = arrow(KeyCode::ArrowUp);
+    let down = arrow(KeyCode::ArrowDown);
+    handle_input(&mut editor, &up, &mut writer);
+    assert_eq!(editor.line(), "echo hi");
+    assert_eq!(bottom_row(&writer, 10), b"> echo hi ");
+    handle_input(&mut editor, &up, &mut writer);
+    // 较短的行替换较长的行，多出来的字符被擦掉
+    assert_eq!(editor.line(), "ls");
+    assert_eq!(bottom_row(&writer, 10), b"> ls      ");
+    // 已经是最旧的一条
+    handle_input(&mut editor, &up, &mut writer);
+    assert_eq!(editor.line(), "ls");
+
+    handle_input(&mut editor, &down, &mut writer);
+    assert_eq!(editor.line(), "echo hi");
+    handle_input(&mut editor, &down, &mut writer);
+    assert_eq!(editor.line(), "ec");
+    assert_eq!(bottom_row(&writer, 10), b"> ec      ");
+    // 不在历史中时下方向键什么也不做
+    handle_input(&mut editor, &down, &mut writer);
+    assert_eq!(editor.line(), "ec");
+}
+
+#[test_case]
+fn test_edited_recall_is_submitted_and_recorded() {
+    let mut writer = TestWriter::new();
+    let mut history = history_of(&["echo a"]);
+    let mut buf = [0u8; 32];
+    let mut editor = LineEditor::with_history(&mut buf, &mut history);
+    handle_input(&mut editor, &arrow(KeyCode::ArrowUp), &mut writer);
+    // 在找回的行上继续编辑后再翻找，编辑的内容被丢弃
+    type_str(&mut editor, "bc", &mut writer);
+    assert_eq!(editor.line(), "echo abc");
+    handle_input(&mut editor, &arrow(KeyCode::ArrowDown), &mut writer);
+    assert_eq!(editor.line(), "");
+    handle_input(&mut editor, &arrow(KeyCode::ArrowUp), &mut writer);
+    type_str(&mut editor, "b\n", &mut writer);
+    assert_eq!(editor.into_line(), "echo ab");
+    assert_eq!(history.get(0), Some("echo ab"));
+    assert_eq!(history.get(1), Some("echo a"));
+}
+
+#[test_case]
+fn test_recall_wider_than_screen() {
+    use crate::vga_buffer::BUFFER_WIDTH;
+    use alloc::string::String;
+
+    let mut writer = TestWriter::new();
+    writer.write_string("> ");
+    let long: String = (0..BUFFER_WIDTH + 10).map(|_| 'x').collect();
+    let mut history = history_of(&[&long]);
+    let mut buf = [0u8; 128];
+    let mut editor = LineEditor::with_history(&mut buf, &mut history);
+
+    handle_input(&mut editor, &arrow(KeyCode::ArrowUp), &mut writer);
+    assert_eq!(editor.line(), long);
+    // 折行到下一行：上一行是提示符加 78 个字符，最后一行是剩下的 12 个
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0).0, b'>');
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 2, BUFFER_WIDTH - 1).0,
+        b'x'
+    );
+    assert_eq!(bottom_row(&writer, 13), b"xxxxxxxxxxxx ");
+
+    // 回到空的草稿时跨行擦除，提示符回到最后一行
+    handle_input(&mut editor, &arrow(KeyCode::ArrowDown), &mut writer);
+    assert_eq!(editor.line(), "");
+    assert_eq!(bottom_row(&writer, 3), b">  ");
+
+    // 比缓冲区长的条目被截断
+    let mut small = [0u8; 4];
+    let mut editor = LineEditor::with_history(&mut small, &mut history);
+    handle_input(&mut editor, &arrow(KeyCode::ArrowUp), &mut writer);
+    assert_eq!(editor.line(), "xxxx");
+}
+
+#[test_case]
+fn test_serial_bytes_edit_like_keys() {
+    let mut writer = TestWriter::new();
+    writer.write_string("> ");
+    let mut history = history_of(&["ls"]);
+    let mut buf = [0u8; 16];
+    let mut editor = LineEditor::with_history(&mut buf, &mut history);
+    let mut feed = |editor: &mut LineEditor, inputs: &[Input]| {
+        inputs
+            .iter()
+            .filter_map(|input| handle_console_input(editor, input, &mut writer))
+            .last()
+    };
+
+    // 键盘输入 "ax"，串口 DEL 删掉 x，串口 Ctrl+U 删掉整行，再交替输入 "ok"
+    let inputs = [
+        Input::Key(press(KeyCode::A, false, Some(Unicode('a')))),
+        Input::Key(press(KeyCode::X, false, Some(Unicode('x')))),
+        Input::Serial(0x7f),
+        Input::Serial(0x15),
+        Input::Serial(b'o'),
+        Input::Key(press(KeyCode::K, false, Some(Unicode('k')))),
+    ];
+    assert_eq!(feed(&mut editor, &inputs), None);
+    assert_eq!(editor.line(), "ok");
+    // 串口的回车提交这一行，同样记录到历史中
+    assert_eq!(
+        feed(&mut editor, &[Input::Serial(b'\r')]),
+        Some(LineEnd::Submitted)
+    );
+    assert_eq!(editor.into_line(), "ok");
+    assert_eq!(history.get(0), Some("ok"));
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 2).0, b'o');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 3).0, b'k');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 4).0, b' ');
+}
@@ -0,0 +1,183 @@
+//! 通过串口导出屏幕内容（文本截图）
+//! 只有串口线连着的真机上报告问题时，用它把 VGA 屏幕上的内容原样带出来：
+//!
+//! ```text
+//! -----BEGIN SCREEN-----
+//! 每行一行文本，去掉行尾的空格
+//! -----END SCREEN-----
+//! -----BEGIN ATTRIBUTES-----
+//! 每行一行属性，游程编码：07*75 1f*5 表示 75 个 0x07 之后是 5 个 0x1f
+//! -----END ATTRIBUTES-----
+//! ```
+//!
+//! 属性一节是可选的。文本按 CP437 转换成 UTF-8，0 字节显示为空格，其他控制字节显示为 '.'，
+//! 所以只有这些字节无法从截图中还原。
+//!
+//! 先在锁内把画面复制成快照，再在锁外写串口，并发的输出不会让导出的画面前后不一致
+use crate::serial::SERIAL1;
+use crate::vga_buffer::{cp437, ColorCode, ScreenRow, Snapshot, BUFFER_WIDTH, WRITER};
+use core::fmt::{self, Write};
+use x86_64::instructions::interrupts;
+
+pub const BEGIN_SCREEN: &str = "-----BEGIN SCREEN-----";
+pub const END_SCREEN: &str = "-----END SCREEN-----";
+pub const BEGIN_ATTRIBUTES: &str = "-----BEGIN ATTRIBUTES-----";
+pub const END_ATTRIBUTES: &str = "-----END ATTRIBUTES-----";
+
+/// 一个单元格在文本一节中的字符
+fn cell_char(byte: u8) -> char {
+    match byte {
+        0 => ' ',
+        _ => cp437::to_char(byte).unwrap_or('.'),
+    }
+}
+
+/// 一行的文本，去掉行尾的空格
+fn write_text_row(out: &mut impl Write, row: &ScreenRow) -> fmt::Result {
+    let len = row
+        .iter()
+        .rposition(|cell| cell_char(cell.ascii_character()) != ' ')
+        .map_or(0, |last| last + 1);
+    for cell in &row[..len] {
+        out.write_char(cell_char(cell.ascii_character()))?;
+    }
+    out.write_char('\n')
+}
+
+/// 一行的属性，相邻的相同属性合并成 "属性*个数"，之间用空格分隔
+fn write_attribute_row(out: &mut impl Write, row: &ScreenRow) -> fmt::Result {
+    let mut cells = row.iter().map(|cell| cell.color_code().attribute());
+    let mut current = cells.next();
+    let mut count = 1;
+    let mut first = true;
+    while let Some(attribute) = current {
+        let next = cells.next();
+        if next == Some(attribute) {
+            count += 1;
+        } else {
+            if !first {
+                out.write_char(' ')?;
+            }
+            write!(out, "{:02x}*{}", attribute, count)?;
+            first = false;
+            count = 1;
+        }
+        current = next;
+    }
+    out.write_char('\n')
+}
+
+/// 解析一行属性，格式错误或者总数不是一行的宽度时返回 None
+pub fn parse_attribute_row(line: &str) -> Option<[ColorCode; BUFFER_WIDTH]> {
+    let mut row = [ColorCode::from_attribute(0); BUFFER_WIDTH];
+    let mut col = 0;
+    for run in line.split_whitespace() {
+        let (attribute, count) = run.split_once('*')?;
+        let attribute = u8::from_str_radix(attribute, 16).ok()?;
+        let count: usize = count.parse().ok()?;
+        let end = col + count;
+        row.get_mut(col..end)?
+            .fill(ColorCode::from_attribute(attribute));
+        col = end;
+    }
+    (col == BUFFER_WIDTH).then_some(row)
+}
+
+/// 把画面按导出的格式写出，attributes 为 true 时附上属性一节
+pub fn write_screen(out: &mut impl Write, screen: &[ScreenRow], attributes: bool) -> fmt::Result {
+    writeln!(out, "{}", BEGIN_SCREEN)?;
+    for row in screen {
+        write_text_row(out, row)?;
+    }
+    writeln!(out, "{}", END_SCREEN)?;
+    if attributes {
+        writeln!(out, "{}", BEGIN_ATTRIBUTES)?;
+        for row in screen {
+            write_attribute_row(out, row)?;
+        }
+        writeln!(out, "{}", END_ATTRIBUTES)?;
+    }
+    Ok(())
+}
+
+/// 把快照写到串口。不要在持有 WRITER 的锁时调用：写串口很慢，期间屏幕输出都要等待
+pub fn dump_snapshot_to_serial(snapshot: &Snapshot, attributes: bool) {
+    interrupts::without_interrupts(|| {
+        write_screen(&mut *SERIAL1.lock(), snapshot.screen(), attributes)
+            .expect("Printing to serial failed");
+    });
+}
+
+/// 复制实时画面并通过串口导出，回滚中导出的也是实时画面
+pub fn dump_screen_to_serial(attributes: bool) {
+    let snapshot = interrupts::without_interrupts(|| WRITER.lock().snapshot());
+    dump_snapshot_to_serial(&snapshot, attributes);
+}
+
+#[cfg(test)]
+use crate::vga_buffer::{Color, TestWriter, BUFFER_HEIGHT};
+#[cfg(test)]
+use alloc::string::String;
+
+#[test_case]
+fn test_rows_are_trimmed() {
+    let mut writer = TestWriter::new();
+    writer.write_string("  hi  \n\u{e9}");
+    let blank = ColorCode::new(Color::Yellow, Color::Black);
+    writer.put_char(BUFFER_HEIGHT - 1, 3, 0, blank);
+    writer.put_char(BUFFER_HEIGHT - 1, 4, 0x01, blank);
+    let mut out = String::new();
+    write_screen(&mut out, writer.snapshot().screen(), false).unwrap();
+    let lines: alloc::vec::Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), BUFFER_HEIGHT + 2);
+    assert_eq!(lines[0], BEGIN_SCREEN);
+    assert_eq!(lines[1], "");
+    // 行首的空格保留，行尾的空格去掉；0 字节当作空格，其他控制字节显示为 '.'
+    assert_eq!(lines[BUFFER_HEIGHT - 1], "  hi");
+    assert_eq!(lines[BUFFER_HEIGHT], "\u{e9}   .");
+    assert_eq!(lines[BUFFER_HEIGHT + 1], END_SCREEN);
+}
+
+#[test_case]
+fn test_attribute_rle_round_trip() {
+    let mut writer = TestWriter::new();
+    let red = ColorCode::new(Color::White, Color::Red);
+    writer.write_string("plain ");
+    writer.set_color(Color::White, Color::Red);
+    writer.write_string("alert");
+    let row = BUFFER_HEIGHT - 1;
+    writer.put_char(row, BUFFER_WIDTH - 1, b'!', red);
+    let snapshot = writer.snapshot();
+
+    let mut out = String::new();
+    write_attribute_row(&mut out, &snapshot.screen()[row]).unwrap();
+    assert_eq!(out, "0e*6 4f*5 0e*68 4f*1\n");
+    let decoded = parse_attribute_row(out.trim_end()).unwrap();
+    for (col, &color_code) in decoded.iter().enumerate() {
+        assert_eq!(color_code, snapshot.screen()[row][col].color_code());
+    }
+
+    // 格式错误或者总数不是一行
+    assert_eq!(parse_attribute_row("0e*79"), None);
+    assert_eq!(parse_attribute_row("0e*81"), None);
+    assert_eq!(parse_attribute_row("0e80"), None);
+}
+
+#[test_case]
+fn test_dump_uses_snapshot_copy() {
+    let mut writer = TestWriter::new();
+    writer.write_string("before");
+    writer.toggle_soft_cursor();
+    let snapshot = writer.snapshot();
+    // 复制之后的输出不会出现在导出的画面中
+    writer.write_string("\nafter");
+    let mut out = String::new();
+    write_screen(&mut out, snapshot.screen(), true).unwrap();
+    let lines: alloc::vec::Vec<&str> = out.lines().collect();
+    assert_eq!(lines[BUFFER_HEIGHT], "before");
+    assert!(!out.contains("after"));
+    // 反色的软件光标不在快照中
+    assert_eq!(lines[BUFFER_HEIGHT + 2], BEGIN_ATTRIBUTES);
+    assert_eq!(lines[2 * BUFFER_HEIGHT + 2], "0e*80");
+    assert_eq!(lines[2 * BUFFER_HEIGHT + 3], END_ATTRIBUTES);
+}
@@ -0,0 +1,404 @@
+//! 输出目标
+//! print! 和 eprint! 只格式化一次，把得到的每一段文字依次交给登记的每个 OutputSink。
+//! 内置两个目标：屏幕（WRITER）和串口（COM1），它们按启动配置的 console 选项决定是否真正输出。
+//! 其他代码可以登记自己的目标，例如测试中捕获输出的缓冲区、帧缓冲区上的控制台，不需要修改 print! 的实现
+//!
+//! 每个目标有自己的 min_level：kerror! 等分级的日志只交给 min_level 允许的目标，
+//! 例如屏幕显示所有消息、串口只收警告和错误。没有级别的 print! 只交给 unleveled 为 true 的目标。
+//! 两者都可以在运行时修改，下一条消息就按新的设置分发。
+//! 启动配置的 loglevel 先决定一条日志是否产生，产生的日志再按各个目标的 min_level 分发
+//!
+//! 内置的两个目标是否输出也可以在运行时用 set_output 切换，覆盖启动配置的 console 选项，
+//! 例如 CI 中只写串口、真机上只写屏幕。
+//!
+//! 登记表是固定大小的数组，放在一把锁中。分发时持有这把锁并关闭中断，
+//! 所以 write_bytes 中不能再打印，也不能等待中断
+use crate::faultinject;
+use crate::log::Level;
+use crate::serial::SERIAL1;
+use crate::vga_buffer::{self, ColorCode};
+use alloc::boxed::Box;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use uart_16550::SerialPort;
+use x86_64::instructions::interrupts;
+
+/// 最多登记的输出目标数量，包括内置的两个
+pub const MAX_SINKS: usize = 8;
+
+pub trait OutputSink: Send {
+    fn write_bytes(&mut self, bytes: &[u8]);
+
+    /// eprint! 的输出，默认与普通输出相同
+    fn write_error_bytes(&mut self, bytes: &[u8]) {
+        self.write_bytes(bytes);
+    }
+
+    /// writeln_colored! 的输出，默认忽略颜色
+    fn write_colored_bytes(&mut self, bytes: &[u8], color_code: ColorCode) {
+        let _ = color_code;
+        self.write_bytes(bytes);
+    }
+}
+
+/// 一条输出的颜色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Normal,
+    /// eprint! 和警告以上的日志
+    Error,
+    /// writeln_colored! 指定的颜色
+    Colored(ColorCode),
+}
+
+impl OutputSink for SerialPort {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.send(byte);
+        }
+    }
+}
+
+/// 内置目标的输出方式，与启动配置的 console 选项相同
+pub use crate::config::Console as OutputMode;
+
+/// set_output 设置的输出方式，NOT_SET 表示按启动配置
+static OUTPUT_MODE: AtomicU8 = AtomicU8::new(NOT_SET);
+const NOT_SET: u8 = 0;
+
+/// 切换内置的屏幕和串口目标是否输出，下一条消息生效，之后不再使用启动配置的 console 选项。
+/// 登记的其他目标不受影响；控制台完全初始化（Full）之前的输出不经过输出目标，也不受影响
+pub fn set_output(mode: OutputMode) {
+    let value = match mode {
+        OutputMode::Vga => 1,
+        OutputMode::Serial => 2,
+        OutputMode::Both => 3,
+    };
+    OUTPUT_MODE.store(value, Ordering::Relaxed);
+}
+
+/// 当前的输出方式：set_output 设置过的，或者启动配置的
+pub fn output() -> OutputMode {
+    match OUTPUT_MODE.load(Ordering::Relaxed) {
+        1 => OutputMode::Vga,
+        2 => OutputMode::Serial,
+        3 => OutputMode::Both,
+        _ => crate::config::get().console,
+    }
+}
+
+/// 登记表中的位置，unregister 时使用
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkId(usize);
+
+pub const VGA_SINK: SinkId = SinkId(0);
+pub const SERIAL_SINK: SinkId = SinkId(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkError {
+    /// 已经登记了 MAX_SINKS 个目标
+    Full,
+}
+
+/// 内置的屏幕输出，见 vga_buffer::write_screen
+struct VgaSink;
+
+impl OutputSink for VgaSink {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        vga_buffer::write_screen(bytes, Style::Normal);
+    }
+
+    fn write_error_bytes(&mut self, bytes: &[u8]) {
+        vga_buffer::write_screen(bytes, Style::Error);
+    }
+
+    fn write_colored_bytes(&mut self, bytes: &[u8], color_code: ColorCode) {
+        vga_buffer::write_screen(bytes, Style::Colored(color_code));
+    }
+}
+
+/// 内置的串口输出，只在启动配置要求（或者没有 VGA）时输出
+struct SerialSink;
+
+impl OutputSink for SerialSink {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if vga_buffer::routes().1 {
+            SERIAL1.lock().write_bytes(bytes);
+        }
+    }
+}
+
+struct Slot {
+    sink: &'static mut dyn OutputSink,
+    /// 接收的最低严重程度，例如 Warn 表示只接收警告和错误
+    min_level: Level,
+    /// 是否接收没有级别的输出（print!、eprint!）
+    unleveled: bool,
+}
+
+impl Slot {
+    /// 新登记的目标接收所有输出
+    fn new(sink: &'static mut dyn OutputSink) -> Self {
+        Slot {
+            sink,
+            min_level: Level::Debug,
+            unleveled: true,
+        }
+    }
+
+    fn accepts(&self, level: Option<Level>) -> bool {
+        match level {
+            Some(level) => level <= self.min_level,
+            None => self.unleveled,
+        }
+    }
+}
+
+type Slots = [Option<Slot>; MAX_SINKS];
+
+lazy_static! {
+    static ref SINKS: Mutex<Slots> = {
+        let mut slots: Slots = [const { None }; MAX_SINKS];
+        // 零大小的类型，Box::new 不会分配内存，堆初始化之前也可以使用
+        slots[VGA_SINK.0] = Some(Slot::new(Box::leak(Box::new(VgaSink))));
+        slots[SERIAL_SINK.0] = Some(Slot::new(Box::leak(Box::new(SerialSink))));
+        Mutex::new(slots)
+    };
+}
+
+/// 登记一个输出目标，它接收所有输出，之后可以用 set_sink_level 和 set_unleveled 调整
+pub fn register(sink: &'static mut dyn OutputSink) -> Result<SinkId, SinkError> {
+    interrupts::without_interrupts(|| {
+        let mut slots = SINKS.lock();
+        let index = slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(SinkError::Full)?;
+        slots[index] = Some(Slot::new(sink));
+        Ok(SinkId(index))
+    })
+}
+
+/// 取消登记并交还输出目标；内置的目标也可以取消，例如完全关闭屏幕输出
+pub fn unregister(id: SinkId) -> Option<&'static mut dyn OutputSink> {
+    interrupts::without_interrupts(|| SINKS.lock()[id.0].take().map(|slot| slot.sink))
+}
+
+/// 修改登记表中的一项，id 没有登记时返回 false
+fn update(id: SinkId, f: impl FnOnce(&mut Slot)) -> bool {
+    interrupts::without_interrupts(|| SINKS.lock()[id.0].as_mut().map(f).is_some())
+}
+
+/// 设置目标接收的最低严重程度，对下一条消息生效；id 没有登记时返回 false
+pub fn set_sink_level(id: SinkId, level: Level) -> bool {
+    update(id, |slot| slot.min_level = level)
+}
+
+/// 设置目标是否接收没有级别的 print! 输出；id 没有登记时返回 false
+pub fn set_unleveled(id: SinkId, unleveled: bool) -> bool {
+    update(id, |slot| slot.unleveled = unleveled)
+}
+
+/// 把格式化得到的每一段交给接收这个级别的所有目标
+struct Fanout<'a> {
+    slots: &'a mut Slots,
+    level: Option<Level>,
+    style: Style,
+    /// 不交给这个目标
+    skip: Option<SinkId>,
+}
+
+impl fmt::Write for Fanout<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            let Some(slot) = slot else { continue };
+            if Some(SinkId(index)) == self.skip || !slot.accepts(self.level) {
+                continue;
+            }
+            match self.style {
+                Style::Normal => slot.sink.write_bytes(s.as_bytes()),
+                Style::Error => slot.sink.write_error_bytes(s.as_bytes()),
+                Style::Colored(color_code) => {
+                    slot.sink.write_colored_bytes(s.as_bytes(), color_code)
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// print!、eprint!、writeln_colored!（level 为 None）和分级日志的实现，style 决定输出的颜色
+pub(crate) fn dispatch(args: fmt::Arguments, level: Option<Level>, style: Style) {
+    dispatch_except(None, args, level, style);
+}
+
+/// 与 dispatch 相同，但不交给 skip，用于已经直接画在屏幕上的内容
+pub(crate) fn dispatch_except(
+    skip: Option<SinkId>,
+    args: fmt::Arguments,
+    level: Option<Level>,
+    style: Style,
+) {
+    if faultinject::should_fail(faultinject::SINK_DISPATCH) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    // 持有锁期间关闭中断，否则中断处理函数中的 println! 会在同一把锁上死锁
+    interrupts::without_interrupts(|| {
+        let mut slots = SINKS.lock();
+        let _ = Fanout {
+            slots: &mut slots,
+            level,
+            style,
+            skip,
+        }
+        .write_fmt(args);
+    });
+}
+
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// 没有交给任何目标就被丢弃的输出条数
+pub fn dropped_outputs() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// 见 vga_buffer::recover_from_formatting_panic
+///
+/// # Safety
+/// 持有锁的执行流不会再使用输出目标表
+pub(crate) unsafe fn force_unlock() {
+    unsafe { SINKS.force_unlock() };
+}
+
+/// 记录写入的字节和是否是错误输出
+#[cfg(test)]
+pub(crate) struct Recording(pub(crate) alloc::vec::Vec<(u8, bool)>);
+
+#[cfg(test)]
+impl OutputSink for Recording {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.0.extend(bytes.iter().map(|&byte| (byte, false)));
+    }
+
+    fn write_error_bytes(&mut self, bytes: &[u8]) {
+        self.0.extend(bytes.iter().map(|&byte| (byte, true)));
+    }
+}
+
+#[test_case]
+fn test_registered_sink_receives_output() {
+    use alloc::vec::Vec;
+
+    let id = register(Box::leak(Box::new(Recording(Vec::new())))).unwrap();
+    crate::print!("to {}", "sinks");
+    crate::eprintln!("!");
+    let sink = unregister(id).unwrap();
+    // 交还的就是登记时泄漏的 Box，收回后释放
+    let recording = unsafe { Box::from_raw(sink as *mut dyn OutputSink as *mut Recording) };
+    let expected: Vec<(u8, bool)> = b"to sinks"
+        .iter()
+        .map(|&byte| (byte, false))
+        .chain(b"!\n".iter().map(|&byte| (byte, true)))
+        .collect();
+    assert_eq!(recording.0, expected);
+
+    // 同一个位置不能取消两次
+    assert!(unregister(id).is_none());
+}
+
+#[test_case]
+fn test_injected_dispatch_failure_is_counted() {
+    use alloc::vec::Vec;
+
+    let id = register(Box::leak(Box::new(Recording(Vec::new())))).unwrap();
+    let before = dropped_outputs();
+    faultinject::set(faultinject::SINK_DISPATCH, faultinject::Mode::Nth(2));
+    crate::print!("a");
+    crate::print!("b");
+    crate::print!("c");
+    faultinject::clear(faultinject::SINK_DISPATCH);
+    let sink = unregister(id).unwrap();
+    let recording = unsafe { Box::from_raw(sink as *mut dyn OutputSink as *mut Recording) };
+    assert_eq!(recording.0, [(b'a', false), (b'c', false)]);
+    assert_eq!(dropped_outputs(), before + 1);
+}
+
+/// 丢弃所有输出；零大小，登记时泄漏的 Box 不占用内存
+#[cfg(test)]
+struct Discard;
+
+#[cfg(test)]
+impl OutputSink for Discard {
+    fn write_bytes(&mut self, _bytes: &[u8]) {}
+}
+
+#[test_case]
+fn test_register_until_full() {
+    let mut ids = alloc::vec::Vec::new();
+    while let Ok(id) = register(Box::leak(Box::new(Discard))) {
+        ids.push(id);
+    }
+    // 内置的两个目标占用了前两个位置
+    assert_eq!(ids.len(), MAX_SINKS - 2);
+    assert_eq!(
+        register(Box::leak(Box::new(Discard))).unwrap_err(),
+        SinkError::Full
+    );
+    for id in ids {
+        assert!(unregister(id).is_some());
+    }
+}
+
+/// 收回登记时泄漏的 Recording，把内容转换为字符串
+#[cfg(test)]
+pub(crate) fn take_recording(id: SinkId) -> alloc::string::String {
+    let sink = unregister(id).unwrap();
+    let recording = unsafe { Box::from_raw(sink as *mut dyn OutputSink as *mut Recording) };
+    recording.0.iter().map(|&(byte, _)| byte as char).collect()
+}
+
+#[test_case]
+fn test_per_sink_levels() {
+    use alloc::vec::Vec;
+
+    let everything = register(Box::leak(Box::new(Recording(Vec::new())))).unwrap();
+    let errors = register(Box::leak(Box::new(Recording(Vec::new())))).unwrap();
+    assert!(set_sink_level(errors, Level::Error));
+    assert!(set_unleveled(errors, false));
+
+    dispatch(format_args!("e\n"), Some(Level::Error), Style::Error);
+    dispatch(format_args!("w\n"), Some(Level::Warn), Style::Error);
+    dispatch(format_args!("d\n"), Some(Level::Debug), Style::Normal);
+    crate::println!("p");
+    // 运行时修改，下一条消息立即按新的级别分发
+    assert!(set_sink_level(errors, Level::Warn));
+    dispatch(format_args!("w2\n"), Some(Level::Warn), Style::Error);
+    assert!(set_sink_level(everything, Level::Error));
+    dispatch(format_args!("i\n"), Some(Level::Info), Style::Normal);
+
+    assert_eq!(take_recording(everything), "e\nw\nd\np\nw2\n");
+    assert_eq!(take_recording(errors), "e\nw2\n");
+    assert!(!set_sink_level(errors, Level::Debug));
+}
+
+#[test_case]
+fn test_serial_only_output_leaves_screen_alone() {
+    use crate::vga_buffer::WRITER;
+
+    let saved = output();
+    let screen = || interrupts::without_interrupts(|| WRITER.lock().snapshot());
+    let before = screen();
+    set_output(OutputMode::Serial);
+    assert_eq!(output(), OutputMode::Serial);
+    crate::println!("serial only");
+    crate::eprintln!("serial only error");
+    assert_eq!(screen().screen(), before.screen());
+
+    set_output(OutputMode::Both);
+    crate::println!("both");
+    assert_ne!(screen().screen(), before.screen());
+    set_output(saved);
+}
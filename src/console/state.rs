@@ -0,0 +1,67 @@
+//! 控制台的初始化阶段
+//! print! 和 panic 处理按当前阶段选择最安全的输出方式：
+//!
+//! - Uninit：什么都还没有准备好，直接以固定颜色写 0xb8000，见 vga_buffer::early
+//! - VgaOnly：WRITER 可以使用，但启动配置和输出目标还没有准备好，只写 WRITER
+//! - Full：经过 console::sink 分发到所有输出目标
+//!
+//! 阶段只能前进，不能后退；advance 用一次原子操作完成，多次调用或乱序调用都是安全的
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum ConsoleState {
+    Uninit = 0,
+    VgaOnly = 1,
+    Full = 2,
+}
+
+impl ConsoleState {
+    fn from_u8(value: u8) -> ConsoleState {
+        match value {
+            0 => ConsoleState::Uninit,
+            1 => ConsoleState::VgaOnly,
+            _ => ConsoleState::Full,
+        }
+    }
+}
+
+static STATE: AtomicU8 = AtomicU8::new(ConsoleState::Uninit as u8);
+
+pub fn state() -> ConsoleState {
+    ConsoleState::from_u8(STATE.load(Ordering::Acquire))
+}
+
+/// 前进到 to，已经在 to 或之后的阶段时不变；返回之前的阶段
+pub fn advance(to: ConsoleState) -> ConsoleState {
+    advance_in(&STATE, to)
+}
+
+fn advance_in(state: &AtomicU8, to: ConsoleState) -> ConsoleState {
+    ConsoleState::from_u8(state.fetch_max(to as u8, Ordering::AcqRel))
+}
+
+#[test_case]
+fn test_advance_is_one_way() {
+    let state = AtomicU8::new(ConsoleState::Uninit as u8);
+    assert_eq!(
+        advance_in(&state, ConsoleState::VgaOnly),
+        ConsoleState::Uninit
+    );
+    assert_eq!(
+        advance_in(&state, ConsoleState::Full),
+        ConsoleState::VgaOnly
+    );
+    // 不能退回之前的阶段
+    assert_eq!(advance_in(&state, ConsoleState::Uninit), ConsoleState::Full);
+    assert_eq!(
+        advance_in(&state, ConsoleState::VgaOnly),
+        ConsoleState::Full
+    );
+    assert_eq!(
+        ConsoleState::from_u8(state.load(Ordering::Relaxed)),
+        ConsoleState::Full
+    );
+    // 测试时 init 已经把控制台推进到最后的阶段
+    assert_eq!(self::state(), ConsoleState::Full);
+}
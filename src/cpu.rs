@@ -0,0 +1,232 @@
+//! CPU 编号和特性
+//! 每个 CPU 用本地 APIC id 区分，再按登记的顺序编号：BSP 是 0，AP 由 smp::init 在启动前依次登记。
+//! 编号用于区分多个 CPU 交错的输出，见 vga_buffer 的 [cpuN] 前缀
+//!
+//! 特性由 CPUID.01H 的 ECX/EDX 得到，第一次查询后缓存起来（虚拟机中 CPUID 会引起 VM exit，代价较高），
+//! 各个 CPU 的特性假定相同
+use core::arch::x86_64::{__cpuid, CpuidResult};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+/// 最多支持的 CPU 数量，超出的 AP 不会被启动
+pub const MAX_CPUS: usize = 16;
+
+/// 第 n 项是编号为 n 的 CPU 的 APIC id，前 REGISTERED 项有效
+static APIC_IDS: [AtomicU8; MAX_CPUS] = [const { AtomicU8::new(0) }; MAX_CPUS];
+static REGISTERED: AtomicUsize = AtomicUsize::new(1);
+/// 已经开始运行内核代码的 CPU 数量，包括 BSP
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+/// 当前 CPU 的初始 APIC id（CPUID.01H:EBX[31:24]），不需要访问 APIC 的寄存器
+pub fn apic_id() -> u8 {
+    (__cpuid(1).ebx >> 24) as u8
+}
+
+/// 当前 CPU 的编号，没有登记过的 CPU（以及 smp::init 之前的 BSP）返回 0
+pub fn id() -> usize {
+    let apic_id = apic_id();
+    (0..REGISTERED.load(Ordering::Acquire))
+        .find(|&index| APIC_IDS[index].load(Ordering::Relaxed) == apic_id)
+        .unwrap_or(0)
+}
+
+/// 登记 BSP，smp::init 开始时调用
+pub(crate) fn register_bsp(apic_id: u8) {
+    APIC_IDS[0].store(apic_id, Ordering::Relaxed);
+}
+
+/// 为即将启动的 AP 分配编号，已满时返回 None
+pub(crate) fn register(apic_id: u8) -> Option<usize> {
+    let index = REGISTERED.load(Ordering::Relaxed);
+    if index >= MAX_CPUS {
+        return None;
+    }
+    APIC_IDS[index].store(apic_id, Ordering::Relaxed);
+    REGISTERED.store(index + 1, Ordering::Release);
+    Some(index)
+}
+
+/// AP 进入内核后调用
+pub(crate) fn report_online() {
+    ONLINE.fetch_add(1, Ordering::AcqRel);
+}
+
+/// 在线的 CPU 数量
+pub fn online() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// 内核关心的几个 CPU 特性
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// 时间戳计数器，RDTSC
+    Tsc,
+    /// 本地 APIC
+    Apic,
+    Sse,
+    Sse2,
+    Sse3,
+    Rdrand,
+    /// 运行在虚拟机中
+    Hypervisor,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 7] = [
+        Feature::Tsc,
+        Feature::Apic,
+        Feature::Sse,
+        Feature::Sse2,
+        Feature::Sse3,
+        Feature::Rdrand,
+        Feature::Hypervisor,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Tsc => "tsc",
+            Feature::Apic => "apic",
+            Feature::Sse => "sse",
+            Feature::Sse2 => "sse2",
+            Feature::Sse3 => "sse3",
+            Feature::Rdrand => "rdrand",
+            Feature::Hypervisor => "hypervisor",
+        }
+    }
+
+    /// 在 CPUID.01H 中的位置：(在 ECX 中, 位)
+    fn location(self) -> (bool, u32) {
+        match self {
+            Feature::Tsc => (false, 4),
+            Feature::Apic => (false, 9),
+            Feature::Sse => (false, 25),
+            Feature::Sse2 => (false, 26),
+            Feature::Sse3 => (true, 0),
+            Feature::Rdrand => (true, 30),
+            Feature::Hypervisor => (true, 31),
+        }
+    }
+}
+
+/// 一组特性，第 n 位对应 Feature::ALL[n]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features(u8);
+
+impl Features {
+    /// 从 CPUID.01H 的结果中取出特性
+    pub fn decode(leaf1: CpuidResult) -> Features {
+        let mut bits = 0;
+        for (index, feature) in Feature::ALL.into_iter().enumerate() {
+            let (in_ecx, bit) = feature.location();
+            let register = if in_ecx { leaf1.ecx } else { leaf1.edx };
+            if register & (1 << bit) != 0 {
+                bits |= 1 << index;
+            }
+        }
+        Features(bits)
+    }
+
+    pub fn contains(self, feature: Feature) -> bool {
+        let index = Feature::ALL.iter().position(|&f| f == feature).unwrap_or(0);
+        self.0 & (1 << index) != 0
+    }
+}
+
+/// 空格分隔的特性名
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for feature in Feature::ALL.into_iter().filter(|&f| self.contains(f)) {
+            if !first {
+                f.write_str(" ")?;
+            }
+            f.write_str(feature.name())?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// 缓存的特性，最高位表示已经查询过；多个 CPU 同时查询时结果相同，不需要加锁
+static FEATURES: AtomicU64 = AtomicU64::new(0);
+const FEATURES_VALID: u64 = 1 << 63;
+
+/// 当前 CPU 的特性，可以在中断处理函数中调用
+pub fn features() -> Features {
+    let cached = FEATURES.load(Ordering::Relaxed);
+    if cached & FEATURES_VALID != 0 {
+        return Features(cached as u8);
+    }
+    let features = Features::decode(__cpuid(1));
+    FEATURES.store(FEATURES_VALID | features.0 as u64, Ordering::Relaxed);
+    features
+}
+
+pub fn has_feature(feature: Feature) -> bool {
+    features().contains(feature)
+}
+
+/// CPUID.00H 的厂商字符串，按 EBX、EDX、ECX 的顺序排列
+pub fn vendor_from(leaf0: CpuidResult) -> [u8; 12] {
+    let mut vendor = [0; 12];
+    vendor[..4].copy_from_slice(&leaf0.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&leaf0.edx.to_le_bytes());
+    vendor[8..].copy_from_slice(&leaf0.ecx.to_le_bytes());
+    vendor
+}
+
+/// 厂商字符串，例如 "GenuineIntel"、"AuthenticAMD"
+pub fn cpu_vendor() -> [u8; 12] {
+    vendor_from(__cpuid(0))
+}
+
+/// 启动时打印的一行：厂商和特性
+pub struct Summary;
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let vendor = cpu_vendor();
+        let vendor = core::str::from_utf8(&vendor).unwrap_or("unknown");
+        write!(
+            f,
+            "{}, features: {}",
+            vendor.trim_end_matches('\0'),
+            features()
+        )
+    }
+}
+
+#[cfg(test)]
+fn leaf(ebx: u32, ecx: u32, edx: u32) -> CpuidResult {
+    CpuidResult {
+        eax: 0,
+        ebx,
+        ecx,
+        edx,
+    }
+}
+
+#[test_case]
+fn test_decode_features() {
+    assert_eq!(Features::decode(leaf(0, 0, 0)), Features::default());
+    // QEMU 默认的 qemu64：EDX 有 TSC、APIC、SSE、SSE2，ECX 有 SSE3 和 hypervisor
+    let features = Features::decode(leaf(0, 1 << 31 | 1, 1 << 26 | 1 << 25 | 1 << 9 | 1 << 4));
+    for feature in Feature::ALL {
+        assert_eq!(features.contains(feature), feature != Feature::Rdrand);
+    }
+    let mut text = alloc::string::String::new();
+    fmt::write(&mut text, format_args!("{}", features)).unwrap();
+    assert_eq!(text, "tsc apic sse sse2 sse3 hypervisor");
+
+    // 其他的位不影响结果
+    let only_rdrand = Features::decode(leaf(u32::MAX, 1 << 30, 0));
+    assert!(only_rdrand.contains(Feature::Rdrand));
+    assert!(!only_rdrand.contains(Feature::Tsc));
+}
+
+#[test_case]
+fn test_vendor_from() {
+    // "GenuineIntel" 按 EBX、EDX、ECX 排列
+    let leaf0 = leaf(0x756e_6547, 0x6c65_746e, 0x4965_6e69);
+    assert_eq!(&vendor_from(leaf0), b"GenuineIntel");
+}
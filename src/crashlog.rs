@@ -0,0 +1,622 @@
+//! 崩溃记录
+//! panic 时把 panic 信息、运行时间、调用栈和最近的控制台输出写到主盘上一段保留的扇区，
+//! 下次启动时 init 检查这段扇区，发现记录就提示上次启动崩溃了，之后可以用 crashlog show 查看、
+//! crashlog clear 清除。crashlog save 可以随时写一份记录，用来检查磁盘是否可用。
+//!
+//! 保留区紧接在 fs 读取归档的范围（前 DISK_SECTORS 个扇区）之后，共 SECTORS 个扇区。
+//! 这通常正是第一个分区开始的地方，ext2/3/4 等文件系统的前 1024 字节还是空的，
+//! 所以空白的扇区不能当作没人使用：保留区要先用 crashlog format 明确地格式化（写入 MAGIC），
+//! 之后才会写入记录。format 要求整个保留区全为 0；shell 的 crashlog format 同时格式化之后的设置扇区（见 settings）。
+//! 磁盘镜像要足够大才能用上，例如把归档补齐到 2 MiB（末尾的 0 不影响 USTAR）：
+//!
+//! ```shell
+//! truncate -s 2M initrd.tar
+//! ```
+//!
+//! 记录的格式，数字都是小端：
+//!
+//! | 偏移 | 长度 | 内容                        |
+//! |------|------|-----------------------------|
+//! | 0    | 8    | MAGIC                       |
+//! | 8    | 4    | 正文长度，0 表示没有记录    |
+//! | 12   | 4    | 正文的 Adler-32 校验和      |
+//! | 16   | ...  | 正文，UTF-8 文本            |
+//!
+//! 只有保留区的第一个扇区以 MAGIC 开头时才会写入，不会覆盖别人的数据。
+//! panic 时可能正持有任何锁、堆也可能已经损坏，所以写入只用静态的缓冲区、只尝试加锁，
+//! 磁盘用轮询的 PIO 访问，驱动器不响应时超时返回；结果也只在拿得到锁时写到屏幕和串口
+use crate::ata::{self, AtaError, Drive, SECTOR_SIZE};
+use crate::console::{sink, CaptureSink, OutputSink};
+use crate::serial::SERIAL1;
+use crate::vga_buffer::WRITER;
+use crate::{backtrace, fs, println, time};
+use alloc::boxed::Box;
+use core::fmt::{self, Write};
+use core::panic::{Location, PanicInfo};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub const MAGIC: [u8; 8] = *b"VMCRASH\0";
+/// 保留区的第一个扇区
+pub const FIRST_LBA: u32 = fs::DISK_SECTORS;
+pub const SECTORS: usize = 16;
+const RECORD_SIZE: usize = SECTORS * SECTOR_SIZE;
+const HEADER_SIZE: usize = 16;
+/// 正文的最大长度，超出的部分被截断
+pub const PAYLOAD_CAPACITY: usize = RECORD_SIZE - HEADER_SIZE;
+/// 保留最近多少字节的控制台输出
+const LOG_CAPACITY: usize = 4096;
+/// 启动时显示记录的前几行
+const PREVIEW_LINES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashlogError {
+    Disk(AtaError),
+    /// 保留区的第一个扇区既不是空的也不是崩溃记录，可能属于文件系统；
+    /// format 时保留区中有任何不为 0 的字节
+    NotOurs,
+    /// 保留区全为 0，还没有用 crashlog format 格式化
+    NotFormatted,
+    /// 缓冲区正被使用，panic 发生在读写记录的过程中
+    Busy,
+    /// 头部中的长度超出了保留区
+    BadLength(u32),
+    BadChecksum {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl From<AtaError> for CrashlogError {
+    fn from(error: AtaError) -> Self {
+        CrashlogError::Disk(error)
+    }
+}
+
+impl fmt::Display for CrashlogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CrashlogError::Disk(error) => write!(f, "disk: {}", error),
+            CrashlogError::NotOurs => write!(
+                f,
+                "sectors {}..{} hold other data",
+                FIRST_LBA,
+                FIRST_LBA as usize + SECTORS
+            ),
+            CrashlogError::NotFormatted => write!(
+                f,
+                "sectors {}..{} not formatted (crashlog format)",
+                FIRST_LBA,
+                FIRST_LBA as usize + SECTORS
+            ),
+            CrashlogError::Busy => write!(f, "record buffer busy"),
+            CrashlogError::BadLength(len) => write!(f, "bad record length {}", len),
+            CrashlogError::BadChecksum { expected, actual } => write!(
+                f,
+                "checksum mismatch (expected {:#010x}, got {:#010x})",
+                expected, actual
+            ),
+        }
+    }
+}
+
+/// 保留区的内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contents<'a> {
+    /// 全为 0，还没有使用过
+    Blank,
+    /// 有头部但没有记录，例如 clear 之后
+    Empty,
+    /// 记录的正文
+    Crash(&'a [u8]),
+}
+
+/// Adler-32
+pub(crate) fn checksum(bytes: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % MOD;
+        b = (b + a) % MOD;
+    }
+    b << 16 | a
+}
+
+/// 在 record 开头写入头部，正文已经放在 HEADER_SIZE 之后
+fn frame(record: &mut [u8; RECORD_SIZE], len: usize) {
+    let sum = checksum(&record[HEADER_SIZE..HEADER_SIZE + len]);
+    record[..8].copy_from_slice(&MAGIC);
+    record[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+    record[12..16].copy_from_slice(&sum.to_le_bytes());
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// 保留区已经格式化过时才可以写入，空白的扇区可能属于文件系统
+fn claimable(first_sector: &[u8]) -> Result<(), CrashlogError> {
+    if first_sector.starts_with(&MAGIC) {
+        Ok(())
+    } else if first_sector.iter().all(|&byte| byte == 0) {
+        Err(CrashlogError::NotFormatted)
+    } else {
+        Err(CrashlogError::NotOurs)
+    }
+}
+
+/// 检查头部和正文的校验和
+pub fn parse(record: &[u8; RECORD_SIZE]) -> Result<Contents<'_>, CrashlogError> {
+    if record[..SECTOR_SIZE].iter().all(|&byte| byte == 0) {
+        return Ok(Contents::Blank);
+    }
+    if !record.starts_with(&MAGIC) {
+        return Err(CrashlogError::NotOurs);
+    }
+    let len = read_u32(&record[8..12]);
+    if len as usize > PAYLOAD_CAPACITY {
+        return Err(CrashlogError::BadLength(len));
+    }
+    if len == 0 {
+        return Ok(Contents::Empty);
+    }
+    let payload = &record[HEADER_SIZE..HEADER_SIZE + len as usize];
+    let expected = read_u32(&record[12..16]);
+    let actual = checksum(payload);
+    if actual != expected {
+        return Err(CrashlogError::BadChecksum { expected, actual });
+    }
+    Ok(Contents::Crash(payload))
+}
+
+/// 读写保留区所在的磁盘，抽象出来以便用内存中的假磁盘测试
+pub trait Disk {
+    fn read(&mut self, lba: u32, buf: &mut [u8]) -> Result<(), AtaError>;
+    fn write(&mut self, lba: u32, buf: &[u8]) -> Result<(), AtaError>;
+}
+
+/// 主盘，通道被占用时不等待
+pub(crate) struct PrimaryDisk;
+
+impl Disk for PrimaryDisk {
+    fn read(&mut self, lba: u32, buf: &mut [u8]) -> Result<(), AtaError> {
+        ata::try_read_sectors(Drive::Master, lba, buf)
+    }
+
+    fn write(&mut self, lba: u32, buf: &[u8]) -> Result<(), AtaError> {
+        ata::try_write_sectors(Drive::Master, lba, buf)
+    }
+}
+
+/// 写入正文，超出容量的部分被丢弃
+pub struct Payload<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Payload<'_> {
+    pub fn push(&mut self, bytes: &[u8]) {
+        let count = bytes.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + count].copy_from_slice(&bytes[..count]);
+        self.len += count;
+    }
+}
+
+impl Write for Payload<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// 读出保留区
+fn load_with<'a>(
+    disk: &mut impl Disk,
+    record: &'a mut [u8; RECORD_SIZE],
+) -> Result<Contents<'a>, CrashlogError> {
+    disk.read(FIRST_LBA, record)?;
+    parse(record)
+}
+
+/// 确认保留区可以写入，由 compose 写正文，加上头部后写回
+fn save_with(
+    disk: &mut impl Disk,
+    record: &mut [u8; RECORD_SIZE],
+    compose: impl FnOnce(&mut Payload),
+) -> Result<(), CrashlogError> {
+    disk.read(FIRST_LBA, &mut record[..SECTOR_SIZE])?;
+    claimable(&record[..SECTOR_SIZE])?;
+    record.fill(0);
+    let mut payload = Payload {
+        buf: &mut record[HEADER_SIZE..],
+        len: 0,
+    };
+    compose(&mut payload);
+    let len = payload.len;
+    frame(record, len);
+    disk.write(FIRST_LBA, record)?;
+    Ok(())
+}
+
+/// 写入一个没有记录的头部，只需要写第一个扇区
+fn clear_with(disk: &mut impl Disk, record: &mut [u8; RECORD_SIZE]) -> Result<(), CrashlogError> {
+    disk.read(FIRST_LBA, &mut record[..SECTOR_SIZE])?;
+    claimable(&record[..SECTOR_SIZE])?;
+    record.fill(0);
+    frame(record, 0);
+    disk.write(FIRST_LBA, &record[..SECTOR_SIZE])?;
+    Ok(())
+}
+
+/// 格式化保留区：整个保留区全为 0 时写入没有记录的头部；已经格式化过时什么也不做
+fn format_with(disk: &mut impl Disk, record: &mut [u8; RECORD_SIZE]) -> Result<(), CrashlogError> {
+    disk.read(FIRST_LBA, record)?;
+    if record.starts_with(&MAGIC) {
+        return Ok(());
+    }
+    if record.iter().any(|&byte| byte != 0) {
+        return Err(CrashlogError::NotOurs);
+    }
+    frame(record, 0);
+    disk.write(FIRST_LBA, &record[..SECTOR_SIZE])?;
+    Ok(())
+}
+
+/// 读写记录用的缓冲区，panic 时堆可能不可用，所以是静态的
+static RECORD: Mutex<[u8; RECORD_SIZE]> = Mutex::new([0; RECORD_SIZE]);
+/// 最近的控制台输出
+static LOG: Mutex<CaptureSink<LOG_CAPACITY>> = Mutex::new(CaptureSink::new());
+
+/// 把控制台输出复制到 LOG，写入发生在关闭中断的分发过程中
+struct LogSink;
+
+impl OutputSink for LogSink {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        LOG.lock().write_bytes(bytes);
+    }
+}
+
+/// 正文：原因和位置、运行时间、调用栈，最后是最近的控制台输出
+fn write_payload(out: &mut Payload, reason: fmt::Arguments, location: Option<&Location>) {
+    let _ = writeln!(out, "{}", reason);
+    if let Some(location) = location {
+        let _ = writeln!(out, "  at {}", location);
+    }
+    let _ = writeln!(out, "uptime: {} ms", time::ticks_to_ms(time::ticks()));
+    let _ = backtrace::write(out);
+    let _ = writeln!(out, "console:");
+    match LOG.try_lock() {
+        Some(log) => {
+            let (head, tail) = log.as_slices();
+            out.push(head);
+            out.push(tail);
+        }
+        None => {
+            let _ = writeln!(out, "  (busy)");
+        }
+    }
+}
+
+/// 保留 bytes 的最后 lines 行时要跳过的字节数，末尾的换行符不会开始新的一行
+fn last_lines_start(bytes: &[&[u8]; 2], lines: usize) -> usize {
+    let len = bytes[0].len() + bytes[1].len();
+    let byte_at = |index: usize| match index.checked_sub(bytes[0].len()) {
+        Some(index) => bytes[1][index],
+        None => bytes[0][index],
+    };
+    let mut end = len;
+    if end > 0 && byte_at(end - 1) == b'\n' {
+        end -= 1;
+    }
+    let mut seen = 0;
+    for index in (0..end).rev() {
+        if byte_at(index) == b'\n' {
+            seen += 1;
+            if seen == lines {
+                return index + 1;
+            }
+        }
+    }
+    0
+}
+
+/// 最近的控制台输出中的最后 lines 行，LOG 正被占用时返回 None；不分配内存
+pub fn try_write_log_tail(out: &mut impl fmt::Write, lines: usize) -> Option<fmt::Result> {
+    let log = LOG.try_lock()?;
+    let (head, tail) = log.as_slices();
+    let skip = last_lines_start(&[head, tail], lines);
+    let (head, tail) = match skip.checked_sub(head.len()) {
+        Some(skip) => (&[][..], &tail[skip..]),
+        None => (&head[skip..], tail),
+    };
+    Some(write!(out, "{}{}", Lossy(head), Lossy(tail)))
+}
+
+/// 把字节按 UTF-8 显示，无效的部分（例如被截断的字符）显示为 U+FFFD
+struct Lossy<'a>(&'a [u8]);
+
+impl fmt::Display for Lossy<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in self.0.utf8_chunks() {
+            f.write_str(chunk.valid())?;
+            if !chunk.invalid().is_empty() {
+                f.write_char(char::REPLACEMENT_CHARACTER)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 没有磁盘或者磁盘太小时不提示
+fn no_disk(error: &CrashlogError) -> bool {
+    matches!(
+        error,
+        CrashlogError::Disk(AtaError::NotIdentified | AtaError::OutOfRange { .. })
+    )
+}
+
+/// 开始保留控制台输出，并检查上次启动留下的记录；在 ata::init 之后调用
+pub fn init() {
+    // 零大小的类型，Box::new 不会分配内存
+    if let Err(error) = sink::register(Box::leak(Box::new(LogSink))) {
+        println!("crashlog: console log unavailable: {:?}", error);
+    }
+    let mut record = RECORD.lock();
+    match load_with(&mut PrimaryDisk, &mut record) {
+        Ok(Contents::Crash(payload)) => {
+            let mut lines = payload.split(|&byte| byte == b'\n');
+            if let Some(first) = lines.next() {
+                println!("previous boot crashed: {}", Lossy(first));
+            }
+            for line in lines.take(PREVIEW_LINES - 1) {
+                println!("{}", Lossy(line));
+            }
+            println!("(crashlog show for the full record, crashlog clear to discard it)");
+        }
+        Ok(Contents::Blank | Contents::Empty) => {}
+        // 保留区属于别人时不使用，也不必提示
+        Err(CrashlogError::NotOurs) => {}
+        Err(error) if no_disk(&error) => {}
+        Err(error) => println!("crashlog: {}", error),
+    }
+}
+
+/// 由 panic 处理调用：写入记录并报告结果，不等待锁、不分配内存
+pub fn record_panic(info: &PanicInfo) {
+    let result = RECORD
+        .try_lock()
+        .ok_or(CrashlogError::Busy)
+        .and_then(|mut record| {
+            save_with(&mut PrimaryDisk, &mut record, |out| {
+                write_payload(
+                    out,
+                    format_args!("kernel panic: {}", info.message()),
+                    info.location(),
+                )
+            })
+        });
+    match result {
+        Ok(()) => report(format_args!("crash record written to disk\n")),
+        Err(error) if no_disk(&error) => {}
+        Err(CrashlogError::NotOurs | CrashlogError::NotFormatted) => {}
+        Err(error) => report(format_args!("crashlog: {}\n", error)),
+    }
+}
+
+/// panic 时报告结果：只尝试加锁，拿不到的一边不写
+fn report(args: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        if let Some(mut serial) = SERIAL1.try_lock() {
+            let _ = serial.write_fmt(args);
+        }
+        if let Some(mut writer) = WRITER.try_lock() {
+            let _ = writer.write_fmt(args);
+        }
+    });
+}
+
+/// 不经过 panic 写一份记录
+pub fn save(reason: &str) -> Result<(), CrashlogError> {
+    save_with(&mut PrimaryDisk, &mut RECORD.lock(), |out| {
+        write_payload(out, format_args!("{}", reason), None)
+    })
+}
+
+/// 格式化保留区，之后才会写入记录，见模块文档
+pub fn format() -> Result<(), CrashlogError> {
+    format_with(&mut PrimaryDisk, &mut RECORD.lock())
+}
+
+/// 清除记录
+pub fn clear() -> Result<(), CrashlogError> {
+    clear_with(&mut PrimaryDisk, &mut RECORD.lock())
+}
+
+/// 把记录的正文写入 out，没有记录时返回 false
+pub fn show(out: &mut impl Write) -> Result<bool, CrashlogError> {
+    let mut record = RECORD.lock();
+    match load_with(&mut PrimaryDisk, &mut record)? {
+        Contents::Crash(payload) => {
+            let _ = write!(out, "{}", Lossy(payload));
+            Ok(true)
+        }
+        Contents::Blank | Contents::Empty => Ok(false),
+    }
+}
+
+/// 内存中的磁盘，保留区之前的扇区不存在
+#[cfg(test)]
+struct FakeDisk {
+    sectors: alloc::vec::Vec<u8>,
+    writes: usize,
+}
+
+#[cfg(test)]
+impl FakeDisk {
+    fn new() -> Self {
+        FakeDisk {
+            sectors: alloc::vec![0; RECORD_SIZE],
+            writes: 0,
+        }
+    }
+
+    fn range(&self, lba: u32, len: usize) -> Result<core::ops::Range<usize>, AtaError> {
+        let start =
+            lba.checked_sub(FIRST_LBA).ok_or(AtaError::NotIdentified)? as usize * SECTOR_SIZE;
+        if start + len > self.sectors.len() {
+            return Err(AtaError::OutOfRange {
+                lba,
+                count: len / SECTOR_SIZE,
+                capacity: FIRST_LBA + SECTORS as u32,
+            });
+        }
+        Ok(start..start + len)
+    }
+}
+
+#[cfg(test)]
+impl Disk for FakeDisk {
+    fn read(&mut self, lba: u32, buf: &mut [u8]) -> Result<(), AtaError> {
+        let range = self.range(lba, buf.len())?;
+        buf.copy_from_slice(&self.sectors[range]);
+        Ok(())
+    }
+
+    fn write(&mut self, lba: u32, buf: &[u8]) -> Result<(), AtaError> {
+        let range = self.range(lba, buf.len())?;
+        self.sectors[range].copy_from_slice(buf);
+        self.writes += 1;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_last_lines_start() {
+    let log: [&[u8]; 2] = [b"one\ntw", b"o\nthree\n"];
+    assert_eq!(last_lines_start(&log, 1), 8);
+    assert_eq!(last_lines_start(&log, 2), 4);
+    assert_eq!(last_lines_start(&log, 3), 0);
+    assert_eq!(last_lines_start(&log, 10), 0);
+    // 最后一行还没有结束
+    let log: [&[u8]; 2] = [b"a\nb", b""];
+    assert_eq!(last_lines_start(&log, 1), 2);
+    assert_eq!(last_lines_start(&[b"", b""], 1), 0);
+}
+
+#[test_case]
+fn test_frame_and_parse() {
+    let mut record = [0; RECORD_SIZE];
+    assert_eq!(parse(&record), Ok(Contents::Blank));
+
+    record[HEADER_SIZE..HEADER_SIZE + 5].copy_from_slice(b"boom\n");
+    frame(&mut record, 5);
+    assert!(record.starts_with(&MAGIC));
+    assert_eq!(parse(&record), Ok(Contents::Crash(b"boom\n")));
+
+    // 正文中的任何一个字节被改动都能发现
+    record[HEADER_SIZE + 1] ^= 0x20;
+    assert!(matches!(
+        parse(&record),
+        Err(CrashlogError::BadChecksum { .. })
+    ));
+    record[8..12].copy_from_slice(&(PAYLOAD_CAPACITY as u32 + 1).to_le_bytes());
+    assert_eq!(
+        parse(&record),
+        Err(CrashlogError::BadLength(PAYLOAD_CAPACITY as u32 + 1))
+    );
+
+    frame(&mut record, 0);
+    assert_eq!(parse(&record), Ok(Contents::Empty));
+    record[0] = b'X';
+    assert_eq!(parse(&record), Err(CrashlogError::NotOurs));
+
+    // 已知的 Adler-32 值
+    assert_eq!(checksum(b""), 1);
+    assert_eq!(checksum(b"Wikipedia"), 0x11e6_0398);
+}
+
+#[test_case]
+fn test_save_and_detect_on_fake_disk() {
+    let mut disk = FakeDisk::new();
+    let mut record = [0; RECORD_SIZE];
+    assert_eq!(load_with(&mut disk, &mut record), Ok(Contents::Blank));
+    // 格式化之前不写入
+    assert_eq!(
+        save_with(&mut disk, &mut record, |out| out.push(b"boom")),
+        Err(CrashlogError::NotFormatted)
+    );
+    assert_eq!(disk.writes, 0);
+    format_with(&mut disk, &mut record).unwrap();
+    assert_eq!(load_with(&mut disk, &mut record), Ok(Contents::Empty));
+
+    save_with(&mut disk, &mut record, |out| {
+        let _ = writeln!(out, "kernel panic: {}", 42);
+        out.push(b"log line\n");
+    })
+    .unwrap();
+    // 下次启动用新的缓冲区读出
+    let mut next_boot = [0xaa; RECORD_SIZE];
+    assert_eq!(
+        load_with(&mut disk, &mut next_boot),
+        Ok(Contents::Crash(b"kernel panic: 42\nlog line\n"))
+    );
+
+    clear_with(&mut disk, &mut record).unwrap();
+    assert_eq!(load_with(&mut disk, &mut next_boot), Ok(Contents::Empty));
+
+    // 正文被截断到容量
+    save_with(&mut disk, &mut record, |out| {
+        for _ in 0..RECORD_SIZE {
+            out.push(b"x");
+        }
+    })
+    .unwrap();
+    match load_with(&mut disk, &mut next_boot) {
+        Ok(Contents::Crash(payload)) => assert_eq!(payload.len(), PAYLOAD_CAPACITY),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test_case]
+fn test_refuses_foreign_data() {
+    let mut disk = FakeDisk::new();
+    disk.sectors[100] = 1;
+    let mut record = [0; RECORD_SIZE];
+    assert_eq!(
+        load_with(&mut disk, &mut record),
+        Err(CrashlogError::NotOurs)
+    );
+    assert_eq!(
+        save_with(&mut disk, &mut record, |out| out.push(b"boom")),
+        Err(CrashlogError::NotOurs)
+    );
+    assert_eq!(
+        clear_with(&mut disk, &mut record),
+        Err(CrashlogError::NotOurs)
+    );
+    assert_eq!(
+        format_with(&mut disk, &mut record),
+        Err(CrashlogError::NotOurs)
+    );
+    assert_eq!(disk.writes, 0);
+    assert_eq!(disk.sectors[100], 1);
+
+    // 像 ext2 一样前 1024 字节为空、之后有超级块时也不格式化
+    let mut ext2 = FakeDisk::new();
+    ext2.sectors[1024 + 56] = 0x53;
+    assert_eq!(
+        save_with(&mut ext2, &mut record, |out| out.push(b"boom")),
+        Err(CrashlogError::NotFormatted)
+    );
+    assert_eq!(
+        format_with(&mut ext2, &mut record),
+        Err(CrashlogError::NotOurs)
+    );
+    assert_eq!(ext2.writes, 0);
+
+    // 磁盘太小时当作没有磁盘
+    let mut small = FakeDisk::new();
+    small.sectors.truncate(SECTOR_SIZE);
+    let error = load_with(&mut small, &mut record).unwrap_err();
+    assert!(no_disk(&error));
+}
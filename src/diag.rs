@@ -0,0 +1,157 @@
+//! 中断延迟统计
+//! 每个时钟中断记录它比预期晚到了多久：预期的到达时间是上一次中断加上一个节拍的周期，
+//! 周期用启动以来估计的 TSC 频率换算（见 time::tick）。关中断太久（例如持锁滚屏）会让下一次中断晚到，
+//! 晚到的时间按 2 的幂微秒分桶累加。早到（上一次晚到后的追赶）记为 0。
+//!
+//! 记录时只做一次除法、一次 lzcnt 和一次 Relaxed 的原子加，不加锁。
+//! 可以在运行 selftest 的滚屏检查或者大量输出时查看 latency 命令的结果
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// 桶 0 是不到 1µs，桶 i 是 [2^(i-1), 2^i) µs，最后一个桶没有上限
+pub const BUCKETS: usize = 12;
+/// 柱状图最长的柱子
+const BAR_WIDTH: usize = 40;
+
+static HISTOGRAM: [AtomicU64; BUCKETS] = [const { AtomicU64::new(0) }; BUCKETS];
+
+/// 晚到 late_us 微秒落在哪个桶
+fn bucket(late_us: u64) -> usize {
+    ((u64::BITS - late_us.leading_zeros()) as usize).min(BUCKETS - 1)
+}
+
+/// 桶 index 的范围 [low, high)，最后一个桶 high 为 None
+pub fn bucket_range(index: usize) -> (u64, Option<u64>) {
+    let low = match index {
+        0 => 0,
+        _ => 1 << (index - 1),
+    };
+    (low, (index < BUCKETS - 1).then(|| 1 << index))
+}
+
+/// 由 time::tick 调用，interval 是与上一次中断之间的 TSC 计数，per_tick 是一个节拍的 TSC 计数
+#[inline]
+pub(crate) fn record_tick(interval: u64, per_tick: u64) {
+    // 一个节拍是 1000µs
+    let late_us = interval.saturating_sub(per_tick) * 1000 / per_tick;
+    HISTOGRAM[bucket(late_us)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// latency_histogram 的返回值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    pub counts: [u64; BUCKETS],
+}
+
+impl LatencyHistogram {
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// 当前的统计，各个桶分别读取，与正在进行的记录之间不是原子的
+pub fn latency_histogram() -> LatencyHistogram {
+    LatencyHistogram {
+        counts: core::array::from_fn(|index| HISTOGRAM[index].load(Ordering::Relaxed)),
+    }
+}
+
+/// 清空统计，例如在开始一次测量之前
+pub fn reset_latency() {
+    for count in &HISTOGRAM {
+        count.store(0, Ordering::Relaxed);
+    }
+}
+
+/// 把统计画成横向的柱状图，每个桶一行：范围、次数和按最大次数缩放的柱子
+pub fn write_latency(out: &mut impl Write, histogram: &LatencyHistogram) -> fmt::Result {
+    let max = histogram.counts.iter().copied().max().unwrap_or(0);
+    writeln!(out, "{:>12}  {:>8}", "LATENCY", "COUNT")?;
+    for (index, &count) in histogram.counts.iter().enumerate() {
+        let (low, high) = bucket_range(index);
+        match high {
+            Some(high) => write!(out, "{:>5}-{:>4}us", low, high)?,
+            None => write!(out, "{:>6}{:>4}us", ">=", low)?,
+        }
+        write!(out, "  {:>8}  ", count)?;
+        // 次数不为 0 的桶至少画一格
+        let bar = match count {
+            0 => 0,
+            _ => (count * BAR_WIDTH as u64).div_ceil(max) as usize,
+        };
+        for _ in 0..bar {
+            out.write_char('#')?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "{} interrupts", histogram.total())
+}
+
+/// 在屏幕上打印当前的统计
+pub fn print_latency() {
+    use alloc::string::String;
+
+    let mut chart = String::new();
+    write_latency(&mut chart, &latency_histogram()).unwrap();
+    crate::println!("{}", chart.trim_end());
+}
+
+#[test_case]
+fn test_latency_buckets() {
+    assert_eq!(bucket(0), 0);
+    assert_eq!(bucket(1), 1);
+    assert_eq!(bucket(2), 2);
+    assert_eq!(bucket(3), 2);
+    assert_eq!(bucket(4), 3);
+    assert_eq!(bucket(1023), 10);
+    assert_eq!(bucket(1024), BUCKETS - 1);
+    assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+    // 每个桶的范围与 bucket 一致
+    for index in 0..BUCKETS {
+        let (low, high) = bucket_range(index);
+        assert_eq!(bucket(low), index);
+        if let Some(high) = high {
+            assert_eq!(bucket(high - 1), index);
+            assert_eq!(bucket(high), index + 1);
+        }
+    }
+}
+
+#[test_case]
+fn test_record_tick_lateness() {
+    // 每个节拍 2_000_000 个 TSC 计数，晚到 1/4 个节拍是 250µs
+    let per_tick = 2_000_000;
+    let before = latency_histogram();
+    record_tick(per_tick + per_tick / 4, per_tick);
+    record_tick(per_tick / 2, per_tick);
+    let after = latency_histogram();
+    assert_eq!(after.counts[bucket(250)] - before.counts[bucket(250)], 1);
+    // 早到记为 0
+    assert_eq!(after.counts[0] - before.counts[0], 1);
+}
+
+#[test_case]
+fn test_latency_bar_chart() {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    let mut counts = [0; BUCKETS];
+    counts[0] = 100;
+    counts[3] = 1;
+    counts[BUCKETS - 1] = 50;
+    let mut chart = String::new();
+    write_latency(&mut chart, &LatencyHistogram { counts }).unwrap();
+    let lines: Vec<&str> = chart.lines().collect();
+    assert_eq!(lines.len(), BUCKETS + 2);
+    assert_eq!(
+        lines[1],
+        alloc::format!("    0-   1us       100  {}", "#".repeat(40))
+    );
+    assert_eq!(lines[2], "    1-   2us         0  ");
+    assert_eq!(lines[4], "    4-   8us         1  #");
+    assert_eq!(
+        lines[BUCKETS],
+        alloc::format!("    >=1024us        50  {}", "#".repeat(20))
+    );
+    assert_eq!(lines[BUCKETS + 1], "151 interrupts");
+}
@@ -0,0 +1,362 @@
+//! 诊断转储快捷键（类似 Linux 的 magic SysRq）
+//! 内核还在运行但行为异常时，按 Ctrl+Alt+D 把诊断信息分页显示在屏幕上，按 Ctrl+Alt+S 写到串口。
+//! 组合键通过 keybindings 注册，可以在 init 时换成别的。
+//!
+//! 转储由若干段组成：内置的运行时间、堆、中断延迟、任务、最近的控制台输出和栈使用量，
+//! 其他模块可以用 register 添加自己的段，之后的转储自动包含它。
+//!
+//! 快捷键的处理函数在持有 WRITER 锁、关闭中断的情况下运行，被打断的代码可能正持有任何锁，
+//! 所以各段只用 try_lock，锁被占用时显示 "busy"，整个过程不分配内存。
+//! 屏幕上每满一页等待按键：关闭中断时键盘中断不会到来，直接轮询键盘控制器
+use crate::console::PolledKeyboard;
+use crate::keybindings::{self, Action, BindError, Chord};
+use crate::keyboard::{DecodedKey, KeyCode, KeyInput};
+use crate::serial::SERIAL1;
+use crate::task::executor;
+use crate::vga_buffer::{Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+use crate::{allocator, crashlog, diag, stack, time};
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+/// 通过 register 添加的段数上限
+pub const MAX_SECTIONS: usize = 16;
+/// log 段显示的行数
+pub const LOG_LINES: usize = 10;
+/// 分页时每页之后显示的提示
+pub const PROMPT: &str = "-- press any key for more, q to stop --";
+
+/// 默认的组合键：Ctrl+Alt+D 显示到屏幕，Ctrl+Alt+S 写到串口
+pub const SCREEN_CHORD: Chord = Chord {
+    ctrl: true,
+    alt: true,
+    ..Chord::plain(KeyCode::D)
+};
+pub const SERIAL_CHORD: Chord = Chord {
+    ctrl: true,
+    alt: true,
+    ..Chord::plain(KeyCode::S)
+};
+
+/// 转储中的一段。write 在关闭中断时调用，不能阻塞或分配内存，需要的锁用 try_lock
+#[derive(Clone, Copy)]
+pub struct Section {
+    pub name: &'static str,
+    pub write: fn(&mut dyn Write) -> fmt::Result,
+}
+
+const BUILTIN_SECTIONS: &[Section] = &[
+    Section {
+        name: "uptime",
+        write: uptime,
+    },
+    Section {
+        name: "heap",
+        write: heap,
+    },
+    Section {
+        name: "interrupts",
+        write: interrupts,
+    },
+    Section {
+        name: "tasks",
+        write: tasks,
+    },
+    Section {
+        name: "log",
+        write: log,
+    },
+    Section {
+        name: "stack",
+        write: stack,
+    },
+];
+
+static REGISTERED: Mutex<[Option<Section>; MAX_SECTIONS]> = Mutex::new([None; MAX_SECTIONS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// 同名的段已经存在
+    Duplicate(&'static str),
+    TableFull,
+}
+
+/// 添加一段，名称不能与已有的段重复
+pub fn register(section: Section) -> Result<(), RegisterError> {
+    let mut registered = REGISTERED.lock();
+    let taken = BUILTIN_SECTIONS
+        .iter()
+        .chain(registered.iter().flatten())
+        .any(|existing| existing.name == section.name);
+    if taken {
+        return Err(RegisterError::Duplicate(section.name));
+    }
+    let slot = registered
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(RegisterError::TableFull)?;
+    *slot = Some(section);
+    Ok(())
+}
+
+/// 依次写出所有段，每段前面是 "== 名称 =="；注册表正被修改时只写内置的段
+pub fn write_dump(out: &mut dyn Write) -> fmt::Result {
+    let registered = REGISTERED.try_lock();
+    let extra = registered.iter().flat_map(|table| table.iter().flatten());
+    for section in BUILTIN_SECTIONS.iter().chain(extra) {
+        writeln!(out, "== {} ==", section.name)?;
+        (section.write)(out)?;
+    }
+    Ok(())
+}
+
+fn busy(out: &mut dyn Write) -> fmt::Result {
+    writeln!(out, "busy")
+}
+
+fn uptime(out: &mut dyn Write) -> fmt::Result {
+    let ticks = time::ticks();
+    writeln!(out, "{} ms ({} ticks)", time::ticks_to_ms(ticks), ticks)
+}
+
+fn heap(out: &mut dyn Write) -> fmt::Result {
+    match allocator::try_heap_stats() {
+        Some(stats) => writeln!(
+            out,
+            "{} of {} bytes used, {} free",
+            stats.used, stats.total, stats.free
+        ),
+        None => busy(out),
+    }
+}
+
+fn interrupts(mut out: &mut dyn Write) -> fmt::Result {
+    diag::write_latency(&mut out, &diag::latency_histogram())
+}
+
+fn tasks(mut out: &mut dyn Write) -> fmt::Result {
+    executor::try_write_tasks(&mut out).unwrap_or_else(|| busy(out))
+}
+
+fn log(mut out: &mut dyn Write) -> fmt::Result {
+    crashlog::try_write_log_tail(&mut out, LOG_LINES).unwrap_or_else(|| busy(out))
+}
+
+fn stack(out: &mut dyn Write) -> fmt::Result {
+    let size = stack::size();
+    if size == 0 {
+        return writeln!(out, "not measured");
+    }
+    writeln!(
+        out,
+        "{} of {} KiB used at most",
+        stack::high_water_mark().div_ceil(1024),
+        size / 1024
+    )
+}
+
+/// 按行数分页的输出：每写满 page_lines 行显示 PROMPT，从 keys 取下一个按键，
+/// 按 q 或者没有按键了就停止，之后的写入返回错误。宽 width 的行写满后与 Writer 一样折行
+pub struct Pager<'a, W: Write + ?Sized, K: Iterator<Item = KeyInput>> {
+    out: &'a mut W,
+    keys: K,
+    page_lines: usize,
+    width: usize,
+    /// 这一页已经写了的行数
+    lines: usize,
+    column: usize,
+    stopped: bool,
+}
+
+impl<'a, W: Write + ?Sized, K: Iterator<Item = KeyInput>> Pager<'a, W, K> {
+    pub fn new(out: &'a mut W, keys: K, page_lines: usize, width: usize) -> Self {
+        Pager {
+            out,
+            keys,
+            page_lines,
+            width,
+            lines: 0,
+            column: 0,
+            stopped: false,
+        }
+    }
+
+    /// 是否因为按了 q 而停止
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// 显示提示并等待一个按下的字符键
+    fn wait_for_key(&mut self) -> fmt::Result {
+        self.out.write_str(PROMPT)?;
+        let key = self
+            .keys
+            .by_ref()
+            .filter(|input| input.event.pressed)
+            .find_map(|input| input.key);
+        self.out.write_char('\n')?;
+        if matches!(key, None | Some(DecodedKey::Unicode('q' | 'Q'))) {
+            self.stopped = true;
+            return Err(fmt::Error);
+        }
+        self.lines = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write + ?Sized, K: Iterator<Item = KeyInput>> Write for Pager<'_, W, K> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.stopped {
+                return Err(fmt::Error);
+            }
+            // 行已写满时下一个字符折到新的一行
+            if c != '\n' && self.column == self.width {
+                self.lines += 1;
+                self.column = 0;
+            }
+            if self.lines == self.page_lines {
+                self.wait_for_key()?;
+            }
+            self.out.write_char(c)?;
+            if c == '\n' {
+                self.lines += 1;
+                self.column = 0;
+            } else {
+                self.column += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ctrl+Alt+D：分页显示到屏幕，留一行给提示
+fn dump_to_screen(writer: &mut Writer) {
+    if writer.column() != 0 {
+        writer.new_line();
+    }
+    let keys = PolledKeyboard::new();
+    let mut pager = Pager::new(writer, keys, BUFFER_HEIGHT - 1, BUFFER_WIDTH);
+    let _ = write_dump(&mut pager);
+}
+
+/// Ctrl+Alt+S：不分页写到串口
+fn dump_to_serial(writer: &mut Writer) {
+    match SERIAL1.try_lock() {
+        Some(mut serial) => {
+            let _ = write_dump(&mut *serial);
+        }
+        None => {
+            let _ = writeln!(writer, "dump: serial port busy");
+        }
+    }
+}
+
+/// 把两个组合键注册到 keybindings，通常传入 SCREEN_CHORD 和 SERIAL_CHORD
+pub fn init(screen: Chord, serial: Chord) -> Result<(), BindError> {
+    keybindings::register(screen, Action::Custom(dump_to_screen))?;
+    keybindings::register(serial, Action::Custom(dump_to_serial))
+}
+
+#[cfg(test)]
+use alloc::string::String;
+
+#[cfg(test)]
+fn key(c: char) -> KeyInput {
+    use crate::keyboard::{KeyEvent, Modifiers};
+
+    KeyInput {
+        event: KeyEvent {
+            code: KeyCode::Spacebar,
+            pressed: true,
+            modifiers: Modifiers::NONE,
+        },
+        key: Some(DecodedKey::Unicode(c)),
+    }
+}
+
+#[test_case]
+fn test_section_registry() {
+    fn hello(out: &mut dyn Write) -> fmt::Result {
+        writeln!(out, "hello")
+    }
+
+    let section = Section {
+        name: "test-hello",
+        write: hello,
+    };
+    assert_eq!(register(section), Ok(()));
+    assert_eq!(
+        register(section),
+        Err(RegisterError::Duplicate("test-hello"))
+    );
+    // 内置的段同样不能重复
+    let heap_again = Section {
+        name: "heap",
+        write: hello,
+    };
+    assert_eq!(register(heap_again), Err(RegisterError::Duplicate("heap")));
+
+    let mut out = String::new();
+    write_dump(&mut out).unwrap();
+    // 内置的段在前，注册的段按注册顺序跟在后面
+    let headers: alloc::vec::Vec<&str> =
+        out.lines().filter(|line| line.starts_with("== ")).collect();
+    assert_eq!(
+        headers[..],
+        [
+            "== uptime ==",
+            "== heap ==",
+            "== interrupts ==",
+            "== tasks ==",
+            "== log ==",
+            "== stack ==",
+            "== test-hello ==",
+        ]
+    );
+    assert!(out.ends_with("== test-hello ==\nhello\n"));
+}
+
+#[test_case]
+fn test_pager_waits_between_pages() {
+    use alloc::vec;
+
+    // 三行一页：第一页之后按空格继续，第二页之后按 q 停止
+    let mut out = String::new();
+    let keys = vec![key(' '), key('q'), key(' ')].into_iter();
+    let mut pager = Pager::new(&mut out, keys, 3, BUFFER_WIDTH);
+    let result = (1..=10).try_for_each(|line| writeln!(pager, "{}", line));
+    assert!(result.is_err());
+    assert!(pager.stopped());
+    let expected = alloc::format!("1\n2\n3\n{0}\n4\n5\n6\n{0}\n", PROMPT);
+    assert_eq!(out, expected);
+
+    // 刚好写满一页时不提示；没有按键了也停止
+    let mut out = String::new();
+    let mut pager = Pager::new(&mut out, core::iter::empty(), 2, BUFFER_WIDTH);
+    assert!(write!(pager, "a\nb\n").is_ok());
+    assert!(write!(pager, "c").is_err());
+    assert_eq!(out, alloc::format!("a\nb\n{}\n", PROMPT));
+}
+
+#[test_case]
+fn test_pager_counts_wrapped_lines() {
+    use crate::keyboard::{KeyEvent, Modifiers};
+    use alloc::vec;
+
+    // 宽 4 列：写满一行后紧跟的换行符不另算一行，超出的字符折到下一行
+    let release = KeyInput {
+        event: KeyEvent {
+            code: KeyCode::Spacebar,
+            pressed: false,
+            modifiers: Modifiers::NONE,
+        },
+        key: None,
+    };
+    let mut out = String::new();
+    // 松开事件不算作按键
+    let keys = vec![release, key('x')].into_iter();
+    let mut pager = Pager::new(&mut out, keys, 2, 4);
+    write!(pager, "abcd\nefghi").unwrap();
+    assert!(!pager.stopped());
+    assert_eq!(out, alloc::format!("abcd\nefgh{}\ni", PROMPT));
+}
@@ -0,0 +1,219 @@
+//! 运行平坦二进制程序
+//! 把 ramfs 中的文件复制到 LOAD_ADDRESS 处新映射的页中，从第一个字节开始执行，
+//! RDI 中是内核提供的函数表 Syscalls。程序返回或者调用 exit 后回到内核，得到退出码，随后页被取消映射
+//!
+//! 程序和内核一样运行在 ring 0，没有任何隔离，只能运行可信的代码。
+//! BootInfoFrameAllocator 不能回收帧，每次运行用过的帧不会再被使用
+//!
+//! 示例程序见 programs/hello.s
+use crate::{fs, println};
+use core::fmt;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+/// 程序的加载地址，与堆不在同一个 4 级页表项下
+pub const LOAD_ADDRESS: u64 = 0x_5555_0000_0000;
+/// 程序文件的最大长度
+pub const MAX_PROGRAM_SIZE: usize = 32 * 1024;
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// 程序通过 RDI 得到的函数表，字段的顺序是 ABI 的一部分，只能在末尾追加
+#[repr(C)]
+pub struct Syscalls {
+    /// 打印 UTF-8 字符串并换行
+    pub puts: extern "C" fn(ptr: *const u8, len: usize),
+    /// 结束程序，code 作为退出码返回给 run 的调用者
+    pub exit: unsafe extern "C" fn(code: i32) -> !,
+}
+
+static SYSCALLS: Syscalls = Syscalls {
+    puts,
+    exit: exec_exit,
+};
+
+/// exec_enter 保存的内核栈指针，exec_exit 用它回到 exec_enter 的调用者
+static mut SAVED_RSP: u64 = 0;
+
+// exec_enter(entry, table)：保存被调用者保存的寄存器和栈指针后调用 entry(table)，
+// entry 返回时或者程序调用 exec_exit(code) 时恢复它们，以退出码作为 exec_enter 的返回值
+core::arch::global_asm!(
+    r#"
+.section .text
+.global exec_enter
+exec_enter:
+    pushq %rbp
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    movq %rsp, {saved_rsp}(%rip)
+    # 入口处 RSP 模 16 余 8，再压 6 个寄存器后仍然余 8，调用前对齐到 16
+    subq $8, %rsp
+    movq %rdi, %rax
+    movq %rsi, %rdi
+    call *%rax
+exec_return:
+    movq {saved_rsp}(%rip), %rsp
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    popq %rbp
+    ret
+
+.global exec_exit
+exec_exit:
+    movl %edi, %eax
+    jmp exec_return
+"#,
+    saved_rsp = sym SAVED_RSP,
+    options(att_syntax)
+);
+
+extern "C" {
+    fn exec_enter(entry: u64, table: *const Syscalls) -> i32;
+    fn exec_exit(code: i32) -> !;
+}
+
+extern "C" fn puts(ptr: *const u8, len: usize) {
+    let bytes = unsafe { core::slice::from_raw_parts(ptr, len) };
+    match core::str::from_utf8(bytes) {
+        Ok(s) => println!("{}", s),
+        Err(_) => println!("<invalid UTF-8>"),
+    }
+}
+
+#[derive(Debug)]
+pub enum ExecError {
+    /// ramfs 中没有这个文件
+    NotFound,
+    Empty,
+    /// 文件长度超过 MAX_PROGRAM_SIZE
+    TooLarge(usize),
+    /// 加载地址处已经有映射，可能有另一个程序正在运行
+    AlreadyMapped,
+    Map(MapToError<Size4KiB>),
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::NotFound => write!(f, "no such file"),
+            ExecError::Empty => write!(f, "empty program"),
+            ExecError::TooLarge(len) => write!(
+                f,
+                "program is {} bytes, at most {} allowed",
+                len, MAX_PROGRAM_SIZE
+            ),
+            ExecError::AlreadyMapped => write!(f, "load address is already mapped"),
+            ExecError::Map(error) => write!(f, "failed to map program: {:?}", error),
+        }
+    }
+}
+
+/// 检查长度并返回需要映射的页数
+pub(crate) fn page_count(len: usize) -> Result<usize, ExecError> {
+    match len {
+        0 => Err(ExecError::Empty),
+        len if len > MAX_PROGRAM_SIZE => Err(ExecError::TooLarge(len)),
+        len => Ok(len.div_ceil(PAGE_SIZE)),
+    }
+}
+
+/// 从 start 开始的 count 个页
+pub(crate) fn pages(start: u64, count: usize) -> impl Iterator<Item = Page<Size4KiB>> + Clone {
+    let start = Page::containing_address(VirtAddr::new(start));
+    (0..count as u64).map(move |index| start + index)
+}
+
+/// 取消映射，没有映射的页被跳过
+pub(crate) fn unmap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    pages: impl Iterator<Item = Page<Size4KiB>>,
+) {
+    for page in pages {
+        if let Ok((_, flush)) = mapper.unmap(page) {
+            flush.flush();
+        }
+    }
+}
+
+/// 为每个页分配帧并建立映射，失败时取消已经建立的映射
+pub(crate) fn map(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    pages: impl Iterator<Item = Page<Size4KiB>> + Clone,
+    flags: PageTableFlags,
+) -> Result<(), ExecError> {
+    for page in pages.clone() {
+        let result = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)
+            .and_then(|frame| unsafe { mapper.map_to(page, frame, flags, frame_allocator) });
+        match result {
+            Ok(flush) => flush.flush(),
+            Err(error) => {
+                unmap(mapper, pages);
+                return Err(ExecError::Map(error));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 加载并运行内存中的程序，返回退出码
+pub fn run(
+    image: &[u8],
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<i32, ExecError> {
+    let count = page_count(image.len())?;
+    if pages(LOAD_ADDRESS, count).any(|page| mapper.translate_page(page).is_ok()) {
+        return Err(ExecError::AlreadyMapped);
+    }
+    // 不设置 NO_EXECUTE，页可以执行
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    map(mapper, frame_allocator, pages(LOAD_ADDRESS, count), flags)?;
+
+    let memory =
+        unsafe { core::slice::from_raw_parts_mut(LOAD_ADDRESS as *mut u8, count * PAGE_SIZE) };
+    memory[..image.len()].copy_from_slice(image);
+    // 新分配的帧中是以前的内容，最后一页的剩余部分清零，程序可以把它当作 BSS 使用
+    memory[image.len()..].fill(0);
+
+    let code = unsafe { exec_enter(LOAD_ADDRESS, &SYSCALLS) };
+    unmap(mapper, pages(LOAD_ADDRESS, count));
+    Ok(code)
+}
+
+/// 运行 ramfs 中的程序，返回退出码
+pub fn run_flat(
+    name: &str,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<i32, ExecError> {
+    let image = fs::read(name).ok_or(ExecError::NotFound)?;
+    run(image, mapper, frame_allocator)
+}
+
+#[test_case]
+fn test_page_count() {
+    assert!(matches!(page_count(0), Err(ExecError::Empty)));
+    assert!(matches!(page_count(1), Ok(1)));
+    assert!(matches!(page_count(PAGE_SIZE), Ok(1)));
+    assert!(matches!(page_count(PAGE_SIZE + 1), Ok(2)));
+    assert!(matches!(
+        page_count(MAX_PROGRAM_SIZE),
+        Ok(n) if n == MAX_PROGRAM_SIZE / PAGE_SIZE
+    ));
+    assert!(matches!(
+        page_count(MAX_PROGRAM_SIZE + 1),
+        Err(ExecError::TooLarge(len)) if len == MAX_PROGRAM_SIZE + 1
+    ));
+}
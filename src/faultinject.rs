@@ -0,0 +1,158 @@
+//! 故障注入
+//! 分配失败、磁盘超时、队列溢出这些错误处理路径平时很难走到。代码中放着一些命名的注入点，
+//! 在注入点调用 should_fail(名字)，返回 true 时按失败处理；测试用 set 决定哪个注入点以什么方式失败：
+//! 总是失败、第 n 次调用失败、或者按概率（随机数来自 rand）失败。
+//!
+//! 只在测试和打开 faultinject feature 时编译进来；否则 should_fail 是返回 false 的 const fn，
+//! 注入点的判断在编译时就被去掉，不影响正常的内核。
+//!
+//! 注入点可能在中断处理函数和全局分配器中，所以 should_fail 不分配内存、只尝试加锁，
+//! 没有设置任何注入点时只读一个原子变量
+#[cfg(any(test, feature = "faultinject"))]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(any(test, feature = "faultinject"))]
+use spin::Mutex;
+
+/// 全局分配器
+pub const ALLOC: &str = "alloc";
+/// memory::BootInfoFrameAllocator 分配物理帧
+pub const FRAME_ALLOC: &str = "frame_alloc";
+/// ata 等待驱动器的状态，失败时返回超时
+pub const ATA_WAIT: &str = "ata_wait";
+/// 扫描码放进队列，失败时按队列已满丢弃
+pub const SCANCODE_PUSH: &str = "scancode_push";
+/// console::sink 分发一条输出，失败时丢弃这条输出
+pub const SINK_DISPATCH: &str = "sink_dispatch";
+
+/// 注入点失败的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Always,
+    /// 设置之后的第 n 次调用（从 1 开始）失败，其他调用正常
+    Nth(u32),
+    /// 每次调用以这个百分比的概率失败
+    Probability(u8),
+}
+
+/// 同时设置的注入点数量上限
+#[cfg(any(test, feature = "faultinject"))]
+const MAX_POINTS: usize = 8;
+
+#[cfg(any(test, feature = "faultinject"))]
+struct Point {
+    name: &'static str,
+    mode: Mode,
+    calls: u32,
+    failures: u32,
+}
+
+#[cfg(any(test, feature = "faultinject"))]
+static POINTS: Mutex<[Option<Point>; MAX_POINTS]> = Mutex::new([const { None }; MAX_POINTS]);
+/// 设置了的注入点数量，为 0 时 should_fail 不加锁
+#[cfg(any(test, feature = "faultinject"))]
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// 注入点 point 这一次是否应当失败
+#[cfg(not(any(test, feature = "faultinject")))]
+#[inline(always)]
+pub const fn should_fail(_point: &str) -> bool {
+    false
+}
+
+/// 注入点 point 这一次是否应当失败；锁正被占用（例如中断打断了 set）时不失败
+#[cfg(any(test, feature = "faultinject"))]
+pub fn should_fail(point: &str) -> bool {
+    if ACTIVE.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+    let Some(mut points) = POINTS.try_lock() else {
+        return false;
+    };
+    let Some(entry) = points
+        .iter_mut()
+        .flatten()
+        .find(|entry| entry.name == point)
+    else {
+        return false;
+    };
+    entry.calls = entry.calls.saturating_add(1);
+    let fail = match entry.mode {
+        Mode::Always => true,
+        Mode::Nth(n) => entry.calls == n,
+        Mode::Probability(percent) => crate::rand::range(100) < percent as u64,
+    };
+    entry.failures += fail as u32;
+    fail
+}
+
+/// 设置注入点，已经设置过时重新开始计数；同时设置的注入点超过上限时 panic
+#[cfg(any(test, feature = "faultinject"))]
+pub fn set(point: &'static str, mode: Mode) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut points = POINTS.lock();
+        let index = match points
+            .iter()
+            .position(|entry| entry.as_ref().is_some_and(|entry| entry.name == point))
+        {
+            Some(index) => index,
+            None => {
+                let index = points
+                    .iter()
+                    .position(Option::is_none)
+                    .expect("too many fault injection points");
+                ACTIVE.fetch_add(1, Ordering::Relaxed);
+                index
+            }
+        };
+        points[index] = Some(Point {
+            name: point,
+            mode,
+            calls: 0,
+            failures: 0,
+        });
+    });
+}
+
+/// 取消注入点，返回设置以来注入了多少次失败
+#[cfg(any(test, feature = "faultinject"))]
+pub fn clear(point: &str) -> u32 {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut points = POINTS.lock();
+        let Some(slot) = points
+            .iter_mut()
+            .find(|entry| entry.as_ref().is_some_and(|entry| entry.name == point))
+        else {
+            return 0;
+        };
+        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+        slot.take().map_or(0, |entry| entry.failures)
+    })
+}
+
+#[test_case]
+fn test_modes() {
+    const POINT: &str = "test_point";
+    assert!(!should_fail(POINT));
+
+    set(POINT, Mode::Always);
+    assert!(should_fail(POINT) && should_fail(POINT));
+    assert!(!should_fail("other_point"));
+    assert_eq!(clear(POINT), 2);
+    assert!(!should_fail(POINT));
+    assert_eq!(clear(POINT), 0);
+
+    set(POINT, Mode::Nth(3));
+    let results: [bool; 5] = core::array::from_fn(|_| should_fail(POINT));
+    assert_eq!(results, [false, false, true, false, false]);
+    assert_eq!(clear(POINT), 1);
+
+    set(POINT, Mode::Probability(0));
+    assert!((0..100).all(|_| !should_fail(POINT)));
+    set(POINT, Mode::Probability(100));
+    assert!((0..100).all(|_| should_fail(POINT)));
+    set(POINT, Mode::Probability(50));
+    let failures = (0..1000).filter(|_| should_fail(POINT)).count();
+    assert!((300..700).contains(&failures), "{} failures", failures);
+    clear(POINT);
+    assert_eq!(ACTIVE.load(Ordering::Relaxed), 0);
+}
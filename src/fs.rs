@@ -0,0 +1,417 @@
+//! 只读的内存文件系统
+//! 启动时从 USTAR 格式的归档中读出所有普通文件，内容放在堆上。归档优先取引导程序加载的第一个模块
+//! （multiboot2 路径），没有模块时读取主盘开头的 DISK_SECTORS 个扇区。
+//! 加载只能成功一次，之后文件表不再改变，list 和 read 返回的引用一直有效
+//!
+//! USTAR 中每个文件是一个 512 字节的头部，之后是补齐到 512 字节的内容，全 0 的块表示归档结束。
+//! 头部中的数字是八进制 ASCII，以 NUL 或空格结尾；校验和是把校验和字段当作 8 个空格时头部所有字节之和。
+//! 目录、链接等其他类型的条目被跳过，同名的文件以后出现的为准
+//!
+//! ```shell
+//! tar --format=ustar -cf initrd.tar -C initrd .
+//! qemu-system-x86_64 ... -drive file=initrd.tar,format=raw,if=ide
+//! ```
+use crate::ata::{self, AtaError, Drive};
+use crate::multiboot2;
+use alloc::string::String;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::fmt;
+
+pub const BLOCK_SIZE: usize = 512;
+/// 没有启动模块时从主盘读取的最大扇区数
+pub const DISK_SECTORS: u32 = 2048;
+
+/// 头部中各字段的位置
+const NAME: core::ops::Range<usize> = 0..100;
+const SIZE: core::ops::Range<usize> = 124..136;
+const CHECKSUM: core::ops::Range<usize> = 148..156;
+const TYPE_FLAG: usize = 156;
+const MAGIC: core::ops::Range<usize> = 257..262;
+const PREFIX: core::ops::Range<usize> = 345..500;
+
+static ARCHIVE: OnceCell<Archive> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// 第 n 块的头部校验和不对
+    BadChecksum(usize),
+    /// 第 n 块的头部中有不是八进制数的字段
+    BadNumber(usize),
+    /// 第 n 块的头部中的文件名不是 UTF-8
+    BadName(usize),
+    /// 第 n 块的条目的内容或者结束标记超出了归档的末尾
+    Truncated(usize),
+    /// 堆中放不下第 n 块的条目的内容
+    OutOfMemory(usize),
+    Disk(AtaError),
+    /// 既没有启动模块也没有磁盘，或者第一块不是 USTAR 头部（例如主盘是引导镜像）
+    NoArchive,
+    AlreadyLoaded,
+}
+
+impl From<AtaError> for FsError {
+    fn from(error: AtaError) -> Self {
+        FsError::Disk(error)
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsError::BadChecksum(block) => write!(f, "bad header checksum at block {}", block),
+            FsError::BadNumber(block) => write!(f, "bad number in header at block {}", block),
+            FsError::BadName(block) => write!(f, "bad file name at block {}", block),
+            FsError::Truncated(block) => write!(f, "archive truncated at block {}", block),
+            FsError::OutOfMemory(block) => write!(f, "out of memory at block {}", block),
+            FsError::Disk(error) => write!(f, "disk: {}", error),
+            FsError::NoArchive => write!(f, "no archive"),
+            FsError::AlreadyLoaded => write!(f, "already loaded"),
+        }
+    }
+}
+
+/// list 返回的文件信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileInfo<'a> {
+    pub name: &'a str,
+    pub size: usize,
+}
+
+struct File {
+    name: String,
+    data: Vec<u8>,
+}
+
+/// 解析后的归档
+pub struct Archive {
+    files: Vec<File>,
+}
+
+/// 归档所在的存储，按块读取
+trait Source {
+    /// 总块数
+    fn blocks(&self) -> usize;
+    /// 从第 block 块开始读满 buf，调用者保证不超出末尾
+    fn read(&mut self, block: usize, buf: &mut [u8]) -> Result<(), FsError>;
+}
+
+impl Source for &[u8] {
+    fn blocks(&self) -> usize {
+        self.len() / BLOCK_SIZE
+    }
+
+    fn read(&mut self, block: usize, buf: &mut [u8]) -> Result<(), FsError> {
+        let start = block * BLOCK_SIZE;
+        buf.copy_from_slice(&self[start..start + buf.len()]);
+        Ok(())
+    }
+}
+
+/// 磁盘开头的若干扇区，逐个条目读取，不需要把整个归档放进堆中
+struct Disk {
+    drive: Drive,
+    sectors: u32,
+}
+
+impl Source for Disk {
+    fn blocks(&self) -> usize {
+        self.sectors as usize
+    }
+
+    fn read(&mut self, block: usize, buf: &mut [u8]) -> Result<(), FsError> {
+        let (whole, tail) = buf.split_at_mut(buf.len() / BLOCK_SIZE * BLOCK_SIZE);
+        ata::read_sectors(self.drive, block as u32, whole)?;
+        if !tail.is_empty() {
+            let mut sector = [0; BLOCK_SIZE];
+            let lba = (block + whole.len() / BLOCK_SIZE) as u32;
+            ata::read_sectors(self.drive, lba, &mut sector)?;
+            tail.copy_from_slice(&sector[..tail.len()]);
+        }
+        Ok(())
+    }
+}
+
+/// 去掉开头的 "./"，"./a.txt" 和 "a.txt" 是同一个文件
+fn normalize(name: &str) -> &str {
+    let mut name = name;
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest;
+    }
+    name
+}
+
+/// 字段中第一个 NUL 之前的部分
+fn until_nul(field: &[u8]) -> &[u8] {
+    let len = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    &field[..len]
+}
+
+/// 解析八进制字段：可以有前导空格，数字之后只能是 NUL 或空格
+fn octal(field: &[u8]) -> Option<u64> {
+    let start = field.iter().position(|&byte| byte != b' ')?;
+    let field = &field[start..];
+    let digits = field
+        .iter()
+        .position(|byte| !(b'0'..=b'7').contains(byte))
+        .unwrap_or(field.len());
+    if digits == 0
+        || field[digits..]
+            .iter()
+            .any(|&byte| byte != 0 && byte != b' ')
+    {
+        return None;
+    }
+    field[..digits].iter().try_fold(0u64, |value, &digit| {
+        value.checked_mul(8)?.checked_add((digit - b'0') as u64)
+    })
+}
+
+fn checksum(header: &[u8; BLOCK_SIZE]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(index, &byte)| {
+            if CHECKSUM.contains(&index) {
+                b' ' as u64
+            } else {
+                byte as u64
+            }
+        })
+        .sum()
+}
+
+/// 完整的文件名：USTAR 中超过 100 字节的名称把目录部分放在 prefix 中
+fn full_name(header: &[u8; BLOCK_SIZE], block: usize) -> Result<String, FsError> {
+    let utf8 = |bytes| core::str::from_utf8(bytes).map_err(|_| FsError::BadName(block));
+    let name = utf8(until_nul(&header[NAME]))?;
+    let prefix = if &header[MAGIC] == b"ustar" {
+        utf8(until_nul(&header[PREFIX]))?
+    } else {
+        ""
+    };
+    let mut full = String::new();
+    if !prefix.is_empty() {
+        full.push_str(prefix);
+        full.push('/');
+    }
+    full.push_str(name);
+    Ok(full)
+}
+
+impl Archive {
+    /// 解析内存中的归档，长度应当是 512 的倍数，末尾不足一块的部分被忽略
+    pub fn parse(bytes: &[u8]) -> Result<Archive, FsError> {
+        Archive::load(&mut &*bytes)
+    }
+
+    fn load(source: &mut impl Source) -> Result<Archive, FsError> {
+        let mut files: Vec<File> = Vec::new();
+        let mut block = 0;
+        loop {
+            if block >= source.blocks() {
+                return Err(FsError::Truncated(block));
+            }
+            let mut header = [0; BLOCK_SIZE];
+            source.read(block, &mut header)?;
+            if header.iter().all(|&byte| byte == 0) {
+                break;
+            }
+            // 默认启动时主盘是引导镜像，开头不是归档，不算作损坏
+            if block == 0 && &header[MAGIC] != b"ustar" {
+                return Err(FsError::NoArchive);
+            }
+            let stored = octal(&header[CHECKSUM]).ok_or(FsError::BadNumber(block))?;
+            if stored != checksum(&header) {
+                return Err(FsError::BadChecksum(block));
+            }
+            let size = octal(&header[SIZE]).ok_or(FsError::BadNumber(block))? as usize;
+            let data_block = block + 1;
+            let next = data_block + size.div_ceil(BLOCK_SIZE);
+            if next > source.blocks() {
+                return Err(FsError::Truncated(block));
+            }
+
+            // '0' 和 NUL 是普通文件，'7' 是连续文件，按普通文件处理
+            if matches!(header[TYPE_FLAG], b'0' | 0 | b'7') {
+                let name = full_name(&header, block)?;
+                let mut data = Vec::new();
+                data.try_reserve_exact(size)
+                    .map_err(|_| FsError::OutOfMemory(block))?;
+                data.resize(size, 0);
+                source.read(data_block, &mut data)?;
+                let name = String::from(normalize(&name));
+                match files.iter_mut().find(|file| file.name == name) {
+                    Some(file) => file.data = data,
+                    None => files.push(File { name, data }),
+                }
+            }
+            block = next;
+        }
+        Ok(Archive { files })
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = FileInfo<'_>> {
+        self.files.iter().map(|file| FileInfo {
+            name: &file.name,
+            size: file.data.len(),
+        })
+    }
+
+    pub fn read(&self, name: &str) -> Option<&[u8]> {
+        let name = normalize(name);
+        self.files
+            .iter()
+            .find(|file| file.name == name)
+            .map(|file| file.data.as_slice())
+    }
+}
+
+/// 加载归档，需要在堆和 ATA 驱动初始化之后调用，返回文件数
+pub fn init() -> Result<usize, FsError> {
+    let archive = match multiboot2::module() {
+        Some(module) => Archive::parse(module)?,
+        None => {
+            let identity = ata::identity(Drive::Master).ok_or(FsError::NoArchive)?;
+            Archive::load(&mut Disk {
+                drive: Drive::Master,
+                sectors: identity.sectors.min(DISK_SECTORS),
+            })?
+        }
+    };
+    let count = archive.files.len();
+    ARCHIVE
+        .try_init_once(|| archive)
+        .map_err(|_| FsError::AlreadyLoaded)?;
+    Ok(count)
+}
+
+/// 所有文件，加载之前为空
+pub fn list() -> impl Iterator<Item = FileInfo<'static>> {
+    ARCHIVE.get().into_iter().flat_map(Archive::list)
+}
+
+/// 文件的内容，名称开头的 "./" 被忽略
+pub fn read(name: &str) -> Option<&'static [u8]> {
+    ARCHIVE.get()?.read(name)
+}
+
+/// 重新计算并写入校验和
+#[cfg(test)]
+fn seal(header: &mut [u8; BLOCK_SIZE]) {
+    let sum = alloc::format!("{:06o}\0 ", checksum(header));
+    header[CHECKSUM].copy_from_slice(sum.as_bytes());
+}
+
+/// 按 USTAR 的格式构造头部，内容补齐到 512 字节
+#[cfg(test)]
+fn push_entry(archive: &mut Vec<u8>, name: &str, type_flag: u8, data: &[u8]) {
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    let size = alloc::format!("{:011o}", data.len());
+    header[SIZE.start..SIZE.start + 11].copy_from_slice(size.as_bytes());
+    header[TYPE_FLAG] = type_flag;
+    header[MAGIC].copy_from_slice(b"ustar");
+    header[263..265].copy_from_slice(b"00");
+    seal(&mut header);
+
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(BLOCK_SIZE), 0);
+}
+
+#[cfg(test)]
+fn sample_archive() -> Vec<u8> {
+    let mut archive = Vec::new();
+    push_entry(&mut archive, "./", b'5', &[]);
+    push_entry(&mut archive, "./hello.txt", b'0', b"hello, world\n");
+    let big: Vec<u8> = (0..700).map(|i| i as u8).collect();
+    push_entry(&mut archive, "./data/big.bin", b'0', &big);
+    archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+    archive
+}
+
+#[test_case]
+fn test_parse_archive() {
+    let bytes = sample_archive();
+    let archive = Archive::parse(&bytes).unwrap();
+    let files: Vec<FileInfo> = archive.list().collect();
+    assert_eq!(
+        files,
+        [
+            FileInfo {
+                name: "hello.txt",
+                size: 13
+            },
+            FileInfo {
+                name: "data/big.bin",
+                size: 700
+            },
+        ]
+    );
+    assert_eq!(archive.read("hello.txt"), Some(&b"hello, world\n"[..]));
+    assert_eq!(archive.read("./hello.txt"), archive.read("hello.txt"));
+    // 内容不是 512 的倍数，跨两个块
+    let big = archive.read("data/big.bin").unwrap();
+    assert_eq!(big.len(), 700);
+    assert_eq!(big[699], (699 % 256) as u8);
+    assert_eq!(archive.read("missing"), None);
+
+    // 只有结束标记的归档是空的
+    let empty = Archive::parse(&[0; BLOCK_SIZE]).unwrap();
+    assert_eq!(empty.list().count(), 0);
+}
+
+#[test_case]
+fn test_reject_corrupt_archives() {
+    let bytes = sample_archive();
+
+    let mut corrupt = bytes.clone();
+    // hello.txt 的头部在第 1 块
+    corrupt[BLOCK_SIZE + 3] ^= 0x20;
+    assert_eq!(
+        Archive::parse(&corrupt).err(),
+        Some(FsError::BadChecksum(1))
+    );
+
+    let mut bad_size = bytes.clone();
+    let header: &mut [u8; BLOCK_SIZE] = (&mut bad_size[BLOCK_SIZE..2 * BLOCK_SIZE])
+        .try_into()
+        .unwrap();
+    header[SIZE.start] = b'9';
+    seal(header);
+    assert_eq!(Archive::parse(&bad_size).err(), Some(FsError::BadNumber(1)));
+
+    // big.bin 的头部在第 3 块，内容占第 4、5 块
+    assert_eq!(
+        Archive::parse(&bytes[..5 * BLOCK_SIZE]).err(),
+        Some(FsError::Truncated(3))
+    );
+    // 缺少结束标记
+    assert_eq!(
+        Archive::parse(&bytes[..6 * BLOCK_SIZE]).err(),
+        Some(FsError::Truncated(6))
+    );
+    assert_eq!(Archive::parse(&[]).err(), Some(FsError::Truncated(0)));
+}
+
+#[test_case]
+fn test_non_archive_is_no_archive() {
+    // 像引导扇区一样开头有代码、末尾是 0x55AA 的磁盘
+    let mut disk = [0u8; 2 * BLOCK_SIZE];
+    disk[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+    disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+    assert_eq!(Archive::parse(&disk).err(), Some(FsError::NoArchive));
+
+    // 第一块是 USTAR 头部但校验和不对时仍然报告损坏
+    let mut corrupt = sample_archive();
+    corrupt[3] ^= 0x20;
+    assert_eq!(
+        Archive::parse(&corrupt).err(),
+        Some(FsError::BadChecksum(0))
+    );
+}
@@ -0,0 +1,87 @@
+//! 全局描述符表（GDT）与任务状态段（TSS）
+//! 64 位模式下分段基本不再使用，但 TSS 中的中断栈表（IST）可以为特定异常提供独立的栈，
+//! 这样内核栈溢出触发的 double fault 不会因为再次压栈失败而变成 triple fault
+//!
+//! 用户态（ring 3）需要 DPL 为 3 的代码段和数据段；从用户态进入中断时 CPU 切换到 TSS 中 RSP0 指向的内核栈
+use lazy_static::lazy_static;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+lazy_static! {
+    static ref TSS: TaskStateSegment = {
+        let mut tss = TaskStateSegment::new();
+        tss.privilege_stack_table[0] = {
+            const STACK_SIZE: usize = 4096 * 5;
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(&raw const STACK);
+            stack_start + STACK_SIZE as u64
+        };
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+            const STACK_SIZE: usize = 4096 * 5;
+            // 还没有内存管理，先用一个静态数组充当栈
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(&raw const STACK);
+            // 栈从高地址向低地址增长
+            stack_start + STACK_SIZE as u64
+        };
+        tss
+    };
+}
+
+lazy_static! {
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code_selector = gdt.append(Descriptor::kernel_code_segment());
+        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+        // append 按描述符的 DPL 设置选择子的 RPL，用户段的选择子已经带有 RPL 3
+        let user_data_selector = gdt.append(Descriptor::user_data_segment());
+        let user_code_selector = gdt.append(Descriptor::user_code_segment());
+        (
+            gdt,
+            Selectors {
+                code_selector,
+                tss_selector,
+                user_code_selector,
+                user_data_selector,
+            },
+        )
+    };
+}
+
+struct Selectors {
+    code_selector: SegmentSelector,
+    tss_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
+}
+
+/// 用户态的代码段和数据段选择子，iretq 进入 ring 3 时分别放入 CS 和 SS
+pub fn user_selectors() -> (SegmentSelector, SegmentSelector) {
+    (GDT.1.user_code_selector, GDT.1.user_data_selector)
+}
+
+pub fn init() {
+    use x86_64::instructions::segmentation::{Segment, CS};
+    use x86_64::instructions::tables::load_tss;
+
+    GDT.0.load();
+    unsafe {
+        // 重新加载 GDT 后，旧的段寄存器可能指向无效的描述符
+        CS::set_reg(GDT.1.code_selector);
+        load_tss(GDT.1.tss_selector);
+    }
+}
+
+/// AP 使用同一个 GDT，但不加载 TSS：TSS 描述符已经被 BSP 标记为 busy，再次加载会触发 #GP。
+/// 因此 AP 上没有 IST，double fault 不会切换栈，也不能从用户态进入内核
+pub(crate) fn init_ap() {
+    use x86_64::instructions::segmentation::{Segment, CS};
+
+    GDT.0.load();
+    unsafe { CS::set_reg(GDT.1.code_selector) };
+}
@@ -0,0 +1,332 @@
+//! VGA mode 13h 图形模式
+//! 320x200，每个像素一个字节，是调色板（DAC）中的颜色编号。序列器打开 chain-4 后，
+//! 0xA0000 开始的 64000 字节按行优先排列，第 y 行第 x 列的像素在 y * 320 + x 处。
+//!
+//! enter_mode13h 在切换之前保存回到文本模式需要的东西：
+//! - 屏幕快照：文本缓冲区所在的平面 0、1 会被像素覆盖
+//! - 字库：字库在平面 2 中，chain-4 下每 4 个像素中就有一个写进平面 2，不保存的话回到文本模式后字形是乱的
+//! - 调色板：load_palette 修改的 DAC 项也被文本模式使用
+//!
+//! exit_to_text 依次恢复寄存器、字库、调色板和屏幕快照。
+//! 图形模式下 0xB8000 不再被 VGA 解码，期间 print! 的内容会丢失，退出后屏幕回到进入前的样子。
+//! enter_mode13h 会锁 WRITER 保存快照，不能在持有 WRITER 的锁时调用（例如在 shell 命令中）
+use crate::memory;
+use crate::statusbar;
+use crate::time;
+use crate::vga_buffer::{self, Snapshot, WRITER};
+use crate::vga_mode::{
+    self, write_indexed, ModeRegisters, VgaMode, GRAPHICS_INDEX, SEQUENCER_INDEX, TEXT_GRAPHICS,
+    TEXT_SEQUENCER,
+};
+use core::fmt;
+use core::ops::Range;
+use spin::Mutex;
+use volatile::Volatile;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 200;
+const FRAMEBUFFER_ADDRESS: u64 = 0xA0000;
+
+/// 256 个字符，每个字符在平面 2 中占 32 字节（8x16 字库只用前 16 字节）
+const FONT_SIZE: usize = 256 * 32;
+const DAC_READ_INDEX: u16 = 0x3C7;
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+const DAC_DATA: u16 = 0x3C9;
+/// 256 项，每项是 6 位的 R、G、B
+const DAC_SIZE: usize = 256 * 3;
+
+/// mode 13h 的寄存器值
+const MODE_13H: ModeRegisters = ModeRegisters {
+    misc: 0x63,
+    // 8 点字符时钟，打开 chain-4
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x0E],
+    // 400 条扫描线，每行像素扫描两次
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F, 0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x9C, 0x0E, 0x8F, 0x28, 0x40, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    // 256 色移位模式，显存映射到 0xA0000 开始的 64 KiB
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF],
+    // 前 16 项直接对应 DAC 的前 16 项，模式寄存器选择图形模式和 8 位像素
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E,
+        0x0F, 0x41, 0x00, 0x0F, 0x00, 0x00,
+    ],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsError {
+    /// 已经在图形模式中
+    AlreadyActive,
+    /// 当前不是能够恢复的文本模式
+    NotText(VgaMode),
+    /// 物理内存映射还没有初始化
+    NoPhysicalMapping,
+}
+
+impl fmt::Display for GraphicsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphicsError::AlreadyActive => write!(f, "already in graphics mode"),
+            GraphicsError::NotText(mode) => write!(f, "cannot leave {:?} mode", mode),
+            GraphicsError::NoPhysicalMapping => write!(f, "physical memory is not mapped"),
+        }
+    }
+}
+
+/// 进入图形模式前保存的状态
+struct Saved {
+    mode: VgaMode,
+    font: [u8; FONT_SIZE],
+    dac: [u8; DAC_SIZE],
+    screen: Snapshot,
+}
+
+static SAVED: Mutex<Option<Saved>> = Mutex::new(None);
+
+type Pixels = [[Volatile<u8>; WIDTH]; HEIGHT];
+
+/// mode 13h 的显存，只能由 enter_mode13h 得到，交给 exit_to_text 后失效
+pub struct Framebuffer {
+    pixels: &'static mut Pixels,
+}
+
+impl Framebuffer {
+    /// 超出屏幕的坐标被忽略
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: u8) {
+        if x < WIDTH && y < HEIGHT {
+            self.pixels[y][x].write(color);
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        (x < WIDTH && y < HEIGHT).then(|| self.pixels[y][x].read())
+    }
+
+    /// 填充左上角在 (x, y) 的矩形，超出屏幕的部分被裁剪
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u8) {
+        let Some((columns, rows)) = clip(x, y, width, height) else {
+            return;
+        };
+        for row in rows {
+            for col in columns.clone() {
+                self.pixels[row][col].write(color);
+            }
+        }
+    }
+
+    pub fn clear(&mut self, color: u8) {
+        self.fill_rect(0, 0, WIDTH, HEIGHT, color);
+    }
+
+    /// 一批绘制操作，打开了垂直回扫同步时先等到回扫开始，让这批写入尽量落在消隐期间。
+    /// 消隐只有约 1ms，一批绘制应该尽量小
+    pub fn batch<R>(&mut self, f: impl FnOnce(&mut Framebuffer) -> R) -> R {
+        vga_mode::sync_to_vretrace();
+        f(self)
+    }
+}
+
+/// 把矩形裁剪到屏幕内，返回 (列范围, 行范围)，完全在屏幕外或者为空时返回 None
+fn clip(x: usize, y: usize, width: usize, height: usize) -> Option<(Range<usize>, Range<usize>)> {
+    let right = x.saturating_add(width).min(WIDTH);
+    let bottom = y.saturating_add(height).min(HEIGHT);
+    (x < right && y < bottom).then_some((x..right, y..bottom))
+}
+
+/// 从 start 开始设置调色板，颜色是 6 位的 (R, G, B)，超过 63 的部分被截掉，超出 255 的项被忽略
+pub fn load_palette(start: u8, colors: &[[u8; 3]]) {
+    let count = colors.len().min(256 - start as usize);
+    unsafe {
+        Port::<u8>::new(DAC_WRITE_INDEX).write(start);
+        let mut data = Port::<u8>::new(DAC_DATA);
+        for color in &colors[..count] {
+            for &component in color {
+                data.write(component & 0x3F);
+            }
+        }
+    }
+}
+
+/// 切换到 320x200x256 图形模式，屏幕被清为颜色 0
+pub fn enter_mode13h() -> Result<Framebuffer, GraphicsError> {
+    let mut saved = SAVED.lock();
+    if saved.is_some() {
+        return Err(GraphicsError::AlreadyActive);
+    }
+    let mode = vga_mode::current_mode();
+    if !matches!(mode, VgaMode::Text80x25 | VgaMode::Text80x50) {
+        return Err(GraphicsError::NotText(mode));
+    }
+    let address = memory::phys_to_virt(PhysAddr::new(FRAMEBUFFER_ADDRESS))
+        .ok_or(GraphicsError::NoPhysicalMapping)?;
+
+    statusbar::pause();
+    let screen = interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.snap_to_bottom();
+        writer.snapshot()
+    });
+    let state = saved.insert(Saved {
+        mode,
+        font: [0; FONT_SIZE],
+        dac: [0; DAC_SIZE],
+        screen,
+    });
+    unsafe {
+        with_font_plane(address.as_mut_ptr(), |plane| {
+            for (index, byte) in state.font.iter_mut().enumerate() {
+                *byte = plane.add(index).read_volatile();
+            }
+        });
+        Port::<u8>::new(DAC_READ_INDEX).write(0);
+        let mut data = Port::<u8>::new(DAC_DATA);
+        for component in state.dac.iter_mut() {
+            *component = data.read();
+        }
+        vga_mode::write_registers(&MODE_13H);
+    }
+
+    let mut framebuffer = Framebuffer {
+        pixels: unsafe { &mut *address.as_mut_ptr::<Pixels>() },
+    };
+    framebuffer.clear(0);
+    Ok(framebuffer)
+}
+
+/// 回到进入图形模式之前的文本模式，恢复字库、调色板和屏幕内容
+pub fn exit_to_text(framebuffer: Framebuffer) {
+    let address = framebuffer.pixels as *mut Pixels as *mut u8;
+    let Some(state) = SAVED.lock().take() else {
+        return;
+    };
+    vga_mode::restore_mode(state.mode).expect("saved mode is a text mode");
+    unsafe {
+        with_font_plane(address, |plane| {
+            for (index, &byte) in state.font.iter().enumerate() {
+                plane.add(index).write_volatile(byte);
+            }
+        });
+        Port::<u8>::new(DAC_WRITE_INDEX).write(0);
+        let mut data = Port::<u8>::new(DAC_DATA);
+        for &component in state.dac.iter() {
+            data.write(component);
+        }
+    }
+    vga_buffer::redraw(|writer| writer.restore(&state.screen));
+    statusbar::resume();
+}
+
+/// 让 0xA0000 处（window 是它的虚拟地址）只读写平面 2，f 返回后把用到的寄存器恢复为文本模式的值
+unsafe fn with_font_plane(window: *mut u8, f: impl FnOnce(*mut u8)) {
+    // 只写平面 2，关闭 odd/even 按顺序寻址
+    write_indexed(SEQUENCER_INDEX, 0x02, 0x04);
+    write_indexed(SEQUENCER_INDEX, 0x04, 0x06);
+    // 从平面 2 读，显存映射到 0xA0000
+    write_indexed(GRAPHICS_INDEX, 0x04, 0x02);
+    write_indexed(GRAPHICS_INDEX, 0x05, 0x00);
+    write_indexed(GRAPHICS_INDEX, 0x06, 0x04);
+    f(window);
+    for index in [0x02, 0x04] {
+        write_indexed(SEQUENCER_INDEX, index, TEXT_SEQUENCER[index as usize]);
+    }
+    for index in [0x04, 0x05, 0x06] {
+        write_indexed(GRAPHICS_INDEX, index, TEXT_GRAPHICS[index as usize]);
+    }
+}
+
+/// 从黑到白的第 level 级灰度（共 levels 级），6 位颜色
+fn gray(level: usize, levels: usize) -> [u8; 3] {
+    let value = if levels > 1 {
+        (level.min(levels - 1) * 63 / (levels - 1)) as u8
+    } else {
+        63
+    };
+    [value; 3]
+}
+
+/// 演示用的灰度从调色板的这一项开始
+const GRAY_BASE: u8 = 64;
+const GRAY_LEVELS: usize = 64;
+/// 演示画面停留的时间
+const DEMO_MS: u32 = 3000;
+
+/// 上半屏画水平的灰度渐变，下半屏画几个矩形，停留几秒后回到文本模式
+pub fn demo() -> Result<(), GraphicsError> {
+    let mut framebuffer = enter_mode13h()?;
+    let grays: [[u8; 3]; GRAY_LEVELS] = core::array::from_fn(|level| gray(level, GRAY_LEVELS));
+    load_palette(GRAY_BASE, &grays);
+
+    for x in 0..WIDTH {
+        let color = GRAY_BASE + (x * GRAY_LEVELS / WIDTH) as u8;
+        framebuffer.fill_rect(x, 0, 1, HEIGHT / 2, color);
+    }
+    // 默认调色板的前 16 项是文本模式的 16 种颜色
+    framebuffer.batch(|framebuffer| {
+        framebuffer.fill_rect(20, 120, 80, 60, 4);
+        framebuffer.fill_rect(120, 130, 80, 50, 2);
+        framebuffer.fill_rect(220, 110, 60, 70, 1);
+        // 超出右下角的部分被裁剪
+        framebuffer.fill_rect(290, 170, 100, 100, 14);
+    });
+
+    time::delay_ms(DEMO_MS);
+    exit_to_text(framebuffer);
+    Ok(())
+}
+
+#[test_case]
+fn test_mode13h_register_tables() {
+    let crtc = &MODE_13H.crtc;
+    // 寄存器 0x12 加上溢出寄存器 0x07 的 bit 1 和 bit 6 是最后一条可见扫描线
+    let vertical_display_end = crtc[0x12] as usize
+        | ((crtc[0x07] as usize >> 1) & 1) << 8
+        | ((crtc[0x07] as usize >> 6) & 1) << 9;
+    let scan_lines_per_row = (crtc[0x09] & 0x1F) as usize + 1;
+    assert_eq!((vertical_display_end + 1) / scan_lines_per_row, HEIGHT);
+    // 水平显示结束 + 1 是字符时钟数，256 色模式下每个字符时钟 4 个像素
+    assert_eq!((crtc[0x01] as usize + 1) * 4, WIDTH);
+    // chain-4，显存映射到 0xA0000
+    assert_ne!(MODE_13H.sequencer[0x04] & 0x08, 0);
+    assert_eq!((MODE_13H.graphics[0x06] >> 2) & 0x03, 1);
+    assert_eq!(
+        vga_mode::decode_mode(
+            MODE_13H.graphics[0x06],
+            MODE_13H.attribute[0x10],
+            crtc[0x09]
+        ),
+        VgaMode::Graphics
+    );
+}
+
+#[test_case]
+fn test_clip() {
+    assert_eq!(clip(0, 0, WIDTH, HEIGHT), Some((0..WIDTH, 0..HEIGHT)));
+    assert_eq!(clip(310, 195, 100, 100), Some((310..WIDTH, 195..HEIGHT)));
+    assert_eq!(clip(5, 6, 1, 1), Some((5..6, 6..7)));
+    assert_eq!(clip(WIDTH, 0, 10, 10), None);
+    assert_eq!(clip(0, HEIGHT, 10, 10), None);
+    assert_eq!(clip(3, 3, 0, 10), None);
+    assert_eq!(clip(3, 3, 10, 0), None);
+    assert_eq!(clip(0, 0, 0, 0), None);
+    // 溢出时不能回绕到屏幕内
+    assert_eq!(clip(usize::MAX, 0, usize::MAX, 1), None);
+    assert_eq!(
+        clip(1, 1, usize::MAX, usize::MAX),
+        Some((1..WIDTH, 1..HEIGHT))
+    );
+}
+
+#[test_case]
+fn test_gray_ramp() {
+    assert_eq!(gray(0, GRAY_LEVELS), [0; 3]);
+    assert_eq!(gray(GRAY_LEVELS - 1, GRAY_LEVELS), [63; 3]);
+    assert_eq!(gray(GRAY_LEVELS, GRAY_LEVELS), [63; 3]);
+    assert_eq!(gray(0, 1), [63; 3]);
+    assert!(
+        (1..GRAY_LEVELS).all(|level| gray(level, GRAY_LEVELS)[0] > gray(level - 1, GRAY_LEVELS)[0])
+    );
+}
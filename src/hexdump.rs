@@ -0,0 +1,157 @@
+//! 规范格式（hexdump -C）的十六进制转储
+//! 每行 16 个字节：地址、两组各 8 个字节的十六进制、竖线之间的 ASCII，不可打印的字节显示为 '.'：
+//!
+//! ```text
+//! 00001000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|
+//! ```
+//!
+//! 行格式化只写入调用者提供的缓冲区、不分配内存，屏幕（Writer::hexdump）和串口（serial_hexdump）共用
+//! 地址至少 8 位，8 位时一行 78 个字符，正好放得进屏幕的一行
+use crate::task::yield_now;
+
+pub const BYTES_PER_LINE: usize = 16;
+/// 一行最长的长度：16 位地址时
+pub const LINE_CAPACITY: usize = 16 + 2 + BYTES_PER_LINE * 3 + 2 + BYTES_PER_LINE + 2;
+
+/// 把从 address 开始的最多 16 个字节格式化为一行，不含换行符
+/// 不满 16 个字节时十六进制部分用空格补齐，ASCII 部分仍然对齐
+pub fn format_line<'a>(address: u64, chunk: &[u8], buf: &'a mut [u8; LINE_CAPACITY]) -> &'a str {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+
+    let mut len = 0;
+    let mut push = |byte: u8| {
+        buf[len] = byte;
+        len += 1;
+    };
+
+    let digits = (64 - address.leading_zeros() as usize).div_ceil(4).max(8);
+    for shift in (0..digits).rev() {
+        push(HEX[(address >> (shift * 4)) as usize & 0xf]);
+    }
+    push(b' ');
+    for i in 0..BYTES_PER_LINE {
+        // 两组之间多一个空格
+        if i % 8 == 0 {
+            push(b' ');
+        }
+        match chunk.get(i) {
+            Some(&byte) => {
+                push(HEX[(byte >> 4) as usize]);
+                push(HEX[(byte & 0xf) as usize]);
+            }
+            None => {
+                push(b' ');
+                push(b' ');
+            }
+        }
+        push(b' ');
+    }
+    push(b' ');
+    push(b'|');
+    for &byte in chunk.iter().take(BYTES_PER_LINE) {
+        push(match byte {
+            0x20..=0x7e => byte,
+            _ => b'.',
+        });
+    }
+    push(b'|');
+
+    // 只写入了 ASCII 字符
+    core::str::from_utf8(&buf[..len]).unwrap()
+}
+
+/// 逐行格式化 bytes，每一行交给 emit
+pub fn for_each_line(base: u64, bytes: &[u8], mut emit: impl FnMut(&str)) {
+    let mut buf = [0; LINE_CAPACITY];
+    for (index, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let address = base.wrapping_add((index * BYTES_PER_LINE) as u64);
+        emit(format_line(address, chunk, &mut buf));
+    }
+}
+
+/// 与 for_each_line 相同，每输出 lines_per_yield 行用 yield_now 让出一次执行权，
+/// 供在执行器中转储大块内存的调用者使用。emit 应当每次自己获取锁，让出期间不持有控制台
+pub async fn for_each_line_yielding(
+    base: u64,
+    bytes: &[u8],
+    lines_per_yield: usize,
+    mut emit: impl FnMut(&str),
+) {
+    let mut buf = [0; LINE_CAPACITY];
+    for (index, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        if index > 0 && index % lines_per_yield.max(1) == 0 {
+            yield_now().await;
+        }
+        let address = base.wrapping_add((index * BYTES_PER_LINE) as u64);
+        emit(format_line(address, chunk, &mut buf));
+    }
+}
+
+#[test_case]
+fn test_format_full_line() {
+    let mut buf = [0; LINE_CAPACITY];
+    let line = format_line(0x1000, b"Hello, world!\n\x00\xff", &mut buf);
+    assert_eq!(
+        line,
+        "00001000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|"
+    );
+    assert_eq!(line.len(), 78);
+}
+
+#[test_case]
+fn test_format_partial_line_and_wide_address() {
+    let mut buf = [0; LINE_CAPACITY];
+    assert_eq!(
+        format_line(0x20, b"abc", &mut buf),
+        "00000020  61 62 63                                          |abc|"
+    );
+    let line = format_line(u64::MAX - 15, &[0x7f; BYTES_PER_LINE], &mut buf);
+    assert!(line.starts_with("fffffffffffffff0  7f 7f"));
+    assert!(line.ends_with("|................|"));
+    assert_eq!(line.len(), LINE_CAPACITY);
+}
+
+#[test_case]
+fn test_for_each_line_addresses() {
+    let bytes = [0u8; 40];
+    let mut addresses = alloc::vec::Vec::new();
+    for_each_line(0xff8, &bytes, |line| {
+        addresses.push(alloc::string::String::from(&line[..8]))
+    });
+    assert_eq!(addresses, ["00000ff8", "00001008", "00001018"]);
+}
+
+#[test_case]
+fn test_yielding_dump_lets_other_tasks_run() {
+    use crate::task::executor::Executor;
+    use crate::task::Task;
+    use alloc::string::String;
+    use spin::Mutex;
+
+    static EVENTS: Mutex<alloc::vec::Vec<String>> = Mutex::new(alloc::vec::Vec::new());
+
+    async fn dump() {
+        let bytes = [0u8; 5 * BYTES_PER_LINE];
+        for_each_line_yielding(0, &bytes, 2, |line| {
+            EVENTS.lock().push(String::from(&line[..8]))
+        })
+        .await;
+    }
+
+    async fn other() {
+        for _ in 0..2 {
+            EVENTS.lock().push(String::from("other"));
+            yield_now().await;
+        }
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(dump()));
+    executor.spawn(Task::new(other()));
+    assert_eq!(executor.run_until_idle(), 0);
+    // 每两行让出一次
+    assert_eq!(
+        *EVENTS.lock(),
+        ["00000000", "00000010", "other", "00000020", "00000030", "other", "00000040"]
+    );
+}
@@ -0,0 +1,324 @@
+//! 中断描述符表（IDT）与 8259 可编程中断控制器（PIC）
+use crate::{backtrace, gdt, hlt_loop, mouse, println, ps2, time, usermode, vga_buffer, watchdog};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use pic8259::ChainedPics;
+use spin::Mutex;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::PrivilegeLevel;
+
+/// CPU 异常占用了 0-31 号中断，PIC 的中断向量从 32 开始重新映射
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+/// 主从两片级联的 PIC
+pub static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    /// IRQ0，由 PIT 通道 0 产生
+    Timer = PIC_1_OFFSET,
+    /// IRQ1，PS/2 键盘
+    Keyboard,
+    /// IRQ4，COM1 收到数据
+    Serial = PIC_1_OFFSET + 4,
+    /// IRQ12，PS/2 鼠标，接在从片的第 4 根线上
+    Mouse = PIC_2_OFFSET + 4,
+}
+
+/// 本地 APIC 的伪中断向量，低 4 位必须全为 1
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const SERIAL_IRQ: u8 = 4;
+pub const MOUSE_IRQ: u8 = 12;
+
+/// 初始化 PIC 之后只开放时钟（IRQ0）和级联（IRQ2），其余 IRQ 由各自的驱动在准备好之后解除屏蔽
+const INITIAL_MASKS: [u8; 2] = [0b1111_1010, 0b1111_1111];
+
+/// 收到的硬件中断（时钟、键盘、串口、鼠标）总数
+static INTERRUPT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// 开机以来收到的硬件中断数，不包括 CPU 异常和伪中断
+pub fn interrupt_count() -> u64 {
+    INTERRUPT_COUNT.load(Ordering::Relaxed)
+}
+
+pub fn init_pics() {
+    let mut pics = PICS.lock();
+    unsafe {
+        pics.initialize();
+        pics.write_masks(INITIAL_MASKS[0], INITIAL_MASKS[1]);
+    }
+}
+
+/// 解除某个 IRQ 线（0-15）的屏蔽
+pub fn unmask_irq(irq: u8) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        let mut masks = unsafe { pics.read_masks() };
+        masks[(irq / 8) as usize] &= !(1 << (irq % 8));
+        unsafe { pics.write_masks(masks[0], masks[1]) };
+    });
+}
+
+/// 关闭中断并屏蔽某个 IRQ 线执行 f，结束后恢复原来的屏蔽状态
+/// 用于需要直接轮询设备、不能让中断处理函数取走数据的场景
+pub fn with_irq_masked<R>(irq: u8, f: impl FnOnce() -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let masks = unsafe { PICS.lock().read_masks() };
+        let mut masked = masks;
+        masked[(irq / 8) as usize] |= 1 << (irq % 8);
+        unsafe { PICS.lock().write_masks(masked[0], masked[1]) };
+        let result = f();
+        unsafe { PICS.lock().write_masks(masks[0], masks[1]) };
+        result
+    })
+}
+
+impl InterruptIndex {
+    fn as_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        unsafe {
+            // double fault 切换到 IST 中的独立栈
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Serial.as_u8()].set_handler_fn(serial_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_u8()].set_handler_fn(mouse_interrupt_handler);
+        idt[SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
+        // 系统调用由汇编写的入口处理，DPL 为 3 才能在用户态通过 int 指令触发
+        unsafe {
+            idt[usermode::SYSCALL_VECTOR]
+                .set_handler_addr(usermode::syscall_entry_addr())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
+        idt
+    };
+}
+
+pub fn init_idt() {
+    IDT.load();
+}
+
+/// 异常处理函数保存的 CPU 上下文，见 stash_exception
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionContext {
+    pub name: &'static str,
+    pub instruction_pointer: u64,
+    pub stack_pointer: u64,
+    pub cpu_flags: u64,
+}
+
+impl ExceptionContext {
+    pub fn new(name: &'static str, stack_frame: &InterruptStackFrame) -> Self {
+        ExceptionContext {
+            name,
+            instruction_pointer: stack_frame.instruction_pointer.as_u64(),
+            stack_pointer: stack_frame.stack_pointer.as_u64(),
+            cpu_flags: stack_frame.cpu_flags.bits(),
+        }
+    }
+}
+
+impl fmt::Display for ExceptionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: rip {:#x}, rsp {:#x}, rflags {:#x}",
+            self.name, self.instruction_pointer, self.stack_pointer, self.cpu_flags
+        )
+    }
+}
+
+/// 正在处理的异常。PanicInfo 中没有 CPU 上下文，异常处理函数中（或者它没有返回就）panic 时，
+/// panic 报告从这里取出被打断的指令地址和标志。
+///
+/// 只考虑单核：AP 上发生的异常也写到这里，与 BSP 上的互相覆盖。
+/// 处理函数正常返回时恢复为进入之前的值；不返回（重启、停机、跳回用户程序的调用者）时会留下，
+/// 之后与异常无关的 panic 也会显示它，所以报告中写作 "last exception"
+static LAST_EXCEPTION: Mutex<Option<ExceptionContext>> = Mutex::new(None);
+
+/// stash_exception 返回的守卫，drop 时恢复进入之前的值，嵌套的异常返回后外层的上下文仍然有效
+#[must_use]
+pub struct ExceptionStash {
+    previous: Option<ExceptionContext>,
+}
+
+impl Drop for ExceptionStash {
+    fn drop(&mut self) {
+        *LAST_EXCEPTION.lock() = self.previous;
+    }
+}
+
+/// 在异常处理函数开头调用，守卫要一直持有到返回。
+/// 异常处理函数在关中断时运行，持锁期间不会再有异常，所以这里直接加锁
+pub fn stash_exception(context: ExceptionContext) -> ExceptionStash {
+    ExceptionStash {
+        previous: LAST_EXCEPTION.lock().replace(context),
+    }
+}
+
+/// 最近一次还没有返回的异常；panic 处理中使用，锁被占用时返回 None
+pub fn last_exception() -> Option<ExceptionContext> {
+    LAST_EXCEPTION.try_lock().and_then(|last| *last)
+}
+
+/// 伪中断不需要发送 EOI
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    let _stash = stash_exception(ExceptionContext::new("breakpoint", &stack_frame));
+    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+    backtrace::print();
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    let _stash = stash_exception(ExceptionContext::new("double fault", &stack_frame));
+    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+
+    // 用户程序的错误只结束这个程序
+    if stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3 {
+        usermode::fault(usermode::UserFault {
+            address: Cr2::read_raw(),
+            instruction: stack_frame.instruction_pointer.as_u64(),
+            error_code,
+        });
+    }
+
+    // 用户程序的错误不会回到这里，只有内核自己的缺页留下上下文
+    let _stash = stash_exception(ExceptionContext::new("page fault", &stack_frame));
+    println!("EXCEPTION: PAGE FAULT");
+    // CR2 保存了触发缺页的虚拟地址
+    println!("Accessed Address: {:?}", Cr2::read());
+    println!("Error Code: {:?}", error_code);
+    println!("{:#?}", stack_frame);
+    // 出错的指令本身是上面的 instruction_pointer，回溯从处理函数的调用者开始
+    backtrace::print();
+    hlt_loop();
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    INTERRUPT_COUNT.fetch_add(1, Ordering::Relaxed);
+    time::tick();
+    vga_buffer::soft_cursor::on_tick(time::ticks());
+    // 必须发送 EOI（end of interrupt），否则 PIC 不会再发出下一个中断
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    }
+    // 报告很长，在 EOI 之后打印；重启时不再返回
+    watchdog::on_tick(&stack_frame);
+}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+
+    INTERRUPT_COUNT.fetch_add(1, Ordering::Relaxed);
+    // 必须读出 0x60 端口的扫描码，否则键盘控制器不会发送下一个
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    // 指示灯命令的回应不是按键；解除 IRQ1 屏蔽后可能还会收到一次已经被读走的回应
+    if !matches!(scancode, ps2::ACK | ps2::RESEND) {
+        crate::task::keyboard::add_scancode(scancode);
+        crate::screensaver::record_activity();
+    }
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+
+    INTERRUPT_COUNT.fetch_add(1, Ordering::Relaxed);
+    // 直接读端口而不是 SERIAL1：被打断的代码可能正持有它的锁。
+    // 线路状态寄存器（0x3FD）bit 0 表示接收缓冲区中有数据，读完 FIFO 中的全部字节
+    let mut status = Port::<u8>::new(0x3fd);
+    let mut data = Port::<u8>::new(0x3f8);
+    while unsafe { status.read() } & 0x01 != 0 {
+        crate::task::serial::add_byte(unsafe { data.read() });
+    }
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Serial.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    INTERRUPT_COUNT.fetch_add(1, Ordering::Relaxed);
+    let mut controller = ps2::Controller::new();
+    // 初始化时轮询读走的回应也会触发 IRQ12，解除屏蔽后才送到；这时输出缓冲区是空的
+    if controller.status() & ps2::Controller::OUTPUT_FULL != 0 {
+        let mut port = x86_64::instructions::port::Port::new(0x60);
+        mouse::add_byte(unsafe { port.read() });
+    }
+
+    // 从片上的中断要同时向主片和从片发送 EOI，ChainedPics 会处理
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+    }
+}
+
+#[test_case]
+fn test_breakpoint_exception() {
+    // 断点异常处理完后应当继续执行
+    x86_64::instructions::interrupts::int3();
+}
+
+#[test_case]
+fn test_exception_stash_restores_outer_context() {
+    let outer = ExceptionContext {
+        name: "page fault",
+        instruction_pointer: 0x20_1234,
+        stack_pointer: 0x4444_0000,
+        cpu_flags: 0x246,
+    };
+    let inner = ExceptionContext {
+        name: "breakpoint",
+        ..outer
+    };
+    assert_eq!(last_exception(), None);
+    {
+        let _outer = stash_exception(outer);
+        {
+            let _inner = stash_exception(inner);
+            assert_eq!(last_exception(), Some(inner));
+        }
+        // 嵌套的处理函数返回后，外层仍在处理中
+        assert_eq!(last_exception(), Some(outer));
+    }
+    assert_eq!(last_exception(), None);
+    assert_eq!(
+        alloc::format!("{}", outer),
+        "page fault: rip 0x201234, rsp 0x44440000, rflags 0x246"
+    );
+}
@@ -0,0 +1,254 @@
+//! 控制台快捷键
+//! 按键在交给 read_line 之前先查这张表，绑定了动作的组合键被吞掉，不会作为字符回显，
+//! 没有绑定的组合键照常传下去。只匹配按下事件，按住时的重复按下会重复触发。
+//!
+//! 默认绑定：
+//! - Ctrl+L 清屏，Ctrl+C 取消当前输入行，Ctrl+U 删除整行输入
+//! - PageUp/PageDown 翻一屏，Shift+上/下 滚动一行
+//! - Alt+F1 到 Alt+F4 切换虚拟控制台
+//! - PrintScreen 通过串口导出屏幕的文字和颜色
+//!
+//! 其他模块可以在初始化时用 register 添加自己的绑定；同一个组合键只能绑定一次，
+//! 重复注册（包括与默认绑定冲突）会被拒绝而不是覆盖
+use crate::keyboard::{KeyCode, KeyEvent};
+use crate::vga_buffer::{Writer, BUFFER_HEIGHT};
+use spin::Mutex;
+
+/// 通过 register 添加的绑定数量上限
+pub const MAX_BINDINGS: usize = 32;
+
+/// 组合键：一个键加上按下时的修饰键，CapsLock 不参与匹配
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    pub code: KeyCode,
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl Chord {
+    pub const fn plain(code: KeyCode) -> Self {
+        Chord {
+            code,
+            shift: false,
+            ctrl: false,
+            alt: false,
+        }
+    }
+
+    pub const fn ctrl(code: KeyCode) -> Self {
+        Chord {
+            ctrl: true,
+            ..Chord::plain(code)
+        }
+    }
+
+    pub const fn alt(code: KeyCode) -> Self {
+        Chord {
+            alt: true,
+            ..Chord::plain(code)
+        }
+    }
+
+    pub const fn shift(code: KeyCode) -> Self {
+        Chord {
+            shift: true,
+            ..Chord::plain(code)
+        }
+    }
+
+    pub fn from_event(event: &KeyEvent) -> Self {
+        Chord {
+            code: event.code,
+            shift: event.modifiers.shift,
+            ctrl: event.modifiers.ctrl,
+            alt: event.modifiers.alt,
+        }
+    }
+}
+
+/// 绑定的动作，由输入路径（console）负责执行
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    ClearScreen,
+    /// 取消当前输入，read_line 返回 None
+    CancelLine,
+    /// 删除已经输入的整行
+    KillLine,
+    ScrollUp(usize),
+    ScrollDown(usize),
+    /// 切换到这个编号的虚拟控制台，没有这个控制台时什么也不做
+    SwitchConsole(usize),
+    /// 通过串口导出屏幕内容，见 console::screen_dump
+    DumpScreen,
+    /// 自定义处理函数，在持有 WRITER 锁、关闭中断的情况下调用，
+    /// 只能通过参数输出，不能使用 print!
+    Custom(fn(&mut Writer)),
+}
+
+const DEFAULT_BINDINGS: &[(Chord, Action)] = &[
+    (Chord::ctrl(KeyCode::L), Action::ClearScreen),
+    (Chord::ctrl(KeyCode::C), Action::CancelLine),
+    (Chord::ctrl(KeyCode::U), Action::KillLine),
+    (
+        Chord::plain(KeyCode::PageUp),
+        Action::ScrollUp(BUFFER_HEIGHT),
+    ),
+    (
+        Chord::plain(KeyCode::PageDown),
+        Action::ScrollDown(BUFFER_HEIGHT),
+    ),
+    (Chord::shift(KeyCode::ArrowUp), Action::ScrollUp(1)),
+    (Chord::shift(KeyCode::ArrowDown), Action::ScrollDown(1)),
+    (Chord::alt(KeyCode::F1), Action::SwitchConsole(0)),
+    (Chord::alt(KeyCode::F2), Action::SwitchConsole(1)),
+    (Chord::alt(KeyCode::F3), Action::SwitchConsole(2)),
+    (Chord::alt(KeyCode::F4), Action::SwitchConsole(3)),
+    (Chord::plain(KeyCode::PrintScreen), Action::DumpScreen),
+];
+
+static REGISTERED: Mutex<[Option<(Chord, Action)>; MAX_BINDINGS]> =
+    Mutex::new([None; MAX_BINDINGS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindError {
+    /// 这个组合键已经绑定了动作
+    AlreadyBound(Chord),
+    TableFull,
+}
+
+/// 添加一个绑定，组合键已经被绑定时返回错误
+pub fn register(chord: Chord, action: Action) -> Result<(), BindError> {
+    if find(chord).is_some() {
+        return Err(BindError::AlreadyBound(chord));
+    }
+    let mut registered = REGISTERED.lock();
+    let slot = registered
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(BindError::TableFull)?;
+    *slot = Some((chord, action));
+    Ok(())
+}
+
+fn find(chord: Chord) -> Option<Action> {
+    DEFAULT_BINDINGS
+        .iter()
+        .copied()
+        .chain(REGISTERED.lock().iter().flatten().copied())
+        .find(|(bound, _)| *bound == chord)
+        .map(|(_, action)| action)
+}
+
+/// 查找事件对应的动作，松开事件总是返回 None
+pub fn lookup(event: &KeyEvent) -> Option<Action> {
+    if !event.pressed {
+        return None;
+    }
+    find(Chord::from_event(event))
+}
+
+#[cfg(test)]
+use crate::keyboard::Modifiers;
+
+#[cfg(test)]
+fn event(code: KeyCode, modifiers: Modifiers, pressed: bool) -> KeyEvent {
+    KeyEvent {
+        code,
+        pressed,
+        modifiers,
+    }
+}
+
+#[cfg(test)]
+const CTRL: Modifiers = Modifiers {
+    ctrl: true,
+    ..Modifiers::NONE
+};
+
+#[test_case]
+fn test_default_bindings_match_exact_modifiers() {
+    assert!(matches!(
+        lookup(&event(KeyCode::L, CTRL, true)),
+        Some(Action::ClearScreen)
+    ));
+    // 没有 Ctrl 的 L 和多按了 Shift 的 Ctrl+L 都不匹配
+    assert!(lookup(&event(KeyCode::L, Modifiers::NONE, true)).is_none());
+    let ctrl_shift = Modifiers {
+        shift: true,
+        ..CTRL
+    };
+    assert!(lookup(&event(KeyCode::L, ctrl_shift, true)).is_none());
+    // CapsLock 不影响匹配
+    let ctrl_caps = Modifiers {
+        capslock: true,
+        ..CTRL
+    };
+    assert!(matches!(
+        lookup(&event(KeyCode::C, ctrl_caps, true)),
+        Some(Action::CancelLine)
+    ));
+}
+
+#[test_case]
+fn test_release_events_are_not_dispatched() {
+    assert!(lookup(&event(KeyCode::C, CTRL, false)).is_none());
+}
+
+#[test_case]
+fn test_register_rejects_conflicts() {
+    fn noop(_writer: &mut Writer) {}
+
+    let chord = Chord::alt(KeyCode::F12);
+    assert_eq!(register(chord, Action::Custom(noop)), Ok(()));
+    assert!(matches!(
+        lookup(&event(
+            KeyCode::F12,
+            Modifiers {
+                alt: true,
+                ..Modifiers::NONE
+            },
+            true
+        )),
+        Some(Action::Custom(_))
+    ));
+    assert_eq!(
+        register(chord, Action::ClearScreen),
+        Err(BindError::AlreadyBound(chord))
+    );
+    // 默认绑定同样不能被覆盖
+    let ctrl_c = Chord::ctrl(KeyCode::C);
+    assert_eq!(
+        register(ctrl_c, Action::ClearScreen),
+        Err(BindError::AlreadyBound(ctrl_c))
+    );
+}
+
+#[test_case]
+fn test_scancode_sequences_reach_bindings() {
+    use crate::keyboard::Decoder;
+    use alloc::vec::Vec;
+    use pc_keyboard::layouts::Us104Key;
+
+    // 从扫描码开始走完整的路径：修饰键的按下/松开跨越多个扫描码被记住
+    let actions = |scancodes: &[u8]| -> Vec<Action> {
+        let mut decoder = Decoder::new(Us104Key);
+        scancodes
+            .iter()
+            .filter_map(|&scancode| decoder.decode_input(scancode))
+            .filter_map(|input| lookup(&input.event))
+            .collect()
+    };
+    // Ctrl 按下，C 按下松开，Ctrl 松开，再单独按 C
+    let ctrl_c = actions(&[0x1d, 0x2e, 0xae, 0x9d, 0x2e]);
+    assert!(matches!(ctrl_c[..], [Action::CancelLine]));
+    // 右 Ctrl（0xE0 前缀）加 L
+    let ctrl_l = actions(&[0xe0, 0x1d, 0x26, 0xa6, 0xe0, 0x9d]);
+    assert!(matches!(ctrl_l[..], [Action::ClearScreen]));
+    // 右 Alt 加 F2，松开 Alt 后的 F2 不再匹配
+    let alt_f2 = actions(&[0xe0, 0x38, 0x3c, 0xbc, 0xe0, 0xb8, 0x3c]);
+    assert!(matches!(alt_f2[..], [Action::SwitchConsole(1)]));
+    // PrintScreen 的扫描码前面带着一个假的 E0 2A
+    let print_screen = actions(&[0xe0, 0x2a, 0xe0, 0x37, 0xe0, 0xb7, 0xe0, 0xaa]);
+    assert!(matches!(print_screen[..], [Action::DumpScreen]));
+}
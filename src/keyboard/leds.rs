@@ -0,0 +1,42 @@
+//! 键盘指示灯
+//! 解码器只在软件中记录 CapsLock 等开关状态，键盘上的指示灯需要通过 PS/2 命令 0xED
+//! 加一个状态字节来设置
+//!
+//! 交换期间关闭中断并屏蔽 IRQ1，回应字节不会被中断处理函数当作扫描码取走；
+//! 交换期间收到的其他键盘字节是用户按下的键，照常放进扫描码队列，
+//! 鼠标发来的字节交给鼠标驱动
+use crate::interrupts::{self, KEYBOARD_IRQ};
+use crate::ps2::{self, CommandError, Controller, KeyboardPort};
+
+const SET_LEDS: u8 = 0xed;
+
+/// 0xED 命令的状态字节：位 0 ScrollLock，位 1 NumLock，位 2 CapsLock
+pub fn led_byte(caps: bool, num: bool, scroll: bool) -> u8 {
+    (caps as u8) << 2 | (num as u8) << 1 | scroll as u8
+}
+
+/// 设置键盘指示灯
+/// 全局解码器切换开关状态时会自动调用，一般不需要手动调用
+pub fn set_leds(caps: bool, num: bool, scroll: bool) -> Result<(), CommandError> {
+    interrupts::with_irq_masked(KEYBOARD_IRQ, || {
+        // 中断已经关闭，鼠标中断处理函数不会同时访问鼠标驱动
+        let mut port = KeyboardPort {
+            controller: Controller::new(),
+            aux: crate::mouse::add_byte,
+        };
+        ps2::send_command(
+            &mut port,
+            &[SET_LEDS, led_byte(caps, num, scroll)],
+            crate::task::keyboard::add_scancode,
+        )
+    })
+}
+
+#[test_case]
+fn test_led_byte_bits() {
+    assert_eq!(led_byte(false, false, false), 0);
+    assert_eq!(led_byte(false, false, true), 0b001);
+    assert_eq!(led_byte(false, true, false), 0b010);
+    assert_eq!(led_byte(true, false, false), 0b100);
+    assert_eq!(led_byte(true, true, true), 0b111);
+}
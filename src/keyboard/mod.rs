@@ -0,0 +1,687 @@
+//! 扫描码解码
+//! 把扫描码集 1 的字节流解码为按键：
+//! - 跟踪 Shift、Ctrl、Alt 的按下/松开以及 CapsLock、NumLock 的开关状态
+//! - 处理 0xE0 扩展前缀（方向键、Home/End、Delete、小键盘回车等）
+//! - 按住按键时键盘重复发送的按下码会被当作重复的按键事件原样传出
+//!
+//! 解码状态机和键盘布局由 pc-keyboard 提供，布局通过 KeyboardLayout trait 抽象，
+//! 全局解码器使用 AnyLayout，可以在运行时切换
+//!
+//! 扫描码集默认是 1：键盘本身发送集 2，但 8042 控制器默认打开翻译（配置字节的 bit 6），
+//! 把集 2 转换为集 1 再交给 CPU，QEMU 和大多数真机都是这样。关闭了翻译的环境收到的是集 2，
+//! 需要用 set_scancode_set 切换，否则解码出的字符是乱码。
+//! 分辨的办法是按一下 A 键：集 1 的按下码是 0x1e，集 2 是 0x1c
+//!
+//! 除了字符以外，解码器还产生按键事件：每个按下或松开都对应一个 KeyEvent，
+//! 字符是从按下事件按布局转换得到的，所以事件是字符的超集。
+//! 全局的 decode 在返回字符的同时把事件放进事件队列，由 poll_event 或 KeyEventStream 取出
+//!
+//! 全局解码器的 CapsLock、NumLock、ScrollLock 状态变化时会通过 leds 同步键盘指示灯；
+//! ScrollLock 打开期间暂停屏幕输出（见 vga_buffer::pause_output）
+use crate::vga_buffer;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use pc_keyboard::layouts::{AnyLayout, Us104Key};
+use pc_keyboard::ScancodeSet as _;
+use pc_keyboard::{HandleControl, KeyState, Keyboard, ScancodeSet1, ScancodeSet2};
+use spin::Mutex;
+
+mod leds;
+mod typematic;
+
+pub use leds::set_leds;
+pub use typematic::{set_typematic, Typematic};
+
+pub use pc_keyboard::{DecodedKey, KeyCode, KeyboardLayout};
+
+/// 事件发生时修饰键的状态，左右两侧的键合并
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub capslock: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers {
+        shift: false,
+        ctrl: false,
+        alt: false,
+        capslock: false,
+    };
+
+    fn from_pc_keyboard(modifiers: &pc_keyboard::Modifiers) -> Self {
+        Modifiers {
+            shift: modifiers.is_shifted(),
+            ctrl: modifiers.is_ctrl(),
+            alt: modifiers.is_alt() || modifiers.is_altgr(),
+            capslock: modifiers.capslock,
+        }
+    }
+}
+
+/// 一次按下或松开
+/// modifiers 是处理完这个事件之后的状态，例如 Shift 按下事件本身已经带有 shift
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    pub modifiers: Modifiers,
+}
+
+/// 一个扫描码的完整解码结果：事件，以及按下时按布局转换得到的字符
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyInput {
+    pub event: KeyEvent,
+    pub key: Option<DecodedKey>,
+}
+
+/// CapsLock、NumLock、ScrollLock 的开关状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locks {
+    pub caps: bool,
+    pub num: bool,
+    pub scroll: bool,
+}
+
+/// 解码器期望的扫描码集，见模块文档
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScancodeSet {
+    #[default]
+    Set1,
+    Set2,
+}
+
+/// 按 ScancodeSet 选择的扫描码状态机
+enum Scancodes {
+    Set1(ScancodeSet1),
+    Set2(ScancodeSet2),
+}
+
+impl Scancodes {
+    const fn new(set: ScancodeSet) -> Self {
+        match set {
+            ScancodeSet::Set1 => Scancodes::Set1(ScancodeSet1::new()),
+            ScancodeSet::Set2 => Scancodes::Set2(ScancodeSet2::new()),
+        }
+    }
+
+    fn set(&self) -> ScancodeSet {
+        match self {
+            Scancodes::Set1(_) => ScancodeSet::Set1,
+            Scancodes::Set2(_) => ScancodeSet::Set2,
+        }
+    }
+
+    fn advance_state(
+        &mut self,
+        byte: u8,
+    ) -> Result<Option<pc_keyboard::KeyEvent>, pc_keyboard::Error> {
+        match self {
+            Scancodes::Set1(set) => set.advance_state(byte),
+            Scancodes::Set2(set) => set.advance_state(byte),
+        }
+    }
+}
+
+/// 有状态的扫描码解码器，需要按顺序喂入键盘发出的每一个字节
+pub struct Decoder<L: KeyboardLayout> {
+    /// 扫描码到按键事件由 scancodes 负责，keyboard 只用来按布局和修饰键把事件转换为字符，
+    /// 切换扫描码集时不需要重建 keyboard，修饰键和开关状态保持不变
+    scancodes: Scancodes,
+    keyboard: Keyboard<L, ScancodeSet1>,
+    /// pc-keyboard 不跟踪 ScrollLock，由这里自己记录
+    scroll_lock: bool,
+}
+
+impl<L: KeyboardLayout> Decoder<L> {
+    /// 使用扫描码集 1
+    pub const fn new(layout: L) -> Self {
+        Decoder {
+            scancodes: Scancodes::new(ScancodeSet::Set1),
+            keyboard: Keyboard::new(ScancodeSet1::new(), layout, HandleControl::Ignore),
+            scroll_lock: false,
+        }
+    }
+
+    /// 切换期望的扫描码集，正在解码的多字节序列被丢弃
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        self.scancodes = Scancodes::new(set);
+    }
+
+    pub fn scancode_set(&self) -> ScancodeSet {
+        self.scancodes.set()
+    }
+
+    /// 处理一个扫描码字节
+    /// 前缀字节、松开码以及无法识别的扫描码返回 None；
+    /// 修饰键按下时返回 RawKey，可打印字符按当前修饰键状态返回 Unicode
+    pub fn decode(&mut self, scancode: u8) -> Option<DecodedKey> {
+        self.decode_input(scancode).and_then(|input| input.key)
+    }
+
+    /// 处理一个扫描码字节，返回完整的按键事件以及按下时对应的字符
+    /// 前缀字节和无法识别的扫描码返回 None
+    pub fn decode_input(&mut self, scancode: u8) -> Option<KeyInput> {
+        // 无效的扫描码会让状态机回到初始状态，直接丢弃
+        let event = self.scancodes.advance_state(scancode).ok()??;
+        let code = event.code;
+        let pressed = event.state != KeyState::Up;
+        if pressed && code == KeyCode::ScrollLock {
+            self.scroll_lock = !self.scroll_lock;
+        }
+        let key = self.keyboard.process_keyevent(event);
+        let event = KeyEvent {
+            code,
+            pressed,
+            modifiers: self.modifiers(),
+        };
+        Some(KeyInput { event, key })
+    }
+
+    /// 当前修饰键状态
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers::from_pc_keyboard(self.keyboard.get_modifiers())
+    }
+
+    /// 三个开关键的状态
+    pub fn locks(&self) -> Locks {
+        let modifiers = self.keyboard.get_modifiers();
+        Locks {
+            caps: modifiers.capslock,
+            num: modifiers.numlock,
+            scroll: self.scroll_lock,
+        }
+    }
+
+    /// pc-keyboard 只允许在 EventDecoder 上切换布局，这里直接重建解码器，
+    /// 修饰键和 CapsLock 等开关状态会回到初始值
+    pub fn set_layout(&mut self, layout: L) {
+        self.keyboard = Keyboard::new(ScancodeSet1::new(), layout, HandleControl::Ignore);
+        self.scroll_lock = false;
+    }
+}
+
+static DECODER: Mutex<Decoder<AnyLayout>> = Mutex::new(Decoder::new(AnyLayout::Us104Key(Us104Key)));
+
+/// 用全局解码器处理一个扫描码，默认布局为 US-QWERTY
+/// 产生的按键事件同时放进事件队列
+pub fn decode(scancode: u8) -> Option<DecodedKey> {
+    decode_input(scancode).and_then(|input| input.key)
+}
+
+/// 与 decode 相同，但返回完整的解码结果，需要修饰键状态的调用者使用。
+/// 屏保显示期间按下的键只用来唤醒屏保，返回 None
+pub fn decode_input(scancode: u8) -> Option<KeyInput> {
+    let (input, before, locks) = {
+        let mut decoder = DECODER.lock();
+        let before = decoder.locks();
+        let input = decoder.decode_input(scancode)?;
+        (input, before, decoder.locks())
+    };
+    // 指示灯命令要轮询控制器，在释放解码器的锁之后再发送
+    if locks != before {
+        sync_leds(locks);
+        apply_scroll_lock(before, locks);
+    }
+    if input.event.pressed {
+        crate::watchdog::check_ticks();
+        // 唤醒屏保的键不交给使用者，解码器的状态已经更新
+        if crate::screensaver::swallow_key() {
+            return None;
+        }
+    }
+    EVENTS.lock().push(input.event);
+    EVENT_WAKER.wake();
+    Some(input)
+}
+
+const EVENT_QUEUE_CAPACITY: usize = 32;
+
+/// 固定容量的事件队列，满了以后丢弃最旧的事件，不分配内存
+struct EventQueue {
+    events: [Option<KeyEvent>; EVENT_QUEUE_CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl EventQueue {
+    const fn new() -> Self {
+        EventQueue {
+            events: [None; EVENT_QUEUE_CAPACITY],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, event: KeyEvent) {
+        let tail = (self.head + self.len) % EVENT_QUEUE_CAPACITY;
+        self.events[tail] = Some(event);
+        if self.len == EVENT_QUEUE_CAPACITY {
+            self.head = (self.head + 1) % EVENT_QUEUE_CAPACITY;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<KeyEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % EVENT_QUEUE_CAPACITY;
+        self.len -= 1;
+        event
+    }
+}
+
+static EVENTS: Mutex<EventQueue> = Mutex::new(EventQueue::new());
+static EVENT_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// 取出最早的一个按键事件，没有事件时立即返回 None
+/// 事件只在扫描码经过 decode 时产生，需要有任务在消费扫描码（例如 shell）
+pub fn poll_event() -> Option<KeyEvent> {
+    EVENTS.lock().pop()
+}
+
+/// poll_event 的异步版本，永远不会结束
+pub struct KeyEventStream {
+    _private: (),
+}
+
+impl KeyEventStream {
+    pub fn new() -> Self {
+        KeyEventStream { _private: () }
+    }
+}
+
+impl Default for KeyEventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        if let Some(event) = poll_event() {
+            return Poll::Ready(Some(event));
+        }
+        // 与扫描码队列相同：先注册 waker 再检查一次，避免丢失唤醒
+        EVENT_WAKER.register(cx.waker());
+        match poll_event() {
+            Some(event) => {
+                EVENT_WAKER.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// 全局解码器当前的开关键状态
+pub fn locks() -> Locks {
+    DECODER.lock().locks()
+}
+
+/// 切换全局解码器的键盘布局，开关状态被重置，指示灯随之熄灭
+pub fn set_layout(layout: AnyLayout) {
+    let (before, locks) = {
+        let mut decoder = DECODER.lock();
+        let before = decoder.locks();
+        decoder.set_layout(layout);
+        (before, decoder.locks())
+    };
+    sync_leds(locks);
+    apply_scroll_lock(before, locks);
+}
+
+/// 切换全局解码器期望的扫描码集，默认是集 1；布局和开关状态不变
+pub fn set_scancode_set(set: ScancodeSet) {
+    DECODER.lock().set_scancode_set(set);
+}
+
+/// 全局解码器当前期望的扫描码集
+pub fn scancode_set() -> ScancodeSet {
+    DECODER.lock().scancode_set()
+}
+
+/// ScrollLock 打开时暂停屏幕输出，关闭时恢复并写出暂停期间的输出
+fn apply_scroll_lock(before: Locks, after: Locks) {
+    match (before.scroll, after.scroll) {
+        (false, true) => vga_buffer::pause_output(),
+        (true, false) => vga_buffer::resume_output(),
+        _ => {}
+    }
+}
+
+/// 没有键盘或者键盘不回应时只是指示灯不亮，不影响输入，忽略错误
+fn sync_leds(locks: Locks) {
+    let _ = set_leds(locks.caps, locks.num, locks.scroll);
+}
+
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 依次喂入扫描码，收集所有解码结果
+#[cfg(test)]
+fn decode_all(scancodes: &[u8]) -> Vec<DecodedKey> {
+    let mut decoder = Decoder::new(Us104Key);
+    scancodes
+        .iter()
+        .filter_map(|&scancode| decoder.decode(scancode))
+        .collect()
+}
+
+#[cfg(test)]
+use DecodedKey::{RawKey, Unicode};
+
+#[test_case]
+fn test_plain_letters_and_digits() {
+    // a 按下、松开，1 按下、松开
+    assert_eq!(
+        decode_all(&[0x1e, 0x9e, 0x02, 0x82]),
+        [Unicode('a'), Unicode('1')]
+    );
+}
+
+#[test_case]
+fn test_shift_number_row_symbols() {
+    // 左 Shift 按住时输入 1 2 0 - =，然后松开 Shift 再输入 1
+    assert_eq!(
+        decode_all(&[0x2a, 0x02, 0x03, 0x0b, 0x0c, 0x0d, 0xaa, 0x02]),
+        [
+            RawKey(KeyCode::LShift),
+            Unicode('!'),
+            Unicode('@'),
+            Unicode(')'),
+            Unicode('_'),
+            Unicode('+'),
+            Unicode('1'),
+        ]
+    );
+}
+
+#[test_case]
+fn test_right_shift_release_order() {
+    // 先按左 Shift 再按右 Shift，松开左 Shift 后右 Shift 仍然生效
+    assert_eq!(
+        decode_all(&[0x2a, 0x36, 0xaa, 0x1e, 0xb6, 0x1e]),
+        [
+            RawKey(KeyCode::LShift),
+            RawKey(KeyCode::RShift),
+            Unicode('A'),
+            Unicode('a'),
+        ]
+    );
+}
+
+#[test_case]
+fn test_capslock_only_affects_letters() {
+    // CapsLock 按下松开后：字母大写，数字不变；Shift 与 CapsLock 同时生效时字母恢复小写
+    assert_eq!(
+        decode_all(&[0x3a, 0xba, 0x1e, 0x02, 0x2a, 0x1e, 0x02, 0xaa]),
+        [
+            RawKey(KeyCode::CapsLock),
+            Unicode('A'),
+            Unicode('1'),
+            RawKey(KeyCode::LShift),
+            Unicode('a'),
+            Unicode('!'),
+        ]
+    );
+}
+
+#[test_case]
+fn test_capslock_toggles_on_press_only() {
+    let mut decoder = Decoder::new(Us104Key);
+    decoder.decode(0x3a);
+    assert!(decoder.modifiers().capslock);
+    // 松开码不会再次切换
+    decoder.decode(0xba);
+    assert!(decoder.modifiers().capslock);
+    decoder.decode(0x3a);
+    decoder.decode(0xba);
+    assert!(!decoder.modifiers().capslock);
+}
+
+#[test_case]
+fn test_ctrl_and_alt_are_tracked() {
+    let mut decoder = Decoder::new(Us104Key);
+    assert_eq!(decoder.decode(0x1d), Some(RawKey(KeyCode::LControl)));
+    assert_eq!(decoder.decode(0x38), Some(RawKey(KeyCode::LAlt)));
+    assert!(decoder.modifiers().ctrl);
+    assert!(decoder.modifiers().alt);
+    // 右 Ctrl、右 Alt 使用 0xE0 前缀
+    assert_eq!(decoder.decode(0xe0), None);
+    assert_eq!(decoder.decode(0x1d), Some(RawKey(KeyCode::RControl)));
+    assert!(decoder.modifiers().ctrl);
+
+    // 松开左 Ctrl 后右 Ctrl 仍被按住
+    assert_eq!(decoder.decode(0x9d), None);
+    assert!(decoder.modifiers().ctrl);
+    decoder.decode(0xe0);
+    decoder.decode(0x9d);
+    assert!(!decoder.modifiers().ctrl);
+    decoder.decode(0xb8);
+    assert!(!decoder.modifiers().alt);
+}
+
+#[test_case]
+fn test_extended_prefix_keys() {
+    assert_eq!(
+        decode_all(&[
+            0xe0, 0x48, 0xe0, 0xc8, // 上
+            0xe0, 0x50, 0xe0, 0xd0, // 下
+            0xe0, 0x4b, 0xe0, 0xcb, // 左
+            0xe0, 0x4d, 0xe0, 0xcd, // 右
+            0xe0, 0x47, 0xe0, 0xc7, // Home
+            0xe0, 0x4f, 0xe0, 0xcf, // End
+        ]),
+        [
+            RawKey(KeyCode::ArrowUp),
+            RawKey(KeyCode::ArrowDown),
+            RawKey(KeyCode::ArrowLeft),
+            RawKey(KeyCode::ArrowRight),
+            RawKey(KeyCode::Home),
+            RawKey(KeyCode::End),
+        ]
+    );
+}
+
+#[test_case]
+fn test_extended_delete_and_keypad_enter() {
+    // 0xE0 0x53 是 Delete，没有前缀的 0x53 是小键盘的 "."（NumLock 默认打开）
+    assert_eq!(
+        decode_all(&[0xe0, 0x53, 0xe0, 0xd3, 0x53, 0xd3]),
+        [Unicode('\u{7f}'), Unicode('.')]
+    );
+    // 0xE0 0x1C 是小键盘回车，与主键盘回车 0x1C 解码结果相同
+    assert_eq!(
+        decode_all(&[0xe0, 0x1c, 0xe0, 0x9c, 0x1c, 0x9c]),
+        [Unicode('\n'), Unicode('\n')]
+    );
+}
+
+#[test_case]
+fn test_extended_release_does_not_leak_into_next_key() {
+    // 扩展键松开后紧跟普通按键，前缀状态不能残留
+    assert_eq!(
+        decode_all(&[0xe0, 0x4b, 0xe0, 0xcb, 0x1e]),
+        [RawKey(KeyCode::ArrowLeft), Unicode('a')]
+    );
+}
+
+#[test_case]
+fn test_shift_held_across_extended_key() {
+    // Shift 按住期间按方向键，再松开 Shift
+    assert_eq!(
+        decode_all(&[0x2a, 0xe0, 0x4d, 0xe0, 0xcd, 0x1e, 0xaa, 0x1e]),
+        [
+            RawKey(KeyCode::LShift),
+            RawKey(KeyCode::ArrowRight),
+            Unicode('A'),
+            Unicode('a'),
+        ]
+    );
+}
+
+#[test_case]
+fn test_key_repeat_passes_through() {
+    // 按住 a：键盘重复发送按下码，最后只有一个松开码
+    assert_eq!(
+        decode_all(&[0x1e, 0x1e, 0x1e, 0x9e]),
+        [Unicode('a'), Unicode('a'), Unicode('a')]
+    );
+    // 按住 Shift 时 Shift 自身的重复不影响状态
+    assert_eq!(
+        decode_all(&[0x2a, 0x2a, 0x1e, 0xaa, 0x1e]),
+        [
+            RawKey(KeyCode::LShift),
+            RawKey(KeyCode::LShift),
+            Unicode('A'),
+            Unicode('a'),
+        ]
+    );
+}
+
+#[test_case]
+fn test_lock_state_tracking() {
+    let mut decoder = Decoder::new(Us104Key);
+    // NumLock 默认打开
+    assert_eq!(
+        decoder.locks(),
+        Locks {
+            caps: false,
+            num: true,
+            scroll: false
+        }
+    );
+    // ScrollLock 按下、松开，NumLock 按下、松开，CapsLock 按下、松开
+    for scancode in [0x46, 0xc6, 0x45, 0xc5, 0x3a, 0xba] {
+        decoder.decode(scancode);
+    }
+    assert_eq!(
+        decoder.locks(),
+        Locks {
+            caps: true,
+            num: false,
+            scroll: true
+        }
+    );
+    decoder.decode(0x46);
+    assert!(!decoder.locks().scroll);
+}
+
+#[test_case]
+fn test_set_layout_changes_mapping() {
+    let mut decoder = Decoder::new(AnyLayout::Us104Key(Us104Key));
+    // US 布局下 0x15 是 y
+    assert_eq!(decoder.decode(0x15), Some(Unicode('y')));
+    decoder.set_layout(AnyLayout::De105Key(pc_keyboard::layouts::De105Key));
+    // 德语布局下同一个键是 z
+    assert_eq!(decoder.decode(0x15), Some(Unicode('z')));
+}
+
+#[test_case]
+fn test_scancode_set_2() {
+    let mut decoder = Decoder::new(Us104Key);
+    assert_eq!(decoder.scancode_set(), ScancodeSet::Set1);
+    decoder.set_scancode_set(ScancodeSet::Set2);
+    // 集 2 中 A 的按下码是 0x1c，松开是 0xf0 0x1c
+    assert_eq!(decoder.decode(0x1c), Some(Unicode('a')));
+    assert_eq!(decoder.decode(0xf0), None);
+    assert_eq!(decoder.decode(0x1c), None);
+    // Shift 的状态跨越切换保留
+    decoder.decode(0x12);
+    decoder.set_scancode_set(ScancodeSet::Set1);
+    assert_eq!(decoder.decode(0x1e), Some(Unicode('A')));
+}
+
+#[cfg(test)]
+fn events_for(scancodes: &[u8]) -> Vec<KeyEvent> {
+    let mut decoder = Decoder::new(Us104Key);
+    scancodes
+        .iter()
+        .filter_map(|&scancode| decoder.decode_input(scancode))
+        .map(|input| input.event)
+        .collect()
+}
+
+#[cfg(test)]
+fn event(code: KeyCode, pressed: bool, shift: bool, ctrl: bool) -> KeyEvent {
+    KeyEvent {
+        code,
+        pressed,
+        modifiers: Modifiers {
+            shift,
+            ctrl,
+            ..Modifiers::NONE
+        },
+    }
+}
+
+#[test_case]
+fn test_ctrl_shift_c_chord_events() {
+    // Ctrl 按下、Shift 按下、C 按下松开、Shift 松开、Ctrl 松开
+    assert_eq!(
+        events_for(&[0x1d, 0x2a, 0x2e, 0xae, 0xaa, 0x9d]),
+        [
+            event(KeyCode::LControl, true, false, true),
+            event(KeyCode::LShift, true, true, true),
+            event(KeyCode::C, true, true, true),
+            event(KeyCode::C, false, true, true),
+            event(KeyCode::LShift, false, false, true),
+            event(KeyCode::LControl, false, false, false),
+        ]
+    );
+}
+
+#[test_case]
+fn test_extended_key_release_event() {
+    assert_eq!(
+        events_for(&[0xe0, 0x48, 0xe0, 0xc8]),
+        [
+            event(KeyCode::ArrowUp, true, false, false),
+            event(KeyCode::ArrowUp, false, false, false),
+        ]
+    );
+}
+
+#[test_case]
+fn test_capslock_snapshot_in_events() {
+    let events = events_for(&[0x3a, 0xba, 0x1e]);
+    assert!(events[0].pressed && events[0].modifiers.capslock);
+    assert!(!events[1].pressed && events[1].modifiers.capslock);
+    assert_eq!(events[2].code, KeyCode::A);
+}
+
+#[test_case]
+fn test_event_queue_drops_oldest_when_full() {
+    let mut queue = EventQueue::new();
+    for i in 0..EVENT_QUEUE_CAPACITY + 2 {
+        queue.push(event(KeyCode::A, i % 2 == 0, false, false));
+    }
+    // 最早的两个事件被丢弃，剩下的从第 2 个开始
+    assert_eq!(queue.pop(), Some(event(KeyCode::A, true, false, false)));
+    let mut remaining = 1;
+    while queue.pop().is_some() {
+        remaining += 1;
+    }
+    assert_eq!(remaining, EVENT_QUEUE_CAPACITY);
+}
+
+#[test_case]
+fn test_global_decode_feeds_event_queue() {
+    while poll_event().is_some() {}
+    // b 按下松开：字符路径只返回一次，事件路径得到按下和松开两个事件
+    assert_eq!(decode(0x30), Some(Unicode('b')));
+    assert_eq!(decode(0xb0), None);
+    assert_eq!(poll_event(), Some(event(KeyCode::B, true, false, false)));
+    assert_eq!(poll_event(), Some(event(KeyCode::B, false, false, false)));
+    assert_eq!(poll_event(), None);
+}
@@ -0,0 +1,93 @@
+//! 按键重复
+//! 按住按键时，键盘先等待一段延迟，再以固定的速率重复发送按下码。
+//! 延迟和速率通过 PS/2 命令 0xF3 加一个参数字节设置：
+//! 位 5-6 是延迟（250/500/750/1000 毫秒），位 0-4 是速率（0 最快约 30 次每秒，31 最慢约 2 次每秒）
+//!
+//! 与指示灯一样，交换期间屏蔽 IRQ1
+use crate::interrupts::{self, KEYBOARD_IRQ};
+use crate::ps2::{self, CommandError, Controller, KeyboardPort};
+
+const SET_TYPEMATIC: u8 = 0xf3;
+
+/// 可以设置的延迟（毫秒）
+pub const DELAYS_MS: [u16; 4] = [250, 500, 750, 1000];
+/// 速率（每秒重复次数）的范围
+pub const MIN_RATE: u8 = 2;
+pub const MAX_RATE: u8 = 30;
+
+/// 按键重复的设置，只能通过 new 构造，所以总是有效的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Typematic {
+    delay_ms: u16,
+    rate: u8,
+}
+
+impl Typematic {
+    /// 键盘复位后的设置：500 毫秒，约 10.9 次每秒
+    pub const DEFAULT: Typematic = Typematic {
+        delay_ms: 500,
+        rate: 11,
+    };
+
+    /// delay_ms 必须是 DELAYS_MS 之一，rate 在 MIN_RATE..=MAX_RATE 之间
+    pub fn new(delay_ms: u16, rate: u8) -> Option<Typematic> {
+        (DELAYS_MS.contains(&delay_ms) && (MIN_RATE..=MAX_RATE).contains(&rate))
+            .then_some(Typematic { delay_ms, rate })
+    }
+
+    pub fn delay_ms(self) -> u16 {
+        self.delay_ms
+    }
+
+    pub fn rate(self) -> u8 {
+        self.rate
+    }
+
+    /// 0xF3 命令的参数字节，速率取最接近的一档
+    pub fn byte(self) -> u8 {
+        let delay = DELAYS_MS
+            .iter()
+            .position(|&ms| ms == self.delay_ms)
+            .unwrap_or(1) as u8;
+        let wanted = self.rate as u32 * 1000;
+        let rate = (0..32u8)
+            .min_by_key(|&code| rate_millihertz(code).abs_diff(wanted))
+            .unwrap_or(0);
+        delay << 5 | rate
+    }
+}
+
+/// 速率编码对应的每千秒重复次数：周期是 (8 + A) * 2^B * 4.17 毫秒，A 是位 0-2，B 是位 3-4
+fn rate_millihertz(code: u8) -> u32 {
+    let a = (code & 0b111) as u32;
+    let b = (code >> 3) & 0b11;
+    239_808 / ((8 + a) << b)
+}
+
+/// 设置按键重复的延迟和速率
+pub fn set_typematic(typematic: Typematic) -> Result<(), CommandError> {
+    interrupts::with_irq_masked(KEYBOARD_IRQ, || {
+        let mut port = KeyboardPort {
+            controller: Controller::new(),
+            aux: crate::mouse::add_byte,
+        };
+        ps2::send_command(
+            &mut port,
+            &[SET_TYPEMATIC, typematic.byte()],
+            crate::task::keyboard::add_scancode,
+        )
+    })
+}
+
+#[test_case]
+fn test_typematic_byte() {
+    assert_eq!(Typematic::DEFAULT.byte(), 0b01_01011);
+    assert_eq!(Typematic::new(250, MAX_RATE).map(Typematic::byte), Some(0));
+    assert_eq!(
+        Typematic::new(1000, MIN_RATE).map(Typematic::byte),
+        Some(0b11_11111)
+    );
+    assert_eq!(Typematic::new(300, 10), None);
+    assert_eq!(Typematic::new(250, MAX_RATE + 1), None);
+    assert_eq!(Typematic::new(250, MIN_RATE - 1), None);
+}
@@ -0,0 +1,178 @@
+// 禁用标准库
+#![no_std]
+// 测试时由 lib 自己提供入口点
+#![cfg_attr(test, no_main)]
+// 自定义测试框架：标准的 test crate 依赖标准库，不能在裸机上使用
+#![feature(custom_test_frameworks)]
+// 中断处理函数使用 x86-interrupt 调用约定
+#![feature(abi_x86_interrupt)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+pub mod acpi;
+pub mod allocator;
+pub mod ata;
+pub mod backtrace;
+pub mod boot;
+pub mod boot_info;
+pub mod boot_trace;
+pub mod cmos;
+pub mod config;
+pub mod console;
+pub mod cpu;
+pub mod crashlog;
+pub mod diag;
+pub mod dump;
+pub mod exec;
+pub mod faultinject;
+pub mod fs;
+pub mod gdt;
+pub mod graphics;
+pub mod hexdump;
+pub mod interrupts;
+pub mod keybindings;
+pub mod keyboard;
+pub mod log;
+pub mod memory;
+pub mod mouse;
+pub mod multiboot2;
+pub mod net;
+pub mod panic;
+pub mod panic_code;
+pub mod pci;
+pub mod power;
+pub mod ps2;
+pub mod rand;
+pub mod screensaver;
+pub mod selftest;
+pub mod serial;
+pub mod settings;
+pub mod shell;
+pub mod smp;
+pub mod speaker;
+pub mod stack;
+pub mod statusbar;
+pub mod symbols;
+pub mod task;
+pub mod time;
+pub mod usermode;
+pub mod vga_buffer;
+pub mod vga_mode;
+pub mod watchdog;
+
+use core::panic::PanicInfo;
+
+#[cfg(test)]
+use bootloader::{entry_point, BootInfo};
+
+/// 内核初始化：加载 GDT 与 IDT，初始化 PIC 与 PIT，最后开启中断
+pub fn init() {
+    // 没有调用 config::init 的入口（例如测试）使用默认配置
+    console::advance(console::ConsoleState::Full);
+    gdt::init();
+    crate::boot_trace!(Gdt);
+    interrupts::init_idt();
+    crate::boot_trace!(Idt);
+    interrupts::init_pics();
+    crate::boot_trace!(Pic);
+    time::init_pit();
+    // 时钟中断开启之前的 spin_delay_ms 要用到
+    time::calibrate_delay();
+    crate::boot_trace!(Timer);
+    rand::init();
+    x86_64::instructions::interrupts::enable();
+    crate::boot_trace!(Interrupts);
+}
+
+/// 使用 hlt 指令让 CPU 在下一个中断到来前休眠，而不是空转
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// 为测试函数打印名称和结果
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T> Testable for T
+where
+    T: Fn(),
+{
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+pub fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// 测试模式下的 panic 处理：通过串口报告失败并退出 QEMU
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    exit_qemu_or_halt(QemuExitCode::Failed);
+}
+
+/// 退出码会被 QEMU 变换为 (value << 1) | 1，所以不使用 0 避免与 QEMU 自身的退出码冲突
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// 向 isa-debug-exit 设备（端口 0xf4）写入退出码。不只用于测试，任何代码都可以调用：
+/// 在 QEMU 中（启动参数带有这个设备时）虚拟机立即退出；没有这个设备时（真机、或者 QEMU 没有配置它）
+/// 写入不会产生任何效果，函数照常返回
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    use x86_64::instructions::port::Port;
+
+    unsafe {
+        let mut port = Port::new(0xf4);
+        port.write(exit_code as u32);
+    }
+}
+
+/// 退出 QEMU，没有 isa-debug-exit 设备时停机。
+/// 用于启动早期遇到无法恢复的错误（例如配置错误）时直接结束，不经过 panic 处理
+pub fn exit_qemu_or_halt(exit_code: QemuExitCode) -> ! {
+    exit_qemu(exit_code);
+    hlt_loop();
+}
+
+#[cfg(test)]
+entry_point!(test_kernel_main);
+
+/// `cargo test` 时 lib 的入口点
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    backtrace::init(phys_mem_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
@@ -0,0 +1,224 @@
+//! 分级的内核日志
+//! 级别与启动配置的 loglevel 相同：数字越小越紧急，只输出级别不大于 loglevel 的消息。
+//! 默认的 loglevel 是 4，即只输出错误和警告
+//!
+//! 错误和警告用红色写到屏幕（见 eprint!），其他级别和 print! 相同；每条消息带有级别前缀。
+//! 级别随消息交给 console::sink，由它决定哪些输出目标接收
+//!
+//! 除了运行时的 loglevel，还有编译时的过滤：级别高于 STATIC_MAX_LEVEL（由 log_level_* feature 决定，
+//! 可以被 MODULE_MAX_LEVELS 按模块覆盖）的调用在常量条件下被丢弃，优化后连同格式字符串都不会留在内核中。
+//! 编译进来的调用仍然受 loglevel 过滤
+use crate::console::Style;
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// set_loglevel 设置的值，UNSET 表示使用启动配置的 loglevel
+static LOGLEVEL: AtomicU8 = AtomicU8::new(UNSET);
+const UNSET: u8 = u8::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 3,
+    Warn = 4,
+    Info = 6,
+    Debug = 7,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" | "warning" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// 编译进内核的最高级别，同时打开几个 log_level_* feature 时取最严格的
+pub const STATIC_MAX_LEVEL: Level = if cfg!(feature = "log_level_error") {
+    Level::Error
+} else if cfg!(feature = "log_level_warn") {
+    Level::Warn
+} else if cfg!(feature = "log_level_info") {
+    Level::Info
+} else {
+    Level::Debug
+};
+
+/// 按模块覆盖 STATIC_MAX_LEVEL：模块路径等于前缀或者以 "前缀::" 开头时使用对应的级别，先出现的优先。
+/// 可以比 STATIC_MAX_LEVEL 宽松，例如只保留正在调试的驱动的 kdebug!：("vm_os::ata", Level::Debug)
+#[cfg(not(test))]
+pub const MODULE_MAX_LEVELS: &[(&str, Level)] = &[];
+#[cfg(test)]
+pub const MODULE_MAX_LEVELS: &[(&str, Level)] = &[("vm_os::log::compiled_out", Level::Error)];
+
+/// 模块路径 module 是否属于 prefix 或它的子模块
+const fn in_module(module: &str, prefix: &str) -> bool {
+    let (module, prefix) = (module.as_bytes(), prefix.as_bytes());
+    if module.len() < prefix.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < prefix.len() {
+        if module[i] != prefix[i] {
+            return false;
+        }
+        i += 1;
+    }
+    module.len() == prefix.len()
+        || module.len() >= prefix.len() + 2
+            && module[prefix.len()] == b':'
+            && module[prefix.len() + 1] == b':'
+}
+
+/// 在 table 中查找 module 的级别，没有匹配时使用 default
+pub const fn max_level_for(module: &str, table: &[(&str, Level)], default: Level) -> Level {
+    let mut i = 0;
+    while i < table.len() {
+        if in_module(module, table[i].0) {
+            return table[i].1;
+        }
+        i += 1;
+    }
+    default
+}
+
+/// 模块 module 中这个级别的调用是否编译进内核，日志宏在常量上下文中调用它
+pub const fn static_enabled(level: Level, module: &str) -> bool {
+    level as u8 <= max_level_for(module, MODULE_MAX_LEVELS, STATIC_MAX_LEVEL) as u8
+}
+
+/// 修改运行时的 loglevel，下一条消息生效，之后不再使用启动配置的 loglevel 选项
+pub fn set_loglevel(loglevel: u8) {
+    LOGLEVEL.store(loglevel.min(crate::config::MAX_LOGLEVEL), Ordering::Relaxed);
+}
+
+/// 当前的 loglevel：set_loglevel 设置过的，或者启动配置的
+pub fn loglevel() -> u8 {
+    match LOGLEVEL.load(Ordering::Relaxed) {
+        UNSET => crate::config::get().loglevel,
+        loglevel => loglevel,
+    }
+}
+
+/// 当前的 loglevel 下这个级别的消息是否输出
+pub fn enabled(level: Level, loglevel: u8) -> bool {
+    level as u8 <= loglevel
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, args: fmt::Arguments) {
+    if !enabled(level, loglevel()) {
+        return;
+    }
+    crate::vga_buffer::print_in_state(
+        format_args!("{}: {}\n", level.name(), args),
+        Some(level),
+        if level <= Level::Warn {
+            Style::Error
+        } else {
+            Style::Normal
+        },
+    );
+}
+
+/// 日志宏的共同实现：编译时过滤掉的级别只剩一个常量为假的条件
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __klog {
+    ($level:expr, $($arg:tt)*) => {
+        if const { $crate::log::static_enabled($level, module_path!()) } {
+            $crate::log::_log($level, format_args!($($arg)*));
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! kerror {
+    ($($arg:tt)*) => ($crate::__klog!($crate::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! kwarn {
+    ($($arg:tt)*) => ($crate::__klog!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! kinfo {
+    ($($arg:tt)*) => ($crate::__klog!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! kdebug {
+    ($($arg:tt)*) => ($crate::__klog!($crate::log::Level::Debug, $($arg)*));
+}
+
+#[test_case]
+fn test_level_filter() {
+    // 默认的 loglevel
+    assert!(enabled(Level::Error, 4));
+    assert!(enabled(Level::Warn, 4));
+    assert!(!enabled(Level::Info, 4));
+    assert!(enabled(Level::Debug, crate::config::MAX_LOGLEVEL));
+    assert!(!enabled(Level::Error, 0));
+}
+
+#[test_case]
+fn test_module_max_levels() {
+    const TABLE: &[(&str, Level)] = &[("vm_os::ata", Level::Debug), ("vm_os", Level::Warn)];
+    let level = |module| max_level_for(module, TABLE, Level::Error);
+    assert_eq!(level("vm_os::ata"), Level::Debug);
+    assert_eq!(level("vm_os::ata::identify"), Level::Debug);
+    // 前缀必须在 "::" 处结束
+    assert_eq!(level("vm_os::atapi"), Level::Warn);
+    assert_eq!(level("vm_os::shell"), Level::Warn);
+    assert_eq!(level("vm_osx"), Level::Error);
+    assert_eq!(level("other"), Level::Error);
+}
+
+#[cfg(test)]
+fn log_each_level() {
+    crate::kerror!("e");
+    crate::kwarn!("w");
+    crate::kinfo!("i");
+    crate::kdebug!("d");
+}
+
+#[test_case]
+fn test_compiled_out_levels_produce_no_output() {
+    use crate::console::sink::{self, Recording};
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    assert!(!static_enabled(Level::Warn, "vm_os::log::compiled_out"));
+    assert!(static_enabled(Level::Error, "vm_os::log::compiled_out"));
+    let id = sink::register(Box::leak(Box::new(Recording(Vec::new())))).unwrap();
+    // 默认的 loglevel 4 允许警告，但 compiled_out 中的 kwarn! 没有编译进来
+    compiled_out::log_each_level();
+    // 其他模块的调用都编译进来了，仍然按 loglevel 过滤
+    log_each_level();
+    assert_eq!(sink::take_recording(id), "error: e\nerror: e\nwarning: w\n");
+}
+
+/// 在 MODULE_MAX_LEVELS 中被限制为只编译错误
+#[cfg(test)]
+mod compiled_out {
+    pub fn log_each_level() {
+        crate::kerror!("e");
+        crate::kwarn!("w");
+        crate::kinfo!("i");
+        crate::kdebug!("d");
+    }
+}
@@ -2,23 +2,176 @@
 #![no_std]
 // 禁用 Rust 层级的入口点
 #![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(alloc_error_handler)]
+#![test_runner(vm_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
-mod vga_buffer;
+extern crate alloc;
 
+use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
+use futures_util::stream::StreamExt;
+use vm_os::task::executor::Executor;
+use vm_os::task::yield_times;
+use vm_os::{boot, boot_trace, mouse, println, screensaver, shell, statusbar, time};
 
-static HELLO: &[u8] = b"Hello World!";
+// 由 bootloader 的宏生成真正的 _start 入口，并对入口函数做类型检查
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    use vm_os::console::ConsoleState;
+    use vm_os::{allocator, backtrace, config, memory, multiboot2, vga_buffer};
+    use x86_64::VirtAddr;
+
+    // 在任何可能出错的初始化之前读出上一次启动的记录
+    boot_trace::init(VirtAddr::new(boot_info.physical_memory_offset));
+    // 尽早涂色，之后的启动代码用到的栈都能被统计到
+    vm_os::stack::init(VirtAddr::new(boot_info.physical_memory_offset));
+    // 从这里开始可以使用 WRITER；之前（例如 multiboot2 入口中）的输出直接写屏幕
+    vm_os::console::advance(ConsoleState::VgaOnly);
+    boot_trace!(Console);
+    config::init(multiboot2::command_line().unwrap_or(""));
+    boot_trace!(Config);
+    if !config::get().quiet {
+        println!("Hello World{}", "!");
+    }
+    boot_trace::report();
+    vm_os::init();
+    boot::stage("cpu", || println!("cpu: {}", vm_os::cpu::Summary));
+    boot::stage("pci", || {
+        vm_os::pci::init();
+    });
+    boot::stage("ata", || {
+        for (drive, result) in vm_os::ata::init().iter().enumerate() {
+            match result {
+                Ok(_) | Err(vm_os::ata::AtaError::NoDevice) => {}
+                Err(error) => println!("ata: drive {}: {}", drive, error),
+            }
+        }
+    });
+    boot::stage("crashlog", vm_os::crashlog::init);
+    boot::stage("settings", vm_os::settings::init);
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    backtrace::init(phys_mem_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    boot_trace!(Paging);
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    boot_trace!(Heap);
+    boot::stage("fs", || match vm_os::fs::init() {
+        Ok(_) | Err(vm_os::fs::FsError::NoArchive) => Ok(()),
+        Err(error) => Err(error),
+    });
+    boot::stage("smp", || {
+        vm_os::smp::init(&mut mapper, &mut frame_allocator)
+            .map(|cpus| println!("smp: {} CPUs online", cpus))
+    });
+    boot::stage("net", || {
+        match vm_os::net::init(&mut mapper, &mut frame_allocator) {
+            Ok(nic) => println!("net: {}", nic),
+            // 与 fs 没有归档一样，没有网卡不算失败
+            Err(vm_os::net::NetError::NoDevice) => {}
+            Err(error) => return Err(error),
+        }
+        Ok(())
+    });
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        vga_buffer::WRITER
+            .lock()
+            .set_bell(Some(vm_os::speaker::bell))
+    });
+    vga_buffer::init_scrollback();
+    vga_buffer::init_virtual_consoles();
+    vga_buffer::init_draw_buffer();
+    if cfg!(feature = "selftest") || config::get().selftest {
+        vm_os::selftest::run();
+    }
+    if config::get().gfxdemo {
+        if let Err(error) = vm_os::graphics::demo() {
+            println!("graphics: {}", error);
+        }
+    }
+
+    #[cfg(test)]
+    test_main();
+
+    if !config::get().quiet {
+        boot::summary();
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            vm_os::boot_info::print_boot_info(&mut vga_buffer::WRITER.lock())
+        });
+    }
+    boot_trace!(Done);
+    let mut executor = Executor::new();
+    executor.spawn_named("example", example_task());
+    executor.spawn_named("shell", shell::run());
+    executor.spawn_named("heartbeat", heartbeat());
+    executor.spawn_named("screensaver", screensaver::run());
+    if let Some(interval) = config::get().statusbar {
+        statusbar::set_interval(interval);
+        statusbar::register_defaults();
+        executor.spawn_named("statusbar", statusbar::run());
+    }
+    if let Err(error) = vm_os::dump::init(vm_os::dump::SCREEN_CHORD, vm_os::dump::SERIAL_CHORD) {
+        println!("dump: {:?}", error);
+    }
+    match mouse::init() {
+        Ok(()) => {
+            executor.spawn_named("mouse", mouse::track_cursor());
+        }
+        Err(error) => println!("mouse: {:?}", error),
+    }
+    executor.run();
+}
+
+/// Multiboot2 路径的入口，由 multiboot2/boot.s 在进入长模式后调用
+#[cfg(feature = "multiboot2")]
+#[no_mangle]
+extern "C" fn multiboot2_main(magic: u32, info_addr: u64) -> ! {
+    match vm_os::multiboot2::boot_info(magic, info_addr) {
+        Ok(boot_info) => kernel_main(boot_info),
+        Err(error) => panic!("multiboot2: {:?}", error),
+    }
+}
+
+async fn async_number() -> u32 {
+    // 主动让出 3 次，验证 await 能跨越多次 Pending
+    yield_times(3).await;
+    42
+}
+
+async fn example_task() {
+    let number = async_number().await;
+    println!("async number: {}", number);
+}
+
+/// 每秒打印一次，共 5 次，演示由时钟中断驱动的定时器
+async fn heartbeat() {
+    let mut interval = time::interval(1000);
+    for second in 1..=5 {
+        interval.next().await;
+        println!("heartbeat: {}s", second);
+    }
+}
 
 /// 程序 panic 时调用
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    loop {}
+    vm_os::panic::handle_panic(info)
 }
 
-/// 编译时不重整函数名
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    panic!("Some panic message");
-    loop {}
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::test_panic_handler(info)
+}
+
+/// 堆分配失败时调用，见 allocator 模块
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    vm_os::allocator::handle_alloc_error(layout)
 }
@@ -0,0 +1,213 @@
+//! 分页与物理帧分配
+//! bootloader 开启 "map_physical_memory" 后，会把全部物理内存映射到虚拟地址 physical_memory_offset 处，
+//! 因此可以通过 "物理地址 + 偏移" 直接访问任意页表帧
+//!
+//! 设备的寄存器（MMIO）不通过物理内存映射访问：那里的页是可缓存的。
+//! map_mmio 从 MMIO_START 开始的一段虚拟地址中依次分出区域，映射为不可缓存的页
+use crate::boot_trace;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// 映射设备寄存器使用的虚拟地址范围
+pub const MMIO_START: u64 = 0x_5555_0000_0000;
+pub const MMIO_SIZE: u64 = 1 << 30;
+const PAGE_SIZE: u64 = 4096;
+
+/// 下一个可以分出去的 MMIO 虚拟地址，分出去的区域不回收
+static MMIO_NEXT: AtomicU64 = AtomicU64::new(MMIO_START);
+
+/// init 时记录，供 user_accessible 查页表和 phys_to_virt 使用
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// 内存映射中可用区域的总字节数，创建帧分配器时记录
+static USABLE_MEMORY: OnceCell<u64> = OnceCell::uninit();
+
+/// 引导程序报告的可用物理内存字节数，帧分配器创建之前返回 None
+pub fn usable_memory() -> Option<u64> {
+    USABLE_MEMORY.get().copied()
+}
+
+/// 初始化一个 OffsetPageTable
+///
+/// # Safety
+/// 调用者必须保证全部物理内存都已映射到 physical_memory_offset 处，
+/// 并且此函数只能调用一次，否则会出现多个 &mut 引用指向同一个页表
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let _ = PHYSICAL_MEMORY_OFFSET.try_init_once(|| physical_memory_offset);
+    let level_4_table = active_level_4_table(physical_memory_offset);
+    OffsetPageTable::new(level_4_table, physical_memory_offset)
+}
+
+/// 返回当前活动的 4 级页表的可变引用
+/// CR3 寄存器保存的是 4 级页表的物理地址
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr
+}
+
+/// 把虚拟地址翻译为物理地址，地址没有映射时返回 None
+/// 只读取页表、不创建任何 &mut 引用，可以在已经有 OffsetPageTable 存在时（例如 panic 处理中）使用
+///
+/// # Safety
+/// 调用者必须保证全部物理内存都已映射到 physical_memory_offset 处
+pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
+    walk(addr, physical_memory_offset).map(|(phys, _)| phys)
+}
+
+/// 物理地址在物理内存映射中的虚拟地址，init 之前返回 None
+pub fn phys_to_virt(addr: PhysAddr) -> Option<VirtAddr> {
+    PHYSICAL_MEMORY_OFFSET
+        .get()
+        .map(|&offset| offset + addr.as_u64())
+}
+
+/// addr 所在的页已经映射，并且各级页表项都允许用户态访问
+pub fn user_accessible(addr: VirtAddr) -> bool {
+    let Some(&offset) = PHYSICAL_MEMORY_OFFSET.get() else {
+        return false;
+    };
+    unsafe { walk(addr, offset) }
+        .is_some_and(|(_, flags)| flags.contains(PageTableFlags::USER_ACCESSIBLE))
+}
+
+/// 逐级查找 addr，返回物理地址和沿途各级页表项标志的交集
+unsafe fn walk(
+    addr: VirtAddr,
+    physical_memory_offset: VirtAddr,
+) -> Option<(PhysAddr, PageTableFlags)> {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+    let table_indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut frame_addr = level_4_table_frame.start_address();
+    let mut path_flags = PageTableFlags::all();
+
+    for (level, &index) in table_indexes.iter().enumerate() {
+        let virt = physical_memory_offset + frame_addr.as_u64();
+        let table = &*virt.as_ptr::<PageTable>();
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        frame_addr = entry.addr();
+        path_flags &= entry.flags();
+        // 3 级和 2 级页表项可以直接映射 1GiB / 2MiB 的大页
+        if entry.flags().contains(PageTableFlags::HUGE_PAGE) && (level == 1 || level == 2) {
+            let page_size = if level == 1 { 1 << 30 } else { 1 << 21 };
+            return Some((frame_addr + (addr.as_u64() & (page_size - 1)), path_flags));
+        }
+    }
+
+    Some((frame_addr + u64::from(addr.page_offset()), path_flags))
+}
+
+#[derive(Debug)]
+pub enum MmioError {
+    /// MMIO 的虚拟地址范围已经用完
+    OutOfSpace,
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for MmioError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        MmioError::Map(error)
+    }
+}
+
+/// 把从 phys 开始的 size 字节设备寄存器映射为不可缓存、不可执行的页，返回 phys 对应的虚拟地址。
+/// phys 不需要按页对齐，返回的地址保留页内偏移
+pub fn map_mmio(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    phys: PhysAddr,
+    size: u64,
+) -> Result<VirtAddr, MmioError> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let offset = phys - first.start_address();
+    let pages = (offset + size.max(1)).div_ceil(PAGE_SIZE);
+    let start = MMIO_NEXT
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |next| {
+            let end = next.checked_add(pages * PAGE_SIZE)?;
+            (end <= MMIO_START + MMIO_SIZE).then_some(end)
+        })
+        .map_err(|_| MmioError::OutOfSpace)?;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+    let first_page = Page::containing_address(VirtAddr::new(start));
+    for index in 0..pages {
+        unsafe { mapper.map_to(first_page + index, first + index, flags, frame_allocator)? }
+            .flush();
+    }
+    Ok(VirtAddr::new(start + offset))
+}
+
+/// 从 bootloader 提供的内存映射中返回可用帧的分配器
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    next: usize,
+}
+
+impl BootInfoFrameAllocator {
+    /// 根据传入的内存映射创建帧分配器
+    ///
+    /// # Safety
+    /// 调用者必须保证内存映射是有效的，所有标记为 "Usable" 的帧都确实未被使用
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        let _ = USABLE_MEMORY.try_init_once(|| {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+                .map(|r| r.range.end_addr() - r.range.start_addr())
+                .sum()
+        });
+        BootInfoFrameAllocator {
+            memory_map,
+            next: 0,
+        }
+    }
+
+    /// 返回内存映射中所有可用帧的迭代器
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        let regions = self.memory_map.iter();
+        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
+        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
+        // 帧按 4KiB 对齐
+        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
+        // 启动面包屑的记录要在重启后保留，不能分配出去
+        let frame_addresses =
+            frame_addresses.filter(|&addr| addr != boot_trace::reserved_frame().as_u64());
+        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if crate::faultinject::should_fail(crate::faultinject::FRAME_ALLOC) {
+            return None;
+        }
+        let frame = self.usable_frames().nth(self.next);
+        self.next += 1;
+        frame
+    }
+}
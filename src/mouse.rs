@@ -0,0 +1,489 @@
+//! PS/2 鼠标
+//! 通过控制器打开第二个端口并让鼠标开始发送数据，之后每次移动或按键变化鼠标发送一个 3 字节的数据包：
+//! - 第 0 字节：位 0-2 左/右/中键，位 3 总是 1，位 4/5 是 x/y 的符号位，位 6/7 是 x/y 溢出
+//! - 第 1、2 字节：x、y 位移的低 8 位，与符号位合起来是 9 位补码，y 向上为正
+//!
+//! IRQ12 的中断处理函数只拼装数据包并更新位置，光标和状态由 track_cursor 任务绘制。
+//! 丢失字节后数据包会错位，拼装时根据第 0 字节的位 3 丢弃不可能是包头的字节，重新对齐
+use crate::interrupts::{unmask_irq, with_irq_masked, KEYBOARD_IRQ, MOUSE_IRQ};
+use crate::ps2::{self, AuxPort, CommandError, Controller, Ps2Port};
+use crate::vga_buffer::{
+    Cell, CellError, Color, ColorCode, ScreenChar, Writer, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER,
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+use futures_util::future;
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// 光标移动一列、一行需要的位移计数
+const COUNTS_PER_COLUMN: i32 = 8;
+const COUNTS_PER_ROW: i32 = 16;
+const MAX_X: i32 = BUFFER_WIDTH as i32 * COUNTS_PER_COLUMN - 1;
+const MAX_Y: i32 = BUFFER_HEIGHT as i32 * COUNTS_PER_ROW - 1;
+
+const LEFT: u8 = 1 << 0;
+const RIGHT: u8 = 1 << 1;
+const MIDDLE: u8 = 1 << 2;
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+const ENABLE_REPORTING: u8 = 0xf4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// 一个数据包，位移已经做过符号扩展，y 向上为正
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub dx: i16,
+    pub dy: i16,
+    pub buttons: Buttons,
+}
+
+impl Packet {
+    fn parse([flags, x, y]: [u8; 3]) -> Self {
+        Packet {
+            dx: delta(x, flags & X_SIGN != 0, flags & X_OVERFLOW != 0),
+            dy: delta(y, flags & Y_SIGN != 0, flags & Y_OVERFLOW != 0),
+            buttons: Buttons {
+                left: flags & LEFT != 0,
+                right: flags & RIGHT != 0,
+                middle: flags & MIDDLE != 0,
+            },
+        }
+    }
+}
+
+/// 9 位补码，符号位在第 0 字节中；溢出时数值不可靠，丢弃这次位移
+fn delta(low: u8, negative: bool, overflow: bool) -> i16 {
+    match (overflow, negative) {
+        (true, _) => 0,
+        (false, true) => low as i16 - 0x100,
+        (false, false) => low as i16,
+    }
+}
+
+/// 把字节流拼成数据包
+pub struct PacketDecoder {
+    bytes: [u8; 3],
+    len: usize,
+}
+
+impl Default for PacketDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacketDecoder {
+    pub const fn new() -> Self {
+        PacketDecoder {
+            bytes: [0; 3],
+            len: 0,
+        }
+    }
+
+    /// 放入一个字节，凑齐 3 个字节时返回数据包
+    pub fn push(&mut self, byte: u8) -> Option<Packet> {
+        // 位 3 为 0 的字节不可能是包头，说明之前丢了字节，跳过直到重新对齐
+        if self.len == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.bytes.len() {
+            return None;
+        }
+        self.len = 0;
+        Some(Packet::parse(self.bytes))
+    }
+}
+
+/// 光标所在的单元格和按键状态，(0, 0) 是左上角
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseState {
+    pub x: usize,
+    pub y: usize,
+    pub buttons: Buttons,
+}
+
+/// 以位移计数为单位累计位置，限制在屏幕范围内，换算成单元格坐标
+pub struct Tracker {
+    x: i32,
+    y: i32,
+    buttons: Buttons,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tracker {
+    /// 从屏幕中央开始
+    pub const fn new() -> Self {
+        Tracker {
+            x: MAX_X / 2,
+            y: MAX_Y / 2,
+            buttons: Buttons {
+                left: false,
+                right: false,
+                middle: false,
+            },
+        }
+    }
+
+    pub fn apply(&mut self, packet: Packet) {
+        self.x = (self.x + packet.dx as i32).clamp(0, MAX_X);
+        // 屏幕的行号向下增加，与鼠标的 y 方向相反
+        self.y = (self.y - packet.dy as i32).clamp(0, MAX_Y);
+        self.buttons = packet.buttons;
+    }
+
+    pub fn state(&self) -> MouseState {
+        MouseState {
+            x: (self.x / COUNTS_PER_COLUMN) as usize,
+            y: (self.y / COUNTS_PER_ROW) as usize,
+            buttons: self.buttons,
+        }
+    }
+}
+
+struct Mouse {
+    decoder: PacketDecoder,
+    tracker: Tracker,
+}
+
+/// 中断处理函数也会获取这个锁，其他地方必须在关闭中断的情况下访问
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse {
+    decoder: PacketDecoder::new(),
+    tracker: Tracker::new(),
+});
+static CHANGED: AtomicBool = AtomicBool::new(false);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// 由鼠标中断处理函数调用
+pub(crate) fn add_byte(byte: u8) {
+    let mut mouse = MOUSE.lock();
+    if let Some(packet) = mouse.decoder.push(byte) {
+        mouse.tracker.apply(packet);
+        CHANGED.store(true, Ordering::Release);
+        WAKER.wake();
+    }
+}
+
+/// 当前的光标位置和按键状态
+pub fn state() -> MouseState {
+    interrupts::without_interrupts(|| MOUSE.lock().tracker.state())
+}
+
+/// 打开第二个端口，让鼠标开始发送数据，然后解除 IRQ12 的屏蔽
+/// 没有鼠标时返回错误，不会卡住
+pub fn init() -> Result<(), CommandError> {
+    with_irq_masked(KEYBOARD_IRQ, || {
+        let mut controller = Controller::new();
+        enable_aux_port(&mut controller)?;
+        // 这期间的其他字节来源不明，直接丢弃
+        ps2::send_command(&mut AuxPort(controller), &[ENABLE_REPORTING], |_| {})
+    })?;
+    unmask_irq(MOUSE_IRQ);
+    Ok(())
+}
+
+/// 控制器命令：0xA8 打开第二个端口，0x20/0x60 读/写配置字节
+fn enable_aux_port(controller: &mut Controller) -> Result<(), CommandError> {
+    if !controller.write_command(0xa8) || !controller.write_command(0x20) {
+        return Err(CommandError::Timeout);
+    }
+    let config = controller.read().ok_or(CommandError::Timeout)?;
+    // 位 1 打开 IRQ12，位 5 为 1 时第二个端口的时钟被关闭
+    let config = (config | 1 << 1) & !(1 << 5);
+    if !controller.write_command(0x60) || !controller.write(config) {
+        return Err(CommandError::Timeout);
+    }
+    Ok(())
+}
+
+/// 状态文字的长度，例如 "mouse 40,12 L-R"
+const STATUS_LEN: usize = 15;
+
+fn format_status(state: MouseState) -> [u8; STATUS_LEN] {
+    let mut text = *b"mouse 00,00 ---";
+    text[6] = b'0' + (state.x / 10) as u8;
+    text[7] = b'0' + (state.x % 10) as u8;
+    text[9] = b'0' + (state.y / 10) as u8;
+    text[10] = b'0' + (state.y % 10) as u8;
+    let buttons = state.buttons;
+    for (i, (pressed, name)) in [
+        (buttons.left, b'L'),
+        (buttons.middle, b'M'),
+        (buttons.right, b'R'),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if pressed {
+            text[12 + i] = name;
+        }
+    }
+    text
+}
+
+/// 光标所在的单元格和它原来的颜色
+struct Cursor {
+    cell: Cell,
+    original: ColorCode,
+}
+
+/// 反色显示光标所在的单元格，移动时恢复之前的单元格，并在右上角显示坐标和按键
+pub struct CursorRenderer {
+    cursor: Option<Cursor>,
+}
+
+impl Default for CursorRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CursorRenderer {
+    pub const fn new() -> Self {
+        CursorRenderer { cursor: None }
+    }
+
+    /// 坐标不在屏幕内时只擦除旧的光标，返回错误；MouseState 的坐标已经限制在屏幕内，正常不会发生
+    pub fn draw(&mut self, state: MouseState, writer: &mut Writer) -> Result<(), CellError> {
+        self.erase(writer);
+        let cell = Cell::new(state.y, state.x)?;
+        let status_color = ColorCode::new(Color::Black, Color::LightGray);
+        for (i, &byte) in format_status(state).iter().enumerate() {
+            let status = Cell::new(0, BUFFER_WIDTH - STATUS_LEN + i)?;
+            writer.try_put(status, ScreenChar::new(byte, status_color))?;
+        }
+        let under = writer.try_read(cell)?;
+        let original = under.color_code();
+        writer.try_put(
+            cell,
+            ScreenChar::new(under.ascii_character(), original.inverted()),
+        )?;
+        self.cursor = Some(Cursor { cell, original });
+        Ok(())
+    }
+
+    fn erase(&mut self, writer: &mut Writer) {
+        let Some(cursor) = self.cursor.take() else {
+            return;
+        };
+        // 保存的单元格是检查过的，不会越界
+        let Ok(under) = writer.try_read(cursor.cell) else {
+            return;
+        };
+        // 单元格在这期间被重写或者随屏幕滚走了，不能再改它的颜色
+        if under.color_code() == cursor.original.inverted() {
+            let _ = writer.try_put(
+                cursor.cell,
+                ScreenChar::new(under.ascii_character(), cursor.original),
+            );
+        }
+    }
+}
+
+/// 鼠标状态变化时重新绘制光标，需要先调用 init
+pub async fn track_cursor() {
+    let mut renderer = CursorRenderer::new();
+    loop {
+        future::poll_fn(|cx| {
+            if CHANGED.swap(false, Ordering::Acquire) {
+                return Poll::Ready(());
+            }
+            // 先注册 waker 再检查一次，避免丢失唤醒
+            WAKER.register(cx.waker());
+            if CHANGED.swap(false, Ordering::Acquire) {
+                WAKER.take();
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        let state = state();
+        // 坐标由 Tracker 限制在屏幕内
+        let _ = interrupts::without_interrupts(|| renderer.draw(state, &mut WRITER.lock()));
+    }
+}
+
+#[cfg(test)]
+use alloc::vec::Vec;
+
+#[cfg(test)]
+fn packets(bytes: &[u8]) -> Vec<Packet> {
+    let mut decoder = PacketDecoder::new();
+    bytes
+        .iter()
+        .filter_map(|&byte| decoder.push(byte))
+        .collect()
+}
+
+#[cfg(test)]
+fn packet(dx: i16, dy: i16, left: bool, right: bool) -> Packet {
+    Packet {
+        dx,
+        dy,
+        buttons: Buttons {
+            left,
+            right,
+            middle: false,
+        },
+    }
+}
+
+#[test_case]
+fn test_packet_buttons_and_deltas() {
+    assert_eq!(
+        packets(&[0x08 | LEFT, 5, 3, 0x08 | RIGHT | MIDDLE, 0, 0]),
+        [
+            packet(5, 3, true, false),
+            Packet {
+                dx: 0,
+                dy: 0,
+                buttons: Buttons {
+                    left: false,
+                    right: true,
+                    middle: true,
+                },
+            },
+        ]
+    );
+}
+
+#[test_case]
+fn test_packet_sign_extension_and_overflow() {
+    // 0xff 带符号位是 -1，0x00 带符号位是 -256
+    assert_eq!(
+        packets(&[0x08 | X_SIGN | Y_SIGN, 0xff, 0x00]),
+        [packet(-1, -256, false, false)]
+    );
+    // 溢出的方向位移记为 0，另一个方向不受影响
+    assert_eq!(
+        packets(&[0x08 | X_OVERFLOW | Y_SIGN, 0x7f, 0xfe]),
+        [packet(0, -2, false, false)]
+    );
+}
+
+#[test_case]
+fn test_tracker_clamps_to_screen() {
+    let mut tracker = Tracker::new();
+    assert_eq!(
+        (tracker.state().x, tracker.state().y),
+        (BUFFER_WIDTH / 2 - 1, BUFFER_HEIGHT / 2)
+    );
+    // 一直向左上移动，停在 (0, 0)
+    for _ in 0..10 {
+        tracker.apply(packet(-256, 255, false, false));
+    }
+    assert_eq!((tracker.state().x, tracker.state().y), (0, 0));
+    // 一直向右下移动，停在右下角
+    for _ in 0..10 {
+        tracker.apply(packet(255, -256, true, false));
+    }
+    let state = tracker.state();
+    assert_eq!((state.x, state.y), (BUFFER_WIDTH - 1, BUFFER_HEIGHT - 1));
+    assert!(state.buttons.left);
+
+    // 不足一列的位移被累计下来
+    tracker.apply(packet(-(COUNTS_PER_COLUMN as i16) / 2, 0, false, false));
+    assert_eq!(tracker.state().x, BUFFER_WIDTH - 1);
+    tracker.apply(packet(-(COUNTS_PER_COLUMN as i16) / 2, 0, false, false));
+    assert_eq!(tracker.state().x, BUFFER_WIDTH - 2);
+}
+
+#[test_case]
+fn test_decoder_resyncs_after_lost_byte() {
+    let decoded = packets(&[
+        // 第二个包丢了 y 字节，下一个包头被当作 y，拼出一个错误的包
+        0x08,
+        2,
+        0x08,
+        1,
+        1, // 错位后的 1、1 位 3 为 0，被丢弃
+        0x08 | LEFT,
+        4,
+        4,
+        0x08,
+        3,
+        3,
+    ]);
+    assert_eq!(decoded.len(), 3);
+    assert_eq!(decoded[1], packet(4, 4, true, false));
+    assert_eq!(decoded[2], packet(3, 3, false, false));
+
+    // 开头就不是包头时直接跳过
+    assert_eq!(
+        packets(&[0x00, 0x07, 0x08, 1, 2]),
+        [packet(1, 2, false, false)]
+    );
+}
+
+#[test_case]
+fn test_status_text() {
+    let state = MouseState {
+        x: 7,
+        y: 24,
+        buttons: Buttons {
+            left: true,
+            right: true,
+            middle: false,
+        },
+    };
+    assert_eq!(&format_status(state), b"mouse 07,24 L-R");
+}
+
+#[test_case]
+fn test_cursor_inverts_and_restores_cell() {
+    use crate::vga_buffer::TestWriter;
+
+    let mut writer = TestWriter::new();
+    writer.write_string("ab");
+    let row = BUFFER_HEIGHT - 1;
+    let original = writer.read_char(row, 0).1;
+    let mut renderer = CursorRenderer::new();
+    let at = |x, y| MouseState {
+        x,
+        y,
+        buttons: Buttons::default(),
+    };
+
+    renderer.draw(at(0, row), &mut writer).unwrap();
+    assert_eq!(writer.read_char(row, 0), (b'a', original.inverted()));
+    renderer.draw(at(1, row), &mut writer).unwrap();
+    assert_eq!(writer.read_char(row, 0), (b'a', original));
+    assert_eq!(writer.read_char(row, 1), (b'b', original.inverted()));
+    let status: Vec<u8> = (BUFFER_WIDTH - STATUS_LEN..BUFFER_WIDTH)
+        .map(|col| writer.read_char(0, col).0)
+        .collect();
+    assert_eq!(status, b"mouse 01,24 ---");
+
+    // 光标所在的行滚走后，移动光标不会改动滚上来的内容
+    writer.write_string("\n");
+    renderer.draw(at(5, 3), &mut writer).unwrap();
+    assert_eq!(writer.read_char(row - 1, 1), (b'b', original.inverted()));
+    assert_eq!(writer.read_char(row, 1).1, original);
+
+    // 屏幕外的坐标被拒绝而不是限制到边缘：旧的光标被擦除，不画新的
+    assert_eq!(
+        renderer.draw(at(BUFFER_WIDTH, 3), &mut writer),
+        Err(CellError::ColOutOfRange(BUFFER_WIDTH))
+    );
+    assert_eq!(writer.read_char(3, 5).1, original);
+    assert_eq!(writer.read_char(3, BUFFER_WIDTH - 1).1, original);
+}
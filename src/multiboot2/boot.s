@@ -0,0 +1,90 @@
+# Multiboot2 入口
+# 引导程序在 32 位保护模式、关闭分页的状态下跳转到这里，EAX 是魔数，EBX 是信息结构的物理地址。
+# 用 2MiB 大页恒等映射最低的 1GiB，打开 PAE 和长模式后开启分页，
+# 加载只有一个 64 位代码段的 GDT，远跳转进入 64 位代码，再调用 multiboot2_main(magic, info)
+
+.section .text
+.code32
+.global _multiboot2_start
+_multiboot2_start:
+    cli
+    movl $mb2_stack_top, %esp
+    movl %eax, %edi
+    movl %ebx, %esi
+
+    # P4[0] -> P3，P3[0] -> P2，P2 的 512 项各映射 2MiB
+    movl $mb2_p3, %eax
+    orl $0x3, %eax
+    movl %eax, mb2_p4
+    movl $mb2_p2, %eax
+    orl $0x3, %eax
+    movl %eax, mb2_p3
+    xorl %ecx, %ecx
+1:
+    movl %ecx, %eax
+    shll $21, %eax
+    # PRESENT | WRITABLE | HUGE_PAGE
+    orl $0x83, %eax
+    movl %eax, mb2_p2(,%ecx,8)
+    incl %ecx
+    cmpl $512, %ecx
+    jne 1b
+
+    movl $mb2_p4, %eax
+    movl %eax, %cr3
+    # CR4.PAE
+    movl %cr4, %eax
+    orl $(1 << 5), %eax
+    movl %eax, %cr4
+    # EFER.LME
+    movl $0xC0000080, %ecx
+    rdmsr
+    orl $(1 << 8), %eax
+    wrmsr
+    # CR0.PG
+    movl %cr0, %eax
+    orl $(1 << 31), %eax
+    movl %eax, %cr0
+
+    lgdt mb2_gdt_pointer
+    ljmp $0x08, $mb2_long_mode
+
+.code64
+mb2_long_mode:
+    xorw %ax, %ax
+    movw %ax, %ss
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %fs
+    movw %ax, %gs
+    # 32 位模式下寄存器的高 32 位未定义，清零后作为参数
+    movl %edi, %edi
+    movl %esi, %esi
+    # 帧指针为 0，回溯在这里结束
+    xorl %ebp, %ebp
+    call multiboot2_main
+2:
+    hlt
+    jmp 2b
+
+.section .rodata
+.balign 8
+mb2_gdt:
+    .quad 0
+    # 64 位代码段：L、P、S、可执行
+    .quad (1 << 53) | (1 << 47) | (1 << 44) | (1 << 43)
+mb2_gdt_pointer:
+    .word mb2_gdt_pointer - mb2_gdt - 1
+    .long mb2_gdt
+
+.section .bss
+.balign 4096
+mb2_p4:
+    .skip 4096
+mb2_p3:
+    .skip 4096
+mb2_p2:
+    .skip 4096
+mb2_stack_bottom:
+    .skip 4096 * 16
+mb2_stack_top:
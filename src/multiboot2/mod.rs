@@ -0,0 +1,543 @@
+//! Multiboot2 启动路径，让 GRUB 等引导程序直接加载内核
+//! 默认的启动路径是 bootimage 打包的 bootloader，它把 BootInfo 交给 entry_point! 指定的函数。
+//! 打开 multiboot2 feature 后：
+//! - .multiboot2_header 段中放置 Multiboot2 头部，请求内存映射和命令行
+//! - multiboot2.ld 把头部放在内核映像的开头，入口改为 boot.s 中的 _multiboot2_start
+//! - _multiboot2_start 在 32 位保护模式下运行，恒等映射最低的 1GiB 后进入长模式，
+//!   把 GRUB 放在 EAX/EBX 中的魔数和信息结构地址交给 main.rs 中的 multiboot2_main
+//! - boot_info 检查魔数并解析信息结构，构造与 bootloader 相同的 BootInfo，
+//!   两条路径最终都进入同一个 kernel_main
+//! - 第一个模块（例如 initrd 归档）所在的内存被保留下来，通过 module 交给 fs
+//! - 引导程序复制的 ACPI RSDP 留在信息结构中，通过 rsdp_address 交给 acpi
+//!
+//! 这条路径下物理内存偏移为 0（恒等映射），所以只有 1GiB 以下的内存标记为可用
+//!
+//! ```shell
+//! cargo build --features multiboot2
+//! # grub.cfg 中用 multiboot2 命令加载 target/x86_64-vm_os/debug/vm_os
+//! ```
+use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
+use conquer_once::spin::OnceCell;
+use core::ops::Range;
+
+#[cfg(feature = "multiboot2")]
+core::arch::global_asm!(include_str!("boot.s"), options(att_syntax));
+
+/// 引导程序跳转到内核时放在 EAX 中的魔数
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+/// 内核头部的魔数
+pub const HEADER_MAGIC: u32 = 0xe852_50d6;
+
+/// 信息结构中的标签类型
+const TAG_END: u32 = 0;
+const TAG_COMMAND_LINE: u32 = 1;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+/// 内容是 RSDP 的副本，分别是版本 1 和版本 2 以上的 RSDP
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+
+/// 入口代码恒等映射的范围，超出部分的内存无法通过物理内存偏移 0 访问
+pub const IDENTITY_MAPPED_LIMIT: u64 = 1 << 30;
+
+/// bootloader 的 MemoryMap 最多容纳的区域数
+const MAX_REGIONS: usize = 64;
+
+#[cfg(feature = "multiboot2")]
+static BOOT_INFO: OnceCell<bootloader::BootInfo> = OnceCell::uninit();
+static COMMAND_LINE: OnceCell<&'static str> = OnceCell::uninit();
+static MODULE: OnceCell<&'static [u8]> = OnceCell::uninit();
+static RSDP: OnceCell<u64> = OnceCell::uninit();
+
+/// 头部：固定的四个字段，之后是 8 字节对齐的标签，以结束标签收尾
+#[repr(C, align(8))]
+pub struct Header {
+    magic: u32,
+    /// 0 表示 32 位保护模式的 i386
+    architecture: u32,
+    header_length: u32,
+    /// 与前三个字段相加为 0（模 2^32）
+    checksum: u32,
+    information_request: InformationRequestTag,
+    end: HeaderTag,
+}
+
+#[repr(C, align(8))]
+struct HeaderTag {
+    typ: u16,
+    flags: u16,
+    size: u32,
+}
+
+/// 请求引导程序提供的信息
+#[repr(C, align(8))]
+struct InformationRequestTag {
+    tag: HeaderTag,
+    requests: [u32; 2],
+}
+
+const HEADER_LENGTH: u32 = core::mem::size_of::<Header>() as u32;
+
+#[cfg_attr(feature = "multiboot2", link_section = ".multiboot2_header")]
+#[used]
+pub static HEADER: Header = Header {
+    magic: HEADER_MAGIC,
+    architecture: 0,
+    header_length: HEADER_LENGTH,
+    checksum: 0u32.wrapping_sub(HEADER_MAGIC.wrapping_add(HEADER_LENGTH)),
+    information_request: InformationRequestTag {
+        tag: HeaderTag {
+            typ: 1,
+            flags: 0,
+            size: core::mem::size_of::<HeaderTag>() as u32 + 8,
+        },
+        requests: [TAG_COMMAND_LINE, TAG_MEMORY_MAP],
+    },
+    end: HeaderTag {
+        typ: 0,
+        flags: 0,
+        size: 8,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// EAX 中不是 BOOTLOADER_MAGIC，内核不是由 Multiboot2 引导程序启动的
+    BadMagic(u32),
+    /// 信息结构的地址没有 8 字节对齐
+    Misaligned,
+    /// 长度字段与实际数据不符
+    Truncated,
+    /// 引导程序没有提供内存映射
+    MissingMemoryMap,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().unwrap(),
+    ))
+}
+
+/// 引导程序传入的信息结构：total_size u32、保留 u32，之后是 8 字节对齐的标签，
+/// 每个标签以 type u32、size u32 开头，size 包含这 8 个字节但不包含对齐填充
+pub struct BootInformation<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> BootInformation<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let total_size = read_u32(bytes, 0).ok_or(ParseError::Truncated)? as usize;
+        if total_size < 8 || total_size > bytes.len() {
+            return Err(ParseError::Truncated);
+        }
+        Ok(BootInformation {
+            bytes: &bytes[..total_size],
+        })
+    }
+
+    /// 从引导程序给出的物理地址读取信息结构
+    ///
+    /// # Safety
+    /// addr 必须指向有效的信息结构，并且在整个内核运行期间可以访问
+    pub unsafe fn from_addr(addr: u64) -> Result<BootInformation<'static>, ParseError> {
+        if !addr.is_multiple_of(8) {
+            return Err(ParseError::Misaligned);
+        }
+        let total_size = core::ptr::read(addr as *const u32) as usize;
+        BootInformation::parse(core::slice::from_raw_parts(addr as *const u8, total_size))
+    }
+
+    /// 信息结构占用的内存
+    pub fn range(&self) -> Range<u64> {
+        let start = self.bytes.as_ptr() as u64;
+        start..start + self.bytes.len() as u64
+    }
+
+    /// 按顺序返回 (类型, 包含头部的标签内容)，遇到结束标签或者格式错误时停止
+    fn tags(&self) -> impl Iterator<Item = (u32, &'a [u8])> {
+        let bytes = self.bytes;
+        let mut offset = 8;
+        core::iter::from_fn(move || {
+            let typ = read_u32(bytes, offset)?;
+            let size = read_u32(bytes, offset + 4)? as usize;
+            if typ == TAG_END || size < 8 {
+                return None;
+            }
+            let tag = bytes.get(offset..offset + size)?;
+            offset += size.next_multiple_of(8);
+            Some((typ, tag))
+        })
+    }
+
+    fn find_tag(&self, typ: u32) -> Option<&'a [u8]> {
+        self.tags()
+            .find(|&(tag_type, _)| tag_type == typ)
+            .map(|(_, tag)| tag)
+    }
+
+    /// 启动命令行，以 NUL 结尾的 UTF-8 字符串
+    pub fn command_line(&self) -> Option<&'a str> {
+        let string = &self.find_tag(TAG_COMMAND_LINE)?[8..];
+        let len = string.iter().position(|&byte| byte == 0)?;
+        core::str::from_utf8(&string[..len]).ok()
+    }
+
+    /// 引导程序加载的模块：mod_start u32、mod_end u32，之后是以 NUL 结尾的字符串
+    pub fn modules(&self) -> impl Iterator<Item = Module<'a>> {
+        self.tags()
+            .filter(|&(typ, _)| typ == TAG_MODULE)
+            .filter_map(|(_, tag)| {
+                let start = read_u32(tag, 8)? as u64;
+                let end = read_u32(tag, 12)? as u64;
+                let string = tag.get(16..)?;
+                let len = string.iter().position(|&byte| byte == 0)?;
+                Some(Module {
+                    range: start..end,
+                    string: core::str::from_utf8(&string[..len]).ok()?,
+                })
+            })
+            .filter(|module| module.range.start <= module.range.end)
+    }
+
+    /// RSDP 的副本，有新版本的标签时优先使用
+    pub fn rsdp(&self) -> Option<&'a [u8]> {
+        let tag = self
+            .find_tag(TAG_ACPI_NEW)
+            .or_else(|| self.find_tag(TAG_ACPI_OLD))?;
+        tag.get(8..)
+    }
+
+    /// 内存映射：entry_size u32、entry_version u32，之后每项是 base u64、length u64、type u32、保留 u32
+    pub fn memory_areas(&self) -> Option<impl Iterator<Item = MemoryArea> + 'a> {
+        let tag = self.find_tag(TAG_MEMORY_MAP)?;
+        let entry_size = read_u32(tag, 8)? as usize;
+        if entry_size < 24 {
+            return None;
+        }
+        let entries = tag.get(16..)?;
+        Some(entries.chunks_exact(entry_size).map(|entry| MemoryArea {
+            base: read_u64(entry, 0).unwrap(),
+            length: read_u64(entry, 8).unwrap(),
+            typ: read_u32(entry, 16).unwrap(),
+        }))
+    }
+}
+
+/// 模块占用的物理内存和 GRUB 配置中写在模块路径后面的字符串
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module<'a> {
+    pub range: Range<u64>,
+    pub string: &'a str,
+}
+
+/// 内存映射中的一项，type 的取值与 BIOS E820 相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryArea {
+    pub base: u64,
+    pub length: u64,
+    pub typ: u32,
+}
+
+impl MemoryArea {
+    fn region_type(&self) -> MemoryRegionType {
+        match self.typ {
+            1 => MemoryRegionType::Usable,
+            3 => MemoryRegionType::AcpiReclaimable,
+            4 => MemoryRegionType::AcpiNvs,
+            5 => MemoryRegionType::BadMemory,
+            _ => MemoryRegionType::Reserved,
+        }
+    }
+}
+
+const FRAME_SIZE: u64 = 4096;
+
+fn align_down(addr: u64) -> u64 {
+    addr & !(FRAME_SIZE - 1)
+}
+
+fn align_up(addr: u64) -> u64 {
+    align_down(addr.saturating_add(FRAME_SIZE - 1))
+}
+
+fn add_region(map: &mut MemoryMap, range: Range<u64>, region_type: MemoryRegionType) {
+    // 空区域会被 MemoryMap 丢弃；放满后忽略剩下的区域，少一点可用内存也比 panic 好
+    if range.start < range.end && map.len() < MAX_REGIONS {
+        map.add_region(MemoryRegion {
+            range: FrameRange::new(range.start, range.end),
+            region_type,
+        });
+    }
+}
+
+/// 把内存映射转换为 bootloader 的 MemoryMap
+/// 可用区域中与 reserved 重叠的部分（内核映像、信息结构等）标记为对应的类型，剩余部分按帧向内取整，
+/// reserved 按帧向外取整，保证分配出的帧不会与它们共用一页；limit 以上的可用内存被丢弃。
+/// reserved 需要按起始地址排序
+pub fn memory_map(
+    areas: impl Iterator<Item = MemoryArea>,
+    reserved: &[(Range<u64>, MemoryRegionType)],
+    limit: u64,
+) -> MemoryMap {
+    let mut map = MemoryMap::new();
+    for area in areas {
+        let end = area.base.saturating_add(area.length);
+        if area.region_type() != MemoryRegionType::Usable {
+            add_region(
+                &mut map,
+                align_down(area.base)..align_up(end),
+                area.region_type(),
+            );
+            continue;
+        }
+
+        let (start, end) = (align_up(area.base), align_down(end.min(limit)));
+        let mut cursor = start;
+        for (range, region_type) in reserved {
+            let range = align_down(range.start)..align_up(range.end);
+            if range.end <= cursor || range.start >= end {
+                continue;
+            }
+            add_region(&mut map, cursor..range.start, MemoryRegionType::Usable);
+            let carved = range.start.max(cursor)..range.end.min(end);
+            add_region(&mut map, carved.clone(), *region_type);
+            cursor = carved.end;
+        }
+        add_region(&mut map, cursor..end, MemoryRegionType::Usable);
+    }
+    map
+}
+
+// 由 multiboot2.ld 定义，内核映像（包括 boot.s 中的页表和栈）的起止地址
+#[cfg(feature = "multiboot2")]
+extern "C" {
+    static __kernel_start: u8;
+    static __kernel_end: u8;
+}
+
+/// 检查魔数、解析信息结构，构造与 bootloader 路径相同的 BootInfo
+/// 只能在 multiboot2_main 中调用一次，此时还没有堆
+#[cfg(feature = "multiboot2")]
+pub fn boot_info(magic: u32, info_addr: u64) -> Result<&'static bootloader::BootInfo, ParseError> {
+    use bootloader::BootInfo;
+
+    if magic != BOOTLOADER_MAGIC {
+        return Err(ParseError::BadMagic(magic));
+    }
+    let info = unsafe { BootInformation::from_addr(info_addr)? };
+    let kernel =
+        core::ptr::addr_of!(__kernel_start) as u64..core::ptr::addr_of!(__kernel_end) as u64;
+    // 只使用第一个模块，在恒等映射范围以外的模块无法访问
+    let module = info
+        .modules()
+        .next()
+        .map(|module| module.range)
+        .filter(|range| range.end <= IDENTITY_MAPPED_LIMIT);
+    let mut reserved = [
+        (0..FRAME_SIZE, MemoryRegionType::FrameZero),
+        (kernel, MemoryRegionType::Kernel),
+        (info.range(), MemoryRegionType::BootInfo),
+        (module.clone().unwrap_or(0..0), MemoryRegionType::Package),
+    ];
+    reserved.sort_unstable_by_key(|(range, _)| range.start);
+    let areas = info.memory_areas().ok_or(ParseError::MissingMemoryMap)?;
+    let memory_map = memory_map(areas, &reserved, IDENTITY_MAPPED_LIMIT);
+
+    if let Some(command_line) = info.command_line() {
+        COMMAND_LINE.init_once(|| command_line);
+    }
+    // 信息结构所在的内存被保留，其中的 RSDP 副本一直有效
+    if let Some(rsdp) = info.rsdp() {
+        RSDP.init_once(|| rsdp.as_ptr() as u64);
+    }
+    if let Some(range) = module {
+        let len = (range.end - range.start) as usize;
+        MODULE.init_once(|| unsafe { core::slice::from_raw_parts(range.start as *const u8, len) });
+    }
+    // 没有 TLS 和递归页表；恒等映射，物理内存偏移为 0
+    Ok(BOOT_INFO.get_or_init(|| BootInfo::new(memory_map, None, 0, 0)))
+}
+
+/// 引导程序传入的启动命令行，bootloader 路径下总是 None
+pub fn command_line() -> Option<&'static str> {
+    COMMAND_LINE.get().copied()
+}
+
+/// 引导程序加载的第一个模块的内容，bootloader 路径下总是 None
+pub fn module() -> Option<&'static [u8]> {
+    MODULE.get().copied()
+}
+
+/// 引导程序提供的 RSDP 的物理地址，bootloader 路径下总是 None
+pub fn rsdp_address() -> Option<u64> {
+    RSDP.get().copied()
+}
+
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 按规范的布局构造信息结构：命令行标签、模块标签、内存映射标签、结束标签
+#[cfg(test)]
+fn build_info(
+    command_line: &str,
+    modules: &[(u32, u32, &str)],
+    areas: &[(u64, u64, u32)],
+) -> Vec<u8> {
+    fn tag(info: &mut Vec<u8>, typ: u32, body: &[u8]) {
+        info.extend_from_slice(&typ.to_le_bytes());
+        info.extend_from_slice(&(8 + body.len() as u32).to_le_bytes());
+        info.extend_from_slice(body);
+        info.resize(info.len().next_multiple_of(8), 0);
+    }
+
+    let mut info = alloc::vec![0; 8];
+    let mut string = Vec::from(command_line.as_bytes());
+    string.push(0);
+    tag(&mut info, TAG_COMMAND_LINE, &string);
+    for &(start, end, string) in modules {
+        let mut body = Vec::new();
+        body.extend_from_slice(&start.to_le_bytes());
+        body.extend_from_slice(&end.to_le_bytes());
+        body.extend_from_slice(string.as_bytes());
+        body.push(0);
+        tag(&mut info, TAG_MODULE, &body);
+    }
+
+    let mut body = Vec::new();
+    body.extend_from_slice(&24u32.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    for &(base, length, typ) in areas {
+        body.extend_from_slice(&base.to_le_bytes());
+        body.extend_from_slice(&length.to_le_bytes());
+        body.extend_from_slice(&typ.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+    }
+    tag(&mut info, TAG_MEMORY_MAP, &body);
+    tag(&mut info, TAG_END, &[]);
+
+    let total_size = info.len() as u32;
+    info[..4].copy_from_slice(&total_size.to_le_bytes());
+    info
+}
+
+#[test_case]
+fn test_header_checksum() {
+    let sum = HEADER
+        .magic
+        .wrapping_add(HEADER.architecture)
+        .wrapping_add(HEADER.header_length)
+        .wrapping_add(HEADER.checksum);
+    assert_eq!(sum, 0);
+    assert_eq!(HEADER.header_length, 40);
+    assert_eq!(HEADER.information_request.tag.size, 16);
+}
+
+#[test_case]
+fn test_parse_synthetic_info() {
+    let areas = [
+        (0, 0x9fc00, 1),
+        (0x9fc00, 0x400, 2),
+        (0x10_0000, 0x7ee_0000, 1),
+        (0xfffc_0000, 0x4_0000, 2),
+    ];
+    let modules = [(0x20_0000, 0x20_2800, "initrd"), (0x30_0000, 0x30_0000, "")];
+    let bytes = build_info("root=/dev/sda loglevel=3", &modules, &areas);
+    let info = BootInformation::parse(&bytes).unwrap();
+    assert_eq!(info.command_line(), Some("root=/dev/sda loglevel=3"));
+    assert_eq!(info.rsdp(), None);
+    let parsed: Vec<Module> = info.modules().collect();
+    assert_eq!(
+        parsed,
+        [
+            Module {
+                range: 0x20_0000..0x20_2800,
+                string: "initrd"
+            },
+            Module {
+                range: 0x30_0000..0x30_0000,
+                string: ""
+            },
+        ]
+    );
+    let parsed: Vec<MemoryArea> = info.memory_areas().unwrap().collect();
+    assert_eq!(parsed.len(), areas.len());
+    assert_eq!(
+        parsed[2],
+        MemoryArea {
+            base: 0x10_0000,
+            length: 0x7ee_0000,
+            typ: 1
+        }
+    );
+
+    // 长度字段超出实际数据
+    assert_eq!(
+        BootInformation::parse(&bytes[..bytes.len() - 8]).err(),
+        Some(ParseError::Truncated)
+    );
+    // 没有命令行和内存映射时返回 None
+    let empty = build_info("", &[], &[]);
+    let info = BootInformation::parse(&empty).unwrap();
+    assert_eq!(info.command_line(), Some(""));
+    assert_eq!(info.memory_areas().map(|areas| areas.count()), Some(0));
+    let mut bare = alloc::vec![0u8; 16];
+    bare[0] = 16;
+    let info = BootInformation::parse(&bare).unwrap();
+    assert_eq!(info.command_line(), None);
+    assert!(info.memory_areas().is_none());
+}
+
+#[test_case]
+fn test_memory_map_carves_reserved_ranges() {
+    let areas = [
+        MemoryArea {
+            base: 0,
+            length: 0x9fc00,
+            typ: 1,
+        },
+        MemoryArea {
+            base: 0x9fc00,
+            length: 0x400,
+            typ: 2,
+        },
+        MemoryArea {
+            base: 0x10_0000,
+            length: 0x7ff0_0000,
+            typ: 1,
+        },
+    ];
+    let reserved = [
+        (0..FRAME_SIZE, MemoryRegionType::FrameZero),
+        (0x10_0000..0x18_0800, MemoryRegionType::Kernel),
+        (0x20_0010..0x20_0100, MemoryRegionType::BootInfo),
+    ];
+    let map = memory_map(areas.into_iter(), &reserved, IDENTITY_MAPPED_LIMIT);
+    let regions: Vec<(u64, u64, MemoryRegionType)> = map
+        .iter()
+        .map(|region| {
+            (
+                region.range.start_addr(),
+                region.range.end_addr(),
+                region.region_type,
+            )
+        })
+        .collect();
+    assert_eq!(
+        regions,
+        [
+            (0, 0x1000, MemoryRegionType::FrameZero),
+            (0x1000, 0x9f000, MemoryRegionType::Usable),
+            (0x9f000, 0xa0000, MemoryRegionType::Reserved),
+            (0x10_0000, 0x18_1000, MemoryRegionType::Kernel),
+            (0x18_1000, 0x20_0000, MemoryRegionType::Usable),
+            (0x20_0000, 0x20_1000, MemoryRegionType::BootInfo),
+            (0x20_1000, IDENTITY_MAPPED_LIMIT, MemoryRegionType::Usable),
+        ]
+    );
+}
@@ -0,0 +1,165 @@
+//! Intel 8254x（e1000）
+//! 复位：屏蔽中断后置位 CTRL.RST，硬件完成复位后把它清零；之后置位 CTRL.SLU 让链路开始协商。
+//! 复位时硬件从 EEPROM 载入 MAC 地址到接收地址寄存器 RAL0/RAH0，RAH0 的 AV 位表示有效；
+//! 没有载入时通过 EERD 逐字读 EEPROM 的前 3 个字
+use super::{poll, LinkStatus, MacAddress, NetError, Registers, TIMEOUT_SPINS};
+
+pub(super) const VENDOR_ID: u16 = 0x8086;
+/// 82540EM（QEMU 的 e1000）和 82545EM，两者的寄存器布局相同
+pub(super) const DEVICE_IDS: &[u16] = &[0x100e, 0x100f];
+
+const CTRL: usize = 0x0000;
+const STATUS: usize = 0x0008;
+const EERD: usize = 0x0014;
+/// 写 1 屏蔽对应的中断
+const IMC: usize = 0x00d8;
+const RAL0: usize = 0x5400;
+const RAH0: usize = 0x5404;
+
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+const STATUS_SPEED_SHIFT: u32 = 6;
+const RAH_AV: u32 = 1 << 31;
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+const EERD_ADDR_SHIFT: u32 = 8;
+const EERD_DATA_SHIFT: u32 = 16;
+
+/// 复位并返回 MAC 地址
+pub(super) fn init(regs: &mut impl Registers) -> Result<MacAddress, NetError> {
+    regs.write32(IMC, u32::MAX);
+    let ctrl = regs.read32(CTRL);
+    regs.write32(CTRL, ctrl | CTRL_RST);
+    if !poll(TIMEOUT_SPINS, || regs.read32(CTRL) & CTRL_RST == 0) {
+        return Err(NetError::ResetTimeout);
+    }
+    // 复位也清除了中断屏蔽
+    regs.write32(IMC, u32::MAX);
+    let ctrl = regs.read32(CTRL);
+    regs.write32(CTRL, ctrl | CTRL_SLU);
+    read_mac(regs)
+}
+
+fn read_mac(regs: &mut impl Registers) -> Result<MacAddress, NetError> {
+    let high = regs.read32(RAH0);
+    let mut mac = [0; 6];
+    if high & RAH_AV != 0 {
+        mac[..4].copy_from_slice(&regs.read32(RAL0).to_le_bytes());
+        mac[4..].copy_from_slice(&(high as u16).to_le_bytes());
+    } else {
+        for (word, bytes) in mac.chunks_exact_mut(2).enumerate() {
+            bytes.copy_from_slice(&read_eeprom(regs, word as u8)?.to_le_bytes());
+        }
+    }
+    Ok(MacAddress(mac))
+}
+
+fn read_eeprom(regs: &mut impl Registers, word: u8) -> Result<u16, NetError> {
+    regs.write32(EERD, (word as u32) << EERD_ADDR_SHIFT | EERD_START);
+    let mut value = 0;
+    let done = poll(TIMEOUT_SPINS, || {
+        value = regs.read32(EERD);
+        value & EERD_DONE != 0
+    });
+    if !done {
+        return Err(NetError::EepromTimeout);
+    }
+    Ok((value >> EERD_DATA_SHIFT) as u16)
+}
+
+pub(super) fn link_status(regs: &mut impl Registers) -> LinkStatus {
+    let status = regs.read32(STATUS);
+    let up = status & STATUS_LU != 0;
+    let speed = match (status >> STATUS_SPEED_SHIFT) & 0b11 {
+        0 => 10,
+        1 => 100,
+        _ => 1000,
+    };
+    LinkStatus {
+        up,
+        speed: up.then_some(speed),
+    }
+}
+
+#[cfg(test)]
+use super::MockRegisters;
+
+/// QEMU 默认的 MAC 地址 52:54:00:12:34:56 在 EEPROM 中的前 3 个字
+#[cfg(test)]
+const EEPROM: [u16; 3] = [0x5452, 0x1200, 0x5634];
+
+/// 模拟复位立即完成、EEPROM 读取立即完成的 e1000
+#[cfg(test)]
+fn device(regs: &mut MockRegisters, offset: usize) {
+    match offset {
+        CTRL => {
+            let ctrl = regs.read32(CTRL);
+            regs.set32(CTRL, ctrl & !CTRL_RST);
+        }
+        EERD => {
+            let word = (regs.read32(EERD) >> EERD_ADDR_SHIFT) as u8 as usize;
+            regs.set32(EERD, (EEPROM[word] as u32) << EERD_DATA_SHIFT | EERD_DONE);
+        }
+        _ => {}
+    }
+}
+
+#[test_case]
+fn test_init_reads_mac_from_receive_address() {
+    let mut regs = MockRegisters::new(device);
+    regs.set32(RAL0, 0x1200_5452);
+    regs.set32(RAH0, RAH_AV | 0x5634);
+    let mac = init(&mut regs).unwrap();
+    assert_eq!(mac, MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]));
+    // 屏蔽中断、复位、再屏蔽中断、置位 SLU；没有读 EEPROM
+    assert_eq!(
+        regs.writes,
+        [
+            (IMC, u32::MAX),
+            (CTRL, CTRL_RST),
+            (IMC, u32::MAX),
+            (CTRL, CTRL_SLU)
+        ]
+    );
+}
+
+#[test_case]
+fn test_init_falls_back_to_eeprom() {
+    let mut regs = MockRegisters::new(device);
+    let mac = init(&mut regs).unwrap();
+    assert_eq!(mac, MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]));
+    let eeprom_reads: alloc::vec::Vec<_> = regs
+        .writes
+        .iter()
+        .filter(|&&(offset, _)| offset == EERD)
+        .map(|&(_, value)| value)
+        .collect();
+    assert_eq!(eeprom_reads, [0x001, 0x101, 0x201]);
+
+    // 复位位一直不清零
+    let mut stuck = MockRegisters::new(|_, _| {});
+    assert!(matches!(init(&mut stuck), Err(NetError::ResetTimeout)));
+}
+
+#[test_case]
+fn test_link_status() {
+    let mut regs = MockRegisters::new(|_, _| {});
+    // 全双工、1000 Mbps、链路连通
+    regs.set32(STATUS, 0x0000_0083);
+    assert_eq!(
+        link_status(&mut regs),
+        LinkStatus {
+            up: true,
+            speed: Some(1000)
+        }
+    );
+    regs.set32(STATUS, 0x0000_0041);
+    assert_eq!(
+        link_status(&mut regs),
+        LinkStatus {
+            up: false,
+            speed: None
+        }
+    );
+}
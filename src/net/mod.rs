@@ -0,0 +1,425 @@
+//! 网卡的最小初始化
+//! 在 PCI 设备中找第一块支持的网卡：Intel e1000（QEMU 默认的 82540EM）或者 virtio-net 的传统接口，
+//! 复位后读出出厂的 MAC 地址和链路状态，启动时打印一行，netinfo 命令显示。还不收发数据包。
+//!
+//! 初始化流程只通过 Registers 读写寄存器，测试中换成预设的寄存器值：
+//! - e1000 的寄存器在内存 BAR 0 中，用 memory::map_mmio 映射为不可缓存的页后按 32 位 volatile 访问
+//! - 传统 virtio 的寄存器在 I/O BAR 0 中，用端口访问
+//!
+//! ```shell
+//! qemu-system-x86_64 ... -nic user,model=e1000
+//! qemu-system-x86_64 ... -nic user,model=virtio-net-pci
+//! ```
+use crate::memory::{self, MmioError};
+use crate::pci::{self, Bar, PciAddress, PciDevice};
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+mod e1000;
+mod virtio;
+
+/// 等待设备完成复位等操作时最多轮询的次数
+const TIMEOUT_SPINS: usize = 1_000_000;
+
+/// 按相对 BAR 起始处的偏移读写设备寄存器，抽象出来以便用预设的寄存器值测试
+pub trait Registers {
+    fn read8(&mut self, offset: usize) -> u8;
+    fn write8(&mut self, offset: usize, value: u8);
+    fn read32(&mut self, offset: usize) -> u32;
+    fn write32(&mut self, offset: usize, value: u32);
+}
+
+/// 映射到 base 处的内存寄存器。
+/// 页是不可缓存的，CPU 按程序顺序逐个访问；volatile 保证编译器不合并、不省略、不重排这些访问
+pub struct Mmio {
+    base: VirtAddr,
+}
+
+impl Registers for Mmio {
+    fn read8(&mut self, offset: usize) -> u8 {
+        unsafe { (self.base + offset as u64).as_ptr::<u8>().read_volatile() }
+    }
+
+    fn write8(&mut self, offset: usize, value: u8) {
+        unsafe {
+            (self.base + offset as u64)
+                .as_mut_ptr::<u8>()
+                .write_volatile(value)
+        }
+    }
+
+    fn read32(&mut self, offset: usize) -> u32 {
+        unsafe { (self.base + offset as u64).as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write32(&mut self, offset: usize, value: u32) {
+        unsafe {
+            (self.base + offset as u64)
+                .as_mut_ptr::<u32>()
+                .write_volatile(value)
+        }
+    }
+}
+
+/// 从端口 base 开始的 I/O 寄存器
+pub struct PortIo {
+    base: u16,
+}
+
+impl Registers for PortIo {
+    fn read8(&mut self, offset: usize) -> u8 {
+        unsafe { Port::new(self.base + offset as u16).read() }
+    }
+
+    fn write8(&mut self, offset: usize, value: u8) {
+        unsafe { Port::new(self.base + offset as u16).write(value) }
+    }
+
+    fn read32(&mut self, offset: usize) -> u32 {
+        unsafe { Port::new(self.base + offset as u16).read() }
+    }
+
+    fn write32(&mut self, offset: usize, value: u32) {
+        unsafe { Port::new(self.base + offset as u16).write(value) }
+    }
+}
+
+/// 一块网卡的寄存器，按 BAR 的类型选择访问方式
+enum Access {
+    Mmio(Mmio),
+    Port(PortIo),
+}
+
+impl Registers for Access {
+    fn read8(&mut self, offset: usize) -> u8 {
+        match self {
+            Access::Mmio(regs) => regs.read8(offset),
+            Access::Port(regs) => regs.read8(offset),
+        }
+    }
+
+    fn write8(&mut self, offset: usize, value: u8) {
+        match self {
+            Access::Mmio(regs) => regs.write8(offset, value),
+            Access::Port(regs) => regs.write8(offset, value),
+        }
+    }
+
+    fn read32(&mut self, offset: usize) -> u32 {
+        match self {
+            Access::Mmio(regs) => regs.read32(offset),
+            Access::Port(regs) => regs.read32(offset),
+        }
+    }
+
+    fn write32(&mut self, offset: usize, value: u32) {
+        match self {
+            Access::Mmio(regs) => regs.write32(offset, value),
+            Access::Port(regs) => regs.write32(offset, value),
+        }
+    }
+}
+
+/// 反复调用 done 直到它返回 true，超过 spins 次返回 false
+fn poll(spins: usize, mut done: impl FnMut() -> bool) -> bool {
+    for _ in 0..spins {
+        if done() {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkStatus {
+    pub up: bool,
+    /// 协商出的速率（Mbps），设备不报告时为 None
+    pub speed: Option<u32>,
+}
+
+impl fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.up, self.speed) {
+            (false, _) => write!(f, "down"),
+            (true, None) => write!(f, "up"),
+            (true, Some(speed)) => write!(f, "up, {} Mbps", speed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NicKind {
+    E1000,
+    VirtioNet,
+}
+
+impl NicKind {
+    /// 由 PCI 厂商号和设备号识别支持的网卡
+    pub fn identify(vendor_id: u16, device_id: u16) -> Option<NicKind> {
+        match vendor_id {
+            e1000::VENDOR_ID if e1000::DEVICE_IDS.contains(&device_id) => Some(NicKind::E1000),
+            virtio::VENDOR_ID if virtio::DEVICE_IDS.contains(&device_id) => {
+                Some(NicKind::VirtioNet)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NicKind::E1000 => "e1000",
+            NicKind::VirtioNet => "virtio-net",
+        }
+    }
+
+    fn link_status(self, regs: &mut impl Registers) -> LinkStatus {
+        match self {
+            NicKind::E1000 => e1000::link_status(regs),
+            NicKind::VirtioNet => virtio::link_status(regs),
+        }
+    }
+}
+
+/// 第一块支持的网卡
+pub fn find<'a>(
+    mut devices: impl Iterator<Item = &'a PciDevice>,
+) -> Option<(NicKind, &'a PciDevice)> {
+    devices.find_map(|device| {
+        NicKind::identify(device.vendor_id, device.device_id).map(|kind| (kind, device))
+    })
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    NoDevice,
+    /// BAR 0 不存在或者类型不对
+    NoBar(NicKind),
+    Mmio(MmioError),
+    /// 设备没有在限定的时间内完成复位
+    ResetTimeout,
+    EepromTimeout,
+    /// virtio 设备不提供 MAC 地址
+    NoMac,
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::NoDevice => write!(f, "no supported NIC found"),
+            NetError::NoBar(kind) => write!(f, "{}: BAR 0 is missing or unexpected", kind.name()),
+            NetError::Mmio(error) => write!(f, "failed to map registers: {:?}", error),
+            NetError::ResetTimeout => write!(f, "device did not finish reset"),
+            NetError::EepromTimeout => write!(f, "EEPROM read timed out"),
+            NetError::NoMac => write!(f, "device does not report a MAC address"),
+        }
+    }
+}
+
+impl From<MmioError> for NetError {
+    fn from(error: MmioError) -> Self {
+        NetError::Mmio(error)
+    }
+}
+
+/// 初始化好的网卡
+struct Nic {
+    kind: NicKind,
+    address: PciAddress,
+    mac: MacAddress,
+    regs: Access,
+}
+
+static NIC: Mutex<Option<Nic>> = Mutex::new(None);
+
+/// 网卡的概况，链路状态是读取时的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NicInfo {
+    pub kind: NicKind,
+    pub address: PciAddress,
+    pub mac: MacAddress,
+    pub link: LinkStatus,
+}
+
+impl fmt::Display for NicInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}: mac {}, link {}",
+            self.kind.name(),
+            self.address,
+            self.mac,
+            self.link
+        )
+    }
+}
+
+/// 初始化第一块支持的网卡，需要在 pci::init 和堆初始化之后调用，只应调用一次
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<NicInfo, NetError> {
+    let (kind, device) = find(pci::devices()).ok_or(NetError::NoDevice)?;
+    pci::enable_decoding(device.address);
+    let mut regs = match (kind, device.bars[0]) {
+        (NicKind::E1000, Some(Bar::Memory { address, size, .. })) => {
+            let base = memory::map_mmio(mapper, frame_allocator, PhysAddr::new(address), size)?;
+            Access::Mmio(Mmio { base })
+        }
+        (NicKind::VirtioNet, Some(Bar::Io { port, .. })) => {
+            Access::Port(PortIo { base: port as u16 })
+        }
+        _ => return Err(NetError::NoBar(kind)),
+    };
+    let mac = match kind {
+        NicKind::E1000 => e1000::init(&mut regs)?,
+        NicKind::VirtioNet => virtio::init(&mut regs)?,
+    };
+    let mut nic = Nic {
+        kind,
+        address: device.address,
+        mac,
+        regs,
+    };
+    let info = nic.info();
+    *NIC.lock() = Some(nic);
+    Ok(info)
+}
+
+impl Nic {
+    fn info(&mut self) -> NicInfo {
+        NicInfo {
+            kind: self.kind,
+            address: self.address,
+            mac: self.mac,
+            link: self.kind.link_status(&mut self.regs),
+        }
+    }
+}
+
+/// 已经初始化的网卡，没有时返回 None
+pub fn info() -> Option<NicInfo> {
+    NIC.lock().as_mut().map(Nic::info)
+}
+
+#[cfg(test)]
+use alloc::collections::BTreeMap;
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 预设的寄存器：按字节保存，没有设置的读出 0，多字节按小端组合。
+/// 每次写入都被记录，之后调用 on_write 模拟设备的反应
+#[cfg(test)]
+struct MockRegisters {
+    bytes: BTreeMap<usize, u8>,
+    writes: Vec<(usize, u32)>,
+    on_write: fn(&mut MockRegisters, usize),
+}
+
+#[cfg(test)]
+impl MockRegisters {
+    fn new(on_write: fn(&mut MockRegisters, usize)) -> Self {
+        MockRegisters {
+            bytes: BTreeMap::new(),
+            writes: Vec::new(),
+            on_write,
+        }
+    }
+
+    fn set32(&mut self, offset: usize, value: u32) {
+        for (index, byte) in value.to_le_bytes().into_iter().enumerate() {
+            self.bytes.insert(offset + index, byte);
+        }
+    }
+}
+
+#[cfg(test)]
+impl Registers for MockRegisters {
+    fn read8(&mut self, offset: usize) -> u8 {
+        self.bytes.get(&offset).copied().unwrap_or(0)
+    }
+
+    fn write8(&mut self, offset: usize, value: u8) {
+        self.bytes.insert(offset, value);
+        self.writes.push((offset, value as u32));
+        (self.on_write)(self, offset);
+    }
+
+    fn read32(&mut self, offset: usize) -> u32 {
+        u32::from_le_bytes(core::array::from_fn(|index| self.read8(offset + index)))
+    }
+
+    fn write32(&mut self, offset: usize, value: u32) {
+        self.set32(offset, value);
+        self.writes.push((offset, value));
+        (self.on_write)(self, offset);
+    }
+}
+
+#[test_case]
+fn test_find_supported_nic() {
+    let device = |device: u8, vendor_id, device_id| PciDevice {
+        address: PciAddress {
+            bus: 0,
+            device,
+            function: 0,
+        },
+        vendor_id,
+        device_id,
+        class: 0x02,
+        subclass: 0x00,
+        prog_if: 0,
+        header_type: pci::HEADER_GENERAL,
+        bars: [None; 6],
+    };
+    // 只有现代接口的 virtio-net（0x1041）和其他厂商的网卡不支持
+    let devices = [
+        device(2, 0x1af4, 0x1041),
+        device(3, 0x10ec, 0x8139),
+        device(4, 0x8086, 0x100e),
+        device(5, 0x1af4, 0x1000),
+    ];
+    let (kind, found) = find(devices.iter()).unwrap();
+    assert_eq!((kind, found.address.device), (NicKind::E1000, 4));
+    assert_eq!(
+        find(devices[..2].iter().chain(&devices[3..])).map(|(kind, _)| kind),
+        Some(NicKind::VirtioNet)
+    );
+    assert!(find(devices[..2].iter()).is_none());
+}
+
+#[test_case]
+fn test_display_mac_and_link() {
+    use alloc::format;
+
+    let mac = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    assert_eq!(format!("{}", mac), "52:54:00:12:34:56");
+    let up = LinkStatus {
+        up: true,
+        speed: Some(1000),
+    };
+    assert_eq!(format!("{}", up), "up, 1000 Mbps");
+    let down = LinkStatus {
+        up: false,
+        speed: None,
+    };
+    assert_eq!(format!("{}", down), "down");
+    assert_eq!(format!("{}", NetError::NoDevice), "no supported NIC found");
+}
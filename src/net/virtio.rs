@@ -0,0 +1,92 @@
+//! virtio-net 的传统（legacy）接口
+//! 过渡设备（设备号 0x1000）的 I/O BAR 0 开头是传统的通用寄存器，没有启用 MSI-X 时网卡的配置
+//! 从偏移 0x14 开始：6 字节的 MAC 地址，之后是 16 位的状态。
+//!
+//! 初始化：设备状态写 0 复位，依次置位 ACKNOWLEDGE 和 DRIVER，只协商 MAC 和 STATUS 两个特性。
+//! 还没有建立队列，所以不置位 DRIVER_OK。设备不提供 STATUS 特性时按规范认为链路总是连通的
+use super::{poll, LinkStatus, MacAddress, NetError, Registers, TIMEOUT_SPINS};
+
+pub(super) const VENDOR_ID: u16 = 0x1af4;
+/// 只有现代接口的设备号 0x1041 没有 I/O BAR，不支持
+pub(super) const DEVICE_IDS: &[u16] = &[0x1000];
+
+const DEVICE_FEATURES: usize = 0x00;
+const GUEST_FEATURES: usize = 0x04;
+const DEVICE_STATUS: usize = 0x12;
+const CONFIG_MAC: usize = 0x14;
+const CONFIG_STATUS: usize = 0x1a;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const FEATURE_MAC: u32 = 1 << 5;
+const FEATURE_STATUS: u32 = 1 << 16;
+const LINK_UP: u8 = 1;
+
+/// 复位、协商特性并返回 MAC 地址
+pub(super) fn init(regs: &mut impl Registers) -> Result<MacAddress, NetError> {
+    regs.write8(DEVICE_STATUS, 0);
+    // 设备状态读回 0 时复位完成
+    if !poll(TIMEOUT_SPINS, || regs.read8(DEVICE_STATUS) == 0) {
+        return Err(NetError::ResetTimeout);
+    }
+    regs.write8(DEVICE_STATUS, STATUS_ACKNOWLEDGE);
+    regs.write8(DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+    let features = regs.read32(DEVICE_FEATURES);
+    if features & FEATURE_MAC == 0 {
+        return Err(NetError::NoMac);
+    }
+    regs.write32(GUEST_FEATURES, features & (FEATURE_MAC | FEATURE_STATUS));
+    Ok(MacAddress(core::array::from_fn(|index| {
+        regs.read8(CONFIG_MAC + index)
+    })))
+}
+
+pub(super) fn link_status(regs: &mut impl Registers) -> LinkStatus {
+    let up = regs.read32(DEVICE_FEATURES) & FEATURE_STATUS == 0
+        || regs.read8(CONFIG_STATUS) & LINK_UP != 0;
+    LinkStatus { up, speed: None }
+}
+
+#[cfg(test)]
+use super::MockRegisters;
+
+#[test_case]
+fn test_init_negotiates_and_reads_mac() {
+    let mut regs = MockRegisters::new(|_, _| {});
+    // MAC、STATUS 和一个不协商的特性（bit 0，校验和卸载）
+    regs.set32(DEVICE_FEATURES, FEATURE_MAC | FEATURE_STATUS | 1);
+    for (index, byte) in [0x52, 0x54, 0x00, 0x12, 0x34, 0x57].into_iter().enumerate() {
+        regs.bytes.insert(CONFIG_MAC + index, byte);
+    }
+    let mac = init(&mut regs).unwrap();
+    assert_eq!(mac, MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x57]));
+    assert_eq!(
+        regs.writes,
+        [
+            (DEVICE_STATUS, 0),
+            (DEVICE_STATUS, 1),
+            (DEVICE_STATUS, 3),
+            (GUEST_FEATURES, FEATURE_MAC | FEATURE_STATUS)
+        ]
+    );
+
+    let mut no_mac = MockRegisters::new(|_, _| {});
+    assert!(matches!(init(&mut no_mac), Err(NetError::NoMac)));
+}
+
+#[test_case]
+fn test_link_status() {
+    let mut regs = MockRegisters::new(|_, _| {});
+    // 没有 STATUS 特性：总是连通
+    assert!(link_status(&mut regs).up);
+    regs.set32(DEVICE_FEATURES, FEATURE_STATUS);
+    assert!(!link_status(&mut regs).up);
+    regs.bytes.insert(CONFIG_STATUS, LINK_UP);
+    assert_eq!(
+        link_status(&mut regs),
+        LinkStatus {
+            up: true,
+            speed: None
+        }
+    );
+}
@@ -0,0 +1,548 @@
+//! 内核的 panic 处理
+//! main.rs 中的 #[panic_handler] 只是转发到这里，方便各个模块扩充 panic 时的输出
+//!
+//! panic 信息用整行的分隔线框起来，同时以红色写到屏幕和写到串口，
+//! 在很长的串口日志中也能一眼找到
+//!
+//! 回溯之后在屏幕右下角画出紧凑的 panic 码（见 panic_code），串口上也写一行同样的文本。
+//! 打印之后把同样的信息写到磁盘上的崩溃记录中（见 crashlog），然后执行 PanicAction：停机、以失败退出 QEMU，或者倒数几秒后重启。
+//! 动作由启动配置的 panic= 选择，执行动作时再次 panic 会退化为停机
+//!
+//! panic_count 是进入过 panic 处理的次数，last_panic 是最近一次（不算递归的）panic 的消息，
+//! 供看门狗和诊断读取
+use crate::console::{self, ConsoleState};
+use crate::interrupts::{last_exception, ExceptionContext};
+use crate::panic_code::{self, PanicSummary};
+use crate::serial::SERIAL1;
+use crate::vga_buffer::{self, early, Color, ColorCode, BUFFER_WIDTH, WRITER};
+use crate::{backtrace, crashlog, exit_qemu, hlt_loop, power, time, QemuExitCode};
+use core::fmt::{self, Write};
+use core::panic::{Location, PanicInfo};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// 是否已经进入 panic 处理流程
+/// 只考虑单核：同一时刻只有一个执行流会进入 panic 处理，
+/// 所以第二次进入只可能是 panic 处理本身（例如打印时）又发生了 panic
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// panic 之后做什么
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    /// 停在 panic 画面，便于调试
+    Halt,
+    /// 以 QemuExitCode::Failed 退出 QEMU，没有 isa-debug-exit 设备时停机
+    ExitQemuFailure,
+    /// 倒数这么多秒后重启
+    RebootAfter(u32),
+}
+
+/// 只写 reboot 时的倒数秒数
+pub const DEFAULT_REBOOT_DELAY: u32 = 10;
+/// 倒数秒数的上限
+pub const MAX_REBOOT_DELAY: u32 = 3600;
+
+impl PanicAction {
+    /// 编译时的默认动作，启动配置中没有 panic= 时使用
+    pub const DEFAULT: PanicAction = PanicAction::Halt;
+
+    /// 解析 panic= 的值：halt、exit、reboot 或 reboot:秒数
+    pub fn parse(value: &str) -> Option<PanicAction> {
+        match value.split_once(':') {
+            None => match value {
+                "halt" => Some(PanicAction::Halt),
+                "exit" => Some(PanicAction::ExitQemuFailure),
+                "reboot" => Some(PanicAction::RebootAfter(DEFAULT_REBOOT_DELAY)),
+                _ => None,
+            },
+            Some(("reboot", seconds)) => match seconds.parse() {
+                Ok(seconds) if seconds <= MAX_REBOOT_DELAY => {
+                    Some(PanicAction::RebootAfter(seconds))
+                }
+                _ => None,
+            },
+            Some(_) => None,
+        }
+    }
+
+    /// 退出 QEMU 时使用的退出码
+    pub fn exit_code(self) -> Option<QemuExitCode> {
+        match self {
+            PanicAction::ExitQemuFailure => Some(QemuExitCode::Failed),
+            _ => None,
+        }
+    }
+
+    /// 放进 ACTION 的编码：0 停机，1 退出 QEMU，2 + 秒数 重启
+    const fn encode(self) -> u32 {
+        match self {
+            PanicAction::Halt => 0,
+            PanicAction::ExitQemuFailure => 1,
+            PanicAction::RebootAfter(seconds) => 2 + seconds,
+        }
+    }
+
+    fn decode(value: u32) -> PanicAction {
+        match value {
+            0 => PanicAction::Halt,
+            1 => PanicAction::ExitQemuFailure,
+            value => PanicAction::RebootAfter(value - 2),
+        }
+    }
+}
+
+/// 当前的动作；panic 时可能正持有任何锁，所以用原子变量保存
+static ACTION: AtomicU32 = AtomicU32::new(PanicAction::DEFAULT.encode());
+
+/// 设置 panic 之后的动作，由 config::init 调用；倒数秒数超过 MAX_REBOOT_DELAY 时取上限
+pub fn set_action(action: PanicAction) {
+    let action = match action {
+        PanicAction::RebootAfter(seconds) => {
+            PanicAction::RebootAfter(seconds.min(MAX_REBOOT_DELAY))
+        }
+        action => action,
+    };
+    ACTION.store(action.encode(), Ordering::Relaxed);
+}
+
+pub fn action() -> PanicAction {
+    PanicAction::decode(ACTION.load(Ordering::Relaxed))
+}
+
+/// 进入 panic 处理的次数，包括递归的 panic
+static PANIC_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// 最近一次 panic 的消息；panic 时只 try_lock，拿不到锁就不记录
+static LAST_PANIC: Mutex<Option<PanicMessage>> = Mutex::new(None);
+
+/// PanicMessage 最多保存的字节数
+pub const PANIC_MESSAGE_LEN: usize = 128;
+
+/// 保存在固定大小缓冲区中的 panic 消息。
+/// 超出 PANIC_MESSAGE_LEN 时只保留能放下的最长前缀，截断在字符边界上，之后写入的内容都被丢弃
+#[derive(Clone, Copy)]
+pub struct PanicMessage {
+    buf: [u8; PANIC_MESSAGE_LEN],
+    len: usize,
+    truncated: bool,
+}
+
+impl PanicMessage {
+    pub fn new(message: &dyn fmt::Display) -> Self {
+        let mut saved = PanicMessage {
+            buf: [0; PANIC_MESSAGE_LEN],
+            len: 0,
+            truncated: false,
+        };
+        let _ = write!(saved, "{}", message);
+        saved
+    }
+
+    pub fn as_str(&self) -> &str {
+        // 只在字符边界上截断，一定是合法的 UTF-8
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+
+    /// 消息是否被截断
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl Write for PanicMessage {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Ok(());
+        }
+        let room = PANIC_MESSAGE_LEN - self.len;
+        let mut end = s.len();
+        if end > room {
+            end = room;
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.truncated = true;
+        }
+        self.buf[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PanicMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// 开机以来进入 panic 处理的次数
+pub fn panic_count() -> u32 {
+    PANIC_COUNT.load(Ordering::Relaxed)
+}
+
+/// 最近一次 panic 的消息，没有 panic 过时返回 None
+pub fn last_panic() -> Option<PanicMessage> {
+    *LAST_PANIC.lock()
+}
+
+fn record(message: &dyn fmt::Display) {
+    let saved = PanicMessage::new(message);
+    if let Some(mut last) = LAST_PANIC.try_lock() {
+        *last = Some(saved);
+    }
+}
+
+/// 递归 panic 时写到屏幕左上角的标记：红底白字的 '!'
+const RECURSIVE_PANIC_MARKER: (u8, ColorCode) = (b'!', ColorCode::new(Color::White, Color::Red));
+
+/// 与屏幕同宽的分隔线；panic 时堆可能已经损坏，所以在编译时构造
+const BANNER: &str = match core::str::from_utf8(&[b'='; BUFFER_WIDTH]) {
+    Ok(banner) => banner,
+    Err(_) => unreachable!(),
+};
+
+/// write_report 最多写出的行数，消息不折行时
+pub const REPORT_MAX_LINES: usize = 6;
+
+/// 分隔线、消息和位置、分隔线；during_formatting 时说明上面的半行是被打断的输出，
+/// exception 是还没有返回的异常处理函数保存的上下文，见 interrupts::stash_exception
+fn write_report(
+    out: &mut impl Write,
+    message: &dyn fmt::Display,
+    location: Option<&Location>,
+    during_formatting: bool,
+    exception: Option<ExceptionContext>,
+) -> fmt::Result {
+    writeln!(out, "{}", BANNER)?;
+    writeln!(out, "kernel panic: {}", message)?;
+    if during_formatting {
+        writeln!(out, "  panic occurred during console formatting")?;
+    }
+    if let Some(location) = location {
+        writeln!(out, "  at {}", location)?;
+    }
+    if let Some(exception) = exception {
+        writeln!(out, "  last exception {}", exception)?;
+    }
+    writeln!(out, "{}", BANNER)
+}
+
+/// 直接写到 WRITER 和串口，不经过 print! 的输出选择，两边都一定能看到
+/// 控制台还没有初始化时不使用 WRITER，以固定颜色直接写屏幕，见 vga_buffer::early。
+/// 在 print! 格式化的途中 panic 时先强制释放控制台的锁，否则这里会在同一把锁上卡住
+pub fn report(info: &PanicInfo) {
+    let during_formatting = unsafe { vga_buffer::recover_from_formatting_panic() };
+    let message = info.message();
+    let location = info.location();
+    let exception = last_exception();
+    if console::state() == ConsoleState::Uninit {
+        // 控制台还没有初始化，WRITER 不会写屏幕
+        let _ = write_report(
+            &mut unsafe { early::screen() },
+            &message,
+            location,
+            during_formatting,
+            exception,
+        );
+    } else {
+        report_to_writer(&message, location, during_formatting, exception);
+    }
+    interrupts::without_interrupts(|| {
+        let _ = write_report(
+            &mut *SERIAL1.lock(),
+            &message,
+            location,
+            during_formatting,
+            exception,
+        );
+    });
+}
+
+fn report_to_writer(
+    message: &dyn fmt::Display,
+    location: Option<&Location>,
+    during_formatting: bool,
+    exception: Option<ExceptionContext>,
+) {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        // 被打断的分段重绘一次提交完，之后的报告不会被盖住
+        writer.finish_redraw();
+        // Scroll Lock 暂停的输出先写出来，之后的回溯等输出不再被暂存
+        vga_buffer::resume_output_locked(&mut writer);
+        let color = writer.color_code();
+        writer.set_color(Color::LightRed, Color::Black);
+        // 分隔线从行首开始才不会折成两行
+        if writer.column() != 0 {
+            writer.new_line();
+        }
+        let _ = write_report(
+            &mut *writer,
+            message,
+            location,
+            during_formatting,
+            exception,
+        );
+        writer.set_color_code(color);
+    });
+}
+
+/// show_panic_code 自己、handle_panic 和 main.rs 中的 #[panic_handler]，它们不写进 panic 码
+const HANDLER_FRAMES: usize = 3;
+
+/// 编码 panic 码，写到串口，控制台初始化之后再画到屏幕上
+#[inline(never)]
+fn show_panic_code(info: &PanicInfo) {
+    let mut addresses = [0; HANDLER_FRAMES + panic_code::MAX_FRAMES];
+    let frames = backtrace::return_addresses(&mut addresses).saturating_sub(HANDLER_FRAMES);
+    let uptime_secs = (time::ticks_to_ms(time::ticks()) / 1000).min(u32::MAX as u64) as u32;
+    let code = PanicSummary::new(
+        info.location(),
+        &info.message(),
+        uptime_secs,
+        &addresses[HANDLER_FRAMES..],
+        frames,
+    )
+    .encode();
+    let mut buf = [0; panic_code::MAX_TEXT_LEN];
+    let text = code.text(&mut buf);
+    interrupts::without_interrupts(|| {
+        let _ = writeln!(SERIAL1.lock(), "panic code {}", text);
+        if console::state() != ConsoleState::Uninit {
+            code.draw(&mut WRITER.lock());
+        }
+    });
+}
+
+/// 从 seconds 倒数到 1，每个数字显示后等待一秒
+fn countdown(seconds: u32, mut show: impl FnMut(u32), mut wait_one_second: impl FnMut()) {
+    for remaining in (1..=seconds).rev() {
+        show(remaining);
+        wait_one_second();
+    }
+}
+
+/// 在屏幕和串口的同一行上追加文字
+fn write_both(args: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        let _ = WRITER.lock().write_fmt(args);
+        let _ = SERIAL1.lock().write_fmt(args);
+    });
+}
+
+fn run_action(action: PanicAction) -> ! {
+    if let Some(code) = action.exit_code() {
+        exit_qemu(code);
+    }
+    if let PanicAction::RebootAfter(seconds) = action {
+        write_both(format_args!("rebooting in"));
+        // 可能正处在中断处理函数中，时钟中断不会再来，只能用 TSC 计时
+        countdown(
+            seconds,
+            |remaining| write_both(format_args!(" {}", remaining)),
+            || time::spin_delay_ms(1000),
+        );
+        write_both(format_args!("\n"));
+        power::reboot();
+    }
+    hlt_loop();
+}
+
+pub fn handle_panic(info: &PanicInfo) -> ! {
+    PANIC_COUNT.fetch_add(1, Ordering::Relaxed);
+    // 执行动作时（例如倒数或重启的输出中）再次 panic 也走这里，只停机
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        // 不再经过 Writer，直接写 VGA 缓冲区，避免再次 panic 导致无限递归
+        let (byte, color_code) = RECURSIVE_PANIC_MARKER;
+        unsafe { early::raw_write_char_at(0, 0, byte, color_code) };
+        hlt_loop();
+    }
+
+    record(&info.message());
+    report(info);
+    backtrace::print();
+    crashlog::record_panic(info);
+    // 最后画，之前的输出都推到图案上方
+    show_panic_code(info);
+    run_action(action());
+}
+
+#[test_case]
+fn test_report_is_framed_by_banners() {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    let mut out = String::new();
+    let location = Location::caller();
+    write_report(&mut out, &"boom", Some(location), false, None).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0].len(), BUFFER_WIDTH);
+    assert!(lines[0].bytes().all(|byte| byte == b'='));
+    assert_eq!(lines[1], "kernel panic: boom");
+    assert!(lines[2].starts_with("  at src/panic.rs:"));
+    assert_eq!(lines[3], lines[0]);
+
+    let mut out = String::new();
+    write_report(&mut out, &"boom", None, true, None).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(
+        lines[1..3],
+        [
+            "kernel panic: boom",
+            "  panic occurred during console formatting"
+        ]
+    );
+
+    // 异常处理函数中 panic 时带上它保存的上下文
+    let exception = ExceptionContext {
+        name: "double fault",
+        instruction_pointer: 0xdead_beef,
+        stack_pointer: 0x1000,
+        cpu_flags: 0x2,
+    };
+    let mut out = String::new();
+    write_report(&mut out, &"boom", None, false, Some(exception)).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(
+        lines[2],
+        "  last exception double fault: rip 0xdeadbeef, rsp 0x1000, rflags 0x2"
+    );
+
+    let mut out = String::new();
+    write_report(&mut out, &"boom", Some(location), true, Some(exception)).unwrap();
+    assert_eq!(out.lines().count(), REPORT_MAX_LINES);
+}
+
+#[test_case]
+fn test_report_resumes_paused_output() {
+    let row_starts_with = |row: usize, text: &str| {
+        let writer = WRITER.lock();
+        text.bytes()
+            .enumerate()
+            .all(|(col, byte)| writer.read_char(row, col).0 == byte)
+    };
+    vga_buffer::pause_output();
+    crate::println!("held while paused");
+    report_to_writer(&"paused report", None, false, None);
+    backtrace::print();
+    assert!(!vga_buffer::output_paused());
+    interrupts::without_interrupts(|| {
+        let rows = 0..crate::vga_buffer::BUFFER_HEIGHT;
+        let find = |text| rows.clone().find(|&row| row_starts_with(row, text));
+        let held = find("held while paused").expect("held output was not flushed");
+        let report = find("kernel panic: paused report").expect("report missing");
+        let backtrace = find("stack backtrace:").expect("backtrace did not reach WRITER");
+        assert!(held < report && report < backtrace);
+    });
+}
+
+#[test_case]
+fn test_parse_action() {
+    assert_eq!(PanicAction::parse("halt"), Some(PanicAction::Halt));
+    assert_eq!(
+        PanicAction::parse("exit"),
+        Some(PanicAction::ExitQemuFailure)
+    );
+    assert_eq!(
+        PanicAction::parse("reboot"),
+        Some(PanicAction::RebootAfter(DEFAULT_REBOOT_DELAY))
+    );
+    assert_eq!(
+        PanicAction::parse("reboot:0"),
+        Some(PanicAction::RebootAfter(0))
+    );
+    assert_eq!(
+        PanicAction::parse("reboot:3600"),
+        Some(PanicAction::RebootAfter(MAX_REBOOT_DELAY))
+    );
+    for bad in [
+        "",
+        "Halt",
+        "reboot:",
+        "reboot:-1",
+        "reboot:3601",
+        "exit:1",
+        "halt:",
+    ] {
+        assert_eq!(PanicAction::parse(bad), None, "{:?}", bad);
+    }
+}
+
+#[test_case]
+fn test_action_round_trips_through_static() {
+    let saved = action();
+    for action in [
+        PanicAction::ExitQemuFailure,
+        PanicAction::RebootAfter(0),
+        PanicAction::RebootAfter(MAX_REBOOT_DELAY),
+        PanicAction::Halt,
+    ] {
+        set_action(action);
+        assert_eq!(self::action(), action);
+    }
+    set_action(PanicAction::RebootAfter(u32::MAX));
+    assert_eq!(action(), PanicAction::RebootAfter(MAX_REBOOT_DELAY));
+    set_action(saved);
+}
+
+#[test_case]
+fn test_panic_message_truncates_at_char_boundary() {
+    let short = PanicMessage::new(&"index out of bounds");
+    assert_eq!(short.as_str(), "index out of bounds");
+    assert!(!short.truncated());
+
+    // 'é' 占两个字节，从第 PANIC_MESSAGE_LEN - 1 个字节开始的那个放不下
+    let long = format_args!("{:1$}é tail", "", PANIC_MESSAGE_LEN - 1);
+    let message = PanicMessage::new(&long);
+    assert!(message.truncated());
+    assert_eq!(message.as_str().len(), PANIC_MESSAGE_LEN - 1);
+    assert!(message.as_str().bytes().all(|byte| byte == b' '));
+
+    let saved = *LAST_PANIC.lock();
+    record(&"recoverable");
+    assert_eq!(
+        last_panic().map(|m| m.as_str() == "recoverable"),
+        Some(true)
+    );
+    *LAST_PANIC.lock() = saved;
+}
+
+#[test_case]
+fn test_countdown() {
+    use alloc::vec::Vec;
+
+    let events = core::cell::RefCell::new(Vec::new());
+    countdown(
+        3,
+        |remaining| events.borrow_mut().push(Some(remaining)),
+        || events.borrow_mut().push(None),
+    );
+    // 每个数字之后等待一秒，最后一秒等完才重启
+    assert_eq!(
+        events.into_inner(),
+        [Some(3), None, Some(2), None, Some(1), None]
+    );
+
+    let mut waits = 0;
+    countdown(0, |_| unreachable!(), || waits += 1);
+    assert_eq!(waits, 0);
+}
+
+#[test_case]
+fn test_report_before_console_init() {
+    use crate::vga_buffer::Buffer;
+    use alloc::boxed::Box;
+    use core::sync::atomic::AtomicUsize;
+
+    // 与 Uninit 阶段的 panic 相同的写法，只是换成了离屏缓冲区
+    let mut buffer = Box::new(Buffer::new());
+    let position = AtomicUsize::new(0);
+    let mut screen = early::EarlyWriter::new(&mut buffer, &position);
+    write_report(&mut screen, &"too early", None, false, None).unwrap();
+    assert_eq!(&buffer.row_text(0), b"====");
+    assert_eq!(&buffer.row_text(1), b"kernel panic: too early");
+    assert_eq!(buffer.color_at(1, 0), early::EARLY_COLOR);
+    assert_eq!(&buffer.row_text(2), b"====");
+}
@@ -0,0 +1,489 @@
+//! panic 画面上的紧凑错误码
+//! 远程测试机的 panic 画面往往只能拍照，照片又常常截掉关键的部分。
+//! 这里把 panic 的位置、消息的哈希、开机时长和最近的几个返回地址编码成固定上限的几十个字节，
+//! 末尾带一个 Fletcher-16 校验和，照片读错一位也能发现。编码结果同时以两种形式显示：
+//! - 一行 base32 文本（RFC 4648 字母表，不补 '='），可以照着抄下来
+//! - 右下角 24×12 格的黑白方块图案，每格一位，行优先、每个字节高位在前，外面围一圈实心边框，
+//!   便于从照片中定位
+//!
+//! 字节布局（多字节字段都是小端）：
+//!
+//! | 偏移 | 长度 | 内容 |
+//! |------|------|------|
+//! | 0 | 1 | 版本，目前为 1 |
+//! | 1 | 4 | 文件路径的 FNV-1a 哈希，没有位置时为 0 |
+//! | 5 | 2 | 行号，超过 u16 的取上限 |
+//! | 7 | 4 | 消息的 FNV-1a 哈希 |
+//! | 11 | 4 | 开机以来的秒数 |
+//! | 15 | 1 | 低 7 位是编码的帧数，最高位表示还有更多的帧被截掉 |
+//! | 16 | 4 × 帧数 | 返回地址的低 32 位 |
+//! | 末尾 | 2 | 前面所有字节的 Fletcher-16 |
+//!
+//! 文件和消息只保存哈希，主机端对照源码树和内核的 ELF 文件还原；
+//! 解码的逻辑就是这里的 decode，测试用它和编码器互相验证。
+//! 编码不分配内存，panic 时堆可能已经损坏
+use crate::vga_buffer::{Color, ColorCode, Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+use core::fmt::{self, Write};
+use core::panic::Location;
+
+const VERSION: u8 = 1;
+
+/// 最多编码的返回地址数，更深的回溯被截掉
+pub const MAX_FRAMES: usize = 4;
+
+const HEADER_LEN: usize = 16;
+const CHECKSUM_LEN: usize = 2;
+const TRUNCATED: u8 = 0x80;
+
+/// 编码结果的最大字节数
+pub const MAX_BYTES: usize = HEADER_LEN + MAX_FRAMES * 4 + CHECKSUM_LEN;
+
+/// base32 文本的最大长度
+pub const MAX_TEXT_LEN: usize = (MAX_BYTES * 8).div_ceil(5);
+
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// 图案的数据区，每格一位
+pub const GRID_COLS: usize = 24;
+pub const GRID_ROWS: usize = 12;
+
+const _: () = assert!(MAX_BYTES * 8 <= GRID_COLS * GRID_ROWS);
+
+/// 每格在屏幕上占两列，字符单元高大约是宽的两倍，这样接近正方形
+const CELL_WIDTH: usize = 2;
+
+/// 加上一圈边框后图案在屏幕上占的列数和行数
+pub const PATTERN_WIDTH: usize = (GRID_COLS + 2) * CELL_WIDTH;
+pub const PATTERN_HEIGHT: usize = GRID_ROWS + 2;
+
+const TEXT_PREFIX: &str = "panic code ";
+
+/// 文本行加图案占用的行数，画在屏幕的右下角
+pub const PANEL_HEIGHT: usize = PATTERN_HEIGHT + 1;
+
+/// 文本行的宽度，不足 MAX_TEXT_LEN 的文本用空格补齐
+pub const TEXT_WIDTH: usize = TEXT_PREFIX.len() + MAX_TEXT_LEN;
+
+// 文本行和图案都放得下；draw 把之前的输出推到图案上方，panic 报告（见 panic::write_report）
+// 在那里仍然能完整显示
+const _: () = assert!(TEXT_WIDTH <= BUFFER_WIDTH && PATTERN_WIDTH <= BUFFER_WIDTH);
+const _: () = assert!(PANEL_HEIGHT + crate::panic::REPORT_MAX_LINES <= BUFFER_HEIGHT);
+
+/// CP437 的实心方块
+const FULL_BLOCK: u8 = 0xdb;
+
+/// 要编码的内容，也是 decode 的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanicSummary {
+    pub file_hash: u32,
+    pub line: u16,
+    pub message_hash: u32,
+    pub uptime_secs: u32,
+    /// 前 frame_count 个有效，只保存返回地址的低 32 位
+    pub frames: [u32; MAX_FRAMES],
+    pub frame_count: usize,
+    /// 回溯比 MAX_FRAMES 深，多出来的帧没有编码
+    pub truncated: bool,
+}
+
+impl PanicSummary {
+    /// addresses 是从最近的一帧开始的返回地址，total_frames 是回溯到的总帧数，
+    /// 可以大于 addresses.len()（见 backtrace::return_addresses）
+    pub fn new(
+        location: Option<&Location>,
+        message: &dyn fmt::Display,
+        uptime_secs: u32,
+        addresses: &[u64],
+        total_frames: usize,
+    ) -> Self {
+        let mut frames = [0; MAX_FRAMES];
+        let frame_count = addresses.len().min(total_frames).min(MAX_FRAMES);
+        for (frame, &address) in frames.iter_mut().zip(&addresses[..frame_count]) {
+            *frame = address as u32;
+        }
+        let mut message_hash = Fnv1a::new();
+        let _ = write!(message_hash, "{}", message);
+        PanicSummary {
+            file_hash: location.map_or(0, |location| fnv1a(location.file().as_bytes())),
+            line: location.map_or(0, |location| location.line().min(u16::MAX as u32) as u16),
+            message_hash: message_hash.0,
+            uptime_secs,
+            frames,
+            frame_count,
+            truncated: total_frames > frame_count,
+        }
+    }
+
+    pub fn frames(&self) -> &[u32] {
+        &self.frames[..self.frame_count]
+    }
+
+    pub fn encode(&self) -> PanicCode {
+        let mut bytes = [0; MAX_BYTES];
+        bytes[0] = VERSION;
+        bytes[1..5].copy_from_slice(&self.file_hash.to_le_bytes());
+        bytes[5..7].copy_from_slice(&self.line.to_le_bytes());
+        bytes[7..11].copy_from_slice(&self.message_hash.to_le_bytes());
+        bytes[11..15].copy_from_slice(&self.uptime_secs.to_le_bytes());
+        bytes[15] = self.frame_count as u8 | if self.truncated { TRUNCATED } else { 0 };
+        let mut len = HEADER_LEN;
+        for frame in self.frames() {
+            bytes[len..len + 4].copy_from_slice(&frame.to_le_bytes());
+            len += 4;
+        }
+        let sum = checksum(&bytes[..len]);
+        bytes[len..len + CHECKSUM_LEN].copy_from_slice(&sum.to_le_bytes());
+        PanicCode {
+            bytes,
+            len: len + CHECKSUM_LEN,
+        }
+    }
+}
+
+/// 解码失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// 文本中有 base32 字母表以外的字符
+    BadCharacter(u8),
+    /// 字节数与帧数对不上，或者超过了 MAX_BYTES
+    BadLength(usize),
+    UnknownVersion(u8),
+    /// 帧数超过 MAX_FRAMES
+    BadFrameCount(u8),
+    ChecksumMismatch {
+        expected: u16,
+        actual: u16,
+    },
+}
+
+/// 编码好的字节
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanicCode {
+    bytes: [u8; MAX_BYTES],
+    len: usize,
+}
+
+impl PanicCode {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// base32 文本，写进 buf 中
+    pub fn text<'a>(&self, buf: &'a mut [u8; MAX_TEXT_LEN]) -> &'a str {
+        let mut len = 0;
+        let (mut bits, mut pending) = (0u32, 0usize);
+        for &byte in self.as_bytes() {
+            bits = bits << 8 | byte as u32;
+            pending += 8;
+            while pending >= 5 {
+                pending -= 5;
+                buf[len] = ALPHABET[(bits >> pending) as usize & 0x1f];
+                len += 1;
+            }
+        }
+        if pending > 0 {
+            buf[len] = ALPHABET[(bits << (5 - pending)) as usize & 0x1f];
+            len += 1;
+        }
+        core::str::from_utf8(&buf[..len]).expect("base32 alphabet is ASCII")
+    }
+
+    /// 图案中数据区第 (row, col) 格是否填满；超出编码长度的格子是空的
+    pub fn bit(&self, row: usize, col: usize) -> bool {
+        let index = row * GRID_COLS + col;
+        self.as_bytes()
+            .get(index / 8)
+            .is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0)
+    }
+
+    /// 按图案的格子坐标（包括边框）逐格调用 put(行, 列, 是否填满)
+    pub fn render(&self, mut put: impl FnMut(usize, usize, bool)) {
+        for row in 0..GRID_ROWS + 2 {
+            for col in 0..GRID_COLS + 2 {
+                let border = row == 0 || col == 0 || row == GRID_ROWS + 1 || col == GRID_COLS + 1;
+                put(row, col, border || self.bit(row - 1, col - 1));
+            }
+        }
+    }
+
+    /// 在屏幕右下角画出文本行和图案。先换行空出最下面的 PANEL_HEIGHT 行，
+    /// 把 panic 报告和回溯推到图案上方，不被图案盖住；之后光标在最后一行的行首
+    pub fn draw(&self, writer: &mut Writer) {
+        if writer.column() != 0 {
+            writer.new_line();
+        }
+        // 光标所在的最后一行已经是空行
+        for _ in 1..PANEL_HEIGHT {
+            writer.new_line();
+        }
+        let color = ColorCode::new(Color::White, Color::Black);
+        let top = BUFFER_HEIGHT - PANEL_HEIGHT;
+        let mut buf = [0; MAX_TEXT_LEN];
+        writer.write_fmt_at(
+            top,
+            BUFFER_WIDTH - TEXT_WIDTH,
+            format_args!("{}{:<2$}", TEXT_PREFIX, self.text(&mut buf), MAX_TEXT_LEN),
+        );
+        let left = BUFFER_WIDTH - PATTERN_WIDTH;
+        self.render(|row, col, filled| {
+            let byte = if filled { FULL_BLOCK } else { b' ' };
+            for offset in 0..CELL_WIDTH {
+                writer.put_char(top + 1 + row, left + col * CELL_WIDTH + offset, byte, color);
+            }
+        });
+    }
+}
+
+/// 从 base32 文本解码，大小写都可以
+pub fn decode(text: &str) -> Result<PanicSummary, DecodeError> {
+    let mut bytes = [0; MAX_BYTES];
+    let mut len = 0;
+    let (mut bits, mut pending) = (0u32, 0usize);
+    for &character in text.as_bytes() {
+        let value = ALPHABET
+            .iter()
+            .position(|&letter| letter == character.to_ascii_uppercase())
+            .ok_or(DecodeError::BadCharacter(character))?;
+        bits = bits << 5 | value as u32;
+        pending += 5;
+        if pending >= 8 {
+            pending -= 8;
+            *bytes
+                .get_mut(len)
+                .ok_or(DecodeError::BadLength(text.len() * 5 / 8))? = (bits >> pending) as u8;
+            len += 1;
+        }
+    }
+    decode_bytes(&bytes[..len])
+}
+
+/// 从图案解码，filled(行, 列) 返回数据区（不包括边框）中的格子是否填满
+pub fn decode_pattern(filled: impl Fn(usize, usize) -> bool) -> Result<PanicSummary, DecodeError> {
+    let mut bytes = [0; GRID_COLS * GRID_ROWS / 8];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        for bit in 0..8 {
+            let cell = index * 8 + bit;
+            if filled(cell / GRID_COLS, cell % GRID_COLS) {
+                *byte |= 0x80 >> bit;
+            }
+        }
+    }
+    // 图案中编码长度之后的格子都是空的，按帧数截取
+    let len = encoded_len(bytes[HEADER_LEN - 1]);
+    decode_bytes(&bytes[..len.min(bytes.len())])
+}
+
+fn encoded_len(frame_byte: u8) -> usize {
+    HEADER_LEN + (frame_byte & !TRUNCATED) as usize * 4 + CHECKSUM_LEN
+}
+
+/// 检查校验和并取出各个字段
+pub fn decode_bytes(bytes: &[u8]) -> Result<PanicSummary, DecodeError> {
+    if bytes.len() < HEADER_LEN + CHECKSUM_LEN || bytes.len() != encoded_len(bytes[15]) {
+        return Err(DecodeError::BadLength(bytes.len()));
+    }
+    let (body, stored) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    let expected = u16::from_le_bytes([stored[0], stored[1]]);
+    let actual = checksum(body);
+    if expected != actual {
+        return Err(DecodeError::ChecksumMismatch { expected, actual });
+    }
+    if body[0] != VERSION {
+        return Err(DecodeError::UnknownVersion(body[0]));
+    }
+    let frame_count = (body[15] & !TRUNCATED) as usize;
+    if frame_count > MAX_FRAMES {
+        return Err(DecodeError::BadFrameCount(body[15]));
+    }
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([
+            body[offset],
+            body[offset + 1],
+            body[offset + 2],
+            body[offset + 3],
+        ])
+    };
+    let mut frames = [0; MAX_FRAMES];
+    for (index, frame) in frames.iter_mut().take(frame_count).enumerate() {
+        *frame = u32_at(HEADER_LEN + index * 4);
+    }
+    Ok(PanicSummary {
+        file_hash: u32_at(1),
+        line: u16::from_le_bytes([body[5], body[6]]),
+        message_hash: u32_at(7),
+        uptime_secs: u32_at(11),
+        frames,
+        frame_count,
+        truncated: body[15] & TRUNCATED != 0,
+    })
+}
+
+/// Fletcher-16
+fn checksum(bytes: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for &byte in bytes {
+        a = (a + byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+    b << 8 | a
+}
+
+/// 32 位 FNV-1a，实现了 fmt::Write，可以不分配内存地哈希格式化的结果
+struct Fnv1a(u32);
+
+impl Fnv1a {
+    const fn new() -> Self {
+        Fnv1a(0x811c_9dc5)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u32).wrapping_mul(0x0100_0193);
+        }
+    }
+}
+
+impl Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.update(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// 主机端用同样的函数哈希源码树中的文件路径
+pub fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash = Fnv1a::new();
+    hash.update(bytes);
+    hash.0
+}
+
+#[cfg(test)]
+fn sample(addresses: &[u64], total_frames: usize) -> PanicSummary {
+    PanicSummary::new(
+        Some(Location::caller()),
+        &format_args!("index {} out of range", 7),
+        3600,
+        addresses,
+        total_frames,
+    )
+}
+
+#[test_case]
+fn test_round_trip_through_text_and_pattern() {
+    let summary = sample(&[0x20_1234, 0xffff_8000_0020_5678], 2);
+    assert_eq!(summary.frames(), [0x20_1234, 0x0020_5678]);
+    assert!(!summary.truncated);
+    assert_eq!(summary.file_hash, fnv1a(b"src/panic_code.rs"));
+    assert_eq!(summary.message_hash, fnv1a(b"index 7 out of range"));
+
+    let code = summary.encode();
+    assert_eq!(code.as_bytes().len(), HEADER_LEN + 2 * 4 + CHECKSUM_LEN);
+    let mut buf = [0; MAX_TEXT_LEN];
+    let text = code.text(&mut buf);
+    assert_eq!(text.len(), (code.as_bytes().len() * 8).div_ceil(5));
+    assert_eq!(decode(text), Ok(summary));
+    let mut lower = [0; MAX_TEXT_LEN];
+    lower[..text.len()].copy_from_slice(text.as_bytes());
+    lower.make_ascii_lowercase();
+    assert_eq!(
+        decode(core::str::from_utf8(&lower[..text.len()]).unwrap()),
+        Ok(summary)
+    );
+    assert_eq!(decode_pattern(|row, col| code.bit(row, col)), Ok(summary));
+
+    // 没有位置、没有回溯
+    let empty = PanicSummary::new(None, &"", 0, &[], 0);
+    assert_eq!(decode(empty.encode().text(&mut buf)), Ok(empty));
+}
+
+#[test_case]
+fn test_deep_backtrace_is_truncated() {
+    let addresses: [u64; 10] = core::array::from_fn(|index| 0x20_0000 + index as u64);
+    let summary = sample(&addresses, 30);
+    assert_eq!(
+        summary.frames(),
+        [0x20_0000, 0x20_0001, 0x20_0002, 0x20_0003]
+    );
+    assert!(summary.truncated);
+    let code = summary.encode();
+    assert_eq!(code.as_bytes().len(), MAX_BYTES);
+    let mut buf = [0; MAX_TEXT_LEN];
+    assert_eq!(code.text(&mut buf).len(), MAX_TEXT_LEN);
+    assert_eq!(decode(code.text(&mut buf)), Ok(summary));
+
+    // 回溯收集时就已经截掉了一部分，仍然标记为截断
+    let summary = sample(&addresses[..2], 3);
+    assert_eq!(summary.frame_count, 2);
+    assert!(summary.truncated);
+    assert_eq!(decode_bytes(summary.encode().as_bytes()), Ok(summary));
+}
+
+#[test_case]
+fn test_corruption_is_detected() {
+    let code = sample(&[0x20_1234], 1).encode();
+    let mut buf = [0; MAX_TEXT_LEN];
+    let text = code.text(&mut buf);
+
+    // 抄错一个字符
+    let mut typo = [0; MAX_TEXT_LEN];
+    typo[..text.len()].copy_from_slice(text.as_bytes());
+    typo[3] = if typo[3] == b'A' { b'B' } else { b'A' };
+    assert!(matches!(
+        decode(core::str::from_utf8(&typo[..text.len()]).unwrap()),
+        Err(DecodeError::ChecksumMismatch { .. })
+    ));
+    // 照片中一个格子读反
+    assert!(matches!(
+        decode_pattern(|row, col| code.bit(row, col) != (row == 2 && col == 5)),
+        Err(DecodeError::ChecksumMismatch { .. })
+    ));
+    // 截掉了结尾
+    assert_eq!(
+        decode(&text[..text.len() - 2]),
+        Err(DecodeError::BadLength(code.as_bytes().len() - 1))
+    );
+    assert_eq!(decode("A0"), Err(DecodeError::BadCharacter(b'0')));
+    assert_eq!(decode(""), Err(DecodeError::BadLength(0)));
+}
+
+#[test_case]
+fn test_pattern_fits_the_screen() {
+    let code = sample(&[1, 2, 3, 4, 5], 5).encode();
+    let (mut cells, mut filled) = (0, 0);
+    code.render(|row, col, full| {
+        assert!(row < PATTERN_HEIGHT && col * CELL_WIDTH < PATTERN_WIDTH);
+        cells += 1;
+        filled += full as usize;
+    });
+    assert_eq!(cells, (GRID_ROWS + 2) * (GRID_COLS + 2));
+    // 边框一定是满的
+    assert!(filled >= 2 * (GRID_ROWS + GRID_COLS + 2));
+
+    let mut writer = crate::vga_buffer::TestWriter::new();
+    writer.write_string("report\nbacktrace");
+    code.draw(&mut writer);
+    let top = BUFFER_HEIGHT - PANEL_HEIGHT;
+    // 之前的输出被推到图案上方
+    assert_eq!(writer.read_char(top - 2, 0).0, b'r');
+    assert_eq!(writer.read_char(top - 1, 0).0, b'b');
+    assert_eq!(writer.read_char(top, 0).0, b' ');
+    assert_eq!(writer.column(), 0);
+    assert_eq!(
+        writer.read_char(top, BUFFER_WIDTH - TEXT_WIDTH).0,
+        TEXT_PREFIX.as_bytes()[0]
+    );
+    let left = BUFFER_WIDTH - PATTERN_WIDTH;
+    assert_eq!(writer.read_char(top + 1, left).0, FULL_BLOCK);
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1).0,
+        FULL_BLOCK
+    );
+    // 从屏幕上读回图案
+    let read = |row: usize, col: usize| {
+        writer
+            .read_char(top + 2 + row, left + (col + 1) * CELL_WIDTH)
+            .0
+            == FULL_BLOCK
+    };
+    assert_eq!(decode_pattern(read), decode_bytes(code.as_bytes()));
+}
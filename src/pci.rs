@@ -0,0 +1,446 @@
+//! PCI 总线枚举
+//! 使用配置机制 #1：向 0xCF8 写入 [使能位, 总线, 设备, 功能, 寄存器偏移] 组成的地址，
+//! 再从 0xCFC 读写该功能配置空间中的一个 32 位寄存器。
+//!
+//! init 逐个尝试全部 256 条总线 × 32 个设备，厂商号为 0xFFFF 表示不存在；
+//! 功能 0 的头部类型 bit 7 置位时是多功能设备，继续检查功能 1-7。
+//! 桥（头部类型 1）会被识别出来，但暂时不递归扫描它的次级总线——穷举已经覆盖了所有总线号
+//!
+//! BAR 的大小通过写全 1 再读回得到：只有表示地址的位可写，读回值取反加一就是大小。
+//! 探测期间关闭设备的内存和 I/O 译码，结束后恢复 BAR 和命令寄存器原来的值
+use conquer_once::spin::OnceCell;
+use core::fmt;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// 设备表的容量，超出的设备被忽略
+pub const MAX_DEVICES: usize = 64;
+
+const REG_ID: u8 = 0x00;
+const REG_COMMAND: u8 = 0x04;
+const REG_CLASS: u8 = 0x08;
+const REG_HEADER: u8 = 0x0c;
+const REG_BAR0: u8 = 0x10;
+
+/// 命令寄存器 bit 0-1：I/O 和内存空间译码
+const COMMAND_DECODE: u32 = 0b11;
+const MULTI_FUNCTION: u8 = 0x80;
+const HEADER_TYPE_MASK: u8 = 0x7f;
+/// 普通设备的头部类型
+pub const HEADER_GENERAL: u8 = 0x00;
+/// PCI-to-PCI 桥
+pub const HEADER_BRIDGE: u8 = 0x01;
+
+/// 总线:设备.功能
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    /// 写入 0xCF8 的值，offset 的低 2 位被忽略（寄存器 4 字节对齐）
+    pub fn config_address(self, offset: u8) -> u32 {
+        1 << 31
+            | (self.bus as u32) << 16
+            | ((self.device & 0x1f) as u32) << 11
+            | ((self.function & 0x07) as u32) << 8
+            | (offset & 0xfc) as u32
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// 解码后的 BAR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        /// 64 位 BAR 占用两个槽，下一个槽为 None
+        wide: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+/// 由 BAR 原来的值和写全 1 后读回的值解码，high 是 64 位 BAR 的高半部分 (原值, 读回值)
+/// 读回值的地址位全为 0 表示这个 BAR 没有实现
+pub fn decode_bar(original: u32, probed: u32, high: Option<(u32, u32)>) -> Option<Bar> {
+    if original & 1 != 0 {
+        let mask = probed & !0x3;
+        if mask == 0 {
+            return None;
+        }
+        // I/O 地址空间只有 16 位，高位可能读回 0
+        let size = !(mask | 0xffff_0000) + 1;
+        return Some(Bar::Io {
+            port: original & !0x3,
+            size,
+        });
+    }
+    let (high, probed_high) = match high {
+        Some((high, probed_high)) => (high, probed_high),
+        // 32 位 BAR 的地址高位看作不可写
+        None => (0, u32::MAX),
+    };
+    let mask = (probed_high as u64) << 32 | (probed & !0xf) as u64;
+    if mask == 0xffff_ffff_0000_0000 {
+        return None;
+    }
+    Some(Bar::Memory {
+        address: (high as u64) << 32 | (original & !0xf) as u64,
+        size: (!mask).wrapping_add(1),
+        prefetchable: original & 0x8 != 0,
+        wide: is_wide(original),
+    })
+}
+
+/// 内存 BAR 的类型字段（bit 1-2）为 2 时是 64 位
+fn is_wide(bar: u32) -> bool {
+    bar & 1 == 0 && (bar >> 1) & 0x3 == 0x2
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    /// 去掉多功能位的头部类型
+    pub header_type: u8,
+    /// 普通设备有 6 个 BAR，桥只有前 2 个
+    pub bars: [Option<Bar>; 6],
+}
+
+impl PciDevice {
+    pub fn is_bridge(&self) -> bool {
+        self.header_type == HEADER_BRIDGE || self.class == 0x06
+    }
+}
+
+/// 读写配置空间，抽象出来以便用预设的寄存器值测试
+pub trait ConfigSpace {
+    fn read(&mut self, address: PciAddress, offset: u8) -> u32;
+    fn write(&mut self, address: PciAddress, offset: u8, value: u32);
+}
+
+struct HardwareConfig;
+
+impl ConfigSpace for HardwareConfig {
+    fn read(&mut self, address: PciAddress, offset: u8) -> u32 {
+        interrupts::without_interrupts(|| unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(address.config_address(offset));
+            Port::<u32>::new(CONFIG_DATA).read()
+        })
+    }
+
+    fn write(&mut self, address: PciAddress, offset: u8, value: u32) {
+        interrupts::without_interrupts(|| unsafe {
+            Port::<u32>::new(CONFIG_ADDRESS).write(address.config_address(offset));
+            Port::<u32>::new(CONFIG_DATA).write(value);
+        })
+    }
+}
+
+/// 写全 1 后读回，再写回原值
+fn probe(config: &mut impl ConfigSpace, address: PciAddress, offset: u8, original: u32) -> u32 {
+    config.write(address, offset, u32::MAX);
+    let probed = config.read(address, offset);
+    config.write(address, offset, original);
+    probed
+}
+
+fn read_bars(config: &mut impl ConfigSpace, address: PciAddress, count: usize) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    // 只写命令寄存器所在的低 16 位：高 16 位的状态寄存器写 1 清除，写 0 没有影响
+    let command = config.read(address, REG_COMMAND) & 0xffff;
+    config.write(address, REG_COMMAND, command & !COMMAND_DECODE);
+    let mut index = 0;
+    while index < count {
+        let offset = REG_BAR0 + 4 * index as u8;
+        let original = config.read(address, offset);
+        let probed = probe(config, address, offset, original);
+        let high = if is_wide(original) && index + 1 < count {
+            let high = config.read(address, offset + 4);
+            Some((high, probe(config, address, offset + 4, high)))
+        } else {
+            None
+        };
+        bars[index] = decode_bar(original, probed, high);
+        index += if high.is_some() { 2 } else { 1 };
+    }
+    config.write(address, REG_COMMAND, command);
+    bars
+}
+
+fn read_function(config: &mut impl ConfigSpace, address: PciAddress) -> Option<PciDevice> {
+    let id = config.read(address, REG_ID);
+    let vendor_id = id as u16;
+    if vendor_id == 0xffff {
+        return None;
+    }
+    let class = config.read(address, REG_CLASS);
+    let header_type = (config.read(address, REG_HEADER) >> 16) as u8 & HEADER_TYPE_MASK;
+    let bar_count = match header_type {
+        HEADER_GENERAL => 6,
+        HEADER_BRIDGE => 2,
+        // CardBus 桥等其他类型没有同样布局的 BAR
+        _ => 0,
+    };
+    Some(PciDevice {
+        address,
+        vendor_id,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        header_type,
+        bars: read_bars(config, address, bar_count),
+    })
+}
+
+/// 枚举所有总线上的所有功能，对每个存在的功能调用 f
+pub fn scan(config: &mut impl ConfigSpace, mut f: impl FnMut(PciDevice)) {
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let first = PciAddress {
+                bus,
+                device,
+                function: 0,
+            };
+            if config.read(first, REG_ID) as u16 == 0xffff {
+                continue;
+            }
+            let header = (config.read(first, REG_HEADER) >> 16) as u8;
+            let functions = if header & MULTI_FUNCTION != 0 { 8 } else { 1 };
+            for function in 0..functions {
+                let address = PciAddress {
+                    bus,
+                    device,
+                    function,
+                };
+                if let Some(found) = read_function(config, address) {
+                    f(found);
+                }
+            }
+        }
+    }
+}
+
+struct DeviceTable {
+    devices: [Option<PciDevice>; MAX_DEVICES],
+}
+
+static DEVICES: OnceCell<DeviceTable> = OnceCell::uninit();
+
+/// 扫描总线并保存结果，只有第一次调用会扫描，返回找到的功能数（包括放不下的）
+pub fn init() -> usize {
+    let mut found = 0;
+    DEVICES.init_once(|| {
+        let mut table = DeviceTable {
+            devices: [None; MAX_DEVICES],
+        };
+        scan(&mut HardwareConfig, |device| {
+            if let Some(slot) = table.devices.get_mut(found) {
+                *slot = Some(device);
+            }
+            found += 1;
+        });
+        table
+    });
+    found
+}
+
+/// init 找到的设备，按总线、设备、功能排列；init 之前为空
+pub fn devices() -> impl Iterator<Item = &'static PciDevice> {
+    DEVICES
+        .get()
+        .into_iter()
+        .flat_map(|table| table.devices.iter().flatten())
+}
+
+/// 打开设备的 I/O 和内存空间译码，驱动访问 BAR 之前调用
+pub fn enable_decoding(address: PciAddress) {
+    enable(&mut HardwareConfig, address);
+}
+
+fn enable(config: &mut impl ConfigSpace, address: PciAddress) {
+    let command = config.read(address, REG_COMMAND) & 0xffff;
+    config.write(address, REG_COMMAND, command | COMMAND_DECODE);
+}
+
+/// 常见类别的名称
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "network controller",
+        (0x03, 0x00) => "VGA controller",
+        (0x03, _) => "display controller",
+        (0x04, _) => "multimedia controller",
+        (0x05, _) => "memory controller",
+        (0x06, 0x00) => "host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "bridge",
+        (0x07, _) => "communication controller",
+        (0x08, _) => "system peripheral",
+        (0x0c, 0x03) => "USB controller",
+        (0x0c, 0x05) => "SMBus controller",
+        (0x0c, _) => "serial bus controller",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+use alloc::collections::BTreeMap;
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 预设的配置空间：没有设置的 ID 寄存器读出全 1（功能不存在），其他寄存器读出 0；
+/// 写入全 1 时读回 masks 中的值，不在 masks 中的读回 0，模拟只有地址位可写的 BAR
+#[cfg(test)]
+#[derive(Default)]
+struct MockConfig {
+    registers: BTreeMap<(PciAddress, u8), u32>,
+    masks: BTreeMap<(PciAddress, u8), u32>,
+}
+
+#[cfg(test)]
+impl ConfigSpace for MockConfig {
+    fn read(&mut self, address: PciAddress, offset: u8) -> u32 {
+        let absent = if offset == REG_ID { u32::MAX } else { 0 };
+        self.registers
+            .get(&(address, offset))
+            .copied()
+            .unwrap_or(absent)
+    }
+
+    fn write(&mut self, address: PciAddress, offset: u8, value: u32) {
+        let value = match value {
+            u32::MAX => self.masks.get(&(address, offset)).copied().unwrap_or(0),
+            value => value,
+        };
+        self.registers.insert((address, offset), value);
+    }
+}
+
+#[cfg(test)]
+const fn at(device: u8, function: u8) -> PciAddress {
+    PciAddress {
+        bus: 0,
+        device,
+        function,
+    }
+}
+
+#[test_case]
+fn test_config_address_encoding() {
+    let address = PciAddress {
+        bus: 0x12,
+        device: 0x1f,
+        function: 7,
+    };
+    assert_eq!(address.config_address(0x3c), 0x8012_ff3c);
+    // 寄存器偏移按 4 字节对齐
+    assert_eq!(address.config_address(0x3e), 0x8012_ff3c);
+    assert_eq!(at(3, 0).config_address(0x10), 0x8000_1810);
+}
+
+#[test_case]
+fn test_decode_bar() {
+    // 4KiB 的 32 位内存 BAR
+    assert_eq!(
+        decode_bar(0xfebd_5000, 0xffff_f000, None),
+        Some(Bar::Memory {
+            address: 0xfebd_5000,
+            size: 0x1000,
+            prefetchable: false,
+            wide: false,
+        })
+    );
+    // 16MiB 可预取的 64 位内存 BAR
+    assert_eq!(
+        decode_bar(0xfd00_000c, 0xff00_000c, Some((0x1, 0xffff_ffff))),
+        Some(Bar::Memory {
+            address: 0x1_fd00_0000,
+            size: 0x100_0000,
+            prefetchable: true,
+            wide: true,
+        })
+    );
+    // 32 字节的 I/O BAR，高 16 位读回 0
+    assert_eq!(
+        decode_bar(0xc041, 0xffe1, None),
+        Some(Bar::Io {
+            port: 0xc040,
+            size: 0x20
+        })
+    );
+    // 没有实现的 BAR
+    assert_eq!(decode_bar(0, 0, None), None);
+    assert_eq!(decode_bar(0x1, 0x1, None), None);
+}
+
+#[test_case]
+fn test_scan_functions_and_restore_bars() {
+    let mut config = MockConfig::default();
+    // 00:00.0 单功能的主桥
+    config.registers.insert((at(0, 0), REG_ID), 0x1237_8086);
+    config.registers.insert((at(0, 0), REG_CLASS), 0x0600_0002);
+    // 00:01 多功能：.0 是 ISA 桥，.1 是带一个 I/O BAR 的 IDE 控制器
+    config.registers.insert((at(1, 0), REG_ID), 0x7000_8086);
+    config.registers.insert((at(1, 0), REG_CLASS), 0x0601_0000);
+    config.registers.insert((at(1, 0), REG_HEADER), 0x0080_0000);
+    config.registers.insert((at(1, 1), REG_ID), 0x7010_8086);
+    config.registers.insert((at(1, 1), REG_CLASS), 0x0101_8000);
+    config
+        .registers
+        .insert((at(1, 1), REG_COMMAND), 0x0280_0005);
+    config.registers.insert((at(1, 1), REG_BAR0 + 16), 0xc041);
+    config.masks.insert((at(1, 1), REG_BAR0 + 16), 0xfff1);
+
+    let mut found = Vec::new();
+    scan(&mut config, |device| found.push(device));
+    let addresses: Vec<_> = found.iter().map(|device| device.address).collect();
+    assert_eq!(addresses, [at(0, 0), at(1, 0), at(1, 1)]);
+    assert!(found[0].is_bridge() && found[1].is_bridge() && !found[2].is_bridge());
+    assert_eq!(
+        (found[2].class, found[2].subclass, found[2].prog_if),
+        (0x01, 0x01, 0x80)
+    );
+    assert_eq!(
+        found[2].bars,
+        [
+            None,
+            None,
+            None,
+            None,
+            Some(Bar::Io {
+                port: 0xc040,
+                size: 0x10
+            }),
+            None
+        ]
+    );
+    // BAR 和命令寄存器恢复为原值，状态寄存器没有被写 1 清除
+    assert_eq!(config.registers[&(at(1, 1), REG_BAR0 + 16)], 0xc041);
+    assert_eq!(config.registers[&(at(1, 1), REG_COMMAND)], 0x0005);
+}
@@ -0,0 +1,181 @@
+//! 关机与重启
+//! 关机先通过 ACPI 进入 S5：向 FADT 中的 PM1a（和 PM1b）控制寄存器写入 DSDT 中 \_S5 的 SLP_TYP 和 SLP_EN。
+//! 找不到或者无法解析 ACPI 表时记录一条警告，然后依次尝试各个模拟器提供的关机端口，
+//! 都不起作用时提示可以关闭电源并停机；
+//! 重启先通过键盘控制器拉低 CPU 的复位线，不起作用时加载一个空的 IDT 再触发异常，三重错误会让 CPU 复位。
+//!
+//! 每一步在真实硬件上成功时都不会返回，所以按顺序执行，执行到下一步就说明上一步没有生效
+use crate::acpi::{self, SleepControl};
+use crate::ps2::Controller;
+use crate::vga_buffer::WRITER;
+use crate::{hlt_loop, kwarn, println, QemuExitCode};
+use x86_64::instructions::port::Port;
+
+/// 关机或重启的一个尝试
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// 向端口写入 16 位的值
+    Write16(u16, u16),
+    /// 向端口写入 32 位的值
+    Write32(u16, u32),
+    /// 向键盘控制器发送命令
+    ControllerCommand(u8),
+}
+
+/// ACPI 关机之后的尝试：
+/// 依次是新版 QEMU 的关机端口 0x604、Bochs 和旧版 QEMU 共用的 0xB004，最后是启动时配置了的 isa-debug-exit 设备
+const SHUTDOWN_STEPS: &[Step] = &[
+    Step::Write16(0x604, 0x2000),
+    Step::Write16(0xb004, 0x2000),
+    Step::Write32(0xf4, QemuExitCode::Success as u32),
+];
+
+/// 键盘控制器命令 0xFE 让复位线产生一个脉冲
+const REBOOT_STEPS: &[Step] = &[Step::ControllerCommand(0xfe)];
+
+/// 执行关机和重启步骤的端口，抽象出来以便测试尝试的顺序
+pub trait PowerPorts {
+    fn run(&mut self, step: Step);
+}
+
+struct HardwarePorts;
+
+impl PowerPorts for HardwarePorts {
+    fn run(&mut self, step: Step) {
+        match step {
+            Step::Write16(port, value) => unsafe { Port::new(port).write(value) },
+            Step::Write32(port, value) => unsafe { Port::new(port).write(value) },
+            Step::ControllerCommand(command) => {
+                Controller::new().write_command(command);
+            }
+        }
+    }
+}
+
+fn run_steps(ports: &mut impl PowerPorts, steps: impl IntoIterator<Item = Step>) {
+    for step in steps {
+        ports.run(step);
+    }
+}
+
+/// 关机的全部尝试，有 ACPI 时最先写 PM1 控制寄存器
+fn shutdown_steps(acpi: Option<&SleepControl>) -> impl Iterator<Item = Step> {
+    let pm1a = acpi.map(|control| Step::Write16(control.fadt.pm1a_control, control.pm1a_value()));
+    let pm1b = acpi.and_then(|control| {
+        let port = control.fadt.pm1b_control?;
+        Some(Step::Write16(port, control.pm1b_value()))
+    });
+    pm1a.into_iter()
+        .chain(pm1b)
+        .chain(SHUTDOWN_STEPS.iter().copied())
+}
+
+/// 关闭中断，并让之后的输出能显示出来
+/// 调用者（例如 shell 命令）可能正持有 WRITER 的锁，而它再也不会返回去释放这个锁
+fn prepare() {
+    x86_64::instructions::interrupts::disable();
+    // 单核且中断已经关闭，持有锁的只可能是当前执行流中永远不会恢复的调用者
+    if WRITER.is_locked() {
+        unsafe { WRITER.force_unlock() };
+    }
+    // 输出都是同步写入的，没有需要刷新的缓冲；回滚中的视图要回到底部才能看到最后的提示
+    WRITER.lock().snap_to_bottom();
+}
+
+/// 关闭计算机，所有尝试都失败时停机
+pub fn shutdown() -> ! {
+    prepare();
+    println!("Shutting down...");
+    let acpi = match acpi::sleep_control() {
+        Ok(control) => {
+            acpi::enable(&control.fadt);
+            Some(control)
+        }
+        Err(error) => {
+            kwarn!("ACPI shutdown unavailable: {}", error);
+            None
+        }
+    };
+    run_steps(&mut HardwarePorts, shutdown_steps(acpi.as_ref()));
+    println!("It is now safe to turn off your computer");
+    hlt_loop();
+}
+
+/// 重启计算机
+pub fn reboot() -> ! {
+    use x86_64::instructions::tables::{lidt, DescriptorTablePointer};
+    use x86_64::VirtAddr;
+
+    prepare();
+    println!("Rebooting...");
+    run_steps(&mut HardwarePorts, REBOOT_STEPS.iter().copied());
+
+    // 空的 IDT 中找不到任何处理函数：断点异常升级为 double fault，再升级为三重错误
+    let empty = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::new(0),
+    };
+    unsafe { lidt(&empty) };
+    x86_64::instructions::interrupts::int3();
+    hlt_loop();
+}
+
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 记录执行过的步骤
+#[cfg(test)]
+struct RecordingPorts(Vec<Step>);
+
+#[cfg(test)]
+impl PowerPorts for RecordingPorts {
+    fn run(&mut self, step: Step) {
+        self.0.push(step);
+    }
+}
+
+#[test_case]
+fn test_shutdown_tries_exits_in_order() {
+    let mut ports = RecordingPorts(Vec::new());
+    run_steps(&mut ports, shutdown_steps(None));
+    assert_eq!(
+        ports.0,
+        [
+            Step::Write16(0x604, 0x2000),
+            Step::Write16(0xb004, 0x2000),
+            Step::Write32(0xf4, 0x10),
+        ]
+    );
+}
+
+#[test_case]
+fn test_reboot_pulses_reset_line() {
+    let mut ports = RecordingPorts(Vec::new());
+    run_steps(&mut ports, REBOOT_STEPS.iter().copied());
+    assert_eq!(ports.0, [Step::ControllerCommand(0xfe)]);
+}
+
+#[test_case]
+fn test_shutdown_prefers_acpi() {
+    use crate::acpi::Fadt;
+
+    let control = SleepControl {
+        fadt: Fadt {
+            dsdt: 0x1000,
+            pm1a_control: 0x1804,
+            pm1b_control: Some(0x1808),
+            smi_command: 0,
+            acpi_enable: 0,
+        },
+        slp_typ_a: 5,
+        slp_typ_b: 7,
+    };
+    let mut ports = RecordingPorts(Vec::new());
+    run_steps(&mut ports, shutdown_steps(Some(&control)));
+    // SLP_TYP 在 bit 10-12，SLP_EN 是 bit 13，之后仍然尝试模拟器的端口
+    assert_eq!(
+        ports.0[..2],
+        [Step::Write16(0x1804, 0x3400), Step::Write16(0x1808, 0x3c00)]
+    );
+    assert_eq!(ports.0[2..], *SHUTDOWN_STEPS);
+}
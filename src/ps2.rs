@@ -0,0 +1,291 @@
+//! PS/2 控制器（8042）
+//! 0x64 是状态/命令端口，0x60 是数据端口。写入数据端口的字节发给第一个端口上的键盘，
+//! 先向命令端口写 0xD4 再写数据端口则发给第二个端口上的鼠标
+//!
+//! 设备对每个命令字节回应 0xFA（ACK），要求重发时回应 0xFE（RESEND）。
+//! 命令与回应的交换直接轮询控制器完成，调用者需要屏蔽对应的 IRQ，
+//! 否则回应字节会被中断处理函数取走。等待有次数上限，设备不存在时也不会卡住
+use x86_64::instructions::port::Port;
+
+/// 设备对命令字节的确认
+pub const ACK: u8 = 0xfa;
+/// 设备要求重发上一个命令字节
+pub const RESEND: u8 = 0xfe;
+
+/// 每个命令字节最多重发的次数
+const MAX_RESENDS: usize = 3;
+/// 等待回应时最多接收的其他字节，超过后按超时处理
+const MAX_STRAY_BYTES: usize = 16;
+/// 轮询控制器状态的次数上限
+const TIMEOUT_SPINS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    /// 控制器一直不接受写入，或者设备一直没有回应
+    Timeout,
+    /// 设备多次要求重发
+    TooManyResends,
+}
+
+/// 向设备发送字节、接收设备回应的通道，抽象出来以便用脚本化的回应测试命令交换
+pub trait Ps2Port {
+    /// 等待控制器可以接收后写入一个字节，超时返回 false
+    fn write(&mut self, byte: u8) -> bool;
+    /// 等待设备发来一个字节，超时返回 None
+    fn read(&mut self) -> Option<u8>;
+}
+
+/// 轮询方式访问控制器，作为 Ps2Port 时读写第一个端口上的设备
+pub struct Controller {
+    status: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Controller {
+    /// 状态位 0：输出缓冲区有数据
+    pub const OUTPUT_FULL: u8 = 1 << 0;
+    /// 状态位 1：输入缓冲区还没有被控制器取走
+    const INPUT_FULL: u8 = 1 << 1;
+    /// 状态位 5：输出缓冲区中的字节来自第二个端口（鼠标）
+    pub const AUX_DATA: u8 = 1 << 5;
+
+    pub const fn new() -> Self {
+        Controller {
+            status: Port::new(0x64),
+            data: Port::new(0x60),
+        }
+    }
+
+    pub fn status(&mut self) -> u8 {
+        unsafe { self.status.read() }
+    }
+
+    fn wait_status(&mut self, mask: u8, set: bool) -> bool {
+        for _ in 0..TIMEOUT_SPINS {
+            if (self.status() & mask != 0) == set {
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+        false
+    }
+
+    /// 向控制器本身写一个命令字节，超时返回 false
+    pub fn write_command(&mut self, command: u8) -> bool {
+        if !self.wait_status(Self::INPUT_FULL, false) {
+            return false;
+        }
+        // 命令端口与状态端口是同一个端口号
+        unsafe { self.status.write(command) };
+        true
+    }
+}
+
+impl Ps2Port for Controller {
+    fn write(&mut self, byte: u8) -> bool {
+        if !self.wait_status(Self::INPUT_FULL, false) {
+            return false;
+        }
+        unsafe { self.data.write(byte) };
+        true
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        if !self.wait_status(Self::OUTPUT_FULL, true) {
+            return None;
+        }
+        Some(unsafe { self.data.read() })
+    }
+}
+
+/// 第二个端口上的设备（鼠标），每个字节前都要先向控制器发送 0xD4
+pub struct AuxPort(pub Controller);
+
+impl Ps2Port for AuxPort {
+    fn write(&mut self, byte: u8) -> bool {
+        self.0.write_command(0xd4) && self.0.write(byte)
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.0.read()
+    }
+}
+
+/// 第一个端口上的设备（键盘），读取时把第二个端口发来的字节交给 aux，
+/// 鼠标的数据不会被当作键盘的回应或者扫描码
+pub struct KeyboardPort<F: FnMut(u8)> {
+    pub controller: Controller,
+    pub aux: F,
+}
+
+impl<F: FnMut(u8)> Ps2Port for KeyboardPort<F> {
+    fn write(&mut self, byte: u8) -> bool {
+        self.controller.write(byte)
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        for _ in 0..=MAX_STRAY_BYTES {
+            if !self.controller.wait_status(Controller::OUTPUT_FULL, true) {
+                return None;
+            }
+            // 先读状态再读数据，状态描述的是即将读出的字节
+            let status = self.controller.status();
+            let byte = unsafe { self.controller.data.read() };
+            if status & Controller::AUX_DATA == 0 {
+                return Some(byte);
+            }
+            (self.aux)(byte);
+        }
+        None
+    }
+}
+
+/// 逐字节发送命令，每个字节都要等到 ACK 才发送下一个，收到 RESEND 时重发该字节
+/// 等待回应期间收到的其他字节交给 stray
+pub fn send_command<P: Ps2Port>(
+    port: &mut P,
+    bytes: &[u8],
+    mut stray: impl FnMut(u8),
+) -> Result<(), CommandError> {
+    for &byte in bytes {
+        send_byte(port, byte, &mut stray)?;
+    }
+    Ok(())
+}
+
+fn send_byte<P: Ps2Port>(
+    port: &mut P,
+    byte: u8,
+    stray: &mut impl FnMut(u8),
+) -> Result<(), CommandError> {
+    for _ in 0..=MAX_RESENDS {
+        if !port.write(byte) {
+            return Err(CommandError::Timeout);
+        }
+        if wait_response(port, stray)? == ACK {
+            return Ok(());
+        }
+    }
+    Err(CommandError::TooManyResends)
+}
+
+/// 返回 ACK 或 RESEND
+fn wait_response<P: Ps2Port>(port: &mut P, stray: &mut impl FnMut(u8)) -> Result<u8, CommandError> {
+    for _ in 0..=MAX_STRAY_BYTES {
+        match port.read() {
+            Some(response @ (ACK | RESEND)) => return Ok(response),
+            Some(other) => stray(other),
+            None => return Err(CommandError::Timeout),
+        }
+    }
+    Err(CommandError::Timeout)
+}
+
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 按脚本回应的端口，脚本用完后读取超时
+#[cfg(test)]
+struct ScriptedPort {
+    responses: &'static [u8],
+    next: usize,
+    written: Vec<u8>,
+    writable: bool,
+}
+
+#[cfg(test)]
+impl ScriptedPort {
+    fn new(responses: &'static [u8]) -> Self {
+        ScriptedPort {
+            responses,
+            next: 0,
+            written: Vec::new(),
+            writable: true,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Ps2Port for ScriptedPort {
+    fn write(&mut self, byte: u8) -> bool {
+        if self.writable {
+            self.written.push(byte);
+        }
+        self.writable
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let response = self.responses.get(self.next).copied();
+        self.next += 1;
+        response
+    }
+}
+
+#[test_case]
+fn test_command_acknowledged() {
+    let mut port = ScriptedPort::new(&[ACK, ACK]);
+    assert_eq!(send_command(&mut port, &[0xed, 0b100], |_| {}), Ok(()));
+    assert_eq!(port.written, [0xed, 0b100]);
+}
+
+#[test_case]
+fn test_command_resend() {
+    // 第一个字节被要求重发一次，第二个字节两次
+    let mut port = ScriptedPort::new(&[RESEND, ACK, RESEND, RESEND, ACK]);
+    assert_eq!(send_command(&mut port, &[0xed, 0b010], |_| {}), Ok(()));
+    assert_eq!(port.written, [0xed, 0xed, 0b010, 0b010, 0b010]);
+}
+
+#[test_case]
+fn test_command_gives_up_after_resends() {
+    let mut port = ScriptedPort::new(&[RESEND; MAX_RESENDS + 2]);
+    assert_eq!(
+        send_command(&mut port, &[0xed, 0], |_| {}),
+        Err(CommandError::TooManyResends)
+    );
+    assert_eq!(port.written, [0xed; MAX_RESENDS + 1]);
+}
+
+#[test_case]
+fn test_command_timeout() {
+    // 第一个字节确认后设备不再回应
+    let mut port = ScriptedPort::new(&[ACK]);
+    assert_eq!(
+        send_command(&mut port, &[0xed, 0], |_| {}),
+        Err(CommandError::Timeout)
+    );
+    assert_eq!(port.written, [0xed, 0]);
+
+    // 控制器一直不接受写入
+    let mut port = ScriptedPort::new(&[]);
+    port.writable = false;
+    assert_eq!(
+        send_command(&mut port, &[0xed, 0], |_| {}),
+        Err(CommandError::Timeout)
+    );
+
+    // 只有源源不断的其他字节，没有回应
+    let mut port = ScriptedPort::new(&[0x1e; MAX_STRAY_BYTES + 1]);
+    assert_eq!(
+        send_command(&mut port, &[0xed, 0], |_| {}),
+        Err(CommandError::Timeout)
+    );
+}
+
+#[test_case]
+fn test_stray_bytes_during_command_are_forwarded() {
+    // 键盘的 a 按下、松开夹在两个 ACK 之间
+    let mut port = ScriptedPort::new(&[ACK, 0x1e, 0x9e, ACK]);
+    let mut forwarded = Vec::new();
+    assert_eq!(
+        send_command(&mut port, &[0xed, 0], |byte| forwarded.push(byte)),
+        Ok(())
+    );
+    assert_eq!(forwarded, [0x1e, 0x9e]);
+}
@@ -0,0 +1,224 @@
+//! 内核随机数
+//! CPU 支持 RDRAND 时直接使用它：RDRAND 在熵暂时不足时清零 CF，按 Intel 的建议最多重试 10 次；
+//! 有些 CPU 的 RDRAND 有缺陷，总是返回同一个值（例如全 1）却报告成功，初始化时连续取几个值检查。
+//! 不支持或有缺陷时退回 xorshift64*，种子由 RDTSC 和时钟节拍数经 SplitMix64 混合得到。
+//!
+//! 这些数不适合用于密码学，只用于填充图案、生成测试输入等场合。
+//! 全局状态是一个原子变量，可以在中断处理函数中使用
+use crate::cpu::{self, Feature};
+use crate::time;
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Intel 建议的 RDRAND 重试次数
+const RDRAND_RETRIES: usize = 10;
+/// 初始化时检查 RDRAND 是否总是返回同一个值所取的样本数
+const RDRAND_SAMPLES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Rdrand,
+    Xorshift,
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Rdrand => "rdrand",
+            Source::Xorshift => "xorshift64*",
+        }
+    }
+}
+
+static SOURCE: OnceCell<Source> = OnceCell::uninit();
+/// 后备生成器的状态，不会是 0
+static STATE: AtomicU64 = AtomicU64::new(1);
+
+/// SplitMix64 的一步，把相近的输入映射为差别很大的输出，用来处理种子
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// xorshift64*：周期 2^64 - 1，状态不能为 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Xorshift(u64);
+
+impl Xorshift {
+    /// 任意种子都可以，包括 0
+    pub fn new(seed: u64) -> Xorshift {
+        Xorshift(splitmix64(seed).max(1))
+    }
+
+    fn step(state: u64) -> u64 {
+        let mut x = state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        x
+    }
+
+    fn output(state: u64) -> u64 {
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = Xorshift::step(self.0);
+        Xorshift::output(self.0)
+    }
+}
+
+/// 执行 RDRAND，重试 RDRAND_RETRIES 次仍然失败时返回 None
+fn rdrand() -> Option<u64> {
+    for _ in 0..RDRAND_RETRIES {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            core::arch::asm!(
+                "rdrand {value}",
+                "setc {ok}",
+                value = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack),
+            );
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// RDRAND 可用并且连续几次返回的值不全相同
+fn rdrand_works() -> bool {
+    if !cpu::has_feature(Feature::Rdrand) {
+        return false;
+    }
+    let Some(first) = rdrand() else {
+        return false;
+    };
+    (1..RDRAND_SAMPLES).any(|_| rdrand().is_some_and(|value| value != first))
+}
+
+fn detect() -> Source {
+    let seed = time::rdtsc() ^ time::ticks().rotate_left(32);
+    STATE.store(Xorshift::new(seed).0, Ordering::Relaxed);
+    if rdrand_works() {
+        Source::Rdrand
+    } else {
+        Source::Xorshift
+    }
+}
+
+/// 选择来源并为后备生成器播种，第一次取随机数时也会自动调用
+pub fn init() {
+    SOURCE.init_once(detect);
+}
+
+/// 当前使用的来源
+pub fn source() -> Source {
+    *SOURCE.get_or_init(detect)
+}
+
+fn xorshift_u64() -> u64 {
+    let previous = STATE
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+            Some(Xorshift::step(state))
+        })
+        .unwrap();
+    Xorshift::output(Xorshift::step(previous))
+}
+
+/// 一个随机的 u64；RDRAND 偶尔重试后仍然失败时，这一次改用后备生成器
+pub fn u64() -> u64 {
+    match source() {
+        Source::Rdrand => rdrand().unwrap_or_else(xorshift_u64),
+        Source::Xorshift => xorshift_u64(),
+    }
+}
+
+/// [0, bound) 中均匀分布的数：拒绝低于 2^64 mod bound 的值，剩下的值的个数是 bound 的整数倍，
+/// 取模后没有偏差。bound 为 0 时返回 0
+fn range_with(bound: u64, mut next: impl FnMut() -> u64) -> u64 {
+    if bound == 0 {
+        return 0;
+    }
+    let threshold = bound.wrapping_neg() % bound;
+    loop {
+        let value = next();
+        if value >= threshold {
+            return value % bound;
+        }
+    }
+}
+
+/// [0, bound) 中均匀分布的随机数，bound 为 0 时返回 0
+pub fn range(bound: u64) -> u64 {
+    range_with(bound, u64)
+}
+
+fn fill_with(buf: &mut [u8], mut next: impl FnMut() -> u64) {
+    for chunk in buf.chunks_mut(8) {
+        chunk.copy_from_slice(&next().to_le_bytes()[..chunk.len()]);
+    }
+}
+
+/// 用随机字节填满 buf
+pub fn fill(buf: &mut [u8]) {
+    fill_with(buf, u64);
+}
+
+#[test_case]
+fn test_xorshift_is_deterministic() {
+    let mut a = Xorshift::new(42);
+    let mut b = Xorshift::new(42);
+    for _ in 0..100 {
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+    assert_ne!(Xorshift::new(42).next_u64(), Xorshift::new(43).next_u64());
+    // 种子 0 也能产生非零的状态
+    let mut zero = Xorshift::new(0);
+    assert_ne!(zero.next_u64(), zero.next_u64());
+}
+
+#[test_case]
+fn test_range_bounds_and_rejection() {
+    let mut generator = Xorshift::new(1);
+    for bound in [1, 2, 3, 7, 1000, u64::MAX] {
+        for _ in 0..50 {
+            assert!(range_with(bound, || generator.next_u64()) < bound);
+        }
+    }
+    assert_eq!(range_with(0, || unreachable!()), 0);
+
+    // bound = 2^63 + 1 时 2^64 mod bound = 2^63 - 1，低于它的值会被拒绝
+    let bound = (1 << 63) + 1;
+    let mut values = [0, (1 << 63) - 2, (1 << 63) - 1].into_iter();
+    assert_eq!(range_with(bound, || values.next().unwrap()), (1 << 63) - 1);
+    assert_eq!(values.next(), None);
+    // 2 的幂没有需要拒绝的值
+    assert_eq!(range_with(8, || 13), 5);
+}
+
+#[test_case]
+fn test_fill_odd_lengths() {
+    for len in [0, 1, 7, 9, 17] {
+        let mut buf = [0xaa; 17];
+        let mut counter = 0u64;
+        fill_with(&mut buf[..len], || {
+            counter += 1;
+            0x0807_0605_0403_0201 * counter
+        });
+        assert_eq!(counter, len.div_ceil(8) as u64);
+        for (index, &byte) in buf[..len].iter().enumerate() {
+            let value = 0x0807_0605_0403_0201 * (index / 8 + 1) as u64;
+            assert_eq!(byte, value.to_le_bytes()[index % 8]);
+        }
+        // 不会写到 buf 之外
+        assert!(buf[len..].iter().all(|&byte| byte == 0xaa));
+    }
+    let mut buf = [0; 3];
+    fill(&mut buf);
+}
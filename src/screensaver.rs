@@ -0,0 +1,184 @@
+//! 屏幕保护
+//! 键盘中断处理函数在每次按键时记录当前的节拍数；屏保任务每一帧检查一次，
+//! 超过 timeout 个节拍没有按键就保存屏幕快照，然后在黑色背景上画一行来回弹跳的文字。
+//! 之后的任何按键都会让屏保在下一帧恢复快照。屏保显示期间解码出的按键只用来唤醒，
+//! 不交给 shell，否则它的回显会在恢复快照时被擦掉（见 swallow_key）。
+//!
+//! 每一帧的画面只由帧号决定（frame_position 是纯函数），重画同一帧得到同样的画面。
+//! 屏保期间其他任务打印的内容会在恢复快照时被覆盖
+use crate::vga_buffer::{self, Color, ColorCode, Writer, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+use crate::{statusbar, time};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use futures_util::stream::StreamExt;
+use x86_64::instructions::interrupts;
+
+/// 默认 5 分钟没有按键后启动
+pub const DEFAULT_TIMEOUT_TICKS: u64 = 5 * 60 * time::TIMER_FREQUENCY_HZ as u64;
+/// 每帧的间隔（毫秒）
+const FRAME_MS: u32 = 100;
+const TEXT: &str = "vm_os";
+
+static LAST_ACTIVITY: AtomicU64 = AtomicU64::new(0);
+static TIMEOUT_TICKS: AtomicU64 = AtomicU64::new(DEFAULT_TIMEOUT_TICKS);
+static ENABLED: AtomicBool = AtomicBool::new(true);
+/// 屏保正在显示，从画出第一帧到恢复快照
+static SHOWING: AtomicBool = AtomicBool::new(false);
+
+/// 记录一次按键，由键盘中断处理函数调用
+pub(crate) fn record_activity() {
+    LAST_ACTIVITY.store(time::ticks(), Ordering::Relaxed);
+}
+
+/// 屏保正在显示时返回 true，按下的键应当被丢弃；由键盘解码调用
+pub(crate) fn swallow_key() -> bool {
+    SHOWING.load(Ordering::SeqCst)
+}
+
+/// 设置多少个节拍没有按键后启动屏保，同时重新启用屏保
+pub fn set_timeout(ticks: u64) {
+    TIMEOUT_TICKS.store(ticks, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// 关闭屏保，正在显示时在下一帧恢复屏幕
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// 从 last_activity 到 now 是否已经空闲了 timeout 个节拍
+fn idle(now: u64, last_activity: u64, timeout: u64) -> bool {
+    now.saturating_sub(last_activity) >= timeout
+}
+
+/// 在 0..=span 之间来回移动：0, 1, ..., span, span - 1, ..., 1, 0, 1, ...
+fn bounce(frame: u64, span: usize) -> usize {
+    if span == 0 {
+        return 0;
+    }
+    let period = 2 * span as u64;
+    let phase = (frame % period) as usize;
+    if phase <= span {
+        phase
+    } else {
+        2 * span - phase
+    }
+}
+
+/// 第 frame 帧文字左端所在的 (行, 列)，行和列各自来回弹跳
+pub fn frame_position(frame: u64) -> (usize, usize) {
+    (
+        bounce(frame, BUFFER_HEIGHT - 1),
+        bounce(frame, BUFFER_WIDTH - TEXT.len()),
+    )
+}
+
+/// 画第 frame 帧：整个屏幕涂黑，再在 frame_position 处写文字
+fn draw_frame(writer: &mut Writer, frame: u64) {
+    let black = ColorCode::new(Color::Black, Color::Black);
+    for row in 0..BUFFER_HEIGHT {
+        for col in 0..BUFFER_WIDTH {
+            writer.put_char(row, col, b' ', black);
+        }
+    }
+    let (row, col) = frame_position(frame);
+    let color = ColorCode::new(Color::LightCyan, Color::Black);
+    for (offset, byte) in TEXT.bytes().enumerate() {
+        writer.put_char(row, col + offset, byte, color);
+    }
+}
+
+/// 屏保任务
+pub async fn run() {
+    let mut frames = time::interval(FRAME_MS);
+    // 显示中时保存的是 (快照, 启动时最后一次按键的节拍数, 帧号)
+    let mut active = None;
+    loop {
+        frames.next().await;
+        let last_activity = LAST_ACTIVITY.load(Ordering::Relaxed);
+        let enabled = ENABLED.load(Ordering::Relaxed);
+        match &mut active {
+            None => {
+                let timeout = TIMEOUT_TICKS.load(Ordering::Relaxed);
+                if enabled && idle(time::ticks(), last_activity, timeout) {
+                    interrupts::without_interrupts(|| {
+                        let mut writer = WRITER.lock();
+                        writer.snap_to_bottom();
+                        statusbar::pause();
+                        let snapshot = writer.snapshot();
+                        draw_frame(&mut writer, 0);
+                        SHOWING.store(true, Ordering::SeqCst);
+                        active = Some((snapshot, last_activity, 0));
+                    });
+                }
+            }
+            Some((snapshot, since, _)) if !enabled || last_activity != *since => {
+                // 整屏恢复分段提交，期间的按键不会被推迟
+                vga_buffer::redraw(|writer| writer.restore(snapshot));
+                SHOWING.store(false, Ordering::SeqCst);
+                statusbar::resume();
+                active = None;
+            }
+            Some((_, _, frame)) => {
+                *frame += 1;
+                interrupts::without_interrupts(|| draw_frame(&mut WRITER.lock(), *frame));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::vga_buffer::TestWriter;
+
+#[test_case]
+fn test_bounce_and_idle() {
+    let positions: alloc::vec::Vec<_> = (0..8).map(|frame| bounce(frame, 3)).collect();
+    assert_eq!(positions, [0, 1, 2, 3, 2, 1, 0, 1]);
+    assert_eq!(bounce(12345, 0), 0);
+    assert_eq!(frame_position(0), (0, 0));
+    assert_eq!(
+        frame_position(BUFFER_HEIGHT as u64 - 1),
+        (BUFFER_HEIGHT - 1, BUFFER_HEIGHT - 1)
+    );
+    // 文字的右端碰到屏幕边缘后折返
+    let span = (BUFFER_WIDTH - TEXT.len()) as u64;
+    assert_eq!(frame_position(span).1, BUFFER_WIDTH - TEXT.len());
+    assert_eq!(frame_position(span + 1).1, BUFFER_WIDTH - TEXT.len() - 1);
+
+    assert!(!idle(999, 0, 1000));
+    assert!(idle(1000, 0, 1000));
+    // 按键的节拍数可能比屏保任务读到的 now 更新
+    assert!(!idle(5, 6, 1000));
+}
+
+#[test_case]
+fn test_draw_frame_is_idempotent() {
+    let mut writer = TestWriter::new();
+    writer.write_string("hello");
+    let snapshot = writer.snapshot();
+    draw_frame(&mut writer, 7);
+    draw_frame(&mut writer, 7);
+    let (row, col) = frame_position(7);
+    assert_eq!(writer.read_char(row, col).0, b'v');
+    assert_eq!(writer.read_char(row, col + TEXT.len()).0, b' ');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b' ');
+
+    writer.restore(&snapshot);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b'h');
+}
+
+#[test_case]
+fn test_key_pressed_while_showing_is_swallowed() {
+    use crate::keyboard;
+
+    // 'a' 的按下和松开
+    const PRESS: u8 = 0x1e;
+    const RELEASE: u8 = 0x9e;
+
+    SHOWING.store(true, Ordering::SeqCst);
+    assert!(keyboard::decode_input(PRESS).is_none());
+    // 松开照常交出，修饰键等状态不会卡住
+    assert!(keyboard::decode_input(RELEASE).is_some());
+    SHOWING.store(false, Ordering::SeqCst);
+    assert!(keyboard::decode(PRESS).is_some());
+    keyboard::decode(RELEASE);
+}
@@ -0,0 +1,366 @@
+//! 启动自检
+//! 不依赖串口和 QEMU 的退出设备，直接在真实的 WRITER 上检查控制台的基本功能，
+//! 结果用颜色标出 PASS/FAIL 并在最后汇总，适合在真机上确认显示是否正常。
+//! 打开 selftest feature 后在启动时运行：
+//!
+//! ```shell
+//! cargo run --features selftest
+//! ```
+//!
+//! 每项检查前保存屏幕快照，检查后恢复，期间暂时摘下回滚缓冲区，不会留下痕迹。
+//! 检查失败返回错误信息而不是 panic，一项失败不影响后面的检查。
+//! 其他模块可以用 register 添加自己的检查
+use crate::task::yield_now;
+use crate::vga_buffer::{Color, ColorCode, Writer, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+use spin::Mutex;
+
+/// 通过 register 添加的检查数量上限
+pub const MAX_CHECKS: usize = 16;
+
+pub type CheckResult = Result<(), String>;
+
+/// 一项检查，run 可以随意改写屏幕，结束后屏幕会被恢复
+#[derive(Clone, Copy)]
+pub struct Check {
+    pub name: &'static str,
+    pub run: fn(&mut Writer) -> CheckResult,
+}
+
+const BUILTIN_CHECKS: &[Check] = &[
+    Check {
+        name: "cell readback",
+        run: check_cell_readback,
+    },
+    Check {
+        name: "color",
+        run: check_color,
+    },
+    Check {
+        name: "scroll",
+        run: check_scroll,
+    },
+    Check {
+        name: "wrap",
+        run: check_wrap,
+    },
+    Check {
+        name: "clear screen",
+        run: check_clear_screen,
+    },
+    Check {
+        name: "non-printable",
+        run: check_non_printable,
+    },
+];
+
+static REGISTERED: Mutex<[Option<Check>; MAX_CHECKS]> = Mutex::new([None; MAX_CHECKS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// 已经有同名的检查
+    Duplicate(&'static str),
+    TableFull,
+}
+
+/// 添加一项检查，名字与已有的检查重复时返回错误
+pub fn register(check: Check) -> Result<(), RegisterError> {
+    let mut registered = REGISTERED.lock();
+    let exists = BUILTIN_CHECKS
+        .iter()
+        .chain(registered.iter().flatten())
+        .any(|existing| existing.name == check.name);
+    if exists {
+        return Err(RegisterError::Duplicate(check.name));
+    }
+    let slot = registered
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(RegisterError::TableFull)?;
+    *slot = Some(check);
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// 在 WRITER 上运行全部检查并打印结果
+pub fn run() -> Summary {
+    use x86_64::instructions::interrupts;
+
+    let registered = *REGISTERED.lock();
+    let checks = BUILTIN_CHECKS.iter().chain(registered.iter().flatten());
+    interrupts::without_interrupts(|| run_checks(&mut WRITER.lock(), checks))
+}
+
+/// 与 run 相同，但每项检查单独获取 WRITER 的锁，检查之间用 yield_now 让出执行权，
+/// 供在执行器中运行的调用者使用，其他任务在检查之间照常运行
+pub async fn run_async() -> Summary {
+    use x86_64::instructions::interrupts;
+
+    let registered = *REGISTERED.lock();
+    let mut summary = interrupts::without_interrupts(|| begin(&mut WRITER.lock()));
+    for check in BUILTIN_CHECKS.iter().chain(registered.iter().flatten()) {
+        interrupts::without_interrupts(|| run_check(&mut WRITER.lock(), check, &mut summary));
+        yield_now().await;
+    }
+    interrupts::without_interrupts(|| finish(&mut WRITER.lock(), summary));
+    summary
+}
+
+fn run_checks<'a>(writer: &mut Writer, checks: impl Iterator<Item = &'a Check>) -> Summary {
+    let mut summary = begin(writer);
+    for check in checks {
+        run_check(writer, check, &mut summary);
+    }
+    finish(writer, summary);
+    summary
+}
+
+/// 回到回滚的底部并打印标题，返回空的汇总
+fn begin(writer: &mut Writer) -> Summary {
+    writer.snap_to_bottom();
+    writer.clear_highlight();
+    // 报告从新的一行开始
+    if writer.column() != 0 {
+        writer.new_line();
+    }
+    let _ = writeln!(writer, "self-test:");
+    Summary {
+        passed: 0,
+        failed: 0,
+    }
+}
+
+fn run_check(writer: &mut Writer, check: &Check, summary: &mut Summary) {
+    let snapshot = writer.snapshot();
+    let result = writer.without_scrollback(|writer| (check.run)(writer));
+    writer.restore(&snapshot);
+
+    let color = writer.color_code();
+    match result {
+        Ok(()) => {
+            summary.passed += 1;
+            writer.set_color(Color::LightGreen, Color::Black);
+            let _ = write!(writer, "  [PASS]");
+            writer.set_color_code(color);
+            let _ = writeln!(writer, " {}", check.name);
+        }
+        Err(message) => {
+            summary.failed += 1;
+            writer.set_color(Color::LightRed, Color::Black);
+            let _ = write!(writer, "  [FAIL]");
+            writer.set_color_code(color);
+            let _ = writeln!(writer, " {}: {}", check.name, message);
+        }
+    }
+}
+
+fn finish(writer: &mut Writer, summary: Summary) {
+    let _ = writeln!(
+        writer,
+        "self-test: {} passed, {} failed",
+        summary.passed, summary.failed
+    );
+}
+
+/// 比较单元格中的字符，不同时返回带坐标的错误信息
+fn expect_char(writer: &Writer, row: usize, col: usize, expected: u8) -> CheckResult {
+    let (actual, _) = writer.read_char(row, col);
+    if actual != expected {
+        return Err(format!(
+            "({}, {}) is {:#04x}, expected {:#04x}",
+            row, col, actual, expected
+        ));
+    }
+    Ok(())
+}
+
+fn expect_text(writer: &Writer, row: usize, text: &str) -> CheckResult {
+    for (col, byte) in text.bytes().enumerate() {
+        expect_char(writer, row, col, byte)?;
+    }
+    Ok(())
+}
+
+fn check_cell_readback(writer: &mut Writer) -> CheckResult {
+    let cells = [
+        (0, 0, b'A', ColorCode::new(Color::White, Color::Blue)),
+        (
+            BUFFER_HEIGHT - 1,
+            BUFFER_WIDTH - 1,
+            b'Z',
+            ColorCode::new(Color::Black, Color::LightGray),
+        ),
+        (12, 40, 0xdb, ColorCode::new(Color::Pink, Color::Brown)),
+    ];
+    for &(row, col, byte, color) in &cells {
+        writer.put_char(row, col, byte, color);
+    }
+    for &(row, col, byte, color) in &cells {
+        if writer.read_char(row, col) != (byte, color) {
+            return Err(format!("({}, {}) did not read back", row, col));
+        }
+    }
+    Ok(())
+}
+
+fn check_color(writer: &mut Writer) -> CheckResult {
+    writer.clear_screen();
+    writer.set_color(Color::Cyan, Color::Magenta);
+    writer.write_byte(b'c');
+    let expected = ColorCode::new(Color::Cyan, Color::Magenta);
+    if writer.read_char(BUFFER_HEIGHT - 1, 0) != (b'c', expected) {
+        return Err(String::from("written cell does not use the current color"));
+    }
+    Ok(())
+}
+
+fn check_scroll(writer: &mut Writer) -> CheckResult {
+    const LINES: usize = BUFFER_HEIGHT + 5;
+
+    writer.clear_screen();
+    for line in 0..LINES {
+        let _ = writeln!(writer, "line {}", line);
+    }
+    // 最后一个换行后光标在空白的最底行，其上依次是最后打印的各行
+    for row in 0..BUFFER_HEIGHT - 1 {
+        let line = LINES - (BUFFER_HEIGHT - 1) + row;
+        expect_text(writer, row, &format!("line {} ", line))?;
+    }
+    expect_text(writer, BUFFER_HEIGHT - 1, "        ")
+}
+
+fn check_wrap(writer: &mut Writer) -> CheckResult {
+    writer.clear_screen();
+    for _ in 0..BUFFER_WIDTH {
+        writer.write_byte(b'a');
+    }
+    writer.write_byte(b'b');
+    expect_char(writer, BUFFER_HEIGHT - 2, 0, b'a')?;
+    expect_char(writer, BUFFER_HEIGHT - 2, BUFFER_WIDTH - 1, b'a')?;
+    expect_text(writer, BUFFER_HEIGHT - 1, "b ")
+}
+
+fn check_clear_screen(writer: &mut Writer) -> CheckResult {
+    writer.write_string("dirty\ncells");
+    writer.clear_screen();
+    for row in 0..BUFFER_HEIGHT {
+        for col in 0..BUFFER_WIDTH {
+            expect_char(writer, row, col, b' ')?;
+        }
+    }
+    // 光标回到行首
+    writer.write_byte(b'x');
+    expect_char(writer, BUFFER_HEIGHT - 1, 0, b'x')
+}
+
+fn check_non_printable(writer: &mut Writer) -> CheckResult {
+    writer.clear_screen();
+    writer.write_string("a\x01b");
+    expect_text(writer, BUFFER_HEIGHT - 1, "a")?;
+    expect_char(writer, BUFFER_HEIGHT - 1, 1, 0xfe)?;
+    expect_char(writer, BUFFER_HEIGHT - 1, 2, b'b')
+}
+
+#[cfg(test)]
+use crate::vga_buffer::TestWriter;
+
+#[test_case]
+fn test_builtin_checks_pass_and_restore_screen() {
+    let mut writer = TestWriter::with_scrollback(64);
+    writer.write_string("keep me");
+    let summary = run_checks(&mut writer, BUILTIN_CHECKS.iter());
+    assert_eq!(
+        summary,
+        Summary {
+            passed: BUILTIN_CHECKS.len(),
+            failed: 0
+        }
+    );
+    // 原来的内容还在，只是被报告推了上去：报告前的换行、标题、每项一行、汇总
+    let report_lines = 1 + 1 + BUILTIN_CHECKS.len() + 1;
+    expect_text(&writer, BUFFER_HEIGHT - 1 - report_lines, "keep me").unwrap();
+    // 检查期间的输出没有进入历史，滚出屏幕的只有报告推上去的行
+    assert_eq!(writer.history_len(), report_lines);
+}
+
+#[test_case]
+fn test_failing_check_is_reported() {
+    fn always_fails(writer: &mut Writer) -> CheckResult {
+        writer.write_string("garbage");
+        Err(String::from("broken"))
+    }
+
+    let checks = [
+        Check {
+            name: "broken",
+            run: always_fails,
+        },
+        BUILTIN_CHECKS[0],
+    ];
+    let mut writer = TestWriter::new();
+    let summary = run_checks(&mut writer, checks.iter());
+    assert_eq!(
+        summary,
+        Summary {
+            passed: 1,
+            failed: 1
+        }
+    );
+    // 从下往上依次是空行、汇总、通过的一项、失败的一项
+    expect_text(&writer, BUFFER_HEIGHT - 4, "  [FAIL] broken: broken").unwrap();
+}
+
+#[test_case]
+fn test_register_rejects_duplicate_names() {
+    fn nothing(_: &mut Writer) -> CheckResult {
+        Ok(())
+    }
+
+    assert_eq!(
+        register(Check {
+            name: "wrap",
+            run: nothing,
+        }),
+        Err(RegisterError::Duplicate("wrap"))
+    );
+}
+
+#[test_case]
+fn test_run_async_yields_between_checks() {
+    use crate::task::executor::Executor;
+    use crate::task::Task;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static OTHER_POLLS: AtomicUsize = AtomicUsize::new(0);
+    static SUMMARY: Mutex<Option<Summary>> = Mutex::new(None);
+
+    async fn self_test() {
+        *SUMMARY.lock() = Some(run_async().await);
+        DONE.store(true, Ordering::SeqCst);
+    }
+
+    async fn other() {
+        while !DONE.load(Ordering::SeqCst) {
+            OTHER_POLLS.fetch_add(1, Ordering::SeqCst);
+            yield_now().await;
+        }
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(self_test()));
+    executor.spawn(Task::new(other()));
+    assert_eq!(executor.run_until_idle(), 0);
+    let summary = SUMMARY.lock().unwrap();
+    assert_eq!(summary.failed, 0);
+    assert!(summary.passed >= BUILTIN_CHECKS.len());
+    // 每项检查之后都轮到了另一个任务
+    assert!(OTHER_POLLS.load(Ordering::SeqCst) >= summary.passed);
+}
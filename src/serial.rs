@@ -0,0 +1,58 @@
+//! 串口输出
+//! QEMU 可以把 COM1 (0x3F8) 重定向到宿主机的 stdio，测试结果通过它打印到终端
+use lazy_static::lazy_static;
+use spin::Mutex;
+use uart_16550::SerialPort;
+
+lazy_static! {
+    pub static ref SERIAL1: Mutex<SerialPort> = {
+        // 0x3F8 是第一个串口（COM1）的标准端口号
+        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        serial_port.init();
+        Mutex::new(serial_port)
+    };
+}
+
+#[doc(hidden)]
+pub fn _print(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        SERIAL1
+            .lock()
+            .write_fmt(args)
+            .expect("Printing to serial failed");
+    });
+}
+
+/// 以 hexdump -C 的格式把 bytes 输出到串口，base 是第一个字节显示的地址，格式与 Writer::hexdump 相同
+pub fn serial_hexdump(base: u64, bytes: &[u8]) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        crate::hexdump::for_each_line(base, bytes, |line| {
+            serial.write_str(line).expect("Printing to serial failed");
+            serial.write_char('\n').expect("Printing to serial failed");
+        });
+    });
+}
+
+/// 通过串口打印，用法同 print!
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => {
+        $crate::serial::_print(format_args!($($arg)*));
+    };
+}
+
+/// 通过串口打印并换行，用法同 println!
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(
+        concat!($fmt, "\n"), $($arg)*));
+}
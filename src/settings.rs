@@ -0,0 +1,603 @@
+//! 保存在磁盘上的设置
+//! 配色、loglevel、输出目标、按键重复和屏保超时可以用 shell 的 set 命令修改、save 命令保存，
+//! 下次启动时在 ata 初始化之后读出并应用。命令行给出的同名选项（见 config::Given）优先，
+//! 这次启动中不会被保存的值覆盖；set 修改的值总是立即生效。
+//!
+//! 设置放在主盘紧接崩溃记录保留区之后的一个扇区（LBA）中，格式如下，数字都是小端：
+//!
+//! | 偏移 | 长度 | 内容                                   |
+//! |------|------|----------------------------------------|
+//! | 0    | 8    | MAGIC                                  |
+//! | 8    | 2    | 格式版本 VERSION                       |
+//! | 10   | 2    | 保留，为 0                             |
+//! | 12   | 16   | 配色的名字，不足的部分用 0 填充        |
+//! | 28   | 1    | loglevel                               |
+//! | 29   | 1    | 输出目标：0 vga，1 serial，2 both      |
+//! | 30   | 2    | 按键重复的延迟（毫秒）                 |
+//! | 32   | 1    | 按键重复的速率（每秒次数）             |
+//! | 33   | 1    | 保留，为 0                             |
+//! | 34   | 4    | 屏保超时（秒），0 表示关闭             |
+//! | 508  | 4    | 前 508 字节的 Adler-32 校验和          |
+//!
+//! 扇区全为 0 表示没有保存过；校验和不对、版本不认识或者值无效时使用默认值并报告原因。
+//! 与崩溃记录一样，空白的扇区可能属于分区中的文件系统，只有以 MAGIC 开头时才会写入：
+//! crashlog format 在扇区全为 0 时写入默认设置，之后 save 才能保存
+use crate::ata::{AtaError, SECTOR_SIZE};
+use crate::config::{self, BootConfig, Console, Given, Theme, MAX_LOGLEVEL};
+use crate::crashlog::{self, Disk, PrimaryDisk};
+use crate::keyboard::{self, Typematic};
+use crate::ps2::CommandError;
+use crate::vga_buffer::{Writer, WRITER};
+use crate::{console, log, println, screensaver, time};
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub const MAGIC: [u8; 8] = *b"VMSETTNG";
+pub const VERSION: u16 = 1;
+/// 崩溃记录保留区之后的第一个扇区
+pub const LBA: u32 = crashlog::FIRST_LBA + crashlog::SECTORS as u32;
+const THEME_LEN: usize = 16;
+const CHECKSUM_OFFSET: usize = SECTOR_SIZE - 4;
+
+/// 可以修改和保存的设置项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Theme,
+    Loglevel,
+    Console,
+    RepeatDelay,
+    RepeatRate,
+    Screensaver,
+}
+
+impl Key {
+    pub const ALL: [Key; 6] = [
+        Key::Theme,
+        Key::Loglevel,
+        Key::Console,
+        Key::RepeatDelay,
+        Key::RepeatRate,
+        Key::Screensaver,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Key::Theme => "theme",
+            Key::Loglevel => "loglevel",
+            Key::Console => "console",
+            Key::RepeatDelay => "repeat_delay",
+            Key::RepeatRate => "repeat_rate",
+            Key::Screensaver => "screensaver",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Key> {
+        Key::ALL.into_iter().find(|key| key.name() == name)
+    }
+
+    /// 命令行是否给出了这一项
+    fn given(self, given: Given) -> bool {
+        match self {
+            Key::Theme => given.theme,
+            Key::Loglevel => given.loglevel,
+            Key::Console => given.console,
+            Key::RepeatDelay | Key::RepeatRate | Key::Screensaver => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub theme: Theme,
+    pub loglevel: u8,
+    pub console: Console,
+    pub typematic: Typematic,
+    /// 多少秒没有按键后启动屏保，0 表示关闭
+    pub screensaver_secs: u32,
+}
+
+impl Settings {
+    pub const DEFAULT: Settings = Settings {
+        theme: BootConfig::DEFAULT.theme,
+        loglevel: BootConfig::DEFAULT.loglevel,
+        console: BootConfig::DEFAULT.console,
+        typematic: Typematic::DEFAULT,
+        screensaver_secs: (screensaver::DEFAULT_TIMEOUT_TICKS / time::TIMER_FREQUENCY_HZ as u64)
+            as u32,
+    };
+
+    /// 按 set 命令的写法修改一项，值无效时不修改
+    pub fn set(&mut self, key: Key, value: &str) -> Result<(), SetError> {
+        let bad_value = SetError::BadValue(key);
+        match key {
+            Key::Theme => self.theme = Theme::from_name(value).ok_or(bad_value)?,
+            Key::Loglevel => match value.parse() {
+                Ok(level) if level <= MAX_LOGLEVEL => self.loglevel = level,
+                _ => return Err(bad_value),
+            },
+            Key::Console => self.console = Console::from_name(value).ok_or(bad_value)?,
+            Key::RepeatDelay => {
+                let delay = value.parse().map_err(|_| bad_value)?;
+                self.typematic = Typematic::new(delay, self.typematic.rate()).ok_or(bad_value)?;
+            }
+            Key::RepeatRate => {
+                let rate = value.parse().map_err(|_| bad_value)?;
+                self.typematic =
+                    Typematic::new(self.typematic.delay_ms(), rate).ok_or(bad_value)?;
+            }
+            Key::Screensaver => self.screensaver_secs = value.parse().map_err(|_| bad_value)?,
+        }
+        Ok(())
+    }
+
+    /// 按 set 命令的写法写出一项的值
+    pub fn write_value(&self, key: Key, out: &mut impl fmt::Write) -> fmt::Result {
+        match key {
+            Key::Theme => out.write_str(self.theme.name()),
+            Key::Loglevel => write!(out, "{}", self.loglevel),
+            Key::Console => out.write_str(self.console.name()),
+            Key::RepeatDelay => write!(out, "{}", self.typematic.delay_ms()),
+            Key::RepeatRate => write!(out, "{}", self.typematic.rate()),
+            Key::Screensaver => write!(out, "{}", self.screensaver_secs),
+        }
+    }
+
+    /// 编码为一个扇区
+    pub fn encode(&self) -> [u8; SECTOR_SIZE] {
+        let mut sector = [0; SECTOR_SIZE];
+        sector[..8].copy_from_slice(&MAGIC);
+        sector[8..10].copy_from_slice(&VERSION.to_le_bytes());
+        let theme = self.theme.name().as_bytes();
+        sector[12..12 + theme.len()].copy_from_slice(theme);
+        sector[28] = self.loglevel;
+        sector[29] = match self.console {
+            Console::Vga => 0,
+            Console::Serial => 1,
+            Console::Both => 2,
+        };
+        sector[30..32].copy_from_slice(&self.typematic.delay_ms().to_le_bytes());
+        sector[32] = self.typematic.rate();
+        sector[34..38].copy_from_slice(&self.screensaver_secs.to_le_bytes());
+        let sum = crashlog::checksum(&sector[..CHECKSUM_OFFSET]);
+        sector[CHECKSUM_OFFSET..].copy_from_slice(&sum.to_le_bytes());
+        sector
+    }
+
+    /// 解码一个扇区，全为 0 时返回 Ok(None)
+    pub fn decode(sector: &[u8; SECTOR_SIZE]) -> Result<Option<Settings>, SettingsError> {
+        if sector.iter().all(|&byte| byte == 0) {
+            return Ok(None);
+        }
+        if !sector.starts_with(&MAGIC) {
+            return Err(SettingsError::NotOurs);
+        }
+        let expected = read_u32(&sector[CHECKSUM_OFFSET..]);
+        let actual = crashlog::checksum(&sector[..CHECKSUM_OFFSET]);
+        if actual != expected {
+            return Err(SettingsError::BadChecksum { expected, actual });
+        }
+        let version = u16::from_le_bytes([sector[8], sector[9]]);
+        if version != VERSION {
+            return Err(SettingsError::UnsupportedVersion(version));
+        }
+        let theme = &sector[12..12 + THEME_LEN];
+        let theme_len = theme
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(THEME_LEN);
+        let theme = core::str::from_utf8(&theme[..theme_len])
+            .ok()
+            .and_then(Theme::from_name)
+            .ok_or(SettingsError::BadValue(Key::Theme))?;
+        let loglevel = sector[28];
+        if loglevel > MAX_LOGLEVEL {
+            return Err(SettingsError::BadValue(Key::Loglevel));
+        }
+        let console = match sector[29] {
+            0 => Console::Vga,
+            1 => Console::Serial,
+            2 => Console::Both,
+            _ => return Err(SettingsError::BadValue(Key::Console)),
+        };
+        let delay_ms = u16::from_le_bytes([sector[30], sector[31]]);
+        let typematic = Typematic::new(delay_ms, sector[32])
+            .ok_or(SettingsError::BadValue(Key::RepeatDelay))?;
+        Ok(Some(Settings {
+            theme,
+            loglevel,
+            console,
+            typematic,
+            screensaver_secs: read_u32(&sector[34..38]),
+        }))
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsError {
+    Disk(AtaError),
+    /// 扇区既不是空的也不是设置，可能属于别的数据
+    NotOurs,
+    /// 扇区全为 0，还没有格式化
+    NotFormatted,
+    BadChecksum {
+        expected: u32,
+        actual: u32,
+    },
+    /// 由其他版本的内核保存
+    UnsupportedVersion(u16),
+    /// 校验和正确但某一项的值无效
+    BadValue(Key),
+}
+
+impl From<AtaError> for SettingsError {
+    fn from(error: AtaError) -> Self {
+        SettingsError::Disk(error)
+    }
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Disk(error) => write!(f, "disk: {}", error),
+            SettingsError::NotOurs => write!(f, "sector {} holds other data", LBA),
+            SettingsError::NotFormatted => {
+                write!(f, "sector {} not formatted (crashlog format)", LBA)
+            }
+            SettingsError::BadChecksum { expected, actual } => write!(
+                f,
+                "checksum mismatch (expected {:#010x}, got {:#010x})",
+                expected, actual
+            ),
+            SettingsError::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {} (expected {})", version, VERSION)
+            }
+            SettingsError::BadValue(key) => write!(f, "bad value for {}", key.name()),
+        }
+    }
+}
+
+/// set 命令的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetError {
+    UnknownKey,
+    BadValue(Key),
+    /// 值已经修改，但键盘没有接受按键重复的设置
+    Keyboard(CommandError),
+}
+
+/// 启动时读出的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Loaded {
+    Saved,
+    Missing,
+    Failed(SettingsError),
+}
+
+fn load_with(disk: &mut impl Disk) -> Result<Option<Settings>, SettingsError> {
+    let mut sector = [0; SECTOR_SIZE];
+    disk.read(LBA, &mut sector)?;
+    Settings::decode(&sector)
+}
+
+/// 读出保存的设置，没有保存过或者无法使用时返回默认值
+fn load_or_default(disk: &mut impl Disk) -> (Settings, Loaded) {
+    match load_with(disk) {
+        Ok(Some(settings)) => (settings, Loaded::Saved),
+        Ok(None) => (Settings::DEFAULT, Loaded::Missing),
+        Err(error) => (Settings::DEFAULT, Loaded::Failed(error)),
+    }
+}
+
+fn save_with(disk: &mut impl Disk, settings: &Settings) -> Result<(), SettingsError> {
+    let mut sector = [0; SECTOR_SIZE];
+    disk.read(LBA, &mut sector)?;
+    if !sector.starts_with(&MAGIC) {
+        return Err(if sector.iter().all(|&byte| byte == 0) {
+            SettingsError::NotFormatted
+        } else {
+            SettingsError::NotOurs
+        });
+    }
+    disk.write(LBA, &settings.encode())?;
+    Ok(())
+}
+
+/// 扇区全为 0 时写入默认设置；已经格式化过时什么也不做
+fn format_with(disk: &mut impl Disk) -> Result<(), SettingsError> {
+    let mut sector = [0; SECTOR_SIZE];
+    disk.read(LBA, &mut sector)?;
+    if sector.starts_with(&MAGIC) {
+        return Ok(());
+    }
+    if sector.iter().any(|&byte| byte != 0) {
+        return Err(SettingsError::NotOurs);
+    }
+    disk.write(LBA, &Settings::DEFAULT.encode())?;
+    Ok(())
+}
+
+/// 命令行给出的项以启动配置为准，其他的项使用保存的值
+fn effective(stored: &Settings, boot: &BootConfig, given: Given) -> Settings {
+    let mut settings = *stored;
+    if given.theme {
+        settings.theme = boot.theme;
+    }
+    if given.loglevel {
+        settings.loglevel = boot.loglevel;
+    }
+    if given.console {
+        settings.console = boot.console;
+    }
+    settings
+}
+
+/// 让一项设置生效；重复的延迟和速率一起设置
+fn apply(settings: &Settings, key: Key, writer: &mut Writer) -> Result<(), CommandError> {
+    match key {
+        Key::Theme => {
+            let (foreground, background) = settings.theme.colors();
+            writer.set_color(foreground, background);
+        }
+        Key::Loglevel => log::set_loglevel(settings.loglevel),
+        Key::Console => console::set_output(settings.console),
+        Key::RepeatDelay | Key::RepeatRate => keyboard::set_typematic(settings.typematic)?,
+        Key::Screensaver => match settings.screensaver_secs {
+            0 => screensaver::disable(),
+            secs => screensaver::set_timeout(secs as u64 * time::TIMER_FREQUENCY_HZ as u64),
+        },
+    }
+    Ok(())
+}
+
+struct State {
+    /// 保存到磁盘上的值
+    stored: Settings,
+    /// 正在使用的值
+    active: Settings,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    stored: Settings::DEFAULT,
+    active: Settings::DEFAULT,
+});
+
+/// 启动时应用的项，RepeatRate 与 RepeatDelay 一起设置
+const APPLY_AT_BOOT: [Key; 5] = [
+    Key::Theme,
+    Key::Loglevel,
+    Key::Console,
+    Key::RepeatDelay,
+    Key::Screensaver,
+];
+
+/// 读出保存的设置并应用命令行没有给出的项；在 ata::init 和 config::init 之后调用
+pub fn init() {
+    let (stored, loaded) = load_or_default(&mut PrimaryDisk);
+    let given = config::given();
+    let active = effective(&stored, config::get(), given);
+    *STATE.lock() = State { stored, active };
+    let result = interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        APPLY_AT_BOOT
+            .into_iter()
+            .filter(|key| !key.given(given))
+            .try_for_each(|key| apply(&active, key, &mut writer))
+    });
+    match loaded {
+        Loaded::Saved => {}
+        Loaded::Missing => println!("settings: none saved, using defaults"),
+        // 扇区属于别人时不使用，也不必提示
+        Loaded::Failed(SettingsError::NotOurs) => {}
+        Loaded::Failed(error) => println!("settings: {}, using defaults", error),
+    }
+    if let Err(error) = result {
+        println!("settings: keyboard repeat: {:?}", error);
+    }
+}
+
+/// 修改一项设置并立即生效（即使命令行给出了这一项），save 之后下次启动也会使用
+pub fn set(key: &str, value: &str, writer: &mut Writer) -> Result<(), SetError> {
+    let key = Key::from_name(key).ok_or(SetError::UnknownKey)?;
+    let mut state = STATE.lock();
+    let mut active = state.active;
+    active.set(key, value)?;
+    state.stored.set(key, value)?;
+    state.active = active;
+    apply(&active, key, writer).map_err(SetError::Keyboard)
+}
+
+/// 正在使用的设置
+pub fn current() -> Settings {
+    STATE.lock().active
+}
+
+/// 把 set 修改过的和从磁盘读出的设置写回磁盘，命令行给出的值不会被保存
+pub fn save() -> Result<(), SettingsError> {
+    let stored = STATE.lock().stored;
+    save_with(&mut PrimaryDisk, &stored)
+}
+
+/// 准备设置所在的扇区，由 crashlog format 调用
+pub fn format() -> Result<(), SettingsError> {
+    format_with(&mut PrimaryDisk)
+}
+
+/// 内存中的磁盘，只有设置所在的扇区
+#[cfg(test)]
+struct FakeDisk {
+    sector: [u8; SECTOR_SIZE],
+    writes: usize,
+}
+
+#[cfg(test)]
+impl FakeDisk {
+    fn new() -> Self {
+        FakeDisk {
+            sector: [0; SECTOR_SIZE],
+            writes: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Disk for FakeDisk {
+    fn read(&mut self, lba: u32, buf: &mut [u8]) -> Result<(), AtaError> {
+        if lba != LBA || buf.len() != SECTOR_SIZE {
+            return Err(AtaError::NotIdentified);
+        }
+        buf.copy_from_slice(&self.sector);
+        Ok(())
+    }
+
+    fn write(&mut self, lba: u32, buf: &[u8]) -> Result<(), AtaError> {
+        if lba != LBA || buf.len() != SECTOR_SIZE {
+            return Err(AtaError::NotIdentified);
+        }
+        self.sector.copy_from_slice(buf);
+        self.writes += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn custom() -> Settings {
+    let mut settings = Settings::DEFAULT;
+    for (key, value) in [
+        ("theme", "green"),
+        ("loglevel", "7"),
+        ("console", "both"),
+        ("repeat_delay", "250"),
+        ("repeat_rate", "30"),
+        ("screensaver", "0"),
+    ] {
+        settings.set(Key::from_name(key).unwrap(), value).unwrap();
+    }
+    settings
+}
+
+#[test_case]
+fn test_round_trip_on_fake_disk() {
+    let mut disk = FakeDisk::new();
+    assert_eq!(
+        load_or_default(&mut disk),
+        (Settings::DEFAULT, Loaded::Missing)
+    );
+
+    let settings = custom();
+    assert_ne!(settings, Settings::DEFAULT);
+    // 格式化之前不写入
+    assert_eq!(
+        save_with(&mut disk, &settings),
+        Err(SettingsError::NotFormatted)
+    );
+    assert_eq!(disk.writes, 0);
+    format_with(&mut disk).unwrap();
+    assert_eq!(
+        load_or_default(&mut disk),
+        (Settings::DEFAULT, Loaded::Saved)
+    );
+    save_with(&mut disk, &settings).unwrap();
+    assert!(disk.sector.starts_with(&MAGIC));
+    assert_eq!(load_or_default(&mut disk), (settings, Loaded::Saved));
+
+    // 无效的值不修改设置
+    let mut changed = settings;
+    assert_eq!(
+        changed.set(Key::Loglevel, "8"),
+        Err(SetError::BadValue(Key::Loglevel))
+    );
+    assert_eq!(
+        changed.set(Key::RepeatDelay, "300"),
+        Err(SetError::BadValue(Key::RepeatDelay))
+    );
+    assert_eq!(changed, settings);
+
+    let mut text = alloc::string::String::new();
+    settings.write_value(Key::Console, &mut text).unwrap();
+    assert_eq!(text, "both");
+}
+
+#[test_case]
+fn test_checksum_and_foreign_data_rejected() {
+    let mut disk = FakeDisk::new();
+    format_with(&mut disk).unwrap();
+    save_with(&mut disk, &custom()).unwrap();
+    disk.sector[28] ^= 1;
+    let (settings, loaded) = load_or_default(&mut disk);
+    assert_eq!(settings, Settings::DEFAULT);
+    assert!(matches!(
+        loaded,
+        Loaded::Failed(SettingsError::BadChecksum { .. })
+    ));
+    // 校验和损坏的设置可以被覆盖
+    save_with(&mut disk, &custom()).unwrap();
+
+    let mut foreign = FakeDisk::new();
+    foreign.sector[100] = 1;
+    assert_eq!(load_with(&mut foreign), Err(SettingsError::NotOurs));
+    assert_eq!(
+        save_with(&mut foreign, &custom()),
+        Err(SettingsError::NotOurs)
+    );
+    assert_eq!(format_with(&mut foreign), Err(SettingsError::NotOurs));
+    assert_eq!(foreign.writes, 0);
+}
+
+#[test_case]
+fn test_version_mismatch_falls_back() {
+    let mut sector = custom().encode();
+    sector[8..10].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    let sum = crashlog::checksum(&sector[..CHECKSUM_OFFSET]);
+    sector[CHECKSUM_OFFSET..].copy_from_slice(&sum.to_le_bytes());
+    let mut disk = FakeDisk::new();
+    disk.sector = sector;
+    assert_eq!(
+        load_or_default(&mut disk),
+        (
+            Settings::DEFAULT,
+            Loaded::Failed(SettingsError::UnsupportedVersion(VERSION + 1))
+        )
+    );
+
+    // 校验和正确但值无效
+    let mut sector = custom().encode();
+    sector[12..12 + THEME_LEN].fill(0);
+    sector[12..18].copy_from_slice(b"purple");
+    let sum = crashlog::checksum(&sector[..CHECKSUM_OFFSET]);
+    sector[CHECKSUM_OFFSET..].copy_from_slice(&sum.to_le_bytes());
+    assert_eq!(
+        Settings::decode(&sector),
+        Err(SettingsError::BadValue(Key::Theme))
+    );
+}
+
+#[test_case]
+fn test_command_line_takes_precedence() {
+    let mut disk = FakeDisk::new();
+    format_with(&mut disk).unwrap();
+    save_with(&mut disk, &custom()).unwrap();
+    let (stored, _) = load_or_default(&mut disk);
+
+    // 没有给出的项使用保存的值
+    let (boot, given) = config::parse_given("quiet", |_| {});
+    assert_eq!(effective(&stored, &boot, given), stored);
+
+    let (boot, given) = config::parse_given("theme=light loglevel=2 console=bogus", |_| {});
+    let active = effective(&stored, &boot, given);
+    assert_eq!(active.theme, Theme::Light);
+    assert_eq!(active.loglevel, 2);
+    assert_eq!(active.console, Console::Both);
+    assert_eq!(active.typematic, stored.typematic);
+    assert!(Key::Theme.given(given) && !Key::Console.given(given));
+}
@@ -0,0 +1,860 @@
+//! 内核 shell
+//! 打印提示符，读取一行，按空白拆分为命令名和参数后查命令表执行，如此循环
+//!
+//! 解析不分配内存，参数个数有固定上限；内置命令放在常量表中，
+//! 其他模块可以在运行时通过 register 注册自己的命令。
+//! 大多数命令在持有 WRITER 的锁时执行；输出很长的命令是异步的，
+//! 由 shell 任务在不持有锁时 await，期间其他任务照常运行
+use crate::ata::{self, Drive};
+use crate::console::{self, console_inputs, read_line_with_history, sink, History, OutputMode};
+use crate::log::Level;
+use crate::pci::{self, Bar};
+use crate::settings::{self, Key, SetError};
+use crate::task::yield_now;
+use crate::vga_buffer::{self, Color, Writer, WRITER};
+use crate::{
+    allocator, crashlog, diag, eprintln, fs, hexdump, net, power, print, println, selftest, stack,
+    time, vga_mode,
+};
+use alloc::boxed::Box;
+use core::fmt::{self, Write};
+use core::future::Future;
+use core::pin::Pin;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub const PROMPT: &str = "> ";
+/// 一行中除命令名以外最多的参数个数
+pub const MAX_ARGS: usize = 8;
+/// 通过 register 注册的命令数量上限
+pub const MAX_REGISTERED_COMMANDS: usize = 16;
+const LINE_CAPACITY: usize = 128;
+/// 保存的历史命令条数
+const HISTORY_LENGTH: usize = 32;
+/// cat 和 hexdump 每写出这么多行让出一次执行权
+const LINES_PER_YIELD: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    TooManyArgs,
+}
+
+/// execute 无法执行命令的原因，由调用者负责报告
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellError<'a> {
+    Parse(ParseError),
+    UnknownCommand(&'a str),
+}
+
+impl fmt::Display for ShellError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShellError::Parse(ParseError::TooManyArgs) => {
+                write!(f, "too many arguments (max {})", MAX_ARGS)
+            }
+            ShellError::UnknownCommand(name) => write!(f, "unknown command: {}", name),
+        }
+    }
+}
+
+/// 拆分后的一行输入，命令名和参数都借用输入行
+#[derive(Debug)]
+pub struct CommandLine<'a> {
+    name: &'a str,
+    args: [&'a str; MAX_ARGS],
+    argc: usize,
+}
+
+impl<'a> CommandLine<'a> {
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn args(&self) -> &[&'a str] {
+        &self.args[..self.argc]
+    }
+}
+
+/// 按空白拆分一行，开头、结尾和连续的空白都会被忽略，空行返回 Ok(None)
+pub fn parse(line: &str) -> Result<Option<CommandLine<'_>>, ParseError> {
+    let mut words = line.split_ascii_whitespace();
+    let Some(name) = words.next() else {
+        return Ok(None);
+    };
+    let mut command = CommandLine {
+        name,
+        args: [""; MAX_ARGS],
+        argc: 0,
+    };
+    for word in words {
+        if command.argc == MAX_ARGS {
+            return Err(ParseError::TooManyArgs);
+        }
+        command.args[command.argc] = word;
+        command.argc += 1;
+    }
+    Ok(Some(command))
+}
+
+/// 命令的输出直接写到调用者给出的 Writer 上，clear、color 等命令也作用于它
+pub type CommandFn = fn(args: &[&str], out: &mut Writer);
+
+/// 异步命令返回的 Future，借用输入行
+pub type CommandFuture<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+/// 异步命令：不持有任何锁，用 print! 输出，可以用 yield_now 让出执行权
+pub type AsyncCommandFn = for<'a> fn(command: CommandLine<'a>) -> CommandFuture<'a>;
+
+/// 命令的执行方式
+#[derive(Clone, Copy)]
+pub enum Run {
+    /// 在持有 WRITER 的锁、关闭中断时执行
+    Locked(CommandFn),
+    /// 由调用者在释放锁之后 await，用于输出很长或者需要等待的命令
+    Async(AsyncCommandFn),
+}
+
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    /// help 中显示的一行说明
+    pub description: &'static str,
+    pub run: Run,
+}
+
+const BUILTINS: &[Command] = &[
+    Command {
+        name: "help",
+        description: "list available commands",
+        run: Run::Locked(help),
+    },
+    Command {
+        name: "clear",
+        description: "clear the screen",
+        run: Run::Locked(clear),
+    },
+    Command {
+        name: "reset",
+        description: "restore default colors and modes, then clear the screen",
+        run: Run::Locked(reset),
+    },
+    Command {
+        name: "echo",
+        description: "print the arguments",
+        run: Run::Locked(echo),
+    },
+    Command {
+        name: "color",
+        description: "color <fg> <bg>: set the text color",
+        run: Run::Locked(color),
+    },
+    Command {
+        name: "colors",
+        description: "show every color name in its own color",
+        run: Run::Locked(colors),
+    },
+    Command {
+        name: "uptime",
+        description: "time since the timer was started",
+        run: Run::Locked(uptime),
+    },
+    Command {
+        name: "ticks",
+        description: "raw timer tick counter",
+        run: Run::Locked(ticks),
+    },
+    Command {
+        name: "disk",
+        description: "disk [read <lba>]: list drives or dump a sector",
+        run: Run::Locked(disk),
+    },
+    Command {
+        name: "crashlog",
+        description: "crashlog [show|save|clear|format]: crash record kept on disk",
+        run: Run::Locked(crashlog),
+    },
+    Command {
+        name: "set",
+        description: "set [<key> <value>]: show or change a setting (see save)",
+        run: Run::Locked(set),
+    },
+    Command {
+        name: "save",
+        description: "write the settings to disk for the next boot",
+        run: Run::Locked(save),
+    },
+    Command {
+        name: "ls",
+        description: "list files in the ramfs",
+        run: Run::Locked(ls),
+    },
+    Command {
+        name: "cat",
+        description: "cat <name>: print a file from the ramfs",
+        run: Run::Async(cat),
+    },
+    Command {
+        name: "hexdump",
+        description: "hexdump <name>: dump a file from the ramfs in hex",
+        run: Run::Async(hexdump_file),
+    },
+    Command {
+        name: "lspci",
+        description: "list PCI devices found at boot",
+        run: Run::Locked(lspci),
+    },
+    Command {
+        name: "netinfo",
+        description: "network card, MAC address and link state",
+        run: Run::Locked(netinfo),
+    },
+    Command {
+        name: "stack",
+        description: "peak kernel stack usage",
+        run: Run::Locked(stack),
+    },
+    Command {
+        name: "heap",
+        description: "heap usage",
+        run: Run::Locked(heap),
+    },
+    Command {
+        name: "latency",
+        description: "latency [reset]: timer interrupt latency histogram",
+        run: Run::Locked(latency),
+    },
+    Command {
+        name: "sinklevel",
+        description: "sinklevel <vga|serial> <level>: lowest log level a sink shows",
+        run: Run::Locked(sinklevel),
+    },
+    Command {
+        name: "output",
+        description: "output [vga|serial|both]: where print output goes",
+        run: Run::Locked(output),
+    },
+    Command {
+        name: "vsync",
+        description: "vsync [on|off]: wait for vertical retrace before bulk screen updates",
+        run: Run::Locked(vsync),
+    },
+    Command {
+        name: "selftest",
+        description: "run the console self-test",
+        run: Run::Async(run_selftest),
+    },
+    Command {
+        name: "screendump",
+        description: "screendump [-a]: send the screen text (and colors with -a) to serial",
+        run: Run::Async(screendump),
+    },
+    Command {
+        name: "shutdown",
+        description: "power off the machine",
+        run: Run::Locked(shutdown),
+    },
+    Command {
+        name: "reboot",
+        description: "restart the machine",
+        run: Run::Locked(reboot),
+    },
+];
+
+static REGISTERED: Mutex<[Option<Command>; MAX_REGISTERED_COMMANDS]> =
+    Mutex::new([None; MAX_REGISTERED_COMMANDS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// 同名命令已经存在
+    Duplicate(&'static str),
+    TableFull,
+}
+
+/// 注册一个命令，名称不能与已有命令重复
+pub fn register(command: Command) -> Result<(), RegisterError> {
+    if find(command.name).is_some() {
+        return Err(RegisterError::Duplicate(command.name));
+    }
+    let mut registered = REGISTERED.lock();
+    let slot = registered
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(RegisterError::TableFull)?;
+    *slot = Some(command);
+    Ok(())
+}
+
+fn find(name: &str) -> Option<Command> {
+    BUILTINS
+        .iter()
+        .copied()
+        .chain(REGISTERED.lock().iter().flatten().copied())
+        .find(|command| command.name == name)
+}
+
+/// 解析并执行一行输入，空行什么也不做。
+/// 异步命令不在这里执行，而是返回它的 Future，由调用者释放 out 的锁之后 await
+pub fn execute<'a>(
+    line: &'a str,
+    out: &mut Writer,
+) -> Result<Option<CommandFuture<'a>>, ShellError<'a>> {
+    let Some(command_line) = parse(line).map_err(ShellError::Parse)? else {
+        return Ok(None);
+    };
+    // 先释放命令表的锁再执行，命令中可以调用 help 或 register
+    let command =
+        find(command_line.name()).ok_or(ShellError::UnknownCommand(command_line.name()))?;
+    match command.run {
+        Run::Locked(run) => {
+            run(command_line.args(), out);
+            Ok(None)
+        }
+        Run::Async(run) => Ok(Some(run(command_line))),
+    }
+}
+
+/// shell 任务：从键盘和串口读取命令并执行
+pub async fn run() {
+    let mut keys = console_inputs();
+    let mut buf = [0u8; LINE_CAPACITY];
+    let mut history = History::new(HISTORY_LENGTH);
+    loop {
+        print!("{}", PROMPT);
+        // Ctrl+C 取消输入时直接重新显示提示符
+        let Some(line) = read_line_with_history(&mut keys, &mut buf, &mut history).await else {
+            continue;
+        };
+        let result = interrupts::without_interrupts(|| execute(line, &mut WRITER.lock()));
+        // 异步命令和 eprintln! 都需要获取 WRITER 的锁，必须在上面的锁释放后进行
+        match result {
+            Ok(Some(command)) => command.await,
+            Ok(None) => {}
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+}
+
+fn help(_args: &[&str], out: &mut Writer) {
+    let registered = *REGISTERED.lock();
+    for command in BUILTINS.iter().chain(registered.iter().flatten()) {
+        let _ = writeln!(out, "{:<10} {}", command.name, command.description);
+    }
+}
+
+fn clear(_args: &[&str], out: &mut Writer) {
+    out.clear_screen();
+}
+
+fn reset(_args: &[&str], out: &mut Writer) {
+    out.reset();
+}
+
+fn echo(args: &[&str], out: &mut Writer) {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            out.write_byte(b' ');
+        }
+        out.write_string(arg);
+    }
+    out.write_byte(b'\n');
+}
+
+fn color(args: &[&str], out: &mut Writer) {
+    let [foreground, background] = args else {
+        let _ = writeln!(out, "usage: color <fg> <bg>");
+        return;
+    };
+    match (Color::from_name(foreground), Color::from_name(background)) {
+        (Some(foreground), Some(background)) => out.set_color(foreground, background),
+        (None, _) => {
+            let _ = writeln!(out, "color: unknown color {}", foreground);
+        }
+        (_, None) => {
+            let _ = writeln!(out, "color: unknown color {}", background);
+        }
+    }
+}
+
+fn colors(_args: &[&str], out: &mut Writer) {
+    vga_buffer::color_test(out);
+}
+
+fn uptime(_args: &[&str], out: &mut Writer) {
+    let ms = time::ticks_to_ms(time::ticks());
+    let _ = writeln!(out, "up {}.{:03}s", ms / 1000, ms % 1000);
+}
+
+fn ticks(_args: &[&str], out: &mut Writer) {
+    let _ = writeln!(out, "{}", time::ticks());
+}
+
+fn disk(args: &[&str], out: &mut Writer) {
+    match args {
+        [] => {
+            for drive in [Drive::Master, Drive::Slave] {
+                match ata::identity(drive) {
+                    Some(identity) => {
+                        let _ = writeln!(
+                            out,
+                            "{:?}: {} ({} sectors)",
+                            drive,
+                            identity.model(),
+                            identity.sectors
+                        );
+                    }
+                    None => {
+                        let _ = writeln!(out, "{:?}: none", drive);
+                    }
+                }
+            }
+        }
+        ["read", lba] => {
+            let Ok(lba) = lba.parse::<u32>() else {
+                let _ = writeln!(out, "disk: invalid lba {}", lba);
+                return;
+            };
+            let mut sector = [0; ata::SECTOR_SIZE];
+            match ata::read_sectors(Drive::Master, lba, &mut sector) {
+                Ok(()) => out.hexdump(lba as u64 * ata::SECTOR_SIZE as u64, &sector),
+                Err(error) => {
+                    let _ = writeln!(out, "disk: {}", error);
+                }
+            }
+        }
+        _ => {
+            let _ = writeln!(out, "usage: disk [read <lba>]");
+        }
+    }
+}
+
+fn crashlog(args: &[&str], out: &mut Writer) {
+    let result = match args {
+        [] | ["show"] => crashlog::show(out).map(|found| {
+            if !found {
+                let _ = writeln!(out, "no crash record");
+            }
+        }),
+        ["save"] => crashlog::save("saved from shell"),
+        ["clear"] => crashlog::clear(),
+        // 设置所在的扇区在同一片保留区中，一起格式化
+        ["format"] => crashlog::format().map(|()| {
+            if let Err(error) = settings::format() {
+                let _ = writeln!(out, "settings: {}", error);
+            }
+        }),
+        _ => {
+            let _ = writeln!(out, "usage: crashlog [show|save|clear|format]");
+            return;
+        }
+    };
+    if let Err(error) = result {
+        let _ = writeln!(out, "crashlog: {}", error);
+    }
+}
+
+fn set(args: &[&str], out: &mut Writer) {
+    match args {
+        [] => {
+            let current = settings::current();
+            for key in Key::ALL {
+                let _ = write!(out, "{} = ", key.name());
+                let _ = current.write_value(key, out);
+                out.new_line();
+            }
+        }
+        [key, value] => match settings::set(key, value, out) {
+            Ok(()) => {}
+            Err(SetError::UnknownKey) => {
+                let _ = writeln!(out, "set: unknown key {}", key);
+            }
+            Err(SetError::BadValue(key)) => {
+                let _ = writeln!(out, "set: bad value {:?} for {}", value, key.name());
+            }
+            Err(SetError::Keyboard(error)) => {
+                let _ = writeln!(out, "set: keyboard did not accept it: {:?}", error);
+            }
+        },
+        _ => {
+            let _ = writeln!(out, "usage: set [<key> <value>]");
+        }
+    }
+}
+
+fn save(_args: &[&str], out: &mut Writer) {
+    match settings::save() {
+        Ok(()) => {
+            let _ = writeln!(out, "settings saved");
+        }
+        Err(error) => {
+            let _ = writeln!(out, "save: {}", error);
+        }
+    }
+}
+
+fn ls(_args: &[&str], out: &mut Writer) {
+    for file in fs::list() {
+        let _ = writeln!(out, "{:>8} {}", file.size, file.name);
+    }
+}
+
+/// 按 UTF-8 解码后逐个字符写入，无效的字节和无法显示的字符显示为 0xfe
+fn cat(command: CommandLine) -> CommandFuture {
+    Box::pin(cat_yielding(command))
+}
+
+/// 按 UTF-8 解码后写出，无效的字节序列显示为替代字符
+fn write_lossy(out: &mut Writer, data: &[u8]) {
+    for chunk in data.utf8_chunks() {
+        chunk.valid().chars().for_each(|c| out.write_char(c));
+        if !chunk.invalid().is_empty() {
+            out.write_char(char::REPLACEMENT_CHARACTER);
+        }
+    }
+}
+
+/// 每写 LINES_PER_YIELD 行释放控制台并让出一次执行权
+async fn cat_yielding(command: CommandLine<'_>) {
+    let [name] = command.args() else {
+        println!("usage: cat <name>");
+        return;
+    };
+    let Some(data) = fs::read(name) else {
+        println!("cat: {}: no such file", name);
+        return;
+    };
+    // 在换行符处分段不会切断多字节字符
+    let mut lines = data.split_inclusive(|&byte| byte == b'\n').peekable();
+    while lines.peek().is_some() {
+        interrupts::without_interrupts(|| {
+            let mut out = WRITER.lock();
+            for line in lines.by_ref().take(LINES_PER_YIELD) {
+                write_lossy(&mut out, line);
+            }
+        });
+        yield_now().await;
+    }
+    interrupts::without_interrupts(|| {
+        let mut out = WRITER.lock();
+        if out.column() != 0 {
+            out.new_line();
+        }
+    });
+}
+
+fn hexdump_file(command: CommandLine) -> CommandFuture {
+    Box::pin(async move {
+        let [name] = command.args() else {
+            println!("usage: hexdump <name>");
+            return;
+        };
+        let Some(data) = fs::read(name) else {
+            println!("hexdump: {}: no such file", name);
+            return;
+        };
+        hexdump::for_each_line_yielding(0, data, LINES_PER_YIELD, |line| println!("{}", line))
+            .await;
+    })
+}
+
+fn lspci(_args: &[&str], out: &mut Writer) {
+    let _ = writeln!(out, "address vendor:device class");
+    for device in pci::devices() {
+        let _ = writeln!(
+            out,
+            "{} {:04x}:{:04x}   {:02x}{:02x} {}",
+            device.address,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+            pci::class_name(device.class, device.subclass)
+        );
+        for (index, bar) in device.bars.iter().enumerate() {
+            match bar {
+                Some(Bar::Memory {
+                    address,
+                    size,
+                    prefetchable,
+                    ..
+                }) => {
+                    let prefetch = if *prefetchable { " prefetchable" } else { "" };
+                    let _ = writeln!(
+                        out,
+                        "  BAR{} memory {:#x} size {:#x}{}",
+                        index, address, size, prefetch
+                    );
+                }
+                Some(Bar::Io { port, size }) => {
+                    let _ = writeln!(out, "  BAR{} io {:#x} size {:#x}", index, port, size);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+fn stack(_args: &[&str], out: &mut Writer) {
+    stack::write_usage(out);
+}
+
+fn netinfo(_args: &[&str], out: &mut Writer) {
+    match net::info() {
+        Some(nic) => {
+            let _ = writeln!(out, "{}", nic);
+        }
+        // 启动时已经打印过原因：没有支持的网卡或者初始化失败
+        None => {
+            let _ = writeln!(out, "no network card initialized");
+        }
+    }
+}
+
+fn heap(_args: &[&str], out: &mut Writer) {
+    let _ = writeln!(out, "{}", allocator::heap_stats());
+}
+
+fn latency(args: &[&str], out: &mut Writer) {
+    match args {
+        [] => {
+            let _ = diag::write_latency(out, &diag::latency_histogram());
+        }
+        ["reset"] => diag::reset_latency(),
+        _ => {
+            let _ = writeln!(out, "usage: latency [reset]");
+        }
+    }
+}
+
+fn sinklevel(args: &[&str], out: &mut Writer) {
+    let [sink, level] = args else {
+        let _ = writeln!(out, "usage: sinklevel <vga|serial> <error|warn|info|debug>");
+        return;
+    };
+    let id = match *sink {
+        "vga" => sink::VGA_SINK,
+        "serial" => sink::SERIAL_SINK,
+        _ => {
+            let _ = writeln!(out, "sinklevel: unknown sink {}", sink);
+            return;
+        }
+    };
+    match Level::from_name(level) {
+        Some(level) => {
+            if !console::set_sink_level(id, level) {
+                let _ = writeln!(out, "sinklevel: {} is not registered", sink);
+            }
+        }
+        None => {
+            let _ = writeln!(out, "sinklevel: unknown level {}", level);
+        }
+    }
+}
+
+fn output(args: &[&str], out: &mut Writer) {
+    match args {
+        [] => {}
+        ["vga"] => console::set_output(OutputMode::Vga),
+        ["serial"] => console::set_output(OutputMode::Serial),
+        ["both"] => console::set_output(OutputMode::Both),
+        _ => {
+            let _ = writeln!(out, "usage: output [vga|serial|both]");
+            return;
+        }
+    }
+    let mode = match console::output() {
+        OutputMode::Vga => "vga",
+        OutputMode::Serial => "serial",
+        OutputMode::Both => "both",
+    };
+    let _ = writeln!(out, "output {}", mode);
+}
+
+fn vsync(args: &[&str], out: &mut Writer) {
+    match args {
+        [] => {}
+        ["on"] => vga_mode::set_vsync(true),
+        ["off"] => vga_mode::set_vsync(false),
+        _ => {
+            let _ = writeln!(out, "usage: vsync [on|off]");
+            return;
+        }
+    }
+    let state = if vga_mode::vsync_enabled() {
+        "on"
+    } else {
+        "off"
+    };
+    let _ = writeln!(out, "vsync {}", state);
+}
+
+/// 每项检查之间让出执行权
+fn run_selftest(_command: CommandLine) -> CommandFuture {
+    Box::pin(async {
+        selftest::run_async().await;
+    })
+}
+
+/// 写串口很慢，所以是异步命令：只在复制画面时持有 WRITER 的锁
+fn screendump(command: CommandLine) -> CommandFuture {
+    Box::pin(async move {
+        let attributes = match command.args() {
+            [] => false,
+            ["-a"] => true,
+            _ => {
+                println!("usage: screendump [-a]");
+                return;
+            }
+        };
+        console::screen_dump::dump_screen_to_serial(attributes);
+        println!("screen sent to serial");
+    })
+}
+
+fn shutdown(_args: &[&str], _out: &mut Writer) {
+    power::shutdown();
+}
+
+fn reboot(_args: &[&str], _out: &mut Writer) {
+    power::reboot();
+}
+
+#[cfg(test)]
+use crate::vga_buffer::{ColorCode, TestWriter, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+/// 读取一行屏幕内容并去掉行尾空白
+#[cfg(test)]
+fn screen_line(writer: &Writer, row: usize) -> alloc::string::String {
+    let line: alloc::string::String = (0..BUFFER_WIDTH)
+        .map(|col| writer.read_char(row, col).0 as char)
+        .collect();
+    alloc::string::String::from(line.trim_end())
+}
+
+/// 命令输出以换行结尾，最后一行输出在倒数第二行
+#[cfg(test)]
+fn last_output(writer: &Writer) -> alloc::string::String {
+    screen_line(writer, BUFFER_HEIGHT - 2)
+}
+
+#[test_case]
+fn test_parse_splits_on_whitespace() {
+    let command_line = parse("  echo   hello  world ").unwrap().unwrap();
+    assert_eq!(command_line.name(), "echo");
+    assert_eq!(command_line.args(), ["hello", "world"]);
+
+    let command_line = parse("help").unwrap().unwrap();
+    assert_eq!(command_line.name(), "help");
+    assert!(command_line.args().is_empty());
+}
+
+#[test_case]
+fn test_parse_empty_line() {
+    assert!(parse("").unwrap().is_none());
+    assert!(parse("   \t ").unwrap().is_none());
+}
+
+#[test_case]
+fn test_parse_argument_limit() {
+    let command_line = parse("x 1 2 3 4 5 6 7 8").unwrap().unwrap();
+    assert_eq!(command_line.args().len(), MAX_ARGS);
+    assert_eq!(
+        parse("x 1 2 3 4 5 6 7 8 9").unwrap_err(),
+        ParseError::TooManyArgs
+    );
+}
+
+#[test_case]
+fn test_execute_echo() {
+    let mut writer = TestWriter::new();
+    execute(" echo  a   b ", &mut writer).unwrap();
+    assert_eq!(last_output(&writer), "a b");
+}
+
+#[test_case]
+fn test_async_command_is_returned_not_run() {
+    let mut writer = TestWriter::new();
+    let command = execute("cat missing.txt", &mut writer).unwrap();
+    assert!(command.is_some());
+    // 在持有 writer 时什么也没有输出
+    assert_eq!(last_output(&writer), "");
+    assert!(execute("echo hi", &mut writer).unwrap().is_none());
+}
+
+#[test_case]
+fn test_execute_unknown_command() {
+    let mut writer = TestWriter::new();
+    assert_eq!(
+        execute("frobnicate now", &mut writer).err(),
+        Some(ShellError::UnknownCommand("frobnicate"))
+    );
+    // 错误由调用者报告，execute 本身不输出
+    assert_eq!(last_output(&writer), "");
+    assert_eq!(
+        execute("x 1 2 3 4 5 6 7 8 9", &mut writer).err(),
+        Some(ShellError::Parse(ParseError::TooManyArgs))
+    );
+}
+
+#[test_case]
+fn test_execute_empty_line_prints_nothing() {
+    let mut writer = TestWriter::new();
+    execute("   ", &mut writer).unwrap();
+    for row in 0..BUFFER_HEIGHT {
+        assert_eq!(screen_line(&writer, row), "");
+    }
+}
+
+#[test_case]
+fn test_execute_color_and_clear() {
+    let mut writer = TestWriter::new();
+    execute("color white blue", &mut writer).unwrap();
+    writer.write_byte(b'x');
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, 0).1,
+        ColorCode::new(Color::White, Color::Blue)
+    );
+
+    execute("clear", &mut writer).unwrap();
+    assert_eq!(screen_line(&writer, BUFFER_HEIGHT - 1), "");
+    // 清屏使用当前颜色
+    assert_eq!(
+        writer.read_char(0, 0).1,
+        ColorCode::new(Color::White, Color::Blue)
+    );
+
+    execute("color purple black", &mut writer).unwrap();
+    assert_eq!(last_output(&writer), "color: unknown color purple");
+    execute("color red", &mut writer).unwrap();
+    assert_eq!(last_output(&writer), "usage: color <fg> <bg>");
+}
+
+#[test_case]
+fn test_registered_command_is_dispatched() {
+    fn hello(_args: &[&str], out: &mut Writer) {
+        out.write_string("hello from test\n");
+    }
+    let command = Command {
+        name: "test-hello",
+        description: "registered by a test",
+        run: Run::Locked(hello),
+    };
+    assert_eq!(register(command), Ok(()));
+    assert_eq!(
+        register(command),
+        Err(RegisterError::Duplicate("test-hello"))
+    );
+    // 不能覆盖内置命令
+    assert_eq!(
+        register(Command {
+            name: "help",
+            ..command
+        }),
+        Err(RegisterError::Duplicate("help"))
+    );
+
+    let mut writer = TestWriter::new();
+    execute("test-hello", &mut writer).unwrap();
+    assert_eq!(last_output(&writer), "hello from test");
+    execute("help", &mut writer).unwrap();
+    assert_eq!(last_output(&writer), "test-hello registered by a test");
+}
@@ -0,0 +1,325 @@
+//! 启动其他 CPU（AP）
+//! 1. 从 ACPI 的 MADT 得到本地 APIC 的地址和所有启用的处理器
+//! 2. 映射本地 APIC 的寄存器页，打开 BSP 的 APIC（伪中断向量寄存器的软件启用位）
+//! 3. 从帧分配器取一个 1MiB 以下的帧放置蹦床（trampoline.s），恒等映射后填写 CR3 和入口
+//! 4. 对每个 AP 依次：分配编号和内核栈，发送 INIT、SIPI、SIPI，等它报告在线后再启动下一个
+//!
+//! AP 加载共享的 GDT 和 IDT，打开自己的 APIC，把在线计数加一并打印一行，然后关中断停在 hlt 循环中。
+//! TSS 描述符在 BSP 加载后处于 busy 状态，不能再被其他 CPU 加载，所以 AP 没有 TSS，
+//! 也就没有 double fault 的 IST 栈；AP 目前不处理中断，只会在异常时用到。
+//!
+//! 没有 MADT 时不尝试 MP 表，只使用 BSP。蹦床所在的帧和 AP 的栈不会被回收
+use crate::acpi::{self, AcpiError};
+use crate::cpu::{self, MAX_CPUS};
+use crate::exec::{self, ExecError};
+use crate::{gdt, interrupts, memory, println, time};
+use core::fmt;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+core::arch::global_asm!(include_str!("trampoline.s"), options(att_syntax));
+
+extern "C" {
+    static smp_trampoline_start: u8;
+    static smp_trampoline_end: u8;
+}
+
+/// 本地 APIC 寄存器页的虚拟地址
+const LAPIC_ADDRESS: u64 = 0x_7777_0000_0000;
+/// AP 的栈从这里开始，每个 AP 占 AP_STACK_PAGES + 1 页，低处的一页不映射，作为保护页
+const AP_STACKS: u64 = LAPIC_ADDRESS + 0x10_0000;
+const AP_STACK_PAGES: usize = 4;
+const PAGE_SIZE: u64 = 4096;
+
+const LAPIC_SVR: usize = 0xF0;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+/// SVR 的软件启用位
+const LAPIC_ENABLE: u32 = 1 << 8;
+/// ICR 的发送状态位，为 1 表示上一个 IPI 还没有发出
+const ICR_PENDING: u32 = 1 << 12;
+
+/// INIT 之后、两次 SIPI 之间和等待 AP 报告的时间（毫秒）
+const INIT_DELAY_MS: u32 = 10;
+const SIPI_DELAY_MS: u32 = 1;
+const ONLINE_TIMEOUT_MS: u32 = 100;
+
+/// 填写在蹦床末尾的参数，布局与 trampoline.s 相同
+#[repr(C)]
+struct Boot {
+    cr3: u64,
+    stack: u64,
+    entry: u64,
+    arg: u64,
+}
+
+#[derive(Debug)]
+pub enum SmpError {
+    Acpi(AcpiError),
+    /// 帧分配器给出的帧不在 1MiB 以下，SIPI 无法指向它
+    NoLowMemory(PhysAddr),
+    /// 4 级页表在 4GiB 以上，32 位代码无法加载到 CR3
+    HighPageTable(PhysAddr),
+    Map(ExecError),
+}
+
+impl fmt::Display for SmpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SmpError::Acpi(error) => write!(f, "{}", error),
+            SmpError::NoLowMemory(addr) => {
+                write!(
+                    f,
+                    "no frame below 1 MiB for the trampoline (got {:#x})",
+                    addr
+                )
+            }
+            SmpError::HighPageTable(addr) => {
+                write!(f, "page table at {:#x} is above 4 GiB", addr)
+            }
+            SmpError::Map(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<AcpiError> for SmpError {
+    fn from(error: AcpiError) -> Self {
+        SmpError::Acpi(error)
+    }
+}
+
+impl From<ExecError> for SmpError {
+    fn from(error: ExecError) -> Self {
+        SmpError::Map(error)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for SmpError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        SmpError::Map(ExecError::Map(error))
+    }
+}
+
+/// INIT IPI 的 (ICR 高 32 位, 低 32 位)：断言电平，交付模式 INIT
+fn init_ipi(apic_id: u8) -> (u32, u32) {
+    ((apic_id as u32) << 24, 0x0000_4500)
+}
+
+/// SIPI 的 (ICR 高 32 位, 低 32 位)，向量是蹦床所在的页号，蹦床必须在 1MiB 以下并且页对齐
+fn startup_ipi(apic_id: u8, trampoline: PhysAddr) -> Option<(u32, u32)> {
+    let page = trampoline.as_u64() / PAGE_SIZE;
+    if !trampoline.is_aligned(PAGE_SIZE) || page > 0xFF {
+        return None;
+    }
+    Some(((apic_id as u32) << 24, 0x0000_4600 | page as u32))
+}
+
+/// 编号为 index 的 AP 的栈所在的页（不包括保护页）和栈顶
+fn ap_stack(index: usize) -> (u64, u64) {
+    let slot = AP_STACKS + index as u64 * (AP_STACK_PAGES as u64 + 1) * PAGE_SIZE;
+    let bottom = slot + PAGE_SIZE;
+    (bottom, bottom + AP_STACK_PAGES as u64 * PAGE_SIZE)
+}
+
+fn trampoline() -> &'static [u8] {
+    unsafe {
+        let start = &raw const smp_trampoline_start;
+        let end = &raw const smp_trampoline_end;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
+
+unsafe fn lapic_read(register: usize) -> u32 {
+    core::ptr::read_volatile((LAPIC_ADDRESS as usize + register) as *const u32)
+}
+
+unsafe fn lapic_write(register: usize, value: u32) {
+    core::ptr::write_volatile((LAPIC_ADDRESS as usize + register) as *mut u32, value);
+}
+
+/// 打开当前 CPU 的本地 APIC，伪中断使用 interrupts::SPURIOUS_VECTOR
+unsafe fn lapic_enable() {
+    lapic_write(LAPIC_SVR, LAPIC_ENABLE | interrupts::SPURIOUS_VECTOR as u32);
+}
+
+unsafe fn send_ipi((high, low): (u32, u32)) {
+    lapic_write(LAPIC_ICR_HIGH, high);
+    lapic_write(LAPIC_ICR_LOW, low);
+    while lapic_read(LAPIC_ICR_LOW) & ICR_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// 等待在线的 CPU 数量达到 count，超时返回 false
+fn wait_online(count: usize, timeout_ms: u32) -> bool {
+    let deadline = time::ticks() + time::ms_to_ticks(timeout_ms);
+    while cpu::online() < count {
+        if time::ticks() >= deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// 把 page 映射到 frame，已经映射到同一个帧时什么也不做
+fn map_fixed(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    page: Page<Size4KiB>,
+    frame: PhysFrame<Size4KiB>,
+    flags: PageTableFlags,
+) -> Result<(), SmpError> {
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(MapToError::PageAlreadyMapped(existing)) if existing == frame => {}
+        Err(error) => return Err(error.into()),
+    }
+    Ok(())
+}
+
+/// AP 在蹦床之后进入的 Rust 代码，index 是它的编号
+extern "C" fn ap_main(index: u64) -> ! {
+    gdt::init_ap();
+    interrupts::init_idt();
+    unsafe { lapic_enable() };
+    cpu::report_online();
+    println!("hello from cpu{} (apic id {})", index, cpu::apic_id());
+    loop {
+        x86_64::instructions::interrupts::disable();
+        x86_64::instructions::hlt();
+    }
+}
+
+/// 启动 MADT 中列出的所有 AP，返回在线的 CPU 数量（包括 BSP）
+/// 需要在中断开启后调用，等待使用时钟节拍
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<usize, SmpError> {
+    let madt = acpi::madt()?;
+    let bsp = cpu::apic_id();
+    cpu::register_bsp(bsp);
+
+    let mmio = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::NO_EXECUTE;
+    let lapic_frame = PhysFrame::containing_address(PhysAddr::new(madt.local_apic_address));
+    let lapic_page = Page::containing_address(VirtAddr::new(LAPIC_ADDRESS));
+    map_fixed(mapper, frame_allocator, lapic_page, lapic_frame, mmio)?;
+    unsafe { lapic_enable() };
+
+    let mut aps = madt
+        .local_apics()
+        .filter(|apic| apic.enabled && apic.apic_id != bsp)
+        .peekable();
+    if aps.peek().is_none() {
+        return Ok(cpu::online());
+    }
+
+    let (level_4_table, _) = Cr3::read();
+    let cr3 = level_4_table.start_address();
+    if cr3.as_u64() > u32::MAX as u64 {
+        return Err(SmpError::HighPageTable(cr3));
+    }
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(ExecError::Map(MapToError::FrameAllocationFailed))?;
+    let trampoline_addr = frame.start_address();
+    if startup_ipi(0, trampoline_addr).is_none() {
+        return Err(SmpError::NoLowMemory(trampoline_addr));
+    }
+    let identity = Page::containing_address(VirtAddr::new(trampoline_addr.as_u64()));
+    map_fixed(
+        mapper,
+        frame_allocator,
+        identity,
+        frame,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+    )?;
+    let code = trampoline();
+    let base = memory::phys_to_virt(trampoline_addr)
+        .ok_or(AcpiError::NoPhysicalMapping)?
+        .as_mut_ptr::<u8>();
+    unsafe { core::ptr::copy_nonoverlapping(code.as_ptr(), base, code.len()) };
+    let boot = unsafe { &mut *(base.add(code.len() - size_of::<Boot>()) as *mut Boot) };
+    boot.cr3 = cr3.as_u64();
+    boot.entry = ap_main as *const () as u64;
+
+    for apic in aps {
+        let Some(index) = cpu::register(apic.apic_id) else {
+            println!("smp: more than {} CPUs, ignoring the rest", MAX_CPUS);
+            break;
+        };
+        let (stack_bottom, stack_top) = ap_stack(index);
+        let stack_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        exec::map(
+            mapper,
+            frame_allocator,
+            exec::pages(stack_bottom, AP_STACK_PAGES),
+            stack_flags,
+        )?;
+        unsafe {
+            core::ptr::write_volatile(&mut boot.stack, stack_top);
+            core::ptr::write_volatile(&mut boot.arg, index as u64);
+        }
+
+        let expected = cpu::online() + 1;
+        let sipi = startup_ipi(apic.apic_id, trampoline_addr).unwrap();
+        unsafe {
+            send_ipi(init_ipi(apic.apic_id));
+            time::delay_ms(INIT_DELAY_MS);
+            send_ipi(sipi);
+            time::delay_ms(SIPI_DELAY_MS);
+            // 第一次 SIPI 没有启动 AP 时再发一次，已经启动的 AP 会忽略它
+            if cpu::online() < expected {
+                send_ipi(sipi);
+            }
+        }
+        if !wait_online(expected, ONLINE_TIMEOUT_MS) {
+            println!("smp: cpu{} (apic id {}) did not start", index, apic.apic_id);
+        }
+    }
+    Ok(cpu::online())
+}
+
+#[test_case]
+fn test_ipi_encoding() {
+    assert_eq!(init_ipi(3), (3 << 24, 0x4500));
+    assert_eq!(
+        startup_ipi(2, PhysAddr::new(0x8000)),
+        Some((2 << 24, 0x4608))
+    );
+    assert_eq!(
+        startup_ipi(1, PhysAddr::new(0xff000)),
+        Some((1 << 24, 0x46ff))
+    );
+    // 1MiB 以上或者没有页对齐
+    assert_eq!(startup_ipi(1, PhysAddr::new(0x10_0000)), None);
+    assert_eq!(startup_ipi(1, PhysAddr::new(0x8010)), None);
+}
+
+#[test_case]
+fn test_ap_stacks_do_not_overlap() {
+    let (bottom, top) = ap_stack(1);
+    assert_eq!(top - bottom, AP_STACK_PAGES as u64 * PAGE_SIZE);
+    assert!(bottom > LAPIC_ADDRESS + PAGE_SIZE);
+    // 相邻两个栈之间隔着一个未映射的保护页
+    let (next_bottom, _) = ap_stack(2);
+    assert_eq!(next_bottom - top, PAGE_SIZE);
+}
+
+#[test_case]
+fn test_trampoline_fits_in_a_page() {
+    let code = trampoline();
+    assert!(code.len() <= PAGE_SIZE as usize);
+    assert!(code.len().is_multiple_of(8));
+    // 实模式下 SIPI 从第一个字节开始执行：cli
+    assert_eq!(code[0], 0xfa);
+}
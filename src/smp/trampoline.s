@@ -0,0 +1,103 @@
+# AP 启动蹦床
+# smp::init 把 smp_trampoline_start..smp_trampoline_end 复制到 1MiB 以下的一个页中（同时恒等映射），
+# 并填写末尾的 cr3、stack、entry、arg。SIPI 让 AP 在实模式下从这个页的开头执行，CS 是页的段地址。
+# 代码与位置无关：先算出页的线性地址，填写 GDTR 的基址和两个远跳转的目标，
+# 再依次进入保护模式和长模式，最后切换到内核栈调用 entry(arg)
+
+.section .text
+.code16
+.global smp_trampoline_start
+smp_trampoline_start:
+    cli
+    cld
+    movw %cs, %ax
+    movw %ax, %ds
+    xorl %ebx, %ebx
+    movw %ax, %bx
+    shll $4, %ebx
+
+    leal (tramp_gdt - smp_trampoline_start)(%ebx), %eax
+    movl %eax, (tramp_gdtr + 2 - smp_trampoline_start)
+    leal (tramp_protected - smp_trampoline_start)(%ebx), %eax
+    movl %eax, (tramp_protected_far - smp_trampoline_start)
+    leal (tramp_long - smp_trampoline_start)(%ebx), %eax
+    movl %eax, (tramp_long_far - smp_trampoline_start)
+
+    lgdtl (tramp_gdtr - smp_trampoline_start)
+    movl %cr0, %eax
+    orl $1, %eax
+    movl %eax, %cr0
+    ljmpl *(tramp_protected_far - smp_trampoline_start)
+
+.code32
+tramp_protected:
+    movw $0x10, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+
+    # PAE
+    movl %cr4, %eax
+    orl $(1 << 5), %eax
+    movl %eax, %cr4
+    movl (smp_tramp_cr3 - smp_trampoline_start)(%ebx), %eax
+    movl %eax, %cr3
+    # EFER.LME 和 EFER.NXE，内核的页表项带有 NO_EXECUTE
+    movl $0xC0000080, %ecx
+    rdmsr
+    orl $((1 << 8) | (1 << 11)), %eax
+    wrmsr
+    # 开启分页（PG）和写保护（WP）
+    movl %cr0, %eax
+    orl $((1 << 31) | (1 << 16)), %eax
+    movl %eax, %cr0
+    ljmpl *(tramp_long_far - smp_trampoline_start)(%ebx)
+
+.code64
+tramp_long:
+    xorl %eax, %eax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+    # 进入 64 位模式后 RBX 的高 32 位没有定义
+    movl %ebx, %ebx
+    movq (smp_tramp_stack - smp_trampoline_start)(%rbx), %rsp
+    movq (smp_tramp_arg - smp_trampoline_start)(%rbx), %rdi
+    movq (smp_tramp_entry - smp_trampoline_start)(%rbx), %rax
+    xorl %ebp, %ebp
+    callq *%rax
+tramp_park:
+    hlt
+    jmp tramp_park
+
+.balign 8
+tramp_gdt:
+    .quad 0
+    # 0x08：32 位代码段
+    .quad 0x00cf9a000000ffff
+    # 0x10：数据段
+    .quad 0x00cf92000000ffff
+    # 0x18：64 位代码段
+    .quad 0x00af9a000000ffff
+tramp_gdtr:
+    .word 4 * 8 - 1
+    .long 0
+tramp_protected_far:
+    .long 0
+    .word 0x08
+tramp_long_far:
+    .long 0
+    .word 0x18
+
+# 由 smp::init 填写，布局与 smp::Boot 相同
+.balign 8
+smp_tramp_cr3:
+    .quad 0
+smp_tramp_stack:
+    .quad 0
+smp_tramp_entry:
+    .quad 0
+smp_tramp_arg:
+    .quad 0
+.global smp_trampoline_end
+smp_trampoline_end:
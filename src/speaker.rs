@@ -0,0 +1,241 @@
+//! PC 扬声器
+//! PIT 通道 2 的输出接到扬声器上：把通道 2 设为方波模式并写入分频值决定音调，
+//! 再打开端口 0x61（系统控制端口 B）的 bit 0（通道 2 的门控）和 bit 1（扬声器数据使能）发声。
+//! 0x61 的其他位控制奇偶校验、NMI 等，只能读-改-写，不能覆盖
+//!
+//! 时长用时钟节拍测量；中断关闭（例如 panic 时）节拍不会增加，
+//! 改为数通道 2 输出（0x61 的 bit 5）的周期
+use crate::time::{self, PIT_BASE_FREQUENCY};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+/// 分频值最大为 65535，更低的频率无法表示
+pub const MIN_FREQUENCY: u32 = PIT_BASE_FREQUENCY.div_ceil(u16::MAX as u32);
+/// 再高已经听不到了
+pub const MAX_FREQUENCY: u32 = 20_000;
+
+/// 响铃的音调和时长
+const BELL: (u32, u32) = (880, 100);
+
+const PIT_CHANNEL2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+const CONTROL: u16 = 0x61;
+
+/// 通道 2，先低字节后高字节，模式 3（方波发生器），二进制计数
+const CHANNEL2_SQUARE_WAVE: u8 = 0xb6;
+/// 0x61 的 bit 0 和 bit 1
+const SPEAKER_BITS: u8 = 0b11;
+/// 0x61 的 bit 0：只打开通道 2 的门控，不送往扬声器，中断关闭时用来计时
+const GATE_BIT: u8 = 0b01;
+/// 0x61 的 bit 5：通道 2 的输出电平
+const OUTPUT_BIT: u8 = 1 << 5;
+/// 中断关闭时计时用的频率，每个周期 1 毫秒
+const COUNTING_FREQUENCY: u32 = 1000;
+/// 等待通道 2 输出翻转的轮询次数上限，输出不变化时也不会卡住
+const TIMEOUT_SPINS: usize = 100_000;
+
+static SILENCED: AtomicBool = AtomicBool::new(false);
+/// bell 发出的声音在这个节拍关闭，u64::MAX 表示没有在响
+static BELL_OFF_AT: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// 访问 PIT 和 0x61 的端口，抽象出来以便测试写入的值
+pub trait SpeakerPorts {
+    fn read(&mut self, port: u16) -> u8;
+    fn write(&mut self, port: u16, value: u8);
+}
+
+struct HardwarePorts;
+
+impl SpeakerPorts for HardwarePorts {
+    fn read(&mut self, port: u16) -> u8 {
+        unsafe { Port::new(port).read() }
+    }
+
+    fn write(&mut self, port: u16, value: u8) {
+        unsafe { Port::new(port).write(value) }
+    }
+}
+
+/// 频率对应的分频值，超出 [MIN_FREQUENCY, MAX_FREQUENCY] 的频率先被限制到范围内，结果四舍五入
+pub fn divisor(frequency: u32) -> u16 {
+    let frequency = frequency.clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+    ((PIT_BASE_FREQUENCY + frequency / 2) / frequency) as u16
+}
+
+fn set_frequency(ports: &mut impl SpeakerPorts, frequency: u32) {
+    let divisor = divisor(frequency);
+    ports.write(PIT_COMMAND, CHANNEL2_SQUARE_WAVE);
+    ports.write(PIT_CHANNEL2, divisor as u8);
+    ports.write(PIT_CHANNEL2, (divisor >> 8) as u8);
+}
+
+/// 把 0x61 中 mask 的各位设为 bits，保留其他位
+fn update_control(ports: &mut impl SpeakerPorts, mask: u8, bits: u8) {
+    let value = ports.read(CONTROL);
+    ports.write(CONTROL, (value & !mask) | (bits & mask));
+}
+
+fn start(ports: &mut impl SpeakerPorts, frequency: u32) {
+    set_frequency(ports, frequency);
+    update_control(ports, SPEAKER_BITS, SPEAKER_BITS);
+}
+
+fn stop(ports: &mut impl SpeakerPorts) {
+    update_control(ports, SPEAKER_BITS, 0);
+}
+
+/// 全局静音开关，静音时 beep、play 和 bell 都只等待或什么也不做
+/// 启动命令行中的 nobeep 选项效果相同
+pub fn set_silenced(silenced: bool) {
+    SILENCED.store(silenced, Ordering::Relaxed);
+}
+
+pub fn silenced() -> bool {
+    SILENCED.load(Ordering::Relaxed) || crate::config::get().nobeep
+}
+
+/// 以 frequency Hz 发声 duration_ms 毫秒，返回时扬声器已经关闭
+/// 频率会被限制到可以表示的范围内，频率为 0 表示休止，只等待
+pub fn beep(frequency: u32, duration_ms: u32) {
+    if frequency == 0 || silenced() {
+        wait_ms(duration_ms, None);
+        return;
+    }
+    interrupts::without_interrupts(|| start(&mut HardwarePorts, frequency));
+    wait_ms(duration_ms, Some(frequency));
+    interrupts::without_interrupts(|| stop(&mut HardwarePorts));
+}
+
+/// 依次播放 (频率, 毫秒) 组成的音符，频率为 0 的音符是休止
+pub fn play(notes: &[(u32, u32)]) {
+    // 一段旋律可能很长，期间主循环不会运行
+    let _watchdog = crate::watchdog::suspend();
+    for &(frequency, duration_ms) in notes {
+        beep(frequency, duration_ms);
+    }
+}
+
+/// sounding 是正在发出的频率，休止时为 None
+fn wait_ms(ms: u32, sounding: Option<u32>) {
+    if interrupts::are_enabled() {
+        time::delay_ms(ms);
+    } else {
+        count_periods(&mut HardwarePorts, ms, sounding);
+    }
+}
+
+/// 中断关闭时计时：正在发声时按通道 2 当前的周期数折算；
+/// 休止时让通道 2 以 1kHz 运行但不送往扬声器，数 ms 个周期
+fn count_periods(ports: &mut impl SpeakerPorts, ms: u32, sounding: Option<u32>) {
+    let frequency = match sounding {
+        Some(frequency) => PIT_BASE_FREQUENCY / divisor(frequency) as u32,
+        None => {
+            set_frequency(ports, COUNTING_FREQUENCY);
+            update_control(ports, SPEAKER_BITS, GATE_BIT);
+            COUNTING_FREQUENCY
+        }
+    };
+    let periods = ms as u64 * frequency as u64 / 1000;
+    for _ in 0..periods {
+        // 等待一个完整的周期：输出先变低，再变高
+        if !wait_output(ports, false) || !wait_output(ports, true) {
+            break;
+        }
+    }
+    if sounding.is_none() {
+        update_control(ports, SPEAKER_BITS, 0);
+    }
+}
+
+fn wait_output(ports: &mut impl SpeakerPorts, high: bool) -> bool {
+    for _ in 0..TIMEOUT_SPINS {
+        if (ports.read(CONTROL) & OUTPUT_BIT != 0) == high {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+/// 响铃：开始发声后立即返回，由时钟中断在到时后关闭，可以在持有 WRITER 的锁时调用
+pub fn bell() {
+    if silenced() {
+        return;
+    }
+    let (frequency, duration_ms) = BELL;
+    interrupts::without_interrupts(|| {
+        start(&mut HardwarePorts, frequency);
+        let off_at = time::ticks() + time::ms_to_ticks(duration_ms);
+        BELL_OFF_AT.store(off_at, Ordering::Relaxed);
+    });
+}
+
+/// 由时钟中断处理函数调用，关闭到时的响铃
+pub(crate) fn on_tick(now: u64) {
+    if now >= BELL_OFF_AT.load(Ordering::Relaxed) {
+        BELL_OFF_AT.store(u64::MAX, Ordering::Relaxed);
+        stop(&mut HardwarePorts);
+    }
+}
+
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// 模拟的端口：0x61 保存最后写入的值，记录所有写入
+#[cfg(test)]
+struct MockPorts {
+    control: u8,
+    writes: Vec<(u16, u8)>,
+}
+
+#[cfg(test)]
+impl SpeakerPorts for MockPorts {
+    fn read(&mut self, port: u16) -> u8 {
+        assert_eq!(port, CONTROL);
+        self.control
+    }
+
+    fn write(&mut self, port: u16, value: u8) {
+        if port == CONTROL {
+            self.control = value;
+        }
+        self.writes.push((port, value));
+    }
+}
+
+#[test_case]
+fn test_divisor_clamps_and_rounds() {
+    assert_eq!(divisor(1000), 1193);
+    // 1_193_182 / 440 = 2711.77
+    assert_eq!(divisor(440), 2712);
+    assert_eq!(divisor(MIN_FREQUENCY), 62799);
+    assert_eq!(divisor(0), divisor(MIN_FREQUENCY));
+    assert_eq!(divisor(1), divisor(MIN_FREQUENCY));
+    assert_eq!(divisor(MAX_FREQUENCY), 60);
+    assert_eq!(divisor(u32::MAX), divisor(MAX_FREQUENCY));
+}
+
+#[test_case]
+fn test_control_port_keeps_other_bits() {
+    let mut ports = MockPorts {
+        control: 0b1010_1100,
+        writes: Vec::new(),
+    };
+    start(&mut ports, 1000);
+    assert_eq!(
+        ports.writes,
+        [
+            (PIT_COMMAND, 0xb6),
+            (PIT_CHANNEL2, 0xa9),
+            (PIT_CHANNEL2, 0x04),
+            (CONTROL, 0b1010_1111),
+        ]
+    );
+    stop(&mut ports);
+    assert_eq!(ports.control, 0b1010_1100);
+
+    ports.control = 0xff;
+    update_control(&mut ports, SPEAKER_BITS, GATE_BIT);
+    assert_eq!(ports.control, 0xfd);
+}
@@ -0,0 +1,161 @@
+//! 内核栈使用量的最高水位
+//! init 在启动的最早阶段用页表找出当前栈的范围：从 RSP 所在的页向下找到 bootloader 留下的
+//! 未映射保护页，向上找到第一个未映射的页。然后把 RSP 以下（再留出 SAFETY_MARGIN）的部分涂成 PATTERN。
+//! 之后从栈底向上扫描，第一个不是 PATTERN 的字节以上的部分就是曾经用到过的最深位置。
+//!
+//! 扫描在第一个被改写的字节处停止，之上的活动数据里即使恰好出现 PATTERN 也不影响结果；
+//! 只有最深处被写入的值恰好以 0xAA 结尾时会少算几个字节。
+//! 找不到保护页时（例如 multiboot2 路径的栈在 .bss 中，下面紧接着其他数据）不涂色，也不报告使用量
+use crate::memory;
+use crate::vga_buffer::{Color, Writer, WRITER};
+use core::fmt::Write;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::VirtAddr;
+
+pub const PATTERN: u8 = 0xAA;
+/// 使用量超过总量的这个百分比时 print_usage 用警告颜色
+pub const WARN_PERCENT: usize = 75;
+/// RSP 之下不涂色的字节数：涂色的代码本身（以及可能调用的 memset）还要用到这一部分
+const SAFETY_MARGIN: u64 = 4096;
+/// 查找栈的边界时最多检查的页数，bootloader 默认的栈是 512 页
+const MAX_STACK_PAGES: u64 = 1024;
+const PAGE_SIZE: u64 = 4096;
+
+/// 栈的范围，都为 0 表示没有测量
+static BOTTOM: AtomicU64 = AtomicU64::new(0);
+static TOP: AtomicU64 = AtomicU64::new(0);
+
+fn current_rsp() -> u64 {
+    let rsp: u64;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)) };
+    rsp
+}
+
+/// 从 rsp 所在的页出发向下、向上找到连续映射的页，两端都必须在 max_pages 页之内遇到未映射的页
+fn find_bounds(rsp: u64, max_pages: u64, mapped: impl Fn(u64) -> bool) -> Option<Range<u64>> {
+    let page = rsp & !(PAGE_SIZE - 1);
+    let mut bottom = page;
+    loop {
+        let below = bottom.checked_sub(PAGE_SIZE)?;
+        if !mapped(below) {
+            break;
+        }
+        bottom = below;
+        if (page - bottom) / PAGE_SIZE >= max_pages {
+            return None;
+        }
+    }
+    let mut top = page + PAGE_SIZE;
+    while mapped(top) {
+        top = top.checked_add(PAGE_SIZE)?;
+        if (top - bottom) / PAGE_SIZE > max_pages {
+            return None;
+        }
+    }
+    Some(bottom..top)
+}
+
+/// 从栈底开始连续的 PATTERN 字节数，byte_at(i) 是栈底之上第 i 个字节
+fn untouched(len: usize, byte_at: impl Fn(usize) -> u8) -> usize {
+    (0..len).find(|&i| byte_at(i) != PATTERN).unwrap_or(len)
+}
+
+/// 找出栈的范围并涂色，只能在启动时、中断开启之前调用一次
+pub fn init(physical_memory_offset: VirtAddr) {
+    let rsp = current_rsp();
+    let mapped = |addr: u64| {
+        VirtAddr::try_new(addr).is_ok_and(|addr| unsafe {
+            memory::translate_addr(addr, physical_memory_offset).is_some()
+        })
+    };
+    let Some(stack) = find_bounds(rsp, MAX_STACK_PAGES, mapped) else {
+        return;
+    };
+    let Some(end) = rsp
+        .checked_sub(SAFETY_MARGIN)
+        .filter(|&end| end > stack.start)
+    else {
+        return;
+    };
+    unsafe {
+        core::ptr::write_bytes(
+            stack.start as *mut u8,
+            PATTERN,
+            (end - stack.start) as usize,
+        )
+    };
+    BOTTOM.store(stack.start, Ordering::Relaxed);
+    TOP.store(stack.end, Ordering::Relaxed);
+}
+
+/// 栈的总大小，没有测量时为 0
+pub fn size() -> usize {
+    (TOP.load(Ordering::Relaxed) - BOTTOM.load(Ordering::Relaxed)) as usize
+}
+
+/// 启动以来栈用到过的最大字节数，没有测量时为 0
+pub fn high_water_mark() -> usize {
+    let bottom = BOTTOM.load(Ordering::Relaxed);
+    let size = size();
+    // 栈正在被使用，只能逐字节 volatile 读取，不能当作切片
+    size - untouched(size, |i| unsafe {
+        core::ptr::read_volatile((bottom as *const u8).add(i))
+    })
+}
+
+/// 输出使用量，超过 WARN_PERCENT 时用红色
+pub fn write_usage(out: &mut Writer) {
+    let size = size();
+    if size == 0 {
+        let _ = writeln!(out, "stack: not measured");
+        return;
+    }
+    let used = high_water_mark();
+    let percent = used * 100 / size;
+    let color = out.color_code();
+    if percent > WARN_PERCENT {
+        out.set_color(Color::LightRed, Color::Black);
+    }
+    let _ = writeln!(
+        out,
+        "stack: {} of {} KiB used at most ({}%)",
+        used.div_ceil(1024),
+        size / 1024,
+        percent
+    );
+    out.set_color_code(color);
+}
+
+pub fn print_usage() {
+    interrupts::without_interrupts(|| write_usage(&mut WRITER.lock()));
+}
+
+#[test_case]
+fn test_untouched_stops_at_first_dirty_byte() {
+    let mut stack = [PATTERN; 64];
+    assert_eq!(untouched(stack.len(), |i| stack[i]), 64);
+    stack[40..].fill(0x11);
+    assert_eq!(untouched(stack.len(), |i| stack[i]), 40);
+    // 活动数据中恰好出现 PATTERN 不影响结果
+    stack[50..54].fill(PATTERN);
+    assert_eq!(untouched(stack.len(), |i| stack[i]), 40);
+    stack[0] = 0;
+    assert_eq!(untouched(stack.len(), |i| stack[i]), 0);
+    assert_eq!(untouched(0, |_| unreachable!()), 0);
+}
+
+#[test_case]
+fn test_find_bounds() {
+    const BASE: u64 = 0x10_0000;
+    // 保护页在 BASE，栈是之后的 4 页
+    let stack = |addr: u64| (BASE + PAGE_SIZE..BASE + 5 * PAGE_SIZE).contains(&addr);
+    let bounds = Some(BASE + PAGE_SIZE..BASE + 5 * PAGE_SIZE);
+    assert_eq!(find_bounds(BASE + 3 * PAGE_SIZE + 8, 16, stack), bounds);
+    assert_eq!(find_bounds(BASE + PAGE_SIZE, 16, stack), bounds);
+    assert_eq!(find_bounds(BASE + 5 * PAGE_SIZE - 1, 16, stack), bounds);
+    // 栈比允许的页数大，或者找不到保护页
+    assert_eq!(find_bounds(BASE + 3 * PAGE_SIZE, 3, stack), None);
+    assert_eq!(find_bounds(BASE, 16, |_| true), None);
+}
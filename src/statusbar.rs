@@ -0,0 +1,400 @@
+//! 定时刷新的状态栏
+//! 状态栏任务每隔 interval 毫秒（默认 500）向所有注册的 StatusProvider 要一段文字，
+//! 按注册顺序用 " | " 连起来画在第 0 行。超出行宽的部分从行尾截掉，所以先注册的段优先显示；
+//! 空的段连同分隔符一起省略。第一次画之前用 set_scroll_top 把这一行留出来，之后的滚屏不会冲掉它。
+//!
+//! 只在拼出的内容变了、或者屏幕上的那一行已经不是上次画的内容（例如被清屏）时才重画。
+//! 屏幕被其他功能整个占用时（图形模式、屏保）由它们调用 pause，期间不画；
+//! resume 之后的第一次刷新总是重画，因为恢复的画面可能是暂停前的旧内容。
+//! panic 之后执行器不再运行，状态栏也就不会再画。
+//!
+//! 由启动参数 statusbar（或者 statusbar=毫秒）打开
+use crate::vga_buffer::{
+    Align, Color, ColorCode, Marquee, StatusLine, Writer, BUFFER_WIDTH, WRITER,
+};
+use crate::{allocator, interrupts, keyboard, time};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+pub const DEFAULT_INTERVAL_MS: u32 = 500;
+/// 状态栏所在的行
+pub const ROW: usize = 0;
+const SEPARATOR: &str = " | ";
+/// 状态栏和跑马灯的颜色：浅灰底黑字
+const COLOR: ColorCode = ColorCode::new(Color::Black, Color::LightGray);
+
+static INTERVAL_MS: AtomicU32 = AtomicU32::new(DEFAULT_INTERVAL_MS);
+/// pause 的嵌套层数
+static PAUSED: AtomicUsize = AtomicUsize::new(0);
+static STATUS_BAR: Mutex<StatusBar> = Mutex::new(StatusBar::new());
+
+/// 状态栏中的一段
+pub trait StatusProvider: Send {
+    /// 把这一段写入 out，什么也不写时这一段被省略。
+    /// 调用时持有 WRITER 的锁，需要屏幕状态时从 writer 读取，不能再打印
+    fn segment(&mut self, writer: &Writer, out: &mut dyn Write) -> fmt::Result;
+}
+
+pub struct StatusBar {
+    providers: Vec<Box<dyn StatusProvider>>,
+    /// 上次画出的内容，None 表示下次一定重画
+    painted: Option<[u8; BUFFER_WIDTH]>,
+}
+
+impl StatusBar {
+    pub const fn new() -> Self {
+        StatusBar {
+            providers: Vec::new(),
+            painted: None,
+        }
+    }
+
+    /// 追加到已有的段之后
+    pub fn register(&mut self, provider: impl StatusProvider + 'static) {
+        self.providers.push(Box::new(provider));
+    }
+
+    /// 按注册顺序拼出整行，写入 buf
+    fn compose(&mut self, writer: &Writer, buf: &mut [u8; BUFFER_WIDTH]) {
+        let mut text = String::new();
+        let mut segment = String::new();
+        for provider in &mut self.providers {
+            segment.clear();
+            let _ = provider.segment(writer, &mut segment);
+            if segment.is_empty() {
+                continue;
+            }
+            if !text.is_empty() {
+                text.push_str(SEPARATOR);
+            }
+            text.push_str(&segment);
+        }
+        // 截断到行宽，宽字符和非 ASCII 字符的处理见 StatusLine::text
+        StatusLine::new(buf).text(&text, BUFFER_WIDTH, Align::Left);
+    }
+
+    /// 拼出当前的内容，需要时画到 ROW 行，返回是否画了。paused 时什么也不做，
+    /// 并让下一次刷新一定重画
+    pub fn refresh(&mut self, writer: &mut Writer, paused: bool) -> bool {
+        if paused {
+            self.painted = None;
+            return false;
+        }
+        let mut line = [b' '; BUFFER_WIDTH];
+        self.compose(writer, &mut line);
+        let on_screen = (0..BUFFER_WIDTH).all(|col| writer.read_char(ROW, col).0 == line[col]);
+        if self.painted == Some(line) && on_screen {
+            return false;
+        }
+        let scroll_top = writer.scroll_top().max(ROW + 1);
+        writer.set_scroll_top(scroll_top);
+        let color = writer.color_code();
+        writer.set_color_code(COLOR);
+        // StatusLine 拼出的总是可打印的 ASCII
+        let text = core::str::from_utf8(&line).unwrap();
+        writer.write_fmt_at(ROW, 0, format_args!("{}", text));
+        writer.set_color_code(color);
+        self.painted = Some(line);
+        true
+    }
+}
+
+impl Default for StatusBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 向全局状态栏注册一段
+pub fn register(provider: impl StatusProvider + 'static) {
+    STATUS_BAR.lock().register(provider);
+}
+
+/// 注册内置的段：运行时间、堆、中断频率、CapsLock、当前控制台
+pub fn register_defaults() {
+    register(Uptime);
+    register(Heap);
+    register(IrqRate::new());
+    register(CapsLock);
+    register(ActiveConsole);
+}
+
+/// 设置刷新间隔，最小 1 毫秒
+pub fn set_interval(ms: u32) {
+    INTERVAL_MS.store(ms.max(1), Ordering::Relaxed);
+}
+
+/// 暂停绘制，可以嵌套，与 resume 成对调用
+pub fn pause() {
+    PAUSED.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn resume() {
+    let _ = PAUSED.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |depth| {
+        depth.checked_sub(1)
+    });
+}
+
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::SeqCst) > 0
+}
+
+/// 状态栏任务
+pub async fn run() {
+    loop {
+        let interval = INTERVAL_MS.load(Ordering::Relaxed);
+        time::sleep(Duration::from_millis(interval as u64)).await;
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            // 回滚中不画，免得盖住正在查看的历史
+            let paused = paused() || writer.scroll_offset() > 0;
+            STATUS_BAR.lock().refresh(&mut writer, paused);
+        });
+    }
+}
+
+/// 把状态栏的一行换成跑马灯：保留这一行不参与滚屏，每隔 interval_ms 毫秒向左移动一列。
+/// 与 run 画在同一行，两者只应该运行一个
+pub async fn run_marquee(text: &'static str, interval_ms: u32) {
+    let mut marquee = Marquee::new(text, ROW, COLOR);
+    x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().set_scroll_top(ROW + 1));
+    loop {
+        time::sleep(Duration::from_millis(interval_ms.max(1) as u64)).await;
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            if !paused() && writer.scroll_offset() == 0 {
+                marquee.tick(&mut writer);
+            }
+        });
+    }
+}
+
+/// 启动以来的时间，例如 "up 1h02m03s"
+pub struct Uptime;
+
+impl StatusProvider for Uptime {
+    fn segment(&mut self, _: &Writer, out: &mut dyn Write) -> fmt::Result {
+        let seconds = time::ticks_to_ms(time::ticks()) / 1000;
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        if hours > 0 {
+            write!(out, "up {}h{:02}m{:02}s", hours, minutes, seconds)
+        } else {
+            write!(out, "up {}m{:02}s", minutes, seconds)
+        }
+    }
+}
+
+/// 堆的已用和空闲量，以 KiB 为单位；堆的锁正被占用时省略
+pub struct Heap;
+
+impl StatusProvider for Heap {
+    fn segment(&mut self, _: &Writer, out: &mut dyn Write) -> fmt::Result {
+        match allocator::try_heap_stats() {
+            Some(stats) => write!(
+                out,
+                "heap {}K used {}K free",
+                stats.used / 1024,
+                stats.free / 1024
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 每秒的硬件中断数，由两次刷新之间 interrupts::interrupt_count 的变化算出
+pub struct IrqRate {
+    /// 上次刷新时的 (毫秒, 中断数)
+    last: Option<(u64, u64)>,
+}
+
+impl IrqRate {
+    pub const fn new() -> Self {
+        IrqRate { last: None }
+    }
+
+    /// 从上次到 (now_ms, count) 的平均每秒次数，第一次或者时间没有前进时返回 None
+    fn update(&mut self, now_ms: u64, count: u64) -> Option<u64> {
+        let rate = self.last.and_then(|(then_ms, then_count)| {
+            let elapsed = now_ms.checked_sub(then_ms).filter(|&ms| ms > 0)?;
+            Some(count.saturating_sub(then_count) * 1000 / elapsed)
+        });
+        self.last = Some((now_ms, count));
+        rate
+    }
+}
+
+impl Default for IrqRate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatusProvider for IrqRate {
+    fn segment(&mut self, _: &Writer, out: &mut dyn Write) -> fmt::Result {
+        let now_ms = time::ticks_to_ms(time::ticks());
+        match self.update(now_ms, interrupts::interrupt_count()) {
+            Some(rate) => write!(out, "{} irq/s", rate),
+            None => Ok(()),
+        }
+    }
+}
+
+/// CapsLock 打开时显示 "CAPS"
+pub struct CapsLock;
+
+impl StatusProvider for CapsLock {
+    fn segment(&mut self, _: &Writer, out: &mut dyn Write) -> fmt::Result {
+        if keyboard::locks().caps {
+            out.write_str("CAPS")?;
+        }
+        Ok(())
+    }
+}
+
+/// 当前的虚拟控制台，从 1 开始编号，例如 "vt1"
+pub struct ActiveConsole;
+
+impl StatusProvider for ActiveConsole {
+    fn segment(&mut self, writer: &Writer, out: &mut dyn Write) -> fmt::Result {
+        write!(out, "vt{}", writer.active_console() + 1)
+    }
+}
+
+#[cfg(test)]
+use crate::vga_buffer::{TestWriter, BUFFER_HEIGHT};
+
+/// 测试用的段，内容由测试改变
+#[cfg(test)]
+struct Fixed(alloc::sync::Arc<Mutex<&'static str>>);
+
+#[cfg(test)]
+impl StatusProvider for Fixed {
+    fn segment(&mut self, _: &Writer, out: &mut dyn Write) -> fmt::Result {
+        out.write_str(*self.0.lock())
+    }
+}
+
+#[cfg(test)]
+fn fixed(text: &'static str) -> (Fixed, alloc::sync::Arc<Mutex<&'static str>>) {
+    let shared = alloc::sync::Arc::new(Mutex::new(text));
+    (Fixed(shared.clone()), shared)
+}
+
+#[test_case]
+fn test_segments_compose_in_order() {
+    let writer = TestWriter::new();
+    let mut bar = StatusBar::new();
+    bar.register(fixed("up 0m01s").0);
+    bar.register(fixed("").0);
+    bar.register(fixed("vt1").0);
+    let mut line = [0; BUFFER_WIDTH];
+    bar.compose(&writer, &mut line);
+    // 空的段连同分隔符一起省略
+    assert_eq!(&line[..16], b"up 0m01s | vt1  ");
+    assert!(line[16..].iter().all(|&byte| byte == b' '));
+}
+
+#[test_case]
+fn test_truncation_keeps_earlier_segments() {
+    let writer = TestWriter::new();
+    let mut bar = StatusBar::new();
+    let long: &'static str = "x".repeat(BUFFER_WIDTH - 5).leak();
+    bar.register(fixed(long).0);
+    bar.register(fixed("second").0);
+    bar.register(fixed("third").0);
+    let mut line = [0; BUFFER_WIDTH];
+    bar.compose(&writer, &mut line);
+    assert_eq!(&line[BUFFER_WIDTH - 6..], b"x | se");
+    assert!(!line.windows(5).any(|window| window == b"third"));
+}
+
+#[test_case]
+fn test_repaint_only_when_changed() {
+    let mut writer = TestWriter::new();
+    let mut bar = StatusBar::new();
+    let (provider, text) = fixed("up 0m01s");
+    bar.register(provider);
+    assert!(bar.refresh(&mut writer, false));
+    let row: Vec<u8> = (0..8).map(|col| writer.read_char(ROW, col).0).collect();
+    assert_eq!(row, b"up 0m01s");
+    assert_eq!(
+        writer.read_char(ROW, 0).1,
+        ColorCode::new(Color::Black, Color::LightGray)
+    );
+    // 画状态栏不改变文字的颜色
+    assert_eq!(
+        writer.color_code(),
+        ColorCode::new(Color::Yellow, Color::Black)
+    );
+    assert!(!bar.refresh(&mut writer, false));
+
+    *text.lock() = "up 0m02s";
+    assert!(bar.refresh(&mut writer, false));
+    assert!(!bar.refresh(&mut writer, false));
+
+    // 被其他输出盖掉时重画
+    writer.put_char(ROW, 3, b'!', ColorCode::new(Color::White, Color::Black));
+    assert!(bar.refresh(&mut writer, false));
+    assert_eq!(writer.read_char(ROW, 3).0, b'0');
+}
+
+#[test_case]
+fn test_row_is_reserved_before_painting() {
+    use core::fmt::Write;
+
+    let mut writer = TestWriter::new();
+    let mut bar = StatusBar::new();
+    bar.register(fixed("vt1").0);
+    assert!(bar.refresh(&mut writer, false));
+    assert_eq!(writer.scroll_top(), ROW + 1);
+    for line in 0..BUFFER_HEIGHT * 2 {
+        writeln!(writer, "line {}", line).unwrap();
+    }
+    // 滚屏没有冲掉状态栏，不需要重画
+    assert_eq!(writer.read_char(ROW, 0).0, b'v');
+    assert!(!bar.refresh(&mut writer, false));
+}
+
+#[test_case]
+fn test_pause_and_resume() {
+    let mut writer = TestWriter::new();
+    let mut bar = StatusBar::new();
+    bar.register(fixed("vt1").0);
+    assert!(bar.refresh(&mut writer, false));
+    // 暂停期间不碰屏幕
+    writer.put_char(ROW, 0, b'#', ColorCode::new(Color::White, Color::Black));
+    assert!(!bar.refresh(&mut writer, true));
+    assert_eq!(writer.read_char(ROW, 0).0, b'#');
+    // 恢复后一定重画，即使内容没有变
+    writer.put_char(ROW, 0, b'v', ColorCode::new(Color::Black, Color::LightGray));
+    assert!(bar.refresh(&mut writer, false));
+    assert!(!bar.refresh(&mut writer, false));
+
+    assert!(!paused());
+    pause();
+    pause();
+    resume();
+    assert!(paused());
+    resume();
+    assert!(!paused());
+    // 多余的 resume 不会让计数下溢
+    resume();
+    pause();
+    assert!(paused());
+    resume();
+}
+
+#[test_case]
+fn test_irq_rate() {
+    let mut rate = IrqRate::new();
+    assert_eq!(rate.update(1000, 1000), None);
+    assert_eq!(rate.update(1500, 1500), Some(1000));
+    // 时间没有前进
+    assert_eq!(rate.update(1500, 1600), None);
+    assert_eq!(rate.update(2500, 1700), Some(100));
+}
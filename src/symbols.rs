@@ -0,0 +1,365 @@
+//! 内嵌的符号表，把回溯中的返回地址解析为函数名
+//! 符号表由 scripts/ksyms.py 从构建好的内核 ELF 中提取，通过环境变量 KERNEL_SYMBOLS
+//! 交给 build.rs 嵌入到下一次构建中；没有提供时嵌入一个空表，回溯只打印原始地址
+//!
+//! 格式（小端）：
+//! - 头部 24 字节：魔数 "KSYM"、版本 u32、ksym_anchor 的地址 u64、符号数 u32、保留 u32
+//! - 按地址升序排列的符号，每个 24 字节：地址 u64、大小 u64（0 表示未知）、名字偏移 u32、名字长度 u32
+//! - 名字，偏移从名字区的开头算起
+//!
+//! 嵌入符号表本身会改变内核的大小，函数的地址可能因此移动，所以头部记录了 ksym_anchor 的地址，
+//! 与运行时的地址不同就说明符号表是另一次构建的，不再用它解析
+use core::fmt;
+
+pub const MAGIC: &[u8; 4] = b"KSYM";
+pub const VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 24;
+const ENTRY_SIZE: usize = 24;
+
+static KERNEL_SYMBOLS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/symbols.bin"));
+
+/// 生成脚本按名字找到这个函数并把它的地址写进头部
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn ksym_anchor() {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// 构建时没有提供符号表
+    Missing,
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// 长度与头部记录的符号数不符
+    Truncated,
+    /// 符号表来自另一次构建
+    Stale {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+/// 解析后的符号表，直接引用原始字节，不分配内存
+pub struct SymbolMap<'a> {
+    entries: &'a [u8],
+    names: &'a [u8],
+    anchor: u64,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+impl<'a> SymbolMap<'a> {
+    /// 检查头部，符号本身在查找时才读取
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, MapError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(MapError::Truncated);
+        }
+        if &bytes[..4] != MAGIC {
+            return Err(MapError::BadMagic);
+        }
+        let version = read_u32(bytes, 4);
+        if version != VERSION {
+            return Err(MapError::UnsupportedVersion(version));
+        }
+        let anchor = read_u64(bytes, 8);
+        let count = read_u32(bytes, 16) as usize;
+        if anchor == 0 && count == 0 {
+            return Err(MapError::Missing);
+        }
+        let entries_end = HEADER_SIZE + count * ENTRY_SIZE;
+        if bytes.len() < entries_end {
+            return Err(MapError::Truncated);
+        }
+        Ok(SymbolMap {
+            entries: &bytes[HEADER_SIZE..entries_end],
+            names: &bytes[entries_end..],
+            anchor,
+        })
+    }
+
+    /// 生成符号表时 ksym_anchor 的地址
+    pub fn anchor(&self) -> u64 {
+        self.anchor
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn address(&self, index: usize) -> u64 {
+        read_u64(self.entries, index * ENTRY_SIZE)
+    }
+
+    /// 返回包含 addr 的符号名以及 addr 相对它的偏移
+    /// 在第一个符号之前、超出符号大小时返回 None；大小未知的符号延伸到下一个符号，
+    /// 最后一个符号大小未知时只匹配它自己的地址
+    pub fn resolve(&self, addr: u64) -> Option<(&'a str, u64)> {
+        // 二分查找最后一个地址不大于 addr 的符号
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.address(mid) <= addr {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let index = low.checked_sub(1)?;
+
+        let entry = &self.entries[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE];
+        let offset = addr - read_u64(entry, 0);
+        let size = read_u64(entry, 8);
+        let last = index + 1 == self.len();
+        if (size != 0 && offset >= size) || (size == 0 && last && offset > 0) {
+            return None;
+        }
+        let name_start = read_u32(entry, 16) as usize;
+        let name_end = name_start + read_u32(entry, 20) as usize;
+        let name = core::str::from_utf8(self.names.get(name_start..name_end)?).ok()?;
+        Some((name, offset))
+    }
+}
+
+/// 内嵌的符号表，过期时返回 MapError::Stale
+pub fn kernel_map() -> Result<SymbolMap<'static>, MapError> {
+    let map = SymbolMap::parse(KERNEL_SYMBOLS)?;
+    let actual = ksym_anchor as *const () as u64;
+    if map.anchor() != actual {
+        return Err(MapError::Stale {
+            expected: map.anchor(),
+            actual,
+        });
+    }
+    Ok(map)
+}
+
+/// 用内嵌的符号表解析地址，符号表不可用时返回 None
+pub fn resolve(addr: u64) -> Option<(&'static str, u64)> {
+    kernel_map().ok()?.resolve(addr)
+}
+
+/// 显示时把 rustc 旧式修饰过的名字（_ZN...E）还原成路径：去掉末尾的哈希，
+/// 还原 $LT$、$u20$ 之类的转义，把 .. 换成 ::；其他格式的名字原样显示
+pub struct Demangle<'a>(pub &'a str);
+
+impl fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(inner) = legacy_inner(self.0) else {
+            return f.write_str(self.0);
+        };
+        let mut parts = Parts(inner).peekable();
+        let mut first = true;
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() && is_hash(part) {
+                break;
+            }
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+            write_part(f, part)?;
+        }
+        Ok(())
+    }
+}
+
+/// 去掉 _ZN 和 E，并检查中间的长度前缀都是完整的
+fn legacy_inner(symbol: &str) -> Option<&str> {
+    let inner = symbol.strip_prefix("_ZN")?.strip_suffix('E')?;
+    let mut rest = inner;
+    while !rest.is_empty() {
+        rest = split_part(rest)?.1;
+    }
+    (!inner.is_empty()).then_some(inner)
+}
+
+/// 拆出开头的一段 "<长度><内容>"
+fn split_part(s: &str) -> Option<(&str, &str)> {
+    let digits = s.bytes().take_while(u8::is_ascii_digit).count();
+    let len: usize = s[..digits].parse().ok()?;
+    let rest = &s[digits..];
+    if len > rest.len() || !rest.is_char_boundary(len) {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+struct Parts<'a>(&'a str);
+
+impl<'a> Iterator for Parts<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let (part, rest) = split_part(self.0)?;
+        self.0 = rest;
+        Some(part)
+    }
+}
+
+/// 末尾的 "h" 加 16 位十六进制数是区分不同 crate 版本的哈希
+fn is_hash(part: &str) -> bool {
+    part.len() == 17
+        && part.starts_with('h')
+        && part[1..].bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn write_part(f: &mut fmt::Formatter, part: &str) -> fmt::Result {
+    // 以 $ 开头的部分前面会多一个下划线
+    let mut rest = match part.strip_prefix('_') {
+        Some(stripped) if stripped.starts_with('$') => stripped,
+        _ => part,
+    };
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("..") {
+            f.write_str("::")?;
+            rest = after;
+        } else if let Some((escape, after)) = rest
+            .strip_prefix('$')
+            .and_then(|body| body.split_once('$'))
+            .and_then(|(code, after)| Some((unescape(code)?, after)))
+        {
+            fmt::Write::write_char(f, escape)?;
+            rest = after;
+        } else {
+            fmt::Write::write_char(f, c)?;
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Ok(())
+}
+
+fn unescape(code: &str) -> Option<char> {
+    Some(match code {
+        "SP" => '@',
+        "BP" => '*',
+        "RF" => '&',
+        "LT" => '<',
+        "GT" => '>',
+        "LP" => '(',
+        "RP" => ')',
+        "C" => ',',
+        _ => char::from_u32(u32::from_str_radix(code.strip_prefix('u')?, 16).ok()?)?,
+    })
+}
+
+#[cfg(test)]
+use alloc::{string::ToString, vec::Vec};
+
+/// 按 scripts/ksyms.py 的格式构造符号表
+#[cfg(test)]
+fn build_map(anchor: u64, symbols: &[(u64, u64, &str)]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&anchor.to_le_bytes());
+    bytes.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    let mut name_offset = 0;
+    for &(address, size, name) in symbols {
+        bytes.extend_from_slice(&address.to_le_bytes());
+        bytes.extend_from_slice(&size.to_le_bytes());
+        bytes.extend_from_slice(&(name_offset as u32).to_le_bytes());
+        bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        name_offset += name.len();
+    }
+    for &(_, _, name) in symbols {
+        bytes.extend_from_slice(name.as_bytes());
+    }
+    bytes
+}
+
+#[test_case]
+fn test_resolve_boundaries() {
+    let bytes = build_map(
+        0x1000,
+        &[(0x1000, 0x10, "a"), (0x1010, 0, "b"), (0x1100, 0, "c")],
+    );
+    let map = SymbolMap::parse(&bytes).unwrap();
+    assert_eq!(map.len(), 3);
+    // 第一个符号之前
+    assert_eq!(map.resolve(0xfff), None);
+    assert_eq!(map.resolve(0), None);
+    assert_eq!(map.resolve(0x1000), Some(("a", 0)));
+    assert_eq!(map.resolve(0x100f), Some(("a", 0xf)));
+    // 大小未知的符号一直延伸到下一个符号
+    assert_eq!(map.resolve(0x1010), Some(("b", 0)));
+    assert_eq!(map.resolve(0x10ff), Some(("b", 0xef)));
+    assert_eq!(map.resolve(0x1100), Some(("c", 0)));
+    // 最后一个符号之后
+    assert_eq!(map.resolve(0x1101), None);
+    assert_eq!(map.resolve(u64::MAX), None);
+
+    // 有大小时超出的部分不属于它
+    let bytes = build_map(0x1000, &[(0x1000, 0x10, "a"), (0x1020, 0x8, "b")]);
+    let map = SymbolMap::parse(&bytes).unwrap();
+    assert_eq!(map.resolve(0x1010), None);
+    assert_eq!(map.resolve(0x1027), Some(("b", 7)));
+    assert_eq!(map.resolve(0x1028), None);
+}
+
+#[test_case]
+fn test_parse_rejects_bad_maps() {
+    let bytes = build_map(0x1000, &[(0x1000, 0x10, "a")]);
+    assert!(SymbolMap::parse(&bytes).is_ok());
+    assert_eq!(
+        SymbolMap::parse(&bytes[..HEADER_SIZE + 8]).err(),
+        Some(MapError::Truncated)
+    );
+    assert_eq!(
+        SymbolMap::parse(&bytes[..10]).err(),
+        Some(MapError::Truncated)
+    );
+
+    let mut bad = bytes.clone();
+    bad[0] = b'X';
+    assert_eq!(SymbolMap::parse(&bad).err(), Some(MapError::BadMagic));
+    let mut bad = bytes.clone();
+    bad[4] = 2;
+    assert_eq!(
+        SymbolMap::parse(&bad).err(),
+        Some(MapError::UnsupportedVersion(2))
+    );
+    assert_eq!(
+        SymbolMap::parse(&build_map(0, &[])).err(),
+        Some(MapError::Missing)
+    );
+}
+
+#[test_case]
+fn test_demangle_legacy_names() {
+    let cases = [
+        (
+            "_ZN5vm_os10vga_buffer6Writer8new_line17h0123456789abcdefE",
+            "vm_os::vga_buffer::Writer::new_line",
+        ),
+        (
+            "_ZN62_$LT$vm_os..ps2..Controller$u20$as$u20$vm_os..ps2..Ps2Port$GT$5write17hfedcba9876543210E",
+            "<vm_os::ps2::Controller as vm_os::ps2::Ps2Port>::write",
+        ),
+        (
+            "_ZN5vm_os5shell3run27$u7b$$u7b$closure$u7d$$u7d$17h00000000000000ffE",
+            "vm_os::shell::run::{{closure}}",
+        ),
+        ("_ZN4core3ptr13drop_in_placeE", "core::ptr::drop_in_place"),
+        // 不是旧式修饰或者格式不完整时原样显示
+        ("ksym_anchor", "ksym_anchor"),
+        ("_RNvCs1234_5vm_os4main", "_RNvCs1234_5vm_os4main"),
+        ("_ZN5vm_os3fooE2", "_ZN5vm_os3fooE2"),
+        ("_ZN9vm_osE", "_ZN9vm_osE"),
+        // 不认识的转义保留原样
+        ("_ZN3a$Q$E", "a$Q$"),
+    ];
+    for (mangled, demangled) in cases {
+        assert_eq!(Demangle(mangled).to_string(), demangled);
+    }
+}
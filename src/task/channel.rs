@@ -0,0 +1,248 @@
+//! 任务之间的有界多生产者单消费者（MPSC）通道
+//!
+//! 数据放在固定容量的 ArrayQueue 中：
+//! - 接收方的 waker 存放在 AtomicWaker 里，try_send 可以在中断处理函数中调用，既不加锁也不分配内存
+//! - 队列满时等待的发送方可能有多个，它们的 waker 存放在一个只在任务上下文中访问的列表里
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+struct Shared<T> {
+    queue: ArrayQueue<T>,
+    receiver_waker: AtomicWaker,
+    sender_wakers: Mutex<Vec<Waker>>,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+}
+
+impl<T> Shared<T> {
+    fn wake_senders(&self) {
+        for waker in self.sender_wakers.lock().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// 创建容量为 capacity 的通道
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: ArrayQueue::new(capacity),
+        receiver_waker: AtomicWaker::new(),
+        sender_wakers: Mutex::new(Vec::new()),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// 接收方已经被 drop，未发送的数据原样返回
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// 队列已满
+    Full(T),
+    /// 接收方已经被 drop
+    Closed(T),
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// 队列满时等待，直到接收方取走数据
+    pub async fn send(&self, item: T) -> Result<(), SendError<T>> {
+        let mut item = Some(item);
+        poll_fn(|cx| self.poll_send(cx, &mut item)).await
+    }
+
+    fn poll_send(&self, cx: &mut Context, item: &mut Option<T>) -> Poll<Result<(), SendError<T>>> {
+        let value = item.take().expect("send polled after completion");
+        let value = match self.try_send(value) {
+            Ok(()) => return Poll::Ready(Ok(())),
+            Err(TrySendError::Closed(value)) => return Poll::Ready(Err(SendError(value))),
+            Err(TrySendError::Full(value)) => value,
+        };
+
+        {
+            let mut wakers = self.shared.sender_wakers.lock();
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+        // 注册之后再试一次：接收方可能在第一次尝试和注册之间取走了数据
+        match self.try_send(value) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(TrySendError::Closed(value)) => Poll::Ready(Err(SendError(value))),
+            Err(TrySendError::Full(value)) => {
+                *item = Some(value);
+                Poll::Pending
+            }
+        }
+    }
+
+    /// 不等待的发送，不加锁也不分配内存，可以在中断处理函数中调用
+    pub fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        if !self.shared.receiver_alive.load(Ordering::SeqCst) {
+            return Err(TrySendError::Closed(item));
+        }
+        match self.shared.queue.push(item) {
+            Ok(()) => {
+                self.shared.receiver_waker.wake();
+                Ok(())
+            }
+            Err(item) => Err(TrySendError::Full(item)),
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::SeqCst);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // 最后一个发送方离开时唤醒接收方，让它看到通道已关闭
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.receiver_waker.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// 等待下一个数据；所有发送方都被 drop 并且队列已空时返回 None
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    fn poll_recv(&self, cx: &mut Context) -> Poll<Option<T>> {
+        if let Some(item) = self.try_recv() {
+            return Poll::Ready(Some(item));
+        }
+
+        // 注册 waker 后再检查一次，避免错过两次检查之间的发送或关闭
+        self.shared.receiver_waker.register(cx.waker());
+        if let Some(item) = self.try_recv() {
+            self.shared.receiver_waker.take();
+            return Poll::Ready(Some(item));
+        }
+        if self.shared.senders.load(Ordering::SeqCst) == 0 {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+
+    /// 不等待的接收
+    pub fn try_recv(&self) -> Option<T> {
+        let item = self.shared.queue.pop()?;
+        self.shared.wake_senders();
+        Some(item)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::SeqCst);
+        self.shared.wake_senders();
+    }
+}
+
+#[cfg(test)]
+use super::simple_executor::SimpleExecutor;
+#[cfg(test)]
+use super::Task;
+#[cfg(test)]
+use alloc::boxed::Box;
+#[cfg(test)]
+use core::future::Future;
+
+#[test_case]
+fn test_send_waits_when_full() {
+    let (sender, mut receiver) = channel(1);
+    let waker = Waker::noop();
+    let mut cx = Context::from_waker(waker);
+
+    assert_eq!(sender.try_send(1), Ok(()));
+    let mut send = Box::pin(sender.send(2));
+    assert!(send.as_mut().poll(&mut cx).is_pending());
+
+    // 接收方取走数据后，等待中的发送可以完成
+    let mut recv = Box::pin(receiver.recv());
+    assert_eq!(recv.as_mut().poll(&mut cx), Poll::Ready(Some(1)));
+    drop(recv);
+    assert_eq!(send.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+    assert_eq!(receiver.try_recv(), Some(2));
+}
+
+#[test_case]
+fn test_try_send_never_blocks() {
+    let (sender, receiver) = channel(2);
+    assert_eq!(sender.try_send(1), Ok(()));
+    assert_eq!(sender.try_send(2), Ok(()));
+    assert_eq!(sender.try_send(3), Err(TrySendError::Full(3)));
+    drop(receiver);
+    assert_eq!(sender.try_send(4), Err(TrySendError::Closed(4)));
+}
+
+#[test_case]
+fn test_recv_returns_none_after_senders_dropped() {
+    let (sender, mut receiver) = channel(4);
+    let second = sender.clone();
+    sender.try_send(7).unwrap();
+    drop(sender);
+    drop(second);
+
+    let waker = Waker::noop();
+    let mut cx = Context::from_waker(waker);
+    let mut recv = Box::pin(receiver.recv());
+    // 关闭前发送的数据仍然可以收到
+    assert_eq!(recv.as_mut().poll(&mut cx), Poll::Ready(Some(7)));
+    drop(recv);
+    let mut recv = Box::pin(receiver.recv());
+    assert_eq!(recv.as_mut().poll(&mut cx), Poll::Ready(None));
+}
+
+#[test_case]
+fn test_ping_pong_between_tasks() {
+    static ROUNDS: AtomicUsize = AtomicUsize::new(0);
+
+    let (ping_tx, mut ping_rx) = channel::<u32>(1);
+    let (pong_tx, mut pong_rx) = channel::<u32>(1);
+
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(async move {
+        for n in 0..5 {
+            ping_tx.send(n).await.unwrap();
+            assert_eq!(pong_rx.recv().await, Some(n + 1));
+            ROUNDS.fetch_add(1, Ordering::SeqCst);
+        }
+    }));
+    executor.spawn(Task::new(async move {
+        while let Some(n) = ping_rx.recv().await {
+            pong_tx.send(n + 1).await.unwrap();
+        }
+    }));
+    executor.run();
+    assert_eq!(ROUNDS.load(Ordering::SeqCst), 5);
+}
@@ -0,0 +1,576 @@
+//! 基于唤醒的执行器
+//! 只轮询被唤醒的任务；没有任务可运行时用 hlt 让 CPU 休眠，直到下一个中断到来
+//!
+//! 主循环一轮一轮地运行：每一轮按唤醒顺序轮询这一轮开始时已经被唤醒的任务，
+//! 轮询期间被唤醒的任务（包括唤醒自己的任务）排到队尾、下一轮才轮到。
+//! 两轮之间处理定时器、喂看门狗，所以反复唤醒自己的任务不会让其他任务饿死
+use super::{Task, TaskId};
+use crate::vga_buffer::PadRight;
+use crate::{println, time, watchdog};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
+
+/// 唤醒队列的容量，中断处理函数中不能分配内存，所以使用固定容量的队列
+const TASK_QUEUE_CAPACITY: usize = 100;
+
+/// 所有执行器中存活任务的元数据，TaskId 全局唯一，所以多个执行器可以共用一张表
+/// 只在任务上下文中加锁，中断处理函数（waker）不会访问它
+static TASK_TABLE: Mutex<BTreeMap<TaskId, TaskInfo>> = Mutex::new(BTreeMap::new());
+
+/// 唤醒队列已满而丢弃的唤醒次数
+static LOST_WAKES: AtomicU64 = AtomicU64::new(0);
+
+struct TaskInfo {
+    name: &'static str,
+    polls: u64,
+    abort_requested: bool,
+    waker: Arc<TaskWaker>,
+}
+
+impl TaskInfo {
+    fn snapshot(&self, id: TaskId) -> TaskSnapshot {
+        TaskSnapshot {
+            id,
+            name: self.name,
+            state: if self.waker.scheduled.load(Ordering::SeqCst) {
+                TaskState::Ready
+            } else {
+                TaskState::Waiting
+            },
+            polls: self.polls,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// 已被唤醒，等待轮询
+    Ready,
+    /// 上次轮询返回了 Pending，等待被唤醒
+    Waiting,
+}
+
+/// 任务列表中的一项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskSnapshot {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub state: TaskState,
+    pub polls: u64,
+}
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    /// 被唤醒、等待轮询的任务 id，由执行器和所有 waker 共享
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    /// 每个任务的 waker 只创建一次，之后每次轮询都复用
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_CAPACITY)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    /// 新任务会立即进入唤醒队列，保证至少被轮询一次
+    pub fn spawn(&mut self, task: Task) -> TaskId {
+        let task_id = task.id;
+        let task_waker = Arc::new(TaskWaker {
+            task_id,
+            task_queue: self.task_queue.clone(),
+            scheduled: AtomicBool::new(false),
+        });
+        TASK_TABLE.lock().insert(
+            task_id,
+            TaskInfo {
+                name: task.name,
+                polls: 0,
+                abort_requested: false,
+                waker: task_waker.clone(),
+            },
+        );
+        if self.tasks.insert(task_id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.waker_cache
+            .insert(task_id, Waker::from(task_waker.clone()));
+        task_waker.wake_task();
+        task_id
+    }
+
+    /// 以指定的名称创建任务，名称会出现在 print_tasks 的输出中
+    pub fn spawn_named(
+        &mut self,
+        name: &'static str,
+        future: impl Future<Output = ()> + 'static,
+    ) -> TaskId {
+        self.spawn(Task::named(name, future))
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            watchdog::pet();
+            time::process_timers();
+            self.run_round();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// 处理到期的定时器，然后轮询任务直到没有任务被唤醒，不休眠，返回尚未完成的任务数。
+    /// 测试中用它代替永不返回的 run；有任务不断唤醒自己时不会返回
+    pub fn run_until_idle(&mut self) -> usize {
+        time::process_timers();
+        self.run_ready_tasks();
+        self.tasks.len()
+    }
+
+    /// 一轮接一轮地运行，直到队列为空
+    fn run_ready_tasks(&mut self) {
+        while !self.task_queue.is_empty() {
+            self.run_round();
+        }
+    }
+
+    /// 按唤醒顺序（先进先出）轮询这一轮开始时队列中的任务，之后被唤醒的任务留到下一轮
+    fn run_round(&mut self) {
+        // 解构 self，避免闭包借用整个执行器
+        let Self {
+            tasks,
+            task_queue,
+            waker_cache,
+        } = self;
+
+        for _ in 0..task_queue.len() {
+            let Some(task_id) = task_queue.pop() else {
+                break;
+            };
+            let task = match tasks.get_mut(&task_id) {
+                Some(task) => task,
+                // 任务已经完成或被终止，但之前的唤醒还留在队列里
+                None => continue,
+            };
+
+            let aborted = {
+                let mut table = TASK_TABLE.lock();
+                let info = table.get_mut(&task_id).expect("task missing from table");
+                // 先清除标志再轮询，轮询期间的唤醒会让任务重新入队
+                info.waker.scheduled.store(false, Ordering::SeqCst);
+                if !info.abort_requested {
+                    info.polls += 1;
+                }
+                info.abort_requested
+            };
+            if aborted {
+                remove_task(tasks, waker_cache, task_id);
+                continue;
+            }
+
+            let waker = &waker_cache[&task_id];
+            let mut context = Context::from_waker(waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => remove_task(tasks, waker_cache, task_id),
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    /// 检查和休眠之间如果来了中断并唤醒了任务，这次唤醒就会丢失，直到下一个中断才被处理。
+    /// 所以先关中断再检查，然后用 sti; hlt 原子地开中断并休眠
+    fn sleep_if_idle(&self) {
+        use x86_64::instructions::interrupts::{self, enable_and_hlt};
+
+        interrupts::disable();
+        if self.should_sleep() {
+            enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+
+    /// 必须在关中断的状态下调用
+    /// 有到期但还未处理的定时器时也不能休眠，否则要等到下一个中断才会唤醒它们
+    fn should_sleep(&self) -> bool {
+        self.task_queue.is_empty() && !time::timers_expired()
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        let mut table = TASK_TABLE.lock();
+        for task_id in self.tasks.keys() {
+            table.remove(task_id);
+        }
+    }
+}
+
+fn remove_task(
+    tasks: &mut BTreeMap<TaskId, Task>,
+    waker_cache: &mut BTreeMap<TaskId, Waker>,
+    task_id: TaskId,
+) {
+    tasks.remove(&task_id);
+    waker_cache.remove(&task_id);
+    TASK_TABLE.lock().remove(&task_id);
+}
+
+/// 终止一个任务，任务的 Future 会在执行器下一次处理唤醒队列时被 drop，之后不会再被轮询。
+/// 如果任务已经在唤醒队列中，队列里的这一项会直接触发 drop；
+/// 正在运行的任务终止自己时，本次轮询照常结束，随后被 drop。
+/// 任务不存在时返回 false
+pub fn abort(task_id: TaskId) -> bool {
+    let waker = {
+        let mut table = TASK_TABLE.lock();
+        match table.get_mut(&task_id) {
+            Some(info) => {
+                info.abort_requested = true;
+                info.waker.clone()
+            }
+            None => return false,
+        }
+    };
+    waker.wake_task();
+    true
+}
+
+/// 唤醒队列已满而丢弃的唤醒次数，不为 0 说明同时被唤醒的任务超过了队列容量
+pub fn lost_wakes() -> u64 {
+    LOST_WAKES.load(Ordering::Relaxed)
+}
+
+/// 所有存活任务的快照，按 id 排序
+pub fn task_list() -> Vec<TaskSnapshot> {
+    TASK_TABLE
+        .lock()
+        .iter()
+        .map(|(id, info)| info.snapshot(*id))
+        .collect()
+}
+
+/// 不等待锁、不分配内存地把任务列表写入 out，供中断处理函数使用；
+/// 被打断的代码正持有 TASK_TABLE 时返回 None
+pub fn try_write_tasks(out: &mut impl fmt::Write) -> Option<fmt::Result> {
+    let table = TASK_TABLE.try_lock()?;
+    Some(write_tasks(
+        out,
+        table.iter().map(|(id, info)| info.snapshot(*id)),
+    ))
+}
+
+/// 把任务列表按 "id 名称 状态 轮询次数" 的表格写入 out
+pub fn write_tasks(
+    out: &mut impl fmt::Write,
+    tasks: impl IntoIterator<Item = TaskSnapshot>,
+) -> fmt::Result {
+    writeln!(
+        out,
+        "{:>4}  {:<16}  {:<7}  {:>8}",
+        "ID", "NAME", "STATE", "POLLS"
+    )?;
+    for task in tasks {
+        let state = match task.state {
+            TaskState::Ready => "ready",
+            TaskState::Waiting => "waiting",
+        };
+        // 名字可能含有宽字符，按显示宽度补齐
+        writeln!(
+            out,
+            "{:>4}  {}  {:<7}  {:>8}",
+            task.id.as_u64(),
+            PadRight(task.name, 16),
+            state,
+            task.polls
+        )?;
+    }
+    Ok(())
+}
+
+/// 在屏幕上打印任务列表
+pub fn print_tasks() {
+    use alloc::string::String;
+
+    // 先格式化到字符串，避免在持有 TASK_TABLE 时再去锁 WRITER
+    let mut table = String::new();
+    write_tasks(&mut table, task_list()).unwrap();
+    println!("{}", table.trim_end());
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    /// 任务是否已经在唤醒队列中，重复的唤醒只入队一次
+    scheduled: AtomicBool,
+}
+
+impl TaskWaker {
+    /// 只向固定容量的队列推入 id，不分配内存，可以在中断处理函数中调用。
+    /// 队列已满时不能 panic（可能在中断处理函数中），丢弃这次唤醒并计数，
+    /// 同时清除标志，下一次唤醒还能让任务入队
+    fn wake_task(&self) {
+        if !self.scheduled.swap(true, Ordering::SeqCst)
+            && self.task_queue.push(self.task_id).is_err()
+        {
+            self.scheduled.store(false, Ordering::SeqCst);
+            LOST_WAKES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+#[cfg(test)]
+use super::{yield_now, yield_times};
+#[cfg(test)]
+use crate::vga_buffer::display_width;
+#[cfg(test)]
+use core::future::poll_fn;
+#[cfg(test)]
+use core::sync::atomic::AtomicUsize;
+
+#[cfg(test)]
+fn snapshot(id: TaskId) -> Option<TaskSnapshot> {
+    task_list().into_iter().find(|task| task.id == id)
+}
+
+#[test_case]
+fn test_wake_queue_is_fifo() {
+    static ORDER: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    async fn record(n: u32) {
+        ORDER.lock().push(n);
+        yield_times(1).await;
+        ORDER.lock().push(n + 10);
+    }
+
+    let mut executor = Executor::new();
+    for n in 0..3 {
+        executor.spawn(Task::new(record(n)));
+    }
+    executor.run_ready_tasks();
+    assert_eq!(*ORDER.lock(), [0, 1, 2, 10, 11, 12]);
+    assert!(executor.tasks.is_empty());
+    assert!(executor.waker_cache.is_empty());
+}
+
+#[test_case]
+fn test_self_waking_task_does_not_starve_others() {
+    static SPINS: AtomicUsize = AtomicUsize::new(0);
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    async fn spin() {
+        loop {
+            SPINS.fetch_add(1, Ordering::SeqCst);
+            yield_now().await;
+        }
+    }
+
+    async fn count() {
+        for _ in 0..3 {
+            COUNT.fetch_add(1, Ordering::SeqCst);
+            yield_now().await;
+        }
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(spin()));
+    let counter = executor.spawn(Task::new(count()));
+    // 每一轮两个任务各轮询一次，唤醒自己的任务不会在同一轮中再次被轮询
+    for round in 1..=3 {
+        executor.run_round();
+        assert_eq!(SPINS.load(Ordering::SeqCst), round);
+        assert_eq!(COUNT.load(Ordering::SeqCst), round);
+    }
+    executor.run_round();
+    assert_eq!(SPINS.load(Ordering::SeqCst), 4);
+    assert!(!executor.tasks.contains_key(&counter));
+    // 队列中始终只有唤醒自己的任务
+    assert_eq!(executor.task_queue.len(), 1);
+}
+
+#[test_case]
+fn test_full_queue_drops_wake_without_panicking() {
+    let mut executor = Executor::new();
+    let lost_before = lost_wakes();
+    for _ in 0..TASK_QUEUE_CAPACITY {
+        executor.spawn(Task::new(async {}));
+    }
+    let extra = executor.spawn(Task::new(async {}));
+    assert_eq!(lost_wakes(), lost_before + 1);
+    assert_eq!(snapshot(extra).unwrap().state, TaskState::Waiting);
+
+    // 队列腾出空间后，再次唤醒可以让任务入队
+    executor.run_ready_tasks();
+    executor.waker_cache[&extra].wake_by_ref();
+    executor.run_ready_tasks();
+    assert!(executor.tasks.is_empty());
+}
+
+#[test_case]
+fn test_wakeup_before_sleep_is_not_lost() {
+    static WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(poll_fn(|cx| {
+        *WAKER.lock() = Some(cx.waker().clone());
+        Poll::<()>::Pending
+    })));
+    executor.run_ready_tasks();
+    assert!(executor.should_sleep());
+
+    // 模拟中断恰好在 run_ready_tasks 之后、sleep_if_idle 之前到来
+    WAKER.lock().as_ref().unwrap().wake_by_ref();
+    assert!(!executor.should_sleep());
+    executor.run_ready_tasks();
+    assert!(executor.should_sleep());
+}
+
+#[test_case]
+fn test_interrupt_wake_polls_once() {
+    static POLLS: AtomicUsize = AtomicUsize::new(0);
+    static WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(poll_fn(|cx| {
+        POLLS.fetch_add(1, Ordering::SeqCst);
+        *WAKER.lock() = Some(cx.waker().clone());
+        Poll::<()>::Pending
+    })));
+    executor.run_ready_tasks();
+    assert_eq!(POLLS.load(Ordering::SeqCst), 1);
+
+    // 没有唤醒就不会再被轮询
+    executor.run_ready_tasks();
+    assert_eq!(POLLS.load(Ordering::SeqCst), 1);
+
+    // 在关中断的上下文中唤醒两次，模拟中断处理函数，重复的唤醒只会带来一次轮询
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let waker = WAKER.lock();
+        waker.as_ref().unwrap().wake_by_ref();
+        waker.as_ref().unwrap().wake_by_ref();
+    });
+    executor.run_ready_tasks();
+    assert_eq!(POLLS.load(Ordering::SeqCst), 2);
+}
+
+#[test_case]
+fn test_task_ids_are_unique() {
+    let mut executor = Executor::new();
+    let ids: Vec<TaskId> = (0..10)
+        .map(|_| executor.spawn_named("unique", async {}))
+        .collect();
+    for (i, a) in ids.iter().enumerate() {
+        for b in &ids[i + 1..] {
+            assert_ne!(a, b);
+        }
+    }
+}
+
+#[test_case]
+fn test_metadata_tracks_state_and_polls() {
+    static WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+
+    let mut executor = Executor::new();
+    let id = executor.spawn_named(
+        "metadata",
+        poll_fn(|cx| {
+            *WAKER.lock() = Some(cx.waker().clone());
+            Poll::<()>::Pending
+        }),
+    );
+    let info = snapshot(id).unwrap();
+    assert_eq!(
+        (info.name, info.state, info.polls),
+        ("metadata", TaskState::Ready, 0)
+    );
+
+    executor.run_ready_tasks();
+    let info = snapshot(id).unwrap();
+    assert_eq!((info.state, info.polls), (TaskState::Waiting, 1));
+
+    WAKER.lock().as_ref().unwrap().wake_by_ref();
+    assert_eq!(snapshot(id).unwrap().state, TaskState::Ready);
+    executor.run_ready_tasks();
+    assert_eq!(snapshot(id).unwrap().polls, 2);
+
+    drop(executor);
+    assert_eq!(snapshot(id), None);
+}
+
+#[test_case]
+fn test_abort_waiting_and_ready_tasks() {
+    static POLLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn pending() -> impl Future<Output = ()> {
+        poll_fn(|_| {
+            POLLS.fetch_add(1, Ordering::SeqCst);
+            Poll::Pending
+        })
+    }
+
+    let mut executor = Executor::new();
+    let waiting = executor.spawn_named("waiting", pending());
+    executor.run_ready_tasks();
+    assert_eq!(POLLS.load(Ordering::SeqCst), 1);
+
+    // 刚创建的任务还在唤醒队列里
+    let ready = executor.spawn_named("ready", pending());
+    assert!(abort(waiting));
+    assert!(abort(ready));
+    executor.run_ready_tasks();
+
+    // 两个任务都没有再被轮询，并且已经被移除
+    assert_eq!(POLLS.load(Ordering::SeqCst), 1);
+    assert!(executor.tasks.is_empty());
+    assert_eq!(snapshot(waiting), None);
+    assert!(!abort(ready));
+}
+
+#[test_case]
+fn test_write_tasks_matches_tracked_state() {
+    use alloc::string::String;
+
+    let mut executor = Executor::new();
+    let id = executor.spawn_named("printer", poll_fn(|_| Poll::<()>::Pending));
+    executor.run_ready_tasks();
+
+    let mut out = String::new();
+    write_tasks(&mut out, [snapshot(id).unwrap()]).unwrap();
+    let expected = alloc::format!(
+        "  ID  NAME              STATE       POLLS\n{:>4}  printer           waiting         1\n",
+        id.as_u64()
+    );
+    assert_eq!(out, expected);
+
+    // 名字含宽字符时后面的列仍然对齐
+    let id = executor.spawn_named("定时器", poll_fn(|_| Poll::<()>::Pending));
+    let mut out = String::new();
+    write_tasks(&mut out, [snapshot(id).unwrap()]).unwrap();
+    let row = out.lines().nth(1).unwrap();
+    let state = row.find("ready").unwrap();
+    assert_eq!(display_width(&row[..state]), 24);
+}
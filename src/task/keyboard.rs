@@ -0,0 +1,233 @@
+//! 异步键盘输入
+//! 键盘中断处理函数只把扫描码放进固定容量的队列并唤醒消费者，解码和打印都在异步任务中完成
+use crate::keyboard::{self, DecodedKey, KeyInput};
+use crate::{faultinject, print, println};
+use conquer_once::spin::OnceCell;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::future;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+
+const SCANCODE_QUEUE_CAPACITY: usize = 100;
+
+/// 中断处理函数不能分配内存，所以队列在 init_queue 中创建，
+/// 在此之前 IRQ1 一直处于屏蔽状态
+static SCANCODE_QUEUE: OnceCell<ScancodeQueue> = OnceCell::uninit();
+
+/// 扫描码队列以及消费者的 waker
+struct ScancodeQueue {
+    queue: ArrayQueue<u8>,
+    waker: AtomicWaker,
+    /// 队列已满时被丢弃的扫描码数量
+    dropped: AtomicU64,
+}
+
+impl ScancodeQueue {
+    fn new(capacity: usize) -> Self {
+        ScancodeQueue {
+            queue: ArrayQueue::new(capacity),
+            waker: AtomicWaker::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// 不阻塞、不分配内存，可以在中断处理函数中调用
+    fn push(&self, scancode: u8) {
+        if faultinject::should_fail(faultinject::SCANCODE_PUSH)
+            || self.queue.push(scancode).is_err()
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.waker.wake();
+        }
+    }
+
+    fn poll_next(&self, cx: &mut Context) -> Poll<Option<u8>> {
+        // 快速路径：队列不空就不必注册 waker
+        if let Some(scancode) = self.queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        // 先注册 waker 再检查一次：
+        // 如果扫描码恰好在第一次检查和注册之间到达，中断处理函数调用 wake 时 waker 还未注册，
+        // 第二次检查就能取到它，不会丢失
+        self.waker.register(cx.waker());
+        match self.queue.pop() {
+            Some(scancode) => {
+                self.waker.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// 由键盘中断处理函数调用
+pub(crate) fn add_scancode(scancode: u8) {
+    match SCANCODE_QUEUE.try_get() {
+        Ok(queue) => queue.push(scancode),
+        // IRQ1 在队列初始化前是屏蔽的，正常情况下不会走到这里
+        Err(_) => {
+            UNINITIALIZED_DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+static UNINITIALIZED_DROPS: AtomicU64 = AtomicU64::new(0);
+
+/// 因队列已满或尚未初始化而丢弃的扫描码数量
+pub fn dropped_scancodes() -> u64 {
+    let dropped = SCANCODE_QUEUE
+        .try_get()
+        .map(|queue| queue.dropped.load(Ordering::Relaxed))
+        .unwrap_or(0);
+    dropped + UNINITIALIZED_DROPS.load(Ordering::Relaxed)
+}
+
+/// 创建扫描码队列并解除 IRQ1 的屏蔽，重复调用没有影响
+pub fn init_queue() {
+    let _ = SCANCODE_QUEUE.try_init_once(|| ScancodeQueue::new(SCANCODE_QUEUE_CAPACITY));
+    crate::interrupts::unmask_irq(crate::interrupts::KEYBOARD_IRQ);
+}
+
+/// 不阻塞地取出最早的扫描码，队列为空或尚未创建时返回 None
+/// 供不经过执行器的轮询读取使用，不要和 ScancodeStream 同时消费队列
+pub fn try_next_scancode() -> Option<u8> {
+    SCANCODE_QUEUE.try_get().ok()?.queue.pop()
+}
+
+/// 队列中是否有尚未取走的扫描码
+pub fn scancode_pending() -> bool {
+    SCANCODE_QUEUE
+        .try_get()
+        .is_ok_and(|queue| !queue.queue.is_empty())
+}
+
+static STREAM_CREATED: AtomicBool = AtomicBool::new(false);
+
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScancodeStream {
+    /// 创建扫描码队列并解除 IRQ1 的屏蔽，只能调用一次
+    pub fn new() -> Self {
+        assert!(
+            !STREAM_CREATED.swap(true, Ordering::Relaxed),
+            "ScancodeStream::new should only be called once"
+        );
+        init_queue();
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        SCANCODE_QUEUE
+            .try_get()
+            .expect("scancode queue not initialized")
+            .poll_next(cx)
+    }
+}
+
+/// 把扫描码流解码为按键流，前缀字节不会产生按键
+pub fn key_inputs(scancodes: ScancodeStream) -> impl Stream<Item = KeyInput> + Unpin {
+    scancodes.filter_map(|scancode| future::ready(keyboard::decode_input(scancode)))
+}
+
+/// 解码按键并打印到屏幕上
+pub async fn print_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+    let mut reported_drops = 0;
+
+    while let Some(scancode) = scancodes.next().await {
+        let dropped = dropped_scancodes();
+        if dropped != reported_drops {
+            println!("WARNING: {} scancodes dropped", dropped - reported_drops);
+            reported_drops = dropped;
+        }
+        if let Some(key) = keyboard::decode(scancode) {
+            match key {
+                DecodedKey::Unicode(character) => print!("{}", character),
+                DecodedKey::RawKey(key) => print!("{:?}", key),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use alloc::sync::Arc;
+#[cfg(test)]
+use alloc::task::Wake;
+#[cfg(test)]
+use core::task::Waker;
+
+#[cfg(test)]
+struct CountingWaker(AtomicU64);
+
+#[cfg(test)]
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn test_scancodes_are_delivered_in_order() {
+    let queue = ScancodeQueue::new(4);
+    let waker = Waker::noop();
+    let mut cx = Context::from_waker(waker);
+    queue.push(0x1e);
+    queue.push(0x9e);
+    assert_eq!(queue.poll_next(&mut cx), Poll::Ready(Some(0x1e)));
+    assert_eq!(queue.poll_next(&mut cx), Poll::Ready(Some(0x9e)));
+    assert_eq!(queue.poll_next(&mut cx), Poll::Pending);
+}
+
+#[test_case]
+fn test_push_wakes_registered_consumer() {
+    let queue = ScancodeQueue::new(4);
+    let counter = Arc::new(CountingWaker(AtomicU64::new(0)));
+    let waker = Waker::from(counter.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    assert_eq!(queue.poll_next(&mut cx), Poll::Pending);
+    // 模拟中断处理函数推入扫描码
+    queue.push(0x10);
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    assert_eq!(queue.poll_next(&mut cx), Poll::Ready(Some(0x10)));
+}
+
+#[test_case]
+fn test_full_queue_drops_and_counts() {
+    let queue = ScancodeQueue::new(2);
+    for scancode in 0..5 {
+        queue.push(scancode);
+    }
+    assert_eq!(queue.dropped.load(Ordering::Relaxed), 3);
+    assert_eq!(queue.queue.len(), 2);
+}
+
+#[test_case]
+fn test_injected_push_failure_is_counted() {
+    let queue = ScancodeQueue::new(4);
+    faultinject::set(faultinject::SCANCODE_PUSH, faultinject::Mode::Nth(2));
+    for scancode in [0x1e, 0x30, 0x2e] {
+        queue.push(scancode);
+    }
+    faultinject::clear(faultinject::SCANCODE_PUSH);
+    assert_eq!(queue.dropped.load(Ordering::Relaxed), 1);
+    assert_eq!(queue.queue.pop(), Some(0x1e));
+    assert_eq!(queue.queue.pop(), Some(0x2e));
+}
@@ -0,0 +1,114 @@
+//! 异步任务
+//! 每个 Task 持有一个固定在堆上的 Future，交给执行器轮询
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+pub mod channel;
+pub mod executor;
+pub mod keyboard;
+pub mod serial;
+pub mod simple_executor;
+
+/// 每个任务唯一的 id，执行器用它在唤醒队列中指代任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+pub struct Task {
+    id: TaskId,
+    /// 任务列表中显示的名称
+    name: &'static str,
+    /// Future 在被轮询后不能再移动（可能存在自引用），因此用 Pin<Box<..>> 固定在堆上
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+impl Task {
+    /// 'static 约束保证任务在执行器中存活期间不会引用已经失效的数据
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task::named("anonymous", future)
+    }
+
+    pub fn named(name: &'static str, future: impl Future<Output = ()> + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            name,
+            future: Box::pin(future),
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+/// 手写的 Future：先返回 remaining 次 Pending，之后才完成
+pub struct YieldTimes {
+    remaining: usize,
+}
+
+/// 让出执行权 n 次后再继续，用于演示一个 await 跨越多次轮询
+pub fn yield_times(n: usize) -> YieldTimes {
+    YieldTimes { remaining: n }
+}
+
+/// 让出执行权一次：第一次轮询唤醒自己并返回 Pending，第二次轮询完成。
+/// 执行器把自己唤醒的任务排到队尾，期间其他被唤醒的任务会先运行，长时间的工作可以在循环中 await 它
+pub fn yield_now() -> YieldTimes {
+    yield_times(1)
+}
+
+impl Future for YieldTimes {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.remaining == 0 {
+            return Poll::Ready(());
+        }
+        self.remaining -= 1;
+        // 返回 Pending 前必须安排唤醒，否则基于唤醒的执行器永远不会再轮询它
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[test_case]
+fn test_yield_now_wakes_once() {
+    use alloc::sync::Arc;
+    use alloc::task::Wake;
+    use core::sync::atomic::AtomicUsize;
+    use core::task::Waker;
+
+    struct CountWakes(AtomicUsize);
+
+    impl Wake for CountWakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut future = core::pin::pin!(yield_now());
+    assert_eq!(future.as_mut().poll(&mut context), Poll::Pending);
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+    assert_eq!(future.as_mut().poll(&mut context), Poll::Ready(()));
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,111 @@
+//! 异步串口输入
+//! 与键盘相同：COM1 的接收中断处理函数只把收到的字节放进固定容量的队列并唤醒消费者，
+//! 行编辑在异步任务中完成（见 console::discipline）
+use conquer_once::spin::OnceCell;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+
+const INPUT_QUEUE_CAPACITY: usize = 100;
+
+/// 在 init_queue 中创建，在此之前 IRQ4 一直处于屏蔽状态
+static INPUT_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// 队列已满或尚未创建时被丢弃的字节数
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// 由串口中断处理函数调用，不阻塞、不分配内存
+pub(crate) fn add_byte(byte: u8) {
+    match INPUT_QUEUE.try_get() {
+        Ok(queue) if queue.push(byte).is_ok() => WAKER.wake(),
+        _ => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// 因队列已满或尚未创建而丢弃的字节数
+pub fn dropped_bytes() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// 创建输入队列并解除 IRQ4 的屏蔽，重复调用没有影响
+pub fn init_queue() {
+    let _ = INPUT_QUEUE.try_init_once(|| ArrayQueue::new(INPUT_QUEUE_CAPACITY));
+    // 初始化 COM1 时打开了它的接收中断
+    lazy_static::initialize(&crate::serial::SERIAL1);
+    crate::interrupts::unmask_irq(crate::interrupts::SERIAL_IRQ);
+}
+
+/// 不阻塞地取出最早的字节，队列为空或尚未创建时返回 None
+/// 供不经过执行器的轮询读取使用，不要和 SerialStream 同时消费队列
+pub fn try_next_byte() -> Option<u8> {
+    INPUT_QUEUE.try_get().ok()?.pop()
+}
+
+/// 队列中是否有尚未取走的字节
+pub fn byte_pending() -> bool {
+    INPUT_QUEUE.try_get().is_ok_and(|queue| !queue.is_empty())
+}
+
+static STREAM_CREATED: AtomicBool = AtomicBool::new(false);
+
+pub struct SerialStream {
+    _private: (),
+}
+
+impl Default for SerialStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SerialStream {
+    /// 创建输入队列并解除 IRQ4 的屏蔽，只能调用一次
+    pub fn new() -> Self {
+        assert!(
+            !STREAM_CREATED.swap(true, Ordering::Relaxed),
+            "SerialStream::new should only be called once"
+        );
+        init_queue();
+        SerialStream { _private: () }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    /// 与 ScancodeStream 相同，先注册 waker 再检查一次，不会错过两次检查之间到达的字节
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = INPUT_QUEUE
+            .try_get()
+            .expect("serial input queue not initialized");
+        if let Some(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(byte) => {
+                WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[test_case]
+fn test_queued_bytes_are_taken_in_order() {
+    let _ = INPUT_QUEUE.try_init_once(|| ArrayQueue::new(INPUT_QUEUE_CAPACITY));
+    while try_next_byte().is_some() {}
+    add_byte(b'o');
+    add_byte(b'k');
+    assert!(byte_pending());
+    assert_eq!(try_next_byte(), Some(b'o'));
+    assert_eq!(try_next_byte(), Some(b'k'));
+    assert_eq!(try_next_byte(), None);
+    assert!(!byte_pending());
+}
@@ -0,0 +1,143 @@
+//! 最简单的执行器：按先进先出的顺序轮流轮询所有任务，直到全部完成
+//! 使用不做任何事情的 dummy waker，所以即使任务都处于 Pending 也会一直空转
+use super::Task;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+pub struct SimpleExecutor {
+    task_queue: VecDeque<Task>,
+    /// 运行中的任务通过 Spawner 新建的任务先放在这里，每轮调度前并入 task_queue
+    spawned: Rc<RefCell<VecDeque<Task>>>,
+}
+
+impl Default for SimpleExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimpleExecutor {
+    pub fn new() -> SimpleExecutor {
+        SimpleExecutor {
+            task_queue: VecDeque::new(),
+            spawned: Rc::new(RefCell::new(VecDeque::new())),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        self.task_queue.push_back(task)
+    }
+
+    /// run 会借用 &mut self，任务内部拿不到执行器本身
+    /// 需要在任务中创建新任务时，先取得一个 Spawner 并移动到任务里
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            queue: self.spawned.clone(),
+        }
+    }
+
+    /// 尚未完成的任务数量，已完成的任务会立即从队列中移除
+    pub fn len(&self) -> usize {
+        self.task_queue.len() + self.spawned.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 轮流轮询所有任务，直到全部完成后返回
+    pub fn run(&mut self) {
+        loop {
+            self.task_queue.extend(self.spawned.borrow_mut().drain(..));
+            let Some(mut task) = self.task_queue.pop_front() else {
+                break;
+            };
+            let waker = dummy_waker();
+            let mut context = Context::from_waker(&waker);
+            match task.poll(&mut context) {
+                // 完成的任务直接丢弃
+                Poll::Ready(()) => {}
+                Poll::Pending => self.task_queue.push_back(task),
+            }
+        }
+    }
+}
+
+/// 在运行中的任务里创建新任务的句柄
+#[derive(Clone)]
+pub struct Spawner {
+    queue: Rc<RefCell<VecDeque<Task>>>,
+}
+
+impl Spawner {
+    /// 新任务会在当前这一轮轮询结束后加入执行器
+    pub fn spawn(&self, task: Task) {
+        self.queue.borrow_mut().push_back(task);
+    }
+}
+
+/// dummy waker 的所有操作都是空的，clone 时返回一个新的 dummy
+fn dummy_raw_waker() -> RawWaker {
+    fn no_op(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        dummy_raw_waker()
+    }
+
+    let vtable = &RawWakerVTable::new(clone, no_op, no_op, no_op);
+    RawWaker::new(core::ptr::null::<()>(), vtable)
+}
+
+fn dummy_waker() -> Waker {
+    unsafe { Waker::from_raw(dummy_raw_waker()) }
+}
+
+#[cfg(test)]
+use super::yield_times;
+#[cfg(test)]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[test_case]
+fn test_tasks_run_to_completion() {
+    static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+    async fn counted(yields: usize) {
+        yield_times(yields).await;
+        COMPLETED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(counted(0)));
+    executor.spawn(Task::new(counted(2)));
+    executor.spawn(Task::new(counted(5)));
+    executor.run();
+    assert_eq!(COMPLETED.load(Ordering::SeqCst), 3);
+}
+
+#[test_case]
+fn test_completed_tasks_are_removed() {
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(async {}));
+    executor.spawn(Task::new(yield_times(3)));
+    assert_eq!(executor.len(), 2);
+    executor.run();
+    assert!(executor.is_empty());
+}
+
+#[test_case]
+fn test_spawn_from_running_task() {
+    static CHILD_RAN: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = SimpleExecutor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+        spawner.spawn(Task::new(async {
+            CHILD_RAN.fetch_add(1, Ordering::SeqCst);
+        }));
+        yield_times(1).await;
+    }));
+    executor.run();
+    assert_eq!(CHILD_RAN.load(Ordering::SeqCst), 1);
+    assert!(executor.is_empty());
+}
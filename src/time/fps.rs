@@ -0,0 +1,98 @@
+//! 帧率统计
+//! 把最近一秒按 100 毫秒分成 10 个桶计数，每帧调用一次 tick，每隔一秒报告一次滑动窗口内的帧数。
+//! 正在进行的桶不计入，报告的总是刚刚过去的完整一秒，不需要分配内存
+use super::{ticks, TIMER_FREQUENCY_HZ};
+use crate::print_at;
+
+const WINDOW_BUCKETS: usize = 10;
+const BUCKET_TICKS: u64 = TIMER_FREQUENCY_HZ as u64 / WINDOW_BUCKETS as u64;
+
+pub struct FpsCounter {
+    /// 窗口内的桶加上正在计数的桶，按桶号取模存放
+    buckets: [u32; WINDOW_BUCKETS + 1],
+    /// 正在计数的桶号，即节拍数 / BUCKET_TICKS；还没有调用过 tick 时为 None
+    current: Option<u64>,
+    next_report: u64,
+}
+
+impl Default for FpsCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FpsCounter {
+    pub const fn new() -> Self {
+        FpsCounter {
+            buckets: [0; WINDOW_BUCKETS + 1],
+            current: None,
+            next_report: 0,
+        }
+    }
+
+    /// 记录一帧，每过一秒返回一次最近一秒的帧数
+    pub fn tick(&mut self) -> Option<u32> {
+        self.tick_at(ticks())
+    }
+
+    /// 与 tick 相同，当前节拍数由调用者给出
+    pub fn tick_at(&mut self, now: u64) -> Option<u32> {
+        let bucket = now / BUCKET_TICKS;
+        match self.current {
+            None => self.next_report = now + TIMER_FREQUENCY_HZ as u64,
+            Some(current) => {
+                // 清空中间没有帧的桶，间隔超过一整圈时全部清空
+                for skipped in (current + 1..=bucket).take(self.buckets.len()) {
+                    self.buckets[Self::slot(skipped)] = 0;
+                }
+            }
+        }
+        self.current = Some(bucket);
+        self.buckets[Self::slot(bucket)] += 1;
+
+        if now < self.next_report {
+            return None;
+        }
+        self.next_report = now + TIMER_FREQUENCY_HZ as u64;
+        let total: u32 = self.buckets.iter().sum();
+        Some(total - self.buckets[Self::slot(bucket)])
+    }
+
+    fn slot(bucket: u64) -> usize {
+        (bucket % (WINDOW_BUCKETS as u64 + 1)) as usize
+    }
+}
+
+/// 在屏幕左上角显示帧率
+pub fn draw_fps(fps: u32) {
+    print_at!(0, 0, "{:>4} fps", fps);
+}
+
+/// 按 fps 的速率从 start 开始产生帧，对每一帧调用 tick_at，收集所有报告
+#[cfg(test)]
+fn simulate(counter: &mut FpsCounter, start: u64, fps: u64, seconds: u64) -> alloc::vec::Vec<u32> {
+    (0..fps * seconds)
+        .filter_map(|frame| counter.tick_at(start + frame * TIMER_FREQUENCY_HZ as u64 / fps))
+        .collect()
+}
+
+#[test_case]
+fn test_steady_rate_reported_once_per_second() {
+    let mut counter = FpsCounter::new();
+    // 第一次报告在第 1 秒的第一帧，此时窗口正好是第 0 秒
+    let reports = simulate(&mut counter, 0, 60, 3);
+    assert_eq!(reports, [60, 60]);
+    assert_eq!(counter.tick_at(3000), Some(60));
+}
+
+#[test_case]
+fn test_rate_change_and_idle_gap() {
+    let mut counter = FpsCounter::new();
+    simulate(&mut counter, 0, 50, 2);
+    // 降到 25 帧，一秒后的报告只反映新的速率
+    let reports = simulate(&mut counter, 2000, 25, 2);
+    assert_eq!(reports, [50, 25]);
+    // 停顿 5 秒后的第一帧：窗口内没有帧
+    assert_eq!(counter.tick_at(9000), Some(0));
+    assert_eq!(counter.tick_at(9500), None);
+}
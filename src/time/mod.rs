@@ -0,0 +1,165 @@
+//! 基于 PIT（可编程间隔定时器）的时钟节拍
+//! PIT 通道 0 连接到 IRQ0，每次计数归零都会触发一次时钟中断，中断处理函数在这里累加节拍数
+use crate::cpu::{self, Feature};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+mod fps;
+mod spin;
+mod stopwatch;
+mod timer;
+
+pub use fps::{draw_fps, FpsCounter};
+pub use spin::{calibrate_delay, delay_spin, spin_loops_per_ms, CALIBRATION_MS};
+pub use stopwatch::Stopwatch;
+pub use timer::{
+    interval, process_timers, sleep, sleep_until, timers_expired, Interval, TimerFuture,
+};
+
+/// PIT 的输入时钟频率（Hz）
+pub const PIT_BASE_FREQUENCY: u32 = 1_193_182;
+/// 配置的时钟中断频率，1 个节拍 = 1 毫秒
+pub const TIMER_FREQUENCY_HZ: u32 = 1000;
+/// 分频值只能是整数，1_193_182 / 1193 ≈ 1000.15 Hz，每秒误差约 0.15 个节拍
+pub const PIT_DIVISOR: u16 = (PIT_BASE_FREQUENCY / TIMER_FREQUENCY_HZ) as u16;
+
+const PIT_CHANNEL0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// 第 1 个节拍和最近一个节拍时的时间戳计数器（TSC），用来估计 TSC 的频率
+static FIRST_TICK_TSC: AtomicU64 = AtomicU64::new(0);
+static LAST_TICK_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// 还没有经过两个节拍、无法估计 TSC 频率时假设的每毫秒计数（1 GHz）
+const FALLBACK_TSC_PER_MS: u64 = 1_000_000;
+
+/// 设置 PIT 通道 0 的分频值，在初始化 PIC 时调用
+pub fn init_pit() {
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel0 = Port::<u8>::new(PIT_CHANNEL0);
+    unsafe {
+        // 通道 0，先低字节后高字节，模式 3（方波发生器），二进制计数
+        command.write(0x36);
+        channel0.write((PIT_DIVISOR & 0xFF) as u8);
+        channel0.write((PIT_DIVISOR >> 8) as u8);
+    }
+}
+
+/// 读取时间戳计数器，单位是 CPU 周期；调用前应当确认 CPU 有 TSC（cpu::Feature::Tsc）
+pub fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// 由时钟中断处理函数调用
+pub(crate) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    if cpu::has_feature(Feature::Tsc) {
+        let tsc = rdtsc();
+        if now == 1 {
+            FIRST_TICK_TSC.store(tsc, Ordering::Relaxed);
+        }
+        let previous = LAST_TICK_TSC.swap(tsc, Ordering::Relaxed);
+        // 估计出 TSC 频率之后才能换算延迟，用的是到上一个节拍为止的估计
+        let elapsed = previous.wrapping_sub(FIRST_TICK_TSC.load(Ordering::Relaxed));
+        if let Some(per_tick) = tsc_rate(elapsed, now - 1) {
+            crate::diag::record_tick(tsc.wrapping_sub(previous), per_tick);
+        }
+    }
+    timer::on_tick(now);
+    crate::speaker::on_tick(now);
+}
+
+/// 开机以来的时钟节拍数
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// 毫秒换算为节拍数，向上取整
+pub fn ms_to_ticks(ms: u32) -> u64 {
+    (ms as u64 * TIMER_FREQUENCY_HZ as u64).div_ceil(1000)
+}
+
+/// 节拍数换算为毫秒
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks * 1000 / TIMER_FREQUENCY_HZ as u64
+}
+
+/// 用 hlt 等待，直到节拍数前进 ms 对应的数量
+///
+/// 精度为 ±1 个节拍：调用时可能正处在两个节拍之间，第一个节拍可能马上到来，
+/// 所以实际等待时间在 (ms - 1, ms] 毫秒之间。
+/// 必须在中断开启后调用，否则节拍数永远不会增加
+pub fn delay_ms(ms: u32) {
+    debug_assert!(
+        x86_64::instructions::interrupts::are_enabled(),
+        "delay_ms requires interrupts to be enabled"
+    );
+    let target = ticks() + ms_to_ticks(ms);
+    while ticks() < target {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// 第 1 个节拍到第 ticks 个节拍之间 TSC 前进了 elapsed，换算为每毫秒的计数
+fn tsc_rate(elapsed: u64, ticks: u64) -> Option<u64> {
+    let ms = ticks_to_ms(ticks.checked_sub(1)?);
+    (ms > 0).then(|| elapsed / ms).filter(|&rate| rate > 0)
+}
+
+/// 每毫秒的 TSC 周期数，由启动以来的节拍估计，还没有经过两个节拍时假设为 1 GHz
+pub fn tsc_per_ms() -> u64 {
+    let elapsed = LAST_TICK_TSC
+        .load(Ordering::Relaxed)
+        .wrapping_sub(FIRST_TICK_TSC.load(Ordering::Relaxed));
+    tsc_rate(elapsed, ticks()).unwrap_or(FALLBACK_TSC_PER_MS)
+}
+
+/// 用 TSC 忙等 ms 毫秒，不依赖时钟中断
+///
+/// 用于中断可能已经关闭、或者正在中断处理函数中（PIC 不会再送来时钟中断）的场合，例如 panic 处理。
+/// TSC 的频率由启动以来的节拍估计，只是近似值。
+/// CPU 没有 TSC 时退回 PIT 的节拍：中断开启时等同于 delay_ms，
+/// 否则按 calibrate_delay 校准的速度空转，更不准确
+pub fn spin_delay_ms(ms: u32) {
+    if !cpu::has_feature(Feature::Tsc) {
+        if x86_64::instructions::interrupts::are_enabled() {
+            delay_ms(ms);
+        } else {
+            delay_spin(ms as u64 * spin_loops_per_ms());
+        }
+        return;
+    }
+    let per_ms = tsc_per_ms();
+    let start = rdtsc();
+    let target = ms as u64 * per_ms;
+    while rdtsc().wrapping_sub(start) < target {
+        core::hint::spin_loop();
+    }
+}
+
+#[test_case]
+fn test_tsc_rate() {
+    // 第 1 到第 1001 个节拍经过了 1000 毫秒
+    assert_eq!(tsc_rate(2_000_000_000, 1001), Some(2_000_000));
+    assert_eq!(tsc_rate(5_000, 2), Some(5_000));
+    // 少于两个节拍，或者 TSC 没有前进
+    assert_eq!(tsc_rate(5_000, 1), None);
+    assert_eq!(tsc_rate(5_000, 0), None);
+    assert_eq!(tsc_rate(0, 100), None);
+}
+
+#[test_case]
+fn test_ms_ticks_conversion() {
+    assert_eq!(ms_to_ticks(0), 0);
+    assert_eq!(ms_to_ticks(1), 1);
+    assert_eq!(ms_to_ticks(250), 250);
+    assert_eq!(ticks_to_ms(1000), 1000);
+}
+
+#[test_case]
+fn test_delay_ms_advances_ticks() {
+    let start = ticks();
+    delay_ms(10);
+    assert!(ticks() - start >= 9);
+}
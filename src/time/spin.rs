@@ -0,0 +1,98 @@
+//! 不依赖时钟中断和 TSC 的忙等
+//! 时钟中断开启之前（或者 CPU 没有 TSC、中断又被关闭时）只能靠空转计时。
+//! calibrate_delay 让 PIT 通道 2 单次计数 CALIBRATION_MS 毫秒，数这段时间内能空转多少次，
+//! 之后 delay_spin 按这个速度换算。
+//!
+//! 结果只是粗略的估计：校准本身有轮询端口的开销，空转的速度还随 CPU 频率调节、
+//! 虚拟机调度和中断变化，误差可能有几十个百分点。没有校准过、或者 PIT 没有响应时，
+//! 假设每毫秒 FALLBACK_LOOPS_PER_MS 次，误差可能是数量级的
+use super::{PIT_BASE_FREQUENCY, PIT_COMMAND};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+/// 校准时计时的毫秒数
+pub const CALIBRATION_MS: u32 = 10;
+/// 没有校准时假设的每毫秒空转次数
+pub const FALLBACK_LOOPS_PER_MS: u64 = 50_000;
+
+const PIT_CHANNEL2: u16 = 0x42;
+const CONTROL: u16 = 0x61;
+/// 通道 2，先低字节后高字节，模式 0（计数结束时输出变高），二进制计数
+const CHANNEL2_ONE_SHOT: u8 = 0xb0;
+/// 0x61 的 bit 0（通道 2 的门控）和 bit 1（扬声器数据使能）
+const GATE_BIT: u8 = 0b01;
+const SPEAKER_BIT: u8 = 0b10;
+/// 0x61 的 bit 5：通道 2 的输出电平
+const OUTPUT_BIT: u8 = 1 << 5;
+/// 每次检查 PIT 输出之间空转的次数
+const LOOPS_PER_POLL: u64 = 100;
+/// 输出一直不变高（没有 PIT）时放弃校准，不会一直卡在这里
+const MAX_LOOPS: u64 = 1 << 28;
+
+/// 校准的结果，0 表示没有校准
+static LOOPS_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+/// 空转 iterations 次
+pub fn delay_spin(iterations: u64) {
+    for i in 0..iterations {
+        core::hint::black_box(i);
+        core::hint::spin_loop();
+    }
+}
+
+/// PIT 计数 pit_ticks 次（PIT_BASE_FREQUENCY Hz）的时间内空转了 iterations 次，换算为每毫秒的次数
+fn loops_per_ms(iterations: u64, pit_ticks: u64) -> Option<u64> {
+    let per_ms = iterations
+        .checked_mul(PIT_BASE_FREQUENCY as u64)?
+        .checked_div(pit_ticks.checked_mul(1000)?)?;
+    (per_ms > 0).then_some(per_ms)
+}
+
+/// 用 PIT 通道 2 估计每毫秒的空转次数并保存，返回估计值；PIT 没有响应时返回 None，保留原来的值。
+/// 会关闭扬声器，期间关闭中断
+pub fn calibrate_delay() -> Option<u64> {
+    let pit_ticks = PIT_BASE_FREQUENCY as u64 * CALIBRATION_MS as u64 / 1000;
+    let mut control = Port::<u8>::new(CONTROL);
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel2 = Port::<u8>::new(PIT_CHANNEL2);
+    let iterations = interrupts::without_interrupts(|| unsafe {
+        let saved = control.read();
+        control.write(saved & !SPEAKER_BIT | GATE_BIT);
+        command.write(CHANNEL2_ONE_SHOT);
+        channel2.write(pit_ticks as u8);
+        channel2.write((pit_ticks >> 8) as u8);
+        let mut iterations = 0;
+        while control.read() & OUTPUT_BIT == 0 && iterations < MAX_LOOPS {
+            delay_spin(LOOPS_PER_POLL);
+            iterations += LOOPS_PER_POLL;
+        }
+        control.write(saved & !(SPEAKER_BIT | GATE_BIT));
+        iterations
+    });
+    if iterations >= MAX_LOOPS {
+        return None;
+    }
+    let per_ms = loops_per_ms(iterations, pit_ticks)?;
+    LOOPS_PER_MS.store(per_ms, Ordering::Relaxed);
+    Some(per_ms)
+}
+
+/// 每毫秒的空转次数，没有校准时是 FALLBACK_LOOPS_PER_MS
+pub fn spin_loops_per_ms() -> u64 {
+    match LOOPS_PER_MS.load(Ordering::Relaxed) {
+        0 => FALLBACK_LOOPS_PER_MS,
+        per_ms => per_ms,
+    }
+}
+
+#[test_case]
+fn test_loops_per_ms() {
+    // 11931 个 PIT 计数约为 10 毫秒
+    assert_eq!(loops_per_ms(500_000, 11_931), Some(50_003));
+    assert_eq!(loops_per_ms(1_000_000, 1_193_182), Some(1_000));
+    // 没有计时或者没有空转
+    assert_eq!(loops_per_ms(500_000, 0), None);
+    assert_eq!(loops_per_ms(0, 11_931), None);
+    assert_eq!(loops_per_ms(u64::MAX, 1), None);
+}
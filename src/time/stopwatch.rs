@@ -0,0 +1,33 @@
+//! 用 TSC 计时
+//! 用来测量一段代码花了多少个 CPU 周期，例如比较 new_line 优化前后的耗时。
+//! 周期数不是挂钟时间：要换算成时间需要知道 TSC 的频率，time::tsc_per_ms 只是由节拍估计的近似值，
+//! 老的 CPU 上 TSC 还会随频率调节变化。比较同一台机器上的两次测量时可以直接用周期数
+use super::rdtsc;
+use crate::cpu::{self, Feature};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch {
+    start: u64,
+}
+
+impl Stopwatch {
+    /// 开始计时，CPU 没有 TSC 时返回 None
+    pub fn start() -> Option<Stopwatch> {
+        cpu::has_feature(Feature::Tsc).then(|| Stopwatch { start: rdtsc() })
+    }
+
+    /// 从 start 到现在经过的周期数
+    pub fn elapsed_cycles(&self) -> u64 {
+        rdtsc().wrapping_sub(self.start)
+    }
+}
+
+#[test_case]
+fn test_elapsed_cycles_never_decrease() {
+    let Some(stopwatch) = Stopwatch::start() else {
+        return;
+    };
+    let first = stopwatch.elapsed_cycles();
+    let second = stopwatch.elapsed_cycles();
+    assert!(second >= first, "{} then {}", first, second);
+}
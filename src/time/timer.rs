@@ -0,0 +1,244 @@
+//! 异步定时器
+//!
+//! 等待中的定时器按 (截止节拍, 序号) 排序存放在 TIMERS 中，只在任务上下文中加锁。
+//! 时钟中断处理函数只比较 NEXT_DEADLINE 并设置 EXPIRED 标志，是 O(1) 的；
+//! 真正遍历并唤醒到期定时器的工作由执行器主循环中的 process_timers 完成
+use super::ticks;
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use futures_util::stream::Stream;
+use spin::Mutex;
+
+/// 键中的序号保证截止节拍相同的定时器也能共存，并按注册顺序唤醒
+type TimerKey = (u64, u64);
+
+static TIMERS: Mutex<BTreeMap<TimerKey, Waker>> = Mutex::new(BTreeMap::new());
+/// 最早的截止节拍，没有定时器时为 u64::MAX
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+/// 有定时器到期，等待 process_timers 处理
+static EXPIRED: AtomicBool = AtomicBool::new(false);
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// 由时钟中断处理函数调用，只做一次比较
+pub(crate) fn on_tick(now: u64) {
+    if now >= NEXT_DEADLINE.load(Ordering::Relaxed) {
+        EXPIRED.store(true, Ordering::Release);
+    }
+}
+
+/// 是否有到期的定时器还没有被处理，执行器在休眠前检查它
+pub fn timers_expired() -> bool {
+    EXPIRED.load(Ordering::Acquire)
+}
+
+/// 唤醒所有到期的定时器，由执行器在每次轮询任务前调用
+pub fn process_timers() {
+    if EXPIRED.swap(false, Ordering::AcqRel) {
+        wake_expired(ticks());
+    }
+}
+
+/// 按截止节拍从早到晚唤醒所有 deadline <= now 的定时器，并更新 NEXT_DEADLINE
+fn wake_expired(now: u64) {
+    let mut timers = TIMERS.lock();
+    while let Some(entry) = timers.first_entry() {
+        if entry.key().0 > now {
+            break;
+        }
+        entry.remove().wake();
+    }
+    update_next_deadline(&timers);
+}
+
+fn update_next_deadline(timers: &BTreeMap<TimerKey, Waker>) {
+    let next = timers.keys().next().map_or(u64::MAX, |key| key.0);
+    NEXT_DEADLINE.store(next, Ordering::Relaxed);
+}
+
+/// 在指定的节拍到来时完成的 Future，drop 时自动注销
+pub struct TimerFuture {
+    deadline: u64,
+    key: Option<TimerKey>,
+}
+
+/// 等待一段时间，精度为一个节拍（1 毫秒）
+pub fn sleep(duration: Duration) -> TimerFuture {
+    let ms = duration.as_millis().min(u32::MAX as u128) as u32;
+    sleep_until(ticks() + super::ms_to_ticks(ms))
+}
+
+/// 等待到指定的节拍，已经过去的节拍会立即完成
+pub fn sleep_until(deadline: u64) -> TimerFuture {
+    TimerFuture {
+        deadline,
+        key: None,
+    }
+}
+
+impl TimerFuture {
+    fn unregister(&mut self) {
+        if let Some(key) = self.key.take() {
+            let mut timers = TIMERS.lock();
+            if timers.remove(&key).is_some() {
+                update_next_deadline(&timers);
+            }
+        }
+    }
+}
+
+impl Future for TimerFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if ticks() >= self.deadline {
+            self.unregister();
+            return Poll::Ready(());
+        }
+
+        let deadline = self.deadline;
+        let key = *self
+            .key
+            .get_or_insert_with(|| (deadline, NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed)));
+        {
+            let mut timers = TIMERS.lock();
+            timers.insert(key, cx.waker().clone());
+            if self.deadline < NEXT_DEADLINE.load(Ordering::Relaxed) {
+                NEXT_DEADLINE.store(self.deadline, Ordering::Relaxed);
+            }
+        }
+
+        // 截止节拍可能在第一次检查之后、NEXT_DEADLINE 更新之前到来，此时中断处理函数不会设置 EXPIRED
+        if ticks() >= self.deadline {
+            self.unregister();
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for TimerFuture {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// 周期性产生节拍的 Stream，每一项是本次的截止节拍
+pub struct Interval {
+    period: u64,
+    timer: TimerFuture,
+}
+
+/// 每隔 ms 毫秒产生一次，处理不及时错过的周期会被跳过而不是补发
+pub fn interval(ms: u32) -> Interval {
+    let period = super::ms_to_ticks(ms).max(1);
+    Interval {
+        period,
+        timer: sleep_until(ticks() + period),
+    }
+}
+
+impl Stream for Interval {
+    type Item = u64;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u64>> {
+        match Pin::new(&mut self.timer).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(()) => {
+                let fired = self.timer.deadline;
+                let mut next = fired + self.period;
+                let now = ticks();
+                if next <= now {
+                    next = now + self.period;
+                }
+                self.timer = sleep_until(next);
+                Poll::Ready(Some(fired))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use alloc::{sync::Arc, task::Wake, vec::Vec};
+
+#[cfg(test)]
+static WOKEN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// 被唤醒时记录自己的编号
+#[cfg(test)]
+struct RecordingWaker(u32);
+
+#[cfg(test)]
+impl Wake for RecordingWaker {
+    fn wake(self: Arc<Self>) {
+        WOKEN.lock().push(self.0);
+    }
+}
+
+#[cfg(test)]
+fn poll_with_id(timer: &mut TimerFuture, id: u32) -> Poll<()> {
+    let waker = Waker::from(Arc::new(RecordingWaker(id)));
+    Pin::new(timer).poll(&mut Context::from_waker(&waker))
+}
+
+#[test_case]
+fn test_timers_wake_in_deadline_order() {
+    WOKEN.lock().clear();
+    let base = ticks() + 1_000_000;
+    let mut a = sleep_until(base + 300);
+    let mut b = sleep_until(base + 100);
+    let mut c = sleep_until(base + 200);
+    assert!(poll_with_id(&mut a, 1).is_pending());
+    assert!(poll_with_id(&mut b, 2).is_pending());
+    assert!(poll_with_id(&mut c, 3).is_pending());
+    assert_eq!(NEXT_DEADLINE.load(Ordering::Relaxed), base + 100);
+
+    wake_expired(base + 150);
+    assert_eq!(*WOKEN.lock(), [2]);
+    assert_eq!(NEXT_DEADLINE.load(Ordering::Relaxed), base + 200);
+
+    wake_expired(base + 1000);
+    assert_eq!(*WOKEN.lock(), [2, 3, 1]);
+    assert_eq!(NEXT_DEADLINE.load(Ordering::Relaxed), u64::MAX);
+}
+
+#[test_case]
+fn test_same_deadline_is_coalesced() {
+    WOKEN.lock().clear();
+    let deadline = ticks() + 1_000_000;
+    let mut a = sleep_until(deadline);
+    let mut b = sleep_until(deadline);
+    assert!(poll_with_id(&mut a, 1).is_pending());
+    assert!(poll_with_id(&mut b, 2).is_pending());
+
+    on_tick(deadline);
+    assert!(timers_expired());
+    EXPIRED.store(false, Ordering::Release);
+    wake_expired(deadline);
+    assert_eq!(*WOKEN.lock(), [1, 2]);
+    assert!(TIMERS.lock().is_empty());
+}
+
+#[test_case]
+fn test_dropped_timer_unregisters() {
+    WOKEN.lock().clear();
+    let mut timer = sleep_until(ticks() + 1_000_000);
+    assert!(poll_with_id(&mut timer, 1).is_pending());
+    assert_eq!(TIMERS.lock().len(), 1);
+
+    drop(timer);
+    assert!(TIMERS.lock().is_empty());
+    assert_eq!(NEXT_DEADLINE.load(Ordering::Relaxed), u64::MAX);
+    wake_expired(u64::MAX);
+    assert!(WOKEN.lock().is_empty());
+}
+
+#[test_case]
+fn test_past_deadline_is_ready_immediately() {
+    let mut timer = sleep_until(0);
+    assert!(poll_with_id(&mut timer, 1).is_ready());
+    assert!(TIMERS.lock().is_empty());
+}
@@ -0,0 +1,275 @@
+//! 用户态（ring 3）程序
+//! 把程序复制到 USER_CODE 处只读、用户可访问的页中，另外映射一页用户栈，
+//! 通过 iretq 以用户代码段和数据段进入 ring 3，从第一个字节开始执行，RDI 是 run 的 arg 参数。
+//!
+//! 程序通过 int 0x80 发起系统调用：RAX 是调用号，RDI、RSI 是参数，返回值放在 RAX 中，其他寄存器不变
+//! - SYS_WRITE(ptr, len)：检查整个范围都映射为用户可访问后复制到内核中打印，返回字节数，失败返回 u64::MAX
+//! - SYS_EXIT(code)：结束程序，run 返回 Exit::Code(code)。程序不能从入口返回，必须以 SYS_EXIT 结束
+//!
+//! 用户态的缺页由缺页处理函数转交给 fault，报告后结束程序，run 返回 Exit::Fault，内核继续运行。
+//! 结束时回到 run 的方式与 exec 相同：恢复 user_enter 保存的内核栈指针和寄存器
+//!
+//! 示例程序见 programs/user_hello.s 和 programs/user_fault.s
+use crate::exec::{self, ExecError};
+use crate::{gdt, memory, println};
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{FrameAllocator, Mapper, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+/// 程序的加载地址，使用单独的 4 级页表项，新建的上级页表都带有 USER_ACCESSIBLE
+pub const USER_CODE: u64 = 0x_6666_0000_0000;
+/// 用户栈的栈顶，栈只有一页
+pub const USER_STACK_TOP: u64 = USER_CODE + 0x10_0000;
+const USER_STACK: u64 = USER_STACK_TOP - PAGE_SIZE as u64;
+const PAGE_SIZE: usize = 4096;
+
+pub const SYSCALL_VECTOR: u8 = 0x80;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_EXIT: u64 = 2;
+/// 系统调用失败时的返回值
+pub const SYSCALL_ERROR: u64 = u64::MAX;
+/// 一次 SYS_WRITE 最多写出的字节数
+pub const MAX_WRITE: usize = 1024;
+
+/// user_return 的参数中表示程序因为缺页而结束的位，低 32 位是退出码
+const FAULTED: u64 = 1 << 32;
+
+/// user_enter 保存的内核栈指针
+static mut SAVED_RSP: u64 = 0;
+/// 最近一次用户态缺页的信息，由 fault 写入，run 取走
+static LAST_FAULT: Mutex<Option<UserFault>> = Mutex::new(None);
+
+// user_enter(entry, stack, arg, code_selector, data_selector)：保存 RFLAGS、被调用者保存的寄存器和栈指针，
+// 构造中断返回帧后 iretq 进入 ring 3。user_return(status) 恢复它们，status 作为 user_enter 的返回值。
+// syscall_entry 是 int 0x80 的处理函数，保存调用者保存的寄存器后调用 syscall_dispatch
+core::arch::global_asm!(
+    r#"
+.section .text
+.global user_enter
+user_enter:
+    pushfq
+    pushq %rbp
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    movq %rsp, {saved_rsp}(%rip)
+
+    pushq %r8
+    pushq %rsi
+    # 用户态开中断
+    pushq $0x202
+    pushq %rcx
+    pushq %rdi
+    movq %rdx, %rdi
+    # 不把内核的寄存器值带到用户态
+    xorl %eax, %eax
+    xorl %ebx, %ebx
+    xorl %ecx, %ecx
+    xorl %edx, %edx
+    xorl %esi, %esi
+    xorl %ebp, %ebp
+    xorl %r8d, %r8d
+    xorl %r9d, %r9d
+    xorl %r10d, %r10d
+    xorl %r11d, %r11d
+    xorl %r12d, %r12d
+    xorl %r13d, %r13d
+    xorl %r14d, %r14d
+    xorl %r15d, %r15d
+    iretq
+
+.global user_return
+user_return:
+    movq %rdi, %rax
+    movq {saved_rsp}(%rip), %rsp
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    popq %rbp
+    popfq
+    ret
+
+.global syscall_entry
+syscall_entry:
+    pushq %rcx
+    pushq %rdx
+    pushq %rsi
+    pushq %rdi
+    pushq %r8
+    pushq %r9
+    pushq %r10
+    pushq %r11
+    movq %rsi, %rdx
+    movq %rdi, %rsi
+    movq %rax, %rdi
+    # 从 ring 3 进入时 CPU 把栈对齐到 16 后压入 5 个值，再压 8 个寄存器后调用前需要再对齐
+    subq $8, %rsp
+    call {dispatch}
+    addq $8, %rsp
+    popq %r11
+    popq %r10
+    popq %r9
+    popq %r8
+    popq %rdi
+    popq %rsi
+    popq %rdx
+    popq %rcx
+    iretq
+"#,
+    saved_rsp = sym SAVED_RSP,
+    dispatch = sym syscall_dispatch,
+    options(att_syntax)
+);
+
+extern "C" {
+    fn user_enter(entry: u64, stack: u64, arg: u64, code_selector: u64, data_selector: u64) -> u64;
+    fn user_return(status: u64) -> !;
+    fn syscall_entry();
+}
+
+/// int 0x80 处理函数的地址，由 interrupts 模块放进 IDT
+pub(crate) fn syscall_entry_addr() -> VirtAddr {
+    VirtAddr::new(syscall_entry as *const () as u64)
+}
+
+extern "C" fn syscall_dispatch(number: u64, arg0: u64, arg1: u64) -> u64 {
+    match number {
+        SYS_WRITE => write(arg0, arg1).unwrap_or(SYSCALL_ERROR),
+        SYS_EXIT => unsafe { user_return(arg0 as u32 as u64) },
+        _ => SYSCALL_ERROR,
+    }
+}
+
+/// [ptr, ptr + len) 中的每一页都映射为用户可访问
+fn user_range(ptr: u64, len: usize) -> bool {
+    let Some(end) = ptr.checked_add(len as u64) else {
+        return false;
+    };
+    if len == 0 {
+        return true;
+    }
+    let (Ok(start), Ok(last)) = (VirtAddr::try_new(ptr), VirtAddr::try_new(end - 1)) else {
+        return false;
+    };
+    let mut page = start.align_down(PAGE_SIZE as u64);
+    while page <= last {
+        if !memory::user_accessible(page) {
+            return false;
+        }
+        page += PAGE_SIZE as u64;
+    }
+    true
+}
+
+fn write(ptr: u64, len: u64) -> Option<u64> {
+    let len = usize::try_from(len).ok().filter(|&len| len <= MAX_WRITE)?;
+    if !user_range(ptr, len) {
+        return None;
+    }
+    let bytes = Vec::from(unsafe { core::slice::from_raw_parts(ptr as *const u8, len) });
+    println!("{}", String::from_utf8_lossy(&bytes));
+    Some(len as u64)
+}
+
+/// 用户态程序的缺页
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserFault {
+    pub address: u64,
+    pub instruction: u64,
+    pub error_code: PageFaultErrorCode,
+}
+
+/// 由缺页处理函数在错误来自 ring 3 时调用：报告后结束程序，回到 run
+pub(crate) fn fault(fault: UserFault) -> ! {
+    println!(
+        "user program: page fault at {:#x} (rip {:#x}, {:?}), terminated",
+        fault.address, fault.instruction, fault.error_code
+    );
+    *LAST_FAULT.lock() = Some(fault);
+    unsafe { user_return(FAULTED) }
+}
+
+/// 程序结束的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Code(i32),
+    Fault(UserFault),
+}
+
+/// 在用户态运行 image，arg 通过 RDI 传给程序，返回程序结束的方式
+pub fn run(
+    image: &[u8],
+    arg: u64,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<Exit, ExecError> {
+    let count = exec::page_count(image.len())?;
+    let code_pages = exec::pages(USER_CODE, count);
+    let stack_pages = exec::pages(USER_STACK, 1);
+    if code_pages
+        .clone()
+        .chain(stack_pages.clone())
+        .any(|page| mapper.translate_page(page).is_ok())
+    {
+        return Err(ExecError::AlreadyMapped);
+    }
+
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let writable = user | PageTableFlags::WRITABLE;
+    exec::map(mapper, frame_allocator, code_pages.clone(), writable)?;
+    if let Err(error) = exec::map(mapper, frame_allocator, stack_pages.clone(), writable) {
+        exec::unmap(mapper, code_pages);
+        return Err(error);
+    }
+    let code = unsafe { core::slice::from_raw_parts_mut(USER_CODE as *mut u8, count * PAGE_SIZE) };
+    code[..image.len()].copy_from_slice(image);
+    code[image.len()..].fill(0);
+    unsafe { core::slice::from_raw_parts_mut(USER_STACK as *mut u8, PAGE_SIZE) }.fill(0);
+    // 复制完成后代码页改为只读
+    for page in code_pages.clone() {
+        if let Ok(flush) = unsafe { mapper.update_flags(page, user) } {
+            flush.flush();
+        }
+    }
+
+    let (code_selector, data_selector) = gdt::user_selectors();
+    let status = unsafe {
+        user_enter(
+            USER_CODE,
+            USER_STACK_TOP,
+            arg,
+            code_selector.0 as u64,
+            data_selector.0 as u64,
+        )
+    };
+    exec::unmap(mapper, code_pages.chain(stack_pages));
+
+    if status & FAULTED != 0 {
+        let fault = LAST_FAULT
+            .lock()
+            .take()
+            .expect("user fault was not recorded");
+        return Ok(Exit::Fault(fault));
+    }
+    Ok(Exit::Code(status as u32 as i32))
+}
+
+#[test_case]
+fn test_user_range_rejects_kernel_memory() {
+    static KERNEL: u8 = 0;
+    let kernel = &KERNEL as *const u8 as u64;
+    assert!(!user_range(kernel, 1));
+    assert!(user_range(kernel, 0));
+    // 越过地址空间末尾或者不是规范地址
+    assert!(!user_range(u64::MAX, 2));
+    assert!(!user_range(0x0000_8000_0000_0000, 1));
+    assert_eq!(write(kernel, 1), None);
+    assert_eq!(write(USER_CODE, MAX_WRITE as u64 + 1), None);
+}
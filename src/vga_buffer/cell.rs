@@ -0,0 +1,271 @@
+//! 按坐标访问单元格
+//! 所有按 (行, 列) 读写屏幕的功能共用这里的越界规则：Cell 的检查构造和 try_ 系列方法在越界时
+//! 返回 CellError，不会悄悄地限制到屏幕边缘或者丢弃。
+//!
+//! 以前的宽松行为仍然保留为便利方法，但不再是默认：
+//! - put_char、read_char、highlight 越界时 panic
+//! - write_fmt_at（print_at!）丢弃超出行尾或不在屏幕内的部分
+//! - Cell::clamped 把坐标限制在屏幕内，光标写入用它防止在 panic 处理中再次 panic
+use super::{char_display_width, ScreenChar, Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+use core::fmt;
+
+/// 屏幕上的一个单元格。字段是公开的，可以直接构造或者计算出任意坐标，
+/// 读写时才检查；Cell::new 在构造时就检查
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub row: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellError {
+    /// 行号不小于 BUFFER_HEIGHT
+    RowOutOfRange(usize),
+    /// 列号不小于 BUFFER_WIDTH
+    ColOutOfRange(usize),
+}
+
+impl fmt::Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CellError::RowOutOfRange(row) => {
+                write!(f, "row {} is outside 0..{}", row, BUFFER_HEIGHT)
+            }
+            CellError::ColOutOfRange(col) => {
+                write!(f, "column {} is outside 0..{}", col, BUFFER_WIDTH)
+            }
+        }
+    }
+}
+
+impl Cell {
+    /// 检查后构造，行和列都越界时报告行
+    pub const fn new(row: usize, col: usize) -> Result<Cell, CellError> {
+        Cell { row, col }.check()
+    }
+
+    /// 限制在屏幕内的单元格
+    pub const fn clamped(row: usize, col: usize) -> Cell {
+        Cell {
+            row: if row < BUFFER_HEIGHT {
+                row
+            } else {
+                BUFFER_HEIGHT - 1
+            },
+            col: if col < BUFFER_WIDTH {
+                col
+            } else {
+                BUFFER_WIDTH - 1
+            },
+        }
+    }
+
+    /// 在屏幕内时原样返回
+    pub const fn check(self) -> Result<Cell, CellError> {
+        if self.row >= BUFFER_HEIGHT {
+            Err(CellError::RowOutOfRange(self.row))
+        } else if self.col >= BUFFER_WIDTH {
+            Err(CellError::ColOutOfRange(self.col))
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// 按行优先的编号，0 是左上角
+    pub const fn index(self) -> usize {
+        self.row * BUFFER_WIDTH + self.col
+    }
+
+    pub const fn from_index(index: usize) -> Cell {
+        Cell {
+            row: index / BUFFER_WIDTH,
+            col: index % BUFFER_WIDTH,
+        }
+    }
+}
+
+/// 计算格式化结果占用的列数，不写入
+struct Measure(usize);
+
+impl fmt::Write for Measure {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.chars().map(char_display_width).sum::<usize>();
+        Ok(())
+    }
+}
+
+impl Writer {
+    /// 写入一个单元格，不移动光标
+    pub fn try_put(&mut self, cell: Cell, screen_char: ScreenChar) -> Result<(), CellError> {
+        let cell = cell.check()?;
+        self.before_cell_write(cell);
+        self.buffer.chars[cell.row][cell.col].write(screen_char);
+        Ok(())
+    }
+
+    pub fn try_read(&self, cell: Cell) -> Result<ScreenChar, CellError> {
+        let cell = cell.check()?;
+        Ok(self.buffer.chars[cell.row][cell.col].read())
+    }
+
+    /// 与 write_fmt_at 相同，但起点不在屏幕内、或者内容超出行尾时什么也不写，返回错误；
+    /// 成功时返回写入的列数。格式化会进行两次，第一次只计算宽度
+    pub fn try_write_fmt_at(
+        &mut self,
+        cell: Cell,
+        args: fmt::Arguments,
+    ) -> Result<usize, CellError> {
+        let cell = cell.check()?;
+        let mut measure = Measure(0);
+        let _ = fmt::Write::write_fmt(&mut measure, args);
+        if measure.0 > BUFFER_WIDTH - cell.col {
+            return Err(CellError::ColOutOfRange(cell.col + measure.0 - 1));
+        }
+        self.write_fmt_at(cell.row, cell.col, args);
+        Ok(measure.0)
+    }
+
+    /// 与 highlight 相同，但坐标越界时不改变任何单元格（包括之前的高亮），返回错误
+    pub fn try_highlight(&mut self, start: Cell, end: Cell) -> Result<(), CellError> {
+        let (mut first, mut last) = (start.check()?.index(), end.check()?.index());
+        self.clear_highlight();
+        if first > last {
+            core::mem::swap(&mut first, &mut last);
+        }
+        self.invert_cells(first, last);
+        self.highlight = Some((first, last));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use super::{Color, ColorCode, TestWriter};
+
+#[test_case]
+fn test_cell_errors() {
+    assert_eq!(Cell::new(0, 0), Ok(Cell { row: 0, col: 0 }));
+    assert_eq!(
+        Cell::new(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1).map(Cell::index),
+        Ok(BUFFER_HEIGHT * BUFFER_WIDTH - 1)
+    );
+    assert_eq!(
+        Cell::new(BUFFER_HEIGHT, 0),
+        Err(CellError::RowOutOfRange(BUFFER_HEIGHT))
+    );
+    assert_eq!(
+        Cell::new(0, BUFFER_WIDTH),
+        Err(CellError::ColOutOfRange(BUFFER_WIDTH))
+    );
+    // 都越界时报告行
+    assert_eq!(
+        Cell::new(usize::MAX, usize::MAX),
+        Err(CellError::RowOutOfRange(usize::MAX))
+    );
+    assert_eq!(
+        Cell::clamped(BUFFER_HEIGHT, usize::MAX),
+        Cell {
+            row: BUFFER_HEIGHT - 1,
+            col: BUFFER_WIDTH - 1
+        }
+    );
+    assert_eq!(Cell::from_index(BUFFER_WIDTH + 3), Cell { row: 1, col: 3 });
+}
+
+#[test_case]
+fn test_try_put_and_read() {
+    let mut writer = TestWriter::new();
+    let red = ScreenChar::new(b'r', ColorCode::new(Color::Red, Color::Black));
+    let cell = Cell { row: 2, col: 7 };
+    assert_eq!(writer.try_put(cell, red), Ok(()));
+    assert_eq!(writer.try_read(cell), Ok(red));
+    assert_eq!(writer.read_char(2, 7), (b'r', red.color_code()));
+
+    let below = Cell {
+        row: BUFFER_HEIGHT,
+        col: 0,
+    };
+    let right = Cell {
+        row: 0,
+        col: BUFFER_WIDTH,
+    };
+    assert_eq!(
+        writer.try_put(below, red),
+        Err(CellError::RowOutOfRange(BUFFER_HEIGHT))
+    );
+    assert_eq!(
+        writer.try_put(right, red),
+        Err(CellError::ColOutOfRange(BUFFER_WIDTH))
+    );
+    assert_eq!(
+        writer.try_read(below),
+        Err(CellError::RowOutOfRange(BUFFER_HEIGHT))
+    );
+    assert_eq!(
+        writer.try_read(right),
+        Err(CellError::ColOutOfRange(BUFFER_WIDTH))
+    );
+}
+
+#[test_case]
+fn test_try_write_fmt_at_rejects_overflow() {
+    let mut writer = TestWriter::new();
+    let row = 3;
+    let end = Cell {
+        row,
+        col: BUFFER_WIDTH - 4,
+    };
+    assert_eq!(
+        writer.try_write_fmt_at(end, format_args!("{:>3}%", 42)),
+        Ok(4)
+    );
+    assert_eq!(writer.read_char(row, BUFFER_WIDTH - 1).0, b'%');
+    // write_fmt_at 会写出前 4 列、丢弃 '!'，这里整段拒绝
+    let start = Cell {
+        row: row + 1,
+        col: BUFFER_WIDTH - 4,
+    };
+    assert_eq!(
+        writer.try_write_fmt_at(start, format_args!("{:>3}%!", 42)),
+        Err(CellError::ColOutOfRange(BUFFER_WIDTH))
+    );
+    assert_eq!(writer.read_char(row + 1, BUFFER_WIDTH - 2).0, b' ');
+    assert_eq!(
+        writer.try_write_fmt_at(
+            Cell {
+                row: BUFFER_HEIGHT,
+                col: 0
+            },
+            format_args!("x")
+        ),
+        Err(CellError::RowOutOfRange(BUFFER_HEIGHT))
+    );
+    // 宽字符占两列
+    let wide = Cell {
+        row,
+        col: BUFFER_WIDTH - 1,
+    };
+    assert_eq!(
+        writer.try_write_fmt_at(wide, format_args!("中")),
+        Err(CellError::ColOutOfRange(BUFFER_WIDTH))
+    );
+}
+
+#[test_case]
+fn test_try_highlight_rejects_without_touching_screen() {
+    let mut writer = TestWriter::new();
+    let color = writer.color_code();
+    let start = Cell { row: 0, col: 0 };
+    assert_eq!(writer.try_highlight(start, Cell { row: 0, col: 2 }), Ok(()));
+    let off = Cell {
+        row: 0,
+        col: BUFFER_WIDTH,
+    };
+    assert_eq!(
+        writer.try_highlight(start, off),
+        Err(CellError::ColOutOfRange(BUFFER_WIDTH))
+    );
+    // 之前的高亮还在
+    assert_eq!(writer.read_char(0, 1).1, color.inverted());
+    writer.clear_highlight();
+    assert_eq!(writer.read_char(0, 1).1, color);
+}
@@ -0,0 +1,75 @@
+//! 文本模式的柱状图
+//! 每个值一列，柱子从底部向上画，最大的值正好占满 height 行。
+//! 每个单元格分上下两半，所以高度的精度是半行：整格用实心块 0xdb，最上面多出的半格用下半块 0xdc。
+//! 不分配内存，可以在中断处理或者堆初始化之前使用
+use super::{Cell, ScreenChar, Writer};
+
+const FULL_BLOCK: u8 = 0xdb;
+const LOWER_HALF_BLOCK: u8 = 0xdc;
+
+/// 在以 (row, col) 为左上角、height 行 values.len() 列的区域中画柱状图，使用 writer 的当前颜色。
+/// 区域中柱子以外的单元格被清空；超出屏幕的部分被丢弃，光标不移动
+pub fn draw_bar_chart(writer: &mut Writer, row: usize, col: usize, height: usize, values: &[u8]) {
+    let max = values.iter().copied().max().unwrap_or(0) as usize;
+    let color_code = writer.color_code();
+    for (offset, &value) in values.iter().enumerate() {
+        let halves = bar_halves(value as usize, max, height);
+        for level in 0..height {
+            let byte = match halves.saturating_sub(level * 2) {
+                0 => b' ',
+                1 => LOWER_HALF_BLOCK,
+                _ => FULL_BLOCK,
+            };
+            let cell = Cell {
+                row: row + height - 1 - level,
+                col: col + offset,
+            };
+            let _ = writer.try_put(cell, ScreenChar::new(byte, color_code));
+        }
+    }
+}
+
+/// value 的柱子占多少个半格，四舍五入；max 占满 height 行
+fn bar_halves(value: usize, max: usize, height: usize) -> usize {
+    if max == 0 {
+        return 0;
+    }
+    (value * height * 2 + max / 2) / max
+}
+
+#[cfg(test)]
+use super::TestWriter;
+
+#[test_case]
+fn test_bar_heights() {
+    let mut writer = TestWriter::new();
+    let (top, left, height) = (5, 10, 4);
+    // 100 占满 4 行；60 是 4.8 个半格，四舍五入为 2 行半
+    draw_bar_chart(&mut writer, top, left, height, &[0, 25, 60, 100, 50]);
+
+    let column = |offset| -> alloc::vec::Vec<u8> {
+        (top..top + height)
+            .map(|row| writer.read_char(row, left + offset).0)
+            .collect()
+    };
+    let count = |offset, byte| column(offset).iter().filter(|&&b| b == byte).count();
+    for (offset, full, half) in [(0, 0, 0), (1, 1, 0), (2, 2, 1), (3, 4, 0), (4, 2, 0)] {
+        assert_eq!(count(offset, FULL_BLOCK), full, "column {}", offset);
+        assert_eq!(count(offset, LOWER_HALF_BLOCK), half, "column {}", offset);
+    }
+    // 柱子从底部开始，半格在最上面
+    assert_eq!(column(2), [b' ', LOWER_HALF_BLOCK, FULL_BLOCK, FULL_BLOCK]);
+    // 区域外不受影响
+    assert_eq!(writer.read_char(top - 1, left + 3).0, b' ');
+    assert_eq!(writer.read_char(top, left + 5).0, b' ');
+
+    // 零高度、没有值时什么也不画，一行高时只有整格和半格
+    let mut writer = TestWriter::new();
+    draw_bar_chart(&mut writer, 0, 0, 0, &[1, 2]);
+    draw_bar_chart(&mut writer, 0, 0, 3, &[]);
+    assert_eq!(writer.read_char(0, 0).0, b' ');
+    draw_bar_chart(&mut writer, 0, 0, 1, &[4, 2, 1]);
+    assert_eq!(writer.read_char(0, 0).0, FULL_BLOCK);
+    assert_eq!(writer.read_char(0, 1).0, LOWER_HALF_BLOCK);
+    assert_eq!(writer.read_char(0, 2).0, LOWER_HALF_BLOCK);
+}
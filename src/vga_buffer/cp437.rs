@@ -0,0 +1,90 @@
+//! 代码页 437
+//! VGA 文本模式的字库按 CP437 排列：0x20-0x7e 与 ASCII 相同，0x80-0xff 是带音调的字母、
+//! 希腊字母、制表符和方块等。0x01-0x1f 和 0x7f 也有字形（笑脸、箭头等），
+//! 但这些字节在 write_byte 中是控制字符，所以不用于映射
+use super::{char_display_width, Writer};
+
+/// 0x80-0xff 对应的字符
+const HIGH_HALF: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}', //
+];
+
+/// 非 ASCII 字符在 CP437 中的字节，没有对应的字形时返回 None
+pub fn from_char(c: char) -> Option<u8> {
+    HIGH_HALF
+        .iter()
+        .position(|&high| high == c)
+        .map(|index| 0x80 + index as u8)
+}
+
+/// 字节对应的字符：0x20-0x7e 是 ASCII，0x80-0xff 查表，其他控制字节返回 None
+pub fn to_char(byte: u8) -> Option<char> {
+    match byte {
+        0x20..=0x7e => Some(byte as char),
+        0x80..=0xff => Some(HIGH_HALF[byte as usize - 0x80]),
+        _ => None,
+    }
+}
+
+impl Writer {
+    /// 写入一个字符：ASCII 字符与 write_byte 相同（控制字符按当前设置解释或显示为替代字节），
+    /// 其他字符通过 CP437 映射为一个字节，没有对应字形时显示为替代字节。
+    /// 组合字符被丢弃，宽字符写入两个替代字节，见 char_display_width
+    pub fn write_char(&mut self, c: char) {
+        let byte = self.cell_byte(c);
+        for _ in 0..char_display_width(c) {
+            self.write_byte(byte);
+        }
+    }
+
+    /// 写入 c 时交给 write_byte 的字节
+    pub(super) fn cell_byte(&self, c: char) -> u8 {
+        match u8::try_from(c) {
+            Ok(byte) if byte.is_ascii() => self.printable(byte),
+            _ => from_char(c).unwrap_or(self.replacement),
+        }
+    }
+}
+
+#[cfg(test)]
+use super::{TestWriter, BUFFER_HEIGHT};
+
+#[test_case]
+fn test_write_char() {
+    let mut writer = TestWriter::new();
+    writer.write_char('A');
+    writer.write_char('é');
+    writer.write_char('█');
+    writer.write_char('中');
+    writer.write_char('\u{301}');
+    writer.write_char('\u{1}');
+    let row = BUFFER_HEIGHT - 1;
+    // '中' 是宽字符，占两个单元格；组合字符被丢弃
+    let expected = [b'A', 0x82, 0xdb, 0xfe, 0xfe, 0xfe];
+    for (col, &byte) in expected.iter().enumerate() {
+        assert_eq!(writer.read_char(row, col).0, byte);
+    }
+    writer.write_char('\n');
+    assert_eq!(writer.column(), 0);
+}
+
+#[test_case]
+fn test_write_macro_formats_char() {
+    use core::fmt::Write;
+
+    let (umlaut, han) = ('ü', '中');
+    let mut writer = TestWriter::new();
+    write!(writer, "{}{}", umlaut, han).unwrap();
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, 0x81);
+    // 占三个字节的 '中' 按显示宽度占两个单元格
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, 0xfe);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, 0xfe);
+    assert_eq!(writer.column(), 3);
+}
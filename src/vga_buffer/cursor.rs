@@ -0,0 +1,94 @@
+//! 硬件光标的形状
+//! 光标是字符单元中从起始扫描线到结束扫描线的一段横条，由 CRTC 的两个寄存器控制：
+//! - 0x0A 光标起始寄存器：bit 0-4 起始扫描线，bit 5 置位时隐藏光标
+//! - 0x0B 光标结束寄存器：bit 0-4 结束扫描线，bit 5-6 光标相对字符的延迟（skew）
+//!
+//! 扫描线从字符顶端的 0 开始，最大值是 CRTC 0x09 寄存器中的字符高度 - 1（8x16 字体为 15）。
+//! BIOS 文本模式默认起始 13、结束 14，是一条下划线；起始 0、结束 15 是整格的块状光标。
+//! 标准 VGA 的光标闪烁由硬件按固定的帧数完成，闪烁速度不能由软件设置
+use super::Writer;
+use crate::vga_mode::{crtc_index_port, read_indexed, write_indexed};
+
+const CURSOR_START: u8 = 0x0A;
+const CURSOR_END: u8 = 0x0B;
+const MAX_SCAN_LINE: u8 = 0x09;
+const SCAN_LINE_MASK: u8 = 0x1F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShapeError {
+    /// 起始扫描线在结束扫描线之后，硬件会隐藏光标或者显示成两段
+    StartAfterEnd { start: u8, end: u8 },
+    /// 超出了当前字体的字符高度
+    BeyondCharacter { end: u8, max: u8 },
+}
+
+impl Writer {
+    /// 设置硬件光标占据的扫描线范围，start 和 end 都包含
+    /// 寄存器中的隐藏位和 skew 保持不变
+    pub fn set_cursor_shape(
+        &mut self,
+        start_scanline: u8,
+        end_scanline: u8,
+    ) -> Result<(), CursorShapeError> {
+        unsafe {
+            let crtc = crtc_index_port();
+            let max = read_indexed(crtc, MAX_SCAN_LINE) & SCAN_LINE_MASK;
+            let (start_reg, end_reg) = cursor_registers(
+                start_scanline,
+                end_scanline,
+                max,
+                read_indexed(crtc, CURSOR_START),
+                read_indexed(crtc, CURSOR_END),
+            )?;
+            write_indexed(crtc, CURSOR_START, start_reg);
+            write_indexed(crtc, CURSOR_END, end_reg);
+        }
+        Ok(())
+    }
+}
+
+/// 检查扫描线范围并计算两个寄存器的新值，只替换 bit 0-4，与端口读写分离便于测试
+fn cursor_registers(
+    start: u8,
+    end: u8,
+    max: u8,
+    old_start: u8,
+    old_end: u8,
+) -> Result<(u8, u8), CursorShapeError> {
+    if end > max {
+        return Err(CursorShapeError::BeyondCharacter { end, max });
+    }
+    if start > end {
+        return Err(CursorShapeError::StartAfterEnd { start, end });
+    }
+    Ok((
+        old_start & !SCAN_LINE_MASK | start,
+        old_end & !SCAN_LINE_MASK | end,
+    ))
+}
+
+#[test_case]
+fn test_cursor_registers_keep_other_bits() {
+    // 默认的下划线光标（0x0D/0x0E）改成块状
+    assert_eq!(cursor_registers(0, 15, 15, 0x0D, 0x0E), Ok((0x00, 0x0F)));
+    // 隐藏位和 skew 不受影响
+    assert_eq!(cursor_registers(14, 15, 15, 0x20, 0x60), Ok((0x2E, 0x6F)));
+    // 80x50 模式下字符只有 8 条扫描线
+    assert_eq!(cursor_registers(6, 7, 7, 0x06, 0x07), Ok((0x06, 0x07)));
+}
+
+#[test_case]
+fn test_cursor_registers_reject_invalid_ranges() {
+    assert_eq!(
+        cursor_registers(5, 4, 15, 0, 0),
+        Err(CursorShapeError::StartAfterEnd { start: 5, end: 4 })
+    );
+    assert_eq!(
+        cursor_registers(0, 15, 7, 0, 0),
+        Err(CursorShapeError::BeyondCharacter { end: 15, max: 7 })
+    );
+    assert_eq!(
+        cursor_registers(0, 32, 15, 0, 0),
+        Err(CursorShapeError::BeyondCharacter { end: 32, max: 15 })
+    );
+}
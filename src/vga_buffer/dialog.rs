@@ -0,0 +1,135 @@
+//! 居中的对话框
+//! show_dialog 按消息的大小算出方框，放在屏幕正中，先保存下面的内容，再画出带标题的边框和消息；
+//! 返回的 Dialog 在 dismiss 时把这块区域原样恢复，框外的内容和光标都不受影响。
+//! 方框用 layout 的 draw_box 画，和 Layout 中的方框样式相同
+use super::layout::{draw_box, Area};
+use super::{Color, ColorCode, Snapshot, Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+const COLOR: ColorCode = ColorCode::new(Color::White, Color::Blue);
+
+/// 屏幕上的一个对话框，需要调用 dismiss 才会消失
+#[must_use = "the dialog stays on screen until dismissed"]
+pub struct Dialog {
+    area: Area,
+    /// 画对话框之前的整个画面，dismiss 时只取回 area 中的部分
+    saved: Snapshot,
+}
+
+impl Dialog {
+    /// 对话框占用的区域，包括边框
+    pub fn area(&self) -> Area {
+        self.area
+    }
+
+    /// 恢复对话框下面原来的内容
+    pub fn dismiss(self, writer: &mut Writer) {
+        let Area {
+            row,
+            col,
+            height,
+            width,
+        } = self.area;
+        let rows = self
+            .saved
+            .screen()
+            .iter()
+            .enumerate()
+            .skip(row)
+            .take(height);
+        for (row, saved) in rows {
+            for (col, &screen_char) in saved.iter().enumerate().skip(col).take(width) {
+                let _ = writer.try_put(super::Cell { row, col }, screen_char);
+            }
+        }
+    }
+}
+
+/// 在屏幕正中显示对话框：标题写在上边框上，message 按 '\n' 分行，左右各留一格空白。
+/// 只支持 ASCII；放不下的行和列被截掉，光标不移动
+pub fn show_dialog(writer: &mut Writer, title: &str, message: &str) -> Dialog {
+    let area = dialog_area(title, message);
+    let saved = writer.snapshot();
+    draw_box(writer, &area, title, COLOR);
+    let interior = area.interior();
+    let text = Area {
+        col: interior.col + 1,
+        width: interior.width.saturating_sub(2),
+        ..interior
+    };
+    for (line, content) in message.lines().enumerate() {
+        text.write_line(writer, line, COLOR, format_args!("{}", content));
+    }
+    Dialog { area, saved }
+}
+
+/// 能放下所有消息行和标题的最小方框，不超过屏幕
+fn dialog_area(title: &str, message: &str) -> Area {
+    let longest = message.lines().map(str::len).max().unwrap_or(0);
+    // 标题两边各留一格，左右边框各一格，消息两边各一格空白
+    let width = longest
+        .max(title.len() + 2)
+        .saturating_add(4)
+        .min(BUFFER_WIDTH);
+    let height = message.lines().count().saturating_add(2).min(BUFFER_HEIGHT);
+    Area {
+        row: (BUFFER_HEIGHT - height) / 2,
+        col: (BUFFER_WIDTH - width) / 2,
+        height,
+        width,
+    }
+}
+
+#[cfg(test)]
+use super::layout::{BOTTOM_LEFT, BOTTOM_RIGHT, HORIZONTAL, TOP_LEFT, TOP_RIGHT, VERTICAL};
+#[cfg(test)]
+use super::TestWriter;
+
+#[test_case]
+fn test_dialog_draws_and_restores() {
+    let mut writer = TestWriter::new();
+    writer.set_color(Color::LightGreen, Color::Black);
+    for line in 0..BUFFER_HEIGHT * 2 {
+        writer.write_string(&alloc::format!("background line {}\n", line));
+    }
+    let before = writer.snapshot();
+
+    let dialog = show_dialog(&mut writer, "Note", "hello\nworld!");
+    // 最长的是标题：4 + 2，加上边框和空白共 10 列；两行消息加边框共 4 行
+    let area = dialog.area();
+    assert_eq!((area.height, area.width), (4, 10));
+    assert_eq!(
+        (area.row, area.col),
+        ((BUFFER_HEIGHT - 4) / 2, (BUFFER_WIDTH - 10) / 2)
+    );
+    let (top, left) = (area.row, area.col);
+    let (bottom, right) = (top + 3, left + 9);
+    let byte = |row, col| writer.read_char(row, col).0;
+    assert_eq!(byte(top, left), TOP_LEFT);
+    assert_eq!(byte(top, right), TOP_RIGHT);
+    assert_eq!(byte(bottom, left), BOTTOM_LEFT);
+    assert_eq!(byte(bottom, right), BOTTOM_RIGHT);
+    assert_eq!(byte(top + 1, left), VERTICAL);
+    assert_eq!(byte(bottom, left + 1), HORIZONTAL);
+    // 标题从第三列开始，消息从边框内第二列开始
+    let text = |row, col, len| -> alloc::vec::Vec<u8> {
+        (col..col + len).map(|col| byte(row, col)).collect()
+    };
+    assert_eq!(text(top, left + 2, 4), b"Note");
+    assert_eq!(text(top + 1, left + 1, 8), b" hello  ");
+    assert_eq!(text(top + 2, left + 2, 6), b"world!");
+    assert_eq!(writer.read_char(top + 1, left + 2).1, COLOR);
+
+    dialog.dismiss(&mut writer);
+    for row in 0..BUFFER_HEIGHT {
+        for col in 0..BUFFER_WIDTH {
+            let expected = before.screen()[row][col];
+            assert_eq!(
+                writer.read_char(row, col),
+                (expected.ascii_character, expected.color_code),
+                "cell ({}, {})",
+                row,
+                col
+            );
+        }
+    }
+}
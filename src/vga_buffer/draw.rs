@@ -0,0 +1,127 @@
+//! 批量绘制
+//! begin_draw 返回的 DrawTransaction 存在期间，所有写入都进入离屏的后备缓冲区，
+//! 守卫 drop 时把后备缓冲区整体复制到显存一次，屏幕上不会出现画了一半的帧。
+//! 后备缓冲区和回滚的备用缓冲区一样由调用者提供，见 enable_draw_buffer；
+//! 没有后备缓冲区时事务直接写显存，不做批量处理。
+//!
+//! 开始事务时会先回到回滚的底部，事务期间不应该再回滚。
+//! 打开了垂直回扫同步（见 vga_mode::set_vsync）时，复制到显存之前先等待回扫
+use super::{copy_rows, Buffer, Writer, BUFFER_HEIGHT};
+use crate::vga_mode;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+
+impl Writer {
+    /// 启用批量绘制，spare 作为事务期间的后备缓冲区
+    pub fn enable_draw_buffer(&mut self, spare: &'static mut Buffer) {
+        self.draw_buffer = Some(spare);
+    }
+
+    /// 开始一次批量绘制，返回的守卫 drop 时统一显示
+    pub fn begin_draw(&mut self) -> DrawTransaction<'_> {
+        self.finish_redraw();
+        self.snap_to_bottom();
+        let front = self.draw_buffer.take().map(|back| {
+            copy_rows(&*self.buffer, back, 0..BUFFER_HEIGHT);
+            mem::replace(&mut self.buffer, back)
+        });
+        DrawTransaction {
+            writer: self,
+            front,
+        }
+    }
+
+    /// 批量绘制显示过的帧数
+    pub fn frames_presented(&self) -> u64 {
+        self.frames_presented
+    }
+}
+
+/// 见 Writer::begin_draw，通过 Deref 使用 Writer 的所有方法
+pub struct DrawTransaction<'a> {
+    writer: &'a mut Writer,
+    /// 事务期间被换下来的显存，None 表示没有后备缓冲区或者嵌套在另一个事务中
+    front: Option<&'static mut Buffer>,
+}
+
+impl Deref for DrawTransaction<'_> {
+    type Target = Writer;
+
+    fn deref(&self) -> &Writer {
+        self.writer
+    }
+}
+
+impl DerefMut for DrawTransaction<'_> {
+    fn deref_mut(&mut self) -> &mut Writer {
+        self.writer
+    }
+}
+
+impl Drop for DrawTransaction<'_> {
+    fn drop(&mut self) {
+        let Some(front) = self.front.take() else {
+            return;
+        };
+        let back = mem::replace(&mut self.writer.buffer, front);
+        vga_mode::sync_to_vretrace();
+        copy_rows(&*back, self.writer.buffer, 0..BUFFER_HEIGHT);
+        self.writer.draw_buffer = Some(back);
+        self.writer.frames_presented += 1;
+    }
+}
+
+/// 为 WRITER 启用批量绘制，后备缓冲区分配在堆上，需要在堆初始化之后调用
+pub fn init_draw_buffer() {
+    use super::WRITER;
+    use alloc::boxed::Box;
+    use x86_64::instructions::interrupts;
+
+    let spare = Box::leak(Box::new(Buffer::new()));
+    interrupts::without_interrupts(|| WRITER.lock().enable_draw_buffer(spare));
+}
+
+#[cfg(test)]
+use super::TestWriter;
+
+#[test_case]
+fn test_transaction_presents_once() {
+    let mut writer = TestWriter::with_draw_buffer();
+    writer.write_string("old");
+    {
+        let mut frame = writer.begin_draw();
+        frame.clear_screen();
+        frame.write_string("first");
+        frame.write_string(" second");
+        // 嵌套的事务不会单独显示
+        frame.begin_draw().write_string(" third");
+        assert_eq!(frame.frames_presented(), 0);
+    }
+    assert_eq!(writer.frames_presented(), 1);
+    let row: alloc::string::String = (0..18)
+        .map(|col| writer.read_char(BUFFER_HEIGHT - 1, col).0 as char)
+        .collect();
+    assert_eq!(row, "first second third");
+
+    // 没有后备缓冲区时直接写入，不计帧
+    let mut plain = TestWriter::new();
+    plain.begin_draw().write_string("x");
+    assert_eq!(plain.read_char(BUFFER_HEIGHT - 1, 0).0, b'x');
+    assert_eq!(plain.frames_presented(), 0);
+}
+
+#[test_case]
+fn test_transaction_hides_partial_frame() {
+    let mut writer = TestWriter::with_draw_buffer();
+    writer.write_string("old");
+    let front = writer.backing;
+    let mut frame = writer.begin_draw();
+    frame.write_string(" new");
+    // 事务期间显存保持原样，后备缓冲区中已经有新内容
+    let shown = unsafe { &*front }.chars[BUFFER_HEIGHT - 1][3].read();
+    assert_eq!(shown.ascii_character, b' ');
+    assert_eq!(frame.read_char(BUFFER_HEIGHT - 1, 4).0, b'n');
+    drop(frame);
+    let shown = unsafe { &*front }.chars[BUFFER_HEIGHT - 1][4].read();
+    assert_eq!(shown.ascii_character, b'n');
+}
@@ -0,0 +1,139 @@
+//! 控制台初始化之前的输出（ConsoleState::Uninit）
+//! 不经过 WRITER、不加锁，也不依赖任何需要初始化的状态：从屏幕左上角开始以固定颜色依次写入，
+//! 写满整个屏幕后回到左上角覆盖，不滚动。之后 WRITER 从最后一行开始输出，这些内容在滚出之前仍然可见
+//!
+//! 这个阶段只有启动最早期的代码在运行，中断也还没有开启，所以不考虑并发
+use super::{Buffer, Color, ColorCode, ScreenChar, BUFFER_HEIGHT, BUFFER_WIDTH, VGA_ADDRESS};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// 固定的颜色：黑底白字
+pub const EARLY_COLOR: ColorCode = ColorCode::new(Color::White, Color::Black);
+/// 位置编码为 行 * STRIDE + 列，列可以等于 BUFFER_WIDTH：
+/// 与 Writer 相同，写满一行后等到下一个字符才折行，紧接着的换行符不会多出一个空行
+const STRIDE: usize = BUFFER_WIDTH + 1;
+
+/// 下一个字符的位置
+static POSITION: AtomicUsize = AtomicUsize::new(0);
+
+pub struct EarlyWriter<'a> {
+    buffer: &'a mut Buffer,
+    position: &'a AtomicUsize,
+}
+
+impl<'a> EarlyWriter<'a> {
+    pub fn new(buffer: &'a mut Buffer, position: &'a AtomicUsize) -> Self {
+        EarlyWriter { buffer, position }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        let position = self.position.load(Ordering::Relaxed);
+        let (mut row, mut col) = (position / STRIDE % BUFFER_HEIGHT, position % STRIDE);
+        if byte == b'\n' || col == BUFFER_WIDTH {
+            // 最后一行之后回到第一行
+            row = (row + 1) % BUFFER_HEIGHT;
+            col = 0;
+        }
+        if byte != b'\n' {
+            let ascii_character = match byte {
+                0x20..=0x7e => byte,
+                _ => 0xfe,
+            };
+            self.buffer.chars[row][col].write(ScreenChar::new(ascii_character, EARLY_COLOR));
+            col += 1;
+        }
+        self.position.store(row * STRIDE + col, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Write for EarlyWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+/// 直接写 0xb8000 处的缓冲区
+///
+/// # Safety
+/// 与 raw_write_char_at 相同：调用者必须保证在使用返回值期间没有其他执行流同时写屏幕，
+/// 例如控制台初始化之前的单线程阶段，或者 panic 处理中已经不会再返回的时候
+pub unsafe fn screen() -> EarlyWriter<'static> {
+    EarlyWriter::new(unsafe { &mut *(VGA_ADDRESS as *mut Buffer) }, &POSITION)
+}
+
+/// 不经过 WRITER，直接把一个单元格写到 0xb8000 处的缓冲区，坐标超出屏幕时什么也不做。
+/// 用于最早的启动信息和 panic 处理最后的标记：不会触发 WRITER 的初始化，也不等待它的锁
+///
+/// # Safety
+/// 调用者必须保证没有其他执行流同时写屏幕，例如启动早期的单线程阶段，
+/// 或者 panic 处理中已经不会再返回的时候；否则这次写入可能与 Writer 的输出交错或者被覆盖
+pub unsafe fn raw_write_char_at(row: usize, col: usize, byte: u8, color_code: ColorCode) {
+    if row < BUFFER_HEIGHT && col < BUFFER_WIDTH {
+        let buffer = &mut *(VGA_ADDRESS as *mut Buffer);
+        buffer.chars[row][col].write(ScreenChar::new(byte, color_code));
+    }
+}
+
+#[cfg(test)]
+use alloc::boxed::Box;
+
+#[test_case]
+fn test_early_writer_fills_from_top_left() {
+    use core::fmt::Write;
+
+    let mut buffer = Box::new(Buffer::new());
+    let position = AtomicUsize::new(0);
+    let mut writer = EarlyWriter::new(&mut buffer, &position);
+    write!(writer, "boot {}\nsecond\x01", 1).unwrap();
+    assert_eq!(&buffer.row_text(0), b"boot 1");
+    assert_eq!(&buffer.row_text(1), b"second\xfe");
+    assert_eq!(buffer.color_at(0, 0), EARLY_COLOR);
+    assert_eq!(position.load(Ordering::Relaxed), STRIDE + 7);
+
+    // 写满一行后的换行符只换一行
+    position.store(0, Ordering::Relaxed);
+    let mut writer = EarlyWriter::new(&mut buffer, &position);
+    write!(writer, "{:=<1$}\nnext", "", BUFFER_WIDTH).unwrap();
+    assert_eq!(&buffer.row_text(1), b"next");
+
+    // 写满整个屏幕后回到左上角
+    position.store(
+        (BUFFER_HEIGHT - 1) * STRIDE + BUFFER_WIDTH - 1,
+        Ordering::Relaxed,
+    );
+    let mut writer = EarlyWriter::new(&mut buffer, &position);
+    write!(writer, "yz").unwrap();
+    assert_eq!(
+        buffer.chars[BUFFER_HEIGHT - 1][BUFFER_WIDTH - 1]
+            .read()
+            .ascii_character,
+        b'y'
+    );
+    assert_eq!(buffer.chars[0][0].read().ascii_character, b'z');
+}
+
+#[test_case]
+fn test_raw_write_char_at() {
+    use x86_64::instructions::interrupts;
+
+    let color_code = ColorCode::new(Color::White, Color::Red);
+    // 持有 WRITER 的锁，测试期间没有其他输出写屏幕
+    interrupts::without_interrupts(|| {
+        let _writer = super::WRITER.lock();
+        let screen = unsafe { &*(VGA_ADDRESS as *const Buffer) };
+        let (row, col) = (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1);
+        let saved = screen.chars[row][col].read();
+        unsafe { raw_write_char_at(row, col, b'!', color_code) };
+        assert_eq!(
+            screen.chars[row][col].read(),
+            ScreenChar::new(b'!', color_code)
+        );
+        // 超出屏幕时什么也不写
+        unsafe { raw_write_char_at(BUFFER_HEIGHT, 0, b'?', color_code) };
+        unsafe { raw_write_char_at(0, BUFFER_WIDTH, b'?', color_code) };
+        unsafe { raw_write_char_at(row, col, saved.ascii_character, saved.color_code) };
+    });
+}
@@ -0,0 +1,695 @@
+//! 声明式的静态界面布局
+//! 一个 Layout 是一组元素：带标题的方框、有颜色的标签和预留的区域。
+//! 位置可以是距离屏幕某一边的格数，也可以是屏幕尺寸的百分比，所以同一个布局在 80x25 和 80x50 下
+//! 会解析出不同的坐标，切换文本模式后用新的尺寸重新 render 即可。
+//!
+//! 解析时检查每个元素都在屏幕内，并且互不重叠；完全落在方框内部（边框以内）的元素不算重叠，
+//! 标签和预留区域因此可以放在方框里。Layout::new 是 const fn，在 const 项中使用时按 80x25
+//! 检查，布局有错就是编译错误；其他尺寸在 resolve 或 render 时检查。
+//!
+//! render 返回 Panels，运行时按名字取得方框的内部或预留区域，不必在各处写死坐标。
+//! Writer 只覆盖前 BUFFER_HEIGHT 行，更高的模式下超出的部分不会画出来
+use super::{Cell, ColorCode, ScreenChar, Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+use core::fmt;
+
+/// 一个布局最多包含的元素数
+pub const MAX_ELEMENTS: usize = 16;
+
+/// CP437 的单线框字符
+pub(super) const TOP_LEFT: u8 = 0xda;
+pub(super) const TOP_RIGHT: u8 = 0xbf;
+pub(super) const BOTTOM_LEFT: u8 = 0xc0;
+pub(super) const BOTTOM_RIGHT: u8 = 0xd9;
+pub(super) const HORIZONTAL: u8 = 0xc4;
+pub(super) const VERTICAL: u8 = 0xb3;
+
+/// 行或列的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pos {
+    /// 距离上边或左边的格数
+    Start(usize),
+    /// 元素的末端距离下边或右边的格数
+    End(usize),
+    /// 屏幕尺寸的百分比，向下取整
+    Percent(u8),
+}
+
+/// 高度或宽度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extent {
+    Cells(usize),
+    /// 屏幕尺寸的百分比，向下取整
+    Percent(u8),
+}
+
+impl Pos {
+    const fn resolve(self, total: usize, size: usize) -> Option<usize> {
+        match self {
+            Pos::Start(cells) => Some(cells),
+            Pos::End(cells) => match cells.checked_add(size) {
+                Some(span) => total.checked_sub(span),
+                None => None,
+            },
+            Pos::Percent(percent) => Some(total * percent as usize / 100),
+        }
+    }
+}
+
+impl Extent {
+    const fn resolve(self, total: usize) -> usize {
+        match self {
+            Extent::Cells(cells) => cells,
+            Extent::Percent(percent) => total * percent as usize / 100,
+        }
+    }
+}
+
+/// 元素的位置和大小
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Place {
+    pub row: Pos,
+    pub col: Pos,
+    pub height: Extent,
+    pub width: Extent,
+}
+
+impl Place {
+    pub const fn new(row: Pos, col: Pos, height: Extent, width: Extent) -> Self {
+        Place {
+            row,
+            col,
+            height,
+            width,
+        }
+    }
+}
+
+/// 解析出来的矩形，坐标是 (行, 列)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    pub row: usize,
+    pub col: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Area {
+    const EMPTY: Area = Area {
+        row: 0,
+        col: 0,
+        height: 0,
+        width: 0,
+    };
+
+    const fn bottom(&self) -> usize {
+        self.row + self.height
+    }
+
+    const fn right(&self) -> usize {
+        self.col + self.width
+    }
+
+    const fn intersects(&self, other: &Area) -> bool {
+        self.row < other.bottom()
+            && other.row < self.bottom()
+            && self.col < other.right()
+            && other.col < self.right()
+    }
+
+    const fn contains(&self, other: &Area) -> bool {
+        self.row <= other.row
+            && self.col <= other.col
+            && other.bottom() <= self.bottom()
+            && other.right() <= self.right()
+    }
+
+    /// 去掉一圈边框后的部分
+    pub(super) const fn interior(&self) -> Area {
+        Area {
+            row: self.row + 1,
+            col: self.col + 1,
+            height: self.height.saturating_sub(2),
+            width: self.width.saturating_sub(2),
+        }
+    }
+
+    /// 用空格和 color 填满整个区域
+    pub fn clear(&self, writer: &mut Writer, color: ColorCode) {
+        for row in self.row..self.bottom() {
+            for col in self.col..self.right() {
+                put(writer, row, col, b' ', color);
+            }
+        }
+    }
+
+    /// 清空区域中的第 line 行，再从行首写入 args，超出区域宽度的部分被丢弃；
+    /// line 超出区域的高度时什么也不做
+    pub fn write_line(
+        &self,
+        writer: &mut Writer,
+        line: usize,
+        color: ColorCode,
+        args: fmt::Arguments,
+    ) {
+        if line >= self.height {
+            return;
+        }
+        let row = Area {
+            row: self.row + line,
+            height: 1,
+            ..*self
+        };
+        row.clear(writer, color);
+        let mut clipped = Clipped {
+            writer,
+            area: row,
+            col: row.col,
+            color,
+        };
+        let _ = fmt::Write::write_fmt(&mut clipped, args);
+    }
+}
+
+/// 写入不超出 area 的部分
+struct Clipped<'a> {
+    writer: &'a mut Writer,
+    area: Area,
+    col: usize,
+    color: ColorCode,
+}
+
+impl fmt::Write for Clipped<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.col < self.area.right() {
+                put(self.writer, self.area.row, self.col, byte, self.color);
+                self.col += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writer 覆盖不到的单元格直接跳过
+fn put(writer: &mut Writer, row: usize, col: usize, byte: u8, color: ColorCode) {
+    let _ = writer.try_put(Cell { row, col }, ScreenChar::new(byte, color));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Element {
+    /// 单线方框，标题写在上边框上；按名字取得的是边框以内的部分
+    Box {
+        name: &'static str,
+        title: &'static str,
+        place: Place,
+        color: ColorCode,
+    },
+    /// 一行文字，只能是 ASCII
+    Label {
+        row: Pos,
+        col: Pos,
+        text: &'static str,
+        color: ColorCode,
+    },
+    /// 预留给运行时更新的区域，render 不画任何东西
+    Region { name: &'static str, place: Place },
+}
+
+impl Element {
+    pub const fn boxed(
+        name: &'static str,
+        title: &'static str,
+        place: Place,
+        color: ColorCode,
+    ) -> Self {
+        Element::Box {
+            name,
+            title,
+            place,
+            color,
+        }
+    }
+
+    pub const fn label(row: Pos, col: Pos, text: &'static str, color: ColorCode) -> Self {
+        Element::Label {
+            row,
+            col,
+            text,
+            color,
+        }
+    }
+
+    pub const fn region(name: &'static str, place: Place) -> Self {
+        Element::Region { name, place }
+    }
+
+    const fn name(&self) -> Option<&'static str> {
+        match self {
+            Element::Box { name, .. } | Element::Region { name, .. } => Some(name),
+            Element::Label { .. } => None,
+        }
+    }
+
+    const fn place(&self) -> Place {
+        match *self {
+            Element::Box { place, .. } | Element::Region { place, .. } => place,
+            Element::Label { row, col, text, .. } => {
+                Place::new(row, col, Extent::Cells(1), Extent::Cells(text.len()))
+            }
+        }
+    }
+
+    /// 在 rows 行 cols 列的屏幕上占的矩形，位置算不出来时返回 None
+    const fn area(&self, rows: usize, cols: usize) -> Option<Area> {
+        let place = self.place();
+        let height = place.height.resolve(rows);
+        let width = place.width.resolve(cols);
+        match (
+            place.row.resolve(rows, height),
+            place.col.resolve(cols, width),
+        ) {
+            (Some(row), Some(col)) => Some(Area {
+                row,
+                col,
+                height,
+                width,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// 布局的错误，数字是元素的下标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutError {
+    TooManyElements(usize),
+    OutOfBounds(usize),
+    /// 宽或高为 0，或者方框小于 2x2
+    TooSmall(usize),
+    /// 两个元素重叠，并且不是一个在另一个方框的内部
+    Overlap(usize, usize),
+    DuplicateName(usize),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutError::TooManyElements(count) => {
+                write!(f, "{} elements, at most {}", count, MAX_ELEMENTS)
+            }
+            LayoutError::OutOfBounds(index) => write!(f, "element {} is off screen", index),
+            LayoutError::TooSmall(index) => write!(f, "element {} is too small", index),
+            LayoutError::Overlap(first, second) => {
+                write!(f, "elements {} and {} overlap", first, second)
+            }
+            LayoutError::DuplicateName(index) => {
+                write!(f, "element {} reuses an earlier name", index)
+            }
+        }
+    }
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut index = 0;
+    while index < a.len() {
+        if a[index] != b[index] {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
+
+/// inner 完全落在方框 outer 的边框以内
+const fn nested(outer: &Element, outer_area: &Area, inner_area: &Area) -> bool {
+    matches!(outer, Element::Box { .. }) && outer_area.interior().contains(inner_area)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Layout<'a> {
+    elements: &'a [Element],
+}
+
+impl<'a> Layout<'a> {
+    /// 按 80x25 检查，布局有错时 panic；在 const 项中使用时就是编译错误
+    pub const fn new(elements: &'a [Element]) -> Self {
+        match Layout::try_new(elements, BUFFER_HEIGHT, BUFFER_WIDTH) {
+            Ok(layout) => layout,
+            Err(LayoutError::TooManyElements(_)) => panic!("too many layout elements"),
+            Err(LayoutError::OutOfBounds(_)) => panic!("layout element is off screen"),
+            Err(LayoutError::TooSmall(_)) => panic!("layout element is too small"),
+            Err(LayoutError::Overlap(..)) => panic!("layout elements overlap"),
+            Err(LayoutError::DuplicateName(_)) => panic!("duplicate layout element name"),
+        }
+    }
+
+    /// 按 rows 行 cols 列检查
+    pub const fn try_new(
+        elements: &'a [Element],
+        rows: usize,
+        cols: usize,
+    ) -> Result<Self, LayoutError> {
+        let layout = Layout { elements };
+        match layout.resolve(rows, cols) {
+            Ok(_) => Ok(layout),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// 计算每个元素在 rows 行 cols 列的屏幕上的位置并检查
+    pub const fn resolve(&self, rows: usize, cols: usize) -> Result<Panels<'a>, LayoutError> {
+        let elements = self.elements;
+        if elements.len() > MAX_ELEMENTS {
+            return Err(LayoutError::TooManyElements(elements.len()));
+        }
+        let mut areas = [Area::EMPTY; MAX_ELEMENTS];
+        let mut index = 0;
+        while index < elements.len() {
+            let element = &elements[index];
+            let Some(area) = element.area(rows, cols) else {
+                return Err(LayoutError::OutOfBounds(index));
+            };
+            // 写成减法，过大的坐标不会溢出
+            if area.row > rows
+                || area.height > rows - area.row
+                || area.col > cols
+                || area.width > cols - area.col
+            {
+                return Err(LayoutError::OutOfBounds(index));
+            }
+            let minimum = if matches!(element, Element::Box { .. }) {
+                2
+            } else {
+                1
+            };
+            if area.height < minimum || area.width < minimum {
+                return Err(LayoutError::TooSmall(index));
+            }
+            let mut earlier = 0;
+            while earlier < index {
+                let other = &elements[earlier];
+                let other_area = &areas[earlier];
+                if area.intersects(other_area)
+                    && !nested(other, other_area, &area)
+                    && !nested(element, &area, other_area)
+                {
+                    return Err(LayoutError::Overlap(earlier, index));
+                }
+                if let (Some(name), Some(other_name)) = (element.name(), other.name()) {
+                    if str_eq(name, other_name) {
+                        return Err(LayoutError::DuplicateName(index));
+                    }
+                }
+                earlier += 1;
+            }
+            areas[index] = area;
+            index += 1;
+        }
+        Ok(Panels { elements, areas })
+    }
+
+    /// 解析之后画出方框和标签，返回各个命名区域的位置
+    pub fn render(
+        &self,
+        writer: &mut Writer,
+        rows: usize,
+        cols: usize,
+    ) -> Result<Panels<'a>, LayoutError> {
+        let panels = self.resolve(rows, cols)?;
+        for (element, area) in self.elements.iter().zip(&panels.areas) {
+            match *element {
+                Element::Box { title, color, .. } => draw_box(writer, area, title, color),
+                Element::Label { text, color, .. } => {
+                    for (offset, &byte) in text.as_bytes().iter().enumerate() {
+                        put(writer, area.row, area.col + offset, byte, color);
+                    }
+                }
+                Element::Region { .. } => {}
+            }
+        }
+        Ok(panels)
+    }
+}
+
+/// 边框加上清空的内部，标题放在上边框左侧，放不下的部分被截掉。
+/// Layout 保证方框至少 2x2；更小的区域不报错：空区域什么也不画，只有一行或一列时画成一条线
+pub(super) fn draw_box(writer: &mut Writer, area: &Area, title: &str, color: ColorCode) {
+    if area.height == 0 || area.width == 0 {
+        return;
+    }
+    if area.height == 1 || area.width == 1 {
+        let line = if area.height == 1 {
+            HORIZONTAL
+        } else {
+            VERTICAL
+        };
+        for row in area.row..area.bottom() {
+            for col in area.col..area.right() {
+                put(writer, row, col, line, color);
+            }
+        }
+        return;
+    }
+    let (bottom, right) = (area.bottom() - 1, area.right() - 1);
+    for col in area.col..=right {
+        put(writer, area.row, col, HORIZONTAL, color);
+        put(writer, bottom, col, HORIZONTAL, color);
+    }
+    for row in area.row..=bottom {
+        put(writer, row, area.col, VERTICAL, color);
+        put(writer, row, right, VERTICAL, color);
+    }
+    put(writer, area.row, area.col, TOP_LEFT, color);
+    put(writer, area.row, right, TOP_RIGHT, color);
+    put(writer, bottom, area.col, BOTTOM_LEFT, color);
+    put(writer, bottom, right, BOTTOM_RIGHT, color);
+    area.interior().clear(writer, color);
+    // 标题两边各留一个空格，不覆盖两个角
+    let room = area.width.saturating_sub(4);
+    for (offset, &byte) in title.as_bytes().iter().take(room).enumerate() {
+        put(writer, area.row, area.col + 2 + offset, byte, color);
+    }
+}
+
+/// 解析好的布局，按名字查找区域
+#[derive(Debug, Clone, Copy)]
+pub struct Panels<'a> {
+    elements: &'a [Element],
+    areas: [Area; MAX_ELEMENTS],
+}
+
+impl Panels<'_> {
+    /// 方框返回边框以内的部分，预留区域返回整个区域
+    pub fn get(&self, name: &str) -> Option<Area> {
+        self.elements
+            .iter()
+            .zip(&self.areas)
+            .find(|(element, _)| element.name() == Some(name))
+            .map(|(element, area)| match element {
+                Element::Box { .. } => area.interior(),
+                _ => *area,
+            })
+    }
+}
+
+#[cfg(test)]
+use super::{Color, TestWriter};
+
+#[cfg(test)]
+const WHITE: ColorCode = ColorCode::new(Color::White, Color::Blue);
+
+#[cfg(test)]
+const BOOT_SCREEN: Layout = Layout::new(&[
+    Element::label(Pos::Start(0), Pos::Start(0), "vm_os", WHITE),
+    Element::boxed(
+        "memory",
+        "Memory",
+        Place::new(
+            Pos::Start(2),
+            Pos::Start(0),
+            Extent::Percent(40),
+            Extent::Percent(50),
+        ),
+        WHITE,
+    ),
+    Element::label(Pos::Start(3), Pos::Start(2), "heap", WHITE),
+    Element::region(
+        "log",
+        Place::new(
+            Pos::Start(2),
+            Pos::Percent(50),
+            Extent::Percent(40),
+            Extent::Percent(50),
+        ),
+    ),
+    Element::region(
+        "status",
+        Place::new(
+            Pos::End(0),
+            Pos::Start(0),
+            Extent::Cells(1),
+            Extent::Percent(100),
+        ),
+    ),
+]);
+
+#[test_case]
+fn test_invalid_layouts_are_rejected() {
+    let place = |row, col, height, width| {
+        Place::new(
+            Pos::Start(row),
+            Pos::Start(col),
+            Extent::Cells(height),
+            Extent::Cells(width),
+        )
+    };
+    let boxed = |name, place| Element::boxed(name, "", place, WHITE);
+    let check = |elements: &[Element]| Layout::try_new(elements, BUFFER_HEIGHT, BUFFER_WIDTH).err();
+
+    assert_eq!(
+        check(&[boxed("a", place(0, 70, 3, 20))]),
+        Some(LayoutError::OutOfBounds(0))
+    );
+    assert_eq!(
+        check(&[Element::region(
+            "a",
+            Place::new(
+                Pos::End(30),
+                Pos::Start(0),
+                Extent::Cells(1),
+                Extent::Cells(1)
+            )
+        )]),
+        Some(LayoutError::OutOfBounds(0))
+    );
+    assert_eq!(
+        check(&[boxed("a", place(0, 0, 1, 10))]),
+        Some(LayoutError::TooSmall(0))
+    );
+    assert_eq!(
+        check(&[
+            boxed("a", place(0, 0, 5, 10)),
+            boxed("b", place(4, 9, 5, 10))
+        ]),
+        Some(LayoutError::Overlap(0, 1))
+    );
+    // 压在边框上的标签算重叠，完全在内部的不算
+    let label = |row, col| Element::label(Pos::Start(row), Pos::Start(col), "abc", WHITE);
+    assert_eq!(
+        check(&[boxed("a", place(0, 0, 5, 10)), label(0, 2)]),
+        Some(LayoutError::Overlap(0, 1))
+    );
+    assert_eq!(
+        check(&[label(4, 7), boxed("a", place(0, 0, 5, 10))]),
+        Some(LayoutError::Overlap(0, 1))
+    );
+    assert_eq!(check(&[boxed("a", place(0, 0, 5, 10)), label(1, 1)]), None);
+    assert_eq!(
+        check(&[boxed("a", place(0, 0, 3, 3)), boxed("a", place(5, 0, 3, 3))]),
+        Some(LayoutError::DuplicateName(1))
+    );
+    let many = [label(0, 0); MAX_ELEMENTS + 1];
+    assert_eq!(
+        check(&many),
+        Some(LayoutError::TooManyElements(MAX_ELEMENTS + 1))
+    );
+
+    // 80x25 下合法的布局在更小的屏幕上可能放不下
+    assert_eq!(
+        Layout::try_new(&[boxed("a", place(20, 0, 3, 10))], 20, 80).err(),
+        Some(LayoutError::OutOfBounds(0))
+    );
+}
+
+#[test_case]
+fn test_degenerate_boxes() {
+    let mut writer = TestWriter::new();
+    let area = |height, width| Area {
+        row: 0,
+        col: 0,
+        height,
+        width,
+    };
+    let glyph = |writer: &TestWriter, row, col| writer.read_char(row, col).0;
+
+    for empty in [area(0, 0), area(0, 5), area(5, 0)] {
+        draw_box(&mut writer, &empty, "title", WHITE);
+        assert_eq!(glyph(&writer, 0, 0), b' ');
+    }
+    draw_box(&mut writer, &area(1, 1), "title", WHITE);
+    assert_eq!(glyph(&writer, 0, 0), HORIZONTAL);
+    assert_eq!(glyph(&writer, 0, 1), b' ');
+    assert_eq!(glyph(&writer, 1, 0), b' ');
+
+    draw_box(&mut writer, &area(1, 4), "title", WHITE);
+    assert!((0..4).all(|col| glyph(&writer, 0, col) == HORIZONTAL));
+    assert_eq!(glyph(&writer, 0, 4), b' ');
+    draw_box(&mut writer, &area(3, 1), "", WHITE);
+    assert!((0..3).all(|row| glyph(&writer, row, 0) == VERTICAL));
+
+    // 2x2 只有四个角
+    draw_box(&mut writer, &area(2, 2), "title", WHITE);
+    assert_eq!(glyph(&writer, 0, 0), TOP_LEFT);
+    assert_eq!(glyph(&writer, 1, 1), BOTTOM_RIGHT);
+}
+
+#[test_case]
+fn test_render_boot_screen() {
+    let mut writer = TestWriter::new();
+    let panels = BOOT_SCREEN
+        .render(&mut writer, BUFFER_HEIGHT, BUFFER_WIDTH)
+        .unwrap();
+
+    assert_eq!(writer.read_char(0, 0), (b'v', WHITE));
+    // 方框占第 2..12 行、第 0..40 列，标题在上边框上
+    assert_eq!(writer.read_char(2, 0).0, TOP_LEFT);
+    assert_eq!(writer.read_char(2, 1).0, HORIZONTAL);
+    assert_eq!(writer.read_char(2, 2).0, b'M');
+    assert_eq!(writer.read_char(2, 39).0, TOP_RIGHT);
+    assert_eq!(writer.read_char(11, 0).0, BOTTOM_LEFT);
+    assert_eq!(writer.read_char(11, 39).0, BOTTOM_RIGHT);
+    assert_eq!(writer.read_char(5, 39).0, VERTICAL);
+    // 方框内部的标签在方框之后画出，没有被清掉
+    assert_eq!(writer.read_char(3, 2), (b'h', WHITE));
+
+    let memory = panels.get("memory").unwrap();
+    assert_eq!(
+        memory,
+        Area {
+            row: 3,
+            col: 1,
+            height: 8,
+            width: 38
+        }
+    );
+    assert_eq!(panels.get("log").unwrap().col, 40);
+    assert_eq!(panels.get("status").unwrap().row, BUFFER_HEIGHT - 1);
+    assert_eq!(panels.get("nothing"), None);
+
+    // 按名字更新区域，超出宽度的部分被截掉
+    memory.write_line(&mut writer, 1, WHITE, format_args!("{:-<50}", "used"));
+    assert_eq!(writer.read_char(4, 1).0, b'u');
+    assert_eq!(writer.read_char(4, 38).0, b'-');
+    assert_eq!(writer.read_char(4, 39).0, VERTICAL);
+}
+
+#[test_case]
+fn test_resolve_after_mode_change() {
+    // 80x50 下百分比和靠下对齐的元素重新计算位置
+    let panels = BOOT_SCREEN.resolve(50, 80).unwrap();
+    assert_eq!(
+        panels.get("memory").unwrap(),
+        Area {
+            row: 3,
+            col: 1,
+            height: 18,
+            width: 38
+        }
+    );
+    assert_eq!(panels.get("status").unwrap().row, 49);
+    assert_eq!(panels.get("log").unwrap().height, 20);
+}
@@ -0,0 +1,92 @@
+//! 跑马灯
+//! 在一行中循环滚动显示一段文字，每次 tick 向左移动一列，文字的末尾和开头之间隔开 GAP 列。
+//! 通常放在 set_scroll_top 保留的行中，由定时器驱动（见 statusbar::run_marquee）：
+//! 跑马灯只写它自己的那一行，换行和滚屏不碰保留的行，两者各管各的区域；
+//! 每次 tick 在持有 WRITER 的锁时一次画完整行，不会和输出交错
+use super::{ColorCode, ScreenChar, Writer, BUFFER_WIDTH};
+
+/// 文字首尾之间的空白列数
+pub const GAP: usize = 4;
+/// 非 ASCII 或控制字节显示为 CP437 的实心方块
+const REPLACEMENT: u8 = 0xfe;
+
+pub struct Marquee {
+    /// 只支持 ASCII，其他字节显示为替代字节
+    text: &'static str,
+    row: usize,
+    color: ColorCode,
+    /// 这一行第 0 列显示的是文字加空白中的第几个字节
+    offset: usize,
+}
+
+impl Marquee {
+    pub const fn new(text: &'static str, row: usize, color: ColorCode) -> Self {
+        Marquee {
+            text,
+            row,
+            color,
+            offset: 0,
+        }
+    }
+
+    /// 画出当前的一帧，然后向左移动一列；row 超出屏幕时什么也不画
+    pub fn tick(&mut self, writer: &mut Writer) {
+        let period = self.text.len() + GAP;
+        let bytes = self.text.as_bytes();
+        for col in 0..BUFFER_WIDTH {
+            let index = (self.offset + col) % period;
+            let byte = match bytes.get(index) {
+                Some(&byte) if byte == b' ' || byte.is_ascii_graphic() => byte,
+                Some(_) => REPLACEMENT,
+                None => b' ',
+            };
+            let cell = super::Cell { row: self.row, col };
+            let _ = writer.try_put(cell, ScreenChar::new(byte, self.color));
+        }
+        self.offset = (self.offset + 1) % period;
+    }
+}
+
+#[cfg(test)]
+use super::{Color, TestWriter, BUFFER_HEIGHT};
+
+#[test_case]
+fn test_marquee_and_log_output_share_the_screen() {
+    use alloc::string::String;
+
+    let row_text = |writer: &Writer, row: usize| -> String {
+        (0..BUFFER_WIDTH)
+            .map(|col| writer.read_char(row, col).0 as char)
+            .collect()
+    };
+    let mut writer = TestWriter::new();
+    writer.set_scroll_top(1);
+    let color = ColorCode::new(Color::Black, Color::Cyan);
+    let mut marquee = Marquee::new("news", 0, color);
+    let period = "news".len() + GAP;
+
+    for line in 0..BUFFER_HEIGHT * 2 {
+        marquee.tick(&mut writer);
+        writer.write_string(&alloc::format!("log {}\n", line));
+        // 跑马灯的一行没有被滚走，也没有混进日志
+        let expected: String = (0..BUFFER_WIDTH)
+            .map(|col| match (line + col) % period {
+                index if index < 4 => "news".as_bytes()[index] as char,
+                _ => ' ',
+            })
+            .collect();
+        assert_eq!(row_text(&writer, 0), expected, "after line {}", line);
+        assert_eq!(writer.read_char(0, 0).1, color);
+        // 日志照常滚动，最新的一行在光标上面
+        let latest = alloc::format!("log {}", line);
+        assert!(row_text(&writer, BUFFER_HEIGHT - 2).starts_with(&latest));
+    }
+    // 滚动区域的第一行是日志，不是跑马灯
+    assert!(row_text(&writer, 1).starts_with("log "));
+
+    // 退格退回上一行时只下移滚动区域，空出的是区域的第一行，跑马灯不动
+    let frame = row_text(&writer, 0);
+    writer.backspace();
+    assert_eq!(row_text(&writer, 0), frame);
+    assert!(row_text(&writer, 1).trim().is_empty());
+}
@@ -0,0 +1,2937 @@
+//！ VGA 字符单元布局
+//！ Bit(s)	Value
+//！ 0-7	    ASCII code point
+//！ 8-11	Foreground color
+//！ 12-14	Background color
+//！ 15	    Blink
+use crate::console::{self, ConsoleState, OutputSink, Style};
+use crate::log::Level;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use volatile::Volatile;
+
+mod cell;
+mod chart;
+pub mod cp437;
+mod cursor;
+mod dialog;
+mod draw;
+pub mod early;
+pub mod layout;
+mod marquee;
+mod pause;
+mod rainbow;
+mod redraw;
+mod sanitize;
+mod scrollback;
+mod snapshot;
+pub mod soft_cursor;
+mod status_line;
+mod typewriter;
+mod virtual_console;
+mod width;
+
+pub use cell::{Cell, CellError};
+pub use chart::draw_bar_chart;
+pub use cursor::CursorShapeError;
+pub use dialog::{show_dialog, Dialog};
+pub use draw::{init_draw_buffer, DrawTransaction};
+pub use layout::{Layout, LayoutError};
+pub use marquee::Marquee;
+pub(crate) use pause::resume_output_locked;
+pub use pause::{output_paused, pause_output, resume_output, PAUSE_BUFFER_SIZE, TRUNCATED_MARKER};
+pub use rainbow::{RainbowText, RAINBOW_COLORS};
+pub use redraw::{redraw, ROWS_PER_CHUNK};
+pub use sanitize::{sanitize_hardware_state, Fixes, InheritedScreen};
+pub use scrollback::{init_scrollback, ScreenRow, SCROLLBACK_LINES};
+pub use snapshot::Snapshot;
+pub use status_line::{Align, StatusLine};
+pub use typewriter::write_typewriter;
+pub use virtual_console::{init_virtual_consoles, ConsoleError, VirtualConsole, VIRTUAL_CONSOLES};
+pub use width::{char_display_width, display_width, PadRight};
+
+/// 默认情况下，Rust 编译器可以自由选择枚举的内存布局和大小，但使用 repr 属性可以明确指定
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+/// 按声明顺序排列的全部 16 种颜色，ALL_COLORS[i] as u8 == i
+pub const ALL_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::Blue,
+    Color::Green,
+    Color::Cyan,
+    Color::Red,
+    Color::Magenta,
+    Color::Brown,
+    Color::LightGray,
+    Color::DarkGray,
+    Color::LightBlue,
+    Color::LightGreen,
+    Color::LightCyan,
+    Color::LightRed,
+    Color::Pink,
+    Color::Yellow,
+    Color::White,
+];
+
+/// 标准 VGA 调色板中每种颜色的亮度（0.299R + 0.587G + 0.114B），按 ALL_COLORS 的顺序
+const LUMA: [u8; 16] = [
+    0, 19, 100, 119, 51, 70, 101, 170, 85, 104, 185, 204, 136, 155, 236, 255,
+];
+
+impl TryFrom<u8> for Color {
+    /// 超出 0-15 范围时返回原值
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        ALL_COLORS.get(value as usize).copied().ok_or(value)
+    }
+}
+
+impl Color {
+    /// 按名称查找颜色，不区分大小写，例如 "lightblue"、"White"
+    pub fn from_name(name: &str) -> Option<Color> {
+        ALL_COLORS
+            .into_iter()
+            .find(|color| color.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Color::Black => "black",
+            Color::Blue => "blue",
+            Color::Green => "green",
+            Color::Cyan => "cyan",
+            Color::Red => "red",
+            Color::Magenta => "magenta",
+            Color::Brown => "brown",
+            Color::LightGray => "lightgray",
+            Color::DarkGray => "darkgray",
+            Color::LightBlue => "lightblue",
+            Color::LightGreen => "lightgreen",
+            Color::LightCyan => "lightcyan",
+            Color::LightRed => "lightred",
+            Color::Pink => "pink",
+            Color::Yellow => "yellow",
+            Color::White => "white",
+        }
+    }
+
+    /// 在这个颜色上看得清的前景色：亮的颜色配黑色，暗的颜色配白色
+    pub const fn complement(self) -> Color {
+        if LUMA[self as usize] >= 128 {
+            Color::Black
+        } else {
+            Color::White
+        }
+    }
+}
+
+/// "repr(transparent)" 让包装类型在内存中的表示与被包装的类型完全一致
+/// 使 ColorCode 跟 u8 内存布局相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct ColorCode(u8);
+
+impl ColorCode {
+    /// 使用一个 u8 储存前景背景色
+    /// 如果背景是白色(0000 1111)，前景是蓝色(0000 0001)
+    /// (0000 1111) << 4 = (1111 0000)
+    /// (1111 0000) | 蓝色(0000 0001) = (1111 0001)
+    pub const fn new(foreground: Color, background: Color) -> Self {
+        Self((background as u8) << 4 | (foreground as u8))
+    }
+
+    /// 拆分出 (前景色, 背景色, 闪烁)
+    /// 默认启用闪烁时，属性的 bit 7 是闪烁位而不是背景色的高位，所以背景色只取 bit 4-6，
+    /// 例如 new(White, Yellow) 会解码为 (White, Brown, true)
+    pub fn decode(self) -> (Color, Color, bool) {
+        let foreground = Color::try_from(self.0 & 0x0F).unwrap();
+        let background = Color::try_from((self.0 >> 4) & 0x07).unwrap();
+        let blink = self.0 & 0x80 != 0;
+        (foreground, background, blink)
+    }
+
+    /// 背景色和闪烁位不变，换成新的前景色
+    pub fn with_foreground(self, foreground: Color) -> Self {
+        Self(self.0 & 0xf0 | foreground as u8)
+    }
+
+    /// 前景色不变，换成新的背景色
+    pub fn with_background(self, background: Color) -> Self {
+        let (foreground, _, _) = self.decode();
+        Self::new(foreground, background)
+    }
+
+    /// 前景色与背景色互换，用于反色显示；再次调用得到原来的颜色
+    pub fn inverted(self) -> Self {
+        Self(self.0.rotate_left(4))
+    }
+
+    /// 字符单元中的属性字节
+    pub const fn attribute(self) -> u8 {
+        self.0
+    }
+
+    pub const fn from_attribute(attribute: u8) -> Self {
+        Self(attribute)
+    }
+}
+
+/// Writer 和空白缓冲区使用的默认颜色，即 DEFAULT_COLOR
+impl Default for ColorCode {
+    fn default() -> Self {
+        DEFAULT_COLOR
+    }
+}
+
+/// 写入堆上新分配的空白离屏缓冲区的 Writer，颜色等设置与 WRITER 初始化时相同，drop 时释放缓冲区。
+/// 用于集成测试等需要一个临时 Writer 的地方；crate 内的单元测试用 TestWriter
+pub struct OffscreenWriter {
+    writer: Writer,
+    backing: *mut Buffer,
+}
+
+impl OffscreenWriter {
+    pub fn new() -> Self {
+        use alloc::boxed::Box;
+
+        let backing = Box::into_raw(Box::new(Buffer::new()));
+        OffscreenWriter {
+            writer: Writer::new(unsafe { &mut *backing }),
+            backing,
+        }
+    }
+}
+
+impl Default for OffscreenWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::ops::Deref for OffscreenWriter {
+    type Target = Writer;
+
+    fn deref(&self) -> &Writer {
+        &self.writer
+    }
+}
+
+impl core::ops::DerefMut for OffscreenWriter {
+    fn deref_mut(&mut self) -> &mut Writer {
+        &mut self.writer
+    }
+}
+
+impl Drop for OffscreenWriter {
+    fn drop(&mut self) {
+        // 缓冲区只被 writer 引用，writer 在这之后不会再被使用
+        drop(unsafe { alloc::boxed::Box::from_raw(self.backing) });
+    }
+}
+
+/// new_line 滚动后新出现的最后一行用什么颜色填充
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NewlineFill {
+    /// 使用 Writer 的当前颜色
+    #[default]
+    CurrentColor,
+    /// 沿用被上移的那一行行尾单元格的背景色，前景色仍取当前颜色，
+    /// 整屏都是彩色背景时滚动不会出现一条颜色不同的行
+    MatchLastRow,
+}
+
+/// 自动折行的标记，见 Writer::set_wrap_indicator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrapIndicator {
+    /// 画在被折行的那一行最后一列的字节
+    pub glyph: u8,
+    /// 续行开头空出的列数
+    pub indent: usize,
+}
+
+impl WrapIndicator {
+    /// CP437 中的 →，续行缩进两格
+    pub const DEFAULT: WrapIndicator = WrapIndicator {
+        glyph: 0x1a,
+        indent: 2,
+    };
+}
+
+/// "repr(C)" 指定结构体或枚举在内存中的布局方式应当遵循 C 语言的规则
+/// 意味着
+/// 1. 结构体字段按照声明顺序排列
+/// 2. 遵循 C 语言的对齐规则
+/// 3. 不进行字段重排优化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct ScreenChar {
+    ascii_character: u8,
+    color_code: ColorCode,
+}
+
+impl ScreenChar {
+    pub const fn new(ascii_character: u8, color_code: ColorCode) -> Self {
+        ScreenChar {
+            ascii_character,
+            color_code,
+        }
+    }
+
+    pub const fn ascii_character(self) -> u8 {
+        self.ascii_character
+    }
+
+    pub const fn color_code(self) -> ColorCode {
+        self.color_code
+    }
+}
+
+pub const BUFFER_WIDTH: usize = 80;
+/// ASCII VT，纵向制表符
+const VERTICAL_TAB: u8 = 0x0b;
+const BELL: u8 = 0x07;
+/// ASCII DEL，解释时与退格相同，不解释时显示 CP437 中的房子字形 ⌂
+const DELETE: u8 = 0x7f;
+/// 默认的替代字节，CP437 中的实心方块
+const DEFAULT_REPLACEMENT: u8 = 0xfe;
+/// Writer 创建时和 reset 之后的颜色
+pub const DEFAULT_COLOR: ColorCode = ColorCode::new(Color::Yellow, Color::Black);
+/// eprint! 使用的颜色
+const ERROR_COLOR: ColorCode = ColorCode::new(Color::LightRed, Color::Black);
+/// hr! 默认使用的 CP437 横线
+pub const RULE_CHAR: u8 = 0xc4;
+pub const BUFFER_HEIGHT: usize = 25;
+
+/// u64 最多 20 位十进制数
+const MAX_DECIMAL_DIGITS: usize = 20;
+
+/// value 的十进制表示，写在 digits 的末尾，不分配内存
+fn decimal(value: u64, digits: &mut [u8; MAX_DECIMAL_DIGITS]) -> &[u8] {
+    let mut start = digits.len();
+    let mut rest = value;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    &digits[start..]
+}
+
+/// u64 最多 64 位二进制数
+const MAX_RADIX_DIGITS: usize = 64;
+
+/// value 在 radix 进制下的表示，字母用大写，写在 digits 的末尾，不分配内存
+fn radix_digits(value: u64, radix: u32, digits: &mut [u8; MAX_RADIX_DIGITS]) -> &[u8] {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let radix = radix as u64;
+    let mut start = digits.len();
+    let mut rest = value;
+    loop {
+        start -= 1;
+        digits[start] = DIGITS[(rest % radix) as usize];
+        rest /= radix;
+        if rest == 0 {
+            break;
+        }
+    }
+    &digits[start..]
+}
+
+/// 把单元格坐标限制在屏幕内，见 Cell::clamped
+fn clamp_cell(row: usize, col: usize) -> (usize, usize) {
+    let cell = Cell::clamped(row, col);
+    (cell.row, cell.col)
+}
+
+/// 光标写入的单元格：越界说明光标状态有错，调试构建中断言；
+/// 否则写到最近的有效单元格，打印也发生在 panic 处理中，不能在这里因为越界再次 panic
+fn cursor_cell(row: usize, col: usize) -> (usize, usize) {
+    debug_assert!(
+        row < BUFFER_HEIGHT && col < BUFFER_WIDTH,
+        "cursor ({}, {}) is off screen",
+        row,
+        col
+    );
+    clamp_cell(row, col)
+}
+
+/// 与 0xb8000 处的文本缓冲区布局相同的 BUFFER_HEIGHT × BUFFER_WIDTH 个单元格。
+/// WRITER 指向显存，其他 Writer 可以用 Buffer::new 在普通内存中创建一个，不需要 unsafe：
+/// crate 内的单元测试用 TestWriter，集成测试用 OffscreenWriter，两者都在 drop 时释放
+#[repr(transparent)]
+pub struct Buffer {
+    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Buffer {
+    /// 在普通内存中创建一个空白缓冲区，用于测试或离屏绘制
+    pub fn new() -> Self {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: DEFAULT_COLOR,
+        };
+        Buffer {
+            chars: core::array::from_fn(|_| core::array::from_fn(|_| Volatile::new(blank))),
+        }
+    }
+
+    /// 第 row 行从第 0 列开始的 N 个字符
+    #[cfg(test)]
+    pub(crate) fn row_text<const N: usize>(&self, row: usize) -> [u8; N] {
+        core::array::from_fn(|col| self.chars[row][col].read().ascii_character)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn color_at(&self, row: usize, col: usize) -> ColorCode {
+        self.chars[row][col].read().color_code
+    }
+}
+
+/// 把 from 的 rows 这几行逐个单元格复制到 to 的同一位置
+pub(super) fn copy_rows(from: &Buffer, to: &mut Buffer, rows: core::ops::Range<usize>) {
+    for row in rows {
+        for col in 0..BUFFER_WIDTH {
+            to.chars[row][col].write(from.chars[row][col].read());
+        }
+    }
+}
+
+pub struct Writer {
+    column_position: usize,
+    // 默认背景色
+    color_code: ColorCode,
+    buffer: &'static mut Buffer,
+    /// 插入模式下可打印字符会把光标之后的内容右移，而不是覆盖
+    insert_mode: bool,
+    /// 滚出屏幕的行，见 enable_scrollback
+    scrollback: Option<scrollback::Scrollback>,
+    /// 是否解释控制字符，见 set_control_chars
+    control_chars: bool,
+    newline_fill: NewlineFill,
+    /// 反色显示中的区间，按行优先的单元格编号，两端都包含；下一次输出之前被清除
+    highlight: Option<(usize, usize)>,
+    /// 见 enable_virtual_consoles
+    consoles: Option<virtual_console::VirtualConsoles>,
+    /// 见 set_bell
+    bell: Option<fn()>,
+    /// 见 set_replacement_char
+    replacement: u8,
+    /// 批量绘制的后备缓冲区，见 enable_draw_buffer；事务期间被换到 buffer 中
+    draw_buffer: Option<&'static mut Buffer>,
+    frames_presented: u64,
+    /// 正在分段提交的重绘，见 redraw 模块
+    redraw: Option<redraw::PendingRedraw>,
+    redraw_generation: u64,
+    /// 上次 take_scrolled 之后屏幕上的文字是否上移过
+    scrolled: bool,
+    /// 见 set_wrap_indicator
+    wrap_indicator: Option<WrapIndicator>,
+    /// 见 soft_cursor 模块
+    soft_cursor: soft_cursor::SoftCursor,
+    /// 临时改变颜色期间换行填充使用的颜色，见 write_highlighted
+    fill_override: Option<ColorCode>,
+    /// 滚动区域的第一行，之上的行保留给状态栏等，见 set_scroll_top
+    scroll_top: usize,
+}
+
+impl Writer {
+    /// 创建一个写入指定缓冲区的 Writer，WRITER 使用的是 0xb8000 处的 VGA 缓冲区
+    pub fn new(buffer: &'static mut Buffer) -> Writer {
+        Writer {
+            column_position: 0,
+            color_code: DEFAULT_COLOR,
+            buffer,
+            insert_mode: false,
+            scrollback: None,
+            control_chars: true,
+            newline_fill: NewlineFill::CurrentColor,
+            highlight: None,
+            consoles: None,
+            bell: None,
+            replacement: DEFAULT_REPLACEMENT,
+            draw_buffer: None,
+            frames_presented: 0,
+            redraw: None,
+            redraw_generation: 0,
+            scrolled: false,
+            wrap_indicator: None,
+            soft_cursor: soft_cursor::SoftCursor::default(),
+            fill_override: None,
+            scroll_top: 0,
+        }
+    }
+
+    /// 让这个 Writer 类型将字符写入屏幕的最后一行，并在一行写满或接收到换行符 \n 的时候，将所有的字符向上位移一行
+    pub fn write_byte(&mut self, byte: u8) {
+        self.before_output();
+        match byte {
+            b'\n' => self.new_line(),
+            VERTICAL_TAB if self.control_chars => self.vertical_tab(),
+            DELETE if self.control_chars => self.backspace(),
+            BELL if self.rings_bell() => {
+                if let Some(bell) = self.bell {
+                    bell();
+                }
+            }
+            byte if self.insert_mode => self.insert_char(byte),
+            byte => {
+                self.wrap_if_full();
+
+                let (row, col) = cursor_cell(BUFFER_HEIGHT - 1, self.column_position);
+
+                let color_code = self.color_code;
+                self.buffer.chars[row][col].write(ScreenChar {
+                    ascii_character: byte,
+                    color_code,
+                });
+                self.column_position += 1;
+            }
+        }
+    }
+
+    /// 与 write_byte 相同，但写入会丢掉屏幕上的文字时什么也不写，返回 false：
+    /// - 需要换行（换行符、垂直制表符或者本行已写满），滚动区域的第一行有文字而又没有回滚缓冲区保存它
+    /// - 插入模式下本行最后一格有文字，会被挤出行尾
+    ///
+    /// 分页显示据此知道屏幕已满，应当停下来等待按键，而不是让内容滚出屏幕
+    pub fn try_write_byte(&mut self, byte: u8) -> bool {
+        if self.would_drop(byte) {
+            return false;
+        }
+        self.write_byte(byte);
+        true
+    }
+
+    fn would_drop(&self, byte: u8) -> bool {
+        let has_text = |row: usize, cols: core::ops::Range<usize>| {
+            cols.into_iter()
+                .any(|col| self.buffer.chars[row][col].read().ascii_character != b' ')
+        };
+        let scrolls = match byte {
+            b'\n' => true,
+            VERTICAL_TAB if self.control_chars => true,
+            DELETE if self.control_chars => return false,
+            BELL if self.rings_bell() => return false,
+            _ => self.column_position >= self.line_end(),
+        };
+        if scrolls && self.scrollback.is_none() && has_text(self.scroll_top, 0..BUFFER_WIDTH) {
+            return true;
+        }
+        // 换行之后是空行，插入不会挤掉任何东西
+        let last = self.line_end() - 1;
+        self.insert_mode && !scrolls && has_text(BUFFER_HEIGHT - 1, last..last + 1)
+    }
+
+    /// 所有行上移一行，光标回到行首
+    pub fn new_line(&mut self) {
+        self.scroll_up(1);
+    }
+
+    /// 滚动区域的所有行一次上移 lines 行（最多整个区域），空出的底部各行用换行的填充颜色清空，光标回到行首。
+    /// 结果与调用 lines 次 new_line 相同，但每个单元格只复制一次。scroll_top 之上的行不受影响
+    pub fn scroll_up(&mut self, lines: usize) {
+        let top = self.scroll_top;
+        let lines = lines.min(BUFFER_HEIGHT - top);
+        if lines == 0 {
+            return;
+        }
+        self.before_output();
+        self.save_top_rows(lines);
+        // 连续换行时后面几行的背景沿用第一次填充的颜色，结果一样
+        let fill = self.newline_fill_color();
+        // 从区域的第 lines 行开始，之前的行被移出屏幕，即它们将被下面的字符覆写
+        for row in top + lines..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let character = self.buffer.chars[row][col].read();
+                self.buffer.chars[row - lines][col].write(character);
+            }
+        }
+        for row in BUFFER_HEIGHT - lines..BUFFER_HEIGHT {
+            self.fill_row(row, fill);
+        }
+        self.column_position = 0;
+        self.scrolled = true;
+        self.redraw_view();
+    }
+
+    /// 把前 rows 行留给状态栏、跑马灯等固定内容：之后换行、滚屏和退格只移动 rows 行及以下的部分，
+    /// 不会碰到上面的行，上面的行也不进入回滚历史。至少保留最后一行用于输出。
+    /// 清屏和 reset 仍然清空整个屏幕，保留区的内容由它的主人重画
+    pub fn set_scroll_top(&mut self, rows: usize) {
+        self.scroll_top = rows.min(BUFFER_HEIGHT - 1);
+    }
+
+    pub fn scroll_top(&self) -> usize {
+        self.scroll_top
+    }
+
+    /// 设置自动折行的标记，None 关闭（默认）。
+    /// 打开时每行只写到倒数第二列：一行写满后再写入字符时，在最后一列用暗色画上 glyph，
+    /// 换行后空出 indent 列再继续，在回滚中也能分辨出哪些行原本是同一行。
+    /// 换行符换行时不画标记
+    pub fn set_wrap_indicator(&mut self, indicator: Option<WrapIndicator>) {
+        self.wrap_indicator = indicator.map(|indicator| WrapIndicator {
+            // 续行上至少还能写一个字符
+            indent: indicator.indent.min(BUFFER_WIDTH - 2),
+            ..indicator
+        });
+    }
+
+    pub fn wrap_indicator(&self) -> Option<WrapIndicator> {
+        self.wrap_indicator
+    }
+
+    /// 一行中可以写字符的列数，打开折行标记时最后一列留给标记
+    fn line_end(&self) -> usize {
+        match self.wrap_indicator {
+            Some(_) => BUFFER_WIDTH - 1,
+            None => BUFFER_WIDTH,
+        }
+    }
+
+    /// 本行已写满时自动折行
+    fn wrap_if_full(&mut self) {
+        if self.column_position < self.line_end() {
+            return;
+        }
+        let Some(indicator) = self.wrap_indicator else {
+            self.new_line();
+            return;
+        };
+        let dim = ColorCode(self.color_code.0 & 0xf0 | Color::DarkGray as u8);
+        self.buffer.chars[BUFFER_HEIGHT - 1][BUFFER_WIDTH - 1].write(ScreenChar {
+            ascii_character: indicator.glyph,
+            color_code: dim,
+        });
+        self.new_line();
+        self.column_position = indicator.indent;
+    }
+
+    /// 上次调用以来是否换过行（屏幕上的文字整体上移），读取后清除
+    /// 叠加在文字上的界面（例如 HUD）据此判断是否需要重画
+    pub fn take_scrolled(&mut self) -> bool {
+        core::mem::take(&mut self.scrolled)
+    }
+
+    /// 用当前颜色把整行填满 byte 作为分隔线，然后换行；光标不在行首时先换行，不覆盖已有的内容
+    pub fn write_rule(&mut self, byte: u8) {
+        if self.column_position > 0 {
+            self.new_line();
+        }
+        self.before_output();
+        let rule = ScreenChar {
+            ascii_character: byte,
+            color_code: self.color_code,
+        };
+        for col in 0..BUFFER_WIDTH {
+            self.buffer.chars[BUFFER_HEIGHT - 1][col].write(rule);
+        }
+        self.new_line();
+    }
+
+    fn newline_fill_color(&self) -> ColorCode {
+        let current = self.fill_override.unwrap_or(self.color_code);
+        match self.newline_fill {
+            NewlineFill::CurrentColor => current,
+            NewlineFill::MatchLastRow => {
+                let (_, last) = self.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1);
+                ColorCode(last.0 & 0xf0 | current.0 & 0x0f)
+            }
+        }
+    }
+
+    pub fn set_newline_fill(&mut self, fill: NewlineFill) {
+        self.newline_fill = fill;
+    }
+
+    pub fn newline_fill(&self) -> NewlineFill {
+        self.newline_fill
+    }
+
+    /// 光标下移一行、列不变。光标总在最后一行，所以就是保持列位置的换行
+    fn vertical_tab(&mut self) {
+        let col = self.column_position;
+        self.new_line();
+        self.column_position = col;
+    }
+
+    /// 打开时 write_string 等方法解释 VT、DEL 等控制字符，关闭时 VT 和其他不可打印字节一样显示为 0xfe，
+    /// DEL 原样显示为 ⌂。默认打开，换行符总是被解释
+    pub fn set_control_chars(&mut self, enabled: bool) {
+        self.control_chars = enabled;
+    }
+
+    pub fn control_chars(&self) -> bool {
+        self.control_chars
+    }
+
+    /// 设置 BEL（0x07）的处理函数，例如 speaker::bell；没有设置时 BEL 显示为 0xfe
+    /// 它会在持有 WRITER 的锁、关闭中断时被调用，不能阻塞
+    pub fn set_bell(&mut self, bell: Option<fn()>) {
+        self.bell = bell;
+    }
+
+    /// 设置不可打印的字节和无法显示的字符所用的替代字节，默认是 0xfe（■），也可以用 b'?' 或空格
+    pub fn set_replacement_char(&mut self, byte: u8) {
+        self.replacement = byte;
+    }
+
+    fn rings_bell(&self) -> bool {
+        self.control_chars && self.bell.is_some()
+    }
+
+    /// 返回字符缓冲区第一个单元格的裸指针，供需要自行批量绘制的代码使用
+    ///
+    /// 缓冲区按行优先排列 BUFFER_HEIGHT * BUFFER_WIDTH 个 ScreenChar
+    ///
+    /// # Safety
+    /// - 只能在这个范围内读写，越界是未定义行为
+    /// - 对真实显存必须使用 read_volatile / write_volatile，否则编译器可能合并或删除写入
+    /// - 指针只在持有 &mut Writer 期间有效，不能保存下来在之后使用，
+    ///   也不能在使用指针期间再通过 Writer 的其他方法写屏幕
+    /// - 光标位置不会随之改变，Writer 之后的输出仍从原来的列继续
+    pub unsafe fn buffer_ptr(&mut self) -> *mut ScreenChar {
+        // Volatile<T> 与 T 的内存布局相同
+        self.buffer.chars.as_mut_ptr() as *mut ScreenChar
+    }
+
+    /// 之后写入的字符使用新的颜色，已经在屏幕上的字符不变
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background);
+    }
+
+    pub fn color_code(&self) -> ColorCode {
+        self.color_code
+    }
+
+    /// 之后写入的字符使用新的背景色，前景色不变
+    pub fn set_background(&mut self, background: Color) {
+        self.color_code = self.color_code.with_background(background);
+    }
+
+    /// 用 background 作为背景色写入 s，前景色不变，写完后恢复原来的颜色。
+    /// 与 set_color 后再写入不同，中途折行或换行时新出现的行仍用原来的颜色填充，
+    /// 高亮只落在 s 的字符上
+    pub fn write_highlighted(&mut self, s: &str, background: Color) {
+        let saved = self.color_code;
+        let saved_fill = self.fill_override.replace(saved);
+        self.set_background(background);
+        self.write_string(s);
+        self.color_code = saved;
+        self.fill_override = saved_fill;
+    }
+
+    /// 与 set_color 相同，用于恢复之前通过 color_code 保存的颜色
+    pub fn set_color_code(&mut self, color_code: ColorCode) {
+        self.color_code = color_code;
+    }
+
+    /// 用当前颜色清空整个屏幕，光标回到最后一行行首
+    pub fn clear_screen(&mut self) {
+        self.before_output();
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+        self.column_position = 0;
+    }
+
+    /// 回到已知的初始状态，相当于终端的 reset：默认颜色、覆盖模式、解释控制字符、
+    /// 默认的换行填充和替代字节、关闭折行标记，清除高亮、回到回滚的底部，然后清屏，光标回到行首。
+    /// BEL 的处理函数、回滚缓冲区等由调用者安装的东西保持不变
+    pub fn reset(&mut self) {
+        self.clear_highlight();
+        self.snap_to_bottom();
+        self.color_code = DEFAULT_COLOR;
+        self.insert_mode = false;
+        self.control_chars = true;
+        self.newline_fill = NewlineFill::default();
+        self.replacement = DEFAULT_REPLACEMENT;
+        self.wrap_indicator = None;
+        self.clear_screen();
+    }
+
+    /// 在光标处插入一个字符：本行光标及之后的字符右移一格，移出行尾的字符被丢弃
+    pub fn insert_char(&mut self, byte: u8) {
+        self.before_output();
+        self.wrap_if_full();
+
+        let (row, cursor) = cursor_cell(BUFFER_HEIGHT - 1, self.column_position);
+        for col in (cursor + 1..self.line_end()).rev() {
+            let character = self.buffer.chars[row][col - 1].read();
+            self.buffer.chars[row][col].write(character);
+        }
+        self.buffer.chars[row][cursor].write(ScreenChar {
+            ascii_character: byte,
+            color_code: self.color_code,
+        });
+        self.column_position += 1;
+    }
+
+    /// 切换插入/覆盖模式，对应终端上的 Insert 键，默认为覆盖模式
+    pub fn set_insert_mode(&mut self, on: bool) {
+        self.insert_mode = on;
+    }
+
+    pub fn insert_mode(&self) -> bool {
+        self.insert_mode
+    }
+
+    /// 光标所在的列，等于 BUFFER_WIDTH 时表示本行已写满
+    pub fn column(&self) -> usize {
+        self.column_position
+    }
+
+    /// 把光标移动到最后一行的指定列，超出行宽时停在行尾之后
+    pub fn set_column(&mut self, col: usize) {
+        self.column_position = col.min(BUFFER_WIDTH);
+    }
+
+    /// 把可能只更新了一半的状态拉回合法范围，由 panic 处理在强制释放 WRITER 的锁之后调用，
+    /// 避免报告 panic 的第一次输出因为下标越界再次出错。
+    /// 光标总在最后一行，没有行号需要检查；越界的高亮区间直接丢弃，不再恢复颜色
+    pub fn sanitize(&mut self) {
+        self.column_position = self.column_position.min(BUFFER_WIDTH);
+        if self
+            .highlight
+            .is_some_and(|(first, last)| first > last || last >= BUFFER_HEIGHT * BUFFER_WIDTH)
+        {
+            self.highlight = None;
+        }
+    }
+
+    /// 从缓冲区中已有的内容之后接着写，例如 BIOS 或引导程序已经在屏幕上打印过文字时。
+    /// 光标总在最后一行，所以最后一个非空行及以上的内容整体下移到屏幕底部，
+    /// 光标放在这一行最后一个非空字符之后。空格和 NUL 都算空，屏幕全空时不做任何事
+    pub fn sync_from_buffer(&mut self) {
+        let blank = |character: ScreenChar| matches!(character.ascii_character, b' ' | 0);
+        let Some(last) = (0..BUFFER_HEIGHT)
+            .rev()
+            .find(|&row| (0..BUFFER_WIDTH).any(|col| !blank(self.buffer.chars[row][col].read())))
+        else {
+            return;
+        };
+        let shift = BUFFER_HEIGHT - 1 - last;
+        if shift > 0 {
+            for row in (shift..BUFFER_HEIGHT).rev() {
+                for col in 0..BUFFER_WIDTH {
+                    let character = self.buffer.chars[row - shift][col].read();
+                    self.buffer.chars[row][col].write(character);
+                }
+            }
+            for row in 0..shift {
+                self.fill_row(row, self.color_code);
+            }
+        }
+        let row = &self.buffer.chars[BUFFER_HEIGHT - 1];
+        self.column_position = (0..BUFFER_WIDTH)
+            .rev()
+            .find(|&col| !blank(row[col].read()))
+            .map_or(0, |col| col + 1);
+    }
+
+    /// 保存光标所在的列和当前颜色，运行 f 后恢复，供后台任务绘制 HUD 而不打乱前台的输出
+    /// f 可以随意移动光标、改变颜色和绘制；光标总在最后一行，f 中的换行造成的滚动不会被撤销
+    pub fn with_saved_cursor<F: FnOnce(&mut Writer)>(&mut self, f: F) {
+        let column_position = self.column_position;
+        let color_code = self.color_code;
+        f(self);
+        self.column_position = column_position;
+        self.color_code = color_code;
+    }
+
+    /// 删除光标前的一个字符，供行编辑使用
+    /// 光标在第 0 列时认为上一行是自动折行产生的，把屏幕整体下移一行，回到上一行的行尾，
+    /// 启用了回滚缓冲区时最上面一行从历史中恢复
+    pub fn backspace(&mut self) {
+        self.before_output();
+        if self.column_position == 0 {
+            let top = self.scroll_top;
+            for row in (top + 1..BUFFER_HEIGHT).rev() {
+                for col in 0..BUFFER_WIDTH {
+                    let character = self.buffer.chars[row - 1][col].read();
+                    self.buffer.chars[row][col].write(character);
+                }
+            }
+            if !self.restore_top_row() {
+                self.clear_row(top);
+            }
+            self.column_position = BUFFER_WIDTH;
+        }
+        self.column_position -= 1;
+        self.buffer.chars[BUFFER_HEIGHT - 1][self.column_position].write(ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        });
+    }
+
+    /// ANSI ESC[J：用当前颜色清空从光标到屏幕末尾的单元格，光标不动。
+    /// 光标总在最后一行，实际清掉的是这一行光标之后的部分
+    pub fn clear_to_end_of_screen(&mut self) {
+        self.before_output();
+        let blank = ScreenChar::new(b' ', self.color_code);
+        let cursor = Cell {
+            row: BUFFER_HEIGHT - 1,
+            col: self.column_position,
+        };
+        // 本行写满时光标在行尾之后，没有要清的单元格
+        for index in cursor.index()..BUFFER_HEIGHT * BUFFER_WIDTH {
+            let Cell { row, col } = Cell::from_index(index);
+            self.buffer.chars[row][col].write(blank);
+        }
+    }
+
+    fn clear_row(&mut self, row: usize) {
+        self.fill_row(row, self.color_code);
+    }
+
+    fn fill_row(&mut self, row: usize, color_code: ColorCode) {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code,
+        };
+        for col in 0..BUFFER_WIDTH {
+            self.buffer.chars[row][col].write(blank);
+        }
+    }
+
+    /// 读取某个单元格中的字符和颜色，坐标越界时 panic；不想 panic 时使用 try_read
+    pub fn read_char(&self, row: usize, col: usize) -> (u8, ColorCode) {
+        let screen_char = self
+            .try_read(Cell { row, col })
+            .expect("cell out of bounds");
+        (screen_char.ascii_character, screen_char.color_code)
+    }
+
+    /// 直接写入某个单元格，不移动光标，坐标越界时 panic；不想 panic 时使用 try_put
+    pub fn put_char(&mut self, row: usize, col: usize, byte: u8, color_code: ColorCode) {
+        self.try_put(Cell { row, col }, ScreenChar::new(byte, color_code))
+            .expect("cell out of bounds");
+    }
+
+    /// 坐标越界时返回 None 的单元格访问，由调用者决定是跳过还是 panic
+    fn cell_mut(&mut self, row: usize, col: usize) -> Option<&mut Volatile<ScreenChar>> {
+        let cell = Cell::new(row, col).ok()?;
+        self.before_cell_write(cell);
+        Some(&mut self.buffer.chars[cell.row][cell.col])
+    }
+
+    /// 直接写入单元格（状态栏、鼠标指针等）之前：先把正在分段提交的重绘提交完，
+    /// 否则写进后备缓冲区中已经提交过的行，换回显存时就丢失了；再清除覆盖这个单元格的高亮
+    fn before_cell_write(&mut self, cell: Cell) {
+        self.finish_redraw();
+        self.unhighlight(cell);
+    }
+
+    /// 要直接写入高亮区间内的单元格时，先清除高亮，写入的颜色不会在之后被反色
+    fn unhighlight(&mut self, cell: Cell) {
+        if self
+            .highlight
+            .is_some_and(|(first, last)| (first..=last).contains(&cell.index()))
+        {
+            self.clear_highlight();
+        }
+    }
+
+    /// 反色显示从 start 到 end（都包含）的单元格，坐标是 (行, 列)，跨行时按行优先连续选取；
+    /// start 在 end 之后时两者互换。之前的高亮先被清除，坐标越界时 panic；不想 panic 时使用 try_highlight。
+    /// 高亮只保持到下一次改变这些单元格的输出：写入文字、滚屏或者直接写入区间内的单元格之前，
+    /// 先恢复原来的颜色，所以不会把之后写入的内容反色
+    pub fn highlight(&mut self, start: (usize, usize), end: (usize, usize)) {
+        let cell = |(row, col)| Cell { row, col };
+        self.try_highlight(cell(start), cell(end))
+            .expect("cell out of bounds");
+    }
+
+    /// 恢复高亮前的颜色；反色是对合运算，再反色一次就是原来的颜色
+    pub fn clear_highlight(&mut self) {
+        if let Some((first, last)) = self.highlight.take() {
+            self.invert_cells(first, last);
+        }
+    }
+
+    fn invert_cells(&mut self, first: usize, last: usize) {
+        for index in first..=last {
+            let Cell { row, col } = Cell::from_index(index);
+            let cell = &mut self.buffer.chars[row][col];
+            let mut screen_char = cell.read();
+            screen_char.color_code = screen_char.color_code.inverted();
+            cell.write(screen_char);
+        }
+    }
+
+    /// print_at! 的实现：从 (row, col) 开始写入，不移动光标，与 write_string 一样按 char_display_width 占用单元格，
+    /// 控制字符（包括换行符）显示为替代字节，超出行尾或不在屏幕内的部分被丢弃；
+    /// 越界时需要知道的话使用 try_write_fmt_at
+    pub fn write_fmt_at(&mut self, row: usize, col: usize, args: fmt::Arguments) {
+        let color_code = self.color_code;
+        let _ = fmt::Write::write_fmt(&mut At::new(self, row, col, color_code), args);
+    }
+
+    /// 与 write_fmt_at 相同，但使用指定的颜色，返回最后一个字符之后的位置 (行, 列)，
+    /// 可以接着在那里写下一段，例如同一行的标签和值。超出行尾时返回的列是 BUFFER_WIDTH
+    pub fn write_str_at(
+        &mut self,
+        row: usize,
+        col: usize,
+        s: &str,
+        foreground: Color,
+        background: Color,
+    ) -> (usize, usize) {
+        let mut at = At::new(self, row, col, ColorCode::new(foreground, background));
+        let _ = fmt::Write::write_str(&mut at, s);
+        (row, at.col.min(BUFFER_WIDTH))
+    }
+
+    /// 以 hexdump -C 的格式输出 bytes，base 是第一个字节显示的地址，格式见 hexdump 模块
+    pub fn hexdump(&mut self, base: u64, bytes: &[u8]) {
+        crate::hexdump::for_each_line(base, bytes, |line| {
+            self.write_string(line);
+            self.new_line();
+        });
+    }
+
+    /// 逐个字符写入：ASCII 字符与 write_byte 相同，其他字符通过 CP437 映射或替换，
+    /// 占用的单元格数见 write_char
+    pub fn write_string(&mut self, s: &str) {
+        // 没有换行符的长字符串可能把整个屏幕滚动很多遍，直接渲染最终可见的部分
+        // 快速路径按覆盖模式计算最终画面，插入模式下不能使用；
+        // 它跳过了被滚出屏幕的行，启用回滚缓冲区时也不能使用；
+        // 新行的颜色取决于上一行、或者要画折行标记时也不能使用；
+        // 它假设每个字节占一个单元格，所以只用于纯 ASCII 的字符串；它写满整个屏幕，有保留行时也不能使用
+        if s.len() > BUFFER_WIDTH
+            && s.is_ascii()
+            && !self.insert_mode
+            && self.newline_fill == NewlineFill::CurrentColor
+            && self.scrollback.is_none()
+            && self.wrap_indicator.is_none()
+            && self.scroll_top == 0
+            && !s.contains(['\n', VERTICAL_TAB as char, BELL as char, DELETE as char])
+            && self.write_screenful(s.as_bytes())
+        {
+            return;
+        }
+        // 连续的换行符合并为一次 scroll_up，每个单元格只复制一次
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\n' {
+                self.write_char(c);
+                continue;
+            }
+            let mut lines = 1;
+            while chars.next_if_eq(&'\n').is_some() {
+                lines += 1;
+            }
+            self.new_lines(lines);
+        }
+    }
+
+    /// 与调用 count 次 new_line 相同。scroll_up 一次最多滚动整个区域，
+    /// 超过时分几次滚动，启用回滚缓冲区时历史中的空行数也相同
+    fn new_lines(&mut self, count: usize) {
+        let mut remaining = count;
+        while remaining > 0 {
+            let lines = remaining.min(BUFFER_HEIGHT - self.scroll_top);
+            self.scroll_up(lines);
+            remaining -= lines;
+        }
+    }
+
+    /// 依次写出每一行并换行，用于一次输出固定的菜单或帮助文字；颜色、折行等与 write_string 相同
+    pub fn write_lines(&mut self, lines: &[&str]) {
+        for line in lines {
+            self.write_string(line);
+            self.new_line();
+        }
+    }
+
+    /// 以前的 write_string：逐字节写入，多字节的 UTF-8 字符的每个字节各占一个单元格，显示为替代字节
+    pub fn write_string_raw(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.write_byte(self.printable(byte));
+        }
+    }
+
+    /// 与 write_string 相同，返回这段文字占用的行数：1 加上自动折行和换行符的次数，
+    /// 调用者可以据此预留空间或推算光标所在的行
+    pub fn write_wrapped(&mut self, s: &str) -> usize {
+        let mut lines = 1;
+        for c in s.chars() {
+            let byte = self.cell_byte(c);
+            for _ in 0..char_display_width(c) {
+                // write_byte 在行已写满时先换行再写入
+                let vertical_tab = byte == VERTICAL_TAB && self.control_chars;
+                if byte == b'\n' || vertical_tab || self.column_position >= self.line_end() {
+                    lines += 1;
+                }
+                self.write_byte(byte);
+            }
+        }
+        lines
+    }
+
+    /// 与 write_fmt 相同，返回实际占用的单元格数：替换后的不可打印字节计入，宽字符计为 2，
+    /// 换行符、被解释的控制字符和组合字符不计入。不分配内存，可以用来在变长的输出之后补齐到固定宽度
+    pub fn write_counted(&mut self, args: fmt::Arguments) -> usize {
+        struct Counter<'a> {
+            writer: &'a mut Writer,
+            count: usize,
+        }
+
+        impl fmt::Write for Counter<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let writer = &*self.writer;
+                self.count += s.chars().map(|c| writer.cells(c)).sum::<usize>();
+                self.writer.write_string(s);
+                Ok(())
+            }
+
+            fn write_char(&mut self, c: char) -> fmt::Result {
+                self.count += self.writer.cells(c);
+                self.writer.write_char(c);
+                Ok(())
+            }
+        }
+
+        let mut counter = Counter {
+            writer: self,
+            count: 0,
+        };
+        let _ = fmt::write(&mut counter, args);
+        counter.count
+    }
+
+    /// 写入 c 时占用的单元格数，非 ASCII 字符见 char_display_width
+    fn cells(&self, c: char) -> usize {
+        if c.is_ascii() {
+            self.draws(c as u8) as usize
+        } else {
+            char_display_width(c)
+        }
+    }
+
+    /// 写入 byte 时是否会占用一个单元格
+    fn draws(&self, byte: u8) -> bool {
+        let printable = self.printable(byte);
+        !(printable == b'\n'
+            || (printable == VERTICAL_TAB || printable == DELETE) && self.control_chars
+            || printable == BELL && self.rings_bell())
+    }
+
+    /// 把 byte 写 count 次，与逐个调用 write_byte 相同，照常折行和滚动
+    pub fn write_repeated(&mut self, byte: u8, count: usize) {
+        for _ in 0..count {
+            self.write_byte(byte);
+        }
+    }
+
+    /// 把 value 的十进制表示右对齐写入宽 width 的字段，左侧用 pad 填充，
+    /// 用于状态栏中需要固定列宽的计数器。数字比字段长时完整写出，不做截断
+    pub fn write_u64_padded(&mut self, value: u64, width: usize, pad: u8) {
+        self.write_u64_radix(value, 10, width, pad);
+    }
+
+    /// 与 write_u64_padded 相同，但使用 radix 进制（2、8、10 或 16），十六进制的字母大写。
+    /// 不经过 core::fmt，适合在异常处理函数中输出寄存器，例如 "0x" 之后写 (value, 16, 16, b'0')。
+    /// 不支持的进制会 panic
+    pub fn write_u64_radix(&mut self, value: u64, radix: u32, width: usize, pad: u8) {
+        assert!(
+            matches!(radix, 2 | 8 | 10 | 16),
+            "unsupported radix {}",
+            radix
+        );
+        let mut digits = [0u8; MAX_RADIX_DIGITS];
+        let digits = radix_digits(value, radix, &mut digits);
+        self.write_repeated(self.printable(pad), width.saturating_sub(digits.len()));
+        for &digit in digits {
+            self.write_byte(digit);
+        }
+    }
+
+    /// VGA 字符缓冲区只支持 ASCII 码字节和代码页 437 定义的字节
+    fn printable(&self, byte: u8) -> u8 {
+        match byte {
+            // 可以是能打印的 ASCII 码字节，也可以是换行符
+            0x20..=0x7e | b'\n' => byte,
+            // 需要解释的控制字符原样交给 write_byte
+            VERTICAL_TAB if self.control_chars => byte,
+            // 解释时交给 write_byte 退格，否则画出 0x7f 的字形
+            DELETE => byte,
+            BELL if self.rings_bell() => byte,
+            // 不包含在上述范围之内的字节
+            _ => self.replacement,
+        }
+    }
+
+    /// 不含换行符的字节串的快速路径
+    ///
+    /// 把字节按写入顺序编号为 p = column_position + i，逐字节写入时第 p 个字节会落在第 p / W 行（相对起始行）。
+    /// 如果最后一行的行号 last >= H，原有内容全部被滚出屏幕，屏幕上只剩 last - H + 1 ..= last 这 H 行，
+    /// 可以跳过之前所有的字节，直接把这部分写到对应位置，省去成千上万次 new_line。
+    /// 滚动不到一整屏时返回 false，由调用者走逐字节写入的路径
+    fn write_screenful(&mut self, bytes: &[u8]) -> bool {
+        self.before_output();
+        let start = self.column_position;
+        let end = start + bytes.len();
+        let last_line = (end - 1) / BUFFER_WIDTH;
+        if last_line < BUFFER_HEIGHT {
+            return false;
+        }
+
+        let first_visible = (last_line + 1 - BUFFER_HEIGHT) * BUFFER_WIDTH;
+        let color_code = self.color_code;
+        for p in first_visible..end {
+            let row = BUFFER_HEIGHT - 1 - (last_line - p / BUFFER_WIDTH);
+            let ascii_character = self.printable(bytes[p - start]);
+            self.buffer.chars[row][p % BUFFER_WIDTH].write(ScreenChar {
+                ascii_character,
+                color_code,
+            });
+        }
+        // 最后一行剩余的部分在逐字节写入时由 new_line 清空
+        let last_col = (end - 1) % BUFFER_WIDTH + 1;
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.newline_fill_color(),
+        };
+        for col in last_col..BUFFER_WIDTH {
+            self.buffer.chars[BUFFER_HEIGHT - 1][col].write(blank);
+        }
+        self.column_position = last_col;
+        // 原有内容全部滚出了屏幕
+        self.scrolled = true;
+        true
+    }
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+
+    /// 不覆盖时 core::fmt 会把 char 编码成 UTF-8 交给 write_str，再解码出来
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        Writer::write_char(self, c);
+        Ok(())
+    }
+}
+
+/// 用每种颜色作为前景色写出它的名称，背景色不变，用来检查调色板
+pub fn color_test(writer: &mut Writer) {
+    let saved = writer.color_code();
+    let (_, background, _) = saved.decode();
+    for (index, color) in ALL_COLORS.into_iter().enumerate() {
+        if index != 0 {
+            writer.write_byte(b' ');
+        }
+        writer.set_color(color, background);
+        writer.write_string(color.name());
+        writer.set_color_code(saved);
+    }
+    writer.new_line();
+}
+
+// 问题 1
+// 一般的变量在运行时初始化，而静态变量在编译时初始化
+// Rust 编译器规定了一个称为常量求值器（const evaluator）的组件，它应该在编译时处理这样的初始化工作
+// lazy_static 宏可以定义一个延迟初始化（lazily initialized）的静态变量
+// 这个变量的值将在第一次使用时计算，而非在编译时计算
+//
+// 问题 2
+// 所有与写入数据相关的方法都需要实例的可变引用 "&mut self"，但 WRITER 是 不可变变量
+// 使用自旋锁，提供内部可变性
+lazy_static! {
+    pub static ref WRITER: Mutex<Writer> = {
+        let mut writer = Writer::new(screen_buffer());
+        // 离屏缓冲区是空白的，不用接管
+        if VGA_PRESENT.load(Ordering::Relaxed) {
+            sanitize::adopt_inherited_screen(&mut writer);
+        }
+        Mutex::new(writer)
+    };
+}
+
+/// VGA 文本缓冲区的物理地址，bootloader 把它恒等映射到了同一个虚拟地址
+const VGA_ADDRESS: usize = 0xb8000;
+
+/// 探测用的两个互补的图案，任何一位卡在 0 或 1 都会被发现
+const PROBE_PATTERNS: [ScreenChar; 2] = [
+    ScreenChar::new(0x55, ColorCode(0xaa)),
+    ScreenChar::new(0xaa, ColorCode(0x55)),
+];
+
+/// WRITER 初始化时的探测结果
+static VGA_PRESENT: AtomicBool = AtomicBool::new(true);
+
+/// 没有 VGA 时 WRITER 写入的离屏缓冲区，只在 WRITER 初始化时写入一次
+static mut OFFSCREEN: MaybeUninit<Buffer> = MaybeUninit::uninit();
+
+/// 依次写入探测图案并经由 Volatile 读回，最后恢复原来的内容
+fn probe_cell(cell: &mut Volatile<ScreenChar>) -> bool {
+    let original = cell.read();
+    let stuck = PROBE_PATTERNS.iter().all(|&pattern| {
+        cell.write(pattern);
+        cell.read() == pattern
+    });
+    cell.write(original);
+    stuck
+}
+
+/// 探测 0xb8000 处是否有 VGA 文本缓冲区：在右下角的单元格写入已知图案再读回，
+/// 没有设备时读到的通常是全 1 或者写入前的值
+///
+/// 只是尽力而为：探测本身仍会写一次这个地址（随后恢复），而且与显卡的刷新之间没有同步，
+/// 真实的显示内容在探测期间可能闪一下，极少数情况下也可能误判。
+/// 只由 screen_buffer 在创建 WRITER 时调用，此时还没有其他引用指向 0xb8000
+fn vga_present() -> bool {
+    let buffer = unsafe { &mut *(VGA_ADDRESS as *mut Buffer) };
+    probe_cell(&mut buffer.chars[BUFFER_HEIGHT - 1][BUFFER_WIDTH - 1])
+}
+
+/// WRITER 是否在写真正的屏幕；为 false 时 print! 等宏只输出到串口
+pub fn vga_available() -> bool {
+    lazy_static::initialize(&WRITER);
+    VGA_PRESENT.load(Ordering::Relaxed)
+}
+
+/// 探测到 VGA 时返回 0xb8000 处的缓冲区，否则返回离屏缓冲区，
+/// 直接使用 WRITER 的代码（例如 shell）照常工作，只是内容不会显示出来
+fn screen_buffer() -> &'static mut Buffer {
+    if vga_present() {
+        return unsafe { &mut *(VGA_ADDRESS as *mut Buffer) };
+    }
+    VGA_PRESENT.store(false, Ordering::Relaxed);
+    // lazy_static 保证只会执行一次，不会出现两个可变引用
+    unsafe { (*core::ptr::addr_of_mut!(OFFSCREEN)).write(Buffer::new()) }
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::vga_buffer::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// 与 print! 相同，但使用醒目的错误颜色输出，输出后恢复原来的颜色
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::vga_buffer::_eprint(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
+
+/// 用指定的前景色和背景色输出一行格式化的文字，例如
+/// writeln_colored!(Color::Green, Color::Black, "done in {} ticks", n)。
+/// 文字和换行在同一次输出中写出，期间一直持有锁，其他输出（包括中断处理函数中的）
+/// 不会插在中间，也不会被染上这个颜色；写完后恢复原来的颜色
+#[macro_export]
+macro_rules! writeln_colored {
+    ($foreground:expr, $background:expr, $($arg:tt)*) => (
+        $crate::vga_buffer::_print_colored(
+            $crate::vga_buffer::ColorCode::new($foreground, $background),
+            format_args!("{}\n", format_args!($($arg)*)),
+        )
+    );
+}
+
+/// 把屏幕恢复到初始状态，见 Writer::reset
+#[macro_export]
+macro_rules! reset {
+    () => {
+        $crate::vga_buffer::_reset()
+    };
+}
+
+/// 从 (行, 列) 开始写入格式化的文字，不移动光标、不换行，超出行尾的部分被丢弃
+#[macro_export]
+macro_rules! print_at {
+    ($row:expr, $col:expr, $($arg:tt)*) => (
+        $crate::vga_buffer::_print_at($row, $col, format_args!($($arg)*))
+    );
+}
+
+/// 输出一条横跨整行的分隔线，默认使用 RULE_CHAR，也可以指定字符，例如 hr!(b'=')
+#[macro_export]
+macro_rules! hr {
+    () => {
+        $crate::vga_buffer::_hr($crate::vga_buffer::RULE_CHAR)
+    };
+    ($byte:expr) => {
+        $crate::vga_buffer::_hr($byte)
+    };
+}
+
+/// print_in_state 持有控制台的锁（WRITER 或输出目标表）格式化输出期间为 true。
+/// 格式化会调用任意的 Display 实现，其中发生 panic 时这些锁不会再被释放，panic 处理据此强制释放
+static FORMATTING: AtomicBool = AtomicBool::new(false);
+
+/// 按控制台的初始化阶段选择输出方式，完全初始化后交给 console::sink 分发到登记的所有输出目标
+/// level 是分级日志的级别，只在分发时使用
+pub(crate) fn print_in_state(args: fmt::Arguments, level: Option<Level>, style: Style) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    match console::state() {
+        ConsoleState::Uninit => {
+            // 控制台初始化之前只有启动早期的代码在运行，WRITER 还不会写屏幕
+            let _ = unsafe { early::screen() }.write_fmt(args);
+        }
+        ConsoleState::VgaOnly => interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            FORMATTING.store(true, Ordering::SeqCst);
+            if !pause::hold_fmt(args) {
+                let _ = writer.write_styled_fmt(args, style);
+            }
+            FORMATTING.store(false, Ordering::SeqCst);
+        }),
+        ConsoleState::Full => {
+            FORMATTING.store(true, Ordering::SeqCst);
+            console::sink::dispatch(args, level, style);
+            FORMATTING.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// 由 panic 处理调用：panic 发生在 print! 等宏格式化输出的途中时，强制释放控制台的锁并返回 true。
+/// 已经写出的半行留在屏幕上
+///
+/// # Safety
+/// 只能在 panic 处理中调用。与 panic 模块一样只考虑单核：持有这些锁的就是正在 panic 的执行流，
+/// 它不会再回来使用或释放它们
+pub unsafe fn recover_from_formatting_panic() -> bool {
+    if !FORMATTING.swap(false, Ordering::SeqCst) {
+        return false;
+    }
+    unsafe {
+        WRITER.force_unlock();
+        console::sink::force_unlock();
+        pause::force_unlock();
+    }
+    WRITER.lock().sanitize();
+    true
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    print_in_state(args, None, Style::Normal);
+}
+
+/// 把格式化的输出逐段交给 print!，用于只接受 fmt::Write 的函数，不需要先格式化到 String
+pub struct Stdout;
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        _print(format_args!("{}", s));
+        Ok(())
+    }
+}
+
+impl OutputSink for Writer {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        // print! 交来的总是完整的 UTF-8 片段，可以使用 write_string 的快速路径
+        match core::str::from_utf8(bytes) {
+            Ok(s) => self.write_string(s),
+            Err(_) => {
+                for &byte in bytes {
+                    self.write_byte(self.printable(byte));
+                }
+            }
+        }
+    }
+
+    /// 使用醒目的错误颜色，写完后恢复原来的颜色
+    fn write_error_bytes(&mut self, bytes: &[u8]) {
+        self.write_colored_bytes(bytes, ERROR_COLOR);
+    }
+
+    fn write_colored_bytes(&mut self, bytes: &[u8], color_code: ColorCode) {
+        let saved = self.color_code;
+        self.color_code = color_code;
+        self.write_bytes(bytes);
+        self.color_code = saved;
+    }
+}
+
+/// write_fmt_at 和 write_str_at 的实现，从 (row, col) 开始逐个单元格写入
+struct At<'a> {
+    writer: &'a mut Writer,
+    row: usize,
+    col: usize,
+    color_code: ColorCode,
+}
+
+impl<'a> At<'a> {
+    fn new(writer: &'a mut Writer, row: usize, col: usize, color_code: ColorCode) -> Self {
+        At {
+            writer,
+            row,
+            col,
+            color_code,
+        }
+    }
+}
+
+impl fmt::Write for At<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let replacement = self.writer.replacement;
+        for c in s.chars() {
+            let ascii_character = match c {
+                ' '..='~' => c as u8,
+                _ => cp437::from_char(c).unwrap_or(replacement),
+            };
+            for _ in 0..char_display_width(c) {
+                if let Some(cell) = self.writer.cell_mut(self.row, self.col) {
+                    cell.write(ScreenChar {
+                        ascii_character,
+                        color_code: self.color_code,
+                    });
+                }
+                self.col = self.col.saturating_add(1);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Writer {
+    /// 与 write_fmt 相同，但使用 style 的颜色，写完后恢复原来的颜色
+    fn write_styled_fmt(&mut self, args: fmt::Arguments, style: Style) -> fmt::Result {
+        use core::fmt::Write;
+
+        let saved = self.color_code;
+        match style {
+            Style::Normal => {}
+            Style::Error => self.color_code = ERROR_COLOR,
+            Style::Colored(color_code) => self.color_code = color_code,
+        }
+        let result = self.write_fmt(args);
+        self.color_code = saved;
+        result
+    }
+
+    fn write_styled_bytes(&mut self, bytes: &[u8], style: Style) {
+        match style {
+            Style::Normal => self.write_bytes(bytes),
+            Style::Error => self.write_error_bytes(bytes),
+            Style::Colored(color_code) => self.write_colored_bytes(bytes, color_code),
+        }
+    }
+}
+
+/// 在每一行的开头加上 [cpuN] 再写入
+fn write_with_cpu_prefix(writer: &mut Writer, cpu: usize, bytes: &[u8], style: Style) {
+    use core::fmt::Write;
+
+    for line in bytes.split_inclusive(|&byte| byte == b'\n') {
+        if writer.column_position == 0 {
+            let _ = write!(writer, "[cpu{}] ", cpu);
+        }
+        writer.write_styled_bytes(line, style);
+    }
+}
+
+/// 按启动配置写到 WRITER，console::sink 中内置的 VGA 输出目标使用它
+/// 多个 CPU 在线时在每一行的开头加上 [cpuN]，区分交错的输出
+pub(crate) fn write_screen(bytes: &[u8], style: Style) {
+    if !routes().0 {
+        return;
+    }
+    let mut writer = WRITER.lock();
+    if pause::hold_bytes(bytes) {
+        return;
+    }
+    if crate::cpu::online() > 1 {
+        write_with_cpu_prefix(&mut writer, crate::cpu::id(), bytes, style);
+    } else {
+        writer.write_styled_bytes(bytes, style);
+    }
+}
+
+#[doc(hidden)]
+pub fn _hr(byte: u8) {
+    use x86_64::instructions::interrupts;
+
+    let (vga, serial) = routes();
+    if serial {
+        // 串口终端不一定使用 CP437，其他字符一律换成 '-'
+        let ch = if byte.is_ascii_graphic() {
+            byte as char
+        } else {
+            '-'
+        };
+        for _ in 0..BUFFER_WIDTH {
+            crate::serial::_print(format_args!("{}", ch));
+        }
+        crate::serial::_print(format_args!("\n"));
+    }
+    if vga {
+        interrupts::without_interrupts(|| WRITER.lock().write_rule(byte));
+    }
+}
+
+/// 按输出方式（console::set_output 或者启动配置的 console 选项）决定 (是否写屏幕, 是否写串口)，
+/// 没有 VGA 时只写串口
+pub(crate) fn routes() -> (bool, bool) {
+    let console = console::output();
+    let vga = console.vga() && vga_available();
+    (vga, console.serial() || !vga)
+}
+
+#[doc(hidden)]
+pub fn _reset() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| WRITER.lock().reset());
+}
+
+#[doc(hidden)]
+pub fn _print_at(row: usize, col: usize, args: fmt::Arguments) {
+    use x86_64::instructions::interrupts;
+
+    // 固定位置的输出（例如帧率）在串口上没有意义
+    if !routes().0 {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        WRITER
+            .lock()
+            .with_saved_cursor(|writer| writer.write_fmt_at(row, col, args));
+    });
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    print_in_state(args, None, Style::Error);
+}
+
+#[doc(hidden)]
+pub fn _print_colored(color_code: ColorCode, args: fmt::Arguments) {
+    print_in_state(args, None, Style::Colored(color_code));
+}
+
+/// 测试用的 Writer：缓冲区分配在堆上，drop 时释放，避免每个测试都泄漏 4000 字节
+#[cfg(test)]
+pub(crate) struct TestWriter {
+    writer: Writer,
+    backing: *mut Buffer,
+    /// 回滚缓冲区使用的历史行和备用缓冲区
+    scrollback: Option<(*mut [ScreenRow], *mut Buffer)>,
+    consoles: Option<*mut [VirtualConsole]>,
+    draw_buffer: Option<*mut Buffer>,
+}
+
+#[cfg(test)]
+impl TestWriter {
+    pub(crate) fn new() -> Self {
+        use alloc::boxed::Box;
+
+        let backing = Box::into_raw(Box::new(Buffer::new()));
+        TestWriter {
+            writer: Writer::new(unsafe { &mut *backing }),
+            backing,
+            scrollback: None,
+            consoles: None,
+            draw_buffer: None,
+        }
+    }
+
+    /// 启用能保存 lines 行历史的回滚缓冲区
+    pub(crate) fn with_scrollback(lines: usize) -> Self {
+        use alloc::boxed::Box;
+        use alloc::vec;
+
+        let mut test_writer = Self::new();
+        let blank = ScreenChar::new(b' ', ColorCode::new(Color::Yellow, Color::Black));
+        let history = Box::into_raw(vec![[blank; BUFFER_WIDTH]; lines].into_boxed_slice());
+        let spare = Box::into_raw(Box::new(Buffer::new()));
+        unsafe {
+            test_writer
+                .writer
+                .enable_scrollback(&mut *history, &mut *spare)
+        };
+        test_writer.scrollback = Some((history, spare));
+        test_writer
+    }
+
+    /// 启用 count 个虚拟控制台
+    pub(crate) fn with_virtual_consoles(count: usize) -> Self {
+        use alloc::boxed::Box;
+        use alloc::vec;
+
+        let mut test_writer = Self::new();
+        let consoles = Box::into_raw(vec![VirtualConsole::new(); count].into_boxed_slice());
+        unsafe { test_writer.writer.enable_virtual_consoles(&mut *consoles) };
+        test_writer.consoles = Some(consoles);
+        test_writer
+    }
+
+    /// 启用批量绘制
+    pub(crate) fn with_draw_buffer() -> Self {
+        use alloc::boxed::Box;
+
+        let mut test_writer = Self::new();
+        let spare = Box::into_raw(Box::new(Buffer::new()));
+        unsafe { test_writer.writer.enable_draw_buffer(&mut *spare) };
+        test_writer.draw_buffer = Some(spare);
+        test_writer
+    }
+}
+
+#[cfg(test)]
+impl core::ops::Deref for TestWriter {
+    type Target = Writer;
+
+    fn deref(&self) -> &Writer {
+        &self.writer
+    }
+}
+
+#[cfg(test)]
+impl core::ops::DerefMut for TestWriter {
+    fn deref_mut(&mut self) -> &mut Writer {
+        &mut self.writer
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestWriter {
+    fn drop(&mut self) {
+        // writer 中的引用在这之后不会再被使用；回滚期间两个缓冲区会互换，但都由这里释放
+        unsafe {
+            drop(alloc::boxed::Box::from_raw(self.backing));
+            if let Some((history, spare)) = self.scrollback {
+                drop(alloc::boxed::Box::from_raw(history));
+                drop(alloc::boxed::Box::from_raw(spare));
+            }
+            if let Some(consoles) = self.consoles {
+                drop(alloc::boxed::Box::from_raw(consoles));
+            }
+            if let Some(spare) = self.draw_buffer {
+                drop(alloc::boxed::Box::from_raw(spare));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn assert_same_screen(a: &TestWriter, b: &TestWriter) {
+    for row in 0..BUFFER_HEIGHT {
+        for col in 0..BUFFER_WIDTH {
+            assert_eq!(
+                a.buffer.chars[row][col].read(),
+                b.buffer.chars[row][col].read()
+            );
+        }
+    }
+    assert_eq!(a.column_position, b.column_position);
+}
+
+#[test_case]
+fn test_long_string_fast_path_matches_naive() {
+    use alloc::string::String;
+
+    // 3000 个字符，其中夹杂不可打印字节，起始列不为 0
+    let mut text = String::new();
+    for i in 0..3000u32 {
+        text.push(match i % 97 {
+            0 => '\x07',
+            n => (b'!' + (n % 90) as u8) as char,
+        });
+    }
+    for len in [
+        3000,
+        BUFFER_WIDTH * BUFFER_HEIGHT,
+        BUFFER_WIDTH * BUFFER_HEIGHT + 1,
+        81,
+    ] {
+        let mut fast = TestWriter::new();
+        let mut naive = TestWriter::new();
+        fast.write_string("prefix");
+        naive.write_string("prefix");
+
+        fast.write_string(&text[..len]);
+        for byte in text[..len].bytes() {
+            let byte = naive.printable(byte);
+            naive.write_byte(byte);
+        }
+        assert_same_screen(&fast, &naive);
+    }
+}
+
+#[test_case]
+fn test_write_fmt_at_keeps_cursor() {
+    let mut writer = TestWriter::new();
+    writer.write_string("ab");
+    writer.write_fmt_at(3, BUFFER_WIDTH - 4, format_args!("{:>3}%!", 42));
+    let text: alloc::vec::Vec<u8> = (BUFFER_WIDTH - 4..BUFFER_WIDTH)
+        .map(|col| writer.read_char(3, col).0)
+        .collect();
+    // 超出行尾的 '!' 被丢弃，不会折到下一行
+    assert_eq!(text, b" 42%");
+    assert_eq!(writer.read_char(4, 0).0, b' ');
+    // 光标仍在原来的位置
+    writer.write_string("c");
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, b'c');
+    // 不在屏幕内的位置什么也不写
+    writer.write_fmt_at(BUFFER_HEIGHT, 0, format_args!("x"));
+}
+
+#[test_case]
+fn test_newline_run_matches_new_line() {
+    let screen = |writer: &Writer| -> alloc::vec::Vec<(u8, ColorCode)> {
+        (0..BUFFER_HEIGHT)
+            .flat_map(|row| (0..BUFFER_WIDTH).map(move |col| (row, col)))
+            .map(|(row, col)| writer.read_char(row, col))
+            .collect()
+    };
+    for count in [5, BUFFER_HEIGHT + 3] {
+        let mut batched = TestWriter::with_scrollback(64);
+        let mut single = TestWriter::with_scrollback(64);
+        for writer in [&mut batched, &mut single] {
+            writer.write_string("first\nsecond\nthird");
+            writer.set_background(Color::Blue);
+        }
+        batched.write_string(&alloc::format!("{}x", "\n".repeat(count)));
+        for _ in 0..count {
+            single.new_line();
+        }
+        single.write_string("x");
+        assert_eq!(screen(&batched), screen(&single), "{} newlines", count);
+        // 回滚历史也相同
+        batched.scroll_view_up(64);
+        single.scroll_view_up(64);
+        assert_eq!(screen(&batched), screen(&single), "{} newlines", count);
+    }
+}
+
+#[test_case]
+fn test_write_str_at_chains() {
+    let mut writer = TestWriter::new();
+    writer.write_string("ab");
+    let end = writer.write_str_at(2, 5, "A", Color::White, Color::Blue);
+    assert_eq!(end, (2, 6));
+    let end = writer.write_str_at(end.0, end.1, "BC", Color::Yellow, Color::Blue);
+    assert_eq!(end, (2, 8));
+    let text: alloc::vec::Vec<u8> = (5..8).map(|col| writer.read_char(2, col).0).collect();
+    assert_eq!(text, b"ABC");
+    assert_eq!(
+        writer.read_char(2, 5).1,
+        ColorCode::new(Color::White, Color::Blue)
+    );
+    // 超出行尾的部分被丢弃，不会折到下一行
+    assert_eq!(
+        writer.write_str_at(2, BUFFER_WIDTH - 1, "xyz", Color::White, Color::Black),
+        (2, BUFFER_WIDTH)
+    );
+    assert_eq!(writer.read_char(3, 0).0, b' ');
+    // 光标仍在原来的位置
+    writer.write_string("c");
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, b'c');
+}
+
+#[test_case]
+fn test_with_saved_cursor_restores_cursor() {
+    let mut writer = TestWriter::new();
+    writer.write_string("ab");
+    let color = writer.color_code();
+    writer.with_saved_cursor(|writer| {
+        writer.set_color(Color::White, Color::Red);
+        writer.write_fmt_at(0, 0, format_args!("hud"));
+        writer.set_column(40);
+        writer.write_string("xy");
+    });
+    assert_eq!(
+        writer.read_char(0, 0),
+        (b'h', ColorCode::new(Color::White, Color::Red))
+    );
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 41).0, b'y');
+    assert_eq!((writer.column(), writer.color_code()), (2, color));
+    writer.write_string("c");
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2), (b'c', color));
+}
+
+#[test_case]
+fn test_probe_cell_restores_original() {
+    let mut writer = TestWriter::new();
+    writer.put_char(3, 4, b'q', ColorCode::new(Color::Green, Color::Blue));
+    assert!(probe_cell(&mut writer.buffer.chars[3][4]));
+    assert_eq!(
+        writer.read_char(3, 4),
+        (b'q', ColorCode::new(Color::Green, Color::Blue))
+    );
+}
+
+#[test_case]
+fn test_cell_bounds() {
+    let mut writer = TestWriter::new();
+    let corners = [(0, 0), (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1)];
+    for (row, col) in corners {
+        assert!(Cell::new(row, col).is_ok());
+        assert!(writer.cell_mut(row, col).is_some());
+    }
+    let outside = [
+        (BUFFER_HEIGHT, 0),
+        (0, BUFFER_WIDTH),
+        (BUFFER_HEIGHT, BUFFER_WIDTH),
+        (usize::MAX, usize::MAX),
+    ];
+    for (row, col) in outside {
+        assert!(Cell::new(row, col).is_err());
+        assert!(writer.cell_mut(row, col).is_none());
+    }
+
+    // 拿到的是对应位置的单元格
+    let color_code = ColorCode::new(Color::White, Color::Red);
+    writer
+        .cell_mut(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1)
+        .unwrap()
+        .write(ScreenChar::new(b'z', color_code));
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1),
+        (b'z', color_code)
+    );
+}
+
+#[test_case]
+fn test_hexdump_lines() {
+    let mut writer = TestWriter::new();
+    writer.hexdump(0x10, b"0123456789abcdef!");
+    let row = |row: usize| -> alloc::string::String {
+        (0..BUFFER_WIDTH)
+            .map(|col| writer.read_char(row, col).0 as char)
+            .collect()
+    };
+    assert!(row(BUFFER_HEIGHT - 3).starts_with("00000010  30 31"));
+    assert!(row(BUFFER_HEIGHT - 3)
+        .trim_end()
+        .ends_with("|0123456789abcdef|"));
+    assert!(row(BUFFER_HEIGHT - 2).trim_end().ends_with("|!|"));
+    assert_eq!(writer.column(), 0);
+}
+
+#[test_case]
+fn test_color_code_inverted() {
+    let color = ColorCode::new(Color::Yellow, Color::Blue);
+    assert_eq!(color.inverted(), ColorCode::new(Color::Blue, Color::Yellow));
+    assert_eq!(color.inverted().inverted(), color);
+}
+
+#[test_case]
+fn test_highlight_across_rows_and_restore() {
+    let mut writer = TestWriter::new();
+    // 每一行用不同的颜色，恢复时可以逐个比较
+    for color in [Color::Red, Color::Green, Color::Blue] {
+        writer.set_color(Color::White, color);
+        writer.write_string("\n0123456789");
+    }
+    let before: alloc::vec::Vec<ColorCode> = (0..BUFFER_HEIGHT * BUFFER_WIDTH)
+        .map(|i| writer.read_char(i / BUFFER_WIDTH, i % BUFFER_WIDTH).1)
+        .collect();
+
+    let (start, end) = ((BUFFER_HEIGHT - 3, 5), (BUFFER_HEIGHT - 1, 2));
+    // 反过来给出的区间也一样
+    writer.highlight(end, start);
+    for (i, &color) in before.iter().enumerate() {
+        let (row, col) = (i / BUFFER_WIDTH, i % BUFFER_WIDTH);
+        let inside = i >= start.0 * BUFFER_WIDTH + start.1 && i <= end.0 * BUFFER_WIDTH + end.1;
+        let expected = if inside { color.inverted() } else { color };
+        assert_eq!(writer.read_char(row, col).1, expected);
+    }
+    // 中间一整行都被选中，字符不变
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 2, BUFFER_WIDTH - 1).1,
+        before[(BUFFER_HEIGHT - 1) * BUFFER_WIDTH - 1].inverted()
+    );
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, b'2');
+
+    writer.clear_highlight();
+    for (i, &color) in before.iter().enumerate() {
+        assert_eq!(
+            writer.read_char(i / BUFFER_WIDTH, i % BUFFER_WIDTH).1,
+            color
+        );
+    }
+    // 没有高亮时清除什么也不做
+    writer.clear_highlight();
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, 2).1,
+        before[(BUFFER_HEIGHT - 1) * BUFFER_WIDTH + 2]
+    );
+}
+
+#[test_case]
+fn test_all_colors_in_declaration_order() {
+    assert_eq!(ALL_COLORS.len(), 16);
+    for (index, color) in ALL_COLORS.into_iter().enumerate() {
+        assert_eq!(color as u8, index as u8);
+    }
+}
+
+#[test_case]
+fn test_color_code_decode_round_trip() {
+    for fg in 0..16u8 {
+        for bg in 0..16u8 {
+            let foreground = Color::try_from(fg).unwrap();
+            let background = Color::try_from(bg).unwrap();
+            let (decoded_fg, decoded_bg, blink) = ColorCode::new(foreground, background).decode();
+            assert_eq!(decoded_fg, foreground);
+            assert_eq!(decoded_bg as u8, bg & 0x07);
+            assert_eq!(blink, bg >= 8);
+        }
+    }
+    assert_eq!(Color::try_from(16), Err(16));
+}
+
+#[test_case]
+fn test_write_string_replaces_non_printable_bytes() {
+    // 控制字符 0x07 和 0x1b，以及占两个字节的 'é'，write_string_raw 把它显示为两个替代字节
+    let bytes = b"a\x07b\x1bc\xc3\xa9d";
+    let s = core::str::from_utf8(bytes).unwrap();
+    let mut writer = TestWriter::new();
+    writer.write_string_raw(s);
+
+    let row = BUFFER_HEIGHT - 1;
+    let expected = [b'a', 0xfe, b'b', 0xfe, b'c', 0xfe, 0xfe, b'd'];
+    for (col, &byte) in expected.iter().enumerate() {
+        assert_eq!(writer.read_char(row, col).0, byte);
+    }
+    assert_eq!(writer.read_char(row, expected.len()).0, b' ');
+}
+
+#[test_case]
+fn test_write_string_one_cell_per_char() {
+    let mut writer = TestWriter::new();
+    writer.write_string("é");
+    assert_eq!(writer.column(), 1);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, 0x82);
+    let mut writer = TestWriter::new();
+    writer.write_string_raw("é");
+    assert_eq!(writer.column(), 2);
+
+    // 没有 CP437 字形的字符也只占一个单元格，长字符串不走快速路径
+    let mut writer = TestWriter::new();
+    let long = "ā".repeat(BUFFER_WIDTH);
+    writer.write_string(&long);
+    assert_eq!(writer.column(), BUFFER_WIDTH);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0).0, b' ');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, 0xfe);
+    let mut writer = TestWriter::new();
+    assert_eq!(writer.write_wrapped("é\nā"), 2);
+    assert_eq!(writer.column(), 1);
+}
+
+#[test_case]
+fn test_wide_and_combining_chars() {
+    let row = BUFFER_HEIGHT - 1;
+    let mut writer = TestWriter::new();
+    // 组合字符不占单元格，宽字符占两个
+    assert_eq!(writer.write_counted(format_args!("e\u{301}中x")), 4);
+    let expected = [b'e', 0xfe, 0xfe, b'x', b' '];
+    for (col, &byte) in expected.iter().enumerate() {
+        assert_eq!(writer.read_char(row, col).0, byte);
+    }
+    assert_eq!(writer.column(), 4);
+
+    // 折行按单元格计算：行尾只剩一列时宽字符的后一半在下一行
+    let mut writer = TestWriter::new();
+    let line = "a".repeat(BUFFER_WIDTH - 1);
+    assert_eq!(writer.write_wrapped(&line), 1);
+    assert_eq!(writer.write_wrapped("中"), 2);
+    assert_eq!(writer.column(), 1);
+
+    let mut writer = TestWriter::new();
+    writer.write_fmt_at(row - 1, 0, format_args!("中\u{301}文!"));
+    let expected = [0xfe, 0xfe, 0xfe, 0xfe, b'!'];
+    for (col, &byte) in expected.iter().enumerate() {
+        assert_eq!(writer.read_char(row - 1, col).0, byte);
+    }
+}
+
+#[test_case]
+fn test_sync_from_buffer() {
+    let mut writer = TestWriter::new();
+    // 屏幕全空时光标不动
+    writer.sync_from_buffer();
+    assert_eq!(writer.column(), 0);
+
+    let color = ColorCode::new(Color::LightGray, Color::Black);
+    for (col, byte) in b"SeaBIOS".iter().enumerate() {
+        writer.put_char(0, col, *byte, color);
+    }
+    writer.put_char(3, 0, b'a', color);
+    writer.put_char(3, 2, b'c', color);
+    writer.sync_from_buffer();
+    // 第 3 行移到最后一行，其上的内容一起下移
+    let shift = BUFFER_HEIGHT - 1 - 3;
+    assert_eq!(writer.read_char(shift, 0).0, b'S');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, b'c');
+    assert_eq!(writer.read_char(0, 0).0, b' ');
+    assert_eq!(writer.column(), 3);
+
+    writer.write_string("d");
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 3).0, b'd');
+}
+
+#[test_case]
+fn test_write_counted() {
+    let mut writer = TestWriter::new();
+    assert_eq!(writer.write_counted(format_args!("{}", 12345)), 5);
+    assert_eq!(writer.column(), 5);
+    // 换行符不计入，不可打印的字节替换后计入
+    assert_eq!(
+        writer.write_counted(format_args!("{:>4}\n{}", 7, "\x1b")),
+        5
+    );
+    assert_eq!(writer.column(), 1);
+    assert_eq!(writer.write_counted(format_args!("{}", 'é')), 1);
+}
+
+#[test_case]
+fn test_write_rule() {
+    let mut writer = TestWriter::new();
+    writer.set_color(Color::LightCyan, Color::Blue);
+    writer.write_rule(RULE_CHAR);
+    let color = ColorCode::new(Color::LightCyan, Color::Blue);
+    for col in 0..BUFFER_WIDTH {
+        assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, col), (RULE_CHAR, color));
+    }
+    assert_eq!(writer.column_position, 0);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b' ');
+
+    // 光标不在行首时先换行，已有的文字保留在分隔线上方
+    writer.write_string("menu");
+    writer.write_rule(b'-');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 3, 0).0, b'm');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0).0, b'-');
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 2, BUFFER_WIDTH - 1).0,
+        b'-'
+    );
+    assert_eq!(writer.column_position, 0);
+}
+
+#[test_case]
+fn test_write_styled_fmt_restores_color() {
+    let mut writer = TestWriter::new();
+    writer.set_color(Color::Yellow, Color::Black);
+    writer
+        .write_styled_fmt(format_args!("e{}", 1), Style::Error)
+        .unwrap();
+    writer.write_string("n");
+    let row = BUFFER_HEIGHT - 1;
+    assert_eq!(writer.read_char(row, 0), (b'e', ERROR_COLOR));
+    assert_eq!(writer.read_char(row, 1), (b'1', ERROR_COLOR));
+    assert_eq!(
+        writer.read_char(row, 2),
+        (b'n', ColorCode::new(Color::Yellow, Color::Black))
+    );
+}
+
+#[test_case]
+fn test_take_scrolled() {
+    use x86_64::instructions::interrupts;
+
+    let take = || interrupts::without_interrupts(|| WRITER.lock().take_scrolled());
+    take();
+    print!("no newline");
+    assert!(!take());
+    for i in 0..3 {
+        println!("scroll {}", i);
+    }
+    assert!(take());
+    assert!(!take());
+
+    // 超过一屏、没有换行符的字符串走快速路径，同样滚动了整个屏幕
+    let mut writer = TestWriter::new();
+    writer.write_string(&"y".repeat(BUFFER_WIDTH - 1));
+    assert!(!writer.take_scrolled());
+    writer.write_string(&"y".repeat(BUFFER_WIDTH * BUFFER_HEIGHT));
+    assert!(writer.take_scrolled());
+    assert!(!writer.take_scrolled());
+}
+
+#[test_case]
+fn test_clamp_cell() {
+    assert_eq!(clamp_cell(0, 0), (0, 0));
+    assert_eq!(
+        clamp_cell(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1),
+        (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1)
+    );
+    assert_eq!(clamp_cell(BUFFER_HEIGHT, 3), (BUFFER_HEIGHT - 1, 3));
+    assert_eq!(
+        clamp_cell(usize::MAX, usize::MAX),
+        (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1)
+    );
+}
+
+#[test_case]
+fn test_cpu_prefix() {
+    let mut writer = TestWriter::new();
+    write_with_cpu_prefix(&mut writer, 2, b"ab", Style::Normal);
+    write_with_cpu_prefix(&mut writer, 2, b"c\nd\n", Style::Normal);
+    let row = |writer: &TestWriter, row: usize| -> [u8; 9] {
+        core::array::from_fn(|col| writer.read_char(row, col).0)
+    };
+    // 同一行分几次写入时只有行首有前缀
+    assert_eq!(&row(&writer, BUFFER_HEIGHT - 3), b"[cpu2] ab");
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 3, 9).0, b'c');
+    assert_eq!(&row(&writer, BUFFER_HEIGHT - 2)[..8], b"[cpu2] d");
+    assert_eq!(writer.column_position, 0);
+}
+
+#[test_case]
+fn test_replacement_char() {
+    let mut writer = TestWriter::new();
+    writer.set_replacement_char(b'?');
+    writer.write_string("a\x1bb");
+    writer.write_char('中');
+    let row = BUFFER_HEIGHT - 1;
+    assert_eq!(writer.read_char(row, 1).0, b'?');
+    assert_eq!(writer.read_char(row, 2).0, b'b');
+    assert_eq!(writer.read_char(row, 3).0, b'?');
+}
+
+#[test_case]
+fn test_bell_calls_hook_without_output() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static RINGS: AtomicUsize = AtomicUsize::new(0);
+    fn ring() {
+        RINGS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mut writer = TestWriter::new();
+    writer.set_bell(Some(ring));
+    writer.write_string("a\x07b");
+    assert_eq!(RINGS.load(Ordering::Relaxed), 1);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, b'b');
+
+    // 关闭控制字符处理后按不可打印字节显示
+    writer.set_control_chars(false);
+    writer.write_string("\x07");
+    assert_eq!(RINGS.load(Ordering::Relaxed), 1);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, 0xfe);
+}
+
+#[test_case]
+fn test_vertical_tab_moves_down_same_column() {
+    let mut writer = TestWriter::new();
+    writer.write_string("a\x0bb");
+    // a 写在第 0 列，光标在第 1 列；VT 下移一行后 b 写在下一行的第 1 列
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0).0, b'a');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 1).0, b' ');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b' ');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, b'b');
+    assert_eq!(writer.write_wrapped("\x0bc"), 2);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, b'c');
+
+    // 关闭控制字符处理后按不可打印字节显示
+    let mut writer = TestWriter::new();
+    writer.set_control_chars(false);
+    writer.write_string("a\x0bb");
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, 0xfe);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, b'b');
+}
+
+#[test_case]
+fn test_delete_erases_previous_char() {
+    let mut writer = TestWriter::new();
+    writer.write_string("ab\x7f");
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b'a');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, b' ');
+    assert_eq!(writer.write_counted(format_args!("c\x7f")), 1);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, b' ');
+
+    // 关闭控制字符处理后显示 ⌂
+    let mut writer = TestWriter::new();
+    writer.set_control_chars(false);
+    writer.write_string("ab\x7f");
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, DELETE);
+}
+
+#[test_case]
+fn test_newline_fill_matches_last_row() {
+    let blue = ColorCode::new(Color::White, Color::Blue);
+    for fill in [NewlineFill::CurrentColor, NewlineFill::MatchLastRow] {
+        let mut writer = TestWriter::new();
+        writer.set_newline_fill(fill);
+        // 蓝色背景铺满屏幕，然后换回默认颜色继续输出
+        writer.set_color(Color::White, Color::Blue);
+        writer.clear_screen();
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_string("x\n");
+
+        let (_, color) = writer.read_char(BUFFER_HEIGHT - 1, 0);
+        match fill {
+            NewlineFill::CurrentColor => {
+                assert_eq!(color, ColorCode::new(Color::Yellow, Color::Black))
+            }
+            // 背景沿用蓝色，前景取当前颜色
+            NewlineFill::MatchLastRow => {
+                assert_eq!(color, ColorCode::new(Color::Yellow, Color::Blue))
+            }
+        }
+        // 上移的那一行保持原样
+        assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 1).1, blue);
+    }
+}
+
+#[test_case]
+fn test_backspace_erases_across_wrap() {
+    let mut writer = TestWriter::new();
+    writer.write_string("ab");
+    writer.backspace();
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, b' ');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b'a');
+
+    // 写满一行再多写一个字符，自动折行后退格两次回到上一行的行尾
+    let mut writer = TestWriter::new();
+    for _ in 0..BUFFER_WIDTH {
+        writer.write_byte(b'x');
+    }
+    writer.write_byte(b'y');
+    writer.backspace();
+    writer.backspace();
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1).0,
+        b' '
+    );
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 2).0,
+        b'x'
+    );
+    writer.write_byte(b'z');
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1).0,
+        b'z'
+    );
+}
+
+#[test_case]
+fn test_overwrite_mode_replaces_in_middle() {
+    let mut writer = TestWriter::new();
+    writer.write_string("abcdef");
+    writer.set_column(2);
+    writer.write_string("XY");
+    let row: alloc::vec::Vec<u8> = (0..7)
+        .map(|col| writer.read_char(BUFFER_HEIGHT - 1, col).0)
+        .collect();
+    assert_eq!(row, b"abXYef ");
+}
+
+#[test_case]
+fn test_insert_mode_shifts_right_in_middle() {
+    let mut writer = TestWriter::new();
+    writer.write_string("abcdef");
+    writer.set_insert_mode(true);
+    writer.set_column(2);
+    writer.write_string("XY");
+    let row: alloc::vec::Vec<u8> = (0..9)
+        .map(|col| writer.read_char(BUFFER_HEIGHT - 1, col).0)
+        .collect();
+    assert_eq!(row, b"abXYcdef ");
+
+    // 切回覆盖模式后继续输入会覆盖 c
+    writer.set_insert_mode(false);
+    writer.write_byte(b'Z');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 4).0, b'Z');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 5).0, b'd');
+}
+
+#[test_case]
+fn test_insert_mode_drops_char_at_end_of_row() {
+    let mut writer = TestWriter::new();
+    for _ in 0..BUFFER_WIDTH - 1 {
+        writer.write_byte(b'x');
+    }
+    writer.write_byte(b'y');
+    writer.set_insert_mode(true);
+    writer.set_column(0);
+    writer.write_byte(b'z');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b'z');
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1).0,
+        b'x'
+    );
+}
+
+#[test_case]
+fn test_try_write_byte_refuses_to_scroll_text_away() {
+    let mut writer = TestWriter::new();
+    assert!(writer.try_write_byte(b'a'));
+    for _ in 0..BUFFER_HEIGHT - 1 {
+        assert!(writer.try_write_byte(b'\n'));
+    }
+    // a 已经到了第一行，再换行就会滚出屏幕
+    assert_eq!(writer.read_char(0, 0).0, b'a');
+    assert!(!writer.try_write_byte(b'\n'));
+    writer.set_column(BUFFER_WIDTH);
+    assert!(!writer.try_write_byte(b'b'));
+    assert_eq!(writer.read_char(0, 0).0, b'a');
+    assert_eq!(writer.column(), BUFFER_WIDTH);
+
+    // 有回滚缓冲区时滚出去的行会被保存
+    let mut writer = TestWriter::with_scrollback(4);
+    writer.write_byte(b'a');
+    for _ in 0..BUFFER_HEIGHT {
+        assert!(writer.try_write_byte(b'\n'));
+    }
+}
+
+#[test_case]
+fn test_try_write_byte_in_insert_mode() {
+    let mut writer = TestWriter::new();
+    writer.set_insert_mode(true);
+    writer.write_string("abc");
+    writer.set_column(0);
+    assert!(writer.try_write_byte(b'x'));
+    // 最后一格有文字时插入会把它挤掉
+    let color = writer.color_code();
+    writer.put_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1, b'z', color);
+    assert!(!writer.try_write_byte(b'y'));
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, b'a');
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1).0,
+        b'z'
+    );
+    // 覆盖模式不会挤掉任何东西
+    writer.set_insert_mode(false);
+    assert!(writer.try_write_byte(b'y'));
+}
+
+#[test_case]
+fn test_defaults_agree() {
+    let writer = OffscreenWriter::new();
+    assert_eq!(ColorCode::default(), DEFAULT_COLOR);
+    assert_eq!(writer.color_code(), ColorCode::default());
+    assert_eq!(writer.read_char(0, 0), (b' ', ColorCode::default()));
+    assert_eq!(writer.column(), 0);
+}
+
+#[test_case]
+fn test_color_from_name() {
+    assert_eq!(Color::from_name("lightblue"), Some(Color::LightBlue));
+    assert_eq!(Color::from_name("White"), Some(Color::White));
+    assert_eq!(Color::from_name("purple"), None);
+    for value in 0..16u8 {
+        let color = Color::try_from(value).unwrap();
+        assert_eq!(Color::from_name(color.name()), Some(color));
+    }
+}
+
+#[test_case]
+fn test_color_complement() {
+    assert_eq!(Color::Black.complement(), Color::White);
+    assert_eq!(Color::White.complement(), Color::Black);
+    assert_eq!(Color::Yellow.complement(), Color::Black);
+    assert_eq!(Color::Blue.complement(), Color::White);
+    assert_eq!(Color::DarkGray.complement(), Color::White);
+    assert_eq!(Color::LightCyan.complement(), Color::Black);
+}
+
+#[test_case]
+fn test_eprint_uses_error_color_and_restores() {
+    use x86_64::instructions::interrupts;
+
+    // 全局的 WRITER 与其他测试共用：从行首开始，结束时也回到行首，不影响之后的测试
+    crate::println!();
+    let saved = interrupts::without_interrupts(|| WRITER.lock().color_code);
+    crate::eprintln!("e");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let (character, color_code) = writer.read_char(BUFFER_HEIGHT - 2, 0);
+        assert_eq!(character, b'e');
+        assert_eq!(color_code, ERROR_COLOR);
+        assert_eq!(writer.color_code, saved);
+        assert_eq!(writer.column_position, 0);
+    });
+}
+
+#[test_case]
+fn test_clear_to_end_of_screen() {
+    let mut writer = TestWriter::new();
+    writer.write_string("above\n");
+    writer.write_string(&"x".repeat(BUFFER_WIDTH - 1));
+    writer.set_column(40);
+    writer.set_color(Color::Yellow, Color::Blue);
+    writer.clear_to_end_of_screen();
+
+    assert_eq!(writer.column(), 40);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0).0, b'a');
+    for col in 0..40 {
+        assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, col).0, b'x');
+    }
+    let blank = ColorCode::new(Color::Yellow, Color::Blue);
+    for col in 40..BUFFER_WIDTH {
+        assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, col), (b' ', blank));
+    }
+
+    // 本行写满时什么也不清
+    writer.write_string(&"y".repeat(BUFFER_WIDTH - 40));
+    writer.clear_to_end_of_screen();
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1).0,
+        b'y'
+    );
+}
+
+#[test_case]
+fn test_output_drops_highlight_first() {
+    let mut writer = TestWriter::new();
+    writer.write_string("abc");
+    let color = writer.color_code();
+    writer.highlight((BUFFER_HEIGHT - 1, 0), (BUFFER_HEIGHT - 1, 5));
+    // 滚屏前先恢复颜色，移到上一行的内容不再是反色
+    writer.write_string("\nxyz");
+    assert_eq!(writer.highlight, None);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0), (b'a', color));
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0), (b'x', color));
+    writer.clear_highlight();
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0), (b'x', color));
+
+    // 直接写入区间内的单元格时也一样，区间外的写入不影响高亮
+    let red = ColorCode::new(Color::Red, Color::Black);
+    writer.highlight((0, 0), (0, 3));
+    writer.put_char(1, 0, b'o', red);
+    assert_eq!(writer.read_char(0, 0).1, color.inverted());
+    writer.put_char(0, 2, b'i', red);
+    assert_eq!(writer.read_char(0, 0).1, color);
+    writer.clear_highlight();
+    assert_eq!(writer.read_char(0, 2), (b'i', red));
+}
+
+#[test_case]
+fn test_screenful_drops_highlight_first() {
+    let mut writer = TestWriter::new();
+    let color = writer.color_code();
+    writer.highlight((0, 0), (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1));
+    // 超过一屏、没有换行符的字符串走 write_screenful 的快速路径，改写了所有单元格
+    let long = "x".repeat(BUFFER_WIDTH * BUFFER_HEIGHT + 1);
+    writer.write_string(&long);
+    assert_eq!(writer.highlight, None);
+    writer.clear_highlight();
+    for (row, col) in [(0, 0), (BUFFER_HEIGHT / 2, 7), (BUFFER_HEIGHT - 1, 0)] {
+        assert_eq!(writer.read_char(row, col), (b'x', color));
+    }
+}
+
+#[test_case]
+fn test_sanitize_clamps_interrupted_state() {
+    let mut writer = TestWriter::new();
+    writer.column_position = BUFFER_WIDTH + 7;
+    writer.highlight = Some((0, BUFFER_HEIGHT * BUFFER_WIDTH));
+    writer.sanitize();
+    assert_eq!(writer.column(), BUFFER_WIDTH);
+    assert_eq!(writer.highlight, None);
+    // 之后的输出正常折行，不会越界
+    writer.write_byte(b'x');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b'x');
+    assert_eq!(writer.column(), 1);
+
+    // 合法的状态保持不变
+    writer.highlight(
+        (BUFFER_HEIGHT - 1, 0),
+        (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1),
+    );
+    let highlight = writer.highlight;
+    writer.sanitize();
+    assert_eq!(writer.column(), 1);
+    assert_eq!(writer.highlight, highlight);
+}
+
+#[test_case]
+fn test_write_highlighted_only_changes_background() {
+    let mut writer = TestWriter::new();
+    let normal = writer.color_code();
+    let marked = ColorCode::new(Color::Yellow, Color::Blue);
+    let color_at = |writer: &Writer, row, col| writer.read_char(row, col).1;
+
+    writer.write_string("ab");
+    writer.write_highlighted("XY", Color::Blue);
+    writer.write_string("cd");
+    let last = BUFFER_HEIGHT - 1;
+    for (col, expected) in [normal, normal, marked, marked, normal, normal]
+        .into_iter()
+        .enumerate()
+    {
+        assert_eq!(color_at(&writer, last, col), expected, "col {}", col);
+    }
+    assert_eq!(writer.color_code(), normal);
+
+    // 折行时新的一行用原来的颜色填充，高亮只落在写入的字符上
+    writer.set_column(BUFFER_WIDTH - 2);
+    writer.write_highlighted("wrap", Color::Blue);
+    assert_eq!(color_at(&writer, last - 1, BUFFER_WIDTH - 1), marked);
+    assert_eq!(color_at(&writer, last, 1), marked);
+    assert_eq!(color_at(&writer, last, 2), normal);
+    assert_eq!(color_at(&writer, last, BUFFER_WIDTH - 1), normal);
+    assert_eq!(writer.color_code(), normal);
+}
+
+#[test_case]
+fn test_writeln_colored_colors_the_whole_line() {
+    use x86_64::instructions::interrupts;
+
+    let green = ColorCode::new(Color::Green, Color::Black);
+    // 先换行，不依赖前面的测试把光标留在哪一列
+    crate::println!();
+    let saved = interrupts::without_interrupts(|| WRITER.lock().color_code);
+    let captured = console::capture::<64>(|| {
+        crate::writeln_colored!(Color::Green, Color::Black, "done in {} ticks", 42);
+    })
+    .unwrap();
+    assert_eq!(captured.contents(), "done in 42 ticks\n");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        for col in 0..16 {
+            assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, col).1, green);
+        }
+        assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 15).0, b's');
+        assert_eq!(writer.column_position, 0);
+        assert_eq!(writer.color_code, saved);
+    });
+}
+
+#[test_case]
+fn test_buffer_ptr_blits_a_frame() {
+    let mut writer = TestWriter::new();
+    let color_code = ColorCode::new(Color::White, Color::Blue);
+    unsafe {
+        let ptr = writer.buffer_ptr();
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let character = b'a' + ((row + col) % 26) as u8;
+                ptr.add(row * BUFFER_WIDTH + col)
+                    .write_volatile(ScreenChar::new(character, color_code));
+            }
+        }
+    }
+    assert_eq!(writer.read_char(0, 0), (b'a', color_code));
+    assert_eq!(writer.read_char(1, 2), (b'd', color_code));
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1),
+        (
+            b'a' + ((BUFFER_HEIGHT + BUFFER_WIDTH - 2) % 26) as u8,
+            color_code
+        )
+    );
+}
+
+#[test_case]
+fn test_write_wrapped_counts_lines() {
+    use alloc::string::String;
+
+    let mut writer = TestWriter::new();
+    let text: String = (0..BUFFER_WIDTH * 2 + 5).map(|_| 'w').collect();
+    // 折行两次，共占 3 行
+    assert_eq!(writer.write_wrapped(&text), 3);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 4).0, b'w');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 5).0, b' ');
+
+    let mut writer = TestWriter::new();
+    assert_eq!(writer.write_wrapped("one\ntwo"), 2);
+    // 恰好写满一行不会折行，下一个字符才会
+    let full: String = (0..BUFFER_WIDTH).map(|_| 'f').collect();
+    let mut writer = TestWriter::new();
+    assert_eq!(writer.write_wrapped(&full), 1);
+    assert_eq!(writer.write_wrapped("x"), 2);
+}
+
+#[test_case]
+fn test_write_lines() {
+    let mut writer = TestWriter::new();
+    writer.set_color(Color::Yellow, Color::Blue);
+    writer.write_lines(&["first", "second", "third"]);
+    // 三行在最后一行之上连续排列，光标回到空的最后一行
+    let rows = ["first", "second", "third"];
+    for (offset, text) in rows.iter().enumerate() {
+        let row = BUFFER_HEIGHT - 4 + offset;
+        for (col, byte) in text.bytes().enumerate() {
+            assert_eq!(
+                writer.read_char(row, col),
+                (byte, ColorCode::new(Color::Yellow, Color::Blue))
+            );
+        }
+        assert_eq!(writer.read_char(row, text.len()).0, b' ');
+    }
+    assert_eq!(writer.column(), 0);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b' ');
+}
+
+#[test_case]
+fn test_write_u64_padded() {
+    let mut writer = TestWriter::new();
+    // 比字段短：左侧填充
+    writer.write_u64_padded(42, 5, b' ');
+    writer.write_u64_padded(7, 3, b'0');
+    // 与字段等长
+    writer.write_u64_padded(1234, 4, b'*');
+    // 比字段长：完整写出
+    writer.write_u64_padded(123456, 2, b' ');
+    writer.write_u64_padded(0, 0, b' ');
+    let row: alloc::vec::Vec<u8> = (0..19)
+        .map(|col| writer.read_char(BUFFER_HEIGHT - 1, col).0)
+        .collect();
+    assert_eq!(row, b"   4200712341234560");
+
+    let mut writer = TestWriter::new();
+    writer.write_u64_padded(u64::MAX, 22, b'.');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, b'.');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 2).0, b'1');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 21).0, b'5');
+}
+
+#[test_case]
+fn test_write_repeated() {
+    let mut writer = TestWriter::new();
+    writer.write_string("ab");
+    writer.write_repeated(b'-', 5);
+    for col in 2..7 {
+        assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, col).0, b'-');
+    }
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 7).0, b' ');
+    assert_eq!(writer.column(), 7);
+
+    // 写到行尾时照常折行
+    writer.write_repeated(b'=', BUFFER_WIDTH);
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 2, BUFFER_WIDTH - 1).0,
+        b'='
+    );
+    assert_eq!(writer.column(), 7);
+    writer.write_repeated(b'x', 0);
+    assert_eq!(writer.column(), 7);
+}
+
+#[test_case]
+fn test_write_u64_radix() {
+    let row_text = |writer: &TestWriter, len| -> alloc::vec::Vec<u8> {
+        (0..len)
+            .map(|col| writer.read_char(BUFFER_HEIGHT - 1, col).0)
+            .collect()
+    };
+    let mut writer = TestWriter::new();
+    writer.write_u64_radix(0b1011, 2, 0, b' ');
+    writer.write_byte(b' ');
+    writer.write_u64_radix(0o755, 8, 0, b' ');
+    writer.write_byte(b' ');
+    writer.write_u64_radix(90210, 10, 0, b' ');
+    writer.write_byte(b' ');
+    writer.write_u64_radix(0xbeef, 16, 0, b' ');
+    writer.write_byte(b' ');
+    writer.write_u64_radix(0, 16, 0, b' ');
+    assert_eq!(row_text(&writer, 22), b"1011 755 90210 BEEF 0");
+
+    // 寄存器格式：0x 加 16 位十六进制，高位补零
+    let mut writer = TestWriter::new();
+    writer.write_string("0x");
+    writer.write_u64_radix(0x20_1234, 16, 16, b'0');
+    assert_eq!(row_text(&writer, 18), b"0x0000000000201234");
+
+    // 空格填充、与字段等长、比字段长
+    let mut writer = TestWriter::new();
+    writer.write_u64_radix(5, 2, 6, b' ');
+    writer.write_u64_radix(0o17, 8, 2, b'0');
+    writer.write_u64_radix(0xabc, 16, 1, b'0');
+    assert_eq!(row_text(&writer, 11), b"   10117ABC");
+
+    // u64::MAX 的二进制表示正好 64 位，在一行内放得下
+    let mut writer = TestWriter::new();
+    writer.write_u64_radix(u64::MAX, 2, 0, b' ');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b'1');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 63).0, b'1');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 64).0, b' ');
+    assert_eq!(writer.column(), 64);
+}
+
+#[test_case]
+fn test_wrap_indicator() {
+    use alloc::string::String;
+
+    let line =
+        |len: usize| -> String { (0..len).map(|i| (b'a' + (i % 26) as u8) as char).collect() };
+    let indicator = WrapIndicator::DEFAULT;
+    let last = BUFFER_HEIGHT - 1;
+
+    // 79 个字符正好放得下，不折行
+    let mut writer = TestWriter::new();
+    writer.set_wrap_indicator(Some(indicator));
+    assert_eq!(writer.write_wrapped(&line(79)), 1);
+    assert_eq!(writer.read_char(last, 78).0, line(79).as_bytes()[78]);
+    assert_eq!(writer.read_char(last, 79).0, b' ');
+
+    // 第 80 个字符折到下一行，标记不被覆盖
+    for len in [80, 81] {
+        let mut writer = TestWriter::new();
+        writer.set_wrap_indicator(Some(indicator));
+        let text = line(len);
+        assert_eq!(writer.write_wrapped(&text), 2);
+        let (glyph, color) = writer.read_char(last - 1, 79);
+        assert_eq!(glyph, indicator.glyph);
+        assert_eq!(color, ColorCode::new(Color::DarkGray, Color::Black));
+        assert_eq!(writer.read_char(last - 1, 78).0, text.as_bytes()[78]);
+        assert_eq!(writer.read_char(last, 0).0, b' ');
+        assert_eq!(writer.read_char(last, 1).0, b' ');
+        assert_eq!(writer.read_char(last, 2).0, text.as_bytes()[79]);
+        assert_eq!(writer.column(), 2 + len - 79);
+    }
+
+    // 每个续行放 77 个字符：79 + 77 + 44
+    let mut writer = TestWriter::new();
+    writer.set_wrap_indicator(Some(indicator));
+    let text = line(200);
+    assert_eq!(writer.write_wrapped(&text), 3);
+    for row in [last - 2, last - 1] {
+        assert_eq!(writer.read_char(row, 79).0, indicator.glyph);
+    }
+    assert_eq!(writer.read_char(last - 1, 2).0, text.as_bytes()[79]);
+    assert_eq!(writer.read_char(last - 1, 78).0, text.as_bytes()[155]);
+    assert_eq!(writer.read_char(last, 2).0, text.as_bytes()[156]);
+    assert_eq!(writer.column(), 2 + 44);
+    assert_eq!(writer.read_char(last, 79).0, b' ');
+
+    // 换行符不画标记
+    let mut writer = TestWriter::new();
+    writer.set_wrap_indicator(Some(indicator));
+    writer.write_string(&line(79));
+    writer.write_string("\nx");
+    assert_eq!(writer.read_char(last - 1, 79).0, b' ');
+    assert_eq!(writer.read_char(last, 0).0, b'x');
+
+    // 关闭时与原来一样写满 80 列
+    let mut writer = TestWriter::new();
+    assert_eq!(writer.write_wrapped(&line(80)), 1);
+    assert_eq!(writer.read_char(last, 79).0, line(80).as_bytes()[79]);
+}
+
+#[test_case]
+fn test_reset_restores_defaults() {
+    let mut writer = TestWriter::with_scrollback(16);
+    for _ in 0..BUFFER_HEIGHT + 4 {
+        writer.write_string("line\n");
+    }
+    writer.scroll_view_up(2);
+    writer.set_color(Color::White, Color::Blue);
+    writer.set_insert_mode(true);
+    writer.set_control_chars(false);
+    writer.set_newline_fill(NewlineFill::MatchLastRow);
+    writer.set_replacement_char(b'?');
+    writer.set_wrap_indicator(Some(WrapIndicator::DEFAULT));
+    writer.highlight((0, 0), (1, 5));
+    writer.write_string("half");
+
+    writer.reset();
+    assert_eq!(writer.color_code(), DEFAULT_COLOR);
+    assert!(!writer.insert_mode());
+    assert!(writer.control_chars());
+    assert_eq!(writer.newline_fill(), NewlineFill::CurrentColor);
+    assert_eq!(writer.replacement, DEFAULT_REPLACEMENT);
+    assert_eq!(writer.wrap_indicator(), None);
+    assert_eq!(writer.highlight, None);
+    assert_eq!(writer.scroll_offset(), 0);
+    assert_eq!(writer.column(), 0);
+    for row in 0..BUFFER_HEIGHT {
+        for col in 0..BUFFER_WIDTH {
+            assert_eq!(writer.read_char(row, col), (b' ', DEFAULT_COLOR));
+        }
+    }
+}
+
+#[test_case]
+fn test_scroll_up_matches_repeated_new_line() {
+    let fill = |writer: &mut Writer| {
+        for row in 0..BUFFER_HEIGHT {
+            let color = Color::try_from((row % 15 + 1) as u8).unwrap();
+            writer.set_color(color, Color::Black);
+            writer.write_fmt_at(row, 0, format_args!("row {}", row));
+        }
+        writer.set_newline_fill(NewlineFill::MatchLastRow);
+        writer.put_char(
+            BUFFER_HEIGHT - 1,
+            BUFFER_WIDTH - 1,
+            b'x',
+            ColorCode::new(Color::White, Color::Blue),
+        );
+    };
+    let mut single = TestWriter::with_scrollback(16);
+    let mut batch = TestWriter::with_scrollback(16);
+    fill(&mut single);
+    fill(&mut batch);
+    for _ in 0..5 {
+        single.new_line();
+    }
+    batch.scroll_up(5);
+    for row in 0..BUFFER_HEIGHT {
+        for col in 0..BUFFER_WIDTH {
+            assert_eq!(batch.read_char(row, col), single.read_char(row, col));
+        }
+    }
+    assert_eq!(
+        batch.read_char(0, 4),
+        (b'5', ColorCode::new(Color::Brown, Color::Black))
+    );
+    assert_eq!(batch.column(), 0);
+    // 滚出的行按顺序进入历史
+    single.scroll_view_up(5);
+    batch.scroll_view_up(5);
+    assert_eq!(batch.history_len(), single.history_len());
+    assert_eq!(batch.scroll_offset(), 5);
+    for row in 0..BUFFER_HEIGHT {
+        for col in 0..BUFFER_WIDTH {
+            assert_eq!(
+                batch.read_visible_char(row, col),
+                single.read_visible_char(row, col)
+            );
+        }
+    }
+    assert_eq!(batch.read_visible_char(0, 4).0, b'0');
+
+    // 超过屏幕高度时只清空整个屏幕
+    let mut writer = TestWriter::new();
+    writer.write_string("text");
+    writer.scroll_up(BUFFER_HEIGHT * 3);
+    assert!((0..BUFFER_HEIGHT).all(|row| writer.read_char(row, 0).0 == b' '));
+    writer.scroll_up(0);
+    assert_eq!(writer.column(), 0);
+}
@@ -0,0 +1,188 @@
+//! 暂停屏幕输出
+//! 与终端的 Scroll Lock（或 Ctrl+S / Ctrl+Q）一样：pause_output 之后 print! 等写到屏幕的输出不再画出，
+//! 先放进固定大小的暂存区，resume_output 时一次写到屏幕，便于看清刷得很快的输出。
+//! 只暂停屏幕，串口等其他输出目标照常输出。暂存区不保留颜色，恢复时按当前颜色写出。
+//!
+//! 暂存区满了以后丢弃最旧的字节，恢复时先写一行 TRUNCATED_MARKER 提示中间有输出丢失。
+//! 暂存和写出都在持有 WRITER 的锁时进行（锁的顺序是先 WRITER 后暂存区），
+//! 恢复时不会有新的输出插到暂存的内容之前。panic 报告会先恢复输出，回溯等内容直接画到屏幕上
+use super::{Writer, WRITER};
+use crate::console::sink::OutputSink;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// 暂存区的字节数
+pub const PAUSE_BUFFER_SIZE: usize = 2048;
+/// 暂存区溢出时，恢复后先写出的提示
+pub const TRUNCATED_MARKER: &str = "[... earlier output dropped while paused ...]\n";
+
+static OUTPUT_PAUSED: AtomicBool = AtomicBool::new(false);
+static HELD: Mutex<HeldOutput> = Mutex::new(HeldOutput::new());
+
+/// 固定容量的环形缓冲区，满了以后丢弃最旧的字节
+struct HeldOutput {
+    bytes: [u8; PAUSE_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+    truncated: bool,
+}
+
+impl HeldOutput {
+    const fn new() -> Self {
+        HeldOutput {
+            bytes: [0; PAUSE_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len == PAUSE_BUFFER_SIZE {
+                self.drop_oldest();
+            }
+            self.bytes[(self.head + self.len) % PAUSE_BUFFER_SIZE] = byte;
+            self.len += 1;
+        }
+    }
+
+    /// 丢弃最旧的一个字符，连同它的 UTF-8 后续字节，保证剩下的内容从字符边界开始
+    fn drop_oldest(&mut self) {
+        loop {
+            self.head = (self.head + 1) % PAUSE_BUFFER_SIZE;
+            self.len -= 1;
+            if self.len == 0 || self.bytes[self.head] & 0xc0 != 0x80 {
+                break;
+            }
+        }
+        self.truncated = true;
+    }
+
+    /// 按写入的顺序取出全部内容并清空
+    fn take(&mut self, f: impl FnOnce(&[u8], bool)) {
+        self.bytes.rotate_left(self.head);
+        f(&self.bytes[..self.len], self.truncated);
+        self.head = 0;
+        self.len = 0;
+        self.truncated = false;
+    }
+}
+
+impl fmt::Write for HeldOutput {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// 屏幕输出是否暂停
+pub fn output_paused() -> bool {
+    OUTPUT_PAUSED.load(Ordering::SeqCst)
+}
+
+/// 暂停屏幕输出，之后的输出放进暂存区
+pub fn pause_output() {
+    OUTPUT_PAUSED.store(true, Ordering::SeqCst);
+}
+
+/// 恢复屏幕输出，先把暂存的内容写到屏幕；没有暂停时什么也不做
+pub fn resume_output() {
+    interrupts::without_interrupts(|| {
+        resume_output_locked(&mut WRITER.lock());
+    });
+}
+
+/// 与 resume_output 相同，但由已经持有 WRITER 的锁的调用者使用，例如 panic 处理：
+/// 停机之后没有人会再恢复输出，之后的回溯等输出必须直接画到屏幕上
+pub(crate) fn resume_output_locked(writer: &mut Writer) {
+    let mut held = HELD.lock();
+    if !OUTPUT_PAUSED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    held.take(|bytes, truncated| flush(writer, bytes, truncated));
+}
+
+fn flush(writer: &mut Writer, bytes: &[u8], truncated: bool) {
+    if truncated {
+        writer.write_error_bytes(TRUNCATED_MARKER.as_bytes());
+    }
+    writer.write_bytes(bytes);
+}
+
+/// 暂停时把 bytes 放进暂存区并返回 true；调用者需要持有 WRITER 的锁
+pub(super) fn hold_bytes(bytes: &[u8]) -> bool {
+    if !output_paused() {
+        return false;
+    }
+    HELD.lock().push(bytes);
+    true
+}
+
+/// 与 hold_bytes 相同，但先格式化
+pub(super) fn hold_fmt(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+
+    if !output_paused() {
+        return false;
+    }
+    let _ = HELD.lock().write_fmt(args);
+    true
+}
+
+/// 由 recover_from_formatting_panic 调用，hold_fmt 格式化的途中 panic 时暂存区的锁不会再被释放
+///
+/// # Safety
+/// 与 recover_from_formatting_panic 相同
+pub(super) unsafe fn force_unlock() {
+    unsafe { HELD.force_unlock() };
+}
+
+#[cfg(test)]
+use super::BUFFER_HEIGHT;
+
+#[test_case]
+fn test_paused_output_is_drawn_on_resume() {
+    let row_starts_with = |row: usize, text: &str| {
+        interrupts::without_interrupts(|| {
+            let writer = WRITER.lock();
+            text.bytes()
+                .enumerate()
+                .all(|(col, byte)| writer.read_char(row, col).0 == byte)
+        })
+    };
+    crate::println!("before pause");
+    pause_output();
+    crate::println!("while paused");
+    assert!(row_starts_with(BUFFER_HEIGHT - 2, "before pause"));
+    resume_output();
+    assert!(!output_paused());
+    assert!(row_starts_with(BUFFER_HEIGHT - 2, "while paused"));
+    assert!(row_starts_with(BUFFER_HEIGHT - 3, "before pause"));
+}
+
+#[test_case]
+fn test_overflow_drops_oldest() {
+    let mut held = HeldOutput::new();
+    held.push(&[b'a'; PAUSE_BUFFER_SIZE - 1]);
+    // 'é' 是两个字节，放不下时丢弃最旧的 'a'
+    held.push("é!".as_bytes());
+    held.take(|bytes, truncated| {
+        assert!(truncated);
+        assert_eq!(bytes.len(), PAUSE_BUFFER_SIZE);
+        assert!(bytes.ends_with("é!".as_bytes()));
+    });
+    assert_eq!(held.len, 0);
+    assert!(!held.truncated);
+
+    // 被挤掉的是多字节字符时整个字符一起丢弃
+    let mut held = HeldOutput::new();
+    held.push("é".as_bytes());
+    held.push(&[b'b'; PAUSE_BUFFER_SIZE - 1]);
+    held.take(|bytes, _| {
+        assert_eq!(bytes.len(), PAUSE_BUFFER_SIZE - 1);
+        assert!(bytes.iter().all(|&byte| byte == b'b'));
+    });
+}
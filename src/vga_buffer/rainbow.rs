@@ -0,0 +1,91 @@
+//! 彩虹文字
+//! RainbowText 在固定位置画一段文字，每个字符的前景色依次取调色板中相邻的颜色；
+//! 每次 tick 把调色板转动一格，由定时器周期性调用时颜色就在文字上流动。
+//! tick 只改写已有单元格的前景色，不重写字形，背景色和闪烁位保持不变。
+//! 调色板是 ALL_COLORS 去掉黑色，黑色前景在默认的黑色背景上看不见
+use super::{char_display_width, Cell, Color, ScreenChar, Writer, ALL_COLORS};
+
+/// 调色板的颜色数
+pub const RAINBOW_COLORS: usize = ALL_COLORS.len() - 1;
+
+fn rainbow_color(index: usize) -> Color {
+    ALL_COLORS[1 + index % RAINBOW_COLORS]
+}
+
+pub struct RainbowText {
+    text: &'static str,
+    row: usize,
+    col: usize,
+    /// 调色板转过的格数，第 i 个单元格的颜色是 rainbow_color(i + phase)
+    phase: usize,
+}
+
+impl RainbowText {
+    /// 从 (row, col) 开始显示 text，需要先调用 draw 画出来
+    pub const fn new(text: &'static str, row: usize, col: usize) -> Self {
+        RainbowText {
+            text,
+            row,
+            col,
+            phase: 0,
+        }
+    }
+
+    fn cells(&self) -> impl Iterator<Item = Cell> + '_ {
+        let width: usize = self.text.chars().map(char_display_width).sum();
+        (self.col..self.col + width).map(|col| Cell { row: self.row, col })
+    }
+
+    /// 用 writer 当前的背景色画出文字，超出屏幕的部分被丢弃，光标不移动
+    pub fn draw(&self, writer: &mut Writer) {
+        let background = writer.color_code();
+        let mut cells = self.cells().enumerate();
+        for c in self.text.chars() {
+            let byte = writer.cell_byte(c);
+            for (index, cell) in cells.by_ref().take(char_display_width(c)) {
+                let color_code = background.with_foreground(rainbow_color(index + self.phase));
+                let _ = writer.try_put(cell, ScreenChar::new(byte, color_code));
+            }
+        }
+    }
+
+    /// 调色板转动一格，重新设置每个单元格的前景色
+    pub fn tick(&mut self, writer: &mut Writer) {
+        self.phase = (self.phase + 1) % RAINBOW_COLORS;
+        for (index, cell) in self.cells().enumerate() {
+            let Ok(mut screen_char) = writer.try_read(cell) else {
+                continue;
+            };
+            screen_char.color_code = screen_char
+                .color_code
+                .with_foreground(rainbow_color(index + self.phase));
+            let _ = writer.try_put(cell, screen_char);
+        }
+    }
+}
+
+#[cfg(test)]
+use super::{ColorCode, TestWriter};
+
+#[test_case]
+fn test_tick_rotates_foreground() {
+    let mut writer = TestWriter::new();
+    writer.set_color(Color::White, Color::Blue);
+    let mut rainbow = RainbowText::new("abc", 3, 2);
+    rainbow.draw(&mut writer);
+    for step in 0..3 {
+        for (index, glyph) in b"abc".iter().enumerate() {
+            let (byte, color_code) = writer.read_char(3, 2 + index);
+            let (foreground, background, _) = color_code.decode();
+            assert_eq!(byte, *glyph);
+            assert_eq!(foreground, rainbow_color(index + step), "step {}", step);
+            assert_eq!(background, Color::Blue);
+        }
+        rainbow.tick(&mut writer);
+    }
+    // 文字以外的单元格不受影响
+    assert_eq!(
+        writer.read_char(3, 5).1,
+        ColorCode::new(Color::Yellow, Color::Black)
+    );
+}
@@ -0,0 +1,290 @@
+//! 分段提交的重绘
+//! 整屏重绘（恢复快照、退出屏保等）如果一次写完 4000 个单元格，期间一直持有 WRITER 并关闭中断，
+//! 时钟中断和按键都会被推迟。redraw 先在批量绘制的后备缓冲区上画好整个画面，
+//! 再每次 ROWS_PER_CHUNK 行复制到显存，每段之间释放锁、打开中断。
+//!
+//! 提交完成之前 Writer 写的是后备缓冲区，所以段与段之间的输出不会丢失；
+//! 写入之前（before_output、begin_draw、switch_console，以及 put_char 等直接写单元格的入口）
+//! 会先把剩下的行一次提交完，这些输出总是出现在重绘之后，不会被后面复制的旧内容盖住，
+//! 写进已经提交过的行的内容也不会在换回显存时丢失。
+//! 每次重绘有一个代数，被提前完成或者被新的重绘取代后，原来的提交循环发现代数不同就停止。
+//! panic 等不能被打断的场合用 finish_redraw 一次提交完
+use super::{copy_rows, Buffer, Writer, BUFFER_HEIGHT, WRITER};
+use crate::vga_mode;
+use core::mem;
+use x86_64::instructions::interrupts;
+
+/// 每段提交的行数
+pub const ROWS_PER_CHUNK: usize = 2;
+
+/// 正在分段提交的重绘：后备缓冲区在 Writer::buffer 中，显存换下来放在这里
+pub(super) struct PendingRedraw {
+    front: &'static mut Buffer,
+    next_row: usize,
+}
+
+impl Writer {
+    /// 在后备缓冲区上执行 f，返回这次重绘的代数，之后用 commit_chunk 分段提交。
+    /// 没有后备缓冲区时 f 直接写显存，不需要提交
+    pub fn begin_redraw(&mut self, f: impl FnOnce(&mut Writer)) -> u64 {
+        self.finish_redraw();
+        self.snap_to_bottom();
+        self.redraw_generation += 1;
+        let Some(back) = self.draw_buffer.take() else {
+            f(self);
+            return self.redraw_generation;
+        };
+        copy_rows(&*self.buffer, back, 0..BUFFER_HEIGHT);
+        let front = mem::replace(&mut self.buffer, back);
+        f(self);
+        self.redraw = Some(PendingRedraw { front, next_row: 0 });
+        self.redraw_generation
+    }
+
+    /// 把第 generation 次重绘的下面 rows 行复制到显存，返回是否还有没提交的行。
+    /// 这次重绘已经完成或者被新的重绘取代时不做任何事，返回 false
+    pub fn commit_chunk(&mut self, generation: u64, rows: usize) -> bool {
+        if generation != self.redraw_generation {
+            return false;
+        }
+        let Some(pending) = &mut self.redraw else {
+            return false;
+        };
+        let end = (pending.next_row + rows.max(1)).min(BUFFER_HEIGHT);
+        if pending.next_row == 0 {
+            vga_mode::sync_to_vretrace();
+        }
+        copy_rows(&*self.buffer, pending.front, pending.next_row..end);
+        pending.next_row = end;
+        if end < BUFFER_HEIGHT {
+            return true;
+        }
+        self.end_redraw();
+        false
+    }
+
+    /// 不分段，把正在进行的重绘剩下的行一次提交完
+    pub fn finish_redraw(&mut self) {
+        let Some(pending) = &mut self.redraw else {
+            return;
+        };
+        copy_rows(
+            &*self.buffer,
+            pending.front,
+            pending.next_row..BUFFER_HEIGHT,
+        );
+        self.end_redraw();
+    }
+
+    /// 是否有正在分段提交的重绘
+    pub fn redraw_pending(&self) -> bool {
+        self.redraw.is_some()
+    }
+
+    /// 所有行都已提交：换回显存，后备缓冲区留给下一次使用
+    fn end_redraw(&mut self) {
+        let Some(pending) = self.redraw.take() else {
+            return;
+        };
+        let back = mem::replace(&mut self.buffer, pending.front);
+        self.draw_buffer = Some(back);
+        self.frames_presented += 1;
+    }
+}
+
+/// 在 WRITER 上分段重绘：f 在关闭中断、持有锁时画完整个画面，
+/// 之后每段 ROWS_PER_CHUNK 行单独关闭中断提交，段之间可以响应中断
+pub fn redraw(f: impl FnOnce(&mut Writer)) {
+    let generation = interrupts::without_interrupts(|| WRITER.lock().begin_redraw(f));
+    while interrupts::without_interrupts(|| WRITER.lock().commit_chunk(generation, ROWS_PER_CHUNK))
+    {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+use super::{TestWriter, BUFFER_WIDTH};
+
+#[cfg(test)]
+fn shown(writer: &TestWriter, row: usize, col: usize) -> u8 {
+    unsafe { &*writer.backing }.chars[row][col]
+        .read()
+        .ascii_character
+}
+
+#[test_case]
+fn test_commit_in_chunks() {
+    let mut writer = TestWriter::with_draw_buffer();
+    let generation = writer.begin_redraw(|writer| {
+        for row in 0..BUFFER_HEIGHT {
+            writer.put_char(row, 0, b'a' + row as u8, writer.color_code());
+        }
+    });
+    assert!(writer.redraw_pending());
+    // 提交之前显存保持原样
+    assert_eq!(shown(&writer, 0, 0), b' ');
+
+    assert!(writer.commit_chunk(generation, ROWS_PER_CHUNK));
+    assert_eq!(shown(&writer, 1, 0), b'b');
+    assert_eq!(shown(&writer, 2, 0), b' ');
+    let mut chunks = 1;
+    while writer.commit_chunk(generation, ROWS_PER_CHUNK) {
+        chunks += 1;
+    }
+    assert_eq!(chunks + 1, BUFFER_HEIGHT.div_ceil(ROWS_PER_CHUNK));
+    assert!(!writer.redraw_pending());
+    assert_eq!(writer.frames_presented(), 1);
+    assert_eq!(
+        shown(&writer, BUFFER_HEIGHT - 1, 0),
+        b'a' + BUFFER_HEIGHT as u8 - 1
+    );
+    // 完成后不再提交，后备缓冲区可以再次使用
+    assert!(!writer.commit_chunk(generation, ROWS_PER_CHUNK));
+    writer.begin_draw().write_string("x");
+    assert_eq!(writer.frames_presented(), 2);
+}
+
+#[test_case]
+fn test_screenful_during_redraw_is_kept() {
+    use alloc::string::String;
+
+    let mut writer = TestWriter::with_draw_buffer();
+    let generation = writer.begin_redraw(|writer| {
+        for row in 0..BUFFER_HEIGHT {
+            writer.put_char(row, 0, b'a' + row as u8, writer.color_code());
+        }
+    });
+    assert!(writer.commit_chunk(generation, ROWS_PER_CHUNK));
+    // 超过一屏、没有换行符的字符串走 write_screenful 的快速路径
+    let long: String = (0..BUFFER_WIDTH * BUFFER_HEIGHT + 1)
+        .map(|i| (b'0' + (i % 10) as u8) as char)
+        .collect();
+    writer.write_string(&long);
+    while writer.commit_chunk(generation, ROWS_PER_CHUNK) {}
+    assert!(!writer.redraw_pending());
+    // 已经提交过的前两行显示新的文字，而不是重绘的内容
+    assert_eq!(shown(&writer, 0, 0), b'0');
+    assert_eq!(shown(&writer, 1, 3), b'3');
+    assert_eq!(shown(&writer, BUFFER_HEIGHT - 1, 0), b'0');
+}
+
+#[test_case]
+fn test_output_during_redraw_lands_after_it() {
+    let mut writer = TestWriter::with_draw_buffer();
+    writer.write_string("old");
+    let generation = writer.begin_redraw(|writer| {
+        writer.clear_screen();
+        writer.write_string("new");
+    });
+    assert!(writer.commit_chunk(generation, ROWS_PER_CHUNK));
+    // 段之间到来的输出先让重绘完成，然后写在重绘的画面之后
+    writer.write_string(" late");
+    assert!(!writer.redraw_pending());
+    let row: alloc::string::String = (0..8)
+        .map(|col| shown(&writer, BUFFER_HEIGHT - 1, col) as char)
+        .collect();
+    assert_eq!(row, "new late");
+    // 原来的提交循环发现重绘已经完成
+    assert!(!writer.commit_chunk(generation, ROWS_PER_CHUNK));
+}
+
+#[test_case]
+fn test_newer_redraw_supersedes_older() {
+    let mut writer = TestWriter::with_draw_buffer();
+    let first = writer.begin_redraw(|writer| writer.put_char(0, 0, b'1', writer.color_code()));
+    assert!(writer.commit_chunk(first, 1));
+    let second = writer.begin_redraw(|writer| writer.put_char(5, 0, b'2', writer.color_code()));
+    assert_ne!(first, second);
+    // 第一次重绘在第二次开始前已经提交完
+    assert_eq!(shown(&writer, 0, 0), b'1');
+    assert!(!writer.commit_chunk(first, ROWS_PER_CHUNK));
+    assert_eq!(shown(&writer, 5, 0), b' ');
+    writer.finish_redraw();
+    assert_eq!(shown(&writer, 5, 0), b'2');
+    assert_eq!(writer.frames_presented(), 2);
+
+    // 没有后备缓冲区时直接写显存
+    let mut plain = TestWriter::new();
+    let generation = plain.begin_redraw(|writer| writer.put_char(0, 0, b'x', writer.color_code()));
+    assert!(!plain.redraw_pending());
+    assert!(!plain.commit_chunk(generation, ROWS_PER_CHUNK));
+    assert_eq!(shown(&plain, 0, 0), b'x');
+}
+
+#[test_case]
+fn test_cell_writes_during_redraw_are_kept() {
+    use super::{Cell, ScreenChar};
+
+    let mut writer = TestWriter::with_draw_buffer();
+    let generation = writer.begin_redraw(|writer| writer.write_string("new"));
+    assert!(writer.commit_chunk(generation, ROWS_PER_CHUNK));
+    // 状态栏写第 0 行、鼠标指针写第 1 行，这两行已经提交过
+    let color = writer.color_code();
+    writer.write_fmt_at(0, 0, format_args!("bar"));
+    assert!(!writer.redraw_pending());
+    writer
+        .try_put(Cell { row: 1, col: 0 }, ScreenChar::new(b'^', color))
+        .unwrap();
+    assert_eq!(shown(&writer, 0, 0), b'b');
+    assert_eq!(shown(&writer, 1, 0), b'^');
+    assert_eq!(shown(&writer, BUFFER_HEIGHT - 1, 0), b'n');
+
+    let generation = writer.begin_redraw(|writer| writer.write_string("!"));
+    assert!(writer.commit_chunk(generation, ROWS_PER_CHUNK));
+    writer.put_char(0, 1, b'A', color);
+    assert!(!writer.redraw_pending());
+    assert_eq!(shown(&writer, 0, 1), b'A');
+    assert_eq!(shown(&writer, BUFFER_HEIGHT - 1, 3), b'!');
+}
+
+/// 在 WRITER 上启用后备缓冲区，反复重绘整个屏幕：分段提交时关中断最长的一段比一次提交完短。
+/// 两种方式各取多轮中最短的一轮，排除模拟器偶尔停顿造成的误差
+#[test_case]
+fn test_chunked_redraw_shortens_locked_sections() {
+    use crate::time::rdtsc;
+    use alloc::boxed::Box;
+
+    const ROUNDS: usize = 20;
+    // 持有锁、关闭中断执行 f，返回用了多少个 TSC 周期和 f 的结果
+    fn locked<R>(f: impl FnOnce(&mut Writer) -> R) -> (u64, R) {
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let start = rdtsc();
+            let result = f(&mut writer);
+            (rdtsc().wrapping_sub(start), result)
+        })
+    }
+    let snapshot = interrupts::without_interrupts(|| WRITER.lock().snapshot());
+    let spare = Box::leak(Box::new(Buffer::new()));
+    interrupts::without_interrupts(|| WRITER.lock().enable_draw_buffer(spare));
+
+    let (mut whole, mut chunked) = (u64::MAX, u64::MAX);
+    for _ in 0..ROUNDS {
+        let (cycles, _) = locked(|writer| {
+            writer.begin_redraw(|writer| writer.restore(&snapshot));
+            writer.finish_redraw();
+        });
+        whole = whole.min(cycles);
+
+        let (mut longest, generation) =
+            locked(|writer| writer.begin_redraw(|writer| writer.restore(&snapshot)));
+        loop {
+            let (cycles, more) = locked(|writer| writer.commit_chunk(generation, ROWS_PER_CHUNK));
+            longest = longest.max(cycles);
+            if !more {
+                break;
+            }
+        }
+        chunked = chunked.min(longest);
+    }
+
+    let spare = interrupts::without_interrupts(|| WRITER.lock().draw_buffer.take()).unwrap();
+    drop(unsafe { Box::from_raw(spare as *mut Buffer) });
+    // 一次提交完比分段时最长的一段多复制了整个屏幕
+    assert!(
+        chunked < whole,
+        "longest chunked section {} cycles, whole redraw {}",
+        chunked,
+        whole
+    );
+}
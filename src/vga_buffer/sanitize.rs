@@ -0,0 +1,486 @@
+//! 接管引导程序留下的屏幕和显卡状态
+//! 有的 BIOS 和引导程序交出控制权时屏幕上还留着文字、硬件光标停在屏幕中间，偶尔属性控制器也处在
+//! 奇怪的状态（闪烁位不对、颜色平面被关掉），或者 CRTC 的起始地址不为 0（画面被平移），
+//! 内核最初的输出看起来是乱的。sanitize_hardware_state 逐项修正：
+//!
+//! - ATTRIBUTE：按标准文本模式重写属性控制器，闪烁位保持原样，由 BLINK 处理
+//! - BLINK：属性字节的 bit 7 作为闪烁位，与 ColorCode::decode 的假设一致
+//! - CURSOR：读出硬件光标的位置，与屏幕上最后一个字符一起决定输出从哪一行开始
+//! - START_ADDRESS：CRTC 的起始地址和预置行扫描清零
+//!
+//! 每一项都可以用启动参数 novgafix 跳过，便于排查是哪一项出了问题。
+//! 屏幕上原有的内容按 screen=preserve|clear 保留在输出的上方或者清除。
+//!
+//! 光标的处理发生在 WRITER 初始化时，所以要在第一次使用 WRITER 之前调用 sanitize_hardware_state；
+//! 没有调用时 WRITER 使用默认设置：保留原有的内容，读取光标
+use super::snapshot::save_screen;
+use super::{vga_available, ScreenChar, ScreenRow, Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+use crate::vga_mode::{
+    ATTRIBUTE_INDEX, ATTRIBUTE_PAS, ATTRIBUTE_READ, INPUT_STATUS_1, MISC_OUTPUT_READ,
+    TEXT_ATTRIBUTE,
+};
+use core::sync::atomic::{AtomicU8, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+/// 属性控制器的模式控制寄存器
+const ATTRIBUTE_MODE: u8 = 0x10;
+/// 模式控制寄存器的 bit 3：1 为闪烁，0 为高亮背景
+const BLINK_BIT: u8 = 0x08;
+/// 我们选择的闪烁位，标准文本模式的默认值
+const BLINK_DEFAULT: bool = true;
+
+const CRTC_PRESET_ROW_SCAN: u8 = 0x08;
+const CRTC_START_HIGH: u8 = 0x0C;
+const CRTC_START_LOW: u8 = 0x0D;
+const CRTC_CURSOR_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOW: u8 = 0x0F;
+
+/// 屏幕上原有的内容怎么处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InheritedScreen {
+    /// 保留在上方，输出从它下面的一行开始
+    Preserve = 0,
+    Clear = 1,
+}
+
+impl InheritedScreen {
+    pub fn parse(value: &str) -> Option<InheritedScreen> {
+        match value {
+            "preserve" => Some(InheritedScreen::Preserve),
+            "clear" => Some(InheritedScreen::Clear),
+            _ => None,
+        }
+    }
+}
+
+/// 要执行的修正，见模块文档
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixes(u8);
+
+impl Fixes {
+    pub const NONE: Fixes = Fixes(0);
+    pub const ATTRIBUTE: Fixes = Fixes(1 << 0);
+    pub const BLINK: Fixes = Fixes(1 << 1);
+    pub const CURSOR: Fixes = Fixes(1 << 2);
+    pub const START_ADDRESS: Fixes = Fixes(1 << 3);
+    pub const ALL: Fixes = Fixes(0x0F);
+
+    pub const fn contains(self, other: Fixes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Fixes) -> Fixes {
+        Fixes(self.0 | other.0)
+    }
+
+    pub const fn without(self, other: Fixes) -> Fixes {
+        Fixes(self.0 & !other.0)
+    }
+
+    /// 解析逗号分隔的名称：attr、blink、cursor、start 或者 all
+    pub fn parse_list(list: &str) -> Option<Fixes> {
+        list.split(',').try_fold(Fixes::NONE, |fixes, name| {
+            let fix = match name {
+                "attr" => Fixes::ATTRIBUTE,
+                "blink" => Fixes::BLINK,
+                "cursor" => Fixes::CURSOR,
+                "start" => Fixes::START_ADDRESS,
+                "all" => Fixes::ALL,
+                _ => return None,
+            };
+            Some(fixes.union(fix))
+        })
+    }
+}
+
+static SCREEN: AtomicU8 = AtomicU8::new(InheritedScreen::Preserve as u8);
+static FIXES: AtomicU8 = AtomicU8::new(Fixes::ALL.0);
+
+/// 访问 VGA 的 I/O 端口，抽象出来以便测试
+pub trait VgaPorts {
+    fn read(&mut self, port: u16) -> u8;
+    fn write(&mut self, port: u16, value: u8);
+}
+
+struct HardwarePorts;
+
+impl VgaPorts for HardwarePorts {
+    fn read(&mut self, port: u16) -> u8 {
+        unsafe { Port::new(port).read() }
+    }
+
+    fn write(&mut self, port: u16, value: u8) {
+        unsafe { Port::new(port).write(value) }
+    }
+}
+
+/// 按 screen 处理原有的内容并执行 fixes 中的修正，由控制台初始化（config::init）调用
+pub fn sanitize_hardware_state(screen: InheritedScreen, fixes: Fixes) {
+    SCREEN.store(screen as u8, Ordering::Relaxed);
+    FIXES.store(fixes.0, Ordering::Relaxed);
+    // 第一次使用 WRITER，按上面的设置接管画面
+    if !vga_available() {
+        return;
+    }
+    interrupts::without_interrupts(|| apply_register_fixes(&mut HardwarePorts, fixes));
+}
+
+/// 由 WRITER 的初始化调用，只在探测到 VGA 时调用
+pub(super) fn adopt_inherited_screen(writer: &mut Writer) {
+    let screen = match SCREEN.load(Ordering::Relaxed) {
+        0 => InheritedScreen::Preserve,
+        _ => InheritedScreen::Clear,
+    };
+    let fixes = Fixes(FIXES.load(Ordering::Relaxed));
+    let cursor = fixes
+        .contains(Fixes::CURSOR)
+        .then(|| cursor_position(&mut HardwarePorts));
+    writer.adopt(screen, cursor);
+}
+
+fn apply_register_fixes(ports: &mut impl VgaPorts, fixes: Fixes) {
+    if fixes.contains(Fixes::ATTRIBUTE) {
+        reprogram_attribute(ports);
+    }
+    if fixes.contains(Fixes::BLINK) {
+        set_blink(ports, BLINK_DEFAULT);
+    }
+    if fixes.contains(Fixes::START_ADDRESS) {
+        reset_start_address(ports);
+    }
+}
+
+/// 杂项输出寄存器的 bit 0 选择 CRTC 的索引端口
+fn crtc_port(ports: &mut impl VgaPorts) -> u16 {
+    if ports.read(MISC_OUTPUT_READ) & 0x01 != 0 {
+        0x3D4
+    } else {
+        0x3B4
+    }
+}
+
+fn read_crtc(ports: &mut impl VgaPorts, crtc: u16, index: u8) -> u8 {
+    ports.write(crtc, index);
+    ports.read(crtc + 1)
+}
+
+fn write_crtc(ports: &mut impl VgaPorts, crtc: u16, index: u8, value: u8) {
+    ports.write(crtc, index);
+    ports.write(crtc + 1, value);
+}
+
+/// 读属性控制器的寄存器，写索引时置位 PAS，不会关闭屏幕
+fn read_attribute(ports: &mut impl VgaPorts, index: u8) -> u8 {
+    ports.read(INPUT_STATUS_1);
+    ports.write(ATTRIBUTE_INDEX, index | ATTRIBUTE_PAS);
+    let value = ports.read(ATTRIBUTE_READ);
+    ports.read(INPUT_STATUS_1);
+    value
+}
+
+/// 重写全部 21 个寄存器，模式控制寄存器的闪烁位保持原样。
+/// 调色板寄存器只能在 PAS 清零（屏幕关闭）时写入，写完再置位 PAS 打开屏幕
+fn reprogram_attribute(ports: &mut impl VgaPorts) {
+    let blink = read_attribute(ports, ATTRIBUTE_MODE) & BLINK_BIT;
+    let mut table = TEXT_ATTRIBUTE;
+    table[ATTRIBUTE_MODE as usize] = table[ATTRIBUTE_MODE as usize] & !BLINK_BIT | blink;
+    for (index, &value) in table.iter().enumerate() {
+        ports.read(INPUT_STATUS_1);
+        ports.write(ATTRIBUTE_INDEX, index as u8);
+        ports.write(ATTRIBUTE_INDEX, value);
+    }
+    ports.read(INPUT_STATUS_1);
+    ports.write(ATTRIBUTE_INDEX, ATTRIBUTE_PAS);
+}
+
+/// 只改模式控制寄存器的闪烁位
+fn set_blink(ports: &mut impl VgaPorts, enabled: bool) {
+    let mode = read_attribute(ports, ATTRIBUTE_MODE) & !BLINK_BIT;
+    let mode = if enabled { mode | BLINK_BIT } else { mode };
+    ports.write(ATTRIBUTE_INDEX, ATTRIBUTE_MODE | ATTRIBUTE_PAS);
+    ports.write(ATTRIBUTE_INDEX, mode);
+}
+
+/// 显示从显存开头开始，预置行扫描（包括字节平移）清零
+fn reset_start_address(ports: &mut impl VgaPorts) {
+    let crtc = crtc_port(ports);
+    write_crtc(ports, crtc, CRTC_START_HIGH, 0);
+    write_crtc(ports, crtc, CRTC_START_LOW, 0);
+    write_crtc(ports, crtc, CRTC_PRESET_ROW_SCAN, 0);
+}
+
+/// 硬件光标的 (行, 列)，不在屏幕内时返回 None
+fn cursor_position(ports: &mut impl VgaPorts) -> Option<(usize, usize)> {
+    let crtc = crtc_port(ports);
+    let high = read_crtc(ports, crtc, CRTC_CURSOR_HIGH) as usize;
+    let low = read_crtc(ports, crtc, CRTC_CURSOR_LOW) as usize;
+    let offset = high << 8 | low;
+    (offset < BUFFER_HEIGHT * BUFFER_WIDTH)
+        .then_some((offset / BUFFER_WIDTH, offset % BUFFER_WIDTH))
+}
+
+/// 输出从哪一行开始：光标和最后一个字符之后（取较晚的一个）的第一个整行，
+/// 两者都在某一行的行首时就是那一行。结果可能是 BUFFER_HEIGHT，表示需要多滚动一行
+fn start_row(cursor: Option<(usize, usize)>, text_end: Option<(usize, usize)>) -> usize {
+    match cursor.max(text_end) {
+        Some((row, 0)) => row,
+        Some((row, _)) => row + 1,
+        None => 0,
+    }
+}
+
+impl Writer {
+    /// 最后一个非空字符之后的位置，列可以等于 BUFFER_WIDTH；空格和 NUL 都算空
+    fn text_end(&self) -> Option<(usize, usize)> {
+        (0..BUFFER_HEIGHT * BUFFER_WIDTH).rev().find_map(|index| {
+            let (row, col) = (index / BUFFER_WIDTH, index % BUFFER_WIDTH);
+            let character = self.buffer.chars[row][col].read().ascii_character;
+            (!matches!(character, b' ' | 0)).then_some((row, col + 1))
+        })
+    }
+
+    /// 按 screen 处理屏幕上原有的内容。cursor 为 None 表示没有读取光标，
+    /// 这时与 sync_from_buffer 相同，接着最后一个字符写；Some(None) 表示光标不在屏幕内
+    fn adopt(&mut self, screen: InheritedScreen, cursor: Option<Option<(usize, usize)>>) {
+        match (screen, cursor) {
+            (InheritedScreen::Clear, _) => {
+                for row in 0..BUFFER_HEIGHT {
+                    self.fill_row(row, self.color_code);
+                }
+                self.column_position = 0;
+            }
+            (InheritedScreen::Preserve, None) => self.sync_from_buffer(),
+            (InheritedScreen::Preserve, Some(cursor)) => {
+                let start = start_row(cursor, self.text_end());
+                self.keep_rows_above(start);
+            }
+        }
+    }
+
+    /// 把前 start 行移到最后一行的上方，之后的行被丢弃，光标在最后一行行首
+    fn keep_rows_above(&mut self, start: usize) {
+        let blank = ScreenChar::new(b' ', self.color_code);
+        let mut saved: [ScreenRow; BUFFER_HEIGHT] = [[blank; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        save_screen(self.buffer, &mut saved);
+        // start 行移到最后一行，可能是 -1（多滚动一行）
+        let offset = (BUFFER_HEIGHT - 1) as isize - start as isize;
+        for (row, target) in self.buffer.chars.iter_mut().enumerate() {
+            let source = row as isize - offset;
+            let keep = (0..start as isize).contains(&source);
+            for (col, cell) in target.iter_mut().enumerate() {
+                cell.write(if keep {
+                    saved[source as usize][col]
+                } else {
+                    blank
+                });
+            }
+        }
+        self.column_position = 0;
+    }
+}
+
+/// 按 VGA 的索引寄存器语义模拟端口，记录所有的写入
+#[cfg(test)]
+struct MockPorts {
+    misc: u8,
+    crtc: [u8; 0x19],
+    crtc_index: usize,
+    attribute: [u8; 21],
+    attribute_index: usize,
+    /// 属性控制器的触发器处在 "数据" 状态
+    attribute_data: bool,
+    writes: alloc::vec::Vec<(u16, u8)>,
+}
+
+#[cfg(test)]
+impl MockPorts {
+    fn new(misc: u8) -> Self {
+        MockPorts {
+            misc,
+            crtc: [0; 0x19],
+            crtc_index: 0,
+            attribute: [0; 21],
+            attribute_index: 0,
+            attribute_data: false,
+            writes: alloc::vec::Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl VgaPorts for MockPorts {
+    fn read(&mut self, port: u16) -> u8 {
+        match port {
+            MISC_OUTPUT_READ => self.misc,
+            0x3B5 | 0x3D5 => self.crtc[self.crtc_index],
+            INPUT_STATUS_1 => {
+                self.attribute_data = false;
+                0
+            }
+            ATTRIBUTE_READ => self.attribute[self.attribute_index],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, port: u16, value: u8) {
+        self.writes.push((port, value));
+        match port {
+            0x3B4 | 0x3D4 => self.crtc_index = value as usize,
+            0x3B5 | 0x3D5 => self.crtc[self.crtc_index] = value,
+            ATTRIBUTE_INDEX if self.attribute_data => {
+                self.attribute[self.attribute_index] = value;
+                self.attribute_data = false;
+            }
+            ATTRIBUTE_INDEX => {
+                self.attribute_index = (value & 0x1F) as usize;
+                self.attribute_data = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+use super::{Color, ColorCode, TestWriter};
+
+#[test_case]
+fn test_start_row() {
+    // 光标在最后一个字符之后的空行上
+    assert_eq!(start_row(Some((5, 0)), Some((2, 7))), 5);
+    // 光标停在文字中间，不覆盖它后面的文字
+    assert_eq!(start_row(Some((1, 3)), Some((2, 7))), 3);
+    // 行首的光标就是那一行
+    assert_eq!(start_row(Some((4, 0)), None), 4);
+    assert_eq!(start_row(None, Some((0, 1))), 1);
+    assert_eq!(start_row(None, None), 0);
+    // 最后一行有文字时需要多滚动一行
+    assert_eq!(start_row(Some((BUFFER_HEIGHT - 1, 2)), None), BUFFER_HEIGHT);
+}
+
+#[test_case]
+fn test_adopt_preserve_and_clear() {
+    let green = ColorCode::new(Color::Green, Color::Black);
+    let setup = || {
+        let mut writer = TestWriter::new();
+        writer.put_char(2, 0, b'A', green);
+        writer.put_char(3, 4, b'B', green);
+        writer
+    };
+
+    // 光标在第 6 行行首：第 0-5 行移到最后一行的上方
+    let mut writer = setup();
+    writer.adopt(InheritedScreen::Preserve, Some(Some((6, 0))));
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 5, 0), (b'A', green));
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 4, 4), (b'B', green));
+    assert_eq!(writer.column(), 0);
+
+    // 光标不在屏幕内：接着最后一个字符之后的一行
+    let mut writer = setup();
+    writer.adopt(InheritedScreen::Preserve, Some(None));
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 4).0, b'B');
+
+    // 没有读取光标：与 sync_from_buffer 相同，接着 B 写
+    let mut writer = setup();
+    writer.adopt(InheritedScreen::Preserve, None);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 4).0, b'B');
+    assert_eq!(writer.column(), 5);
+
+    // 最后一行有文字：整体上移一行，第 0 行被丢弃
+    let mut writer = setup();
+    writer.put_char(0, 0, b'T', green);
+    writer.put_char(BUFFER_HEIGHT - 1, 0, b'Z', green);
+    writer.adopt(
+        InheritedScreen::Preserve,
+        Some(Some((BUFFER_HEIGHT - 1, 1))),
+    );
+    assert_eq!(writer.read_char(1, 0).0, b'A');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0).0, b'Z');
+    assert!((0..BUFFER_HEIGHT).all(|row| writer.read_char(row, 0).0 != b'T'));
+
+    let mut writer = setup();
+    writer.adopt(InheritedScreen::Clear, Some(Some((6, 0))));
+    assert_eq!(writer.text_end(), None);
+}
+
+#[test_case]
+fn test_cursor_position_and_start_address() {
+    // 彩色：CRTC 在 0x3D4
+    let mut ports = MockPorts::new(0x67);
+    let offset = 7 * BUFFER_WIDTH + 12;
+    ports.crtc[CRTC_CURSOR_HIGH as usize] = (offset >> 8) as u8;
+    ports.crtc[CRTC_CURSOR_LOW as usize] = offset as u8;
+    assert_eq!(cursor_position(&mut ports), Some((7, 12)));
+    ports.crtc[CRTC_CURSOR_HIGH as usize] = 0xFF;
+    assert_eq!(cursor_position(&mut ports), None);
+
+    // 单色：CRTC 在 0x3B4
+    let mut ports = MockPorts::new(0x66);
+    ports.crtc[CRTC_START_HIGH as usize] = 0x07;
+    ports.crtc[CRTC_START_LOW as usize] = 0xD0;
+    ports.crtc[CRTC_PRESET_ROW_SCAN as usize] = 0x23;
+    reset_start_address(&mut ports);
+    assert_eq!(
+        ports.writes,
+        [
+            (0x3B4, CRTC_START_HIGH),
+            (0x3B5, 0),
+            (0x3B4, CRTC_START_LOW),
+            (0x3B5, 0),
+            (0x3B4, CRTC_PRESET_ROW_SCAN),
+            (0x3B5, 0)
+        ]
+    );
+}
+
+#[test_case]
+fn test_attribute_fixes() {
+    // 闪烁关闭、颜色平面被关掉、画面被平移
+    let mut ports = MockPorts::new(0x67);
+    ports.attribute[ATTRIBUTE_MODE as usize] = 0x04;
+    ports.attribute[0x12] = 0x00;
+    ports.attribute[0x13] = 0x03;
+    apply_register_fixes(&mut ports, Fixes::ATTRIBUTE);
+    let mut expected = TEXT_ATTRIBUTE;
+    expected[ATTRIBUTE_MODE as usize] &= !BLINK_BIT;
+    assert_eq!(ports.attribute, expected);
+    // 最后一次写入置位 PAS 打开屏幕
+    assert_eq!(ports.writes.last(), Some(&(ATTRIBUTE_INDEX, ATTRIBUTE_PAS)));
+
+    ports.writes.clear();
+    apply_register_fixes(&mut ports, Fixes::BLINK);
+    assert_eq!(ports.attribute, TEXT_ATTRIBUTE);
+    assert_eq!(
+        ports.writes,
+        [
+            (ATTRIBUTE_INDEX, ATTRIBUTE_MODE | ATTRIBUTE_PAS),
+            (ATTRIBUTE_INDEX, ATTRIBUTE_MODE | ATTRIBUTE_PAS),
+            (ATTRIBUTE_INDEX, TEXT_ATTRIBUTE[ATTRIBUTE_MODE as usize])
+        ]
+    );
+
+    // 跳过的修正不访问端口
+    ports.writes.clear();
+    apply_register_fixes(&mut ports, Fixes::CURSOR);
+    assert!(ports.writes.is_empty());
+}
+
+#[test_case]
+fn test_parse_fix_list() {
+    assert_eq!(Fixes::parse_list("all"), Some(Fixes::ALL));
+    let fixes = Fixes::parse_list("blink,start").unwrap();
+    assert!(fixes.contains(Fixes::BLINK) && fixes.contains(Fixes::START_ADDRESS));
+    assert!(!fixes.contains(Fixes::ATTRIBUTE));
+    assert_eq!(
+        Fixes::ALL.without(fixes),
+        Fixes::ATTRIBUTE.union(Fixes::CURSOR)
+    );
+    assert_eq!(Fixes::parse_list("blink,,start"), None);
+    assert_eq!(Fixes::parse_list("palette"), None);
+    assert_eq!(
+        InheritedScreen::parse("clear"),
+        Some(InheritedScreen::Clear)
+    );
+    assert_eq!(InheritedScreen::parse("keep"), None);
+}
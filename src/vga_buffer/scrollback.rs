@@ -0,0 +1,417 @@
+//! 回滚缓冲区
+//! new_line 把滚出屏幕顶端的行保存到固定容量的环形缓冲区里，满了以后覆盖最旧的行。
+//!
+//! 向上回滚时先把实时画面复制到备用缓冲区，再和 Writer 的缓冲区互换：
+//! 之后的输出照常写进（已经离屏的）实时画面，显存只用来显示历史内容。
+//! 回到底部时把实时画面复制回显存并换回来。
+//! 默认有新输出时自动回到底部，关闭后视图保持在原处，右上角的 "SCROLL (n)" 提示当前离底部的行数
+use super::{copy_rows, Buffer, Color, ColorCode, ScreenChar, Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+use core::mem;
+
+/// WRITER 保存的历史行数
+pub const SCROLLBACK_LINES: usize = 100;
+
+pub type ScreenRow = [ScreenChar; BUFFER_WIDTH];
+
+pub(super) struct Scrollback {
+    /// 环形缓冲区，start 是最旧的一行
+    lines: &'static mut [ScreenRow],
+    start: usize,
+    len: usize,
+    /// 视图底部离实时画面底部的行数，0 表示没有回滚
+    offset: usize,
+    /// 没有回滚时是空闲的离屏缓冲区，回滚时是真正显示出来的缓冲区
+    display: &'static mut Buffer,
+    snap_on_output: bool,
+}
+
+impl Scrollback {
+    fn push(&mut self, row: ScreenRow) {
+        let capacity = self.lines.len();
+        if capacity == 0 {
+            return;
+        }
+        let index = (self.start + self.len) % capacity;
+        self.lines[index] = row;
+        if self.len == capacity {
+            self.start = (self.start + 1) % capacity;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<ScreenRow> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.lines[(self.start + self.len) % self.lines.len()])
+    }
+
+    /// 第 index 行历史，0 是最旧的一行
+    fn line(&self, index: usize) -> &ScreenRow {
+        &self.lines[(self.start + index) % self.lines.len()]
+    }
+}
+
+impl Writer {
+    /// 启用回滚缓冲区，history 的长度就是能保存的行数，spare 用于在回滚时保存实时画面
+    pub fn enable_scrollback(
+        &mut self,
+        history: &'static mut [ScreenRow],
+        spare: &'static mut Buffer,
+    ) {
+        self.scrollback = Some(Scrollback {
+            lines: history,
+            start: 0,
+            len: 0,
+            offset: 0,
+            display: spare,
+            snap_on_output: true,
+        });
+    }
+
+    /// 有新输出时是否自动回到底部，默认开启
+    pub fn set_snap_on_output(&mut self, on: bool) {
+        if let Some(scrollback) = &mut self.scrollback {
+            scrollback.snap_on_output = on;
+        }
+    }
+
+    pub fn snap_on_output(&self) -> bool {
+        self.scrollback
+            .as_ref()
+            .is_some_and(|scrollback| scrollback.snap_on_output)
+    }
+
+    /// 已保存的历史行数
+    pub fn history_len(&self) -> usize {
+        self.scrollback
+            .as_ref()
+            .map_or(0, |scrollback| scrollback.len)
+    }
+
+    /// 视图离底部的行数，0 表示正在显示实时画面
+    pub fn scroll_offset(&self) -> usize {
+        self.scrollback
+            .as_ref()
+            .map_or(0, |scrollback| scrollback.offset)
+    }
+
+    /// 滚动区域当前显示的行范围，行号从最旧的一行历史开始计数，
+    /// 实时画面中滚动区域的第一行（scroll_top）是 history_len()
+    pub fn visible_lines(&self) -> core::ops::Range<usize> {
+        let height = BUFFER_HEIGHT - self.scroll_top;
+        let bottom = self.history_len() + height - self.scroll_offset();
+        bottom - height..bottom
+    }
+
+    /// 向上回滚 lines 行，最多回滚到最旧的历史，没有历史时什么也不做
+    pub fn scroll_view_up(&mut self, lines: usize) {
+        let offset = self.scroll_offset().saturating_add(lines);
+        self.set_scroll_offset(offset);
+    }
+
+    pub fn scroll_view_down(&mut self, lines: usize) {
+        let offset = self.scroll_offset().saturating_sub(lines);
+        self.set_scroll_offset(offset);
+    }
+
+    pub fn snap_to_bottom(&mut self) {
+        self.set_scroll_offset(0);
+    }
+
+    /// 暂时摘下回滚缓冲区执行 f，期间滚出屏幕的行不进入历史，退格也不会取回历史行
+    /// 调用前视图应当在底部
+    pub fn without_scrollback<R>(&mut self, f: impl FnOnce(&mut Writer) -> R) -> R {
+        let scrollback = self.scrollback.take();
+        let result = f(self);
+        self.scrollback = scrollback;
+        result
+    }
+
+    /// 读取屏幕上实际显示的字符，回滚时是历史内容，否则与 read_char 相同
+    pub fn read_visible_char(&self, row: usize, col: usize) -> (u8, ColorCode) {
+        let buffer = match &self.scrollback {
+            Some(scrollback) if scrollback.offset > 0 => &*scrollback.display,
+            _ => &*self.buffer,
+        };
+        let screen_char = buffer.chars[row][col].read();
+        (screen_char.ascii_character, screen_char.color_code)
+    }
+
+    fn set_scroll_offset(&mut self, offset: usize) {
+        self.hide_soft_cursor();
+        let Some(scrollback) = &mut self.scrollback else {
+            return;
+        };
+        let offset = offset.min(scrollback.len);
+        let old = mem::replace(&mut scrollback.offset, offset);
+        match (old, offset) {
+            (0, 0) => {}
+            (0, _) => {
+                // 进入回滚：实时画面移到离屏缓冲区，显存交给视图
+                copy_rows(&*self.buffer, scrollback.display, 0..BUFFER_HEIGHT);
+                mem::swap(&mut self.buffer, &mut scrollback.display);
+                self.redraw_view();
+            }
+            (_, 0) => {
+                copy_rows(&*self.buffer, scrollback.display, 0..BUFFER_HEIGHT);
+                mem::swap(&mut self.buffer, &mut scrollback.display);
+            }
+            _ => self.redraw_view(),
+        }
+    }
+
+    /// 写入之前调用：提交完正在进行的重绘，恢复软件光标下的单元格和高亮前的颜色，需要时回到底部
+    pub(super) fn before_output(&mut self) {
+        self.finish_redraw();
+        self.hide_soft_cursor();
+        self.clear_highlight();
+        if let Some(scrollback) = &self.scrollback {
+            if scrollback.offset > 0 && scrollback.snap_on_output {
+                self.snap_to_bottom();
+            }
+        }
+    }
+
+    /// scroll_up 滚动之前调用，从上到下保存即将滚出屏幕的 count 行
+    /// 回滚时视图跟着向上移，保持显示的内容不变
+    pub(super) fn save_top_rows(&mut self, count: usize) {
+        let Some(scrollback) = &mut self.scrollback else {
+            return;
+        };
+        let top = self.scroll_top;
+        for row in top..top + count {
+            let line = core::array::from_fn(|col| self.buffer.chars[row][col].read());
+            // 历史已满时最旧的一行被覆盖，视图会随之移动一行
+            let was_full = scrollback.len == scrollback.lines.len();
+            scrollback.push(line);
+            if scrollback.offset > 0 && !was_full {
+                scrollback.offset += 1;
+            }
+        }
+    }
+
+    /// backspace 把屏幕下移之后调用，把最近保存的一行放回滚动区域的第一行，没有历史时返回 false
+    pub(super) fn restore_top_row(&mut self) -> bool {
+        let Some(row) = self.scrollback.as_mut().and_then(Scrollback::pop) else {
+            return false;
+        };
+        for (col, character) in row.iter().enumerate() {
+            self.buffer.chars[self.scroll_top][col].write(*character);
+        }
+        true
+    }
+
+    /// 回滚时重新绘制视图，没有回滚时什么也不做
+    /// 只有滚动区域显示历史，scroll_top 之上的行照原样显示实时画面
+    pub(super) fn redraw_view(&mut self) {
+        let Some(scrollback) = &mut self.scrollback else {
+            return;
+        };
+        if scrollback.offset == 0 {
+            return;
+        }
+        let scroll_top = self.scroll_top;
+        for row in 0..scroll_top {
+            for col in 0..BUFFER_WIDTH {
+                scrollback.display.chars[row][col].write(self.buffer.chars[row][col].read());
+            }
+        }
+        let top = scrollback.len - scrollback.offset.min(scrollback.len);
+        for row in scroll_top..BUFFER_HEIGHT {
+            let line = top + row - scroll_top;
+            for col in 0..BUFFER_WIDTH {
+                let character = if line < scrollback.len {
+                    scrollback.line(line)[col]
+                } else {
+                    self.buffer.chars[scroll_top + line - scrollback.len][col].read()
+                };
+                scrollback.display.chars[row][col].write(character);
+            }
+        }
+        draw_indicator(scrollback.display, scroll_top, scrollback.offset);
+    }
+}
+
+/// 在滚动区域的右上角显示 "SCROLL (n)"
+fn draw_indicator(buffer: &mut Buffer, row: usize, offset: usize) {
+    let mut text = [b' '; 16];
+    let mut len = 0;
+    for &byte in b"SCROLL (" {
+        text[len] = byte;
+        len += 1;
+    }
+    let mut digits = [0u8; 20];
+    let mut count = 0;
+    let mut value = offset;
+    loop {
+        digits[count] = b'0' + (value % 10) as u8;
+        count += 1;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    for &digit in digits[..count].iter().rev() {
+        if len < text.len() - 1 {
+            text[len] = digit;
+            len += 1;
+        }
+    }
+    text[len] = b')';
+    len += 1;
+
+    let color_code = ColorCode::new(Color::Black, Color::LightGray);
+    for (i, &byte) in text[..len].iter().enumerate() {
+        buffer.chars[row][BUFFER_WIDTH - len + i].write(ScreenChar::new(byte, color_code));
+    }
+}
+
+/// 为 WRITER 启用回滚缓冲区，历史行分配在堆上，需要在堆初始化之后调用
+pub fn init_scrollback() {
+    use super::WRITER;
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use x86_64::instructions::interrupts;
+
+    let blank = ScreenChar::new(b' ', ColorCode::new(Color::Yellow, Color::Black));
+    let history = Box::leak(vec![[blank; BUFFER_WIDTH]; SCROLLBACK_LINES].into_boxed_slice());
+    let spare = Box::leak(Box::new(Buffer::new()));
+    interrupts::without_interrupts(|| WRITER.lock().enable_scrollback(history, spare));
+}
+
+#[cfg(test)]
+use super::TestWriter;
+
+/// 写入 count 行 "line N"，第 N 行历史的内容就是 "line N"
+#[cfg(test)]
+fn fill_lines(writer: &mut Writer, count: usize) {
+    use core::fmt::Write;
+
+    for line in 0..count {
+        writeln!(writer, "line {}", line).unwrap();
+    }
+}
+
+/// 读取屏幕上显示的某一行开头的数字（"line N" 中的 N）
+#[cfg(test)]
+fn visible_line_number(writer: &Writer, row: usize) -> Option<usize> {
+    let mut number = None;
+    for col in 5..BUFFER_WIDTH {
+        match writer.read_visible_char(row, col).0 {
+            digit @ b'0'..=b'9' => {
+                number = Some(number.unwrap_or(0) * 10 + (digit - b'0') as usize);
+            }
+            _ => break,
+        }
+    }
+    number
+}
+
+#[test_case]
+fn test_new_line_saves_scrolled_rows() {
+    let mut writer = TestWriter::with_scrollback(40);
+    fill_lines(&mut writer, 30);
+    // 每次换行保存一行：先是原来屏幕上的 24 个空行，然后是 line 0 到 line 5
+    assert_eq!(writer.history_len(), 30);
+    assert_eq!(visible_line_number(&writer, 0), Some(6));
+}
+
+#[test_case]
+fn test_scroll_view_shows_history() {
+    let mut writer = TestWriter::with_scrollback(40);
+    fill_lines(&mut writer, 30);
+    writer.scroll_view_up(4);
+    assert_eq!(writer.scroll_offset(), 4);
+    assert_eq!(writer.visible_lines(), 26..51);
+    assert_eq!(visible_line_number(&writer, 0), Some(2));
+    // 实时画面没有变化
+    assert_eq!(writer.read_char(0, 5).0, b'6');
+
+    // 回滚不超过最旧的历史
+    writer.scroll_view_up(100);
+    assert_eq!(writer.scroll_offset(), 30);
+    assert_eq!(visible_line_number(&writer, 0), None);
+    assert_eq!(visible_line_number(&writer, 24), Some(0));
+
+    writer.snap_to_bottom();
+    assert_eq!(writer.scroll_offset(), 0);
+    assert_eq!(visible_line_number(&writer, 0), Some(6));
+}
+
+#[test_case]
+fn test_scroll_without_history_is_noop() {
+    let mut writer = TestWriter::with_scrollback(40);
+    writer.write_string("hello");
+    writer.scroll_view_up(BUFFER_HEIGHT);
+    assert_eq!(writer.scroll_offset(), 0);
+    assert_eq!(writer.read_visible_char(BUFFER_HEIGHT - 1, 0).0, b'h');
+}
+
+#[test_case]
+fn test_output_snaps_back_by_default() {
+    let mut writer = TestWriter::with_scrollback(40);
+    fill_lines(&mut writer, 30);
+    writer.scroll_view_up(3);
+    writer.write_byte(b'x');
+    assert_eq!(writer.scroll_offset(), 0);
+    assert_eq!(writer.read_visible_char(BUFFER_HEIGHT - 1, 0).0, b'x');
+}
+
+#[test_case]
+fn test_new_lines_while_scrolled_keep_view_stable() {
+    let mut writer = TestWriter::with_scrollback(40);
+    writer.set_snap_on_output(false);
+    fill_lines(&mut writer, 30);
+    writer.scroll_view_up(3);
+    assert_eq!(visible_line_number(&writer, 0), Some(3));
+    fill_lines(&mut writer, 2);
+    // 视图停在原处，离底部更远了
+    assert_eq!(writer.scroll_offset(), 5);
+    assert_eq!(visible_line_number(&writer, 0), Some(3));
+    // 右上角的提示
+    let indicator: [u8; 10] =
+        core::array::from_fn(|i| writer.read_visible_char(0, BUFFER_WIDTH - 10 + i).0);
+    assert_eq!(&indicator, b"SCROLL (5)");
+
+    // 回到底部后能看到回滚期间的输出
+    writer.snap_to_bottom();
+    assert_eq!(writer.read_visible_char(BUFFER_HEIGHT - 2, 5).0, b'1');
+    assert_eq!(writer.read_visible_char(BUFFER_HEIGHT - 3, 5).0, b'0');
+}
+
+#[test_case]
+fn test_scroll_view_keeps_rows_above_scroll_top() {
+    let mut writer = TestWriter::with_scrollback(40);
+    writer.set_scroll_top(1);
+    writer.write_str_at(0, 0, "status", Color::Black, Color::White);
+    fill_lines(&mut writer, 30);
+    // 滚动区域有 24 行：历史是原来的 23 个空行和 line 0 到 line 6，实时画面从 line 7 开始
+    assert_eq!(writer.history_len(), 30);
+    assert_eq!(visible_line_number(&writer, 1), Some(7));
+
+    writer.scroll_view_up(4);
+    assert_eq!(writer.visible_lines(), 26..50);
+    assert_eq!(writer.read_visible_char(0, 0).0, b's');
+    assert_eq!(visible_line_number(&writer, 1), Some(3));
+    assert_eq!(visible_line_number(&writer, BUFFER_HEIGHT - 1), Some(26));
+    let indicator: [u8; 10] =
+        core::array::from_fn(|i| writer.read_visible_char(1, BUFFER_WIDTH - 10 + i).0);
+    assert_eq!(&indicator, b"SCROLL (4)");
+
+    writer.snap_to_bottom();
+    assert_eq!(writer.read_visible_char(0, 0).0, b's');
+    assert_eq!(visible_line_number(&writer, 1), Some(7));
+}
+
+#[test_case]
+fn test_history_is_bounded() {
+    let mut writer = TestWriter::with_scrollback(10);
+    fill_lines(&mut writer, 50);
+    assert_eq!(writer.history_len(), 10);
+    writer.scroll_view_up(100);
+    // 最旧的历史是第 50 - 24 - 10 = 16 行
+    assert_eq!(visible_line_number(&writer, 0), Some(16));
+}
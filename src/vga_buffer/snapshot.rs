@@ -0,0 +1,167 @@
+//! 屏幕快照
+//! 保存实时画面的内容、光标列和当前颜色，之后可以原样恢复，
+//! 用于临时占用屏幕的代码（例如启动自检）在结束后复原现场。
+//! 回滚历史、高亮等其他状态不在快照中。
+//! 属性字节原样保存和恢复，bit 7 是闪烁还是高亮背景由显示模式决定，恢复后的含义与切换后的模式一致
+use super::{Buffer, ColorCode, ScreenChar, ScreenRow, Writer, BUFFER_HEIGHT, BUFFER_WIDTH};
+
+#[derive(Clone)]
+pub struct Snapshot {
+    screen: [ScreenRow; BUFFER_HEIGHT],
+    column_position: usize,
+    color_code: ColorCode,
+}
+
+impl Snapshot {
+    /// 快照中的画面，按行排列
+    pub fn screen(&self) -> &[ScreenRow; BUFFER_HEIGHT] {
+        &self.screen
+    }
+}
+
+impl Writer {
+    /// 保存实时画面，回滚中也是保存实时画面而不是正在显示的历史
+    pub fn snapshot(&self) -> Snapshot {
+        let mut screen =
+            [[super::ScreenChar::new(b' ', self.color_code); super::BUFFER_WIDTH]; BUFFER_HEIGHT];
+        save_screen(self.buffer, &mut screen);
+        self.soft_cursor.unmask(&mut screen);
+        Snapshot {
+            screen,
+            column_position: self.column_position,
+            color_code: self.color_code,
+        }
+    }
+
+    /// 恢复快照时的画面、光标列和颜色
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.restore_clamped(snapshot, BUFFER_HEIGHT, BUFFER_WIDTH);
+    }
+
+    /// 与 restore 相同，但只保留前 rows 行、前 cols 列，其余单元格用快照的颜色清空，
+    /// 光标列也限制在 cols 以内
+    pub fn restore_clamped(&mut self, snapshot: &Snapshot, rows: usize, cols: usize) {
+        let blank = ScreenChar::new(b' ', snapshot.color_code);
+        let mut screen = snapshot.screen;
+        for (row, saved) in screen.iter_mut().enumerate() {
+            for (col, cell) in saved.iter_mut().enumerate() {
+                if row >= rows || col >= cols {
+                    *cell = blank;
+                }
+            }
+        }
+        self.hide_soft_cursor();
+        load_screen(&screen, self.buffer);
+        self.column_position = snapshot.column_position.min(cols);
+        self.color_code = snapshot.color_code;
+    }
+
+    /// 执行会破坏显存内容的 f（例如重新设置显示模式），成功后把之前的画面恢复到 rows 行 cols 列以内。
+    /// f 失败时画面不动
+    pub fn redraw_after<E>(
+        &mut self,
+        rows: usize,
+        cols: usize,
+        f: impl FnOnce(&mut Writer) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let snapshot = self.snapshot();
+        f(self)?;
+        self.restore_clamped(&snapshot, rows, cols);
+        Ok(())
+    }
+}
+
+pub(super) fn save_screen(buffer: &Buffer, screen: &mut [ScreenRow; BUFFER_HEIGHT]) {
+    for (row, saved) in screen.iter_mut().enumerate() {
+        for (col, cell) in saved.iter_mut().enumerate() {
+            *cell = buffer.chars[row][col].read();
+        }
+    }
+}
+
+pub(super) fn load_screen(screen: &[ScreenRow; BUFFER_HEIGHT], buffer: &mut Buffer) {
+    for (row, saved) in screen.iter().enumerate() {
+        for (col, &cell) in saved.iter().enumerate() {
+            buffer.chars[row][col].write(cell);
+        }
+    }
+}
+
+#[cfg(test)]
+use super::{Color, TestWriter};
+
+#[test_case]
+fn test_restore_snapshot() {
+    let mut writer = TestWriter::new();
+    writer.write_string("before");
+    let snapshot = writer.snapshot();
+
+    writer.set_color(Color::White, Color::Red);
+    for _ in 0..BUFFER_HEIGHT {
+        writer.write_string("noise\n");
+    }
+    writer.put_char(
+        0,
+        BUFFER_WIDTH - 1,
+        b'#',
+        ColorCode::new(Color::Blue, Color::Blue),
+    );
+
+    writer.restore(&snapshot);
+    assert_eq!(writer.read_char(0, BUFFER_WIDTH - 1).0, b' ');
+    writer.write_string("!");
+    let text: alloc::vec::Vec<u8> = (0..7)
+        .map(|col| writer.read_char(BUFFER_HEIGHT - 1, col).0)
+        .collect();
+    assert_eq!(text, b"before!");
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, 6).1,
+        ColorCode::new(Color::Yellow, Color::Black)
+    );
+}
+
+#[test_case]
+fn test_redraw_after_mode_switch() {
+    let row_text = |writer: &TestWriter, row, len| -> alloc::vec::Vec<u8> {
+        (0..len).map(|col| writer.read_char(row, col).0).collect()
+    };
+    let mut writer = TestWriter::new();
+    writer.put_char(0, 0, b'T', ColorCode::new(Color::White, Color::Red));
+    let color = writer.color_code();
+    writer.put_char(1, BUFFER_WIDTH - 1, b'R', color);
+    writer.write_string("top row survives");
+    // 切换模式时显存被清掉
+    let switched = writer.redraw_after(BUFFER_HEIGHT, BUFFER_WIDTH, |writer| {
+        writer.clear_screen();
+        Ok::<(), ()>(())
+    });
+    assert_eq!(switched, Ok(()));
+    assert_eq!(
+        writer.read_char(0, 0),
+        (b'T', ColorCode::new(Color::White, Color::Red))
+    );
+    assert_eq!(writer.read_char(1, BUFFER_WIDTH - 1).0, b'R');
+    assert_eq!(
+        row_text(&writer, BUFFER_HEIGHT - 1, 16),
+        b"top row survives"
+    );
+    assert_eq!(writer.column(), 16);
+
+    // 新的尺寸更小：超出的部分被丢弃，光标列被限制
+    writer
+        .redraw_after(1, 4, |writer| {
+            writer.clear_screen();
+            Ok::<(), ()>(())
+        })
+        .unwrap();
+    assert_eq!(writer.read_char(0, 0).0, b'T');
+    assert_eq!(writer.read_char(1, BUFFER_WIDTH - 1).0, b' ');
+    assert_eq!(row_text(&writer, BUFFER_HEIGHT - 1, 4), b"    ");
+    assert_eq!(writer.column(), 4);
+
+    // 切换失败时画面保持原样
+    writer.write_string("x");
+    let failed = writer.redraw_after(1, 1, |_| Err("unsupported"));
+    assert_eq!(failed, Err("unsupported"));
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 4).0, b'x');
+}
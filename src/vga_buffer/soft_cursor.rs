@@ -0,0 +1,173 @@
+//! 软件光标
+//! 不使用硬件光标的全屏界面可以打开软件光标：时钟中断每隔 rate 个节拍把光标所在单元格反色一次，
+//! 再隔 rate 个节拍恢复，形成闪烁。光标所在的单元格是最后一行的当前列。
+//!
+//! 反色时保存单元格原来的内容。Writer 写入、滚动、切换视图之前先恢复它，所以光标处新写入的字符
+//! 不会一直保持反色；绕过这些入口的写入（例如 put_char）改掉了这个单元格时，恢复时发现内容已经变化，
+//! 不再覆盖。快照中保存的是恢复后的内容。
+//!
+//! 时钟中断只尝试加锁，控制台正被占用时跳过这一次闪烁
+use super::{Buffer, ScreenChar, ScreenRow, Writer, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+use crate::time;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+/// 默认每 500 毫秒切换一次
+pub const DEFAULT_BLINK_TICKS: u64 = time::TIMER_FREQUENCY_HZ as u64 / 2;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BLINK_TICKS: AtomicU64 = AtomicU64::new(DEFAULT_BLINK_TICKS);
+
+/// 光标的显示状态，保存在 Writer 中
+#[derive(Debug, Default)]
+pub(super) struct SoftCursor {
+    /// 正在反色显示时：单元格的坐标和反色之前的内容
+    shown: Option<(usize, usize, ScreenChar)>,
+}
+
+impl SoftCursor {
+    /// 把快照中光标所在的单元格换回原来的内容
+    pub(super) fn unmask(&self, screen: &mut [ScreenRow; BUFFER_HEIGHT]) {
+        if let Some((row, col, saved)) = self.shown {
+            screen[row][col] = saved;
+        }
+    }
+}
+
+/// 单元格反色后的内容
+fn inverted(cell: ScreenChar) -> ScreenChar {
+    ScreenChar {
+        color_code: cell.color_code.inverted(),
+        ..cell
+    }
+}
+
+fn show(buffer: &mut Buffer, row: usize, col: usize) -> ScreenChar {
+    let saved = buffer.chars[row][col].read();
+    buffer.chars[row][col].write(inverted(saved));
+    saved
+}
+
+impl Writer {
+    /// 切换一次光标的反色状态，由时钟中断调用；回滚中不显示光标
+    pub fn toggle_soft_cursor(&mut self) {
+        if self.soft_cursor.shown.is_some() {
+            self.hide_soft_cursor();
+        } else if self.scroll_offset() == 0 {
+            let (row, col) = (
+                BUFFER_HEIGHT - 1,
+                self.column_position.min(BUFFER_WIDTH - 1),
+            );
+            let saved = show(self.buffer, row, col);
+            self.soft_cursor.shown = Some((row, col, saved));
+        }
+    }
+
+    /// 光标正在反色显示时恢复单元格原来的内容；单元格已经被改写时保留新的内容
+    pub fn hide_soft_cursor(&mut self) {
+        if let Some((row, col, saved)) = self.soft_cursor.shown.take() {
+            let cell = &mut self.buffer.chars[row][col];
+            if cell.read() == inverted(saved) {
+                cell.write(saved);
+            }
+        }
+    }
+
+    /// 光标是否正处于反色状态
+    pub fn soft_cursor_shown(&self) -> bool {
+        self.soft_cursor.shown.is_some()
+    }
+}
+
+/// 打开软件光标，下一次闪烁时出现
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// 关闭软件光标，正在反色的单元格立即恢复
+pub fn disable() {
+    // 关中断持有锁期间时钟中断不会再切换光标
+    interrupts::without_interrupts(|| {
+        ENABLED.store(false, Ordering::SeqCst);
+        WRITER.lock().hide_soft_cursor();
+    });
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// 设置每隔多少个节拍切换一次，0 当作 1
+pub fn set_blink_rate(ticks: u64) {
+    BLINK_TICKS.store(ticks.max(1), Ordering::SeqCst);
+}
+
+pub fn blink_rate() -> u64 {
+    BLINK_TICKS.load(Ordering::SeqCst)
+}
+
+/// 第 now 个节拍是否应该切换
+fn blink_due(now: u64, rate: u64) -> bool {
+    now.is_multiple_of(rate)
+}
+
+/// 时钟中断每个节拍调用
+pub(crate) fn on_tick(now: u64) {
+    if !enabled() || !blink_due(now, blink_rate()) {
+        return;
+    }
+    if let Some(mut writer) = WRITER.try_lock() {
+        writer.toggle_soft_cursor();
+    }
+}
+
+#[cfg(test)]
+use super::{ColorCode, TestWriter};
+
+#[test_case]
+fn test_soft_cursor_blinks_in_place() {
+    let mut writer = TestWriter::new();
+    writer.write_string("ab");
+    let row = BUFFER_HEIGHT - 1;
+    let before = writer.read_char(row, 2);
+    writer.toggle_soft_cursor();
+    assert!(writer.soft_cursor_shown());
+    assert_eq!(writer.read_char(row, 2), (before.0, before.1.inverted()));
+    // 文字本身不受影响
+    assert_eq!(writer.read_char(row, 1), (b'b', writer.color_code()));
+    writer.toggle_soft_cursor();
+    assert!(!writer.soft_cursor_shown());
+    assert_eq!(writer.read_char(row, 2), before);
+
+    assert!(blink_due(1000, 500));
+    assert!(!blink_due(1001, 500));
+}
+
+#[test_case]
+fn test_writes_clear_soft_cursor() {
+    let mut writer = TestWriter::new();
+    let row = BUFFER_HEIGHT - 1;
+    writer.write_string("ab");
+    writer.toggle_soft_cursor();
+    // 光标处写入的字符使用当前颜色，而不是反色
+    writer.write_byte(b'c');
+    assert!(!writer.soft_cursor_shown());
+    assert_eq!(writer.read_char(row, 2), (b'c', writer.color_code()));
+
+    // 反色时换行：滚到上一行的单元格恢复原样，快照中也是原样
+    let blank = writer.read_char(row, 3);
+    writer.toggle_soft_cursor();
+    let snapshot = writer.snapshot();
+    writer.write_byte(b'\n');
+    assert_eq!(writer.read_char(row - 1, 3), blank);
+    writer.restore(&snapshot);
+    assert_eq!(writer.read_char(row, 3), blank);
+
+    // 绕过 Writer 改写了单元格时不恢复成旧内容
+    writer.toggle_soft_cursor();
+    let col = writer.column();
+    let red = ColorCode::new(super::Color::Red, super::Color::Black);
+    writer.put_char(row, col, b'x', red);
+    writer.hide_soft_cursor();
+    assert_eq!(writer.read_char(row, col), (b'x', red));
+}
@@ -0,0 +1,180 @@
+//! 状态栏中按列对齐的一行
+//! 每个字段有固定的宽度和对齐方式，值的长度变化时其他字段的位置不变，画面不会抖动。
+//! 内容写在调用者提供的 BUFFER_WIDTH 字节的缓冲区中，不分配内存，
+//! 拼好后由 Writer::write_status_line 一次写到指定的行
+use super::{char_display_width, decimal, Writer, BUFFER_WIDTH, MAX_DECIMAL_DIGITS};
+use core::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    /// 左右不能平分时左边少一格
+    Center,
+}
+
+/// 见模块文档。字段从左到右依次排列，超出行宽的部分被丢弃
+pub struct StatusLine<'a> {
+    buf: &'a mut [u8; BUFFER_WIDTH],
+    /// 下一个字段开始的列
+    len: usize,
+}
+
+impl<'a> StatusLine<'a> {
+    /// buf 被清为空格
+    pub fn new(buf: &'a mut [u8; BUFFER_WIDTH]) -> Self {
+        buf.fill(b' ');
+        StatusLine { buf, len: 0 }
+    }
+
+    /// 宽 width 的字段，宽度按 char_display_width 计算，text 比字段长时截断；
+    /// 非 ASCII 字符显示为 '?'，宽字符显示为 "??"，组合字符被丢弃
+    pub fn text(&mut self, text: &str, width: usize, align: Align) -> &mut Self {
+        self.formatted(format_args!("{}", text), width, align)
+    }
+
+    /// 十进制数，比字段长时整个字段填满 '#'，不显示被截断的数字
+    pub fn number(&mut self, value: u64, width: usize, align: Align) -> &mut Self {
+        let mut digits = [0; MAX_DECIMAL_DIGITS];
+        let digits = decimal(value, &mut digits);
+        if digits.len() > width {
+            return self.place(&[b'#'; BUFFER_WIDTH][..width], width, Align::Left);
+        }
+        self.place(digits, width, align)
+    }
+
+    /// 格式化的字段，例如 format_args!("{:.1} fps", fps)，与 text 一样截断
+    pub fn formatted(&mut self, args: fmt::Arguments, width: usize, align: Align) -> &mut Self {
+        let mut field = Field {
+            bytes: [b' '; BUFFER_WIDTH],
+            second_half: [false; BUFFER_WIDTH],
+            len: 0,
+        };
+        let _ = field.write_fmt(args);
+        let mut len = field.len.min(width);
+        // 不把宽字符截成一半
+        if len < field.len && field.second_half[len] {
+            len -= 1;
+        }
+        self.place(&field.bytes[..len], width, align)
+    }
+
+    /// 空出 width 列
+    pub fn gap(&mut self, width: usize) -> &mut Self {
+        self.place(&[], width, Align::Left)
+    }
+
+    /// 拼好的一整行，总是 BUFFER_WIDTH 个可打印的 ASCII 字符
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..]).unwrap()
+    }
+
+    /// 把 bytes 按 align 放进从 len 开始、宽 width 的字段，bytes 不长于 width
+    fn place(&mut self, bytes: &[u8], width: usize, align: Align) -> &mut Self {
+        let start = self.len;
+        let padding = width - bytes.len();
+        let offset = match align {
+            Align::Left => 0,
+            Align::Right => padding,
+            Align::Center => padding / 2,
+        };
+        for (index, &byte) in bytes.iter().enumerate() {
+            if let Some(cell) = self.buf.get_mut(start.saturating_add(offset + index)) {
+                *cell = byte;
+            }
+        }
+        self.len = start.saturating_add(width);
+        self
+    }
+}
+
+/// formatted 的格式化目标，只保留前 BUFFER_WIDTH 列
+struct Field {
+    bytes: [u8; BUFFER_WIDTH],
+    /// 这一列是宽字符的后一半
+    second_half: [bool; BUFFER_WIDTH],
+    len: usize,
+}
+
+impl fmt::Write for Field {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let byte = match c {
+                ' '..='~' => c as u8,
+                _ => b'?',
+            };
+            for half in 0..char_display_width(c) {
+                if self.len == BUFFER_WIDTH {
+                    return Ok(());
+                }
+                self.bytes[self.len] = byte;
+                self.second_half[self.len] = half == 1;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Writer {
+    /// 用当前颜色把 line 写到第 row 行，不移动光标
+    pub fn write_status_line(&mut self, row: usize, line: &StatusLine) {
+        self.write_fmt_at(row, 0, format_args!("{}", line.as_str()));
+    }
+}
+
+#[test_case]
+fn test_status_line_segments() {
+    use super::TestWriter;
+
+    let mut buf = [0; BUFFER_WIDTH];
+    let mut line = StatusLine::new(&mut buf);
+    line.text("up", 4, Align::Left)
+        .number(1234, 8, Align::Right)
+        .gap(2)
+        .formatted(format_args!("{} fps", 60), 10, Align::Center);
+    let mut writer = TestWriter::new();
+    writer.write_status_line(0, &line);
+    let row: [u8; 24] = writer.buffer.row_text(0);
+    assert_eq!(&row, b"up      1234    60 fps  ");
+    assert_eq!(writer.read_char(0, BUFFER_WIDTH - 1).0, b' ');
+    assert_eq!(writer.column(), 0);
+
+    // 值变短时其他字段不动；太长的文字被截断，太长的数字显示为 #
+    let mut line = StatusLine::new(&mut buf);
+    line.text("uptime", 4, Align::Left)
+        .number(7, 8, Align::Right)
+        .number(123_456, 3, Align::Right)
+        .text("é", 2, Align::Right);
+    assert_eq!(&line.as_str()[..18], "upti       7### ? ");
+}
+
+#[test_case]
+fn test_status_line_display_width() {
+    let mut buf = [0; BUFFER_WIDTH];
+    // 组合字符不占列，居中时按 "e" 一列计算
+    let mut line = StatusLine::new(&mut buf);
+    line.text("e\u{301}", 4, Align::Center)
+        .text("中文", 5, Align::Center)
+        .text("|", 1, Align::Left);
+    assert_eq!(&line.as_str()[..10], " e  ???? |");
+
+    // 截断时不留下半个宽字符，后面的字段仍在原来的列
+    let mut line = StatusLine::new(&mut buf);
+    line.text("中文", 3, Align::Left).text("|", 1, Align::Left);
+    assert_eq!(&line.as_str()[..4], "?? |");
+}
+
+#[test_case]
+fn test_status_line_clips_at_row_end() {
+    let mut buf = [0; BUFFER_WIDTH];
+    let mut line = StatusLine::new(&mut buf);
+    line.gap(BUFFER_WIDTH - 3)
+        .text("abcdef", 6, Align::Left)
+        .text("more", 4, Align::Left);
+    assert_eq!(line.as_str().len(), BUFFER_WIDTH);
+    assert!(line.as_str().ends_with(" abc"));
+    // usize::MAX 宽的字段也不会溢出
+    line.gap(usize::MAX).text("x", 1, Align::Left);
+    assert!(line.as_str().ends_with(" abc"));
+}
@@ -0,0 +1,44 @@
+//! 打字机效果
+//! 逐个字符写出，每个字符之后用 hlt 等待若干时钟节拍，用于启动画面等开场文字。
+//! 等待期间一直占用 writer，调用者拿着 WRITER 的锁时其他输出都要等到写完，
+//! 所以只适合启动画面，不要用于一般的日志输出
+use super::Writer;
+use crate::time;
+
+/// 逐个字符写出 s，每个字符之后等待 delay_ticks 个时钟节拍；换行等控制字符与 write_char 相同。
+/// 依赖时钟中断，必须在中断开启后调用（见 time::delay_ms）
+pub fn write_typewriter(writer: &mut Writer, s: &str, delay_ticks: u64) {
+    debug_assert!(
+        x86_64::instructions::interrupts::are_enabled(),
+        "write_typewriter requires interrupts to be enabled"
+    );
+    write_paced(writer, s, || {
+        let target = time::ticks() + delay_ticks;
+        while time::ticks() < target {
+            x86_64::instructions::hlt();
+        }
+    });
+}
+
+/// 每写出一个字符调用一次 wait
+fn write_paced(writer: &mut Writer, s: &str, mut wait: impl FnMut()) {
+    for c in s.chars() {
+        writer.write_char(c);
+        wait();
+    }
+}
+
+#[cfg(test)]
+use super::{TestWriter, BUFFER_HEIGHT};
+
+#[test_case]
+fn test_write_paced() {
+    let mut writer = TestWriter::new();
+    let mut waits = 0;
+    write_paced(&mut writer, "hi\nok", || waits += 1);
+    assert_eq!(waits, 5);
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 0).0, b'h');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, 1).0, b'i');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 0).0, b'o');
+    assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, 1).0, b'k');
+}
@@ -0,0 +1,166 @@
+//! 虚拟控制台
+//! 几个互相独立的屏幕，同一时间只有一个显示在显存中并接收 WRITER 的输出。
+//! 切换时把当前屏幕的内容和光标状态保存到它的 VirtualConsole 中，再把目标控制台的内容复制进显存。
+//!
+//! 回滚缓冲区的历史由所有控制台共用；切换前视图先回到底部，高亮也会被清除
+use super::snapshot::{load_screen, save_screen};
+use super::{Color, ColorCode, ScreenChar, ScreenRow, Writer, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+use x86_64::instructions::interrupts;
+
+/// WRITER 的虚拟控制台数量，默认用 Alt+F1 到 Alt+F4 切换
+pub const VIRTUAL_CONSOLES: usize = 4;
+
+/// 一个不在屏幕上的控制台：屏幕内容和光标状态
+#[derive(Clone)]
+pub struct VirtualConsole {
+    screen: [ScreenRow; BUFFER_HEIGHT],
+    column_position: usize,
+    color_code: ColorCode,
+}
+
+impl VirtualConsole {
+    /// 空白的屏幕，颜色与 Writer::new 相同
+    pub fn new() -> Self {
+        let color_code = ColorCode::new(Color::Yellow, Color::Black);
+        VirtualConsole {
+            screen: [[ScreenChar::new(b' ', color_code); BUFFER_WIDTH]; BUFFER_HEIGHT],
+            column_position: 0,
+            color_code,
+        }
+    }
+}
+
+impl Default for VirtualConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(super) struct VirtualConsoles {
+    consoles: &'static mut [VirtualConsole],
+    active: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleError {
+    /// 没有这个编号的控制台
+    NoSuchConsole(usize),
+}
+
+impl Writer {
+    /// 启用虚拟控制台，当前屏幕成为 0 号控制台，consoles[0] 的内容会在切换走时被覆盖
+    pub fn enable_virtual_consoles(&mut self, consoles: &'static mut [VirtualConsole]) {
+        self.consoles = Some(VirtualConsoles {
+            consoles,
+            active: 0,
+        });
+    }
+
+    /// 当前显示的控制台编号，没有启用时总是 0
+    pub fn active_console(&self) -> usize {
+        self.consoles.as_ref().map_or(0, |consoles| consoles.active)
+    }
+
+    /// 控制台数量，没有启用时只有当前屏幕这一个
+    pub fn console_count(&self) -> usize {
+        self.consoles
+            .as_ref()
+            .map_or(1, |consoles| consoles.consoles.len())
+    }
+
+    /// 切换到第 index 个控制台，之后的输出都写到它上面
+    pub fn switch_console(&mut self, index: usize) -> Result<(), ConsoleError> {
+        if index >= self.console_count() {
+            return Err(ConsoleError::NoSuchConsole(index));
+        }
+        if index == self.active_console() {
+            return Ok(());
+        }
+        // 保存的必须是实时画面本身
+        self.finish_redraw();
+        self.snap_to_bottom();
+        self.clear_highlight();
+
+        let Some(consoles) = &mut self.consoles else {
+            unreachable!("index 0 is always the active console without virtual consoles");
+        };
+        let current = &mut consoles.consoles[consoles.active];
+        save_screen(self.buffer, &mut current.screen);
+        current.column_position = self.column_position;
+        current.color_code = self.color_code;
+
+        let target = &consoles.consoles[index];
+        load_screen(&target.screen, self.buffer);
+        self.column_position = target.column_position;
+        self.color_code = target.color_code;
+        consoles.active = index;
+        Ok(())
+    }
+}
+
+/// 为 WRITER 启用 VIRTUAL_CONSOLES 个虚拟控制台，需要在堆初始化之后调用
+pub fn init_virtual_consoles() {
+    use alloc::boxed::Box;
+    use alloc::vec;
+
+    let consoles = Box::leak(vec![VirtualConsole::new(); VIRTUAL_CONSOLES].into_boxed_slice());
+    interrupts::without_interrupts(|| WRITER.lock().enable_virtual_consoles(consoles));
+}
+
+#[cfg(test)]
+use super::TestWriter;
+
+#[cfg(test)]
+fn bottom_text(writer: &Writer, len: usize) -> alloc::vec::Vec<u8> {
+    (0..len)
+        .map(|col| writer.read_char(BUFFER_HEIGHT - 1, col).0)
+        .collect()
+}
+
+#[test_case]
+fn test_consoles_keep_independent_content() {
+    let mut writer = TestWriter::with_virtual_consoles(2);
+    writer.write_string("first");
+    writer.switch_console(1).unwrap();
+    assert_eq!(writer.active_console(), 1);
+    // 新的控制台是空白的，光标在行首
+    assert_eq!(bottom_text(&writer, 5), b"     ");
+    writer.set_color(Color::White, Color::Blue);
+    writer.write_string("second");
+
+    writer.switch_console(0).unwrap();
+    assert_eq!(bottom_text(&writer, 6), b"first ");
+    // 光标和颜色也各自独立
+    writer.write_string("!");
+    assert_eq!(bottom_text(&writer, 6), b"first!");
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, 5).1,
+        ColorCode::new(Color::Yellow, Color::Black)
+    );
+
+    writer.switch_console(1).unwrap();
+    assert_eq!(bottom_text(&writer, 7), b"second ");
+    writer.write_string("?");
+    assert_eq!(
+        writer.read_char(BUFFER_HEIGHT - 1, 6),
+        (b'?', ColorCode::new(Color::White, Color::Blue))
+    );
+}
+
+#[test_case]
+fn test_switch_to_missing_console() {
+    let mut writer = TestWriter::new();
+    assert_eq!(writer.console_count(), 1);
+    assert_eq!(writer.switch_console(0), Ok(()));
+    assert_eq!(
+        writer.switch_console(1),
+        Err(ConsoleError::NoSuchConsole(1))
+    );
+
+    let mut writer = TestWriter::with_virtual_consoles(2);
+    assert_eq!(
+        writer.switch_console(2),
+        Err(ConsoleError::NoSuchConsole(2))
+    );
+    assert_eq!(writer.active_console(), 0);
+}
@@ -0,0 +1,91 @@
+//! 字符在屏幕上占用的列数
+//! 文本模式每个单元格只能放一个 CP437 字节，所以：
+//! - 组合附加符号（例如 U+0301）和零宽字符没有单独的字形，宽度为 0，写入时被丢弃
+//! - 东亚宽字符（汉字、假名、谚文、全角符号等）在等宽终端中占两列，这里也占两列，
+//!   画成两个替代字节，这样表格和对齐的文字与在串口终端中看到的一致
+//! - 其他字符占一列：ASCII、有 CP437 字形的字符，以及显示为一个替代字节的字符
+//!
+//! 宽字符的范围取自 Unicode 的 East Asian Width 中 W 和 F 的主要区段，不追求完整
+use core::fmt;
+
+/// 宽度为 0 的区段
+const ZERO_WIDTH: &[(char, char)] = &[
+    ('\u{0300}', '\u{036f}'),
+    ('\u{1ab0}', '\u{1aff}'),
+    ('\u{1dc0}', '\u{1dff}'),
+    ('\u{200b}', '\u{200f}'),
+    ('\u{20d0}', '\u{20ff}'),
+    ('\u{fe00}', '\u{fe0f}'),
+    ('\u{fe20}', '\u{fe2f}'),
+    ('\u{feff}', '\u{feff}'),
+];
+
+/// 宽度为 2 的区段
+const WIDE: &[(char, char)] = &[
+    ('\u{1100}', '\u{115f}'),
+    ('\u{2e80}', '\u{303e}'),
+    ('\u{3041}', '\u{33ff}'),
+    ('\u{3400}', '\u{4dbf}'),
+    ('\u{4e00}', '\u{9fff}'),
+    ('\u{a000}', '\u{a4cf}'),
+    ('\u{ac00}', '\u{d7a3}'),
+    ('\u{f900}', '\u{faff}'),
+    ('\u{fe30}', '\u{fe4f}'),
+    ('\u{ff00}', '\u{ff60}'),
+    ('\u{ffe0}', '\u{ffe6}'),
+    ('\u{1f300}', '\u{1f64f}'),
+    ('\u{1f900}', '\u{1f9ff}'),
+    ('\u{20000}', '\u{2fffd}'),
+    ('\u{30000}', '\u{3fffd}'),
+];
+
+fn in_ranges(c: char, ranges: &[(char, char)]) -> bool {
+    ranges.iter().any(|&(low, high)| (low..=high).contains(&c))
+}
+
+/// c 占用的列数，见模块文档
+pub fn char_display_width(c: char) -> usize {
+    if c.is_ascii() {
+        1
+    } else if in_ranges(c, ZERO_WIDTH) {
+        0
+    } else if in_ranges(c, WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+/// s 占用的列数，用来代替 str::len 计算对齐和截断
+pub fn display_width(s: &str) -> usize {
+    s.chars().map(char_display_width).sum()
+}
+
+/// 显示时在右边补空格，直到占满 width 列；比 width 宽时原样显示。
+/// 格式化参数中的宽度按字符计数，遇到宽字符和组合字符会错位，表格中用它代替 {:<width}
+pub struct PadRight<'a>(pub &'a str, pub usize);
+
+impl fmt::Display for PadRight<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)?;
+        for _ in display_width(self.0)..self.1 {
+            f.write_str(" ")?;
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_char_display_width() {
+    assert_eq!(char_display_width('a'), 1);
+    assert_eq!(char_display_width('\n'), 1);
+    assert_eq!(char_display_width('é'), 1);
+    assert_eq!(char_display_width('█'), 1);
+    assert_eq!(char_display_width('\u{301}'), 0);
+    assert_eq!(char_display_width('\u{200d}'), 0);
+    assert_eq!(char_display_width('中'), 2);
+    assert_eq!(char_display_width('あ'), 2);
+    assert_eq!(char_display_width('한'), 2);
+    assert_eq!(char_display_width('Ａ'), 2);
+    assert_eq!(display_width("e\u{301}中x"), 4);
+}
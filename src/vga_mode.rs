@@ -0,0 +1,318 @@
+//! 检测与恢复 VGA 显示模式
+//!
+//! 检测时读取的寄存器：
+//! - 杂项输出寄存器（Miscellaneous Output，读端口 0x3CC）bit 0：CRTC 端口基址，1 为 0x3D4（彩色），0 为 0x3B4（单色）
+//! - 图形控制器杂项寄存器（Graphics Controller 索引 0x06）bit 0：1 表示图形模式，0 表示文本模式
+//! - 属性控制器模式寄存器（Attribute Controller 索引 0x10）bit 0：1 表示图形模式
+//! - CRTC 最大扫描线寄存器（CRTC 索引 0x09）bit 0-4：每个字符行的扫描线数 - 1，
+//!   400 条扫描线下 15（8x16 字体）对应 25 行，7（8x8 字体）对应 50 行
+//!
+//! 局限：
+//! - 只区分标准的 80 列文本模式和 "图形模式"，不识别具体的图形分辨率，也不检查水平时序
+//! - 恢复只重写寄存器，不重新加载字库：从图形模式回到文本模式时平面 2 中的字库可能已被覆盖，
+//!   切换到 80x50 时仍使用 8x16 字库的上半部分，字形会被截断。
+//!   graphics 模块在进入图形模式前自己保存字库，回到文本模式时再写回
+//!
+//! switch_mode 在两种文本模式之间切换并保留屏幕内容：先给 WRITER 拍快照，写完寄存器后按新模式的
+//! 尺寸重新绘制，放不下的行和列被丢弃。WRITER 只管理显存的前 BUFFER_HEIGHT 行，
+//! 所以 80x50 下屏幕的下半部分是空白
+//!
+//! 垂直回扫同步：大量更新显存时先等到垂直回扫开始，在消隐期间写入就不会出现上下半屏不一致的撕裂。
+//! 等待是忙轮询输入状态寄存器 1，最坏情况下要等一整帧（70Hz 下约 14ms），期间 CPU 什么也不做，
+//! 所以默认关闭，由 set_vsync 打开；打开后 draw 的批量显示和 graphics 的 Framebuffer::batch 会先等待
+use crate::vga_buffer::WRITER;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+pub(crate) const MISC_OUTPUT_READ: u16 = 0x3CC;
+const MISC_OUTPUT_WRITE: u16 = 0x3C2;
+pub(crate) const SEQUENCER_INDEX: u16 = 0x3C4;
+pub(crate) const GRAPHICS_INDEX: u16 = 0x3CE;
+pub(crate) const ATTRIBUTE_INDEX: u16 = 0x3C0;
+pub(crate) const ATTRIBUTE_READ: u16 = 0x3C1;
+/// 读取输入状态寄存器 1 会把属性控制器的 索引/数据 触发器复位到 "索引" 状态
+pub(crate) const INPUT_STATUS_1: u16 = 0x3DA;
+/// 输入状态寄存器 1 的 bit 3：正在垂直回扫
+const VRETRACE: u8 = 0x08;
+/// 轮询的次数上限，一次端口读取约 1µs，远大于一帧的时间。
+/// 没有 VGA 时端口读到 0xFF，回扫位一直为 1，不能无限等下去
+const VRETRACE_POLL_LIMIT: u32 = 100_000;
+
+static VSYNC: AtomicBool = AtomicBool::new(false);
+
+/// 属性控制器索引中的 PAS 位，写索引时必须置位，否则屏幕会被关闭
+pub(crate) const ATTRIBUTE_PAS: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VgaMode {
+    Text80x25,
+    Text80x50,
+    Graphics,
+    /// 文本模式，但字符高度不是标准值
+    Unknown,
+}
+
+impl VgaMode {
+    /// 文本模式的 (行数, 列数)，图形模式和未知模式返回 None
+    pub const fn text_size(self) -> Option<(usize, usize)> {
+        match self {
+            VgaMode::Text80x25 => Some((25, 80)),
+            VgaMode::Text80x50 => Some((50, 80)),
+            VgaMode::Graphics | VgaMode::Unknown => None,
+        }
+    }
+}
+
+/// 标准文本模式（BIOS mode 3）的寄存器值
+const TEXT_80X25_CRTC: [u8; 25] = [
+    0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00, 0x50,
+    0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+];
+/// 与 80x25 相同的时序，只把字符高度改为 8 条扫描线，光标形状随之调整
+const TEXT_80X50_CRTC: [u8; 25] = [
+    0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x00, 0x50,
+    0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+];
+const TEXT_MISC: u8 = 0x67;
+pub(crate) const TEXT_SEQUENCER: [u8; 5] = [0x03, 0x00, 0x03, 0x00, 0x02];
+pub(crate) const TEXT_GRAPHICS: [u8; 9] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF];
+pub(crate) const TEXT_ATTRIBUTE: [u8; 21] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+    0x0C, 0x00, 0x0F, 0x08, 0x00,
+];
+
+/// 一个显示模式的全部寄存器值
+pub(crate) struct ModeRegisters {
+    pub misc: u8,
+    pub sequencer: [u8; 5],
+    pub crtc: [u8; 25],
+    pub graphics: [u8; 9],
+    pub attribute: [u8; 21],
+}
+
+const TEXT_80X25: ModeRegisters = ModeRegisters {
+    misc: TEXT_MISC,
+    sequencer: TEXT_SEQUENCER,
+    crtc: TEXT_80X25_CRTC,
+    graphics: TEXT_GRAPHICS,
+    attribute: TEXT_ATTRIBUTE,
+};
+const TEXT_80X50: ModeRegisters = ModeRegisters {
+    crtc: TEXT_80X50_CRTC,
+    ..TEXT_80X25
+};
+
+/// 读取 VGA 寄存器推断当前显示模式
+pub fn current_mode() -> VgaMode {
+    unsafe {
+        let crtc_index = crtc_index_port();
+        let graphics_misc = read_indexed(GRAPHICS_INDEX, 0x06);
+        let attribute_mode = read_attribute(0x10);
+        let max_scan_line = read_indexed(crtc_index, 0x09);
+        decode_mode(graphics_misc, attribute_mode, max_scan_line)
+    }
+}
+
+/// 根据寄存器值推断模式，与端口读写分离便于测试
+pub(crate) fn decode_mode(graphics_misc: u8, attribute_mode: u8, max_scan_line: u8) -> VgaMode {
+    if graphics_misc & 0x01 != 0 || attribute_mode & 0x01 != 0 {
+        return VgaMode::Graphics;
+    }
+    match max_scan_line & 0x1F {
+        15 => VgaMode::Text80x25,
+        7 => VgaMode::Text80x50,
+        _ => VgaMode::Unknown,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VgaModeError {
+    /// 图形模式的寄存器状态无法仅凭 VgaMode 还原
+    Unsupported(VgaMode),
+}
+
+/// 把显示恢复到 current_mode 之前返回的模式
+/// 只支持两种文本模式，字库不会被重新加载（见模块文档）
+pub fn restore_mode(mode: VgaMode) -> Result<(), VgaModeError> {
+    let registers = match mode {
+        VgaMode::Text80x25 => &TEXT_80X25,
+        VgaMode::Text80x50 => &TEXT_80X50,
+        VgaMode::Graphics | VgaMode::Unknown => return Err(VgaModeError::Unsupported(mode)),
+    };
+    unsafe { write_registers(registers) };
+    Ok(())
+}
+
+/// 切换到另一种文本模式，屏幕上的内容在新尺寸内的部分会被保留。
+/// 回滚中的视图先回到底部，保留的是实时画面
+pub fn switch_mode(mode: VgaMode) -> Result<(), VgaModeError> {
+    let (rows, cols) = mode.text_size().ok_or(VgaModeError::Unsupported(mode))?;
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.snap_to_bottom();
+        writer.redraw_after(rows, cols, |_| restore_mode(mode))
+    })
+}
+
+/// 依次写入杂项输出、序列器、CRTC、图形控制器和属性控制器，最后重新打开屏幕显示
+pub(crate) unsafe fn write_registers(registers: &ModeRegisters) {
+    Port::<u8>::new(MISC_OUTPUT_WRITE).write(registers.misc);
+
+    for (index, value) in registers.sequencer.iter().enumerate() {
+        write_indexed(SEQUENCER_INDEX, index as u8, *value);
+    }
+
+    // CRTC 寄存器 0x11 的 bit 7 会写保护寄存器 0-7，需要先解除
+    let crtc_index = crtc_index_port();
+    let crtc = &registers.crtc;
+    let protect = read_indexed(crtc_index, 0x11);
+    write_indexed(crtc_index, 0x11, protect & 0x7F);
+    for (index, value) in crtc.iter().enumerate() {
+        let value = if index == 0x11 { value & 0x7F } else { *value };
+        write_indexed(crtc_index, index as u8, value);
+    }
+    // 恢复写保护位
+    write_indexed(crtc_index, 0x11, crtc[0x11]);
+
+    for (index, value) in registers.graphics.iter().enumerate() {
+        write_indexed(GRAPHICS_INDEX, index as u8, *value);
+    }
+
+    let mut attribute = Port::<u8>::new(ATTRIBUTE_INDEX);
+    for (index, value) in registers.attribute.iter().enumerate() {
+        Port::<u8>::new(INPUT_STATUS_1).read();
+        attribute.write(index as u8);
+        attribute.write(*value);
+    }
+    // 重新打开屏幕显示
+    Port::<u8>::new(INPUT_STATUS_1).read();
+    attribute.write(ATTRIBUTE_PAS);
+}
+
+/// 打开或关闭批量更新前的垂直回扫同步
+pub fn set_vsync(enabled: bool) {
+    VSYNC.store(enabled, Ordering::Relaxed);
+}
+
+pub fn vsync_enabled() -> bool {
+    VSYNC.load(Ordering::Relaxed)
+}
+
+/// 打开了垂直回扫同步时等待下一次回扫，否则立即返回
+pub fn sync_to_vretrace() {
+    if vsync_enabled() {
+        wait_for_vretrace();
+    }
+}
+
+/// 忙等到下一次垂直回扫开始，返回是否等到。
+/// 如果调用时已经在回扫中，先等它结束：回扫剩下的时间可能不够完成更新
+pub fn wait_for_vretrace() -> bool {
+    let mut status = Port::<u8>::new(INPUT_STATUS_1);
+    poll_vretrace(|| unsafe { status.read() }, VRETRACE_POLL_LIMIT)
+}
+
+/// 与端口读取分离便于测试，两个阶段各自最多读 limit 次
+fn poll_vretrace(mut read_status: impl FnMut() -> u8, limit: u32) -> bool {
+    let mut wait_until =
+        |in_retrace: bool| (0..limit).any(|_| (read_status() & VRETRACE != 0) == in_retrace);
+    wait_until(false) && wait_until(true)
+}
+
+/// 根据杂项输出寄存器的 bit 0 选择 CRTC 的索引端口
+pub(crate) unsafe fn crtc_index_port() -> u16 {
+    let misc = Port::<u8>::new(MISC_OUTPUT_READ).read();
+    if misc & 0x01 != 0 {
+        0x3D4
+    } else {
+        0x3B4
+    }
+}
+
+/// 索引寄存器组：先向索引端口写入索引，再从紧随其后的数据端口读写
+pub(crate) unsafe fn read_indexed(index_port: u16, index: u8) -> u8 {
+    Port::<u8>::new(index_port).write(index);
+    Port::<u8>::new(index_port + 1).read()
+}
+
+pub(crate) unsafe fn write_indexed(index_port: u16, index: u8, value: u8) {
+    Port::<u8>::new(index_port).write(index);
+    Port::<u8>::new(index_port + 1).write(value);
+}
+
+unsafe fn read_attribute(index: u8) -> u8 {
+    Port::<u8>::new(INPUT_STATUS_1).read();
+    Port::<u8>::new(ATTRIBUTE_INDEX).write(index | ATTRIBUTE_PAS);
+    let value = Port::<u8>::new(ATTRIBUTE_READ).read();
+    Port::<u8>::new(INPUT_STATUS_1).read();
+    value
+}
+
+#[test_case]
+fn test_decode_text_modes() {
+    assert_eq!(decode_mode(0x0E, 0x0C, 0x4F), VgaMode::Text80x25);
+    assert_eq!(decode_mode(0x0E, 0x0C, 0x47), VgaMode::Text80x50);
+    assert_eq!(decode_mode(0x0E, 0x0C, 0x4D), VgaMode::Unknown);
+}
+
+#[test_case]
+fn test_decode_graphics_mode() {
+    // mode 13h：图形控制器与属性控制器都设置了图形位
+    assert_eq!(decode_mode(0x05, 0x41, 0x41), VgaMode::Graphics);
+    assert_eq!(decode_mode(0x00, 0x01, 0x4F), VgaMode::Graphics);
+}
+
+#[test_case]
+fn test_restore_rejects_graphics() {
+    assert_eq!(
+        restore_mode(VgaMode::Graphics),
+        Err(VgaModeError::Unsupported(VgaMode::Graphics))
+    );
+}
+
+#[test_case]
+fn test_text_size() {
+    assert_eq!(VgaMode::Text80x25.text_size(), Some((25, 80)));
+    assert_eq!(VgaMode::Text80x50.text_size(), Some((50, 80)));
+    assert_eq!(
+        switch_mode(VgaMode::Unknown),
+        Err(VgaModeError::Unsupported(VgaMode::Unknown))
+    );
+}
+
+#[test_case]
+fn test_text_tables_only_differ_in_character_height() {
+    for (index, (a, b)) in TEXT_80X25_CRTC
+        .iter()
+        .zip(TEXT_80X50_CRTC.iter())
+        .enumerate()
+    {
+        if !matches!(index, 0x09..=0x0B) {
+            assert_eq!(a, b);
+        }
+    }
+}
+
+#[test_case]
+fn test_poll_vretrace() {
+    // 显示中，回扫开始
+    let mut states = [0x00, 0x00, 0x08]
+        .into_iter()
+        .chain(core::iter::repeat(0x08));
+    assert!(poll_vretrace(|| states.next().unwrap(), 10));
+    // 调用时在回扫中，要等到下一次
+    let mut states = [0x08, 0x08, 0x00, 0x08].into_iter();
+    let mut reads = 0;
+    assert!(poll_vretrace(
+        || {
+            reads += 1;
+            states.next().unwrap()
+        },
+        10
+    ));
+    assert_eq!(reads, 4);
+    // 没有 VGA 时回扫位一直为 1
+    assert!(!poll_vretrace(|| 0xFF, 10));
+    assert!(!poll_vretrace(|| 0x00, 10));
+}
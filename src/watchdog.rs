@@ -0,0 +1,357 @@
+//! 软件看门狗
+//! 执行器的主循环每一轮调用 pet，时钟中断每个节拍调用 on_tick 累加距离上次 pet 的节拍数。
+//! 超过 timeout 个节拍说明主循环卡住了、而中断还在正常到来，这时在中断处理函数中打印一次报告：
+//! 被打断的指令地址、中断延迟统计、任务列表和调用栈，然后按配置的动作继续运行或者重启。
+//!
+//! - 第一次 pet 之后才开始计数，执行器运行之前的启动阶段耗时再长也不会触发
+//! - 每次卡住只报告一次，之后再 pet 才重新布防
+//! - 确实需要很长时间的操作用 suspend 返回的守卫包起来，期间不计数，结束时计数清零
+//!
+//! 报告直接在中断处理函数中打印：控制台的锁总是在关中断时持有，不会正被打断的代码占着；
+//! 任务表的锁则可能被占着，只尝试加锁。被打断的代码也可能正持有堆的锁，所以整个报告不分配内存
+//!
+//! 另有一个反方向的节拍看门狗，默认关闭，用 enable_tick_watchdog 打开：
+//! 每次按键时用校准过的空转等待 TICK_CHECK_WINDOW_MS 毫秒，看节拍数有没有前进，
+//! 连续多次都没有前进时打印一次警告，提示中断可能没有开启、IDT 没有加载或者 PIC 没有设置好。
+//! 空转的计时只是估计（见 time::calibrate_delay），所以要连续几次才警告
+use crate::symbols::{self, Demangle};
+use crate::task::executor;
+use crate::vga_buffer::Stdout;
+use crate::{backtrace, diag, power, println, time};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// 默认 5 秒没有 pet 就触发
+pub const DEFAULT_TIMEOUT_TICKS: u64 = 5 * time::TIMER_FREQUENCY_HZ as u64;
+
+/// 看门狗触发后做什么
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// 不计数，不会触发
+    Off,
+    /// 打印报告后继续运行
+    Report,
+    /// 打印报告后重启
+    Reset,
+}
+
+impl WatchdogAction {
+    /// 启动配置中没有 watchdog= 时使用
+    pub const DEFAULT: WatchdogAction = WatchdogAction::Report;
+
+    /// 解析 watchdog= 的值：off、report 或 reset
+    pub fn parse(value: &str) -> Option<WatchdogAction> {
+        match value {
+            "off" => Some(WatchdogAction::Off),
+            "report" => Some(WatchdogAction::Report),
+            "reset" => Some(WatchdogAction::Reset),
+            _ => None,
+        }
+    }
+}
+
+/// 计数与阈值的状态机，不依赖时钟中断，节拍由调用者通过 tick 送入
+#[derive(Debug)]
+pub struct Watchdog {
+    action: WatchdogAction,
+    timeout: u64,
+    /// 上次 pet 以来的节拍数
+    since_pet: u64,
+    /// 第一次 pet 之后才计数
+    armed: bool,
+    /// 这次卡住已经报告过，再次 pet 之前不再报告
+    fired: bool,
+    /// suspend 的嵌套层数
+    suspended: usize,
+}
+
+impl Watchdog {
+    pub const fn new(action: WatchdogAction, timeout: u64) -> Self {
+        Watchdog {
+            action,
+            timeout,
+            since_pet: 0,
+            armed: false,
+            fired: false,
+            suspended: 0,
+        }
+    }
+
+    /// 主循环仍在运行：计数清零，重新布防
+    pub fn pet(&mut self) {
+        self.since_pet = 0;
+        self.armed = true;
+        self.fired = false;
+    }
+
+    /// 经过一个节拍，刚好超时（这次卡住的第一次）时返回 true
+    pub fn tick(&mut self) -> bool {
+        if !self.armed || self.suspended > 0 || self.action == WatchdogAction::Off {
+            return false;
+        }
+        self.since_pet += 1;
+        if self.since_pet > self.timeout && !self.fired {
+            self.fired = true;
+            return true;
+        }
+        false
+    }
+
+    /// 上次 pet 以来的节拍数
+    pub fn stalled_ticks(&self) -> u64 {
+        self.since_pet
+    }
+
+    pub fn action(&self) -> WatchdogAction {
+        self.action
+    }
+
+    fn suspend(&mut self) {
+        self.suspended += 1;
+    }
+
+    /// 最外层的守卫结束时计数清零，长时间的操作本身不算作卡住
+    fn resume(&mut self) {
+        self.suspended -= 1;
+        if self.suspended == 0 {
+            self.since_pet = 0;
+        }
+    }
+}
+
+static WATCHDOG: Mutex<Watchdog> = Mutex::new(Watchdog::new(
+    WatchdogAction::DEFAULT,
+    DEFAULT_TIMEOUT_TICKS,
+));
+
+/// 由执行器的主循环调用
+pub fn pet() {
+    interrupts::without_interrupts(|| WATCHDOG.lock().pet());
+}
+
+/// 设置触发后的动作，由 config::init 调用
+pub fn set_action(action: WatchdogAction) {
+    interrupts::without_interrupts(|| WATCHDOG.lock().action = action);
+}
+
+/// 设置多少个节拍没有 pet 后触发
+pub fn set_timeout(ticks: u64) {
+    interrupts::without_interrupts(|| WATCHDOG.lock().timeout = ticks);
+}
+
+/// 暂停计数直到返回的守卫被 drop，可以嵌套
+pub fn suspend() -> Suspended<'static> {
+    Suspended::new(&WATCHDOG)
+}
+
+/// suspend 返回的守卫
+#[must_use = "the watchdog resumes as soon as the guard is dropped"]
+pub struct Suspended<'a>(&'a Mutex<Watchdog>);
+
+impl<'a> Suspended<'a> {
+    fn new(watchdog: &'a Mutex<Watchdog>) -> Self {
+        interrupts::without_interrupts(|| watchdog.lock().suspend());
+        Suspended(watchdog)
+    }
+}
+
+impl Drop for Suspended<'_> {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| self.0.lock().resume());
+    }
+}
+
+/// 由时钟中断处理函数调用，stack_frame 是被打断的代码的中断栈帧
+pub(crate) fn on_tick(stack_frame: &InterruptStackFrame) {
+    let (fired, stalled, action) = {
+        let mut watchdog = WATCHDOG.lock();
+        (watchdog.tick(), watchdog.stalled_ticks(), watchdog.action())
+    };
+    if !fired {
+        return;
+    }
+    report(stack_frame, stalled);
+    if action == WatchdogAction::Reset {
+        power::reboot();
+    }
+}
+
+fn report(stack_frame: &InterruptStackFrame, stalled: u64) {
+    let rip = stack_frame.instruction_pointer.as_u64();
+    println!(
+        "watchdog: main loop stalled for {} ms",
+        time::ticks_to_ms(stalled)
+    );
+    match symbols::resolve(rip) {
+        Some((name, offset)) => println!(
+            "  interrupted at {:#018x} {} +{:#x}",
+            rip,
+            Demangle(name),
+            offset
+        ),
+        None => println!("  interrupted at {:#018x}", rip),
+    }
+    let _ = diag::write_latency(&mut Stdout, &diag::latency_histogram());
+    if executor::try_write_tasks(&mut Stdout).is_none() {
+        println!("task table busy");
+    }
+    // 前几帧是看门狗和时钟中断处理函数自己，之后是被打断的代码
+    backtrace::print();
+}
+
+/// 节拍看门狗每次检查空转的毫秒数，其间应当有好几个节拍
+pub const TICK_CHECK_WINDOW_MS: u32 = 5;
+
+/// 节拍看门狗的计数，与 Watchdog 一样不依赖时钟中断，检查结果由调用者送入
+#[derive(Debug)]
+pub struct TickWatchdog {
+    /// 连续多少次检查节拍数没有前进就警告
+    limit: u32,
+    misses: u32,
+    /// 这次停顿已经警告过，节拍恢复之前不再警告
+    warned: bool,
+}
+
+impl TickWatchdog {
+    pub const fn new(limit: u32) -> Self {
+        TickWatchdog {
+            limit,
+            misses: 0,
+            warned: false,
+        }
+    }
+
+    /// 一次检查的结果，ticked 表示检查期间节拍数前进了；
+    /// 连续 limit 次没有前进（这次停顿的第一次）时返回 true
+    pub fn check(&mut self, ticked: bool) -> bool {
+        if ticked {
+            self.misses = 0;
+            self.warned = false;
+            return false;
+        }
+        self.misses = self.misses.saturating_add(1);
+        if self.misses >= self.limit && !self.warned {
+            self.warned = true;
+            return true;
+        }
+        false
+    }
+
+    /// 连续没有前进的检查次数
+    pub fn misses(&self) -> u32 {
+        self.misses
+    }
+}
+
+/// None 表示关闭
+static TICK_WATCHDOG: Mutex<Option<TickWatchdog>> = Mutex::new(None);
+
+/// 打开节拍看门狗，连续 limit 次按键都没有等到节拍时警告
+pub fn enable_tick_watchdog(limit: u32) {
+    interrupts::without_interrupts(|| *TICK_WATCHDOG.lock() = Some(TickWatchdog::new(limit)));
+}
+
+pub fn disable_tick_watchdog() {
+    interrupts::without_interrupts(|| *TICK_WATCHDOG.lock() = None);
+}
+
+/// 由键盘解码在每次按下时调用，节拍看门狗关闭时什么也不做。
+/// 空转期间不持有锁，中断照常到来；调用者关闭了中断时节拍不会前进，这正是要报告的情况
+pub(crate) fn check_ticks() {
+    if interrupts::without_interrupts(|| TICK_WATCHDOG.lock().is_none()) {
+        return;
+    }
+    let before = time::ticks();
+    time::delay_spin(TICK_CHECK_WINDOW_MS as u64 * time::spin_loops_per_ms());
+    let ticked = time::ticks() != before;
+    let warn = interrupts::without_interrupts(|| {
+        let mut watchdog = TICK_WATCHDOG.lock();
+        let watchdog = watchdog.as_mut()?;
+        watchdog.check(ticked).then(|| watchdog.misses())
+    });
+    if let Some(misses) = warn {
+        println!(
+            "watchdog: no timer tick in about {} ms; are interrupts enabled and the IDT/PIC set up?",
+            misses * TICK_CHECK_WINDOW_MS
+        );
+    }
+}
+
+#[test_case]
+fn test_tick_watchdog_warns_once_per_stall() {
+    let mut watchdog = TickWatchdog::new(3);
+    let checks = |watchdog: &mut TickWatchdog, ticks: &[bool]| -> alloc::vec::Vec<bool> {
+        ticks.iter().map(|&ticked| watchdog.check(ticked)).collect()
+    };
+    // 偶尔一次没有等到节拍不算
+    assert_eq!(
+        checks(&mut watchdog, &[true, false, false, true, false]),
+        [false; 5]
+    );
+    // 连续三次才警告，之后不再重复
+    assert_eq!(
+        checks(&mut watchdog, &[false, false, false, false]),
+        [false, true, false, false]
+    );
+    assert_eq!(watchdog.misses(), 5);
+    // 节拍恢复后重新计数
+    assert_eq!(
+        checks(&mut watchdog, &[true, false, false, false]),
+        [false, false, false, true]
+    );
+}
+
+#[test_case]
+fn test_fires_once_per_stall() {
+    let mut watchdog = Watchdog::new(WatchdogAction::Report, 3);
+    // 第一次 pet 之前不计数
+    assert!((0..10).all(|_| !watchdog.tick()));
+    watchdog.pet();
+    let fired: [bool; 6] = core::array::from_fn(|_| watchdog.tick());
+    assert_eq!(fired, [false, false, false, true, false, false]);
+    assert_eq!(watchdog.stalled_ticks(), 6);
+
+    // pet 之后重新布防，下一次卡住再报告一次
+    watchdog.pet();
+    assert_eq!(watchdog.stalled_ticks(), 0);
+    let fired: [bool; 5] = core::array::from_fn(|_| watchdog.tick());
+    assert_eq!(fired, [false, false, false, true, false]);
+
+    let mut watchdog = Watchdog::new(WatchdogAction::Off, 3);
+    watchdog.pet();
+    assert!((0..10).all(|_| !watchdog.tick()));
+}
+
+#[test_case]
+fn test_suspend_guard() {
+    let watchdog = Mutex::new(Watchdog::new(WatchdogAction::Reset, 3));
+    let tick = || watchdog.lock().tick();
+    watchdog.lock().pet();
+    assert!(!tick() && !tick());
+    {
+        let _outer = Suspended::new(&watchdog);
+        {
+            let _inner = Suspended::new(&watchdog);
+            assert!((0..10).all(|_| !tick()));
+        }
+        // 内层结束时外层仍在暂停
+        assert!((0..10).all(|_| !tick()));
+    }
+    // 守卫结束时计数清零，还要再经过完整的 timeout 才触发
+    assert_eq!(watchdog.lock().stalled_ticks(), 0);
+    let fired: [bool; 4] = core::array::from_fn(|_| tick());
+    assert_eq!(fired, [false, false, false, true]);
+}
+
+#[test_case]
+fn test_parse_action() {
+    assert_eq!(WatchdogAction::parse("off"), Some(WatchdogAction::Off));
+    assert_eq!(
+        WatchdogAction::parse("report"),
+        Some(WatchdogAction::Report)
+    );
+    assert_eq!(WatchdogAction::parse("reset"), Some(WatchdogAction::Reset));
+    assert_eq!(WatchdogAction::parse("reboot"), None);
+    assert_eq!(WatchdogAction::parse(""), None);
+}
@@ -0,0 +1,71 @@
+//! 最基本的冒烟测试：启动、初始化、打印、退出整条流程能走通
+//! 新的集成测试可以从这个文件复制入口和 panic 处理
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(vm_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use vm_os::vga_buffer::{BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+use vm_os::{allocator, console, memory, println};
+use x86_64::instructions::interrupts;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+/// 初始化完成、即将运行测试
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vm_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    INITIALIZED.store(true, Ordering::SeqCst);
+
+    test_main();
+    vm_os::hlt_loop();
+}
+
+/// 测试中的 panic（包括 println! 本身 panic）以 Failed 退出 QEMU
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn reaches_test_main() {
+    assert!(INITIALIZED.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn println_does_not_panic() {
+    println!("basic_boot: println works");
+}
+
+#[test_case]
+fn println_reaches_capture_sink() {
+    let captured = console::capture::<64>(|| println!("basic_boot {}", 42)).unwrap();
+    assert_eq!(captured.contents(), "basic_boot 42\n");
+}
+
+#[test_case]
+fn println_reaches_the_screen() {
+    let line = "basic_boot: on screen";
+    println!("{}", line);
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        // 换行之后光标在最后一行，刚打印的内容在它上面一行
+        for (col, byte) in line.bytes().enumerate() {
+            assert_eq!(writer.read_char(BUFFER_HEIGHT - 2, col).0, byte);
+        }
+        let blank = (0..BUFFER_HEIGHT)
+            .all(|row| (0..BUFFER_WIDTH).all(|col| writer.read_char(row, col).0 == b' '));
+        assert!(!blank, "screen stayed blank");
+    });
+}
@@ -0,0 +1,98 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(vm_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use vm_os::console::capture::{self, LineCapture};
+use vm_os::exec::{self, ExecError, LOAD_ADDRESS, MAX_PROGRAM_SIZE};
+use vm_os::memory::{self, BootInfoFrameAllocator};
+use vm_os::{allocator, println};
+use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// programs/hello.s 汇编后的平坦二进制，打印一行后以 42 退出
+static HELLO: &[u8] = include_bytes!("../programs/hello.bin");
+const HELLO_MESSAGE: &str = "hello from a flat binary";
+
+/// 测试函数没有参数，页表和帧分配器放在这里
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vm_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    *MEMORY.lock() = Some((mapper, frame_allocator));
+
+    test_main();
+    vm_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::test_panic_handler(info)
+}
+
+fn run(image: &[u8]) -> Result<i32, ExecError> {
+    let mut memory = MEMORY.lock();
+    let (mapper, frame_allocator) = memory.as_mut().unwrap();
+    exec::run(image, mapper, frame_allocator)
+}
+
+#[test_case]
+fn runs_sample_program() {
+    let guard = LineCapture::start().unwrap();
+    assert_eq!(run(HELLO).unwrap(), 42);
+    drop(guard);
+    // puts 通过 println! 输出
+    assert_eq!(capture::lines(), [HELLO_MESSAGE]);
+
+    // 运行结束后取消了映射，可以再次运行
+    assert_eq!(run(HELLO).unwrap(), 42);
+    println!();
+}
+
+#[test_case]
+fn rejects_bad_images() {
+    static TOO_LARGE: [u8; MAX_PROGRAM_SIZE + 1] = [0x90; MAX_PROGRAM_SIZE + 1];
+    assert!(matches!(run(&[]), Err(ExecError::Empty)));
+    assert!(matches!(
+        run(&TOO_LARGE),
+        Err(ExecError::TooLarge(len)) if len == MAX_PROGRAM_SIZE + 1
+    ));
+    // ramfs 没有加载
+    let mut memory = MEMORY.lock();
+    let (mapper, frame_allocator) = memory.as_mut().unwrap();
+    assert!(matches!(
+        exec::run_flat("hello.bin", mapper, frame_allocator),
+        Err(ExecError::NotFound)
+    ));
+}
+
+#[test_case]
+fn refuses_mapped_load_address() {
+    let page = Page::containing_address(VirtAddr::new(LOAD_ADDRESS));
+    {
+        let mut memory = MEMORY.lock();
+        let (mapper, frame_allocator) = memory.as_mut().unwrap();
+        let frame = frame_allocator.allocate_frame().unwrap();
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator) }
+            .unwrap()
+            .flush();
+    }
+    assert!(matches!(run(HELLO), Err(ExecError::AlreadyMapped)));
+
+    let mut memory = MEMORY.lock();
+    let (mapper, _) = memory.as_mut().unwrap();
+    mapper.unmap(page).unwrap().1.flush();
+}
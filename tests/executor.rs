@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(vm_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::future::pending;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use vm_os::task::executor::Executor;
+use vm_os::task::yield_times;
+use vm_os::{allocator, memory};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vm_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    vm_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn yielding_tasks_run_to_completion() {
+    static STEPS: AtomicUsize = AtomicUsize::new(0);
+
+    async fn step_and_yield(times: usize) {
+        for _ in 0..times {
+            STEPS.fetch_add(1, Ordering::SeqCst);
+            yield_times(1).await;
+        }
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn_named("three", step_and_yield(3));
+    executor.spawn_named("five", step_and_yield(5));
+    assert_eq!(executor.run_until_idle(), 0);
+    assert_eq!(STEPS.load(Ordering::SeqCst), 8);
+}
+
+#[test_case]
+fn run_until_idle_returns_with_pending_tasks() {
+    let mut executor = Executor::new();
+    executor.spawn_named("forever", pending::<()>());
+    executor.spawn_named("done", async {});
+    // 没有被唤醒的任务时立即返回，不会休眠
+    assert_eq!(executor.run_until_idle(), 1);
+    assert_eq!(executor.run_until_idle(), 1);
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::fmt;
+use core::panic::PanicInfo;
+use vm_os::vga_buffer::{BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+use vm_os::{exit_qemu_or_halt, println, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+/// 写出一部分之后 panic 的 Display
+struct Bomb;
+
+impl fmt::Display for Bomb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("partial")?;
+        panic!("bomb");
+    }
+}
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    vm_os::init();
+    serial_print!("format_panic::panic_in_display... ");
+    println!("before {}", Bomb);
+    serial_println!("[failed]\nprintln! returned");
+    exit_qemu_or_halt(QemuExitCode::Failed);
+}
+
+/// 屏幕上从上到下第一个以 prefix 开头的行
+fn find_row(prefix: &[u8]) -> Option<usize> {
+    let writer = WRITER.lock();
+    (0..BUFFER_HEIGHT).find(|&row| {
+        prefix.len() <= BUFFER_WIDTH
+            && prefix
+                .iter()
+                .enumerate()
+                .all(|(col, &byte)| writer.read_char(row, col).0 == byte)
+    })
+}
+
+/// println! 持有控制台的锁时 panic：如果锁没有被释放，report 会卡住，测试超时失败
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::panic::report(info);
+    let partial = find_row(b"before partial");
+    let message = find_row(b"kernel panic: bomb");
+    let note = find_row(b"  panic occurred during console formatting");
+    // 被打断的半行保留在屏幕上，报告从下一行开始
+    match (partial, message, note) {
+        (Some(partial), Some(message), Some(note))
+            if message == partial + 2 && note == message + 1 =>
+        {
+            serial_println!("[ok]");
+            exit_qemu_or_halt(QemuExitCode::Success);
+        }
+        rows => {
+            serial_println!("[failed]\nrows on screen: {:?}", rows);
+            exit_qemu_or_halt(QemuExitCode::Failed);
+        }
+    }
+}
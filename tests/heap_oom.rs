@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+#![feature(alloc_error_handler)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::alloc::Layout;
+use core::panic::PanicInfo;
+use vm_os::allocator::{self, HEAP_SIZE};
+use vm_os::{exit_qemu_or_halt, memory, serial_print, serial_println, QemuExitCode};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+/// 比整个堆还大，一定分配失败
+const TOO_LARGE: usize = HEAP_SIZE + 1;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vm_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    serial_print!("heap_oom::over_large_allocation... ");
+    let buffer: Vec<u8> = Vec::with_capacity(TOO_LARGE);
+    serial_println!("[failed]\nallocated {} bytes", buffer.capacity());
+    exit_qemu_or_halt(QemuExitCode::Failed);
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    // 与内核的处理相同，只是报告之后以成功退出而不是停机
+    allocator::report_alloc_error(layout);
+    if layout.size() == TOO_LARGE {
+        serial_println!("[ok]");
+        exit_qemu_or_halt(QemuExitCode::Success);
+    }
+    serial_println!("[failed]\nunexpected layout {:?}", layout);
+    exit_qemu_or_halt(QemuExitCode::Failed);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::test_panic_handler(info)
+}
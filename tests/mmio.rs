@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(vm_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use vm_os::memory::{self, BootInfoFrameAllocator, MMIO_SIZE, MMIO_START};
+use x86_64::structures::paging::mapper::{Translate, TranslateResult};
+use x86_64::structures::paging::{OffsetPageTable, PageTableFlags};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// VGA 文本缓冲区：一定存在、不在可用内存中的一段设备内存
+const VGA_BUFFER: u64 = 0xb8000;
+
+/// 测试函数没有参数，页表和帧分配器放在这里
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vm_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    *MEMORY.lock() = Some((mapper, frame_allocator));
+
+    test_main();
+    vm_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::test_panic_handler(info)
+}
+
+fn map(phys: u64, size: u64) -> VirtAddr {
+    let mut memory = MEMORY.lock();
+    let (mapper, frame_allocator) = memory.as_mut().unwrap();
+    memory::map_mmio(mapper, frame_allocator, PhysAddr::new(phys), size).unwrap()
+}
+
+#[test_case]
+fn maps_uncached_pages_in_mmio_range() {
+    let virt = map(VGA_BUFFER, 4000);
+    assert!((MMIO_START..MMIO_START + MMIO_SIZE).contains(&virt.as_u64()));
+    assert!(virt.is_aligned(4096u64));
+
+    let memory = MEMORY.lock();
+    let (mapper, _) = memory.as_ref().unwrap();
+    match mapper.translate(virt) {
+        TranslateResult::Mapped { frame, flags, .. } => {
+            assert_eq!(frame.start_address(), PhysAddr::new(VGA_BUFFER));
+            assert!(flags.contains(PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE));
+            assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+        }
+        other => panic!("not mapped: {:?}", other),
+    }
+}
+
+#[test_case]
+fn mapping_sees_the_same_memory() {
+    // 不对齐的起始地址：返回的地址保留页内偏移，区域跨过页边界时映射两页
+    let phys = VGA_BUFFER + 0xff8;
+    let virt = map(phys, 16);
+    assert_eq!(virt.as_u64() % 4096, 0xff8);
+    let direct = memory::phys_to_virt(PhysAddr::new(phys)).unwrap();
+    unsafe {
+        let mapped = virt.as_mut_ptr::<u64>();
+        let direct = direct.as_mut_ptr::<u64>();
+        mapped.add(1).write_volatile(0x0f41_0f42_0f43_0f44);
+        assert_eq!(direct.add(1).read_volatile(), 0x0f41_0f42_0f43_0f44);
+        direct.write_volatile(0x0f45_0f46_0f47_0f48);
+        assert_eq!(mapped.read_volatile(), 0x0f45_0f46_0f47_0f48);
+    }
+    // 每次映射分到新的虚拟地址
+    assert_ne!(map(phys, 16), virt);
+}
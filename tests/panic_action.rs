@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(vm_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use vm_os::panic::{self, PanicAction};
+use vm_os::{config, QemuExitCode};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    vm_os::init();
+    test_main();
+    vm_os::hlt_loop();
+}
+
+/// 这里的 panic 表示测试失败，不能交给 panic::handle_panic 执行配置的动作
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn command_line_selects_qemu_exit() {
+    assert_eq!(panic::action(), PanicAction::DEFAULT);
+    // 与 CI 启动内核时的命令行相同，init 把动作保存到 panic 模块
+    config::init("panic=exit");
+    assert_eq!(config::get().panic, PanicAction::ExitQemuFailure);
+    assert_eq!(panic::action(), PanicAction::ExitQemuFailure);
+}
+
+#[test_case]
+fn qemu_exit_reports_failure() {
+    let code = PanicAction::ExitQemuFailure.exit_code();
+    assert_eq!(code, Some(QemuExitCode::Failed));
+    // QEMU 的退出码是 (value << 1) | 1，必须与测试成功时的 33 不同
+    assert_ne!(code.map(|code| ((code as u32) << 1) | 1), Some(33));
+    assert_eq!(PanicAction::Halt.exit_code(), None);
+    assert_eq!(PanicAction::RebootAfter(5).exit_code(), None);
+}
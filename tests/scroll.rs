@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(vm_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use vm_os::vga_buffer::{OffscreenWriter, BUFFER_HEIGHT, BUFFER_WIDTH};
+use vm_os::{allocator, memory};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vm_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    vm_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::test_panic_handler(info)
+}
+
+/// 第 row 行的标记字符
+fn marker(row: usize) -> u8 {
+    b'A' + row as u8
+}
+
+#[test_case]
+fn new_line_shifts_rows_up() {
+    let mut writer = OffscreenWriter::new();
+
+    // 每写满一行，下一个字节会触发 new_line，所以写完后第 0 行是 'A'，最后一行是 'Y'
+    for row in 0..BUFFER_HEIGHT {
+        for _ in 0..BUFFER_WIDTH {
+            writer.write_byte(marker(row));
+        }
+    }
+    for row in 0..BUFFER_HEIGHT {
+        assert_eq!(writer.read_char(row, 0).0, marker(row));
+    }
+
+    writer.new_line();
+
+    for row in 0..BUFFER_HEIGHT - 1 {
+        for col in 0..BUFFER_WIDTH {
+            assert_eq!(writer.read_char(row, col).0, marker(row + 1));
+        }
+    }
+    for col in 0..BUFFER_WIDTH {
+        assert_eq!(writer.read_char(BUFFER_HEIGHT - 1, col).0, b' ');
+    }
+}
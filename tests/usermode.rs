@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(vm_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use vm_os::console::capture::{self, LineCapture};
+use vm_os::exec::ExecError;
+use vm_os::memory::{self, BootInfoFrameAllocator};
+use vm_os::usermode::{self, Exit};
+use vm_os::{allocator, println};
+use x86_64::structures::paging::OffsetPageTable;
+use x86_64::VirtAddr;
+
+/// programs/user_hello.s：打印一行，再用内核地址调用 SYS_WRITE，都符合预期时以 0 退出
+static USER_HELLO: &[u8] = include_bytes!("../programs/user_hello.bin");
+/// programs/user_fault.s：读取传入的地址
+static USER_FAULT: &[u8] = include_bytes!("../programs/user_fault.bin");
+const HELLO_MESSAGE: &str = "hello from ring 3";
+
+/// 测试函数没有参数，页表和帧分配器放在这里
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> = Mutex::new(None);
+
+/// 用户态不能访问的内核数据
+static KERNEL_DATA: u64 = 0x1234_5678;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    vm_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    *MEMORY.lock() = Some((mapper, frame_allocator));
+
+    test_main();
+    vm_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    vm_os::test_panic_handler(info)
+}
+
+fn run(image: &[u8], arg: u64) -> Result<Exit, ExecError> {
+    let mut memory = MEMORY.lock();
+    let (mapper, frame_allocator) = memory.as_mut().unwrap();
+    usermode::run(image, arg, mapper, frame_allocator)
+}
+
+fn kernel_address() -> u64 {
+    &KERNEL_DATA as *const u64 as u64
+}
+
+#[test_case]
+fn write_syscall_prints_and_rejects_kernel_pointers() {
+    let guard = LineCapture::start().unwrap();
+    assert_eq!(run(USER_HELLO, kernel_address()).unwrap(), Exit::Code(0));
+    drop(guard);
+    // 被拒绝的第二次调用没有输出
+    assert_eq!(capture::lines(), [HELLO_MESSAGE]);
+}
+
+#[test_case]
+fn user_page_fault_terminates_program() {
+    let address = kernel_address();
+    match run(USER_FAULT, address).unwrap() {
+        Exit::Fault(fault) => {
+            assert_eq!(fault.address, address);
+            assert_eq!(fault.instruction, usermode::USER_CODE);
+        }
+        exit => panic!("expected a fault, got {:?}", exit),
+    }
+    // 内核继续运行，结束后的程序不会留下映射，可以再次运行
+    println!("kernel survived");
+    assert_eq!(run(USER_HELLO, address).unwrap(), Exit::Code(0));
+}